@@ -12,21 +12,37 @@ self: {
       description = "Configuration options for the Nix Autobuild service";
       default = {};
     };
+
+    openFirewall = lib.mkOption {
+      type = lib.types.bool;
+      description = "Open the TCP port of the server in the firewall. Has no effect when `settings.server.enabled` is false.";
+      default = false;
+    };
   };
 
   config = lib.mkIf config.services.nix_autobuild.enable (
     let
-      configFile = builtins.toFile "config.json" (builtins.toJSON config.services.nix_autobuild.settings);
+      cfg = config.services.nix_autobuild;
+      configFile = builtins.toFile "config.json" (builtins.toJSON cfg.settings);
       nix_autobuild = self.packages.${pkgs.system}.backend;
+      tcpPort =
+        if cfg.settings.listen == null
+        then cfg.settings.port
+        else if cfg.settings.listen.tcp == null
+        then null
+        else cfg.settings.listen.tcp.port;
     in {
+      networking.firewall.allowedTCPPorts =
+        lib.mkIf (cfg.openFirewall && cfg.settings.server.enabled && tcpPort != null) [tcpPort];
+
       systemd.services = {
         "nix_autobuild" = {
           description = "A simple build tool for Nix projects.";
           after = ["network.target"];
           wantedBy = ["multi-user.target"];
-          path = [pkgs.nix pkgs.git];
+          path = [pkgs.nix pkgs.git pkgs.xz pkgs.gnutar pkgs.gzip];
           serviceConfig = {
-            ExecStart = "${nix_autobuild}/bin/nix_autobuild ${configFile}";
+            ExecStart = "${nix_autobuild}/bin/nix_autobuild serve ${configFile}";
             User = "root";
             Restart = "always";
           };
@@ -1,6 +1,457 @@
 {lib, ...}: let
   types = lib.types;
 in let
+  accessLogOptionsType = {
+    options = {
+      level = lib.mkOption {
+        type = types.str;
+        description = "One of `off`, `info` (method, path, status, latency, response size, client IP and served file) or `debug` (also the request headers)";
+        default = "info";
+      };
+
+      file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File the access log is appended to. When not set, it is written to stdout, or to `log.file` if that is set.";
+        default = null;
+        example = "/var/log/nix_autobuild/access.log";
+      };
+
+      redact_query_parameters = lib.mkOption {
+        type = types.listOf types.str;
+        description = "Query parameters whose values are replaced by `[redacted]`, matched case-insensitively as parts of the name";
+        default = ["token" "secret" "password" "key" "auth" "signature" "session"];
+      };
+
+    };
+  };
+  atticOptionsType = {
+    options = {
+      cache = lib.mkOption {
+        type = types.str;
+        description = "Cache to push to, `server:cache` or a cache on the default server. The server has to be set up with `attic login` for the user running nix_autobuild.";
+        example = "central:main";
+      };
+
+    };
+  };
+  binaryCacheOptionsType = {
+    options = {
+      secret_key_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "Secret key created with `nix-store --generate-binary-cache-key` used to sign served paths. Paths are unsigned when not set.";
+        default = null;
+        example = "/var/lib/nix_autobuild/cache-priv-key.pem";
+      };
+
+      secret_key = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "The secret key from a file, an environment variable or a systemd credential, instead of `secret_key_file`";
+        default = null;
+        example = { systemd_credential = "cache-priv-key"; };
+      };
+
+      compression = lib.mkOption {
+        type = types.str;
+        description = "Compression of served NARs, either `none` or `xz`. `xz` requires the xz binary.";
+        default = "none";
+      };
+
+      priority = lib.mkOption {
+        type = types.int;
+        description = "Substituter priority announced to clients, lower values are preferred";
+        default = 40;
+      };
+
+      expose_whole_store = lib.mkOption {
+        type = types.bool;
+        description = "Serve every path of the local store instead of only the outputs of tracked builds and their dependencies";
+        default = false;
+      };
+
+    };
+  };
+  buildWindowOptionsType = {
+    options = {
+      start = lib.mkOption {
+        type = types.str;
+        description = "When builds may start, as `HH:MM`";
+        example = "22:00";
+      };
+
+      end = lib.mkOption {
+        type = types.str;
+        description = "When the window closes, as `HH:MM`. Before `start` for a window across midnight. Builds running at that time finish.";
+        example = "06:00";
+      };
+
+      timezone = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Time zone of `start` and `end`, like `Europe/Berlin`. When not set, the local time of the server.";
+        default = null;
+        example = "Europe/Berlin";
+      };
+
+    };
+  };
+  cachixOptionsType = {
+    options = {
+      cache = lib.mkOption {
+        type = types.str;
+        description = "Name of the Cachix cache";
+        example = "my-cache";
+      };
+
+      auth_token = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "Auth token of the cache from a file, an environment variable or a systemd credential. Without it `cachix` uses `CACHIX_AUTH_TOKEN` or its own config.";
+        default = null;
+        example = { systemd_credential = "cachix-token"; };
+      };
+
+    };
+  };
+  chatWebhookOptionsType = {
+    options = {
+      url = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Webhook URL from the channel settings. It contains the token and ends up in the nix store, prefer `url_secret`. Either it or `url_secret` is required.";
+        default = null;
+        example = "https://hooks.slack.com/services/T000/B000/XXXX";
+      };
+
+      url_secret = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "The webhook URL from a file, an environment variable or a systemd credential, instead of `url`";
+        default = null;
+        example = { file = "/run/secrets/slack-webhook"; };
+      };
+
+      events = lib.mkOption {
+        type = types.listOf (types.enum [ "package_success" "package_failed" "package_recovered" "commit_finished" "commit_failed" ]);
+        description = "Events to send, any of `package_failed`, `package_recovered` (a package builds again after failing on the previous commit), `package_success` and `commit_finished`";
+        default = ["package_failed" "package_recovered"];
+      };
+
+    };
+  };
+  emailOptionsType = {
+    options = {
+      smtp_host = lib.mkOption {
+        type = types.str;
+        description = "SMTP server";
+        example = "smtp.example.com";
+      };
+
+      smtp_port = lib.mkOption {
+        type = types.int;
+        description = "SMTP port, usually 587 for `starttls` and 465 for `tls`";
+        default = 587;
+      };
+
+      tls = lib.mkOption {
+        type = types.str;
+        description = "`starttls`, `tls` or `none`. `none` sends the credentials unencrypted and is only meant for a relay on localhost.";
+        default = "starttls";
+      };
+
+      credentials_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File with a single line of SMTP credentials in the format `username:password`. No authentication when not set.";
+        default = null;
+        example = "/run/secrets/smtp";
+      };
+
+      credentials = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "SMTP credentials in the format `username:password` from a file, an environment variable or a systemd credential, instead of `credentials_file`";
+        default = null;
+        example = { env = "SMTP_CREDENTIALS"; };
+      };
+
+      from = lib.mkOption {
+        type = types.str;
+        description = "Sender address";
+        example = "nix_autobuild <ci@example.com>";
+      };
+
+      to = lib.mkOption {
+        type = types.listOf types.str;
+        description = "Recipients of repositories without `email_recipients`";
+        default = [];
+      };
+
+      events = lib.mkOption {
+        type = types.listOf (types.enum [ "package_success" "package_failed" "package_recovered" "commit_finished" "commit_failed" ]);
+        description = "Events to send, any of `commit_failed` (every package of a commit finished and at least one failed), `commit_finished`, `package_failed`, `package_recovered` and `package_success`";
+        default = ["commit_failed"];
+      };
+
+      quiet_secs = lib.mkOption {
+        type = types.int;
+        description = "Seconds to collect notifications of the same commit before sending a single email";
+        default = 300;
+      };
+
+    };
+  };
+  incomingWebhookOptionsType = {
+    options = {
+      github_secret = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "Secret GitHub signs its webhook deliveries with, enables `POST /api/v1/hooks/github`";
+        default = null;
+        example = { file = "/run/secrets/github-webhook"; };
+      };
+
+      github_secret_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing the secret GitHub signs its webhook deliveries with";
+        default = null;
+        example = "/run/secrets/github-webhook";
+      };
+
+      gitlab_token = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "Secret token GitLab sends with its webhooks, enables `POST /api/v1/hooks/gitlab`";
+        default = null;
+        example = { file = "/run/secrets/gitlab-webhook"; };
+      };
+
+      gitlab_token_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing the secret token GitLab sends with its webhooks";
+        default = null;
+        example = "/run/secrets/gitlab-webhook";
+      };
+
+      generic_secret = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "Secret other forges sign their webhooks with like GitHub does, enables `POST /api/v1/hooks/generic`";
+        default = null;
+        example = { env = "WEBHOOK_SECRET"; };
+      };
+
+      generic_secret_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing the secret of webhooks from other forges";
+        default = null;
+        example = "/run/secrets/webhook";
+      };
+
+    };
+  };
+  listenOptionsType = {
+    options = {
+      tcp = lib.mkOption {
+        type = types.nullOr (types.submodule tcpListenOptionsType);
+        description = "Listen on a TCP port";
+        default = null;
+      };
+
+      unix = lib.mkOption {
+        type = types.nullOr (types.submodule unixSocketOptionsType);
+        description = "Listen on a Unix domain socket";
+        default = null;
+      };
+
+    };
+  };
+  logOptionsType = {
+    options = {
+      level = lib.mkOption {
+        type = types.str;
+        description = "One of `off`, `error`, `warn`, `info` (also builds, clones and new commits), `debug` (also requests, polls and pulls) or `trace`";
+        default = "info";
+      };
+
+      format = lib.mkOption {
+        type = types.str;
+        description = "`text` for one readable line per event, `json` for one JSON object per line, e.g. for Loki or Elasticsearch. The access log uses it as well.";
+        default = "text";
+      };
+
+      file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File the logs are appended to. When not set, they are written to stdout.";
+        default = null;
+        example = "/var/log/nix_autobuild/server.log";
+      };
+
+    };
+  };
+  matrixOptionsType = {
+    options = {
+      homeserver = lib.mkOption {
+        type = types.str;
+        description = "Base URL of the homeserver";
+        example = "https://matrix.org";
+      };
+
+      access_token_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing the access token of the account sending the messages. Either it or `access_token` is required.";
+        default = null;
+        example = "/run/secrets/matrix-token";
+      };
+
+      access_token = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "The access token from a file, an environment variable or a systemd credential, instead of `access_token_file`";
+        default = null;
+        example = { systemd_credential = "matrix-token"; };
+      };
+
+      room_id = lib.mkOption {
+        type = types.str;
+        description = "ID of the room the messages are sent to, the account has to be joined already";
+        example = "!abcdefghijklmnop:matrix.org";
+      };
+
+      recoveries = lib.mkOption {
+        type = types.bool;
+        description = "Also send a message when a package builds again after failing on the previous commit";
+        default = true;
+      };
+
+    };
+  };
+  notificationOptionsType = {
+    options = {
+      webhooks = lib.mkOption {
+        type = types.listOf (types.submodule webhookOptionsType);
+        description = "URLs that get a JSON `POST` for every selected event";
+        default = [];
+      };
+
+      matrix = lib.mkOption {
+        type = types.listOf (types.submodule matrixOptionsType);
+        description = "Matrix rooms that get a message when builds fail";
+        default = [];
+      };
+
+      slack = lib.mkOption {
+        type = types.listOf (types.submodule chatWebhookOptionsType);
+        description = "Slack incoming webhooks that get a message for every selected event";
+        default = [];
+      };
+
+      discord = lib.mkOption {
+        type = types.listOf (types.submodule chatWebhookOptionsType);
+        description = "Discord webhooks that get a message for every selected event";
+        default = [];
+      };
+
+      dashboard_url = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Public URL of the frontend, chat messages link to the package on it when set";
+        default = null;
+        example = "https://ci.example.com/";
+      };
+
+      email = lib.mkOption {
+        type = types.nullOr (types.submodule emailOptionsType);
+        description = "Send summaries of failed commits by email";
+        default = null;
+      };
+
+    };
+  };
+  pathOptionsType = {
+    options = {
+      checkouts_dir = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "Checkouts of the repositories, `repos` below `dir` when not set";
+        default = null;
+        example = "/var/lib/nix_autobuild/repos";
+      };
+
+      logs_dir = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "Build logs, one directory per repository and commit, `logs` below `dir` when not set";
+        default = null;
+        example = "/var/lib/nix_autobuild/logs";
+      };
+
+      state_dir = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "State kept across restarts, `state` below `dir` when not set";
+        default = null;
+        example = "/var/lib/nix_autobuild/state";
+      };
+
+      gc_roots_dir = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "GC roots of build results, `gcroots` below `dir` when not set";
+        default = null;
+        example = "/var/lib/nix_autobuild/gcroots";
+      };
+
+    };
+  };
+  postBuildOptionsType = {
+    options = {
+      copy_to = lib.mkOption {
+        type = types.listOf types.str;
+        description = "Stores the out paths of every successful build are copied to with `nix copy --to`, store URIs like `s3://`, `ssh-ng://` or `file://` or absolute paths. The closure is copied, not only the out paths.";
+        default = [];
+        example = ["s3://nix-cache?region=eu-central-1" "ssh-ng://builder@cache.example.com"];
+      };
+
+      cachix = lib.mkOption {
+        type = types.nullOr (types.submodule cachixOptionsType);
+        description = "Push successful builds to a Cachix cache with `cachix push`";
+        default = null;
+      };
+
+      attic = lib.mkOption {
+        type = types.nullOr (types.submodule atticOptionsType);
+        description = "Push successful builds to an Attic cache with `attic push`";
+        default = null;
+      };
+
+      sign_key_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "Secret key created with `nix-store --generate-binary-cache-key`. The closures of the out paths are signed with `nix store sign` before they are pushed, nothing is pushed when signing fails.";
+        default = null;
+        example = "/var/lib/nix_autobuild/cache-priv-key.pem";
+      };
+
+      timeout_secs = lib.mkOption {
+        type = types.int;
+        description = "Seconds after which signing or one push is killed and counts as failed, 0 for no limit. Cancelling the build kills them too.";
+        default = 3600;
+      };
+
+    };
+  };
+  rateLimitOptionsType = {
+    options = {
+      store_per_second = lib.mkOption {
+        type = types.int;
+        description = "Requests per second to `/store` and `/nix/store`, 0 disables the limit";
+        default = 5;
+      };
+
+      store_burst = lib.mkOption {
+        type = types.int;
+        description = "Requests to `/store` and `/nix/store` allowed at once before the rate applies";
+        default = 20;
+      };
+
+      actions_per_second = lib.mkOption {
+        type = types.int;
+        description = "Requests per second that trigger work, e.g. polling or cancelling, 0 disables the limit";
+        default = 1;
+      };
+
+      actions_burst = lib.mkOption {
+        type = types.int;
+        description = "Requests that trigger work allowed at once before the rate applies";
+        default = 5;
+      };
+
+    };
+  };
   repoType = {
     options = {
       url = lib.mkOption {
@@ -9,10 +460,25 @@ in let
         example = "github.com/org/repo";
       };
 
+      name = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Name of the repository in the API paths, badges, checkout directory and dashboard. Letters, digits, `.`, `_` and `-`, unique among the repositories. Derived from the last segment of `url` when not set.";
+        default = null;
+        example = "nix_autobuild";
+      };
+
       poll_interval_sec = lib.mkOption {
-        type = types.int;
-        description = "Polling interval in seconds to check for updates";
-        default = 300;
+        type = types.nullOr types.int;
+        description = "Polling interval in seconds to check for updates, 300 when neither it nor `poll_schedule` is set";
+        default = null;
+        example = 30;
+      };
+
+      poll_schedule = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Cron expression of the poll times in the local time of the server, instead of `poll_interval_sec`. Five fields (minute, hour, day of month, month, day of week), six with the seconds first, or `@hourly`, `@daily` and the like. Day names are clearer than numbers, the days of the week count from 1 for Sunday.";
+        default = null;
+        example = "*/10 8-19 * * Mon-Fri";
       };
 
       branches = lib.mkOption {
@@ -31,10 +497,194 @@ in let
       credentials_file = lib.mkOption {
         type = types.nullOr types.str;
         description = "Optional path to a credentials file. When set, the file must contain a single line with credentials in the format `username:password` (no quotes). If omitted or empty, no credentials are used.";
-        default = "";
+        default = null;
         example = "/path/to/credentials";
       };
 
+      credentials = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "Credentials in the format `username:password` from a file, an environment variable or a systemd credential, instead of `credentials_file`";
+        default = null;
+        example = { systemd_credential = "github"; };
+      };
+
+      email_recipients = lib.mkOption {
+        type = types.listOf types.str;
+        description = "Email addresses notified about this repository instead of `notifications.email.to`";
+        default = [];
+        example = ["team@example.com"];
+      };
+
+      commit_url_template = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Web page of a commit, `{hash}` is replaced by the commit hash. Derived from `url` for GitHub, GitLab and Codeberg when not set.";
+        default = null;
+        example = "https://git.example.com/org/repo/commit/{hash}";
+      };
+
+      supported_architectures = lib.mkOption {
+        type = types.nullOr types.listOf types.str;
+        description = "Architectures to build for this repository instead of the global `supported_architectures`";
+        default = null;
+        example = ["x86_64-linux" "aarch64-linux"];
+      };
+
+      max_concurrent_builds = lib.mkOption {
+        type = types.nullOr types.int;
+        description = "Builds of this repository that may run at the same time, within `n_build_threads`. 0 or null for no limit of its own.";
+        default = null;
+        example = 2;
+      };
+
+    };
+  };
+  secretSourceType = {
+    options = {
+      file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing the secret, surrounding whitespace is ignored";
+        default = null;
+        example = "/run/secrets/github";
+      };
+
+      env = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Environment variable containing the secret";
+        default = null;
+        example = "GITHUB_CREDENTIALS";
+      };
+
+      systemd_credential = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Name of a credential passed by systemd with `LoadCredential=`, read from `$CREDENTIALS_DIRECTORY`";
+        default = null;
+        example = "github";
+      };
+
+    };
+  };
+  serverOptionsType = {
+    options = {
+      enabled = lib.mkOption {
+        type = types.bool;
+        description = "Serve HTTP at all. When disabled the repos are still polled and built, and logs and notifications keep working, but nothing listens.";
+        default = true;
+      };
+
+    };
+  };
+  stateOptionsType = {
+    options = {
+      backend = lib.mkOption {
+        type = types.str;
+        description = "`sqlite` for a database with every status change, `json` for a snapshot of all repos written every `snapshot_interval_secs` and on shutdown, or `none` to keep the state in memory only";
+        default = "sqlite";
+      };
+
+      snapshot_interval_secs = lib.mkOption {
+        type = types.int;
+        description = "Seconds between two snapshots with the `json` backend";
+        default = 60;
+      };
+
+    };
+  };
+  tcpListenOptionsType = {
+    options = {
+      host = lib.mkOption {
+        type = types.str;
+        description = "Address to bind to";
+        default = "127.0.0.1";
+      };
+
+      port = lib.mkOption {
+        type = types.int;
+        description = "Port to bind to";
+        default = 8080;
+      };
+
+    };
+  };
+  tlsOptionsType = {
+    options = {
+      cert_file = lib.mkOption {
+        type = types.path;
+        description = "Path to the PEM encoded certificate chain, leaf certificate first";
+        example = "/var/lib/acme/example.com/fullchain.pem";
+      };
+
+      key_file = lib.mkOption {
+        type = types.path;
+        description = "Path to the PEM encoded private key matching the certificate";
+        example = "/var/lib/acme/example.com/key.pem";
+      };
+
+      reload_on_sighup = lib.mkOption {
+        type = types.bool;
+        description = "Reload the certificate and key from disk when the process receives SIGHUP";
+        default = false;
+      };
+
+    };
+  };
+  unixSocketOptionsType = {
+    options = {
+      path = lib.mkOption {
+        type = types.path;
+        description = "Path of the socket, the parent directory is created and a stale socket removed";
+        example = "/run/nix_autobuild/http.sock";
+      };
+
+      mode = lib.mkOption {
+        type = types.str;
+        description = "Octal permissions of the socket";
+        default = "660";
+      };
+
+      user = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "User owning the socket, a name or numeric ID. Unchanged when not set.";
+        default = null;
+        example = "nginx";
+      };
+
+      group = lib.mkOption {
+        type = types.nullOr types.str;
+        description = "Group owning the socket, a name or numeric ID. Unchanged when not set.";
+        default = null;
+        example = "nginx";
+      };
+
+    };
+  };
+  webhookOptionsType = {
+    options = {
+      url = lib.mkOption {
+        type = types.str;
+        description = "URL the notifications are posted to";
+        example = "https://example.com/hooks/nix_autobuild";
+      };
+
+      events = lib.mkOption {
+        type = types.listOf (types.enum [ "package_success" "package_failed" "package_recovered" "commit_finished" "commit_failed" ]);
+        description = "Events to send, any of `package_success`, `package_failed`, `package_recovered` (a package builds again after failing on the previous commit) and `commit_finished`";
+        default = ["package_success" "package_failed"];
+      };
+
+      secret_file = lib.mkOption {
+        type = types.nullOr types.path;
+        description = "File containing a secret used to sign the payload with HMAC-SHA256. The signature is sent as `sha256=<hex>` in the `X-Autobuild-Signature-256` header. Payloads are unsigned when not set.";
+        default = null;
+        example = "/run/secrets/webhook";
+      };
+
+      secret = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        description = "The signing secret from a file, an environment variable or a systemd credential, instead of `secret_file`";
+        default = null;
+        example = { env = "WEBHOOK_SECRET"; };
+      };
+
     };
   };
   autoBuildOptionsType = {
@@ -51,6 +701,32 @@ in let
       default = "/var/lib/nix_autobuild";
     };
 
+    paths = lib.mkOption {
+      type = (types.submodule pathOptionsType);
+      description = "Where checkouts, logs, state and GC roots are kept, each below `dir` unless set";
+      default = {};
+    };
+
+    state = lib.mkOption {
+      type = (types.submodule stateOptionsType);
+      description = "How the build state is kept in `paths.state_dir` across restarts";
+      default = {};
+    };
+
+    checkout_name_template = lib.mkOption {
+      type = types.nullOr types.str;
+      description = "Directory name of each checkout below `paths.checkouts_dir`, with the placeholders `{host}`, `{owner}`, `{repo}` and `{hash8}` (the start of the SHA-256 of the url). When not set, the name of the repo. Checkouts of an earlier name are moved on startup.";
+      default = null;
+      example = "{owner}-{repo}";
+    };
+
+    frontend_dir = lib.mkOption {
+      type = types.nullOr types.path;
+      description = "Directory of the built frontend, served at the root. It has to contain `index.html`. When not set, the `FRONTEND_PATH` environment variable is used, and without it the path the binary was built with.";
+      default = null;
+      example = "/opt/nix_autobuild/dist";
+    };
+
     supported_architectures = lib.mkOption {
       type = types.listOf types.str;
       description = "List of supported Nix build architectures (e.g. x86_64-linux)";
@@ -58,6 +734,12 @@ in let
       example = ["x86_64-linux" "aarch64-linux"];
     };
 
+    server = lib.mkOption {
+      type = (types.submodule serverOptionsType);
+      description = "The HTTP server with the dashboard, the API and the binary cache";
+      default = {};
+    };
+
     host = lib.mkOption {
       type = types.str;
       description = "Host address for the server to bind to";
@@ -70,12 +752,188 @@ in let
       default = 8080;
     };
 
+    listen = lib.mkOption {
+      type = types.nullOr (types.submodule listenOptionsType);
+      description = "Where to accept connections. When not set, `host` and `port` are used.";
+      default = null;
+    };
+
+    base_path = lib.mkOption {
+      type = types.str;
+      description = "Path prefix of all routes, for serving below a path of a reverse proxy that passes the prefix on. Empty to serve at the root.";
+      default = "";
+      example = "/autobuild";
+    };
+
     n_build_threads = lib.mkOption {
       type = types.int;
       description = "Number of threads to use for building. If 0, uses the number of CPU cores.";
       default = 0;
     };
 
+    dry_run = lib.mkOption {
+      type = types.bool;
+      description = "Evaluate the packages but only run `nix build --dry-run`, recording how many derivations would be built and how many paths fetched. Turning it off on a reload does not rebuild the packages that were only dry run.";
+      default = false;
+    };
+
+    max_commits_per_repo = lib.mkOption {
+      type = types.int;
+      description = "Commits kept per repository, counting the ones on a branch, which are always kept. Beyond it the oldest commits that are on no branch anymore, e.g. after a force push, are forgotten once their builds finished. 0 for no limit on the commits on a branch, of the others the newest 20 are kept.";
+      default = 100;
+    };
+
+    max_commit_age_days = lib.mkOption {
+      type = types.int;
+      description = "Days after which commits that are on no branch anymore are forgotten once their builds finished, by commit time. 0 for no limit.";
+      default = 30;
+    };
+
+    archive_after_days = lib.mkOption {
+      type = types.int;
+      description = "Days after which commits on no branch are moved to gzipped monthly files in `archive` below `paths.state_dir`, also before `max_commit_age_days`; commits forgotten because of `max_commits_per_repo` are archived too. 0 to forget them without an archive.";
+      default = 0;
+    };
+
+    max_log_age_days = lib.mkOption {
+      type = types.int;
+      description = "Days after which build logs are deleted, by the time they were last written. The attempts of the packages then show the log as expired. 0 for no limit.";
+      default = 90;
+    };
+
+    max_total_log_bytes = lib.mkOption {
+      type = types.int;
+      description = "Bytes all build logs in `paths.logs_dir` may take together, beyond them the oldest logs are deleted. 0 for no limit.";
+      default = 0;
+    };
+
+    compress_logs = lib.mkOption {
+      type = types.bool;
+      description = "Gzip the log of a build once it finished, the logs are still served as plain text";
+      default = false;
+    };
+
+    eval_cache = lib.mkOption {
+      type = types.bool;
+      description = "Keep the output of `nix flake show` of each commit in `paths.state_dir`, so a commit is not evaluated again, e.g. after a restart";
+      default = true;
+    };
+
+    max_eval_cache_age_days = lib.mkOption {
+      type = types.int;
+      description = "Days after which a cached evaluation is deleted, by the time it was last used. 0 for no limit.";
+      default = 30;
+    };
+
+    max_eval_cache_bytes = lib.mkOption {
+      type = types.int;
+      description = "Bytes all cached evaluations may take together, beyond them the least recently used are deleted. 0 for no limit.";
+      default = 268435456;
+    };
+
+    result_check_interval_secs = lib.mkOption {
+      type = types.int;
+      description = "Seconds between checks that the output paths of successful builds are still in the store. Collected ones, e.g. by `nix-collect-garbage`, are shown as missing until they are built again. 0 to never check.";
+      default = 3600;
+    };
+
+    build_window = lib.mkOption {
+      type = types.nullOr (types.submodule buildWindowOptionsType);
+      description = "Hours in which packages are built. Outside of them repos are still polled and evaluated, but the builds wait until the window opens. When not set, builds run at any time.";
+      default = null;
+    };
+
+    delete_removed_checkouts = lib.mkOption {
+      type = types.bool;
+      description = "Delete the checkouts of repos that were removed from the config when it is reloaded";
+      default = false;
+    };
+
+    watch_config = lib.mkOption {
+      type = types.bool;
+      description = "Reload the config file like on SIGHUP whenever it changes on disk";
+      default = false;
+    };
+
+    shutdown_grace_secs = lib.mkOption {
+      type = types.int;
+      description = "Seconds to wait on SIGTERM or SIGINT for open requests and cancelled builds to finish before exiting anyway";
+      default = 30;
+    };
+
+    tls = lib.mkOption {
+      type = types.nullOr (types.submodule tlsOptionsType);
+      description = "Serve HTTPS using the given certificate and key. When not set, plain HTTP is used.";
+      default = null;
+    };
+
+    compress_responses = lib.mkOption {
+      type = types.bool;
+      description = "Compress responses with gzip, brotli or zstd when the client supports it";
+      default = true;
+    };
+
+    compress_exclude_content_types = lib.mkOption {
+      type = types.listOf types.str;
+      description = "Content type prefixes that are never compressed, e.g. already compressed archives or streams that must not be buffered";
+      default = ["application/x-nix-nar" "application/x-xz" "application/zstd" "application/gzip" "application/x-bzip2" "application/zip" "image/" "text/event-stream"];
+    };
+
+    cors_allowed_origins = lib.mkOption {
+      type = types.listOf types.str;
+      description = "Origins allowed to access the API from a browser. Entries are `*`, exact origins or contain a single `*` wildcard. When empty, no CORS headers are sent.";
+      default = [];
+      example = ["http://localhost:8081" "https://*.example.com"];
+    };
+
+    binary_cache = lib.mkOption {
+      type = types.nullOr (types.submodule binaryCacheOptionsType);
+      description = "Serve the build results as a nix binary cache, so other machines can use this server as a substituter. Disabled when not set.";
+      default = null;
+    };
+
+    post_build = lib.mkOption {
+      type = (types.submodule postBuildOptionsType);
+      description = "Where successful builds are signed and pushed to";
+      default = {};
+    };
+
+    archive_size_limit = lib.mkOption {
+      type = types.int;
+      description = "Largest store path in bytes that is served as a tar or zip archive. Bigger ones need `force=1` in the request. 0 disables the limit.";
+      default = 4294967296;
+    };
+
+    rate_limits = lib.mkOption {
+      type = (types.submodule rateLimitOptionsType);
+      description = "Limits per client IP for expensive routes, requests above them get `429 Too Many Requests`";
+      default = {};
+    };
+
+    log = lib.mkOption {
+      type = (types.submodule logOptionsType);
+      description = "Logs of the server, the `RUST_LOG` environment variable overrides `level`";
+      default = {};
+    };
+
+    access_log = lib.mkOption {
+      type = (types.submodule accessLogOptionsType);
+      description = "Log of the handled HTTP requests";
+      default = {};
+    };
+
+    webhooks = lib.mkOption {
+      type = (types.submodule incomingWebhookOptionsType);
+      description = "Secrets the webhooks forges send on pushes are checked with";
+      default = {};
+    };
+
+    notifications = lib.mkOption {
+      type = (types.submodule notificationOptionsType);
+      description = "Where to report finished builds";
+      default = {};
+    };
+
     };
   };
 in autoBuildOptionsType
@@ -69,6 +69,7 @@
           src = ./.;
           buildInputs = buildInputs;
           FRONTEND_PATH = "${frontend}/dist";
+          GIT_REV = self.rev or self.dirtyRev or "unknown";
         };
       in {
         packages.backend = backend;
@@ -0,0 +1,212 @@
+//! Access log of the handled requests. It is made of `tracing` events with the
+//! target [`TARGET`], so it can be written somewhere else than the application logs.
+
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use actix_web::{
+    HttpMessage,
+    body::{BodySize, MessageBody},
+    dev::{ServiceRequest, ServiceResponse},
+    http::{Method, StatusCode, header::HeaderMap},
+};
+use tracing::{Level, Subscriber, level_filters::LevelFilter};
+use tracing_subscriber::{Layer, filter::Targets, registry::LookupSpan};
+
+use super::logging;
+use crate::{AccessLogOptions, LogOptions};
+
+pub const TARGET: &str = "access";
+const REDACTED: &str = "[redacted]";
+/// Request headers carrying credentials
+const SENSITIVE_HEADERS: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+/// The file a store or static route answered with, inserted into the request
+/// extensions by the handler
+pub struct ServedFile(pub PathBuf);
+
+/// Layer writing the access log in the format of the other logs, to its own file
+/// or where they go
+pub fn layer<S>(
+    options: &AccessLogOptions,
+    log: &LogOptions,
+) -> Result<impl Layer<S> + use<S>, String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let level = options
+        .level
+        .parse::<LevelFilter>()
+        .map_err(|_| format!("invalid access log level `{}`", options.level))?;
+    let writer = logging::writer(options.file.as_deref().or(log.file.as_deref()))?;
+    Ok(logging::layer(&log.format, writer).with_filter(Targets::new().with_target(TARGET, level)))
+}
+
+/// Turns requests into access log entries, shared by all workers
+pub struct AccessLog {
+    /// lowercase parts of query parameter names whose values are not logged
+    redacted: Vec<String>,
+}
+
+/// What is known about a request before it is handled
+pub struct Entry {
+    start: Instant,
+    method: Method,
+    uri: String,
+    client: String,
+    forwarded_for: Option<String>,
+}
+
+impl AccessLog {
+    pub fn new(options: &AccessLogOptions) -> Self {
+        AccessLog {
+            redacted: options
+                .redact_query_parameters
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// `None` when the access log is off
+    pub fn start(&self, req: &ServiceRequest) -> Option<Entry> {
+        if !tracing::enabled!(target: TARGET, Level::INFO) {
+            return None;
+        }
+        let uri = match req.query_string() {
+            "" => req.path().to_string(),
+            query => format!("{}?{}", req.path(), self.redact_query(query)),
+        };
+        tracing::debug!(
+            target: TARGET,
+            method = %req.method(),
+            uri = %uri,
+            headers = %redact_headers(req.headers()),
+            "request"
+        );
+        let forwarded = ["forwarded", "x-forwarded-for"]
+            .iter()
+            .any(|name| req.headers().contains_key(*name));
+        Some(Entry {
+            start: Instant::now(),
+            method: req.method().clone(),
+            uri,
+            // unix socket connections have no peer address
+            client: req
+                .peer_addr()
+                .map_or("-".to_string(), |address| address.ip().to_string()),
+            forwarded_for: forwarded.then(|| {
+                req.connection_info()
+                    .realip_remote_addr()
+                    .unwrap_or("-")
+                    .to_string()
+            }),
+        })
+    }
+
+    fn redact_query(&self, query: &str) -> String {
+        query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if self.is_redacted(name) => format!("{}={}", name, REDACTED),
+                _ => pair.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    fn is_redacted(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.redacted
+            .iter()
+            .any(|part| name.contains(part.as_str()))
+    }
+}
+
+impl Entry {
+    /// Log the response, the latency is the time until the headers are ready, not
+    /// until a streamed body is sent
+    pub fn finish<B: MessageBody>(self, res: &ServiceResponse<B>) {
+        let file = res
+            .request()
+            .extensions()
+            .get::<ServedFile>()
+            .map(|file| file.0.clone());
+        self.log(res.status(), res.response().body().size(), file.as_deref());
+    }
+
+    /// Log a request whose handling ended in an error instead of a response
+    pub fn failed(self, error: &actix_web::Error) {
+        self.log(
+            error.as_response_error().status_code(),
+            BodySize::None,
+            None,
+        );
+    }
+
+    fn log(self, status: StatusCode, size: BodySize, file: Option<&Path>) {
+        let size = match size {
+            BodySize::None => "0".to_string(),
+            BodySize::Sized(size) => size.to_string(),
+            BodySize::Stream => "-".to_string(),
+        };
+        tracing::info!(
+            target: TARGET,
+            method = %self.method,
+            uri = %self.uri,
+            status = status.as_u16(),
+            latency = ?self.start.elapsed(),
+            size = %size,
+            client = %self.client,
+            forwarded_for = %self.forwarded_for.as_deref().unwrap_or("-"),
+            file = %file.map_or("-".into(), |file| file.display().to_string()),
+        );
+    }
+}
+
+fn redact_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                REDACTED
+            } else {
+                value.to_str().unwrap_or("<binary>")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    #[test]
+    fn redacts_credentials() {
+        let log = AccessLog::new(&AccessLogOptions::default());
+        assert_eq!(
+            log.redact_query("archive=zip&access_token=abc&API_KEY=1&force=1"),
+            "archive=zip&access_token=[redacted]&API_KEY=[redacted]&force=1"
+        );
+        assert_eq!(log.redact_query("download"), "download");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static("authorization"),
+            HeaderValue::from_static("Bearer abc"),
+        );
+        headers.insert(
+            HeaderName::from_static("accept"),
+            HeaderValue::from_static("*/*"),
+        );
+        let logged = redact_headers(&headers);
+        assert!(logged.contains("authorization: [redacted]"));
+        assert!(logged.contains("accept: */*"));
+        assert!(!logged.contains("abc"));
+    }
+}
@@ -0,0 +1,88 @@
+//! The JSON API served below [`PREFIX`]. Within a version, routes and the shape of
+//! requests and responses only change in backwards compatible ways, i.e. new routes,
+//! fields or query parameters. Anything else gets a new version. Routes outside the
+//! prefix (frontend, store files, badges and the binary cache) are not part of it.
+
+use actix_web::{
+    HttpResponse, get,
+    middleware::DefaultHeaders,
+    web::{self, ServiceConfig},
+};
+use serde::Serialize;
+
+use super::{
+    Semaphore, archive, builder_info, cancel, changes, durations, export, history, logs,
+    push_hooks::{self, HookSecrets},
+    rebuild, reload, schema, sse, ws,
+};
+
+pub const VERSION: &str = "1";
+pub const PREFIX: &str = "/api/v1";
+
+/// Revision the server was built from, set by the flake
+pub(super) const GIT_REV: &str = match option_env!("GIT_REV") {
+    Some(rev) => rev,
+    None => "unknown",
+};
+
+#[derive(Serialize, Debug)]
+struct VersionInfo {
+    api_version: &'static str,
+    version: &'static str,
+    git_rev: &'static str,
+}
+
+/// `prefix` is the `base_path` all routes are below, for links in headers
+pub fn configure(cfg: &mut ServiceConfig, prefix: &str, hook_secrets: HookSecrets) {
+    cfg.service(
+        web::scope(PREFIX)
+            .wrap(DefaultHeaders::new().add(("X-Api-Version", VERSION)))
+            .service(version)
+            .service(queue)
+            .service(reload::config_status)
+            .service(schema::config_schema)
+            .route("/repos", web::get().to(super::repos))
+            .service(changes::repo_changes)
+            .service(super::poll_repo)
+            .service(cancel::cancel_commit)
+            .service(cancel::cancel_branch)
+            .service(cancel::cancel_package)
+            .service(rebuild::rebuild_package)
+            .service(logs::package_log)
+            .service(history::package_history)
+            .service(durations::duration_stats)
+            .service(durations::reset_durations)
+            .service(builder_info::current_builder_info)
+            .service(builder_info::builder_info)
+            .service(ws::ws)
+            .service(sse::event_stream)
+            .service(export::export)
+            .service(archive::archive_index)
+            .service(archive::archive_month)
+            .configure(|cfg| push_hooks::configure(cfg, hook_secrets)),
+    )
+    // deprecated, from before the API was versioned
+    .service(
+        web::resource("/repos")
+            .wrap(DefaultHeaders::new().add(("Deprecation", "true")).add((
+                "Link",
+                format!("<{}{}/repos>; rel=\"successor-version\"", prefix, PREFIX),
+            )))
+            .route(web::get().to(super::repos)),
+    );
+}
+
+#[get("/version")]
+async fn version() -> HttpResponse {
+    HttpResponse::Ok().json(VersionInfo {
+        api_version: VERSION,
+        version: env!("CARGO_PKG_VERSION"),
+        git_rev: GIT_REV,
+    })
+}
+
+/// Builds waiting for a slot, first in line first
+#[get("/queue")]
+async fn queue() -> HttpResponse {
+    HttpResponse::Ok().json(Semaphore::get_sem().queue())
+}
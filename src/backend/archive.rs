@@ -0,0 +1,292 @@
+//! Cold storage for commits that leave the build state after
+//! `archive_after_days`, or because of `max_commits_per_repo` or
+//! `max_commit_age_days` while an archive is kept. They go to one gzipped file of
+//! JSON lines per month of their commit time in `archive` below `paths.state_dir`,
+//! which is only ever appended to, one gzip member per batch, so what was archived
+//! never changes, and a commit that is in a month already is not appended again.
+//! `index.json` next to them counts the commits of each month.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    fs::OpenOptions,
+    io::{ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use actix_web::{HttpResponse, get, web};
+use flate2::{Compression, read::MultiGzDecoder, write::GzEncoder};
+
+use crate::{
+    AutoBuildOptions,
+    archive::{ArchiveMonth, ArchivedCommit, ArchivedPackage},
+    commit::CommitInfo,
+};
+
+const DIR_NAME: &str = "archive";
+const INDEX_NAME: &str = "index.json";
+
+/// Held while a batch is appended and the index updated, the pollers of all repos
+/// archive into the same files
+static WRITING: Mutex<()> = Mutex::new(());
+
+fn dir(settings: &AutoBuildOptions) -> PathBuf {
+    settings.state_dir().join(DIR_NAME)
+}
+
+fn month_path(dir: &Path, month: &str) -> PathBuf {
+    dir.join(format!("{}.jsonl.gz", month))
+}
+
+/// `YYYY-MM`, nothing that could leave the directory
+fn is_month(month: &str) -> bool {
+    month.len() == 7
+        && month.char_indices().all(|(index, c)| match index {
+            4 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// `YYYY-MM` in UTC of `unix_secs`
+fn month_of(unix_secs: i64) -> String {
+    chrono::DateTime::from_timestamp(unix_secs, 0)
+        .unwrap_or_default()
+        .format("%Y-%m")
+        .to_string()
+}
+
+fn archived(commit: &CommitInfo, now: i64) -> ArchivedCommit {
+    ArchivedCommit {
+        repo: commit.repo_url(),
+        hash: commit.hash.clone(),
+        message: commit.message.clone(),
+        unix_secs: commit.unix_secs,
+        archived_secs: now,
+        packages: commit
+            .packages
+            .get()
+            .iter()
+            .map(|pkg| ArchivedPackage {
+                path: pkg.path().to_string(),
+                status: pkg.status(),
+                times: pkg.times(),
+                closure_size: pkg.closure_size(),
+            })
+            .collect(),
+    }
+}
+
+/// Archive `commits` before they are forgotten. On an error, nothing may be
+/// forgotten, a batch may still have been appended to some months, which the next
+/// try skips.
+pub fn archive(
+    settings: &AutoBuildOptions,
+    commits: &[Arc<CommitInfo>],
+    now: i64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let commits = commits
+        .iter()
+        .map(|commit| archived(commit, now))
+        .collect::<Vec<_>>();
+    write(&dir(settings), &commits)
+}
+
+fn write(dir: &Path, commits: &[ArchivedCommit]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut months = BTreeMap::<String, Vec<&ArchivedCommit>>::new();
+    for commit in commits {
+        months
+            .entry(month_of(commit.unix_secs))
+            .or_default()
+            .push(commit);
+    }
+    let _writing = WRITING.lock().unwrap_or_else(PoisonError::into_inner);
+    std::fs::create_dir_all(dir)?;
+    let mut index = load_index(dir)
+        .into_iter()
+        .map(|month| (month.month.clone(), month))
+        .collect::<BTreeMap<_, _>>();
+    for (month, commits) in months {
+        let known = archived_keys(dir, &month)?;
+        let commits = commits
+            .into_iter()
+            .filter(|commit| !known.contains(&(commit.repo.clone(), commit.hash.clone())))
+            .collect::<Vec<_>>();
+        if commits.is_empty() {
+            continue;
+        }
+        let path = month_path(dir, &month);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        for commit in &commits {
+            serde_json::to_writer(&mut encoder, commit)?;
+            encoder.write_all(b"\n")?;
+        }
+        let file = encoder.finish()?;
+        file.sync_all()?;
+        let entry = index.entry(month.clone()).or_insert(ArchiveMonth {
+            month,
+            commits: 0,
+            bytes: 0,
+        });
+        entry.commits += commits.len();
+        entry.bytes = file.metadata()?.len();
+    }
+    let temporary = dir.join(format!("{}.tmp", INDEX_NAME));
+    std::fs::write(
+        &temporary,
+        serde_json::to_vec(&index.into_values().collect::<Vec<_>>())?,
+    )?;
+    std::fs::rename(&temporary, dir.join(INDEX_NAME))?;
+    Ok(())
+}
+
+/// Repo and hash of the commits archived in `month`
+fn archived_keys(
+    dir: &Path,
+    month: &str,
+) -> Result<HashSet<(String, String)>, Box<dyn std::error::Error>> {
+    let Some(json) = read(dir, month)? else {
+        return Ok(HashSet::new());
+    };
+    let commits = serde_json::from_str::<Vec<serde_json::Value>>(&json)?;
+    Ok(commits
+        .iter()
+        .filter_map(|commit| {
+            Some((
+                commit["repo"].as_str()?.to_string(),
+                commit["hash"].as_str()?.to_string(),
+            ))
+        })
+        .collect())
+}
+
+fn load_index(dir: &Path) -> Vec<ArchiveMonth> {
+    let path = dir.join(INDEX_NAME);
+    match std::fs::read(&path) {
+        Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|e| {
+            tracing::error!("reading the archive index {}: {}", path.display(), e);
+            Vec::new()
+        }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+        Err(e) => {
+            tracing::error!("reading the archive index {}: {}", path.display(), e);
+            Vec::new()
+        }
+    }
+}
+
+/// The archived months, oldest first, also part of [`super::export`]
+pub fn index(settings: &AutoBuildOptions) -> Vec<ArchiveMonth> {
+    load_index(&dir(settings))
+}
+
+/// The commits of `month` as a JSON array, `None` if none were archived in it
+fn read(dir: &Path, month: &str) -> std::io::Result<Option<String>> {
+    let file = match std::fs::File::open(month_path(dir, month)) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let mut lines = String::new();
+    // a batch cut short by a crash ends the file, the complete lines before it count
+    if let Err(e) = MultiGzDecoder::new(file).read_to_string(&mut lines) {
+        tracing::warn!("the archive of {} ends early: {}", month, e);
+    }
+    let commits = lines
+        .lines()
+        .filter(|line| line.ends_with('}'))
+        .collect::<Vec<_>>()
+        .join(",");
+    Ok(Some(format!("[{}]", commits)))
+}
+
+#[get("/archive")]
+async fn archive_index(settings: web::Data<AutoBuildOptions>) -> HttpResponse {
+    HttpResponse::Ok().json(index(&settings))
+}
+
+#[get("/archive/{month}")]
+async fn archive_month(
+    path: web::Path<String>,
+    settings: web::Data<AutoBuildOptions>,
+) -> HttpResponse {
+    let month = path.into_inner();
+    tracing::debug!("Requested the archive of {}", month);
+    if !is_month(&month) {
+        return HttpResponse::BadRequest().body("400 Bad Request: expected YYYY-MM");
+    }
+    let dir = dir(&settings);
+    match web::block(move || read(&dir, &month)).await {
+        Ok(Ok(Some(commits))) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(commits),
+        Ok(Ok(None)) => HttpResponse::NotFound().body("404 Not Found"),
+        Ok(Err(e)) => {
+            tracing::error!("reading the archive: {}", e);
+            HttpResponse::InternalServerError().body("500 Internal Server Error")
+        }
+        Err(e) => {
+            tracing::error!("reading the archive: {}", e);
+            HttpResponse::InternalServerError().body("500 Internal Server Error")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(hash: &str, unix_secs: i64) -> ArchivedCommit {
+        ArchivedCommit {
+            repo: "github.com/org/repo".to_string(),
+            hash: hash.to_string(),
+            message: "Change".to_string(),
+            unix_secs,
+            archived_secs: 1_800_000_000,
+            packages: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn batches_are_appended_per_month() -> Result<(), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("nix_autobuild_archive_{}", std::process::id()));
+        // 2024-01-15 and 2024-02-15
+        let (january, february) = (1_705_276_800, 1_707_955_200);
+        write(&dir, &[commit("a", january), commit("b", february)])?;
+        let first_batch = std::fs::read(month_path(&dir, "2024-01"))?;
+        write(&dir, &[commit("c", january)])?;
+        // e.g. retried after archiving another month failed
+        let second_batch = std::fs::read(month_path(&dir, "2024-01"))?;
+        write(&dir, &[commit("a", january)])?;
+        assert_eq!(std::fs::read(month_path(&dir, "2024-01"))?, second_batch);
+
+        // the first batch is left as it was
+        assert!(std::fs::read(month_path(&dir, "2024-01"))?.starts_with(&first_batch));
+        let hashes = |month: &str| -> Result<Vec<String>, Box<dyn std::error::Error>> {
+            let json = read(&dir, month)?.unwrap_or_default();
+            let commits = serde_json::from_str::<Vec<serde_json::Value>>(&json)?;
+            Ok(commits
+                .iter()
+                .map(|commit| commit["hash"].as_str().unwrap_or_default().to_string())
+                .collect())
+        };
+        assert_eq!(hashes("2024-01")?, ["a", "c"]);
+        assert_eq!(hashes("2024-02")?, ["b"]);
+        assert_eq!(read(&dir, "2024-03")?, None);
+        let counts = load_index(&dir)
+            .iter()
+            .map(|month| (month.month.clone(), month.commits))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            counts,
+            [("2024-01".to_string(), 2), ("2024-02".to_string(), 1)]
+        );
+
+        assert!(is_month("2024-01"));
+        assert!(!is_month("../2024"));
+        assert!(!is_month("2024-1"));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use actix_web::{HttpResponse, get, http::header, web};
+
+use crate::package::AggregateStatus;
+
+use super::BuildRepos;
+
+#[get("/badge/{repo}.svg")]
+async fn repo_badge(path: web::Path<String>, repos: web::Data<BuildRepos>) -> HttpResponse {
+    let repo_name = path.into_inner();
+    tracing::debug!("Requested badge for {}", repo_name);
+    let status = repos.find(&repo_name).and_then(|repo| {
+        let branch = repo.repo.branches.first()?;
+        repo.branch_status(branch)
+    });
+    badge_response(status)
+}
+
+#[get("/badge/{repo}/{branch:.*}.svg")]
+async fn branch_badge(
+    path: web::Path<(String, String)>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (repo_name, branch) = path.into_inner();
+    tracing::debug!("Requested badge for {} {}", repo_name, branch);
+    let status = repos
+        .find(&repo_name)
+        .and_then(|repo| repo.branch_status(&branch));
+    badge_response(status)
+}
+
+fn badge_response(status: Option<AggregateStatus>) -> HttpResponse {
+    let (message, color) = match status {
+        Some(AggregateStatus::Success) => ("passing", "#4c1"),
+        Some(AggregateStatus::Failed) => ("failing", "#e05d44"),
+        Some(AggregateStatus::Building) => ("building", "#dfb317"),
+        None => ("unknown", "#9f9f9f"),
+    };
+    HttpResponse::Ok()
+        .content_type("image/svg+xml")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .body(render_badge("build", message, color))
+}
+
+/// Render a flat shields.io style badge
+fn render_badge(label: &str, message: &str, color: &str) -> String {
+    // rough width estimate for 11px Verdana, good enough for short ascii words
+    let text_width = |text: &str| text.chars().count() * 7 + 10;
+    let label_width = text_width(label);
+    let message_width = text_width(message);
+    let width = label_width + message_width;
+    let label_x = label_width * 5;
+    let message_x = (label_width * 2 + message_width) * 5;
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {message}">
+<title>{label}: {message}</title>
+<linearGradient id="s" x2="0" y2="100%"><stop offset="0" stop-color="#bbb" stop-opacity=".1"/><stop offset="1" stop-opacity=".1"/></linearGradient>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)">
+<rect width="{label_width}" height="20" fill="#555"/>
+<rect x="{label_width}" width="{message_width}" height="20" fill="{color}"/>
+<rect width="{width}" height="20" fill="url(#s)"/>
+</g>
+<g fill="#fff" text-anchor="middle" font-family="Verdana,Geneva,DejaVu Sans,sans-serif" font-size="110">
+<text x="{label_x}" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)">{label}</text>
+<text x="{label_x}" y="140" transform="scale(.1)">{label}</text>
+<text x="{message_x}" y="150" fill="#010101" fill-opacity=".3" transform="scale(.1)">{message}</text>
+<text x="{message_x}" y="140" transform="scale(.1)">{message}</text>
+</g>
+</svg>"##
+    )
+}
@@ -0,0 +1,216 @@
+//! `build-once`: clone or update the repos, build the tip of each branch a single
+//! time and exit, for cron jobs and CI. Nothing is served and nothing is polled.
+
+use std::{process::ExitCode, sync::Arc, thread, time::Duration};
+
+use git2::{BranchType, Repository};
+
+use crate::{
+    AutoBuildOptions, Repo,
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+use super::{
+    CommitInfoTrait, PackageEnumTrait, RepoInfoTrait, Semaphore, logging, paths, validate,
+};
+
+/// How often the builds are checked for being done
+const WAIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A package with the commit it is built from
+type CommitPackage = (Arc<CommitInfo>, PackageEnum);
+
+/// `SUCCESS` only if every package built, was dry run or was skipped for its
+/// architecture
+pub fn run(
+    config_path: &str,
+    repo: Option<&str>,
+    rev: Option<&str>,
+    dry_run: bool,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let settings = Arc::new(logging::init_from_config(config_path)?);
+    // nothing is served, so the address does not matter
+    for warning in validate::validate(&settings, false)? {
+        tracing::warn!("config {}", warning);
+    }
+    Semaphore::init(super::build_pool_size(&settings));
+    super::dry_run::set(dry_run || settings.dry_run);
+    super::post_build::configure(&settings.post_build);
+    paths::create_dirs(&settings)?;
+
+    let repos = settings
+        .repos
+        .iter()
+        .filter(|candidate| repo.is_none_or(|name| super::repo_matches(candidate, name)))
+        .collect::<Vec<_>>();
+    if let Some(name) = repo
+        && repos.is_empty()
+    {
+        return Err(format!("No repo {} in {}", name, config_path).into());
+    }
+
+    let mut failed = false;
+    let mut packages = Vec::new();
+    for repo in repos {
+        match packages_of(repo, &settings, rev) {
+            Ok(found) => packages.extend(found),
+            Err(e) => {
+                tracing::error!("in repo {}: {}", repo.url, e);
+                failed = true;
+            }
+        }
+    }
+
+    packages.iter().for_each(|(_, pkg)| pkg.build());
+    while !packages.iter().all(|(_, pkg)| pkg.status().is_finished()) {
+        thread::sleep(WAIT_INTERVAL);
+    }
+
+    for (commit, pkg) in &packages {
+        let status = pkg.status();
+        let label = match &status {
+            PackageBuildStatus::Success(_) => "OK",
+            PackageBuildStatus::DryRun { .. } => "DRY-RUN",
+            PackageBuildStatus::UnsupportedArchitecture(_) => "SKIPPED",
+            _ => "FAILED",
+        };
+        failed |= label == "FAILED";
+        match status {
+            PackageBuildStatus::DryRun {
+                to_build,
+                to_substitute,
+            } => println!(
+                "{}\t{}#{}\t{} to build, {} to fetch",
+                label,
+                commit.flake_url,
+                pkg.path(),
+                to_build,
+                to_substitute
+            ),
+            _ => println!("{}\t{}#{}", label, commit.flake_url, pkg.path()),
+        }
+    }
+    Ok(match failed {
+        true => ExitCode::FAILURE,
+        false => ExitCode::SUCCESS,
+    })
+}
+
+/// Packages of `rev`, or of the tip of each branch
+fn packages_of(
+    repo: &Repo,
+    settings: &Arc<AutoBuildOptions>,
+    rev: Option<&str>,
+) -> Result<Vec<CommitPackage>, Box<dyn std::error::Error>> {
+    let checkout_path = super::checkout_path(settings, repo);
+    let repo_info = RepoInfo::new(repo.clone(), checkout_path, settings.clone());
+    let repository = repo_info.clone_or_open()?;
+    repo_info.fetch_changes(&repository)?;
+
+    let mut commits = Vec::new();
+    for commit in tips(repo, &repository, rev)? {
+        if commits
+            .iter()
+            .any(|other: &Arc<CommitInfo>| other.hash == commit.id().to_string())
+        {
+            continue;
+        }
+        commits.push(CommitInfo::new(repo_info.clone(), &repository, &commit));
+    }
+    let mut packages = Vec::new();
+    for commit in commits {
+        let found = commit.get_pkgs_list(&commit.flake_url, true)?;
+        commit.packages.set(found.clone());
+        packages.extend(found.into_iter().map(|pkg| (commit.clone(), pkg)));
+    }
+    Ok(packages)
+}
+
+/// The commit of `rev`, which has to be on one of the branches, or the tip of each
+/// branch
+fn tips<'repo>(
+    repo: &Repo,
+    repository: &'repo Repository,
+    rev: Option<&str>,
+) -> Result<Vec<git2::Commit<'repo>>, git2::Error> {
+    let branch_tips = repo
+        .branches
+        .iter()
+        .map(|branch| {
+            repository
+                .find_branch(&format!("origin/{}", branch), BranchType::Remote)?
+                .get()
+                .peel_to_commit()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let Some(rev) = rev else {
+        return Ok(branch_tips);
+    };
+    let commit = repository.revparse_single(rev)?.peel_to_commit()?;
+    for tip in &branch_tips {
+        if tip.id() == commit.id() || repository.graph_descendant_of(tip.id(), commit.id())? {
+            return Ok(vec![commit]);
+        }
+    }
+    Err(git2::Error::from_str(&format!(
+        "{} is on none of the branches {}",
+        rev,
+        repo.branches.join(", ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Oid, Signature};
+
+    fn commit(repository: &Repository, message: &str, parents: &[Oid]) -> Result<Oid, git2::Error> {
+        let signature = Signature::now("test", "test@example.com")?;
+        let tree = repository.find_tree(repository.treebuilder(None)?.write()?)?;
+        let parents = parents
+            .iter()
+            .map(|parent| repository.find_commit(*parent))
+            .collect::<Result<Vec<_>, _>>()?;
+        let parents = parents.iter().collect::<Vec<_>>();
+        repository.commit(None, &signature, &signature, message, &tree, &parents)
+    }
+
+    #[test]
+    fn a_rev_has_to_be_on_a_branch() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_rev_{}", std::process::id()));
+        let repository = Repository::init(&dir)?;
+        let first = commit(&repository, "first", &[])?;
+        let second = commit(&repository, "second", &[first])?;
+        let elsewhere = commit(&repository, "elsewhere", &[first])?;
+        repository.reference("refs/remotes/origin/main", second, true, "test")?;
+        let repo = serde_json::from_value::<Repo>(serde_json::json!({
+            "url": "github.com/org/repo",
+            "branches": ["main"]
+        }))?;
+
+        let ids = |rev| -> Result<Vec<Oid>, git2::Error> {
+            Ok(tips(&repo, &repository, rev)?
+                .iter()
+                .map(|c| c.id())
+                .collect())
+        };
+        let results = (
+            ids(None),
+            ids(Some(&first.to_string())),
+            ids(Some(&second.to_string())),
+            ids(Some(&elsewhere.to_string())),
+        );
+        std::fs::remove_dir_all(&dir)?;
+        assert_eq!(results.0?, [second]);
+        assert_eq!(results.1?, [first]);
+        assert_eq!(results.2?, [second]);
+        assert!(
+            results
+                .3
+                .is_err_and(|e| e.message().contains("none of the branches main"))
+        );
+        Ok(())
+    }
+}
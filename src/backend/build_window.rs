@@ -0,0 +1,297 @@
+//! Quiet hours of the builder: outside of `build_window` packages wait in
+//! `WaitingForWindow`, and once it opens they are let through oldest commit first
+
+use std::{
+    collections::BTreeSet,
+    sync::{Condvar, Mutex, MutexGuard, OnceLock, PoisonError, RwLock},
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta, Utc};
+use chrono_tz::Tz;
+
+use crate::{BuildWindowOptions, package::BuildControl};
+
+/// How often waiting builds look for a cancel, and the scheduler for clock changes
+const RECHECK: Duration = Duration::from_secs(60);
+
+const TIME_FORMAT: &str = "%H:%M";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Window {
+    start: NaiveTime,
+    end: NaiveTime,
+    /// `None` for the local time of the server
+    timezone: Option<Tz>,
+}
+
+fn parse_time(field: &str, time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time, TIME_FORMAT)
+        .map_err(|_| format!("invalid {} `{}`, use HH:MM", field, time))
+}
+
+impl Window {
+    pub fn parse(options: &BuildWindowOptions) -> Result<Self, String> {
+        let start = parse_time("start", &options.start)?;
+        let end = parse_time("end", &options.end)?;
+        if start == end {
+            return Err(format!("start and end are both {}", options.start));
+        }
+        let timezone = match &options.timezone {
+            Some(name) => Some(
+                name.parse::<Tz>()
+                    .map_err(|_| format!("unknown timezone `{}`", name))?,
+            ),
+            None => None,
+        };
+        Ok(Window {
+            start,
+            end,
+            timezone,
+        })
+    }
+
+    fn local(&self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self.timezone {
+            Some(tz) => at.with_timezone(&tz).naive_local(),
+            None => at.with_timezone(&Local).naive_local(),
+        }
+    }
+
+    /// A window with `start` after `end` spans midnight
+    pub fn contains(&self, at: DateTime<Utc>) -> bool {
+        let time = self.local(at).time();
+        match self.start < self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        }
+    }
+
+    /// Until the window opens or closes next, ignoring clock changes on the way
+    fn until_change(&self, at: DateTime<Utc>) -> TimeDelta {
+        let time = self.local(at).time();
+        let until = |target: NaiveTime| match target - time {
+            delta if delta > TimeDelta::zero() => delta,
+            delta => delta + TimeDelta::days(1),
+        };
+        until(self.start).min(until(self.end))
+    }
+}
+
+struct GateState {
+    open: bool,
+    next_ticket: u64,
+    /// commit time and ticket of the waiting builds, oldest first
+    waiting: BTreeSet<(i64, u64)>,
+}
+
+impl GateState {
+    /// Open, and no older ticket waits
+    fn lets_through(&self, ticket: &(i64, u64)) -> bool {
+        self.open && self.waiting.first() == Some(ticket)
+    }
+}
+
+struct Gate {
+    /// replaced by a reload, `None` once the reloaded config has no window
+    window: RwLock<Option<Window>>,
+    state: Mutex<GateState>,
+    condvar: Condvar,
+}
+
+impl Gate {
+    fn lock(&self) -> MutexGuard<'_, GateState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn window(&self) -> Option<Window> {
+        self.window
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Open or close the gate for the current window at `now`, and tell how long
+    /// until that may change
+    fn update(&self, now: DateTime<Utc>) -> Duration {
+        let window = self.window();
+        let open = window.as_ref().is_none_or(|window| window.contains(now));
+        let mut state = self.lock();
+        if state.open != open {
+            state.open = open;
+            match open {
+                true => tracing::info!(
+                    "Build window opened, {} builds waiting",
+                    state.waiting.len()
+                ),
+                false => tracing::info!("Build window closed"),
+            }
+            self.condvar.notify_all();
+        }
+        window
+            .and_then(|window| window.until_change(now).to_std().ok())
+            .map_or(RECHECK, |until| until.min(RECHECK))
+    }
+
+    /// Opens and closes the gate with the window, for as long as the server runs
+    fn schedule(&self) {
+        loop {
+            thread::sleep(self.update(Utc::now()));
+        }
+    }
+}
+
+/// Only set by `serve`, `build-once` builds right away
+static GATE: OnceLock<Gate> = OnceLock::new();
+
+/// Hold back builds outside of `options` from now on, without options they run at
+/// any time. Called at startup and again by every reload.
+pub fn set(options: Option<&BuildWindowOptions>) -> Result<(), String> {
+    let window = options.map(Window::parse).transpose()?;
+    let gate = match (GATE.get(), &window) {
+        (Some(gate), _) => gate,
+        (None, None) => return Ok(()),
+        (None, Some(_)) => {
+            let mut spawned = false;
+            let gate = GATE.get_or_init(|| {
+                spawned = true;
+                Gate {
+                    window: RwLock::new(None),
+                    state: Mutex::new(GateState {
+                        open: true,
+                        next_ticket: 0,
+                        waiting: BTreeSet::new(),
+                    }),
+                    condvar: Condvar::new(),
+                }
+            });
+            if spawned {
+                thread::spawn(|| gate.schedule());
+            }
+            gate
+        }
+    };
+    if gate.window() == window {
+        return Ok(());
+    }
+    match options {
+        Some(options) => tracing::info!(
+            "Building only between {} and {} {}",
+            options.start,
+            options.end,
+            options.timezone.as_deref().unwrap_or("local time")
+        ),
+        None => tracing::info!("Building at any time"),
+    }
+    *gate.window.write().unwrap_or_else(PoisonError::into_inner) = window;
+    gate.update(Utc::now());
+    Ok(())
+}
+
+/// Builds may start now
+pub fn is_open() -> bool {
+    GATE.get().is_none_or(|gate| gate.lock().open)
+}
+
+/// Block a build of a commit from `commit_secs` until the window is open and no
+/// older commit waits, or until it is cancelled. `waiting` runs first if it has to
+/// wait at all.
+pub fn wait(commit_secs: i64, control: &BuildControl, waiting: impl FnOnce()) {
+    let Some(gate) = GATE.get() else {
+        return;
+    };
+    let ticket = {
+        let mut state = gate.lock();
+        if state.open && state.waiting.is_empty() {
+            return;
+        }
+        let ticket = (commit_secs, state.next_ticket);
+        state.next_ticket += 1;
+        state.waiting.insert(ticket);
+        ticket
+    };
+    waiting();
+
+    let mut state = gate.lock();
+    while !control.is_cancelled() && !state.lets_through(&ticket) {
+        state = gate
+            .condvar
+            .wait_timeout(state, RECHECK)
+            .unwrap_or_else(PoisonError::into_inner)
+            .0;
+    }
+    state.waiting.remove(&ticket);
+    // the next oldest may go too
+    gate.condvar.notify_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn window(start: &str, end: &str) -> Result<Window, String> {
+        Window::parse(&BuildWindowOptions {
+            start: start.to_string(),
+            end: end.to_string(),
+            timezone: Some("Europe/Berlin".to_string()),
+        })
+    }
+
+    fn utc(hour: u32, minute: u32) -> Result<DateTime<Utc>, String> {
+        Utc.with_ymd_and_hms(2026, 1, 7, hour, minute, 0)
+            .single()
+            .ok_or_else(|| "no such time".to_string())
+    }
+
+    #[test]
+    fn windows_may_span_midnight() -> Result<(), String> {
+        // Berlin is UTC+1 in January
+        let night = window("22:00", "06:00")?;
+        assert!(night.contains(utc(21, 0)?));
+        assert!(night.contains(utc(4, 59)?));
+        assert!(!night.contains(utc(5, 0)?));
+        assert!(!night.contains(utc(20, 59)?));
+        assert_eq!(night.until_change(utc(20, 30)?), TimeDelta::minutes(30));
+        assert_eq!(night.until_change(utc(21, 0)?), TimeDelta::hours(8));
+
+        let lunch = window("12:00", "13:30")?;
+        assert!(lunch.contains(utc(11, 0)?));
+        assert!(!lunch.contains(utc(12, 30)?));
+        assert_eq!(
+            lunch.until_change(utc(12, 30)?),
+            TimeDelta::hours(22) + TimeDelta::minutes(30)
+        );
+
+        assert!(window("22:00", "22:00").is_err());
+        assert!(window("10pm", "06:00").is_err());
+        let mars = BuildWindowOptions {
+            start: "22:00".to_string(),
+            end: "06:00".to_string(),
+            timezone: Some("Mars/Olympus_Mons".to_string()),
+        };
+        assert!(Window::parse(&mars).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn a_reloaded_window_moves_the_gate() -> Result<(), String> {
+        let gate = Gate {
+            window: RwLock::new(Some(window("22:00", "06:00")?)),
+            state: Mutex::new(GateState {
+                open: true,
+                next_ticket: 0,
+                waiting: BTreeSet::new(),
+            }),
+            condvar: Condvar::new(),
+        };
+        assert_eq!(gate.update(utc(20, 30)?), Duration::from_secs(60));
+        assert!(!gate.lock().open);
+
+        *gate.window.write().unwrap_or_else(PoisonError::into_inner) = None;
+        gate.update(utc(20, 30)?);
+        assert!(gate.lock().open);
+        Ok(())
+    }
+}
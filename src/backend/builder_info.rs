@@ -0,0 +1,261 @@
+//! The environment the builds run in: the nix version, the nix settings that decide
+//! how and where paths are built and fetched from, the kernel and this server. A
+//! snapshot is taken at startup and on every config reload, and each attempt keeps
+//! the id of the one it was built on, so a result that differs from a local build
+//! can be traced to its builder. The snapshots are kept with the build state of the
+//! `sqlite` and `json` backends.
+
+use std::{
+    collections::BTreeMap,
+    process::Command,
+    sync::{Mutex, PoisonError},
+};
+
+use actix_web::{HttpResponse, get, web};
+use ring::digest;
+use serde_json::Value;
+
+use crate::{
+    builder::BuilderInfo,
+    package::{BuildAttempt, PackageBuildStatus},
+};
+
+use super::{api, database, events};
+
+/// The settings of `nix config show` kept in a snapshot
+const NIX_CONFIG_KEYS: [&str; 9] = [
+    "system",
+    "extra-platforms",
+    "system-features",
+    "sandbox",
+    "max-jobs",
+    "cores",
+    "substituters",
+    "trusted-public-keys",
+    "experimental-features",
+];
+
+struct Snapshots {
+    /// the latest snapshot, which new builds run on
+    current: Option<String>,
+    /// all known snapshots by id
+    known: BTreeMap<String, BuilderInfo>,
+}
+
+static SNAPSHOTS: Mutex<Snapshots> = Mutex::new(Snapshots {
+    current: None,
+    known: BTreeMap::new(),
+});
+
+/// Trimmed stdout of `program` with `args`, `None` if it fails
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(output) => output,
+        Err(e) => {
+            tracing::warn!("running {} for the builder info: {}", program, e);
+            return None;
+        }
+    };
+    if !output.status.success() {
+        tracing::warn!(
+            "{} {} for the builder info: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// The [`NIX_CONFIG_KEYS`] of the JSON of `nix config show`, lists joined by spaces
+fn nix_config(json: &str) -> BTreeMap<String, String> {
+    let Ok(Value::Object(config)) = serde_json::from_str::<Value>(json) else {
+        return BTreeMap::new();
+    };
+    NIX_CONFIG_KEYS
+        .into_iter()
+        .filter_map(|key| {
+            let value = match config.get(key)?.get("value")? {
+                Value::String(value) => value.clone(),
+                Value::Array(values) => values
+                    .iter()
+                    .map(|value| match value {
+                        Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                value => value.to_string(),
+            };
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+/// SHA-256 of the fields but `id` and `captured_secs`, the first 12 hex digits
+fn id_of(info: &BuilderInfo) -> String {
+    let identity = serde_json::json!([
+        info.nix_version,
+        info.nix_config,
+        info.kernel,
+        info.system,
+        info.server_version,
+        info.git_rev,
+    ]);
+    digest::digest(&digest::SHA256, identity.to_string().as_bytes())
+        .as_ref()
+        .iter()
+        .take(6)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Take a snapshot of the builder, the builds started from now on refer to it
+pub fn capture() {
+    let unknown = || "unknown".to_string();
+    let config = output("nix", &["config", "show", "--json"])
+        // before nix 2.20
+        .or_else(|| output("nix", &["show-config", "--json"]));
+    let mut info = BuilderInfo {
+        id: String::new(),
+        captured_secs: events::unix_now(),
+        nix_version: output("nix", &["--version"]).unwrap_or_else(unknown),
+        nix_config: config.as_deref().map(nix_config).unwrap_or_default(),
+        kernel: output("uname", &["-srm"]).unwrap_or_else(unknown),
+        system: format!("{}-{}", std::env::consts::ARCH, std::env::consts::OS),
+        server_version: env!("CARGO_PKG_VERSION").to_string(),
+        git_rev: api::GIT_REV.to_string(),
+    };
+    info.id = id_of(&info);
+    let new = {
+        let mut snapshots = SNAPSHOTS.lock().unwrap_or_else(PoisonError::into_inner);
+        snapshots.current = Some(info.id.clone());
+        match snapshots.known.contains_key(&info.id) {
+            true => None,
+            false => {
+                snapshots.known.insert(info.id.clone(), info.clone());
+                Some(info)
+            }
+        }
+    };
+    match new {
+        Some(info) => {
+            tracing::info!("Building on {} with {}", info.kernel, info.nix_version);
+            database::record_builder(&info);
+        }
+        None => tracing::debug!("The builder did not change"),
+    }
+}
+
+/// Take over the snapshots kept by the state backend at startup
+pub fn load(saved: impl IntoIterator<Item = BuilderInfo>) {
+    let mut snapshots = SNAPSHOTS.lock().unwrap_or_else(PoisonError::into_inner);
+    for info in saved {
+        snapshots.known.insert(info.id.clone(), info);
+    }
+}
+
+/// All snapshots, for the snapshot of the `json` backend
+pub fn saved() -> Vec<BuilderInfo> {
+    SNAPSHOTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .known
+        .values()
+        .cloned()
+        .collect()
+}
+
+/// Note the current snapshot on the attempt a package just started with `status`
+pub fn stamp(attempts: &mut [BuildAttempt], status: &PackageBuildStatus) {
+    let (PackageBuildStatus::Building, Some(attempt)) = (status, attempts.last_mut()) else {
+        return;
+    };
+    attempt.builder = SNAPSHOTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .current
+        .clone();
+}
+
+/// The snapshot new builds run on
+#[get("/builder-info")]
+async fn current_builder_info() -> HttpResponse {
+    let snapshots = SNAPSHOTS.lock().unwrap_or_else(PoisonError::into_inner);
+    match snapshots
+        .current
+        .as_ref()
+        .and_then(|id| snapshots.known.get(id))
+    {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::NotFound().body("404 Not Found"),
+    }
+}
+
+/// The snapshot an attempt names
+#[get("/builder-info/{id}")]
+async fn builder_info(path: web::Path<String>) -> HttpResponse {
+    let id = path.into_inner();
+    let snapshots = SNAPSHOTS.lock().unwrap_or_else(PoisonError::into_inner);
+    match snapshots.known.get(&id) {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::NotFound().body("404 Not Found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selected_settings_are_kept() {
+        let json = r#"{
+            "cores": {"value": 0, "description": "..."},
+            "substituters": {"value": ["https://cache.nixos.org/", "https://org.cachix.org"]},
+            "sandbox": {"value": true},
+            "system": {"value": "x86_64-linux"},
+            "warn-dirty": {"value": true}
+        }"#;
+        let config = nix_config(json);
+        assert_eq!(
+            config.into_iter().collect::<Vec<_>>(),
+            [
+                ("cores", "0"),
+                ("sandbox", "true"),
+                (
+                    "substituters",
+                    "https://cache.nixos.org/ https://org.cachix.org"
+                ),
+                ("system", "x86_64-linux"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+        assert!(nix_config("error: unknown command").is_empty());
+    }
+
+    #[test]
+    fn the_id_ignores_when_it_was_taken() {
+        let info = BuilderInfo {
+            id: String::new(),
+            captured_secs: 100,
+            nix_version: "nix (Nix) 2.24.9".to_string(),
+            nix_config: BTreeMap::new(),
+            kernel: "Linux 6.6.52 x86_64".to_string(),
+            system: "x86_64-linux".to_string(),
+            server_version: "0.1.0".to_string(),
+            git_rev: "unknown".to_string(),
+        };
+        let later = BuilderInfo {
+            captured_secs: 200,
+            ..info.clone()
+        };
+        assert_eq!(id_of(&info), id_of(&later));
+        assert_eq!(id_of(&info).len(), 12);
+        let upgraded = BuilderInfo {
+            nix_version: "nix (Nix) 2.25.0".to_string(),
+            ..info.clone()
+        };
+        assert_ne!(id_of(&info), id_of(&upgraded));
+    }
+}
@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::{Arc, LazyLock, Mutex},
+};
+
+use actix_web::{HttpResponse, get, web};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use ring::signature::Ed25519KeyPair;
+use serde_json::Value;
+
+use crate::{BinaryCacheOptions, package::PackageBuildStatus};
+
+use super::{
+    BuildRepos, STORE_DIR, pipe,
+    secrets::{self, Secret},
+};
+
+const NIX32_ALPHABET: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+/// Closures of build outputs, store paths never change so they are kept forever.
+/// Outputs missing from the store are not kept, see [`closure`].
+static CLOSURES: LazyLock<Mutex<HashMap<String, Arc<Vec<String>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Register the binary cache routes, see
+/// https://nix.dev/manual/nix/latest/store/types/http-binary-cache-store
+pub fn configure(cfg: &mut web::ServiceConfig, options: BinaryCacheOptions, key: Option<Secret>) {
+    cfg.app_data(web::Data::new(options))
+        .app_data(web::Data::new(SigningKey(key)))
+        .service(cache_info)
+        .service(narinfo)
+        .service(nar_xz)
+        .service(nar);
+}
+
+/// `secret_key` or `secret_key_file`, read once at startup
+struct SigningKey(Option<Secret>);
+
+/// The key narinfos are signed with, `None` to leave them unsigned
+pub fn signing_key(options: &BinaryCacheOptions) -> Option<Secret> {
+    secrets::resolve_either(
+        options.secret_key.as_ref(),
+        options.secret_key_file.as_deref(),
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!("reading the binary cache secret key, not signing: {}", e);
+        None
+    })
+}
+
+#[get("/nix-cache-info")]
+async fn cache_info(options: web::Data<BinaryCacheOptions>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/x-nix-cache-info")
+        .body(format!(
+            "StoreDir: {}\nWantMassQuery: 1\nPriority: {}\n",
+            STORE_DIR, options.priority
+        ))
+}
+
+#[get("/{hash}.narinfo")]
+async fn narinfo(
+    path: web::Path<String>,
+    options: web::Data<BinaryCacheOptions>,
+    key: web::Data<SigningKey>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let hash = path.into_inner();
+    tracing::debug!("Requested narinfo {}", hash);
+    let info = web::block(move || {
+        let store_path = find_store_path(&hash, &options, &repos)?;
+        match narinfo_text(&hash, &store_path, &options, key.0.as_ref()) {
+            Ok(info) => Some(info),
+            Err(e) => {
+                tracing::error!("creating narinfo for {}: {}", store_path, e);
+                None
+            }
+        }
+    })
+    .await?;
+    match info {
+        Some(info) => Ok(HttpResponse::Ok()
+            .content_type("text/x-nix-narinfo")
+            .body(info)),
+        None => Err(actix_web::error::ErrorNotFound("404 Not Found")),
+    }
+}
+
+#[get("/nar/{hash}.nar.xz")]
+async fn nar_xz(
+    path: web::Path<String>,
+    options: web::Data<BinaryCacheOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    serve_nar(path.into_inner(), options, repos, true).await
+}
+
+#[get("/nar/{hash}.nar")]
+async fn nar(
+    path: web::Path<String>,
+    options: web::Data<BinaryCacheOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    serve_nar(path.into_inner(), options, repos, false).await
+}
+
+async fn serve_nar(
+    hash: String,
+    options: web::Data<BinaryCacheOptions>,
+    repos: web::Data<BuildRepos>,
+    xz: bool,
+) -> actix_web::Result<HttpResponse> {
+    tracing::debug!("Requested nar {} xz: {}", hash, xz);
+    let store_path = web::block(move || find_store_path(&hash, &options, &repos))
+        .await?
+        .ok_or_else(|| actix_web::error::ErrorNotFound("404 Not Found"))?;
+    let children = dump(&store_path, xz).map_err(|e| {
+        tracing::error!("dumping {}: {}", store_path, e);
+        actix_web::error::ErrorInternalServerError("Failed to dump store path")
+    })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(if xz {
+            "application/x-xz"
+        } else {
+            "application/x-nix-nar"
+        })
+        .streaming(pipe::stdout_stream(children)))
+}
+
+/// `nix-store --dump`, piped through `xz` if requested. The last process writes the
+/// result to its stdout.
+fn dump(store_path: &str, xz: bool) -> std::io::Result<Vec<Child>> {
+    let mut dump = Command::new("nix-store")
+        .arg("--dump")
+        .arg(store_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    if !xz {
+        return Ok(vec![dump]);
+    }
+    let Some(dump_stdout) = dump.stdout.take() else {
+        return Err(std::io::Error::other("nix-store has no stdout"));
+    };
+    let compress = Command::new("xz")
+        .arg("-c")
+        .stdin(Stdio::from(dump_stdout))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn();
+    match compress {
+        Ok(compress) => Ok(vec![dump, compress]),
+        Err(e) => {
+            let _ = dump.kill();
+            let _ = dump.wait();
+            Err(e)
+        }
+    }
+}
+
+/// Full store path for a hash, if it may be served
+fn find_store_path(hash: &str, options: &BinaryCacheOptions, repos: &BuildRepos) -> Option<String> {
+    if hash.len() != 32 || !hash.bytes().all(|c| NIX32_ALPHABET.contains(&c)) {
+        return None;
+    }
+    let prefix = format!("{}-", hash);
+    let is_match = |path: &str| {
+        Path::new(path)
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(&prefix))
+    };
+
+    for output in tracked_outputs(repos) {
+        if let Some(path) = closure(&output).iter().find(|path| is_match(path)) {
+            return Some(path.clone());
+        }
+    }
+
+    if !options.expose_whole_store {
+        return None;
+    }
+    std::fs::read_dir(STORE_DIR)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path().to_string_lossy().to_string())
+        .find(|path| is_match(path) && !path.ends_with(".lock"))
+}
+
+/// Output paths of all successful builds
+pub fn tracked_outputs(repos: &BuildRepos) -> Vec<String> {
+    let mut outputs = Vec::new();
+    for repo in repos.snapshot().0.0 {
+        let Ok(commits) = repo.commits.inner().read() else {
+            continue;
+        };
+        for commit in commits.values() {
+            for pkg in commit.packages.get() {
+                if let PackageBuildStatus::Success(paths) = pkg.status() {
+                    outputs.extend(paths.split_whitespace().map(String::from));
+                }
+            }
+        }
+    }
+    outputs
+}
+
+/// The output itself and everything it references, directly or indirectly
+fn closure(output: &str) -> Arc<Vec<String>> {
+    if let Some(closure) = CLOSURES
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .get(output)
+    {
+        return closure.clone();
+    }
+    let requisites = Command::new("nix-store")
+        .arg("--query")
+        .arg("--requisites")
+        .arg(output)
+        .output();
+    match requisites {
+        Ok(requisites) if requisites.status.success() => {
+            let closure = Arc::new(
+                String::from_utf8_lossy(&requisites.stdout)
+                    .lines()
+                    .map(String::from)
+                    .collect::<Vec<_>>(),
+            );
+            CLOSURES
+                .lock()
+                .unwrap_or_else(|p| p.into_inner())
+                .insert(output.to_string(), closure.clone());
+            closure
+        }
+        // garbage collected or not built on this machine, still serve the output itself.
+        // Not kept: a rebuild or substitution brings the whole closure back, which a
+        // kept miss would hide until the restart. Asking again only costs a
+        // `nix-store` call per lookup of an output that is gone.
+        _ => Arc::new(vec![output.to_string()]),
+    }
+}
+
+/// `nix path-info --json` of one store path, `args` are passed on
+fn path_info(store_path: &str, args: &[&str]) -> Result<Value, Box<dyn std::error::Error>> {
+    let output = Command::new("nix")
+        .arg("path-info")
+        .arg("--json")
+        .args(args)
+        .arg(store_path)
+        .output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into());
+    }
+    let value: Value = serde_json::from_slice(&output.stdout)?;
+    // older nix versions return a list, newer ones an object keyed by path
+    let info = match value {
+        Value::Array(infos) => infos.into_iter().next(),
+        Value::Object(mut infos) => infos
+            .remove(store_path)
+            .or_else(|| infos.into_iter().next().map(|(_, info)| info)),
+        _ => None,
+    };
+    Ok(info.ok_or("unexpected nix path-info output")?)
+}
+
+/// Size of a build output with everything it references, `None` if nix can not tell
+pub fn closure_size(store_path: &str) -> Option<u64> {
+    match path_info(store_path, &["--closure-size"]) {
+        Ok(info) => info.get("closureSize").and_then(Value::as_u64),
+        Err(e) => {
+            tracing::error!("getting the closure size of {}: {}", store_path, e);
+            None
+        }
+    }
+}
+
+fn narinfo_text(
+    hash: &str,
+    store_path: &str,
+    options: &BinaryCacheOptions,
+    key: Option<&Secret>,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let info = path_info(store_path, &[])?;
+
+    let nar_hash = info
+        .get("narHash")
+        .and_then(Value::as_str)
+        .and_then(nar_hash_nix32)
+        .ok_or("missing narHash")?;
+    let nar_size = info
+        .get("narSize")
+        .and_then(Value::as_u64)
+        .ok_or("missing narSize")?;
+    let references = info
+        .get("references")
+        .and_then(Value::as_array)
+        .map(|references| {
+            references
+                .iter()
+                .filter_map(Value::as_str)
+                .map(full_store_path)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    let mut signatures = info
+        .get("signatures")
+        .and_then(Value::as_array)
+        .map(|sigs| {
+            sigs.iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    if let Some(key) = key {
+        let fingerprint = fingerprint(store_path, &nar_hash, nar_size, &references);
+        signatures.push(sign(key.expose(), &fingerprint)?);
+    }
+
+    let (extension, compression) = match options.compression.as_str() {
+        "xz" => (".xz", "xz"),
+        _ => ("", "none"),
+    };
+    let mut text = format!(
+        "StorePath: {}\nURL: nar/{}.nar{}\nCompression: {}\nNarHash: {}\nNarSize: {}\nReferences: {}\n",
+        store_path,
+        hash,
+        extension,
+        compression,
+        nar_hash,
+        nar_size,
+        references
+            .iter()
+            .map(|reference| base_name(reference))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    if let Some(deriver) = info.get("deriver").and_then(Value::as_str) {
+        text.push_str(&format!("Deriver: {}\n", base_name(deriver)));
+    }
+    for signature in signatures {
+        text.push_str(&format!("Sig: {}\n", signature));
+    }
+    Ok(text)
+}
+
+fn full_store_path(path: &str) -> String {
+    if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("{}/{}", STORE_DIR, path)
+    }
+}
+
+fn base_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// What nix signs for a store path
+fn fingerprint(store_path: &str, nar_hash: &str, nar_size: u64, references: &[String]) -> String {
+    format!(
+        "1;{};{};{};{}",
+        store_path,
+        nar_hash,
+        nar_size,
+        references.join(",")
+    )
+}
+
+/// Sign with a key in nix's `name:base64` format
+fn sign(key: &str, fingerprint: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let (name, key_pair) = parse_secret_key(key)?;
+    let signature = key_pair.sign(fingerprint.as_bytes());
+    Ok(format!("{}:{}", name, BASE64.encode(signature.as_ref())))
+}
+
+/// Name and key pair of a `name:base64` secret key from
+/// `nix-store --generate-binary-cache-key`
+pub fn parse_secret_key(key: &str) -> Result<(&str, Ed25519KeyPair), Box<dyn std::error::Error>> {
+    let (name, key) = key
+        .split_once(':')
+        .ok_or("secret key is not in `name:key` format")?;
+    let key = BASE64.decode(key)?;
+    if key.len() != 64 {
+        return Err("secret key must be 64 bytes".into());
+    }
+    let (seed, public_key) = key.split_at(32);
+    let key_pair = Ed25519KeyPair::from_seed_and_public_key(seed, public_key)
+        .map_err(|e| format!("invalid secret key: {}", e))?;
+    Ok((name, key_pair))
+}
+
+/// `sha256:<nix32>` as used in narinfos, from either that form or an SRI hash
+fn nar_hash_nix32(hash: &str) -> Option<String> {
+    if hash.starts_with("sha256:") {
+        return Some(hash.to_string());
+    }
+    let digest = BASE64.decode(hash.strip_prefix("sha256-")?).ok()?;
+    Some(format!("sha256:{}", nix32(&digest)))
+}
+
+/// Nix's own base32 flavour, which also reverses the byte order
+fn nix32(bytes: &[u8]) -> String {
+    let len = (bytes.len() * 8).div_ceil(5);
+    (0..len)
+        .rev()
+        .map(|n| {
+            let bit = n * 5;
+            let (i, j) = (bit / 8, bit % 8);
+            // widened so shifting a whole byte out does not overflow
+            let low = u16::from(bytes[i]) >> j;
+            let high = bytes
+                .get(i + 1)
+                .map_or(0, |&byte| u16::from(byte) << (8 - j));
+            NIX32_ALPHABET[((low | high) & 0x1f) as usize] as char
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_sri_hashes() {
+        // sha256 of the empty string
+        assert_eq!(
+            nar_hash_nix32("sha256-47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").as_deref(),
+            Some("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")
+        );
+        assert_eq!(
+            nar_hash_nix32("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")
+                .as_deref(),
+            Some("sha256:0mdqa9w1p6cmli6976v4wi0sw9r4p5prkj7lzfd1877wk11c9c73")
+        );
+        assert_eq!(nar_hash_nix32("md5-1B2M2Y8AsgTpgAmY7PhCfg=="), None);
+    }
+}
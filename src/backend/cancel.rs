@@ -0,0 +1,196 @@
+use std::{
+    io::Read,
+    process::{Child, Output},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use actix_web::{HttpResponse, post, web};
+use serde::{Deserialize, Serialize};
+
+use crate::{package::BuildControl, repo::RepoInfo};
+
+use super::{
+    BuildRepos, PackageEnumTrait,
+    logs::{self, LogWriter},
+};
+
+/// How often a running build checks whether it was cancelled
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelOutcome {
+    /// was still waiting for a build slot
+    Cancelled,
+    /// nix was running and gets killed
+    Killed,
+    AlreadyFinished,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct CancelSummary {
+    cancelled: usize,
+    killed: usize,
+    already_finished: usize,
+}
+
+impl CancelSummary {
+    fn count(&mut self, outcome: CancelOutcome) {
+        match outcome {
+            CancelOutcome::Cancelled => self.cancelled += 1,
+            CancelOutcome::Killed => self.killed += 1,
+            CancelOutcome::AlreadyFinished => self.already_finished += 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct BranchCancelQuery {
+    #[serde(default)]
+    keep_latest: bool,
+}
+
+/// Cancel all queued and running builds of a commit
+#[post("/repos/{name}/commits/{hash}/cancel")]
+async fn cancel_commit(
+    path: web::Path<(String, String)>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (name, hash) = path.into_inner();
+    tracing::debug!("Requested cancel of {} {}", name, hash);
+    let Some(repo) = repos.find(&name) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let mut summary = CancelSummary::default();
+    if !cancel_commit_builds(&repo, &hash, &mut summary) {
+        return HttpResponse::NotFound().body("404 Not Found");
+    }
+    HttpResponse::Ok().json(summary)
+}
+
+/// Cancel the queued or running build of a single package
+#[post("/repos/{name}/commits/{hash}/packages/{path}/cancel")]
+async fn cancel_package(
+    path: web::Path<(String, String, String)>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (name, hash, pkg_path) = path.into_inner();
+    tracing::debug!("Requested cancel of {} {} {}", name, hash, pkg_path);
+    let Some(pkg) = logs::find_package(&repos, &name, &hash, &pkg_path) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let mut summary = CancelSummary::default();
+    summary.count(pkg.cancel());
+    HttpResponse::Ok().json(summary)
+}
+
+/// Cancel the builds of all tracked commits of a branch, optionally sparing the
+/// newest one
+#[post("/repos/{name}/branches/{branch:.*}/cancel")]
+async fn cancel_branch(
+    path: web::Path<(String, String)>,
+    query: web::Query<BranchCancelQuery>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (name, branch) = path.into_inner();
+    tracing::debug!("Requested cancel of {} {} {:?}", name, branch, query);
+    let Some(repo) = repos.find(&name) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let Some(hashes) = repo.branch_commit_hashes.get(&branch) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let mut summary = CancelSummary::default();
+    // newest commit first
+    let skip = if query.keep_latest { 1 } else { 0 };
+    for hash in hashes.get().iter().skip(skip) {
+        cancel_commit_builds(&repo, hash, &mut summary);
+    }
+    HttpResponse::Ok().json(summary)
+}
+
+/// `false` if the commit is unknown
+fn cancel_commit_builds(repo: &RepoInfo, hash: &str, summary: &mut CancelSummary) -> bool {
+    let commit = match repo.commits.inner().read() {
+        Ok(commits) => commits.get(hash).cloned(),
+        Err(poisoned) => poisoned.into_inner().get(hash).cloned(),
+    };
+    let Some(commit) = commit else {
+        return false;
+    };
+    for pkg in commit.packages.get() {
+        summary.count(pkg.cancel());
+    }
+    true
+}
+
+/// Wait for `child` to exit while collecting its output, stderr is also written to
+/// `log`. Kills it and returns `None` once `control` is cancelled, and kills it with
+/// an error of kind `TimedOut` after `timeout`.
+pub fn wait_cancellable(
+    mut child: Child,
+    control: &BuildControl,
+    log: Option<LogWriter>,
+    timeout: Option<Duration>,
+) -> std::io::Result<Option<Output>> {
+    let deadline = timeout.map(|timeout| (timeout, Instant::now() + timeout));
+    // read both pipes on their own threads so a chatty build can not block on them
+    let stdout = read_in_background(child.stdout.take(), None);
+    let stderr = read_in_background(child.stderr.take(), log);
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if control.is_cancelled() {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        if let Some((timeout, deadline)) = deadline
+            && Instant::now() >= deadline
+        {
+            child.kill()?;
+            child.wait()?;
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("killed after {:?}", timeout),
+            ));
+        }
+        thread::sleep(CANCEL_CHECK_INTERVAL);
+    };
+
+    Ok(Some(Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    }))
+}
+
+fn read_in_background<R: Read + Send + 'static>(
+    pipe: Option<R>,
+    mut log: Option<LogWriter>,
+) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let Some(mut pipe) = pipe else {
+            return output;
+        };
+        let mut buffer = [0; 8192];
+        while let Ok(read @ 1..) = pipe.read(&mut buffer) {
+            output.extend_from_slice(&buffer[..read]);
+            if let Some(writer) = &mut log
+                && let Err(e) = writer.write(&buffer[..read])
+            {
+                tracing::error!("writing build log: {}", e);
+                log = None;
+            }
+        }
+        if let Some(writer) = log
+            && let Err(e) = writer.finish()
+        {
+            tracing::error!("writing build log: {}", e);
+        }
+        output
+    })
+}
@@ -0,0 +1,129 @@
+use std::collections::HashSet;
+
+use actix_web::{
+    HttpMessage, HttpRequest, HttpResponse, get,
+    http::header::{self, EntityTag, IfNoneMatch},
+    web,
+};
+use serde::Deserialize;
+
+use crate::{event::RepoChanges, event::StateEvent, serialize::VecArcWrapper};
+
+use super::{
+    BuildRepos,
+    events::{self, Sequenced},
+};
+
+#[derive(Deserialize, Debug)]
+struct ChangesQuery {
+    /// version returned by the previous request
+    since: Option<u64>,
+    /// unix timestamp, used when no version is known
+    since_time: Option<i64>,
+}
+
+/// Everything that changed after a version or point in time, a full snapshot when
+/// that is too long ago or neither is given. The `ETag` is the version, a client
+/// sending it back in `If-None-Match` already has that state and gets a 304.
+#[get("/repos/changes")]
+async fn repo_changes(
+    req: HttpRequest,
+    query: web::Query<ChangesQuery>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    tracing::debug!("Requested repo changes {:?}", query);
+    let changes = match (query.since, query.since_time) {
+        (Some(version), _) => events::since_version(version),
+        (None, Some(unix_secs)) => events::since_time(unix_secs),
+        (None, None) => None,
+    };
+    let changes = match changes {
+        Some((version, events)) if !repos_changed(&events) => diff(version, events, &repos),
+        _ => RepoChanges {
+            // take the version first, changes racing with the snapshot are sent again
+            version: events::version(),
+            snapshot: Some(repos.snapshot()),
+            repos: VecArcWrapper::default(),
+            patches: Vec::new(),
+        },
+    };
+    let etag = EntityTag::new_strong(changes.version.to_string());
+    let unchanged = match req.get_header::<IfNoneMatch>() {
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        Some(IfNoneMatch::Any) | None => false,
+    };
+    if unchanged {
+        return HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .finish();
+    }
+    HttpResponse::Ok()
+        .insert_header(header::ETag(etag))
+        .json(changes)
+}
+
+/// Removed repos would stay on the client with a diff
+fn repos_changed(events: &[Sequenced]) -> bool {
+    events
+        .iter()
+        .any(|event| matches!(event.event, StateEvent::ReposChanged))
+}
+
+fn diff(version: u64, events: Vec<Sequenced>, repos: &BuildRepos) -> RepoChanges {
+    let changed_repos = events
+        .iter()
+        .filter_map(|event| match &event.event {
+            StateEvent::CommitsChanged { repo } => Some(repo.as_str()),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    // only the newest status of everything is interesting, the replaced repos
+    // already carry their current state
+    let mut seen = HashSet::new();
+    let mut patches = events
+        .iter()
+        .rev()
+        .filter(|event| {
+            let key = match &event.event {
+                StateEvent::RepoStatus { repo, .. } => ("repo", repo, None, None),
+                StateEvent::PollHistory { repo, .. } => ("polls", repo, None, None),
+                StateEvent::NextPoll { repo, .. } => ("next_poll", repo, None, None),
+                StateEvent::CommitStatus { repo, commit, .. } => {
+                    ("commit", repo, Some(commit), None)
+                }
+                StateEvent::PackageStatus {
+                    repo, commit, path, ..
+                } => ("package", repo, Some(commit), Some(path)),
+                StateEvent::PackagePushes {
+                    repo, commit, path, ..
+                } => ("pushes", repo, Some(commit), Some(path)),
+                StateEvent::PackageResult {
+                    repo, commit, path, ..
+                } => ("result", repo, Some(commit), Some(path)),
+                StateEvent::CommitsChanged { .. }
+                | StateEvent::ReposChanged
+                | StateEvent::Snapshot { .. }
+                | StateEvent::ServerShutdown => return false,
+            };
+            !changed_repos.contains(key.1.as_str()) && seen.insert(key)
+        })
+        .map(|event| event.event.clone())
+        .collect::<Vec<_>>();
+    patches.reverse();
+
+    let repos = repos
+        .snapshot()
+        .0
+        .0
+        .into_iter()
+        .filter(|repo| changed_repos.contains(repo.repo.url.as_str()))
+        .collect::<Vec<_>>();
+
+    RepoChanges {
+        version,
+        snapshot: None,
+        repos: VecArcWrapper::from(repos),
+        patches,
+    }
+}
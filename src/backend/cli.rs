@@ -0,0 +1,157 @@
+//! Command line of the server binary
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand, ValueEnum};
+
+use super::{config_file, validate};
+
+#[derive(Parser, Debug)]
+#[command(
+    version,
+    about = "Builds the packages of Nix flakes on every new commit"
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Poll the repos, build new commits and serve the dashboard
+    Serve {
+        /// JSON, TOML, YAML or `.nix` file, a directory of JSON and TOML files, or a
+        /// flake output like `.#config`
+        config: String,
+    },
+    /// Validate the config and print it with the defaults filled in. Listing all
+    /// problems includes a port that is taken, also by a running server.
+    Check { config: String },
+    /// Build the tip of every branch once and exit, non-zero if a build failed
+    BuildOnce {
+        config: String,
+        /// only this repo, by url or last url segment
+        #[arg(long)]
+        repo: Option<String>,
+        /// build this revision instead of the branch tips, it has to be on one of
+        /// the configured branches
+        #[arg(long)]
+        rev: Option<String>,
+        /// only evaluate and report what would be built, like `dry_run`
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Print the config with the defaults filled in
+    ShowConfig {
+        config: String,
+        #[arg(long, value_enum, default_value_t = ConfigFormat::Json)]
+        format: ConfigFormat,
+    },
+    /// Write the NixOS module of the options to `<out_dir>/autoBuildOptionsType.nix`
+    GenerateModule {
+        #[arg(default_value = "bindings")]
+        out_dir: PathBuf,
+        /// only compare with the file there, non-zero if it is outdated
+        #[arg(long)]
+        check: bool,
+    },
+    /// Print the JSON Schema of the config, for editors
+    ShowSchema,
+    /// Load the build state exported by `GET /api/v1/export` on another host into
+    /// the state directory, before the server starts there
+    Import {
+        config: String,
+        /// the export, plain or gzipped JSON
+        file: PathBuf,
+        /// merge into existing state, the repos of the export replace theirs
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+}
+
+pub fn check(config_path: &str) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let settings = config_file::read(config_path)?;
+    let warnings = validate::validate(&settings, settings.server.enabled)?;
+    println!("{}", serde_json::to_string_pretty(&settings)?);
+    if !settings.sources.is_empty() {
+        eprintln!("Values not from the defaults:");
+        for (option, source) in &settings.sources {
+            eprintln!("  {}: {}", option, source);
+        }
+    }
+    for warning in warnings {
+        eprintln!("warning: {}", warning);
+    }
+    eprintln!("{} is valid", config_path);
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn show_config(
+    config_path: &str,
+    format: ConfigFormat,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let settings = config_file::read(config_path)?;
+    match format {
+        ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&settings)?),
+        ConfigFormat::Toml => print!("{}", toml::to_string_pretty(&settings)?),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn arguments_parse() -> Result<(), clap::Error> {
+        assert!(Cli::try_parse_from(["nix_autobuild", "config.json"]).is_err());
+        let cli = Cli::try_parse_from([
+            "nix_autobuild",
+            "build-once",
+            "config.json",
+            "--repo",
+            "nix_autobuild",
+            "--rev",
+            "HEAD~1",
+        ])?;
+        assert!(matches!(
+            cli.command,
+            Command::BuildOnce { repo: Some(repo), rev: Some(rev), .. }
+                if repo == "nix_autobuild" && rev == "HEAD~1"
+        ));
+        assert!(
+            Cli::try_parse_from(["nix_autobuild", "show-config", "c.json", "--format", "yaml"])
+                .is_err()
+        );
+        assert!(matches!(
+            Cli::try_parse_from(["nix_autobuild", "generate-module", "--check"])?.command,
+            Command::GenerateModule { out_dir, check: true } if out_dir == Path::new("bindings")
+        ));
+        assert!(matches!(
+            Cli::try_parse_from(["nix_autobuild", "import", "c.json", "state.json.gz", "--force"])?
+                .command,
+            Command::Import { file, force: true, .. } if file == Path::new("state.json.gz")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn example_config_shows_as_toml() -> Result<(), Box<dyn std::error::Error>> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config.json");
+        let settings = config_file::read(path)?;
+        let shown: crate::AutoBuildOptions = toml::from_str(&toml::to_string_pretty(&settings)?)?;
+        assert_eq!(
+            serde_json::to_value(shown)?,
+            serde_json::to_value(settings)?
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,41 @@
+use actix_web::{
+    dev::ServiceResponse,
+    http::header::{self, HeaderValue},
+};
+
+/// Keep the `Compress` middleware away from responses whose content type starts
+/// with one of `excluded`. It leaves responses alone that already carry a
+/// `Content-Encoding`, so `identity` is set on them.
+pub fn skip_excluded<B>(res: &mut ServiceResponse<B>, excluded: &[String]) {
+    let headers = res.headers();
+    if headers.contains_key(header::CONTENT_ENCODING) {
+        return;
+    }
+    let is_excluded = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            excluded
+                .iter()
+                .any(|prefix| content_type.starts_with(prefix.as_str()))
+        });
+    if is_excluded {
+        res.headers_mut().insert(
+            header::CONTENT_ENCODING,
+            HeaderValue::from_static("identity"),
+        );
+    }
+}
+
+/// Content type of compressed nix store files, so they can be excluded from
+/// compression. Takes precedence over the guess from the extension.
+pub fn store_content_type(path: &str) -> Option<&'static str> {
+    match path.rsplit_once('.')?.1 {
+        "nar" => Some("application/x-nix-nar"),
+        "xz" => Some("application/x-xz"),
+        "zst" => Some("application/zstd"),
+        "gz" => Some("application/gzip"),
+        "bz2" => Some("application/x-bzip2"),
+        _ => None,
+    }
+}
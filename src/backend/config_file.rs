@@ -0,0 +1,670 @@
+//! Reading the settings file. The extension picks the format, `.toml` is TOML,
+//! `.yaml` and `.yml` are YAML and anything else is JSON, all parsed into the same
+//! `AutoBuildOptions`. `.nix` files and flake outputs (`<flake>#<attribute>`) are
+//! evaluated with `nix eval --json` unless the `nix-config` feature is turned off.
+//! A directory is read conf.d style, its `*.json` and `*.toml` files are merged in
+//! lexical order. `NIX_AUTOBUILD_*` environment variables take precedence over the
+//! file, which takes precedence over the defaults.
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde_json::{Map, Value};
+
+use crate::AutoBuildOptions;
+
+/// Of the environment variables that override settings
+const ENV_PREFIX: &str = "NIX_AUTOBUILD_";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+    Nix,
+    Flake,
+}
+
+impl Format {
+    fn of(path: &Path) -> Self {
+        if path.to_string_lossy().contains('#') {
+            return Format::Flake;
+        }
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(str::to_ascii_lowercase);
+        match extension.as_deref() {
+            Some("toml") => Format::Toml,
+            Some("yaml" | "yml") => Format::Yaml,
+            Some("nix") => Format::Nix,
+            _ => Format::Json,
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML",
+            Format::Nix => "Nix expression",
+            Format::Flake => "flake output",
+        })
+    }
+}
+
+/// Flake outputs are no file that could be watched for changes
+pub fn is_file(path: &str) -> bool {
+    Format::of(Path::new(path)) != Format::Flake
+}
+
+pub fn load(path: &str) -> Result<AutoBuildOptions, Box<dyn std::error::Error>> {
+    let settings = read(path)?;
+    log_loaded(path);
+    Ok(settings)
+}
+
+/// Where the settings of `load` came from
+pub fn log_loaded(path: &str) {
+    match fragments(Path::new(path)) {
+        Some(Ok(files)) => {
+            tracing::info!("Read config directory {} of {} files", path, files.len())
+        }
+        _ => tracing::info!("Read config {} as {}", path, Format::of(Path::new(path))),
+    }
+    for (name, _) in env_overrides() {
+        tracing::info!("{} overrides the config file", name);
+    }
+}
+
+/// `load` without the log lines, for printing the config
+pub fn read(path: &str) -> Result<AutoBuildOptions, Box<dyn std::error::Error>> {
+    if let Some(files) = fragments(Path::new(path)) {
+        return read_dir(path, &files?);
+    }
+    let format = Format::of(Path::new(path));
+    let data = match format {
+        Format::Nix | Format::Flake => evaluate(path, format)?,
+        _ => std::fs::read_to_string(path)
+            .map_err(|e| format!("Could not read config file {}: {}", path, e))?,
+    };
+    let settings = parse(Path::new(path), &data)?;
+    Ok(apply_env(settings, env_overrides())?)
+}
+
+/// The `*.json` and `*.toml` files of `path` in lexical order, `None` if it is no
+/// directory
+fn fragments(path: &Path) -> Option<Result<Vec<PathBuf>, String>> {
+    if !path.is_dir() {
+        return None;
+    }
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            return Some(Err(format!(
+                "Could not read config directory {}: {}",
+                path.display(),
+                e
+            )));
+        }
+    };
+    let mut files = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|file| {
+            file.is_file()
+                && matches!(
+                    file.extension().and_then(|extension| extension.to_str()),
+                    Some("json" | "toml")
+                )
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    Some(Ok(files))
+}
+
+/// The merged `files` of the directory `path`, their sources recorded
+fn read_dir(path: &str, files: &[PathBuf]) -> Result<AutoBuildOptions, Box<dyn std::error::Error>> {
+    if files.is_empty() {
+        return Err(format!("Config directory {} has no *.json or *.toml files", path).into());
+    }
+    let mut merged = Map::new();
+    let mut sources = BTreeMap::new();
+    for file in files {
+        let data = std::fs::read_to_string(file)
+            .map_err(|e| format!("Could not read config file {}: {}", file.display(), e))?;
+        let source = file.display().to_string();
+        merge(
+            &mut merged,
+            parse_fragment(file, &data)?,
+            "",
+            &source,
+            &mut sources,
+        );
+    }
+    let settings = serde_json::from_value(Value::Object(merged))
+        .map_err(|e| format!("Invalid config in {}: {}", path, e))?;
+    let vars = env_overrides();
+    for (name, _) in &vars {
+        let option = name[ENV_PREFIX.len()..]
+            .to_ascii_lowercase()
+            .replace("__", ".");
+        record(&mut sources, &option, &Value::Null, name);
+    }
+    let mut settings = apply_env(settings, vars)?;
+    settings.sources = sources;
+    Ok(settings)
+}
+
+/// A file of a config directory, it may be any part of the settings
+fn parse_fragment(path: &Path, data: &str) -> Result<Map<String, Value>, String> {
+    let format = Format::of(path);
+    let parsed = match format {
+        Format::Toml => toml::from_str(data).map_err(|e| e.to_string()),
+        _ => serde_json::from_str(data).map_err(|e| e.to_string()),
+    };
+    match parsed.map_err(|e| format!("Invalid {} in {}: {}", format, path.display(), e))? {
+        Value::Object(options) => Ok(options),
+        _ => Err(format!(
+            "Invalid {} in {}: expected a table of options",
+            format,
+            path.display()
+        )),
+    }
+}
+
+/// `from` over `into`: sections merge, `repos` lists concatenate and everything
+/// else is replaced. `sources` learns that the new values are from `source`.
+fn merge(
+    into: &mut Map<String, Value>,
+    from: Map<String, Value>,
+    path: &str,
+    source: &str,
+    sources: &mut BTreeMap<String, String>,
+) {
+    for (key, value) in from {
+        let option = match path {
+            "" => key.clone(),
+            path => format!("{}.{}", path, key),
+        };
+        match (into.get_mut(&key), value) {
+            (Some(Value::Object(section)), Value::Object(value)) => {
+                merge(section, value, &option, source, sources)
+            }
+            (Some(Value::Array(repos)), Value::Array(value)) if option == "repos" => {
+                for repo in value {
+                    sources.insert(format!("repos[{}]", repos.len()), source.to_string());
+                    repos.push(repo);
+                }
+            }
+            (_, value) => {
+                record(sources, &option, &value, source);
+                into.insert(key, value);
+            }
+        }
+    }
+}
+
+/// `value` of `option` is from `source`, replacing what was recorded below it.
+/// Repos are recorded as a whole.
+fn record(sources: &mut BTreeMap<String, String>, option: &str, value: &Value, source: &str) {
+    sources.retain(|recorded, _| {
+        recorded
+            .strip_prefix(option)
+            .is_none_or(|rest| !(rest.is_empty() || rest.starts_with(['.', '['])))
+    });
+    match value {
+        Value::Object(section) if !section.is_empty() => {
+            for (key, value) in section {
+                record(sources, &format!("{}.{}", option, key), value, source);
+            }
+        }
+        Value::Array(repos) if option == "repos" => {
+            for index in 0..repos.len() {
+                sources.insert(format!("repos[{}]", index), source.to_string());
+            }
+        }
+        _ => {
+            sources.insert(option.to_string(), source.to_string());
+        }
+    }
+}
+
+/// What the watcher compares to tell whether the config changed, the contents of
+/// the file or of the files of the directory
+pub fn contents(path: &str) -> Option<Vec<u8>> {
+    let Some(files) = fragments(Path::new(path)) else {
+        return std::fs::read(path).ok();
+    };
+    let mut contents = Vec::new();
+    for file in files.ok()? {
+        contents.extend(file.as_os_str().as_encoded_bytes());
+        contents.extend(std::fs::read(file).ok()?);
+    }
+    Some(contents)
+}
+
+/// Variables starting with `ENV_PREFIX`, sorted so they apply in a fixed order
+fn env_overrides() -> Vec<(String, String)> {
+    let mut vars = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect::<Vec<_>>();
+    vars.sort();
+    vars
+}
+
+/// `NIX_AUTOBUILD_<OPTION>` replaces an option of the file or its default,
+/// `NIX_AUTOBUILD_LISTEN__TCP__PORT` a nested one. Values are taken as they are for
+/// string options and as JSON otherwise, so lists and objects can be set too.
+fn apply_env(
+    settings: AutoBuildOptions,
+    vars: Vec<(String, String)>,
+) -> Result<AutoBuildOptions, String> {
+    if vars.is_empty() {
+        return Ok(settings);
+    }
+    let mut options = serde_json::to_value(&settings).map_err(|e| e.to_string())?;
+    for (name, raw) in vars {
+        let path = name[ENV_PREFIX.len()..].to_ascii_lowercase();
+        let mut option = &mut options;
+        for key in path.split("__") {
+            // an unset optional section starts out empty
+            if option.is_null() {
+                *option = Value::Object(serde_json::Map::new());
+            }
+            let Value::Object(section) = option else {
+                return Err(format!("{}: `{}` has no nested options", name, key));
+            };
+            if !section.contains_key(key) && !section.is_empty() {
+                return Err(format!("{}: there is no option `{}`", name, key));
+            }
+            option = section.entry(key).or_insert(Value::Null);
+        }
+        *option = match option {
+            Value::String(_) => Value::String(raw),
+            // enums and unset optional strings are no valid JSON
+            _ => serde_json::from_str(&raw).unwrap_or(Value::String(raw)),
+        };
+        serde_json::from_value::<AutoBuildOptions>(options.clone())
+            .map_err(|e| format!("Invalid {}: {}", name, e))?;
+    }
+    serde_json::from_value(options).map_err(|e| e.to_string())
+}
+
+/// The JSON of the evaluated config, with the stderr of nix if that fails
+#[cfg(feature = "nix-config")]
+fn evaluate(source: &str, format: Format) -> Result<String, String> {
+    let mut command = std::process::Command::new("nix");
+    command.arg("eval").arg("--json");
+    match format {
+        Format::Nix => command.arg("--file").arg(source),
+        _ => command.arg(source),
+    };
+    let output = command
+        .output()
+        .map_err(|e| format!("Could not run nix eval for {}: {}", source, e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Evaluating {} failed:\n{}",
+            source,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        ));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|e| format!("nix eval returned invalid UTF-8 for {}: {}", source, e))
+}
+
+#[cfg(not(feature = "nix-config"))]
+fn evaluate(source: &str, format: Format) -> Result<String, String> {
+    Err(format!(
+        "{} is a {}, but this build cannot evaluate Nix (the nix-config feature is off)",
+        source, format
+    ))
+}
+
+/// The errors of all parsers point at the line and column
+fn parse(path: &Path, data: &str) -> Result<AutoBuildOptions, String> {
+    let format = Format::of(path);
+    let parsed = match format {
+        Format::Json | Format::Nix | Format::Flake => {
+            serde_json::from_str(data).map_err(|e| e.to_string())
+        }
+        Format::Toml => toml::from_str(data).map_err(|e| e.to_string()),
+        Format::Yaml => serde_norway::from_str(data).map_err(|e| e.to_string()),
+    };
+    parsed.map_err(|e| format!("Invalid {} in {}: {}", format, path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example(name: &str) -> String {
+        format!("{}/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    #[test]
+    fn example_configs_match() -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_value(load(&example("config.json"))?)?;
+        let toml = serde_json::to_value(load(&example("config.toml"))?)?;
+        let yaml = serde_json::to_value(load(&example("config.yaml"))?)?;
+        assert_eq!(json, toml);
+        assert_eq!(json, yaml);
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_extensions_are_json() -> std::io::Result<()> {
+        let data = std::fs::read_to_string(example("config.json"))?;
+        assert!(parse(Path::new("autobuild.conf"), &data).is_ok());
+        assert!(parse(Path::new("autobuild"), &data).is_ok());
+        assert!(parse(Path::new("autobuild.toml"), &data).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn toml_errors_have_a_position() {
+        let error = parse(Path::new("autobuild.TOML"), "dir = \"data\"\nport = \n")
+            .err()
+            .unwrap_or_default();
+        assert!(error.contains("line 2"), "{}", error);
+        assert!(error.contains("column"), "{}", error);
+    }
+
+    /// `data` after the required settings it does not cover
+    fn yaml(data: &str) -> Result<AutoBuildOptions, String> {
+        let required = "dir: data\nsupported_architectures: [x86_64-linux]\nn_build_threads: 0\n";
+        parse(Path::new("autobuild.yml"), &format!("{}{}", required, data))
+    }
+
+    /// A repo with the required settings, its `branches` follow
+    const REPO: &str =
+        "repos:\n  - url: github.com/a/b\n    poll_interval_sec: 30\n    build_depth: 5\n";
+
+    #[test]
+    fn yaml_scalars_keep_their_meaning() -> Result<(), String> {
+        // unquoted `no`, `on` and `1.0` would be a bool and a float in YAML 1.1
+        let branches = "    branches: [main, dev, no, on, 1.0]\n";
+        let options = yaml(&format!(
+            "host: 127.0.0.1\nport: 8080\n{}{}",
+            REPO, branches
+        ))?;
+        assert_eq!(options.host, "127.0.0.1");
+        assert_eq!(options.port, 8080);
+        assert_eq!(
+            options.repos[0].branches,
+            ["main", "dev", "no", "on", "1.0"]
+        );
+
+        let branches = "    branches:\n      - \"0123\"\n";
+        let options = yaml(&format!("host: \"::1\"\nport: 8080\n{}{}", REPO, branches))?;
+        assert_eq!(options.host, "::1");
+        assert_eq!(options.repos[0].branches, ["0123"]);
+        Ok(())
+    }
+
+    #[test]
+    fn yaml_numbers_are_not_coerced() {
+        // a quoted port is a string, and numbers out of range are rejected
+        let error = |data: &str| yaml(data).err().unwrap_or_default();
+        assert!(error("port: \"8080\"\n").contains("port"));
+        assert!(error("port: 80808\n").contains("port"));
+        let depth = error("repos:\n  - url: github.com/a/b\n    build_depth: many\n");
+        assert!(depth.contains("build_depth"), "{}", depth);
+        assert!(depth.contains("line 6"), "{}", depth);
+    }
+
+    #[test]
+    fn formats_follow_the_extension() {
+        assert_eq!(Format::of(Path::new("a.toml")), Format::Toml);
+        assert_eq!(Format::of(Path::new("a.YAML")), Format::Yaml);
+        assert_eq!(Format::of(Path::new("a.yml")), Format::Yaml);
+        assert_eq!(Format::of(Path::new("a.json")), Format::Json);
+        assert_eq!(Format::of(Path::new("a")), Format::Json);
+        assert_eq!(Format::of(Path::new("autobuild.nix")), Format::Nix);
+        assert_eq!(Format::of(Path::new(".#autobuildConfig")), Format::Flake);
+        assert_eq!(
+            Format::of(Path::new("github:a/b#autobuildConfig")),
+            Format::Flake
+        );
+    }
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn environment_overrides_the_file() -> Result<(), Box<dyn std::error::Error>> {
+        let settings = read(&example("config.json"))?;
+        let settings = apply_env(
+            settings,
+            vars(&[
+                ("NIX_AUTOBUILD_DIR", "/srv/autobuild"),
+                ("NIX_AUTOBUILD_HOST", "0.0.0.0"),
+                ("NIX_AUTOBUILD_LISTEN__TCP__PORT", "9000"),
+                ("NIX_AUTOBUILD_N_BUILD_THREADS", "4"),
+                ("NIX_AUTOBUILD_PORT", "8090"),
+                (
+                    "NIX_AUTOBUILD_SUPPORTED_ARCHITECTURES",
+                    r#"["aarch64-linux"]"#,
+                ),
+            ]),
+        )?;
+        assert_eq!(settings.dir, Path::new("/srv/autobuild"));
+        assert_eq!(settings.host, "0.0.0.0");
+        assert_eq!(settings.port, 8090);
+        assert_eq!(settings.n_build_threads, 4);
+        assert_eq!(settings.supported_architectures, ["aarch64-linux"]);
+        // the unset section gets its other defaults
+        assert_eq!(
+            settings.tcp_address(),
+            Some(("127.0.0.1".to_string(), 9000))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_overrides_name_the_variable() -> Result<(), Box<dyn std::error::Error>> {
+        let settings = read(&example("config.json"))?;
+        let error = apply_env(settings.clone(), vars(&[("NIX_AUTOBUILD_PORT", "http")]))
+            .err()
+            .unwrap_or_default();
+        assert!(
+            error.starts_with("Invalid NIX_AUTOBUILD_PORT: "),
+            "{}",
+            error
+        );
+        let error = apply_env(settings, vars(&[("NIX_AUTOBUILD_PROT", "8090")]))
+            .err()
+            .unwrap_or_default();
+        assert_eq!(error, "NIX_AUTOBUILD_PROT: there is no option `prot`");
+        Ok(())
+    }
+
+    #[test]
+    fn directories_merge_in_lexical_order() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_conf_d_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join("10-base.json"),
+            r#"{"dir": "/srv/autobuild", "supported_architectures": ["x86_64-linux"],
+                "n_build_threads": 0, "listen": {"tcp": {"port": 8080}},
+                "repos": [{"url": "github.com/a/base"}]}"#,
+        )?;
+        std::fs::write(
+            dir.join("20-team.toml"),
+            "[listen.tcp]\nport = 9000\n\n[[repos]]\nurl = \"github.com/team/app\"\n",
+        )?;
+        std::fs::write(dir.join("README.md"), "not part of the config")?;
+        let read = read(&dir.display().to_string());
+        std::fs::remove_dir_all(&dir)?;
+        let settings = read?;
+
+        assert_eq!(
+            settings.tcp_address(),
+            Some(("127.0.0.1".to_string(), 9000))
+        );
+        let urls = settings.repos.iter().map(|repo| repo.url.as_str());
+        assert_eq!(
+            urls.collect::<Vec<_>>(),
+            ["github.com/a/base", "github.com/team/app"]
+        );
+        let from = |option: &str| {
+            settings
+                .sources
+                .get(option)
+                .and_then(|source| Path::new(source).file_name()?.to_str())
+        };
+        assert_eq!(from("dir"), Some("10-base.json"));
+        assert_eq!(from("repos[0]"), Some("10-base.json"));
+        assert_eq!(from("repos[1]"), Some("20-team.toml"));
+        assert_eq!(from("listen.tcp.port"), Some("20-team.toml"));
+        assert_eq!(from("port"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn minimal_config_gets_the_defaults() -> Result<(), String> {
+        let settings = parse(
+            Path::new("autobuild.json"),
+            r#"{"repos": [{"url": "github.com/a/b"}]}"#,
+        )?;
+        let repo = &settings.repos[0];
+        assert_eq!(repo.poll_interval_sec(), 300);
+        assert_eq!(repo.build_depth, 1);
+        assert!(repo.branches.is_empty() && repo.credentials_file.is_none());
+        assert_eq!(settings.dir, Path::new("/var/lib/nix_autobuild"));
+        assert_eq!(
+            settings.tcp_address(),
+            Some(("127.0.0.1".to_string(), 8080))
+        );
+        assert_eq!(settings.n_build_threads, 0);
+        Ok(())
+    }
+
+    /// `default = ...;` of the NixOS options as JSON, `None` for submodule defaults
+    /// that the options of the submodule cover
+    fn nix_literal(literal: &str) -> Option<Value> {
+        if literal == "{}" {
+            return None;
+        }
+        if let Some(items) = literal.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let items = items
+                .split('"')
+                .skip(1)
+                .step_by(2)
+                .map(|item| Value::String(item.to_string()));
+            return Some(Value::Array(items.collect()));
+        }
+        serde_json::from_str(literal).ok()
+    }
+
+    /// Each type of the NixOS module with only its required options set
+    fn filled_in(section: &str) -> Result<Value, Box<dyn std::error::Error>> {
+        fn fill<T: serde::de::DeserializeOwned + serde::Serialize>(
+            json: &str,
+        ) -> Result<Value, Box<dyn std::error::Error>> {
+            Ok(serde_json::to_value(serde_json::from_str::<T>(json)?)?)
+        }
+        match section {
+            "autoBuildOptions" => fill::<AutoBuildOptions>("{}"),
+            "repo" => fill::<crate::Repo>(r#"{"url": "github.com/a/b"}"#),
+            "pathOptions" => fill::<crate::PathOptions>("{}"),
+            "stateOptions" => fill::<crate::StateOptions>("{}"),
+            "buildWindowOptions" => {
+                fill::<crate::BuildWindowOptions>(r#"{"start": "22:00", "end": "06:00"}"#)
+            }
+            "serverOptions" => fill::<crate::ServerOptions>("{}"),
+            "listenOptions" => fill::<crate::ListenOptions>("{}"),
+            "tcpListenOptions" => fill::<crate::TcpListenOptions>("{}"),
+            "unixSocketOptions" => fill::<crate::UnixSocketOptions>(r#"{"path": "/s"}"#),
+            "tlsOptions" => fill::<crate::TlsOptions>(r#"{"cert_file": "/c", "key_file": "/k"}"#),
+            "binaryCacheOptions" => fill::<crate::BinaryCacheOptions>("{}"),
+            "postBuildOptions" => fill::<crate::PostBuildOptions>("{}"),
+            "cachixOptions" => fill::<crate::CachixOptions>(r#"{"cache": "c"}"#),
+            "atticOptions" => fill::<crate::AtticOptions>(r#"{"cache": "c"}"#),
+            "rateLimitOptions" => fill::<crate::RateLimitOptions>("{}"),
+            "logOptions" => fill::<crate::LogOptions>("{}"),
+            "accessLogOptions" => fill::<crate::AccessLogOptions>("{}"),
+            "secretSource" => fill::<crate::SecretSource>("{}"),
+            "incomingWebhookOptions" => fill::<crate::IncomingWebhookOptions>("{}"),
+            "notificationOptions" => fill::<crate::NotificationOptions>("{}"),
+            "webhookOptions" => fill::<crate::WebhookOptions>(r#"{"url": "u"}"#),
+            "matrixOptions" => {
+                fill::<crate::MatrixOptions>(r#"{"homeserver": "h", "room_id": "r"}"#)
+            }
+            "chatWebhookOptions" => fill::<crate::ChatWebhookOptions>("{}"),
+            "emailOptions" => fill::<crate::EmailOptions>(r#"{"smtp_host": "h", "from": "f"}"#),
+            _ => Err(format!("no minimal {} to compare the defaults with", section).into()),
+        }
+    }
+
+    #[test]
+    fn serde_defaults_match_the_nixos_module() -> Result<(), Box<dyn std::error::Error>> {
+        let definition = crate::backend::nixos_module::render();
+        let (mut section, mut option) = (String::new(), String::new());
+        let mut defaults = Value::Null;
+        let mut compared = 0;
+        for line in definition.lines() {
+            let trimmed = line.trim();
+            if let Some(name) = line
+                .strip_prefix("  ")
+                .and_then(|l| l.split_once("Type = "))
+            {
+                section = name.0.to_string();
+                defaults = filled_in(&section)?;
+            } else if let Some(name) = trimmed.strip_suffix(" = lib.mkOption {") {
+                option = name.to_string();
+            } else if let Some(literal) = trimmed
+                .strip_prefix("default = ")
+                .and_then(|l| l.strip_suffix(';'))
+                && let Some(expected) = nix_literal(literal)
+            {
+                assert_eq!(
+                    defaults.get(&option),
+                    Some(&expected),
+                    "serde default of {}.{}",
+                    section,
+                    option
+                );
+                compared += 1;
+            }
+        }
+        assert!(compared > 40, "only {} defaults compared", compared);
+        Ok(())
+    }
+
+    #[test]
+    fn nixos_module_refers_to_every_submodule() -> Result<(), Box<dyn std::error::Error>> {
+        let definition = crate::backend::nixos_module::render();
+        assert!(
+            !definition.contains("options */"),
+            "submodule placeholders left in {}",
+            definition
+        );
+        // only where nix is installed
+        let path =
+            std::env::temp_dir().join(format!("nix_autobuild_module_{}.nix", std::process::id()));
+        std::fs::write(&path, &definition)?;
+        let parsed = std::process::Command::new("nix-instantiate")
+            .arg("--parse")
+            .arg(&path)
+            .output();
+        std::fs::remove_file(&path)?;
+        if let Ok(output) = parsed {
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(())
+    }
+}
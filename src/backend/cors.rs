@@ -0,0 +1,35 @@
+use actix_cors::Cors;
+use actix_web::http::{Method, header::HeaderValue};
+
+/// CORS policy for `allowed_origins`. Each entry is `*`, an exact origin such as
+/// `http://localhost:8081` or contains a single `*` wildcard, e.g.
+/// `https://*.example.com` or `http://localhost:*`.
+pub fn cors(allowed_origins: &[String]) -> Cors {
+    let cors = if allowed_origins.iter().any(|origin| origin == "*") {
+        Cors::default().allow_any_origin()
+    } else {
+        let allowed_origins = allowed_origins.to_vec();
+        Cors::default().allowed_origin_fn(move |origin: &HeaderValue, _| {
+            let Ok(origin) = origin.to_str() else {
+                return false;
+            };
+            allowed_origins
+                .iter()
+                .any(|pattern| origin_matches(pattern, origin))
+        })
+    };
+    cors.allowed_methods([Method::GET, Method::POST, Method::DELETE])
+        .allow_any_header()
+        .max_age(3600)
+}
+
+fn origin_matches(pattern: &str, origin: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            origin.len() >= prefix.len() + suffix.len()
+                && origin.starts_with(prefix)
+                && origin.ends_with(suffix)
+        }
+        None => pattern == origin,
+    }
+}
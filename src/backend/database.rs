@@ -0,0 +1,1534 @@
+//! The build state in an SQLite database in `state_dir`, so a restarted server
+//! shows the results of the commits it built before instead of building them
+//! again. Every status a package goes through is kept as well. When the database
+//! fails the server says so loudly and goes on with the state in memory only.
+
+use std::{
+    path::PathBuf,
+    sync::{
+        Arc, Mutex, OnceLock, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AutoBuildOptions, Repo,
+    builder::BuilderInfo,
+    commit::CommitInfo,
+    package::{
+        BuildAttempt, BuildControl, BuildOutcome, BuildRecord, BuildTimes, EXPIRED_LOG,
+        MAX_ATTEMPTS, NixosConfigPackage, Package, PackageBuildStatus, PackageEnum, static_arch,
+    },
+    serialize::RwLockWrapper,
+};
+
+use super::{
+    durations::{DurationKey, Samples, SavedSamples},
+    export::{Dump, DumpBuild, DumpCommit, DumpRepo},
+};
+
+const FILE_NAME: &str = "state.sqlite3";
+
+/// Values of `state.backend`, this database, [`super::state_file`] or nothing
+pub const STATE_BACKENDS: [&str; 3] = ["sqlite", "json", "none"];
+
+/// The schema, one step per version. A database at version `n` ran the first `n`,
+/// migrations are only ever appended.
+const MIGRATIONS: [&str; 5] = [
+    "
+    CREATE TABLE repos (
+        url TEXT PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE commits (
+        repo TEXT NOT NULL REFERENCES repos (url) ON DELETE CASCADE,
+        hash TEXT NOT NULL,
+        message TEXT NOT NULL,
+        unix_secs INTEGER NOT NULL,
+        -- the packages are known, all of them are in `packages`
+        evaluated INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (repo, hash)
+    );
+    CREATE TABLE packages (
+        repo TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        path TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        name TEXT NOT NULL,
+        description TEXT NOT NULL,
+        pkg_type TEXT NOT NULL,
+        arch TEXT NOT NULL,
+        status TEXT NOT NULL,
+        detail TEXT,
+        started INTEGER,
+        finished INTEGER,
+        closure_size INTEGER,
+        PRIMARY KEY (repo, hash, path),
+        FOREIGN KEY (repo, hash) REFERENCES commits (repo, hash) ON DELETE CASCADE
+    );
+    CREATE TABLE status_transitions (
+        id INTEGER PRIMARY KEY,
+        repo TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        path TEXT NOT NULL,
+        status TEXT NOT NULL,
+        detail TEXT,
+        unix_secs INTEGER NOT NULL,
+        FOREIGN KEY (repo, hash) REFERENCES commits (repo, hash) ON DELETE CASCADE
+    );
+    CREATE INDEX status_transitions_package ON status_transitions (repo, hash, path);
+",
+    "
+    -- the history of a package outlives its commits, it is pruned on its own
+    CREATE TABLE builds (
+        id INTEGER PRIMARY KEY,
+        repo TEXT NOT NULL,
+        path TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        started INTEGER,
+        finished INTEGER NOT NULL,
+        outcome TEXT NOT NULL,
+        out_paths TEXT
+    );
+    CREATE INDEX builds_package ON builds (repo, path, finished);
+    INSERT INTO builds (repo, path, hash, started, finished, outcome, out_paths)
+        SELECT repo, path, hash, started, finished, status,
+            CASE status WHEN 'success' THEN detail END
+        FROM packages
+        WHERE status IN ('success', 'failed', 'cancelled') AND finished IS NOT NULL;
+",
+    "
+    -- the attempts of a package, the builds from before are not numbered
+    ALTER TABLE builds ADD COLUMN attempt INTEGER;
+    ALTER TABLE builds ADD COLUMN detail TEXT;
+    ALTER TABLE builds ADD COLUMN log_ref TEXT;
+    UPDATE builds SET detail = out_paths;
+    CREATE INDEX builds_commit ON builds (repo, hash, path);
+",
+    "
+    -- the latest successful builds of each attribute across its commits
+    CREATE TABLE durations (
+        repo TEXT NOT NULL,
+        name TEXT NOT NULL,
+        arch TEXT NOT NULL,
+        count INTEGER NOT NULL,
+        -- seconds separated by commas, oldest first
+        recent TEXT NOT NULL,
+        PRIMARY KEY (repo, name, arch)
+    );
+",
+    "
+    -- what the builds ran on, see `builder_info`
+    CREATE TABLE builders (
+        id TEXT PRIMARY KEY,
+        captured_secs INTEGER NOT NULL,
+        -- the whole snapshot as JSON
+        info TEXT NOT NULL
+    );
+    ALTER TABLE builds ADD COLUMN builder TEXT;
+",
+];
+
+/// How long a connection waits for the other one to finish writing
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Tries of an action that found the database busy, before it is skipped
+const BUSY_TRIES: u32 = 3;
+/// Pause between two tries of an action
+const BUSY_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// `None` once the database failed
+static DATABASE: OnceLock<Mutex<Option<Connection>>> = OnceLock::new();
+/// Second connection only for [`record_status`], which runs with every status change
+/// of a build and should not wait for an export or a history read on `DATABASE`
+static STATUS_WRITER: OnceLock<Mutex<Option<Connection>>> = OnceLock::new();
+/// One connection failed, the other one is dropped on its next use
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Open or create the database, without it the state is only kept in memory
+pub fn open(settings: &AutoBuildOptions) {
+    let path = settings.state_dir().join(FILE_NAME);
+    match connect(&path) {
+        Ok(connection) => {
+            tracing::info!("Keeping the build state in {}", path.display());
+            let _ = DATABASE.set(Mutex::new(Some(connection)));
+            match connect(&path) {
+                Ok(connection) => {
+                    let _ = STATUS_WRITER.set(Mutex::new(Some(connection)));
+                }
+                Err(e) => tracing::warn!(
+                    "opening a second connection to {}, the build statuses share the first: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+        Err(e) => tracing::error!(
+            "opening the state database {}: {}. The build state is kept in memory only and lost on restart.",
+            path.display(),
+            e
+        ),
+    }
+}
+
+fn connect(path: &std::path::Path) -> Result<Connection, Box<dyn std::error::Error>> {
+    let mut connection = Connection::open(path)?;
+    connection.busy_timeout(BUSY_TIMEOUT)?;
+    connection.pragma_update(None, "foreign_keys", true)?;
+    connection.pragma_update(None, "journal_mode", "WAL")?;
+    connection.pragma_update(None, "synchronous", "NORMAL")?;
+    migrate(&mut connection)?;
+    Ok(connection)
+}
+
+/// Bring the schema to the newest version
+fn migrate(connection: &mut Connection) -> Result<(), Box<dyn std::error::Error>> {
+    let transaction = connection.transaction()?;
+    transaction
+        .execute_batch("CREATE TABLE IF NOT EXISTS schema_version (version INTEGER NOT NULL)")?;
+    let version = transaction
+        .query_row("SELECT version FROM schema_version", [], |row| {
+            row.get::<_, usize>(0)
+        })
+        .optional()?;
+    if version.is_none() {
+        transaction.execute("INSERT INTO schema_version (version) VALUES (0)", [])?;
+    }
+    let version = version.unwrap_or(0);
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "schema version {} is newer than this server, which knows up to {}",
+            version,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+    for migration in &MIGRATIONS[version..] {
+        transaction.execute_batch(migration)?;
+    }
+    transaction.execute("UPDATE schema_version SET version = ?1", [MIGRATIONS.len()])?;
+    transaction.commit()?;
+    Ok(())
+}
+
+/// `f` with the connection, `None` when there is none
+fn with<T>(action: &str, f: impl FnMut(&mut Connection) -> rusqlite::Result<T>) -> Option<T> {
+    with_on(&DATABASE, action, f)
+}
+
+/// `f` with the connection of `database`, `None` when there is none. `f` is tried
+/// again while another connection or process holds the database, then skipped.
+/// Any other error drops the database for good, a half written state is worse
+/// than none.
+fn with_on<T>(
+    database: &OnceLock<Mutex<Option<Connection>>>,
+    action: &str,
+    mut f: impl FnMut(&mut Connection) -> rusqlite::Result<T>,
+) -> Option<T> {
+    let mut connection = database
+        .get()?
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner);
+    if FAILED.load(Ordering::SeqCst) {
+        *connection = None;
+    }
+    for try_number in 1.. {
+        let e = match f(connection.as_mut()?) {
+            Ok(value) => return Some(value),
+            Err(e) => e,
+        };
+        if !is_busy(&e) {
+            tracing::error!(
+                "{} in the state database: {}. The build state is kept in memory only from now on and lost on restart.",
+                action,
+                e
+            );
+            FAILED.store(true, Ordering::SeqCst);
+            *connection = None;
+            return None;
+        }
+        if try_number == BUSY_TRIES {
+            tracing::warn!(
+                "{} in the state database: {}, skipped after {} tries",
+                action,
+                e,
+                BUSY_TRIES
+            );
+            return None;
+        }
+        thread::sleep(BUSY_RETRY_DELAY);
+    }
+    None
+}
+
+/// The database was locked by another connection, which passes
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e,
+        rusqlite::Error::SqliteFailure(failure, _)
+            if matches!(
+                failure.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            )
+    )
+}
+
+pub fn record_repo(repo: &Repo) {
+    with("recording a repo", |connection| {
+        upsert_repo(connection, &repo.url, &repo.name())
+    });
+}
+
+pub fn record_commit(commit: &CommitInfo) {
+    let Some(repo) = commit.repo() else {
+        return;
+    };
+    with("recording a commit", |connection| {
+        upsert_commit(
+            connection,
+            &repo.repo.url,
+            &commit.hash,
+            &commit.message,
+            commit.unix_secs,
+        )
+    });
+}
+
+/// All packages of `commit` once the flake was evaluated
+pub fn record_packages(commit: &CommitInfo, packages: &[PackageEnum]) {
+    let Some(repo) = commit.repo() else {
+        return;
+    };
+    let stored = packages.iter().map(StoredPackage::from).collect::<Vec<_>>();
+    with("recording the packages of a commit", |connection| {
+        insert_packages(connection, &repo.repo.url, &commit.hash, &stored)
+    });
+}
+
+/// A new status of the package at `path` of `commit`
+pub fn record_status(
+    commit: &CommitInfo,
+    path: &str,
+    status: &PackageBuildStatus,
+    times: BuildTimes,
+    closure_size: Option<u64>,
+    attempt: Option<&BuildAttempt>,
+) {
+    // builds cancelled by a shutdown run again after the restart
+    if *status == PackageBuildStatus::Cancelled && super::shutdown::requested() {
+        return;
+    }
+    let Some(repo) = commit.repo() else {
+        return;
+    };
+    let database = match STATUS_WRITER.get() {
+        Some(_) => &STATUS_WRITER,
+        None => &DATABASE,
+    };
+    with_on(database, "recording a build status", |connection| {
+        update_status(
+            connection,
+            &repo.repo.url,
+            &commit.hash,
+            path,
+            StatusChange {
+                status,
+                times,
+                closure_size,
+                attempt,
+            },
+        )
+    });
+}
+
+/// The packages of `commit` as they were last recorded, `None` if its flake was not
+/// evaluated before. Builds that had not finished are `Idle` again.
+pub fn stored_packages(commit: &Arc<CommitInfo>) -> Option<Vec<PackageEnum>> {
+    let repo = commit.repo()?;
+    with("reading the packages of a commit", |connection| {
+        load_packages(connection, &repo.repo.url, &commit.hash)
+    })?
+    .map(|stored| {
+        stored
+            .into_iter()
+            .map(|package| package.into_package(commit))
+            .collect()
+    })
+}
+
+/// The log `log_ref` of the builds of `hash` was deleted
+pub fn expire_log(repo_url: &str, hash: &str, log_ref: &str) {
+    with("expiring a build log", |connection| {
+        connection
+            .execute(
+                "UPDATE builds SET log_ref = ?1 WHERE repo = ?2 AND hash = ?3 AND log_ref = ?4",
+                params![EXPIRED_LOG, repo_url, hash, log_ref],
+            )
+            .map(|_| ())
+    });
+}
+
+/// The urls of all repos in the database, `None` without it
+pub(super) fn repo_urls() -> Option<Vec<String>> {
+    with("listing the repos", |connection| {
+        connection
+            .prepare("SELECT url FROM repos ORDER BY url")?
+            .query_map([], |row| row.get(0))?
+            .collect()
+    })
+}
+
+/// Everything kept of `repo_url`, see [`super::export`]
+pub(super) fn dump_repo(repo_url: &str) -> Option<DumpRepo> {
+    with("exporting a repo", |connection| {
+        read_dump(connection, repo_url)
+    })?
+}
+
+/// Write an export to the database in `state_dir` of `settings`, which has to be
+/// empty unless `force`. Returns the path of the database.
+pub(super) fn import(
+    settings: &AutoBuildOptions,
+    dump: &Dump,
+    force: bool,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = settings.state_dir().join(FILE_NAME);
+    let mut connection = connect(&path)
+        .map_err(|e| format!("opening the state database {}: {}", path.display(), e))?;
+    let existing = connection.query_row("SELECT COUNT(*) FROM repos", [], |row| {
+        row.get::<_, usize>(0)
+    })?;
+    if existing > 0 && !force {
+        return Err(format!(
+            "{} already holds the state of {} repos, --force merges the export into it",
+            path.display(),
+            existing
+        )
+        .into());
+    }
+    for repo in &dump.repos {
+        write_dump(&mut connection, repo)?;
+    }
+    let transaction = connection.transaction()?;
+    for saved in &dump.durations {
+        upsert_durations(&transaction, &saved.key, &saved.samples)?;
+    }
+    for info in &dump.builders {
+        insert_builder(&transaction, info)?;
+    }
+    transaction.commit()?;
+    Ok(path)
+}
+
+/// The commits of `repo_url` were forgotten, see [`super::retention`]
+pub fn forget_commits(repo_url: &str, hashes: &[String]) {
+    with("forgetting commits", |connection| {
+        let transaction = connection.transaction()?;
+        for hash in hashes {
+            transaction.execute(
+                "DELETE FROM commits WHERE repo = ?1 AND hash = ?2",
+                params![repo_url, hash],
+            )?;
+        }
+        transaction.commit()
+    });
+}
+
+/// The last `limit` finished builds of the package at `path` across all commits of
+/// `repo_url`, newest first. `None` without the database.
+pub fn history(repo_url: &str, path: &str, limit: usize) -> Option<Vec<BuildRecord>> {
+    with("reading the history of a package", |connection| {
+        load_history(connection, repo_url, path, limit)
+    })
+}
+
+/// Drop the builds of `repo_url` older than `oldest` and all but the newest
+/// `max_builds` of each package, like the commits. 0 and `None` for no limit.
+pub fn prune_history(repo_url: &str, max_builds: usize, oldest: Option<i64>) {
+    with("pruning the build history", |connection| {
+        delete_old_builds(connection, repo_url, max_builds, oldest)
+    });
+}
+
+/// The latest build durations of `key`, see [`super::durations`]
+pub fn record_durations(key: &DurationKey, samples: &Samples) {
+    with("recording build durations", |connection| {
+        upsert_durations(connection, key, samples)
+    });
+}
+
+/// The build durations of `keys` were reset
+pub fn forget_durations(keys: &[DurationKey]) {
+    with("resetting build durations", |connection| {
+        let transaction = connection.transaction()?;
+        for key in keys {
+            transaction.execute(
+                "DELETE FROM durations WHERE repo = ?1 AND name = ?2 AND arch = ?3",
+                params![key.repo, key.name, key.arch],
+            )?;
+        }
+        transaction.commit()
+    });
+}
+
+/// All recorded build durations, `None` without the database
+pub fn stored_durations() -> Option<Vec<SavedSamples>> {
+    with("reading build durations", load_durations)
+}
+
+/// A new snapshot of the builder, see [`super::builder_info`]
+pub fn record_builder(info: &BuilderInfo) {
+    with("recording the builder", |connection| {
+        insert_builder(connection, info)
+    });
+}
+
+/// All recorded builder snapshots, `None` without the database
+pub fn stored_builders() -> Option<Vec<BuilderInfo>> {
+    with("reading the builders", |connection| {
+        let infos = connection
+            .prepare("SELECT info FROM builders ORDER BY captured_secs")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(infos
+            .iter()
+            .filter_map(|info| serde_json::from_str(info).ok())
+            .collect())
+    })
+}
+
+/// A package as stored in `packages`, in a snapshot of [`super::state_file`] or in
+/// an [`super::export`]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct StoredPackage {
+    pub(super) path: String,
+    pub(super) nixos_config: bool,
+    pub(super) name: String,
+    pub(super) description: String,
+    pub(super) pkg_type: String,
+    pub(super) arch: String,
+    pub(super) status: PackageBuildStatus,
+    pub(super) times: BuildTimes,
+    pub(super) closure_size: Option<u64>,
+    /// kept in `builds`, only the finished ones
+    pub(super) attempts: Vec<BuildAttempt>,
+}
+
+impl From<&PackageEnum> for StoredPackage {
+    fn from(package: &PackageEnum) -> Self {
+        let (nixos_config, name, description, pkg_type, arch) = match package {
+            PackageEnum::Derivation(pkg) => (
+                false,
+                pkg.0.name.clone(),
+                pkg.0.description.clone(),
+                pkg.0.pkg_type.clone(),
+                pkg.0.arch.to_string(),
+            ),
+            PackageEnum::NixosConfig(pkg) => (
+                true,
+                String::new(),
+                String::new(),
+                pkg.0.pkg_type.clone(),
+                String::new(),
+            ),
+        };
+        StoredPackage {
+            path: package.path().to_string(),
+            nixos_config,
+            name,
+            description,
+            pkg_type,
+            arch,
+            status: package.status(),
+            times: package.times(),
+            closure_size: package.closure_size(),
+            attempts: package.attempts(),
+        }
+    }
+}
+
+impl StoredPackage {
+    pub(super) fn into_package(mut self, commit: &Arc<CommitInfo>) -> PackageEnum {
+        let flake_url = format!("{}#{}", commit.flake_url, self.path);
+        let status = match self.status.is_finished() {
+            true => self.status,
+            false => PackageBuildStatus::Idle,
+        };
+        // the interrupted build runs again as the same attempt
+        self.attempts.retain(|attempt| attempt.finished.is_some());
+        if self.nixos_config {
+            return PackageEnum::NixosConfig(
+                Arc::new(NixosConfigPackage {
+                    path: self.path,
+                    pkg_type: self.pkg_type,
+                    flake_url,
+                    status: RwLockWrapper::new(status),
+                    times: RwLockWrapper::new(self.times),
+                    closure_size: RwLockWrapper::new(self.closure_size),
+                    expected_secs: RwLockWrapper::new(None),
+                    result_missing: RwLockWrapper::new(false),
+                    pushes: RwLockWrapper::new(Vec::new()),
+                    attempts: RwLockWrapper::new(self.attempts),
+                    commit: commit.clone(),
+                    control: BuildControl::default(),
+                })
+                .into(),
+            );
+        }
+        PackageEnum::Derivation(
+            Arc::new(Package {
+                description: self.description,
+                name: self.name,
+                pkg_type: self.pkg_type,
+                path: self.path,
+                arch: static_arch(&self.arch),
+                flake_url,
+                status: RwLockWrapper::new(status),
+                times: RwLockWrapper::new(self.times),
+                closure_size: RwLockWrapper::new(self.closure_size),
+                expected_secs: RwLockWrapper::new(None),
+                result_missing: RwLockWrapper::new(false),
+                pushes: RwLockWrapper::new(Vec::new()),
+                attempts: RwLockWrapper::new(self.attempts),
+                commit: commit.clone(),
+                control: BuildControl::default(),
+            })
+            .into(),
+        )
+    }
+}
+
+/// The `status` and `detail` columns of `status`
+fn encode(status: &PackageBuildStatus) -> (&'static str, Option<String>) {
+    match status {
+        PackageBuildStatus::Idle => ("idle", None),
+        PackageBuildStatus::UnsupportedArchitecture(arch) => {
+            ("unsupported_architecture", Some(arch.to_string()))
+        }
+        PackageBuildStatus::WaitingForWindow => ("waiting_for_window", None),
+        PackageBuildStatus::WaitingForBuild => ("waiting_for_build", None),
+        PackageBuildStatus::Building => ("building", None),
+        PackageBuildStatus::Success(out_paths) => ("success", Some(out_paths.clone())),
+        PackageBuildStatus::Failed(error) => ("failed", Some(error.clone())),
+        PackageBuildStatus::Cancelled => ("cancelled", None),
+        PackageBuildStatus::DryRun {
+            to_build,
+            to_substitute,
+        } => ("dry_run", Some(format!("{} {}", to_build, to_substitute))),
+    }
+}
+
+/// `None` for a status this version does not know
+fn decode(status: &str, detail: Option<String>) -> Option<PackageBuildStatus> {
+    Some(match status {
+        "idle" => PackageBuildStatus::Idle,
+        "unsupported_architecture" => {
+            PackageBuildStatus::UnsupportedArchitecture(static_arch(&detail?))
+        }
+        "waiting_for_window" => PackageBuildStatus::WaitingForWindow,
+        "waiting_for_build" => PackageBuildStatus::WaitingForBuild,
+        "building" => PackageBuildStatus::Building,
+        "success" => PackageBuildStatus::Success(detail?),
+        "failed" => PackageBuildStatus::Failed(detail.unwrap_or_default()),
+        "cancelled" => PackageBuildStatus::Cancelled,
+        "dry_run" => {
+            let detail = detail?;
+            let (to_build, to_substitute) = detail.split_once(' ')?;
+            PackageBuildStatus::DryRun {
+                to_build: to_build.parse().ok()?,
+                to_substitute: to_substitute.parse().ok()?,
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn upsert_repo(connection: &Connection, url: &str, name: &str) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO repos (url, name) VALUES (?1, ?2)
+         ON CONFLICT (url) DO UPDATE SET name = excluded.name",
+        params![url, name],
+    )?;
+    Ok(())
+}
+
+fn insert_builder(connection: &Connection, info: &BuilderInfo) -> rusqlite::Result<()> {
+    let json = serde_json::to_string(info)
+        .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    connection
+        .execute(
+            "INSERT OR IGNORE INTO builders (id, captured_secs, info) VALUES (?1, ?2, ?3)",
+            params![info.id, info.captured_secs, json],
+        )
+        .map(|_| ())
+}
+
+fn upsert_durations(
+    connection: &Connection,
+    key: &DurationKey,
+    samples: &Samples,
+) -> rusqlite::Result<()> {
+    let recent = samples
+        .recent
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(",");
+    connection
+        .execute(
+            "INSERT INTO durations (repo, name, arch, count, recent)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+            ON CONFLICT (repo, name, arch) DO UPDATE SET
+                count = excluded.count, recent = excluded.recent",
+            params![key.repo, key.name, key.arch, samples.count, recent],
+        )
+        .map(|_| ())
+}
+
+fn upsert_commit(
+    connection: &Connection,
+    repo: &str,
+    hash: &str,
+    message: &str,
+    unix_secs: i64,
+) -> rusqlite::Result<()> {
+    connection.execute(
+        "INSERT INTO commits (repo, hash, message, unix_secs) VALUES (?1, ?2, ?3, ?4)
+         ON CONFLICT (repo, hash) DO UPDATE SET message = excluded.message",
+        params![repo, hash, message, unix_secs],
+    )?;
+    Ok(())
+}
+
+/// Replace the packages of the commit and mark it evaluated
+fn insert_packages(
+    connection: &mut Connection,
+    repo: &str,
+    hash: &str,
+    packages: &[StoredPackage],
+) -> rusqlite::Result<()> {
+    let transaction = connection.transaction()?;
+    write_packages(&transaction, repo, hash, packages)?;
+    transaction.commit()
+}
+
+/// `insert_packages` within the transaction of `connection`
+fn write_packages(
+    transaction: &Connection,
+    repo: &str,
+    hash: &str,
+    packages: &[StoredPackage],
+) -> rusqlite::Result<()> {
+    transaction.execute(
+        "DELETE FROM packages WHERE repo = ?1 AND hash = ?2",
+        params![repo, hash],
+    )?;
+    for package in packages {
+        let (status, detail) = encode(&package.status);
+        transaction.execute(
+            "INSERT INTO packages (repo, hash, path, kind, name, description, pkg_type, arch,
+                 status, detail, started, finished, closure_size)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+            params![
+                repo,
+                hash,
+                package.path,
+                if package.nixos_config {
+                    "nixos_config"
+                } else {
+                    "derivation"
+                },
+                package.name,
+                package.description,
+                package.pkg_type,
+                package.arch,
+                status,
+                detail,
+                package.times.started,
+                package.times.finished,
+                package.closure_size,
+            ],
+        )?;
+    }
+    transaction.execute(
+        "UPDATE commits SET evaluated = 1 WHERE repo = ?1 AND hash = ?2",
+        params![repo, hash],
+    )?;
+    Ok(())
+}
+
+/// A new status of a package and what changed with it
+struct StatusChange<'a> {
+    status: &'a PackageBuildStatus,
+    times: BuildTimes,
+    closure_size: Option<u64>,
+    /// the latest attempt, which the status ends
+    attempt: Option<&'a BuildAttempt>,
+}
+
+fn update_status(
+    connection: &mut Connection,
+    repo: &str,
+    hash: &str,
+    path: &str,
+    change: StatusChange,
+) -> rusqlite::Result<()> {
+    let StatusChange {
+        status,
+        times,
+        closure_size,
+        attempt,
+    } = change;
+    let (status, detail) = encode(status);
+    let transaction = connection.transaction()?;
+    let updated = transaction.execute(
+        "UPDATE packages SET status = ?4, detail = ?5, started = ?6, finished = ?7,
+             closure_size = ?8
+         WHERE repo = ?1 AND hash = ?2 AND path = ?3",
+        params![
+            repo,
+            hash,
+            path,
+            status,
+            detail,
+            times.started,
+            times.finished,
+            closure_size
+        ],
+    )?;
+    // the package of a commit that is not recorded, e.g. after the database failed
+    if updated == 0 {
+        return Ok(());
+    }
+    if let (Some(_), Some(finished)) = (outcome(status), times.finished) {
+        transaction.execute(
+            "INSERT INTO builds (repo, path, hash, started, finished, outcome, out_paths,
+                 attempt, detail, log_ref, builder)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                repo,
+                path,
+                hash,
+                times.started,
+                finished,
+                status,
+                if status == "success" {
+                    detail.as_deref()
+                } else {
+                    None
+                },
+                attempt.map(|attempt| attempt.number),
+                detail,
+                attempt.and_then(|attempt| attempt.log_ref.as_deref()),
+                attempt.and_then(|attempt| attempt.builder.as_deref())
+            ],
+        )?;
+    }
+    transaction.execute(
+        "INSERT INTO status_transitions (repo, hash, path, status, detail, unix_secs)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            repo,
+            hash,
+            path,
+            status,
+            detail,
+            times
+                .finished
+                .or(times.started)
+                .unwrap_or_else(super::events::unix_now)
+        ],
+    )?;
+    transaction.commit()
+}
+
+/// Of a status that ends a build, `status` as [`encode`]d
+fn outcome(status: &str) -> Option<BuildOutcome> {
+    match status {
+        "success" => Some(BuildOutcome::Success),
+        "failed" => Some(BuildOutcome::Failed),
+        "cancelled" => Some(BuildOutcome::Cancelled),
+        _ => None,
+    }
+}
+
+fn load_history(
+    connection: &Connection,
+    repo: &str,
+    path: &str,
+    limit: usize,
+) -> rusqlite::Result<Vec<BuildRecord>> {
+    let mut statement = connection.prepare(
+        "SELECT hash, started, finished, outcome, out_paths FROM builds
+         WHERE repo = ?1 AND path = ?2 ORDER BY finished DESC, id DESC LIMIT ?3",
+    )?;
+    let rows = statement.query_map(params![repo, path, limit], |row| {
+        let started = row.get::<_, Option<i64>>(1)?;
+        let finished = row.get::<_, i64>(2)?;
+        Ok((
+            row.get::<_, String>(3)?,
+            BuildRecord {
+                commit: row.get(0)?,
+                started,
+                finished,
+                duration_secs: started.map(|started| finished - started),
+                outcome: BuildOutcome::Success,
+                out_paths: row.get(4)?,
+            },
+        ))
+    })?;
+    let mut records = Vec::new();
+    for row in rows {
+        let (status, record) = row?;
+        // a build of a newer version is left out
+        if let Some(outcome) = outcome(&status) {
+            records.push(BuildRecord { outcome, ..record });
+        }
+    }
+    Ok(records)
+}
+
+fn delete_old_builds(
+    connection: &Connection,
+    repo: &str,
+    max_builds: usize,
+    oldest: Option<i64>,
+) -> rusqlite::Result<()> {
+    if let Some(oldest) = oldest {
+        connection.execute(
+            "DELETE FROM builds WHERE repo = ?1 AND finished < ?2",
+            params![repo, oldest],
+        )?;
+    }
+    if max_builds > 0 {
+        connection.execute(
+            "DELETE FROM builds WHERE id IN (
+                 SELECT id FROM (
+                     SELECT id, ROW_NUMBER() OVER (
+                         PARTITION BY path ORDER BY finished DESC, id DESC
+                     ) AS newer FROM builds WHERE repo = ?1
+                 ) WHERE newer > ?2
+             )",
+            params![repo, max_builds],
+        )?;
+    }
+    Ok(())
+}
+
+/// `None` if the repo is not in the database
+fn read_dump(connection: &Connection, repo: &str) -> rusqlite::Result<Option<DumpRepo>> {
+    let Some(name) = connection
+        .query_row("SELECT name FROM repos WHERE url = ?1", [repo], |row| {
+            row.get::<_, String>(0)
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let commits = connection
+        .prepare("SELECT hash, message, unix_secs FROM commits WHERE repo = ?1 ORDER BY rowid")?
+        .query_map([repo], |row| {
+            Ok(DumpCommit {
+                hash: row.get(0)?,
+                message: row.get(1)?,
+                unix_secs: row.get(2)?,
+                packages: None,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut dumped = Vec::with_capacity(commits.len());
+    for commit in commits {
+        let packages = load_packages(connection, repo, &commit.hash)?;
+        dumped.push(DumpCommit { packages, ..commit });
+    }
+    let builds = connection
+        .prepare(
+            "SELECT path, hash, started, finished, outcome, out_paths, attempt, detail, log_ref,
+                 builder
+             FROM builds WHERE repo = ?1 ORDER BY id",
+        )?
+        .query_map([repo], |row| {
+            Ok(DumpBuild {
+                path: row.get(0)?,
+                hash: row.get(1)?,
+                started: row.get(2)?,
+                finished: row.get(3)?,
+                outcome: row.get(4)?,
+                out_paths: row.get(5)?,
+                attempt: row.get(6)?,
+                detail: row.get(7)?,
+                log_ref: row.get(8)?,
+                builder: row.get(9)?,
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    Ok(Some(DumpRepo {
+        url: repo.to_string(),
+        name,
+        commits: dumped,
+        builds,
+    }))
+}
+
+/// Replace what is kept of `repo` by the export. An export without a build history,
+/// e.g. from a server without the database, brings the attempts of its packages.
+fn write_dump(connection: &mut Connection, repo: &DumpRepo) -> rusqlite::Result<()> {
+    // all of the repo or nothing, and one sync instead of one per commit
+    let transaction = connection.transaction()?;
+    upsert_repo(&transaction, &repo.url, &repo.name)?;
+    for commit in &repo.commits {
+        upsert_commit(
+            &transaction,
+            &repo.url,
+            &commit.hash,
+            &commit.message,
+            commit.unix_secs,
+        )?;
+        if let Some(packages) = &commit.packages {
+            write_packages(&transaction, &repo.url, &commit.hash, packages)?;
+        }
+    }
+    let mut builds = repo.builds.clone();
+    if builds.is_empty() {
+        for commit in &repo.commits {
+            for package in commit.packages.iter().flatten() {
+                for attempt in &package.attempts {
+                    let (outcome, detail) = encode(&attempt.status);
+                    let (Some(_), Some(finished)) = (self::outcome(outcome), attempt.finished)
+                    else {
+                        continue;
+                    };
+                    builds.push(DumpBuild {
+                        path: package.path.clone(),
+                        hash: commit.hash.clone(),
+                        started: attempt.started,
+                        finished,
+                        outcome: outcome.to_string(),
+                        out_paths: detail.clone().filter(|_| outcome == "success"),
+                        attempt: Some(attempt.number),
+                        detail,
+                        log_ref: attempt.log_ref.clone(),
+                        builder: attempt.builder.clone(),
+                    });
+                }
+            }
+        }
+        builds.sort_by_key(|build| build.finished);
+    }
+    transaction.execute("DELETE FROM builds WHERE repo = ?1", [&repo.url])?;
+    for build in &builds {
+        transaction.execute(
+            "INSERT INTO builds (repo, path, hash, started, finished, outcome, out_paths,
+                 attempt, detail, log_ref, builder)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                repo.url,
+                build.path,
+                build.hash,
+                build.started,
+                build.finished,
+                build.outcome,
+                build.out_paths,
+                build.attempt,
+                build.detail,
+                build.log_ref,
+                build.builder
+            ],
+        )?;
+    }
+    transaction.commit()
+}
+
+/// `None` if the commit was not evaluated
+fn load_packages(
+    connection: &Connection,
+    repo: &str,
+    hash: &str,
+) -> rusqlite::Result<Option<Vec<StoredPackage>>> {
+    let evaluated = connection
+        .query_row(
+            "SELECT evaluated FROM commits WHERE repo = ?1 AND hash = ?2",
+            params![repo, hash],
+            |row| row.get::<_, bool>(0),
+        )
+        .optional()?;
+    if evaluated != Some(true) {
+        return Ok(None);
+    }
+    let mut statement = connection.prepare(
+        "SELECT path, kind, name, description, pkg_type, arch, status, detail, started,
+             finished, closure_size
+         FROM packages WHERE repo = ?1 AND hash = ?2 ORDER BY rowid",
+    )?;
+    let packages = statement
+        .query_map(params![repo, hash], |row| {
+            let status = row.get::<_, String>(6)?;
+            Ok(StoredPackage {
+                path: row.get(0)?,
+                nixos_config: row.get::<_, String>(1)? == "nixos_config",
+                name: row.get(2)?,
+                description: row.get(3)?,
+                pkg_type: row.get(4)?,
+                arch: row.get(5)?,
+                // written by a newer version, built again
+                status: decode(&status, row.get(7)?).unwrap_or(PackageBuildStatus::Idle),
+                times: BuildTimes {
+                    started: row.get(8)?,
+                    finished: row.get(9)?,
+                },
+                closure_size: row.get(10)?,
+                attempts: Vec::new(),
+            })
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    let mut packages = packages;
+    for package in &mut packages {
+        package.attempts = load_attempts(connection, repo, hash, &package.path)?;
+    }
+    Ok(Some(packages))
+}
+
+/// The latest [`MAX_ATTEMPTS`] finished builds of the package at `path` of the
+/// commit, oldest first
+fn load_attempts(
+    connection: &Connection,
+    repo: &str,
+    hash: &str,
+    path: &str,
+) -> rusqlite::Result<Vec<BuildAttempt>> {
+    let mut statement = connection.prepare_cached(
+        "SELECT attempt, started, finished, outcome, detail, log_ref, builder FROM builds
+         WHERE repo = ?1 AND hash = ?2 AND path = ?3 ORDER BY id DESC LIMIT ?4",
+    )?;
+    let rows = statement.query_map(params![repo, hash, path, MAX_ATTEMPTS], |row| {
+        Ok((
+            row.get::<_, Option<usize>>(0)?,
+            BuildAttempt {
+                number: 0,
+                started: row.get(1)?,
+                finished: Some(row.get(2)?),
+                status: PackageBuildStatus::Idle,
+                log_ref: row.get(5)?,
+                builder: row.get(6)?,
+            },
+            row.get::<_, String>(3)?,
+            row.get::<_, Option<String>>(4)?,
+        ))
+    })?;
+    let mut attempts = Vec::new();
+    for row in rows {
+        let (number, attempt, status, detail) = row?;
+        if let Some(status) = decode(&status, detail) {
+            attempts.push((number, BuildAttempt { status, ..attempt }));
+        }
+    }
+    attempts.reverse();
+    // builds from before the attempts were numbered count from the first one kept
+    Ok((1..)
+        .zip(attempts)
+        .map(|(position, (number, attempt))| BuildAttempt {
+            number: number.unwrap_or(position),
+            ..attempt
+        })
+        .collect())
+}
+
+fn load_durations(connection: &mut Connection) -> rusqlite::Result<Vec<SavedSamples>> {
+    connection
+        .prepare("SELECT repo, name, arch, count, recent FROM durations")?
+        .query_map([], |row| {
+            let recent = row.get::<_, String>(4)?;
+            Ok(SavedSamples {
+                key: DurationKey {
+                    repo: row.get(0)?,
+                    name: row.get(1)?,
+                    arch: row.get(2)?,
+                },
+                samples: Samples {
+                    count: row.get(3)?,
+                    recent: recent
+                        .split(',')
+                        .filter_map(|secs| secs.parse().ok())
+                        .collect(),
+                },
+            })
+        })?
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn package(path: &str, status: PackageBuildStatus) -> StoredPackage {
+        StoredPackage {
+            path: path.to_string(),
+            nixos_config: false,
+            name: "hello-2.12".to_string(),
+            description: "A program that produces a familiar, friendly greeting".to_string(),
+            pkg_type: "derivation".to_string(),
+            arch: "x86_64-linux".to_string(),
+            status,
+            times: BuildTimes::default(),
+            closure_size: None,
+            attempts: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn migrations_run_once() -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = Connection::open_in_memory()?;
+        migrate(&mut connection)?;
+        migrate(&mut connection)?;
+        let version = connection.query_row("SELECT version FROM schema_version", [], |row| {
+            row.get::<_, usize>(0)
+        })?;
+        assert_eq!(version, MIGRATIONS.len());
+
+        connection.execute("UPDATE schema_version SET version = 99", [])?;
+        assert!(migrate(&mut connection).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn a_locked_database_is_busy() -> Result<(), Box<dyn std::error::Error>> {
+        let path =
+            std::env::temp_dir().join(format!("nix_autobuild_busy_{}.sqlite3", std::process::id()));
+        let holder = connect(&path)?;
+        let waiting = connect(&path)?;
+        waiting.busy_timeout(Duration::ZERO)?;
+        holder.execute_batch("BEGIN IMMEDIATE")?;
+        let e = upsert_repo(&waiting, "github.com/org/repo", "repo")
+            .err()
+            .ok_or("the write went through")?;
+        assert!(is_busy(&e));
+        holder.execute_batch("COMMIT")?;
+        upsert_repo(&waiting, "github.com/org/repo", "repo")?;
+        assert!(!is_busy(&rusqlite::Error::InvalidQuery));
+        drop((holder, waiting));
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn statuses_survive_a_restart() -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        let (repo, hash) = ("github.com/org/repo", "abc123");
+        upsert_repo(&connection, repo, "repo")?;
+        upsert_commit(&connection, repo, hash, "Initial commit", 1_700_000_000)?;
+        assert_eq!(load_packages(&connection, repo, hash)?, None);
+
+        let path = "packages.x86_64-linux.hello";
+        insert_packages(
+            &mut connection,
+            repo,
+            hash,
+            &[
+                package(path, PackageBuildStatus::Idle),
+                package("packages.x86_64-linux.default", PackageBuildStatus::Idle),
+            ],
+        )?;
+        let times = BuildTimes {
+            started: Some(10),
+            finished: Some(20),
+        };
+        let mut attempts = Vec::new();
+        for status in [
+            PackageBuildStatus::Building,
+            PackageBuildStatus::Success("/nix/store/abc-hello-2.12".to_string()),
+        ] {
+            BuildAttempt::record(&mut attempts, &status, times, |number| {
+                format!("hello.{}.log", number)
+            });
+            update_status(
+                &mut connection,
+                repo,
+                hash,
+                path,
+                StatusChange {
+                    status: &status,
+                    times,
+                    closure_size: Some(42),
+                    attempt: attempts.last(),
+                },
+            )?;
+        }
+        let packages = load_packages(&connection, repo, hash)?.unwrap_or_default();
+        assert_eq!(packages.len(), 2);
+        assert_eq!(
+            packages[0],
+            StoredPackage {
+                times,
+                closure_size: Some(42),
+                attempts,
+                ..package(
+                    path,
+                    PackageBuildStatus::Success("/nix/store/abc-hello-2.12".to_string())
+                )
+            }
+        );
+        let transitions = connection.query_row(
+            "SELECT COUNT(*) FROM status_transitions WHERE path = ?1",
+            [path],
+            |row| row.get::<_, usize>(0),
+        )?;
+        assert_eq!(transitions, 2);
+
+        connection.execute("DELETE FROM commits WHERE hash = ?1", [hash])?;
+        assert_eq!(load_packages(&connection, repo, hash)?, None);
+        let left = connection.query_row("SELECT COUNT(*) FROM packages", [], |row| {
+            row.get::<_, usize>(0)
+        })?;
+        assert_eq!(left, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn history_outlives_the_commits() -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        let repo = "github.com/org/repo";
+        let path = "packages.x86_64-linux.hello";
+        upsert_repo(&connection, repo, "repo")?;
+        let results = [
+            (
+                "a",
+                PackageBuildStatus::Success("/nix/store/a-hello".to_string()),
+            ),
+            (
+                "b",
+                PackageBuildStatus::Failed("error: builder failed".to_string()),
+            ),
+            ("c", PackageBuildStatus::Cancelled),
+        ];
+        for (index, (hash, status)) in (0..).zip(results) {
+            upsert_commit(&connection, repo, hash, "Change", index)?;
+            insert_packages(
+                &mut connection,
+                repo,
+                hash,
+                &[package(path, PackageBuildStatus::Idle)],
+            )?;
+            let times = BuildTimes {
+                started: Some(100 * index),
+                finished: Some(100 * index + 5 + index),
+            };
+            update_status(
+                &mut connection,
+                repo,
+                hash,
+                path,
+                StatusChange {
+                    status: &status,
+                    times,
+                    closure_size: None,
+                    attempt: None,
+                },
+            )?;
+        }
+        connection.execute("DELETE FROM commits WHERE hash = 'a'", [])?;
+
+        let history = load_history(&connection, repo, path, 10)?;
+        assert_eq!(
+            history
+                .iter()
+                .map(|build| (build.commit.as_str(), build.outcome, build.duration_secs))
+                .collect::<Vec<_>>(),
+            [
+                ("c", BuildOutcome::Cancelled, Some(7)),
+                ("b", BuildOutcome::Failed, Some(6)),
+                ("a", BuildOutcome::Success, Some(5)),
+            ]
+        );
+        assert_eq!(history[2].out_paths.as_deref(), Some("/nix/store/a-hello"));
+        assert_eq!(history[1].out_paths, None);
+        assert_eq!(load_history(&connection, repo, path, 1)?.len(), 1);
+
+        delete_old_builds(&connection, repo, 2, None)?;
+        assert_eq!(load_history(&connection, repo, path, 10)?.len(), 2);
+        delete_old_builds(&connection, repo, 0, Some(200))?;
+        assert_eq!(load_history(&connection, repo, path, 10)?.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn attempts_are_capped_and_restored() -> Result<(), Box<dyn std::error::Error>> {
+        let mut connection = Connection::open_in_memory()?;
+        connection.pragma_update(None, "foreign_keys", true)?;
+        migrate(&mut connection)?;
+        let (repo, hash, path) = (
+            "github.com/org/repo",
+            "abc123",
+            "packages.x86_64-linux.hello",
+        );
+        upsert_repo(&connection, repo, "repo")?;
+        upsert_commit(&connection, repo, hash, "Flaky", 0)?;
+        insert_packages(
+            &mut connection,
+            repo,
+            hash,
+            &[package(path, PackageBuildStatus::Idle)],
+        )?;
+        let (mut attempts, mut times) = (Vec::new(), BuildTimes::default());
+        let mut change = |status: PackageBuildStatus, now: i64| -> rusqlite::Result<()> {
+            times = times.update(&status, now);
+            BuildAttempt::record(&mut attempts, &status, times, |number| {
+                format!("{}.{}.log", path, number)
+            });
+            update_status(
+                &mut connection,
+                repo,
+                hash,
+                path,
+                StatusChange {
+                    status: &status,
+                    times,
+                    closure_size: None,
+                    attempt: attempts.last(),
+                },
+            )
+        };
+        for now in 0..12 {
+            change(PackageBuildStatus::Building, 10 * now)?;
+            change(
+                PackageBuildStatus::Failed("flaky".to_string()),
+                10 * now + 5,
+            )?;
+        }
+        // cancelled while it waited for a slot, nix did not run
+        change(PackageBuildStatus::WaitingForBuild, 200)?;
+        change(PackageBuildStatus::Cancelled, 201)?;
+
+        assert_eq!(attempts.len(), MAX_ATTEMPTS);
+        assert_eq!(
+            attempts
+                .iter()
+                .map(|attempt| attempt.number)
+                .collect::<Vec<_>>(),
+            (4..=13).collect::<Vec<_>>()
+        );
+        let last = attempts.last().cloned();
+        assert_eq!(
+            last.as_ref().map(|attempt| &attempt.status),
+            Some(&PackageBuildStatus::Cancelled)
+        );
+        assert_eq!(last.and_then(|attempt| attempt.log_ref), None);
+        assert_eq!(
+            attempts[0].log_ref.as_deref(),
+            Some("packages.x86_64-linux.hello.4.log")
+        );
+        assert_eq!(load_attempts(&connection, repo, hash, path)?, attempts);
+        Ok(())
+    }
+
+    #[test]
+    fn exports_import_unchanged() -> Result<(), Box<dyn std::error::Error>> {
+        let open = || -> Result<Connection, Box<dyn std::error::Error>> {
+            let mut connection = Connection::open_in_memory()?;
+            connection.pragma_update(None, "foreign_keys", true)?;
+            migrate(&mut connection)?;
+            Ok(connection)
+        };
+        let mut exporting = open()?;
+        let (repo, path) = ("github.com/org/repo", "packages.x86_64-linux.hello");
+        upsert_repo(&exporting, repo, "repo")?;
+        upsert_commit(&exporting, repo, "abc123", "Initial commit", 100)?;
+        upsert_commit(&exporting, repo, "def456", "Not evaluated yet", 200)?;
+        insert_packages(
+            &mut exporting,
+            repo,
+            "abc123",
+            &[
+                package(path, PackageBuildStatus::Idle),
+                package("packages.x86_64-linux.default", PackageBuildStatus::Idle),
+            ],
+        )?;
+        let (mut attempts, mut times) = (Vec::new(), BuildTimes::default());
+        for (status, now) in [
+            (PackageBuildStatus::Building, 10),
+            (PackageBuildStatus::Failed("flaky".to_string()), 20),
+            (PackageBuildStatus::Building, 30),
+            (
+                PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+                45,
+            ),
+        ] {
+            times = times.update(&status, now);
+            BuildAttempt::record(&mut attempts, &status, times, |number| {
+                format!("hello.{}.log", number)
+            });
+            update_status(
+                &mut exporting,
+                repo,
+                "abc123",
+                path,
+                StatusChange {
+                    status: &status,
+                    times,
+                    closure_size: Some(42),
+                    attempt: attempts.last(),
+                },
+            )?;
+        }
+
+        let exported = read_dump(&exporting, repo)?.ok_or("repo not exported")?;
+        assert_eq!(exported.commits.len(), 2);
+        assert_eq!(exported.commits[1].packages, None);
+        assert_eq!(exported.builds.len(), 2);
+        // as it travels
+        let exported: DumpRepo = serde_json::from_str(&serde_json::to_string(&exported)?)?;
+
+        let mut importing = open()?;
+        write_dump(&mut importing, &exported)?;
+        assert_eq!(read_dump(&importing, repo)?, Some(exported.clone()));
+        for hash in ["abc123", "def456"] {
+            assert_eq!(
+                load_packages(&importing, repo, hash)?,
+                load_packages(&exporting, repo, hash)?
+            );
+        }
+        assert_eq!(
+            load_history(&importing, repo, path, 10)?,
+            load_history(&exporting, repo, path, 10)?
+        );
+
+        // from a server without the database, the attempts bring the history
+        let without_history = DumpRepo {
+            builds: Vec::new(),
+            ..exported.clone()
+        };
+        let mut importing = open()?;
+        write_dump(&mut importing, &without_history)?;
+        assert_eq!(read_dump(&importing, repo)?, Some(exported));
+        Ok(())
+    }
+
+    #[test]
+    fn statuses_round_trip() {
+        for status in [
+            PackageBuildStatus::Idle,
+            PackageBuildStatus::UnsupportedArchitecture("aarch64-darwin"),
+            PackageBuildStatus::Building,
+            PackageBuildStatus::Success("/nix/store/a /nix/store/b".to_string()),
+            PackageBuildStatus::Failed("error: builder failed".to_string()),
+            PackageBuildStatus::Cancelled,
+            PackageBuildStatus::DryRun {
+                to_build: 3,
+                to_substitute: 7,
+            },
+        ] {
+            let (name, detail) = encode(&status);
+            assert_eq!(decode(name, detail), Some(status));
+        }
+        assert_eq!(decode("exploded", None), None);
+    }
+}
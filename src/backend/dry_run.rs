@@ -0,0 +1,68 @@
+//! `dry_run`: packages are evaluated and `nix build --dry-run` only reports what a
+//! build would do, they end up `DryRun` with the counts instead of built
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set from the config at startup and on reload, or by `build-once --dry-run`
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+pub fn set(enabled: bool) {
+    if ENABLED.swap(enabled, Ordering::Relaxed) != enabled {
+        match enabled {
+            true => tracing::info!("Dry run, builds only report what they would do"),
+            false => tracing::info!("No dry run anymore, builds run for real"),
+        }
+    }
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Derivations to build and paths to fetch from the stderr of `nix build --dry-run`,
+/// the store paths below `... will be built:` and `... will be fetched ...:`
+pub fn count(stderr: &str) -> (usize, usize) {
+    let (mut to_build, mut to_substitute) = (0, 0);
+    let mut counting = None;
+    for line in stderr.lines() {
+        if line.starts_with("  /") {
+            match counting {
+                Some(true) => to_build += 1,
+                Some(false) => to_substitute += 1,
+                None => {}
+            }
+        } else if line.contains(" will be built") {
+            counting = Some(true);
+        } else if line.contains(" will be fetched") {
+            counting = Some(false);
+        } else {
+            counting = None;
+        }
+    }
+    (to_build, to_substitute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_what_would_be_built_and_fetched() {
+        let stderr = "\
+these 2 derivations will be built:
+  /nix/store/0000000000000000000000000000000a-hello-2.12.drv
+  /nix/store/0000000000000000000000000000000b-hello-wrapped.drv
+these 3 paths will be fetched (1.2 MiB download, 5.0 MiB unpacked):
+  /nix/store/0000000000000000000000000000000c-glibc-2.40
+  /nix/store/0000000000000000000000000000000d-bash-5.2
+  /nix/store/0000000000000000000000000000000e-libidn2-2.3.7
+warning: Git tree '/srv/repo' is dirty
+";
+        assert_eq!(count(stderr), (2, 3));
+        assert_eq!(
+            count("this path will be fetched (0.1 MiB download):\n  /nix/store/x-a\n"),
+            (0, 1)
+        );
+        assert_eq!(count(""), (0, 0));
+    }
+}
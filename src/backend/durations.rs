@@ -0,0 +1,268 @@
+//! How long the successful builds of each package took, the latest
+//! [`MAX_SAMPLES`] of every attribute across its commits, so queued and running
+//! builds show how long they usually take. Kept with the build state of the
+//! `sqlite` and `json` backends.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Mutex, PoisonError},
+};
+
+use actix_web::{HttpResponse, get, post, web};
+use serde::{Deserialize, Serialize};
+
+use crate::package::{BuildTimes, PackageBuildStatus};
+
+use super::{BuildRepos, database};
+
+/// Builds per attribute the statistics are taken over
+const MAX_SAMPLES: usize = 50;
+
+/// An attribute of a repo, the same on every commit
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DurationKey {
+    pub repo: String,
+    /// the attribute path with its architecture as `*`, as
+    /// [`crate::package::Package::get_no_arch_name`]
+    pub name: String,
+    /// empty for NixOS configurations
+    pub arch: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Samples {
+    /// successful builds recorded, also those no longer in `recent`
+    pub count: u64,
+    /// seconds of the latest builds, oldest first
+    pub recent: VecDeque<u64>,
+}
+
+impl Samples {
+    fn push(&mut self, secs: u64) {
+        self.count += 1;
+        self.recent.push_back(secs);
+        let dropped = self.recent.len().saturating_sub(MAX_SAMPLES);
+        self.recent.drain(..dropped);
+    }
+
+    fn mean(&self) -> Option<u64> {
+        let samples = self.recent.len() as u64;
+        (samples > 0).then(|| self.recent.iter().sum::<u64>() / samples)
+    }
+
+    /// Nearest rank, the smallest duration at least `percent` of the builds did not
+    /// exceed
+    fn percentile(&self, percent: u64) -> Option<u64> {
+        let mut sorted = self.recent.iter().copied().collect::<Vec<_>>();
+        sorted.sort_unstable();
+        let rank = (sorted.len() as u64 * percent).div_ceil(100).max(1);
+        sorted.get(rank as usize - 1).copied()
+    }
+}
+
+/// The samples of an attribute as the `json` backend keeps them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedSamples {
+    #[serde(flatten)]
+    pub key: DurationKey,
+    #[serde(flatten)]
+    pub samples: Samples,
+}
+
+/// Entry of `GET /api/v1/stats/durations`
+#[derive(Serialize, Debug, PartialEq)]
+struct DurationStats {
+    #[serde(flatten)]
+    key: DurationKey,
+    count: u64,
+    mean_secs: u64,
+    p50_secs: u64,
+    p95_secs: u64,
+}
+
+static DURATIONS: Mutex<BTreeMap<DurationKey, Samples>> = Mutex::new(BTreeMap::new());
+
+/// Take over the samples kept by the state backend at startup
+pub fn load(saved: impl IntoIterator<Item = SavedSamples>) {
+    let mut durations = DURATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+    for SavedSamples { key, samples } in saved {
+        durations.insert(key, samples);
+    }
+}
+
+/// All samples, for the snapshot of the `json` backend
+pub fn saved() -> Vec<SavedSamples> {
+    DURATIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .map(|(key, samples)| SavedSamples {
+            key: key.clone(),
+            samples: samples.clone(),
+        })
+        .collect()
+}
+
+/// Add the build of `key` if it changed to a successful `status` with `times`
+pub fn record(key: DurationKey, status: &PackageBuildStatus, times: BuildTimes) {
+    let PackageBuildStatus::Success(_) = status else {
+        return;
+    };
+    let (Some(started), Some(finished)) = (times.started, times.finished) else {
+        return;
+    };
+    let secs = (finished - started).max(0) as u64;
+    let samples = {
+        let mut durations = DURATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        let samples = durations.entry(key.clone()).or_default();
+        samples.push(secs);
+        samples.clone()
+    };
+    database::record_durations(&key, &samples);
+}
+
+/// How long a successful build of `key` usually takes, the median, `None` before
+/// the first one
+pub fn expected(key: &DurationKey) -> Option<u64> {
+    DURATIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(key)?
+        .percentile(50)
+}
+
+/// `expected` while a build with `status` waits or runs, `None` otherwise
+pub fn expected_while_pending(key: &DurationKey, status: &PackageBuildStatus) -> Option<u64> {
+    match status {
+        PackageBuildStatus::WaitingForWindow
+        | PackageBuildStatus::WaitingForBuild
+        | PackageBuildStatus::Building => expected(key),
+        _ => None,
+    }
+}
+
+fn stats(key: &DurationKey, samples: &Samples) -> Option<DurationStats> {
+    Some(DurationStats {
+        key: key.clone(),
+        count: samples.count,
+        mean_secs: samples.mean()?,
+        p50_secs: samples.percentile(50)?,
+        p95_secs: samples.percentile(95)?,
+    })
+}
+
+#[get("/stats/durations")]
+async fn duration_stats() -> HttpResponse {
+    let stats = DURATIONS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .iter()
+        .filter_map(|(key, samples)| stats(key, samples))
+        .collect::<Vec<_>>();
+    HttpResponse::Ok().json(stats)
+}
+
+#[derive(Deserialize, Debug)]
+struct ResetQuery {
+    /// name of the repo, all repos without
+    repo: Option<String>,
+    /// as [`DurationKey::name`], all attributes without
+    name: Option<String>,
+    arch: Option<String>,
+}
+
+/// Forget the samples matching the query, e.g. after a change made a package much
+/// faster or slower to build. Answers how many attributes were reset.
+#[post("/stats/durations/reset")]
+async fn reset_durations(
+    query: web::Query<ResetQuery>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    tracing::debug!("Requested a reset of the build durations {:?}", query);
+    let repo = match &query.repo {
+        Some(name) => match repos.find(name) {
+            Some(repo) => Some(repo.repo.url.clone()),
+            None => return HttpResponse::NotFound().body("404 Not Found"),
+        },
+        None => None,
+    };
+    let matches = |key: &DurationKey| {
+        repo.as_ref().is_none_or(|repo| key.repo == *repo)
+            && query.name.as_ref().is_none_or(|name| key.name == *name)
+            && query.arch.as_ref().is_none_or(|arch| key.arch == *arch)
+    };
+    let reset = {
+        let mut durations = DURATIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        let reset = durations
+            .keys()
+            .filter(|key| matches(key))
+            .cloned()
+            .collect::<Vec<_>>();
+        for key in &reset {
+            durations.remove(key);
+        }
+        reset
+    };
+    database::forget_durations(&reset);
+    tracing::info!("Reset the build durations of {} attributes", reset.len());
+    HttpResponse::Ok().json(reset.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_cover_the_latest_builds() {
+        let mut samples = Samples::default();
+        assert_eq!(samples.mean(), None);
+        assert_eq!(samples.percentile(50), None);
+        for secs in 1..=100 {
+            samples.push(secs);
+        }
+        assert_eq!(samples.count, 100);
+        // the first 50 dropped out
+        assert_eq!(samples.recent.len(), MAX_SAMPLES);
+        assert_eq!(samples.mean(), Some(75));
+        assert_eq!(samples.percentile(50), Some(75));
+        assert_eq!(samples.percentile(95), Some(98));
+
+        let mut single = Samples::default();
+        single.push(480);
+        assert_eq!(single.percentile(50), Some(480));
+        assert_eq!(single.percentile(95), Some(480));
+    }
+
+    #[test]
+    fn only_successful_builds_are_recorded() {
+        let key = DurationKey {
+            repo: "github.com/org/durations".to_string(),
+            name: "packages.*.default".to_string(),
+            arch: "x86_64-linux".to_string(),
+        };
+        let times = BuildTimes {
+            started: Some(100),
+            finished: Some(400),
+        };
+        record(
+            key.clone(),
+            &PackageBuildStatus::Failed(String::new()),
+            times,
+        );
+        assert_eq!(expected(&key), None);
+        record(
+            key.clone(),
+            &PackageBuildStatus::Success(String::new()),
+            times,
+        );
+        assert_eq!(expected(&key), Some(300));
+        assert_eq!(
+            expected_while_pending(&key, &PackageBuildStatus::WaitingForBuild),
+            Some(300)
+        );
+        assert_eq!(
+            expected_while_pending(&key, &PackageBuildStatus::Success(String::new())),
+            None
+        );
+    }
+}
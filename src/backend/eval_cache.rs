@@ -0,0 +1,185 @@
+//! The output of `nix flake show` of each commit in `eval` below `paths.state_dir`.
+//! The flake of a commit never changes, so it is evaluated once, also across
+//! restarts, unless a poll is requested with `?force_eval=true`. Entries unused for
+//! `max_eval_cache_age_days` and the least recently used beyond
+//! `max_eval_cache_bytes` are deleted.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use ring::digest;
+
+use crate::AutoBuildOptions;
+
+use super::events;
+
+const DIR_NAME: &str = "eval";
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+struct Entry {
+    path: PathBuf,
+    /// last read or written, in seconds since the epoch
+    used: i64,
+    size: u64,
+}
+
+fn dir(settings: &AutoBuildOptions) -> PathBuf {
+    settings.state_dir().join(DIR_NAME)
+}
+
+/// The file of `flake_url`, which holds the revision and any other parameter
+fn entry_path(dir: &Path, flake_url: &str) -> PathBuf {
+    let hash = digest::digest(&digest::SHA256, flake_url.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<String>();
+    dir.join(format!("{}.json", hash))
+}
+
+/// The cached output for `flake_url`, `None` if there is none or `eval_cache` is off
+pub fn get(settings: &AutoBuildOptions, flake_url: &str) -> Option<Vec<u8>> {
+    if !settings.eval_cache {
+        return None;
+    }
+    let path = entry_path(&dir(settings), flake_url);
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::error!("reading the cached evaluation {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    // the least recently used are evicted first
+    if let Ok(file) = std::fs::File::options().write(true).open(&path) {
+        let _ = file.set_modified(SystemTime::now());
+    }
+    Some(content)
+}
+
+/// Keep `output` of `flake_url` and evict the entries beyond the limits
+pub fn put(settings: &AutoBuildOptions, flake_url: &str, output: &[u8]) {
+    if !settings.eval_cache {
+        return;
+    }
+    let dir = dir(settings);
+    let path = entry_path(&dir, flake_url);
+    // through a temporary file, a half written entry would be taken as the output
+    let temporary = path.with_extension("json.partial");
+    let written = std::fs::create_dir_all(&dir)
+        .and_then(|()| std::fs::write(&temporary, output))
+        .and_then(|()| std::fs::rename(&temporary, &path));
+    if let Err(e) = written {
+        tracing::error!("caching the evaluation {}: {}", path.display(), e);
+        let _ = std::fs::remove_file(&temporary);
+        return;
+    }
+    evict(settings, events::unix_now());
+}
+
+/// The entries to delete: those last used before `oldest`, then the least recently
+/// used of the others until the rest fits into `max_bytes`. `None` and 0 for no limit.
+fn to_evict(mut entries: Vec<Entry>, max_bytes: u64, oldest: Option<i64>) -> Vec<PathBuf> {
+    entries.sort_by_key(|entry| entry.used);
+    let mut total = entries.iter().map(|entry| entry.size).sum::<u64>();
+    entries
+        .into_iter()
+        .filter(|entry| {
+            let evicted = oldest.is_some_and(|oldest| entry.used < oldest)
+                || (max_bytes > 0 && total > max_bytes);
+            if evicted {
+                total -= entry.size;
+            }
+            evicted
+        })
+        .map(|entry| entry.path)
+        .collect()
+}
+
+fn evict(settings: &AutoBuildOptions, now: i64) {
+    let oldest = match settings.max_eval_cache_age_days {
+        0 => None,
+        days => Some(now - days as i64 * SECS_PER_DAY),
+    };
+    let entries = std::fs::read_dir(dir(settings))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| {
+            let metadata = entry.metadata().ok()?;
+            let used = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs() as i64);
+            Some(Entry {
+                path: entry.path(),
+                used,
+                size: metadata.len(),
+            })
+        })
+        .collect();
+    for path in to_evict(entries, settings.max_eval_cache_bytes, oldest) {
+        match std::fs::remove_file(&path) {
+            Ok(()) => tracing::debug!("Evicted the cached evaluation {}", path.display()),
+            Err(e) => tracing::error!("evicting the cached evaluation {}: {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_kept_per_flake_url() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_eval_{}", std::process::id()));
+        let mut settings: AutoBuildOptions = serde_json::from_value(serde_json::json!({
+            "dir": dir,
+            "repos": [],
+        }))?;
+        let url = "git+https://github.com/org/repo?rev=abc123";
+        assert_eq!(get(&settings, url), None);
+        put(&settings, url, b"{}");
+        assert_eq!(get(&settings, url).as_deref(), Some(&b"{}"[..]));
+        assert_eq!(get(&settings, &format!("{}&dir=sub", url)), None);
+
+        settings.eval_cache = false;
+        assert_eq!(get(&settings, url), None);
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn evicts_the_least_recently_used() {
+        let entries = || {
+            [
+                ("a", 100, 10),
+                ("b", 400, 20),
+                ("c", 300, 30),
+                ("d", 200, 40),
+            ]
+            .map(|(name, used, size)| Entry {
+                path: PathBuf::from(name),
+                used,
+                size,
+            })
+            .into_iter()
+            .collect::<Vec<_>>()
+        };
+        let names = |paths: Vec<PathBuf>| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(to_evict(entries(), 0, Some(250))), ["a", "d"]);
+        assert_eq!(names(to_evict(entries(), 60, None)), ["a", "d"]);
+        assert!(to_evict(entries(), 0, None).is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{
+    collections::VecDeque,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio::sync::broadcast;
+
+use crate::event::StateEvent;
+
+/// How many events a slow subscriber may fall behind before it has to resync
+const CHANNEL_CAPACITY: usize = 1024;
+/// How many past events are kept for clients resuming a stream
+const HISTORY_CAPACITY: usize = 256;
+
+/// An event with a monotonically increasing id, starting at 1. The id of the last
+/// published event doubles as the version of the whole state.
+#[derive(Debug, Clone)]
+pub struct Sequenced {
+    pub id: u64,
+    pub unix_secs: i64,
+    pub event: StateEvent,
+}
+
+struct History {
+    last_id: u64,
+    events: VecDeque<Sequenced>,
+}
+
+static EVENTS: LazyLock<broadcast::Sender<Sequenced>> =
+    LazyLock::new(|| broadcast::channel(CHANNEL_CAPACITY).0);
+
+static HISTORY: Mutex<History> = Mutex::new(History {
+    last_id: 0,
+    events: VecDeque::new(),
+});
+
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_secs() as i64)
+}
+
+/// Notify all live subscribers, events are dropped when nobody is listening
+pub fn publish(event: StateEvent) {
+    // hold the lock while sending so ids reach subscribers in order
+    let mut history = HISTORY.lock().unwrap_or_else(|p| p.into_inner());
+    history.last_id += 1;
+    let event = Sequenced {
+        id: history.last_id,
+        unix_secs: unix_now(),
+        event,
+    };
+    if history.events.len() == HISTORY_CAPACITY {
+        history.events.pop_front();
+    }
+    history.events.push_back(event.clone());
+    let _ = EVENTS.send(event);
+}
+
+pub fn subscribe() -> broadcast::Receiver<Sequenced> {
+    EVENTS.subscribe()
+}
+
+/// Subscribe and return the buffered events published after `last_id`, without
+/// gaps or duplicates between the two
+pub fn subscribe_since(last_id: u64) -> (Vec<Sequenced>, broadcast::Receiver<Sequenced>) {
+    let history = HISTORY.lock().unwrap_or_else(|p| p.into_inner());
+    let missed = history
+        .events
+        .iter()
+        .filter(|event| event.id > last_id)
+        .cloned()
+        .collect();
+    (missed, EVENTS.subscribe())
+}
+
+/// Id of the last published event
+pub fn version() -> u64 {
+    HISTORY.lock().unwrap_or_else(|p| p.into_inner()).last_id
+}
+
+/// The current version and the events published after `version`, `None` if some of
+/// them already dropped out of the history
+pub fn since_version(version: u64) -> Option<(u64, Vec<Sequenced>)> {
+    let history = HISTORY.lock().unwrap_or_else(|p| p.into_inner());
+    history.since(version)
+}
+
+/// Like [`since_version`] but for events published after a unix timestamp
+pub fn since_time(unix_secs: i64) -> Option<(u64, Vec<Sequenced>)> {
+    let history = HISTORY.lock().unwrap_or_else(|p| p.into_inner());
+    let version = match history
+        .events
+        .iter()
+        .position(|event| event.unix_secs > unix_secs)
+    {
+        // dropped events may be just as new
+        Some(0) if history.events[0].id > 1 => return None,
+        Some(index) => history.events[index].id - 1,
+        None => history.last_id,
+    };
+    history.since(version)
+}
+
+impl History {
+    fn since(&self, version: u64) -> Option<(u64, Vec<Sequenced>)> {
+        let oldest = self
+            .events
+            .front()
+            .map_or(self.last_id + 1, |event| event.id);
+        // events before `oldest` are gone, so `oldest` itself must be the first one missed
+        if version > self.last_id || version + 1 < oldest {
+            return None;
+        }
+        let events = self
+            .events
+            .iter()
+            .filter(|event| event.id > version)
+            .cloned()
+            .collect();
+        Some((self.last_id, events))
+    }
+}
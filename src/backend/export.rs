@@ -0,0 +1,463 @@
+//! `GET /api/v1/export` and `import`: all kept build state as one versioned JSON
+//! document, to move an instance to another host or keep a backup. It holds the
+//! repos, commits, package statuses, attempts, the build history, the build
+//! durations, the builders the attempts name and the months of the archive, but
+//! neither secrets, which are never part of the state, nor the build logs or the
+//! archived commits.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::ExitCode,
+    sync::PoisonError,
+};
+
+use actix_web::{
+    HttpResponse, get,
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Bytes},
+};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use futures_util::{
+    Stream, StreamExt,
+    future::ready,
+    stream::{self, LocalBoxStream},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AutoBuildOptions, archive::ArchiveMonth, builder::BuilderInfo, commit::CommitBuildStatus,
+};
+
+use super::{
+    BuildRepos, archive, builder_info, config_file, database,
+    database::StoredPackage,
+    durations::{self, SavedSamples},
+    events, state_file,
+};
+
+/// Of the layout below, an import of another version is refused
+pub const VERSION: u32 = 1;
+
+/// Start of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct DumpRepo {
+    pub(super) url: String,
+    pub(super) name: String,
+    pub(super) commits: Vec<DumpCommit>,
+    /// finished builds across the commits, the package history
+    pub(super) builds: Vec<DumpBuild>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct DumpCommit {
+    pub(super) hash: String,
+    pub(super) message: String,
+    pub(super) unix_secs: i64,
+    /// `None` if the flake was not evaluated yet
+    pub(super) packages: Option<Vec<StoredPackage>>,
+}
+
+/// A row of the build history, `outcome` and `detail` as the database keeps them
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(super) struct DumpBuild {
+    pub(super) path: String,
+    pub(super) hash: String,
+    pub(super) started: Option<i64>,
+    pub(super) finished: i64,
+    pub(super) outcome: String,
+    pub(super) out_paths: Option<String>,
+    pub(super) attempt: Option<usize>,
+    pub(super) detail: Option<String>,
+    pub(super) log_ref: Option<String>,
+    /// not in exports from before the builders were recorded
+    #[serde(default)]
+    pub(super) builder: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub(super) struct Dump {
+    pub(super) repos: Vec<DumpRepo>,
+    /// not in exports from before the build durations
+    #[serde(default)]
+    pub(super) durations: Vec<SavedSamples>,
+    /// and the builders the attempts name
+    #[serde(default)]
+    pub(super) builders: Vec<BuilderInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExportQuery {
+    /// a gzipped file instead of plain JSON
+    #[serde(default)]
+    gzip: bool,
+}
+
+/// The tracked repos as they are in memory, for the backends without a database.
+/// Commits that still wait for their evaluation have no packages.
+fn repos_in_memory(repos: &BuildRepos) -> Vec<DumpRepo> {
+    repos
+        .snapshot()
+        .0
+        .inner()
+        .iter()
+        .map(|repo| {
+            let commits = repo
+                .commits
+                .inner()
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .values()
+                .cloned()
+                .collect::<Vec<_>>();
+            DumpRepo {
+                url: repo.repo.url.clone(),
+                name: repo.repo.name(),
+                commits: commits
+                    .iter()
+                    .map(|commit| {
+                        let packages = commit.packages.get();
+                        let evaluated = matches!(commit.status.get(), CommitBuildStatus::Idle)
+                            && !packages.is_empty();
+                        DumpCommit {
+                            hash: commit.hash.clone(),
+                            message: commit.message.clone(),
+                            unix_secs: commit.unix_secs,
+                            packages: evaluated
+                                .then(|| packages.iter().map(StoredPackage::from).collect()),
+                        }
+                    })
+                    .collect(),
+                builds: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// The document in chunks, one per repo, each read from the database on a blocking
+/// thread only when it is sent. The months of the `archive` are listed, their
+/// commits are not part of it.
+async fn chunks(
+    archive: Vec<ArchiveMonth>,
+    repos: &BuildRepos,
+) -> impl Stream<Item = Vec<u8>> + use<> {
+    let start = serde_json::json!({
+        "version": VERSION,
+        "server_version": env!("CARGO_PKG_VERSION"),
+        "unix_secs": events::unix_now(),
+        "archive": archive,
+        "durations": durations::saved(),
+        "builders": builder_info::saved(),
+    })
+    .to_string();
+    // the object without its closing brace, the repos follow
+    let start = format!("{},\"repos\":[", start.trim_end_matches('}'));
+    let urls = web::block(database::repo_urls).await.ok().flatten();
+    let repos: LocalBoxStream<DumpRepo> = match urls {
+        Some(urls) => stream::iter(urls)
+            .filter_map(|url| async move {
+                web::block(move || database::dump_repo(&url))
+                    .await
+                    .ok()
+                    .flatten()
+            })
+            .boxed_local(),
+        None => stream::iter(repos_in_memory(repos)).boxed_local(),
+    };
+    stream::once(ready(start.into_bytes()))
+        .chain(repos.enumerate().map(|(index, repo)| {
+            let mut chunk = match index {
+                0 => Vec::new(),
+                _ => b",".to_vec(),
+            };
+            if let Err(e) = serde_json::to_writer(&mut chunk, &repo) {
+                tracing::error!("exporting {}: {}", repo.url, e);
+            }
+            chunk
+        }))
+        .chain(stream::once(ready(b"]}".to_vec())))
+}
+
+/// `chunks` as one gzip stream
+fn gzipped(chunks: impl Stream<Item = Vec<u8>>) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    let mut encoder = Some(GzEncoder::new(Vec::new(), Compression::default()));
+    chunks
+        .map(Some)
+        .chain(stream::once(ready(None)))
+        .map(move |chunk| {
+            let Some(bytes) = chunk else {
+                return encoder.take().map_or(Ok(Vec::new()), GzEncoder::finish);
+            };
+            let Some(encoder) = encoder.as_mut() else {
+                return Ok(Vec::new());
+            };
+            encoder.write_all(&bytes)?;
+            Ok(std::mem::take(encoder.get_mut()))
+        })
+}
+
+#[get("/export")]
+async fn export(
+    query: web::Query<ExportQuery>,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    tracing::debug!("Requested state export {:?}", query);
+    let attachment = |name: &str| ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(name.to_string())],
+    };
+    let archive = web::block(move || archive::index(&settings))
+        .await
+        .unwrap_or_default();
+    let body = chunks(archive, &repos).await;
+    if query.gzip {
+        let body = gzipped(body).map(|chunk| chunk.map(Bytes::from));
+        return HttpResponse::Ok()
+            .content_type("application/gzip")
+            // already compressed, kept away from the `Compress` middleware
+            .insert_header((header::CONTENT_ENCODING, "identity"))
+            .insert_header(attachment("nix_autobuild-state.json.gz"))
+            .streaming(body);
+    }
+    let body = body.map(|chunk| Ok::<_, std::io::Error>(Bytes::from(chunk)));
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .insert_header(attachment("nix_autobuild-state.json"))
+        .streaming(body)
+}
+
+/// The export in `content`, plain or gzipped
+fn parse(content: &[u8]) -> Result<Dump, Box<dyn std::error::Error>> {
+    let mut unpacked = Vec::new();
+    let content = match content.starts_with(&GZIP_MAGIC) {
+        true => {
+            GzDecoder::new(content)
+                .read_to_end(&mut unpacked)
+                .map_err(|e| format!("cannot unpack: {}", e))?;
+            unpacked.as_slice()
+        }
+        false => content,
+    };
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    // before the layout, which may be another one
+    let Version { version } = serde_json::from_slice(content)?;
+    if version != VERSION {
+        return Err(format!(
+            "the export is of version {}, this server reads version {}",
+            version, VERSION
+        )
+        .into());
+    }
+    Ok(serde_json::from_slice::<Dump>(content)?)
+}
+
+/// Load the export `file` into the state of the backend configured in
+/// `config_path`, which has to be empty unless `force`
+pub fn import(
+    config_path: &str,
+    file: &Path,
+    force: bool,
+) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let settings = config_file::read(config_path)?;
+    let content =
+        std::fs::read(file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+    let dump = parse(&content).map_err(|e| format!("{}: {}", file.display(), e))?;
+    let state_dir = settings.state_dir();
+    std::fs::create_dir_all(&state_dir)
+        .map_err(|e| format!("cannot create {}: {}", state_dir.display(), e))?;
+    let target = match settings.state.backend.as_str() {
+        "sqlite" => database::import(&settings, &dump, force)?,
+        "json" => state_file::import(&settings, &dump, force)?,
+        backend => {
+            return Err(format!("state.backend = \"{}\" keeps no state to import", backend).into());
+        }
+    };
+    let repos = &dump.repos;
+    eprintln!(
+        "Imported {} repos with {} commits and {} builds into {}",
+        repos.len(),
+        repos.iter().map(|repo| repo.commits.len()).sum::<usize>(),
+        repos.iter().map(|repo| repo.builds.len()).sum::<usize>(),
+        target.display()
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn exports_parse_plain_and_gzipped() -> Result<(), Box<dyn std::error::Error>> {
+        let repo = DumpRepo {
+            url: "github.com/org/repo".to_string(),
+            name: "repo".to_string(),
+            commits: vec![DumpCommit {
+                hash: "abc123".to_string(),
+                message: "Initial commit".to_string(),
+                unix_secs: 100,
+                packages: None,
+            }],
+            builds: Vec::new(),
+        };
+        let chunks = || {
+            [
+                format!("{{\"version\":{},\"repos\":[", VERSION).into_bytes(),
+                serde_json::to_vec(&repo).unwrap_or_default(),
+                b"]}".to_vec(),
+            ]
+            .into_iter()
+        };
+        let dump = parse(&chunks().collect::<Vec<_>>().concat())?;
+        assert_eq!(dump.repos, std::slice::from_ref(&repo));
+        assert!(dump.durations.is_empty() && dump.builders.is_empty());
+        let gzipped = gzipped(stream::iter(chunks()))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<std::io::Result<Vec<_>>>()?
+            .concat();
+        assert!(gzipped.starts_with(&GZIP_MAGIC));
+        assert_eq!(parse(&gzipped)?.repos, [repo]);
+
+        let newer =
+            serde_json::json!({ "version": VERSION + 1, "repos": [{ "layout": "changed" }] });
+        let error = parse(newer.to_string().as_bytes())
+            .err()
+            .map(|e| e.to_string());
+        assert_eq!(
+            error.as_deref(),
+            Some("the export is of version 2, this server reads version 1")
+        );
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn repos_read_the_same_after_an_import() -> Result<(), Box<dyn std::error::Error>> {
+        use std::sync::Arc;
+
+        use actix_web::{
+            App,
+            test::{TestRequest, call_service, init_service, read_body},
+        };
+
+        use super::super::{RepoInfoTrait, repos};
+        use crate::{
+            Repo, RepoList,
+            commit::CommitInfo,
+            package::{BuildAttempt, BuildTimes, PackageBuildStatus},
+            repo::RepoInfo,
+            serialize::{RwLockWrapper, VecArcWrapper},
+        };
+
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_export_{}", std::process::id()));
+        let settings = Arc::new(serde_json::from_value::<AutoBuildOptions>(
+            serde_json::json!({ "repos": [], "dir": dir }),
+        )?);
+        let tracked = |build_repos: &BuildRepos| -> Result<_, Box<dyn std::error::Error>> {
+            let repo = RepoInfo::new(
+                serde_json::from_value::<Repo>(serde_json::json!({
+                    "url": "github.com/org/repo",
+                    "branches": ["main"]
+                }))?,
+                dir.join("repo"),
+                settings.clone(),
+            );
+            build_repos.set(RepoList(VecArcWrapper::from(vec![repo.clone()])));
+            Ok(repo)
+        };
+        let exporting = BuildRepos::default();
+        let repo = tracked(&exporting)?;
+        let commit = Arc::new(CommitInfo {
+            flake_url: "git+https://github.com/org/repo?rev=abc123".to_string(),
+            hash: "abc123".to_string(),
+            message: "Initial commit".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(&repo)),
+            unix_secs: 100,
+            diff_stats: None,
+        });
+        let failed = PackageBuildStatus::Failed("error: builder failed".to_string());
+        let packages = [
+            (
+                "hello",
+                PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+            ),
+            ("broken", failed),
+        ]
+        .map(|(name, status)| {
+            StoredPackage {
+                path: format!("packages.x86_64-linux.{}", name),
+                nixos_config: false,
+                name: name.to_string(),
+                description: String::new(),
+                pkg_type: "derivation".to_string(),
+                arch: "x86_64-linux".to_string(),
+                status: status.clone(),
+                times: BuildTimes {
+                    started: Some(10),
+                    finished: Some(20),
+                },
+                closure_size: Some(42),
+                attempts: vec![BuildAttempt {
+                    number: 1,
+                    started: Some(10),
+                    finished: Some(20),
+                    status,
+                    log_ref: Some(format!("packages.x86_64-linux.{}.log", name)),
+                    builder: None,
+                }],
+            }
+            .into_package(&commit)
+        });
+        commit.packages.set(packages.to_vec());
+        repo.commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(commit.hash.clone(), commit);
+
+        let importing = BuildRepos::default();
+        let app = |build_repos: &BuildRepos| {
+            init_service(
+                App::new()
+                    .app_data(web::Data::from(settings.clone()))
+                    .app_data(web::Data::new(build_repos.clone()))
+                    .route("/api/v1/repos", web::get().to(repos))
+                    .service(export),
+            )
+        };
+        let get = TestRequest::get;
+        let exporting = app(&exporting).await;
+        let before =
+            read_body(call_service(&exporting, get().uri("/api/v1/repos").to_request()).await)
+                .await;
+        let exported =
+            read_body(call_service(&exporting, get().uri("/export").to_request()).await).await;
+        // the commits of a fresh server, the poller has not run yet
+        let repo = tracked(&importing)?;
+        std::fs::create_dir_all(settings.state_dir())?;
+        state_file::import(&settings, &parse(&exported)?, false)?;
+        let snapshot = state_file::load(&settings).ok_or("the import wrote no snapshot")?;
+        state_file::restore(&repo, &snapshot);
+        let importing = app(&importing).await;
+        let after =
+            read_body(call_service(&importing, get().uri("/api/v1/repos").to_request()).await)
+                .await;
+
+        assert!(String::from_utf8_lossy(&before).contains("error: builder failed"));
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&after)?,
+            serde_json::from_slice::<serde_json::Value>(&before)?
+        );
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
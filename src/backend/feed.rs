@@ -0,0 +1,250 @@
+use std::{fmt::Write, sync::Arc};
+
+use actix_web::{HttpRequest, HttpResponse, get, web};
+
+use crate::{
+    AutoBuildOptions,
+    commit::CommitInfo,
+    package::{AggregateStatus, PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+use super::{
+    BuildRepos,
+    notify::{dashboard_link, escape},
+};
+
+/// Feed readers only need the recent history
+const MAX_ENTRIES: usize = 50;
+
+#[get("/feed.atom")]
+async fn feed(
+    req: HttpRequest,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    tracing::debug!("Requested feed");
+    let repos = repos.snapshot();
+    atom_response(
+        &req,
+        &settings,
+        "nix_autobuild builds",
+        "urn:nix_autobuild:feed".to_string(),
+        repos.0.inner().iter().cloned(),
+    )
+}
+
+#[get("/repos/{name}/feed.atom")]
+async fn repo_feed(
+    req: HttpRequest,
+    name: web::Path<String>,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    tracing::debug!("Requested feed for {}", name);
+    let Some(repo) = repos.find(&name) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let title = format!("nix_autobuild builds of {}", repo.repo.url);
+    let id = format!("urn:nix_autobuild:feed:{}", repo.repo.url);
+    atom_response(&req, &settings, &title, id, std::iter::once(repo))
+}
+
+fn atom_response(
+    req: &HttpRequest,
+    settings: &AutoBuildOptions,
+    title: &str,
+    id: String,
+    repos: impl Iterator<Item = Arc<RepoInfo>>,
+) -> HttpResponse {
+    let connection = req.connection_info();
+    let dashboard = match &settings.notifications.dashboard_url {
+        Some(url) => url.clone(),
+        None => format!(
+            "{}://{}{}/",
+            connection.scheme(),
+            connection.host(),
+            settings.route_prefix()
+        ),
+    };
+    let self_url = format!(
+        "{}://{}{}",
+        connection.scheme(),
+        connection.host(),
+        req.path()
+    );
+    let mut commits = repos
+        .flat_map(|repo| {
+            let commits = repo.commits.inner().read().ok()?;
+            Some(commits.values().cloned().collect::<Vec<_>>())
+        })
+        .flatten()
+        .filter_map(|commit| settled_status(&commit).map(|status| (commit, status)))
+        .collect::<Vec<_>>();
+    commits.sort_by_key(|(commit, _)| std::cmp::Reverse(commit.unix_secs));
+    commits.truncate(MAX_ENTRIES);
+
+    let updated = commits
+        .first()
+        .map(|(commit, _)| commit.unix_secs)
+        .unwrap_or(0);
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+         <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+         <id>{}</id>\n<title>{}</title>\n<updated>{}</updated>\n\
+         <link rel=\"self\" href=\"{}\"/>\n<link href=\"{}\"/>\n\
+         <author><name>nix_autobuild</name></author>\n",
+        escape(&id),
+        escape(title),
+        rfc3339(updated),
+        escape(&self_url),
+        escape(&dashboard)
+    );
+    for (commit, status) in &commits {
+        entry(&mut xml, commit, *status, &dashboard);
+    }
+    xml.push_str("</feed>\n");
+
+    HttpResponse::Ok()
+        .content_type("application/atom+xml; charset=utf-8")
+        .body(xml)
+}
+
+/// Status of a commit whose packages all finished, `None` while some are still
+/// waiting or building
+fn settled_status(commit: &CommitInfo) -> Option<AggregateStatus> {
+    let statuses = commit
+        .packages
+        .get()
+        .iter()
+        .map(|pkg| pkg.status())
+        .collect::<Vec<_>>();
+    if !statuses.iter().all(|status| status.is_finished()) {
+        return None;
+    }
+    AggregateStatus::from_statuses(&statuses)
+}
+
+fn entry(xml: &mut String, commit: &CommitInfo, status: AggregateStatus, dashboard: &str) {
+    let Some(repo_info) = commit.repo() else {
+        return;
+    };
+    let repo = &repo_info.repo.url;
+    let summary = commit.message.lines().next().unwrap_or_default();
+    let short = commit.hash.get(..8).unwrap_or(&commit.hash);
+    let state = match status {
+        AggregateStatus::Success => "passing",
+        AggregateStatus::Failed => "failing",
+        AggregateStatus::Building => "building",
+    };
+    let branch = repo_info
+        .branch_commit_hashes
+        .iter()
+        .find(|(_, hashes)| hashes.get().contains(&commit.hash))
+        .map(|(branch, _)| branch.clone());
+
+    let mut content = format!(
+        "<p>{} <code>{}</code></p><pre>{}</pre><ul>",
+        escape(repo),
+        commit.hash,
+        escape(&commit.message)
+    );
+    for pkg in commit.packages.get() {
+        let arch = match &pkg {
+            PackageEnum::Derivation(pkg) => Some(pkg.0.arch),
+            PackageEnum::NixosConfig(_) => None,
+        };
+        let link = dashboard_link(
+            dashboard,
+            repo,
+            Some(pkg.path()),
+            arch,
+            branch.as_deref(),
+            &commit.hash,
+        );
+        let result = match pkg.status() {
+            PackageBuildStatus::Success(paths) => format!("success {}", paths),
+            PackageBuildStatus::Failed(_) => "failed".to_string(),
+            PackageBuildStatus::Cancelled => "cancelled".to_string(),
+            PackageBuildStatus::DryRun {
+                to_build,
+                to_substitute,
+            } => format!(
+                "dry run, {} to build and {} to fetch",
+                to_build, to_substitute
+            ),
+            PackageBuildStatus::UnsupportedArchitecture(_) => {
+                "unsupported architecture".to_string()
+            }
+            status => format!("{:?}", status),
+        };
+        let _ = write!(
+            content,
+            "<li><a href=\"{}\">{}</a>: {}</li>",
+            escape(&link),
+            escape(pkg.path()),
+            escape(&result)
+        );
+    }
+    content.push_str("</ul>");
+
+    let _ = write!(
+        xml,
+        "<entry>\n<id>urn:nix_autobuild:commit:{}:{}</id>\n\
+         <title>[{}] {} {}: {}</title>\n<updated>{}</updated>\n\
+         <link href=\"{}\"/>\n<content type=\"html\">{}</content>\n</entry>\n",
+        escape(repo),
+        commit.hash,
+        state,
+        escape(&repo_info.repo.name()),
+        short,
+        escape(summary),
+        rfc3339(commit.unix_secs),
+        escape(&dashboard_link(
+            dashboard,
+            repo,
+            None,
+            None,
+            None,
+            &commit.hash
+        )),
+        escape(&content)
+    );
+}
+
+/// UTC timestamp like `2024-05-01T12:00:00Z`
+fn rfc3339(unix_secs: i64) -> String {
+    let days = unix_secs.div_euclid(86_400);
+    let secs = unix_secs.rem_euclid(86_400);
+    // civil_from_days from Howard Hinnant's date algorithms
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_timestamps() {
+        assert_eq!(rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(rfc3339(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(rfc3339(1_714_565_045), "2024-05-01T12:04:05Z");
+    }
+}
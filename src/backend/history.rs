@@ -0,0 +1,34 @@
+//! The finished builds of a package across the commits of its repo, newest first,
+//! for the history next to its status. Kept in the `sqlite` state backend only and
+//! pruned with the commits by `max_commits_per_repo` and `max_commit_age_days`.
+
+use actix_web::{HttpResponse, get, web};
+use serde::Deserialize;
+
+use super::{BuildRepos, database};
+
+const DEFAULT_LIMIT: usize = 50;
+const MAX_LIMIT: usize = 500;
+
+#[derive(Deserialize, Debug)]
+struct HistoryQuery {
+    /// how many builds, at most [`MAX_LIMIT`]
+    limit: Option<usize>,
+}
+
+#[get("/repos/{name}/packages/{path}/history")]
+async fn package_history(
+    path: web::Path<(String, String)>,
+    query: web::Query<HistoryQuery>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (name, pkg_path) = path.into_inner();
+    tracing::debug!("Requested history of {} {} {:?}", name, pkg_path, query);
+    let Some(repo) = repos.find(&name) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+    // without the database there is no history
+    let history = database::history(&repo.repo.url, &pkg_path, limit).unwrap_or_default();
+    HttpResponse::Ok().json(history)
+}
@@ -0,0 +1,249 @@
+//! Deleting build logs beyond `max_log_age_days` and `max_total_log_bytes`, oldest
+//! first, so the logs of a long running server do not fill the disk. The attempts
+//! whose log is gone keep that it expired.
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::{Arc, PoisonError},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use super::{BuildRepos, PackageEnumTrait, database, events, logs, shutdown};
+use crate::{
+    AutoBuildOptions,
+    event::StateEvent,
+    package::{EXPIRED_LOG, PackageEnum},
+    repo::RepoInfo,
+};
+
+/// How often the logs are checked against the limits
+const CLEAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+struct LogFile {
+    path: PathBuf,
+    /// last written, in seconds since the epoch
+    modified: i64,
+    size: u64,
+}
+
+/// The `files` to delete: those last written before `oldest`, then the oldest of
+/// the others until the rest fits into `max_bytes`. `None` and 0 for no limit.
+fn to_delete(mut files: Vec<LogFile>, max_bytes: u64, oldest: Option<i64>) -> Vec<PathBuf> {
+    files.sort_by_key(|file| file.modified);
+    let mut total = files.iter().map(|file| file.size).sum::<u64>();
+    files
+        .into_iter()
+        .filter(|file| {
+            let expired = oldest.is_some_and(|oldest| file.modified < oldest)
+                || (max_bytes > 0 && total > max_bytes);
+            if expired {
+                total -= file.size;
+            }
+            expired
+        })
+        .map(|file| file.path)
+        .collect()
+}
+
+/// The logs in `logs_dir`, as `{repo}/{commit}/{log_ref}` plus `.gz` if compressed
+fn log_files(logs_dir: &Path) -> Vec<LogFile> {
+    let entries = |dir: &Path| {
+        std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect::<Vec<_>>()
+    };
+    let mut files = Vec::new();
+    for repo in entries(logs_dir) {
+        for commit in entries(&repo.path()) {
+            for log in entries(&commit.path()) {
+                let path = log.path();
+                let name = log.file_name().to_string_lossy().into_owned();
+                if !name.ends_with(".log") && !name.ends_with(".log.gz") {
+                    continue;
+                }
+                let Ok(metadata) = log.metadata() else {
+                    continue;
+                };
+                let modified = metadata
+                    .modified()
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map_or(0, |since| since.as_secs() as i64);
+                files.push(LogFile {
+                    path,
+                    modified,
+                    size: metadata.len(),
+                });
+            }
+        }
+    }
+    files
+}
+
+/// The logs of the builds that wait or run, which are never deleted
+fn in_use(repos: &[Arc<RepoInfo>]) -> HashSet<PathBuf> {
+    repos
+        .iter()
+        .flat_map(|repo| {
+            repo.commits
+                .inner()
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .values()
+                .flat_map(|commit| commit.packages.get())
+                .collect::<Vec<_>>()
+        })
+        .filter(|pkg| !pkg.status().is_finished())
+        .filter_map(|pkg| pkg.log_path())
+        .flat_map(|path| [logs::compressed_path(&path), path])
+        .collect()
+}
+
+/// Mark the attempts that logged to the deleted `path` as expired, in memory and
+/// in the database
+fn expire(repos: &[Arc<RepoInfo>], path: &Path) {
+    let name = |path: Option<&Path>| {
+        path.and_then(Path::file_name)
+            .map(|name| name.to_string_lossy().into_owned())
+    };
+    let (Some(log_ref), Some(hash), Some(repo_dir)) = (
+        name(Some(path)),
+        name(path.parent()),
+        name(path.parent().and_then(Path::parent)),
+    ) else {
+        return;
+    };
+    let log_ref = log_ref.strip_suffix(".gz").unwrap_or(&log_ref);
+    let Some(repo) = repos
+        .iter()
+        .find(|repo| name(Some(&repo.checkout_path)).as_ref() == Some(&repo_dir))
+    else {
+        return;
+    };
+    database::expire_log(&repo.repo.url, &hash, log_ref);
+    let commit = repo
+        .commits
+        .inner()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&hash)
+        .cloned();
+    let Some(commit) = commit else {
+        return;
+    };
+    for pkg in commit.packages.get() {
+        let stored = match &pkg {
+            PackageEnum::Derivation(pkg) => &pkg.0.attempts,
+            PackageEnum::NixosConfig(pkg) => &pkg.0.attempts,
+        };
+        let mut attempts = stored.get();
+        let mut changed = false;
+        for attempt in &mut attempts {
+            if attempt.log_ref.as_deref() == Some(log_ref) {
+                attempt.log_ref = Some(EXPIRED_LOG.to_string());
+                changed = true;
+            }
+        }
+        if !changed {
+            continue;
+        }
+        stored.set(attempts.clone());
+        events::publish(StateEvent::PackageStatus {
+            repo: repo.repo.url.clone(),
+            commit: hash.clone(),
+            path: pkg.path().to_string(),
+            status: pkg.status(),
+            times: pkg.times(),
+            closure_size: pkg.closure_size(),
+            expected_secs: pkg.expected_secs(),
+            attempts: Some(attempts),
+        });
+    }
+}
+
+/// Delete the logs beyond the limits of `settings`, returns how many
+pub fn clean(settings: &AutoBuildOptions, repos: &BuildRepos, now: i64) -> usize {
+    let oldest = match settings.max_log_age_days {
+        0 => None,
+        days => Some(now - days as i64 * SECS_PER_DAY),
+    };
+    let repos = repos.snapshot().0.inner().clone();
+    let in_use = in_use(&repos);
+    let files = log_files(&settings.logs_dir())
+        .into_iter()
+        .filter(|file| !in_use.contains(&file.path))
+        .collect();
+    let mut deleted = 0;
+    for path in to_delete(files, settings.max_total_log_bytes, oldest) {
+        if let Err(e) = std::fs::remove_file(&path) {
+            tracing::error!("deleting the build log {}: {}", path.display(), e);
+            continue;
+        }
+        deleted += 1;
+        expire(&repos, &path);
+        // the directory of the commit once its last log is gone
+        if let Some(commit_dir) = path.parent() {
+            let _ = std::fs::remove_dir(commit_dir);
+        }
+    }
+    if deleted > 0 {
+        tracing::info!("Deleted {} expired build logs", deleted);
+    }
+    deleted
+}
+
+/// Clean the logs at startup and every `CLEAN_INTERVAL` until the shutdown, if
+/// there is a limit
+pub fn start(settings: Arc<AutoBuildOptions>, repos: BuildRepos) {
+    if settings.max_log_age_days == 0 && settings.max_total_log_bytes == 0 {
+        return;
+    }
+    thread::spawn(move || {
+        while !shutdown::requested() {
+            clean(&settings, &repos, events::unix_now());
+            thread::sleep(CLEAN_INTERVAL);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<LogFile> {
+        [
+            ("a", 100, 10),
+            ("b", 400, 20),
+            ("c", 300, 30),
+            ("d", 200, 40),
+        ]
+        .map(|(name, modified, size)| LogFile {
+            path: PathBuf::from(name),
+            modified,
+            size,
+        })
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn deletes_the_oldest_beyond_the_limits() {
+        let names = |paths: Vec<PathBuf>| {
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(to_delete(files(), 0, Some(250))), ["a", "d"]);
+        // 100 bytes in all, 50 fit
+        assert_eq!(names(to_delete(files(), 50, None)), ["a", "d"]);
+        assert_eq!(names(to_delete(files(), 20, Some(150))), ["a", "d", "c"]);
+        assert!(to_delete(files(), 0, None).is_empty());
+        assert!(to_delete(files(), 100, Some(50)).is_empty());
+    }
+}
@@ -0,0 +1,153 @@
+//! Logs of the server as `tracing` events, written as text or JSON lines. The
+//! access log is a second layer of the same subscriber with its own level and file.
+
+use std::{fs::OpenOptions, path::Path, sync::Mutex};
+
+use tracing::{Subscriber, level_filters::LevelFilter};
+use tracing_subscriber::{
+    Layer, filter::Targets, fmt::writer::BoxMakeWriter, layer::SubscriberExt, registry::LookupSpan,
+    util::SubscriberInitExt,
+};
+
+use super::{access_log, config_file};
+use crate::{AccessLogOptions, AutoBuildOptions, LogOptions};
+
+pub const FORMATS: [&str; 2] = ["text", "json"];
+/// Overrides `log.level`, e.g. `debug` or `info,nix_autobuild::backend::reload=trace`
+const ENV_FILTER: &str = "RUST_LOG";
+
+pub fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.parse().map_err(|_| {
+        format!(
+            "invalid log level `{}`, use off, error, warn, info, debug or trace",
+            level
+        )
+    })
+}
+
+/// `log.level` for all targets, or the directives of `RUST_LOG` if it is set
+fn filter(options: &LogOptions) -> Result<Targets, String> {
+    match std::env::var(ENV_FILTER) {
+        Ok(directives) if !directives.trim().is_empty() => directives
+            .parse()
+            .map_err(|e| format!("invalid {} `{}`: {}", ENV_FILTER, directives, e)),
+        _ => Ok(Targets::new().with_default(parse_level(&options.level)?)),
+    }
+}
+
+/// Appends to `file`, or writes to stdout without one
+pub fn writer(file: Option<&Path>) -> Result<BoxMakeWriter, String> {
+    Ok(match file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("opening log {}: {}", path.display(), e))?;
+            BoxMakeWriter::new(Mutex::new(file))
+        }
+        None => BoxMakeWriter::new(std::io::stdout),
+    })
+}
+
+/// Formatting layer of `format`, one of `FORMATS`. Only JSON names the target,
+/// lines of text stay short.
+pub fn layer<S>(format: &str, writer: BoxMakeWriter) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    match format {
+        "json" => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+        _ => tracing_subscriber::fmt::layer()
+            .with_writer(writer)
+            .with_ansi(false)
+            .with_target(false)
+            .boxed(),
+    }
+}
+
+/// The settings of `config_path`, with the logs set up as they ask before logging
+/// anything about them
+pub fn init_from_config(config_path: &str) -> Result<AutoBuildOptions, Box<dyn std::error::Error>> {
+    let settings = config_file::read(config_path)?;
+    init(&settings.log, &settings.access_log)?;
+    config_file::log_loaded(config_path);
+    Ok(settings)
+}
+
+/// Install the subscriber of the server logs and the access log, before anything
+/// is logged
+pub fn init(
+    options: &LogOptions,
+    access_log: &AccessLogOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if !FORMATS.contains(&options.format.as_str()) {
+        return Err(format!("invalid log format `{}`", options.format).into());
+    }
+    let filter = filter(options)?.with_target(access_log::TARGET, LevelFilter::OFF);
+    tracing_subscriber::registry()
+        .with(layer(&options.format, writer(options.file.as_deref())?).with_filter(filter))
+        .with(access_log::layer(access_log, options)?)
+        .try_init()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        io::Write,
+        sync::{Arc, PoisonError},
+    };
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn json_lines_carry_level_and_message() -> Result<(), Box<dyn std::error::Error>> {
+        let buffer = Buffer::default();
+        let writer = BoxMakeWriter::new({
+            let buffer = buffer.clone();
+            move || buffer.clone()
+        });
+        let filter = Targets::new().with_default(parse_level("info")?);
+        let subscriber =
+            tracing_subscriber::registry().with(layer("json", writer).with_filter(filter));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::debug!("Requested badge");
+            tracing::info!("Built {}", "hello");
+        });
+
+        let output = String::from_utf8(
+            buffer
+                .0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        )?;
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1);
+        let line: serde_json::Value = serde_json::from_str(lines[0])?;
+        assert_eq!(line["level"], "INFO");
+        assert_eq!(line["fields"]["message"], "Built hello");
+        assert!(parse_level("loud").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,348 @@
+use std::{
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use actix_web::{
+    HttpRequest, HttpResponse, get,
+    http::header,
+    mime,
+    rt::time::sleep,
+    web::{self, Bytes},
+};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use futures_util::stream;
+use serde::Deserialize;
+
+use crate::{
+    commit::CommitInfo,
+    package::{BuildAttempt, PackageEnum},
+};
+
+use super::{BuildRepos, PackageEnumTrait};
+
+/// How often a followed log is checked for new output
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Where the build log `log_ref` of a package of `commit` is written, `None` once
+/// its repo was removed
+pub fn log_path(commit: &CommitInfo, log_ref: &str) -> Option<PathBuf> {
+    let repo = commit.repo()?;
+    let repo_dir = repo.checkout_path.file_name().unwrap_or_default();
+    Some(
+        repo.settings
+            .logs_dir()
+            .join(repo_dir)
+            .join(&commit.hash)
+            .join(log_ref),
+    )
+}
+
+/// The `log_ref` of attempt `number` of the package at `path`, the first one keeps
+/// the name from before there were attempts
+pub fn log_name(path: &str, number: usize) -> String {
+    let name = path.replace('/', "_");
+    match number {
+        1 => format!("{}.log", name),
+        _ => format!("{}.attempt-{}.log", name, number),
+    }
+}
+
+/// The log of the latest of `attempts` of the package at `path`
+pub fn latest_log_path(
+    commit: &CommitInfo,
+    path: &str,
+    attempts: &[BuildAttempt],
+) -> Option<PathBuf> {
+    let log_ref = attempts
+        .last()
+        .and_then(|attempt| attempt.log_ref.clone())
+        .unwrap_or_else(|| log_name(path, 1));
+    log_path(commit, &log_ref)
+}
+
+/// The log of attempt `number` of `pkg`, `None` if it is not kept or nix did not run
+fn attempt_log_path(pkg: &PackageEnum, number: usize) -> Option<PathBuf> {
+    let log_ref = pkg
+        .attempts()
+        .into_iter()
+        .find(|attempt| attempt.number == number)?
+        .log_ref?;
+    let commit = match pkg {
+        PackageEnum::Derivation(pkg) => &pkg.0.commit,
+        PackageEnum::NixosConfig(pkg) => &pkg.0.commit,
+    };
+    log_path(commit, &log_ref)
+}
+
+/// Where the log at `path` is kept once it was compressed
+pub fn compressed_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".gz");
+    PathBuf::from(name)
+}
+
+/// Replace the finished log at `path` with a gzipped copy. A reader that opened it
+/// before still reads it to the end, later ones find the copy.
+fn compress(path: &Path) -> std::io::Result<()> {
+    let compressed = compressed_path(path);
+    let mut partial = compressed.clone().into_os_string();
+    partial.push(".partial");
+    let mut encoder = GzEncoder::new(File::create(&partial)?, Compression::default());
+    std::io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?.sync_all()?;
+    std::fs::rename(&partial, &compressed)?;
+    std::fs::remove_file(path)
+}
+
+/// The log at `path` as text, unpacked if it was compressed
+fn open_log(path: &Path) -> std::io::Result<Box<dyn Read>> {
+    match File::open(path) {
+        Ok(file) => Ok(Box::new(file)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(Box::new(GzDecoder::new(File::open(compressed_path(path))?)))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Build log file that only ever contains valid UTF-8, invalid bytes are replaced
+/// so the log can always be served as text
+pub struct LogWriter {
+    file: File,
+    /// start of a character that was split between two writes
+    pending: Vec<u8>,
+    path: PathBuf,
+    /// gzip the log once it is finished
+    compress: bool,
+}
+
+impl LogWriter {
+    pub fn create(path: &Path, compress: bool) -> std::io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // of an attempt that ran again after a restart
+        match std::fs::remove_file(compressed_path(path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        Ok(LogWriter {
+            file: File::create(path)?,
+            pending: Vec::new(),
+            path: path.to_path_buf(),
+            compress,
+        })
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.pending.extend_from_slice(bytes);
+        let mut text = String::new();
+        let mut rest = self.pending.as_slice();
+        while !rest.is_empty() {
+            match std::str::from_utf8(rest) {
+                Ok(valid) => {
+                    text.push_str(valid);
+                    rest = &[];
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    text.push_str(&String::from_utf8_lossy(valid));
+                    match e.error_len() {
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            rest = &invalid[len..];
+                        }
+                        // incomplete character, the next write may finish it
+                        None => {
+                            rest = invalid;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        self.pending = rest.to_vec();
+        self.file.write_all(text.as_bytes())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            self.file
+                .write_all(char::REPLACEMENT_CHARACTER.to_string().as_bytes())?;
+        }
+        self.file.flush()?;
+        match self.compress {
+            true => compress(&self.path),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct LogQuery {
+    #[serde(default)]
+    follow: bool,
+    /// `number` of an earlier attempt, the latest one by default
+    attempt: Option<usize>,
+}
+
+/// Build log of a package. The complete log supports range requests unless it was
+/// compressed, with `follow` the log is streamed as it grows until the build
+/// finished. A log deleted by the retention is `410 Gone`.
+#[get("/repos/{name}/commits/{hash}/packages/{path}/log")]
+async fn package_log(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<LogQuery>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let (name, hash, pkg_path) = path.into_inner();
+    tracing::debug!("Requested log of {} {} {}", name, hash, pkg_path);
+    let pkg = find_package(&repos, &name, &hash, &pkg_path)
+        .ok_or_else(|| actix_web::error::ErrorNotFound("404 Not Found"))?;
+    let attempts = pkg.attempts();
+    let attempt = match query.attempt {
+        Some(number) => attempts.iter().find(|attempt| attempt.number == number),
+        None => attempts.last(),
+    };
+    if attempt.is_some_and(BuildAttempt::log_expired) {
+        return Err(actix_web::error::ErrorGone("410 Log expired"));
+    }
+    let log_path = match query.attempt {
+        Some(number) => attempt_log_path(&pkg, number)
+            .ok_or_else(|| actix_web::error::ErrorNotFound("404 Not Found"))?,
+        None => pkg
+            .log_path()
+            .ok_or_else(|| actix_web::error::ErrorNotFound("404 Not Found"))?,
+    };
+
+    // an earlier attempt is complete
+    let latest = query.attempt.is_none_or(|number| {
+        pkg.attempts()
+            .last()
+            .is_some_and(|attempt| attempt.number == number)
+    });
+    if query.follow && latest {
+        return Ok(follow(pkg, log_path));
+    }
+
+    let log = match actix_files::NamedFile::open_async(&log_path).await {
+        Ok(log) => log,
+        Err(_) => {
+            let log = open_log(&log_path)
+                .map_err(|_| actix_web::error::ErrorNotFound("404 Not Found"))?;
+            return Ok(HttpResponse::Ok()
+                .content_type(mime::TEXT_PLAIN_UTF_8)
+                .streaming(stream::unfold(log, |mut log| async move {
+                    let chunk = read_chunk(&mut log)?;
+                    Some((chunk.map(Bytes::from), log))
+                })));
+        }
+    };
+    Ok(log
+        .set_content_type(mime::TEXT_PLAIN_UTF_8)
+        .disable_content_disposition()
+        .into_response(&req))
+}
+
+/// Up to `READ_CHUNK_SIZE` bytes of `log`, `None` at its end
+fn read_chunk(log: &mut impl Read) -> Option<std::io::Result<Vec<u8>>> {
+    let mut buffer = vec![0; READ_CHUNK_SIZE];
+    match log.read(&mut buffer) {
+        Ok(0) => None,
+        Ok(read) => {
+            buffer.truncate(read);
+            Some(Ok(buffer))
+        }
+        Err(e) => Some(Err(e)),
+    }
+}
+
+pub(super) fn find_package(
+    repos: &BuildRepos,
+    name: &str,
+    hash: &str,
+    path: &str,
+) -> Option<PackageEnum> {
+    let repo = repos.find(name)?;
+    let commit = repo.commits.inner().read().ok()?.get(hash)?.clone();
+    commit.packages.get().into_iter().find(|pkg| match pkg {
+        PackageEnum::Derivation(pkg) => pkg.0.path == path,
+        PackageEnum::NixosConfig(pkg) => pkg.0.path == path,
+    })
+}
+
+struct FollowState {
+    pkg: PackageEnum,
+    path: PathBuf,
+    /// opened once the build created it
+    file: Option<Box<dyn Read>>,
+}
+
+fn follow(pkg: PackageEnum, path: PathBuf) -> HttpResponse {
+    let state = FollowState {
+        pkg,
+        path,
+        file: None,
+    };
+    let body = stream::unfold(state, |mut state| async move {
+        let chunk = next_chunk(&mut state).await?;
+        Some((Ok::<_, actix_web::Error>(chunk), state))
+    });
+    HttpResponse::Ok()
+        .content_type(mime::TEXT_PLAIN_UTF_8)
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
+
+/// Next piece of the log, `None` once everything of a finished build was sent
+async fn next_chunk(state: &mut FollowState) -> Option<Bytes> {
+    loop {
+        // check before reading, so output written just before finishing is not lost
+        let finished = state.pkg.status().is_finished();
+        if state.file.is_none() {
+            state.file = open_log(&state.path).ok();
+        }
+        if let Some(file) = &mut state.file
+            && let Some(Ok(chunk)) = read_chunk(file)
+        {
+            return Some(Bytes::from(chunk));
+        }
+        if finished {
+            return None;
+        }
+        sleep(FOLLOW_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_logs_read_as_text() -> std::io::Result<()> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_logs_{}", std::process::id()));
+        let path = dir.join("hash").join("packages.x86_64-linux.hello.log");
+        let mut writer = LogWriter::create(&path, true)?;
+        writer.write("building '/nix/store/abc-hello.drv'\n".as_bytes())?;
+        // split in the middle of `ü`
+        writer.write(&[b'f', 0xc3])?;
+        writer.write(&[0xbc, b'r', b'\n'])?;
+        writer.finish()?;
+        assert!(!path.exists());
+        assert!(compressed_path(&path).exists());
+        let mut text = String::new();
+        open_log(&path)?.read_to_string(&mut text)?;
+        assert_eq!(text, "building '/nix/store/abc-hello.drv'\nfür\n");
+
+        // a rebuild under the same name starts over
+        LogWriter::create(&path, false)?.finish()?;
+        assert!(!compressed_path(&path).exists());
+        std::fs::remove_dir_all(&dir)
+    }
+}
@@ -2,75 +2,197 @@ extern crate git2;
 extern crate serde;
 extern crate serde_json;
 extern crate serde_nixos;
+pub mod access_log;
+pub mod api;
+pub mod archive;
+pub mod badge;
+pub mod build_once;
+pub mod build_window;
+pub mod builder_info;
+pub mod cache;
+pub mod cancel;
+pub mod changes;
+pub mod cli;
+pub mod compress;
+pub mod config_file;
+pub mod cors;
+pub mod database;
+pub mod dry_run;
+pub mod durations;
+pub mod eval_cache;
+pub mod events;
+pub mod export;
+pub mod feed;
+pub mod history;
+pub mod log_retention;
+pub mod logging;
+pub mod logs;
+pub mod nixos_module;
+pub mod notify;
+pub mod paths;
+pub mod pipe;
+pub mod post_build;
+pub mod push_hooks;
+pub mod rate_limit;
+pub mod rebuild;
+pub mod reload;
+pub mod result_check;
+pub mod retention;
+pub mod schedule;
+pub mod schema;
+pub mod secrets;
+pub mod shutdown;
+pub mod sse;
+pub mod state_file;
+pub mod static_files;
+pub mod store;
+pub mod tls;
+pub mod unix_socket;
+pub mod validate;
+pub mod ws;
+
 use crate::serialize::RwLockWrapper;
-use crate::{ARCHITECTURES, AutoBuildOptions, Repo, RepoList, repo::RepoInfo};
 use crate::{
-    commit::{CommitBuildStatus, CommitInfo, RepoStatus},
-    package::{NixosConfigPackage, Package, PackageBuildStatus, PackageEnum},
+    ARCHITECTURES, AutoBuildOptions, Repo, RepoList,
+    repo::{BuildSlots, PollEvent, PollOutcome, PollTrigger, RepoInfo},
+};
+use crate::{
+    commit::{CommitBuildStatus, CommitInfo, DiffStats, RepoStatus},
+    event::StateEvent,
+    package::{
+        BuildAttempt, BuildControl, BuildTimes, NixosConfigPackage, Package, PackageBuildStatus,
+        PackageEnum, PushStatus,
+    },
+    queue::{BuildQueue, QueueEntry},
     serialize::{RwLockHashMapArc, VecArcWrapper},
 };
-use actix_web::{App, HttpResponse, HttpServer, Responder, get};
+use actix_web::{
+    App, HttpResponse, HttpServer, Responder,
+    dev::Service,
+    middleware::{Compress, Condition},
+    post,
+};
+use cancel::CancelOutcome;
+use durations::DurationKey;
 use git2::{Commit, Repository};
 use rayon::prelude::*;
 use serde_json::{Map, Value};
-use std::mem::MaybeUninit;
 use std::os::linux::raw::stat;
-use std::sync::{Condvar, Mutex, RwLock};
-use std::{collections::HashMap, env::args, path::PathBuf, sync::Arc, thread};
-
-const FRONTEND_PATH: &str = match option_env!("FRONTEND_PATH") {
-    Some(path) => path,
-    None => "/workspaces/nix_autobuild/result/dist",
+use std::process::Stdio;
+use std::sync::{Condvar, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+use std::{
+    collections::{HashMap, VecDeque},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Arc,
+    thread,
 };
 
-static mut SEM: MaybeUninit<Semaphore> = MaybeUninit::uninit();
-/// A simple semaphore implementation using Mutex and Condvar
+const STORE_DIR: &str = "/nix/store";
+
+/// The pool all builds and evaluations share, see [`Semaphore::init`]
+static SEM: OnceLock<Semaphore> = OnceLock::new();
+
+/// A simple semaphore implementation using Mutex and Condvar, handing out the slots
+/// in the order they were asked for
 pub struct Semaphore {
-    count: Mutex<usize>,
+    slots: usize,
+    state: Mutex<SlotState>,
     condvar: Condvar,
 }
 
+struct SlotState {
+    free: usize,
+    next_ticket: u64,
+    /// first in line first
+    waiting: VecDeque<(u64, QueueEntry)>,
+}
+
 impl Semaphore {
-    #[allow(static_mut_refs)]
+    pub fn new(count: usize) -> Self {
+        Semaphore {
+            slots: count,
+            state: Mutex::new(SlotState {
+                free: count,
+                next_ticket: 0,
+                waiting: VecDeque::new(),
+            }),
+            condvar: Condvar::new(),
+        }
+    }
+
+    /// Size the shared pool, only the first call before any build counts
     pub fn init(count: usize) {
-        unsafe {
-            SEM.write(Semaphore {
-                count: Mutex::new(count),
-                condvar: Condvar::new(),
-            });
+        if SEM.set(Semaphore::new(count)).is_err() {
+            tracing::warn!("the build pool was already set up, keeping its size");
         }
     }
 
-    #[allow(static_mut_refs)]
+    /// The shared pool, a single slot if nothing set it up
     pub fn get_sem() -> &'static Self {
-        unsafe { SEM.assume_init_ref() }
+        SEM.get_or_init(|| Semaphore::new(1))
     }
 
-    fn acquire(&self) {
-        let mut count = self.count.lock().unwrap();
-        while *count == 0 {
-            count = self.condvar.wait(count).unwrap();
-        }
-        *count -= 1;
+    fn lock(&self) -> MutexGuard<'_, SlotState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
     }
 
-    fn release(&self) {
-        let mut count = self.count.lock().unwrap();
-        *count += 1;
-        self.condvar.notify_one();
+    /// Block until it is the turn of `entry`, the slot is given back when the guard
+    /// drops
+    fn acquire(&self, entry: QueueEntry) -> PoolSlot<'_> {
+        let mut state = self.lock();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiting.push_back((ticket, entry));
+        while state.free == 0 || state.waiting.front().map(|(first, _)| *first) != Some(ticket) {
+            state = self
+                .condvar
+                .wait(state)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        state.waiting.pop_front();
+        state.free -= 1;
+        // the next in line may get a slot too
+        self.condvar.notify_all();
+        PoolSlot(self)
     }
 
-    pub fn execute<F, R>(&self, f: F) -> R
+    /// Run `f` once a slot is free, `entry` describes the job while it waits. The slot
+    /// is free again even if `f` panics.
+    pub fn execute<F, R>(&self, entry: QueueEntry, f: F) -> R
     where
         F: FnOnce() -> R,
     {
-        self.acquire();
-        let result = f();
-        self.release();
-        result
+        let _slot = self.acquire(entry);
+        f()
+    }
+
+    pub fn queue(&self) -> BuildQueue {
+        let state = self.lock();
+        BuildQueue {
+            slots: self.slots,
+            running: self.slots - state.free,
+            waiting: state
+                .waiting
+                .iter()
+                .map(|(_, entry)| entry.clone())
+                .collect(),
+        }
     }
 }
 
+struct PoolSlot<'a>(&'a Semaphore);
+
+impl Drop for PoolSlot<'_> {
+    fn drop(&mut self) {
+        self.0.lock().free += 1;
+        self.0.condvar.notify_all();
+    }
+}
+
+/// Polls kept per repo for the timeline in the frontend
+const POLL_HISTORY_LEN: usize = 30;
+
 pub trait RepoInfoTrait {
     fn new(repo: Repo, checkout_path: PathBuf, settings: Arc<AutoBuildOptions>) -> Arc<RepoInfo>;
 
@@ -78,21 +200,36 @@ pub trait RepoInfoTrait {
 
     fn clone_or_open(&self) -> Result<git2::Repository, git2::Error>;
     fn pull(&self, repository: &Repository) -> Result<bool, git2::Error>;
+    fn fetch_changes(&self, repository: &Repository) -> Result<bool, git2::Error>;
+    fn record_poll(&self, result: &Result<bool, git2::Error>);
 
     fn thread_poll(self: Arc<Self>);
 
     fn parse_commit_parents<'repo>(
         self: &Arc<Self>,
+        repository: &Repository,
         commit: &Commit<'repo>,
         depth: u8,
         commits: &mut Vec<Arc<CommitInfo>>,
+        force_eval: bool,
     );
 
-    fn get_or_create_commit<'repo>(self: &Arc<Self>, commit: &Commit<'repo>) -> Arc<CommitInfo>;
+    /// With `force_eval` a new commit is evaluated without the cache, and so is a
+    /// known one without packages, e.g. after its evaluation failed
+    fn get_or_create_commit<'repo>(
+        self: &Arc<Self>,
+        repository: &Repository,
+        commit: &Commit<'repo>,
+        force_eval: bool,
+    ) -> Arc<CommitInfo>;
 
     fn thread_loop(self: Arc<Self>) -> Result<(), Box<dyn std::error::Error>>;
 
     fn delete_repo(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn set_status(&self, status: RepoStatus);
+
+    fn set_next_poll(&self, unix_secs: Option<i64>);
 }
 
 impl RepoInfoTrait for RepoInfo {
@@ -101,68 +238,108 @@ impl RepoInfoTrait for RepoInfo {
         for branch in &repo.branches {
             branch_commit_hashes.insert(branch.clone(), RwLockWrapper::new(Vec::new()));
         }
-        let credentials = if let Some(credentials_file) = &repo.credentials_file {
-            match std::fs::read_to_string(credentials_file) {
-                Ok(creds) => Some(creds.trim().to_string()),
-                Err(e) => {
-                    println!("ERROR reading credentials file {}: {}", credentials_file, e);
-                    None
-                }
+        // empty was the default of the NixOS module
+        let credentials_file = repo
+            .credentials_file
+            .as_deref()
+            .filter(|file| !file.is_empty())
+            .map(Path::new);
+        let credentials = match secrets::resolve_either(repo.credentials.as_ref(), credentials_file)
+        {
+            Ok(credentials) => credentials,
+            Err(e) => {
+                tracing::error!("reading the credentials of {}: {}", repo.name(), e);
+                None
             }
-        } else {
-            None
         };
+        let supported_architectures = repo
+            .supported_architectures
+            .clone()
+            .unwrap_or_else(|| settings.supported_architectures.clone());
+        database::record_repo(&repo);
         Arc::new(RepoInfo {
             flake_url: format!("git+https://{}", repo.url),
+            supported_architectures,
+            running_builds: BuildSlots::new(repo.max_concurrent_builds),
             repo,
             checkout_path,
             branch_commit_hashes: branch_commit_hashes,
             commits: RwLockHashMapArc::new(RwLock::new(HashMap::new())),
             status: RwLockWrapper::new(RepoStatus::Idle),
+            poll_history: RwLockWrapper::new(VecDeque::new()),
+            next_poll: RwLockWrapper::new(None),
             credentials,
             settings,
+            poll_trigger: PollTrigger::default(),
         })
     }
 
     fn clone_repo(&self) -> Result<git2::Repository, git2::Error> {
-        *self.status.0.write().unwrap() = RepoStatus::Cloning;
-        println!("CLONE\t{}", format!("https://{}", &self.repo.url));
+        self.set_status(RepoStatus::Cloning);
+        tracing::info!("Cloning https://{}", self.repo.url);
 
         let clone_url = if let Some(credentials) = &self.credentials {
-            format!("https://{}@{}", credentials, &self.repo.url)
+            format!("https://{}@{}", credentials.expose(), &self.repo.url)
         } else {
             format!("https://{}", &self.repo.url)
         };
         let res = Repository::clone(clone_url.as_str(), &self.checkout_path);
 
-        *self.status.0.write().unwrap() = RepoStatus::Idle;
+        self.set_status(RepoStatus::Idle);
 
         match &res {
-            Ok(_) => println!("CLONE DONE\t{}", self.checkout_path.display()),
-            Err(e) => println!("CLONE ERROR\t{}: {}", self.checkout_path.display(), e),
+            Ok(_) => tracing::info!("Cloned {}", self.checkout_path.display()),
+            Err(e) => tracing::error!("cloning {}: {}", self.checkout_path.display(), e),
         };
 
         res
     }
 
     fn clone_or_open(&self) -> Result<git2::Repository, git2::Error> {
-        *self.status.0.write().unwrap() = RepoStatus::Opening;
-        println!("OPEN\t{}", self.checkout_path.display());
+        self.set_status(RepoStatus::Opening);
+        tracing::debug!("Opening {}", self.checkout_path.display());
         let res = match Repository::open(&self.checkout_path) {
             Ok(repo) => Ok(repo),
             Err(_) => self.clone_repo(),
         };
-        *self.status.0.write().unwrap() = RepoStatus::Idle;
+        self.set_status(RepoStatus::Idle);
         match &res {
-            Ok(_) => println!("OPENED\t{}", self.checkout_path.display()),
-            Err(e) => println!("OPEN ERROR\t{}: {}", self.checkout_path.display(), e),
+            Ok(_) => tracing::debug!("Opened {}", self.checkout_path.display()),
+            Err(e) => tracing::error!("opening {}: {}", self.checkout_path.display(), e),
         };
         res
     }
 
     fn pull(&self, repository: &Repository) -> Result<bool, git2::Error> {
-        *self.status.0.write().unwrap() = RepoStatus::Pulling;
-        println!("PULL\t{}", self.checkout_path.display());
+        let result = self.fetch_changes(repository);
+        self.record_poll(&result);
+        result
+    }
+
+    fn record_poll(&self, result: &Result<bool, git2::Error>) {
+        let outcome = match result {
+            Ok(true) => PollOutcome::Changed,
+            Ok(false) => PollOutcome::Unchanged,
+            Err(e) => PollOutcome::Failed(e.message().to_string()),
+        };
+        let mut polls = self.poll_history.get();
+        if polls.len() == POLL_HISTORY_LEN {
+            polls.pop_front();
+        }
+        polls.push_back(PollEvent {
+            unix_secs: events::unix_now(),
+            outcome,
+        });
+        self.poll_history.set(polls.clone());
+        events::publish(StateEvent::PollHistory {
+            repo: self.repo.url.clone(),
+            polls,
+        });
+    }
+
+    fn fetch_changes(&self, repository: &Repository) -> Result<bool, git2::Error> {
+        self.set_status(RepoStatus::Pulling);
+        tracing::debug!("Pulling {}", self.checkout_path.display());
         let mut remote = repository.find_remote("origin")?;
         let mut fetch_options = git2::FetchOptions::new();
         let before_refs = repository
@@ -174,14 +351,14 @@ impl RepoInfoTrait for RepoInfo {
         remote
             .fetch(&self.repo.branches, Some(&mut fetch_options), None)
             .map_err(|err| {
-                eprintln!("PULL ERROR\t{}: {}", self.checkout_path.display(), err);
+                tracing::error!("pulling {}: {}", self.checkout_path.display(), err);
                 err
             })?;
 
         let after_refs = repository
             .references()
             .map_err(|err| {
-                eprintln!("PULL ERROR\t{}: {}", self.checkout_path.display(), err);
+                tracing::error!("pulling {}: {}", self.checkout_path.display(), err);
                 err
             })?
             .filter_map(|r| r.ok())
@@ -189,63 +366,103 @@ impl RepoInfoTrait for RepoInfo {
             .collect::<std::collections::HashMap<_, _>>();
 
         let has_changes = before_refs != after_refs;
-        *self.status.0.write().unwrap() = RepoStatus::Idle;
+        self.set_status(RepoStatus::Idle);
 
         match has_changes {
-            true => println!("PULL DONE\t{}", self.checkout_path.display()),
-            false => println!("PULL NO CHANGES\t{}", self.checkout_path.display()),
+            true => tracing::info!("Pulled new commits into {}", self.checkout_path.display()),
+            false => tracing::debug!("No new commits in {}", self.checkout_path.display()),
         }
         Ok(has_changes)
     }
 
     fn thread_poll(self: Arc<RepoInfo>) {
         loop {
-            if let Err(e) = self.clone().thread_loop() {
-                println!("ERROR in repo {}: {}", self.checkout_path.display(), e);
+            match self.clone().thread_loop() {
+                // only returns once the server shuts down or the poller is stopped
+                Ok(()) => return,
+                Err(e) => tracing::error!("in repo {}: {}", self.checkout_path.display(), e),
+            }
+            if shutdown::requested() || self.poll_trigger.is_stopped() {
+                return;
             }
             if let Err(e) = self.delete_repo() {
-                println!("ERROR deleting {}: {}", self.checkout_path.display(), e);
+                tracing::error!("deleting {}: {}", self.checkout_path.display(), e);
             }
         }
     }
 
     fn parse_commit_parents<'repo>(
         self: &Arc<RepoInfo>,
+        repository: &Repository,
         commit: &Commit<'repo>,
         depth: u8,
         commits: &mut Vec<Arc<CommitInfo>>,
+        force_eval: bool,
     ) {
         if depth == 0 {
             return;
         }
         for commit in commit.parents() {
-            commits.push(self.get_or_create_commit(&commit));
+            commits.push(self.get_or_create_commit(repository, &commit, force_eval));
         }
         for commit in commit.parents() {
-            self.parse_commit_parents(&commit, depth - 1, commits);
+            self.parse_commit_parents(repository, &commit, depth - 1, commits, force_eval);
         }
     }
 
     fn get_or_create_commit<'repo>(
         self: &Arc<RepoInfo>,
+        repository: &Repository,
         commit: &Commit<'repo>,
+        force_eval: bool,
     ) -> Arc<CommitInfo> {
-        let mut commits = self.commits.inner().write().unwrap();
-        if let Some(commit_info) = commits.get(&commit.id().to_string()) {
-            return commit_info.clone();
+        let hash = commit.id().to_string();
+        let known = self
+            .commits
+            .inner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&hash)
+            .cloned();
+        if let Some(commit_info) = known {
+            if force_eval
+                && commit_info.packages.get().is_empty()
+                && matches!(commit_info.status.get(), CommitBuildStatus::Idle)
+            {
+                commit_info.clone().build(false);
+            }
+            return commit_info;
         }
-        let commit = CommitInfo::new(self.clone(), &commit);
-        commits.insert(commit.hash.clone(), commit.clone());
+        // the diff stats walk the tree, not while the requests wait for the lock
+        let commit_info = CommitInfo::new(self.clone(), repository, commit);
+        let mut commits = self
+            .commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(known) = commits.get(&hash) {
+            return known.clone();
+        }
+        commits.insert(hash, commit_info.clone());
         drop(commits);
-        commit.clone().build();
-        commit
+        // the database may wait for another writer, the requests must not
+        database::record_commit(&commit_info);
+        let stored = database::stored_packages(&commit_info);
+        events::publish(StateEvent::CommitsChanged {
+            repo: self.repo.url.clone(),
+        });
+        match stored {
+            Some(packages) => commit_info.clone().restore(packages),
+            None => commit_info.clone().build(!force_eval),
+        }
+        commit_info
     }
 
     fn thread_loop(self: Arc<RepoInfo>) -> Result<(), Box<dyn std::error::Error>> {
         // clone repo if not exists
         let repo = self.clone_or_open().map_err(|err| {
-            eprintln!(
-                "ERROR cloning or opening repo {}: {}",
+            tracing::error!(
+                "cloning or opening repo {}: {}",
                 self.checkout_path.display(),
                 err
             );
@@ -253,13 +470,14 @@ impl RepoInfoTrait for RepoInfo {
         })?;
 
         loop {
-            println!("POLL\t{}", self.checkout_path.display());
-            *self.status.0.write().unwrap() = RepoStatus::Polling;
+            tracing::debug!("Polling {}", self.checkout_path.display());
+            self.set_status(RepoStatus::Polling);
+            let force_eval = self.poll_trigger.take_eval_request();
 
             repo.branches(Some(git2::BranchType::Remote))
                 .map_err(|err| {
-                    eprintln!(
-                        "ERROR listing branches for repo {}: {}",
+                    tracing::error!(
+                        "listing branches for repo {}: {}",
                         self.checkout_path.display(),
                         err
                     );
@@ -280,19 +498,21 @@ impl RepoInfoTrait for RepoInfo {
 
                     if let Ok(name) = branch.name() {
                         if let Some(name_str) = name {
-                            println!("Found branch: {}", name_str);
+                            tracing::debug!("Found branch {}", name_str);
                         }
                     }
 
                     let commit = branch.get().peel_to_commit().expect("no commit on branch");
                     let mut commits: Vec<Arc<CommitInfo>> = Vec::new();
                     // Add the current commit first
-                    commits.push(self.get_or_create_commit(&commit));
+                    commits.push(self.get_or_create_commit(&repo, &commit, force_eval));
                     // Then add parent commits up to build_depth - 1
                     self.parse_commit_parents(
+                        &repo,
                         &commit,
                         self.repo.build_depth.saturating_sub(1),
                         &mut commits,
+                        force_eval,
                     );
 
                     *self
@@ -302,34 +522,63 @@ impl RepoInfoTrait for RepoInfo {
                         .0
                         .write()
                         .unwrap() = commits.iter().map(|c| c.hash.clone()).collect();
+                    events::publish(StateEvent::CommitsChanged {
+                        repo: self.repo.url.clone(),
+                    });
                 });
-
-            // sleep for poll interval
-            while !self.pull(&repo)? {
-                *self.status.0.write().unwrap() = RepoStatus::Idle;
-                thread::sleep(std::time::Duration::from_secs(self.repo.poll_interval_sec));
+            retention::prune(&self, events::unix_now());
+
+            // sleep until the next scheduled poll, unless a poll is requested
+            // an evaluation without the cache is requested also if nothing changed
+            while !self.pull(&repo)? && !self.poll_trigger.eval_requested() {
+                self.set_status(RepoStatus::Idle);
+                let next_poll = schedule::next_poll(&self.repo, &chrono::Local::now());
+                self.set_next_poll(Some(next_poll));
+                self.poll_trigger.wait(std::time::Duration::from_secs(
+                    (next_poll - events::unix_now()).max(0) as u64,
+                ));
+                self.set_next_poll(None);
+                if shutdown::requested() || self.poll_trigger.is_stopped() {
+                    return Ok(());
+                }
             }
         }
     }
 
     fn delete_repo(&self) -> Result<(), Box<dyn std::error::Error>> {
-        println!("DELETE\t{}", self.checkout_path.display());
+        tracing::info!("Deleting {}", self.checkout_path.display());
         let output = std::process::Command::new("rm")
             .arg("-rf")
             .arg(&self.checkout_path)
             .output()?;
         if output.status.code().unwrap_or(-1) != 0 {
             let delete_error = String::from_utf8_lossy(&output.stderr);
-            println!(
-                "ERROR deleting {} -> {}",
+            tracing::error!(
+                "deleting {} -> {}",
                 self.checkout_path.display(),
                 delete_error
             );
             return Err("Failed to delete repository".into());
         }
-        println!("DELETED\t{}", self.checkout_path.display());
+        tracing::info!("Deleted {}", self.checkout_path.display());
         Ok(())
     }
+
+    fn set_status(&self, status: RepoStatus) {
+        self.status.set(status.clone());
+        events::publish(StateEvent::RepoStatus {
+            repo: self.repo.url.clone(),
+            status,
+        });
+    }
+
+    fn set_next_poll(&self, unix_secs: Option<i64>) {
+        self.next_poll.set(unix_secs);
+        events::publish(StateEvent::NextPoll {
+            repo: self.repo.url.clone(),
+            unix_secs,
+        });
+    }
 }
 
 impl PackageBase for Package {
@@ -355,42 +604,86 @@ impl PackageBase for Package {
             path,
             commit: commit.clone(),
             status: RwLockWrapper::new(PackageBuildStatus::Idle),
+            times: RwLockWrapper::new(BuildTimes::default()),
+            closure_size: RwLockWrapper::new(None),
+            expected_secs: RwLockWrapper::new(None),
+            result_missing: RwLockWrapper::new(false),
+            pushes: RwLockWrapper::new(Vec::new()),
+            attempts: RwLockWrapper::new(Vec::new()),
+            control: BuildControl::default(),
         }))
     }
 
-    fn build(self: Arc<Self>) {
-        thread::spawn(move || {
-            // skip packages not matching supported architectures
-            *self.status.0.write().unwrap() = PackageBuildStatus::Building;
-            let mut arch_supported = false;
-            for arch in self.commit.repo.settings.supported_architectures.iter() {
-                if self.arch == arch {
-                    arch_supported = true;
-                    break;
-                }
-            }
+    fn commit(&self) -> &Arc<CommitInfo> {
+        &self.commit
+    }
 
-            if !arch_supported {
-                println!("SKIP\t{} unsupported arch: {}", self.flake_url, self.arch);
-                *self.status.0.write().unwrap() =
-                    PackageBuildStatus::UnsupportedArchitecture(self.arch);
-                return;
-            }
+    fn path(&self) -> &str {
+        &self.path
+    }
 
-            match Self::build_static(self.flake_url.as_str(), &self.status) {
-                Ok(path) => {
-                    *self.status.0.write().unwrap() = PackageBuildStatus::Success(path);
-                }
-                Err(e) => {
-                    *self.status.0.write().unwrap() = PackageBuildStatus::Failed(e.to_string());
-                }
-            };
-        });
+    fn flake_url(&self) -> &str {
+        &self.flake_url
+    }
+
+    fn status_lock(&self) -> &RwLockWrapper<PackageBuildStatus> {
+        &self.status
+    }
+
+    fn times(&self) -> &RwLockWrapper<BuildTimes> {
+        &self.times
+    }
+
+    fn closure_size(&self) -> &RwLockWrapper<Option<u64>> {
+        &self.closure_size
+    }
+
+    fn expected_secs(&self) -> &RwLockWrapper<Option<u64>> {
+        &self.expected_secs
+    }
+
+    fn result_missing(&self) -> &RwLockWrapper<bool> {
+        &self.result_missing
+    }
+
+    fn pushes(&self) -> &RwLockWrapper<Vec<PushStatus>> {
+        &self.pushes
+    }
+
+    fn attempts(&self) -> &RwLockWrapper<Vec<BuildAttempt>> {
+        &self.attempts
+    }
+
+    fn control(&self) -> &BuildControl {
+        &self.control
+    }
+
+    fn unsupported_architecture(&self) -> Option<&'static str> {
+        let repo = self.commit.repo();
+        let supported = repo
+            .iter()
+            .flat_map(|repo| &repo.supported_architectures)
+            .any(|arch| self.arch == arch);
+        (!supported).then_some(self.arch)
+    }
+
+    fn duration_key(&self) -> DurationKey {
+        DurationKey {
+            repo: self.commit.repo_url(),
+            name: self.get_no_arch_name(),
+            arch: self.arch.to_string(),
+        }
     }
 }
 
 pub trait PackageEnumTrait {
     fn build(&self);
+
+    fn cancel(&self) -> CancelOutcome;
+
+    fn rebuild(&self, forced: bool) -> bool;
+
+    fn log_path(&self) -> Option<PathBuf>;
 }
 
 impl PackageEnumTrait for PackageEnum {
@@ -404,16 +697,92 @@ impl PackageEnumTrait for PackageEnum {
             }
         }
     }
+
+    fn cancel(&self) -> CancelOutcome {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.inner().cancel(),
+            PackageEnum::NixosConfig(pkg) => pkg.inner().cancel(),
+        }
+    }
+
+    fn rebuild(&self, forced: bool) -> bool {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.inner().clone().rebuild(forced),
+            PackageEnum::NixosConfig(pkg) => pkg.inner().clone().rebuild(forced),
+        }
+    }
+
+    fn log_path(&self) -> Option<PathBuf> {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.inner().log_path(),
+            PackageEnum::NixosConfig(pkg) => pkg.inner().log_path(),
+        }
+    }
+}
+
+/// Check and swap `status` under one write lock, see `PackageBase::queue_again`
+fn queue_again(status: &RwLockWrapper<PackageBuildStatus>) -> bool {
+    let mut status = status
+        .inner()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    match *status {
+        PackageBuildStatus::Failed(_)
+        | PackageBuildStatus::Cancelled
+        | PackageBuildStatus::Success(_)
+        | PackageBuildStatus::DryRun { .. } => {}
+        _ => return false,
+    }
+    *status = PackageBuildStatus::WaitingForBuild;
+    true
+}
+
+/// Against the first parent, `None` if git fails
+fn diff_stats(repository: &Repository, commit: &Commit) -> Option<DiffStats> {
+    if commit.parent_count() == 0 {
+        return Some(DiffStats::Initial);
+    }
+    let parent = commit.parent(0).ok()?.tree().ok()?;
+    let diff = repository
+        .diff_tree_to_tree(Some(&parent), Some(&commit.tree().ok()?), None)
+        .ok()?;
+    let stats = diff.stats().ok()?;
+    Some(DiffStats::Changed {
+        files_changed: stats.files_changed(),
+        insertions: stats.insertions(),
+        deletions: stats.deletions(),
+    })
+}
+
+/// The packages in the output of `nix flake show` of `commit`
+fn flake_packages(
+    commit: &Arc<CommitInfo>,
+    pkgs_value: &Value,
+) -> Result<Vec<PackageEnum>, Box<dyn std::error::Error>> {
+    let Some(pkgs_object) = pkgs_value.as_object() else {
+        return Err("No packages found in flake".into());
+    };
+
+    let mut pkgs_vec: Vec<PackageEnum> = Vec::new();
+    CommitInfo::_parse_pkgs_value(pkgs_object, String::new(), commit, &mut pkgs_vec);
+    commit.set_status(CommitBuildStatus::Idle);
+    Ok(pkgs_vec)
 }
 
 pub trait CommitInfoTrait {
-    fn new(repo: Arc<RepoInfo>, commit: &Commit) -> Arc<CommitInfo>;
+    fn new(repo: Arc<RepoInfo>, repository: &Repository, commit: &Commit) -> Arc<CommitInfo>;
+
+    /// Evaluate the flake, from the eval cache if `cached`, and build its packages
+    fn build(self: Arc<Self>, cached: bool);
 
-    fn build(self: Arc<Self>);
+    /// Take the packages recorded by an earlier run instead of evaluating the flake,
+    /// only the builds that had not finished run again
+    fn restore(self: Arc<Self>, pkgs: Vec<PackageEnum>);
 
     fn get_pkgs_list(
         self: &Arc<Self>,
         flake_url: &str,
+        cached: bool,
     ) -> Result<Vec<PackageEnum>, Box<dyn std::error::Error>>;
 
     fn _parse_pkgs_value(
@@ -422,10 +791,12 @@ pub trait CommitInfoTrait {
         commit: &Arc<CommitInfo>,
         pkgs: &mut Vec<PackageEnum>,
     );
+
+    fn set_status(&self, status: CommitBuildStatus);
 }
 
 impl CommitInfoTrait for CommitInfo {
-    fn new(repo: Arc<RepoInfo>, commit: &Commit) -> Arc<CommitInfo> {
+    fn new(repo: Arc<RepoInfo>, repository: &Repository, commit: &Commit) -> Arc<CommitInfo> {
         let hash = commit.id().to_string();
         Arc::new(CommitInfo {
             message: commit
@@ -436,16 +807,17 @@ impl CommitInfoTrait for CommitInfo {
             flake_url: format!("git+https://{}?rev={}", &repo.repo.url, &hash),
             hash,
             packages: RwLockWrapper::new(Vec::new()),
-            repo: repo.clone(),
+            repo: RwLockWrapper::new(Arc::downgrade(&repo)),
             status: RwLockWrapper::new(CommitBuildStatus::Idle),
             unix_secs: commit.time().seconds(),
+            diff_stats: diff_stats(repository, commit),
         })
     }
 
-    fn build(self: Arc<Self>) {
+    fn build(self: Arc<Self>, cached: bool) {
         thread::spawn(move || {
-            *self.status.0.write().unwrap() = CommitBuildStatus::GettingPackages;
-            let Ok(pkgs) = self.get_pkgs_list(&self.flake_url) else {
+            self.set_status(CommitBuildStatus::GettingPackages);
+            let Ok(pkgs) = self.get_pkgs_list(&self.flake_url, cached) else {
                 return;
             };
             {
@@ -454,19 +826,58 @@ impl CommitInfoTrait for CommitInfo {
                     pkgs_writer.push(pkg.clone());
                 });
             }
+            database::record_packages(&self, &pkgs);
+            events::publish(StateEvent::CommitsChanged {
+                repo: self.repo_url(),
+            });
             pkgs.par_iter().for_each(|pkg| {
                 pkg.build();
             });
-            *self.status.0.write().unwrap() = CommitBuildStatus::Idle;
+            self.set_status(CommitBuildStatus::Idle);
         });
     }
 
+    fn restore(self: Arc<Self>, pkgs: Vec<PackageEnum>) {
+        self.packages.set(pkgs.clone());
+        let unfinished = pkgs
+            .into_iter()
+            .filter(|pkg| !pkg.status().is_finished())
+            .collect::<Vec<_>>();
+        if unfinished.is_empty() {
+            return;
+        }
+        tracing::info!(
+            "Building {} unfinished packages of {} again",
+            unfinished.len(),
+            self.flake_url
+        );
+        for pkg in unfinished {
+            pkg.build();
+        }
+    }
+
     fn get_pkgs_list(
         self: &Arc<Self>,
         flake_url: &str,
+        cached: bool,
     ) -> Result<Vec<PackageEnum>, Box<dyn std::error::Error>> {
-        Semaphore::get_sem().execute(|| {
-            *self.status.0.write().unwrap() = CommitBuildStatus::GettingPackages;
+        let settings = self.repo().map(|repo| repo.settings.clone());
+        let stored = settings
+            .as_deref()
+            .filter(|_| cached)
+            .and_then(|settings| eval_cache::get(settings, flake_url))
+            .and_then(|output| serde_json::from_slice::<Value>(&output).ok());
+        if let Some(pkgs_value) = stored {
+            tracing::debug!("Took the packages of {} from the eval cache", flake_url);
+            return flake_packages(self, &pkgs_value);
+        }
+        let entry = QueueEntry {
+            repo: self.repo_url(),
+            commit: self.hash.clone(),
+            path: None,
+        };
+        Semaphore::get_sem().execute(entry, || {
+            self.set_status(CommitBuildStatus::GettingPackages);
             let output = std::process::Command::new("nix")
                 .arg("flake")
                 .arg("show")
@@ -474,11 +885,11 @@ impl CommitInfoTrait for CommitInfo {
                 .arg("--all-systems")
                 .arg(flake_url)
                 .output()?;
-            println!("LIST\t{}", flake_url); // TODO: add error handling
+            tracing::debug!("Listed the packages of {}", flake_url); // TODO: add error handling
 
             if output.status.code().unwrap_or(-1) != 0 {
                 let list_error = String::from_utf8_lossy(&output.stderr);
-                println!("ERROR listing {} -> {}", flake_url, list_error);
+                tracing::error!("listing {} -> {}", flake_url, list_error);
                 return Err("Failed to list packages in flake".into());
             }
 
@@ -488,13 +899,10 @@ impl CommitInfoTrait for CommitInfo {
             let pkgs_value: Value = serde_json::from_str(&pkgs_json)?;
             //println!("{:#?}", pkgs_value);
 
-            let Some(pkgs_object) = pkgs_value.as_object() else {
-                return Err("No packages found in flake".into());
-            };
-
-            let mut pkgs_vec: Vec<PackageEnum> = Vec::new();
-            Self::_parse_pkgs_value(pkgs_object, String::new(), &self, &mut pkgs_vec);
-            *self.status.0.write().unwrap() = CommitBuildStatus::Idle;
+            let pkgs_vec = flake_packages(self, &pkgs_value)?;
+            if let Some(settings) = &settings {
+                eval_cache::put(settings, flake_url, pkgs_json.as_bytes());
+            }
             Ok(pkgs_vec)
         })
     }
@@ -533,9 +941,20 @@ impl CommitInfoTrait for CommitInfo {
             }
         }
     }
+
+    fn set_status(&self, status: CommitBuildStatus) {
+        self.status.set(status.clone());
+        events::publish(StateEvent::CommitStatus {
+            repo: self.repo_url(),
+            commit: self.hash.clone(),
+            status,
+        });
+    }
 }
 
-pub trait PackageBase: Send + Sync {
+/// The build of a package. Derivations and NixOS configurations only lend it their
+/// fields, and derivations skip the unsupported architectures.
+pub trait PackageBase: Send + Sync + 'static {
     fn from_map(
         map: &Map<String, Value>,
         path: String,
@@ -544,42 +963,320 @@ pub trait PackageBase: Send + Sync {
     where
         Self: Sized;
 
-    fn build(self: Arc<Self>);
+    fn commit(&self) -> &Arc<CommitInfo>;
+
+    fn path(&self) -> &str;
+
+    fn flake_url(&self) -> &str;
+
+    /// As set, `status` takes the latest attempt into account
+    fn status_lock(&self) -> &RwLockWrapper<PackageBuildStatus>;
+
+    fn times(&self) -> &RwLockWrapper<BuildTimes>;
+
+    fn closure_size(&self) -> &RwLockWrapper<Option<u64>>;
+
+    fn expected_secs(&self) -> &RwLockWrapper<Option<u64>>;
+
+    fn result_missing(&self) -> &RwLockWrapper<bool>;
+
+    fn pushes(&self) -> &RwLockWrapper<Vec<PushStatus>>;
+
+    fn attempts(&self) -> &RwLockWrapper<Vec<BuildAttempt>>;
+
+    fn control(&self) -> &BuildControl;
+
+    /// Under which the durations of its successful builds are kept
+    fn duration_key(&self) -> DurationKey;
+
+    /// The architecture of a package none of the supported ones match, it is
+    /// skipped instead of built
+    fn unsupported_architecture(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn build(self: Arc<Self>) {
+        thread::spawn(move || {
+            let _running = shutdown::track_build();
+            // cancelled before the commit got to it
+            if self.control().is_cancelled() {
+                return;
+            }
+            // `Building` is only set once nix runs, a new attempt starts with it
+            self.set_status(PackageBuildStatus::WaitingForBuild);
+            if let Some(arch) = self.unsupported_architecture() {
+                tracing::info!(
+                    "Skipping {}, unsupported architecture {}",
+                    self.flake_url(),
+                    arch
+                );
+                self.set_status(PackageBuildStatus::UnsupportedArchitecture(arch));
+                return;
+            }
+
+            let result = self.build_static(self.flake_url());
+            let closure_size = match &result {
+                Ok(PackageBuildStatus::Success(path)) => cache::closure_size(path),
+                _ => None,
+            };
+            self.closure_size().set(closure_size);
+            if let Ok(PackageBuildStatus::Success(out_paths)) = &result {
+                self.push(out_paths);
+            }
+            self.finish(result);
+        });
+    }
+
+    fn set_status(&self, status: PackageBuildStatus) {
+        let times = self.times().get().update(&status, events::unix_now());
+        let mut attempts = self.attempts().get();
+        BuildAttempt::record(&mut attempts, &status, times, |number| {
+            logs::log_name(self.path(), number)
+        });
+        builder_info::stamp(&mut attempts, &status);
+        let key = self.duration_key();
+        durations::record(key.clone(), &status, times);
+        let expected_secs = durations::expected_while_pending(&key, &status);
+        self.status_lock().set(status.clone());
+        self.times().set(times);
+        self.attempts().set(attempts.clone());
+        self.expected_secs().set(expected_secs);
+        // a new build replaces the collected result
+        self.result_missing().set(false);
+        let commit = self.commit();
+        database::record_status(
+            commit,
+            self.path(),
+            &status,
+            times,
+            self.closure_size().get(),
+            attempts.last(),
+        );
+        events::publish(StateEvent::PackageStatus {
+            repo: commit.repo_url(),
+            commit: commit.hash.clone(),
+            path: self.path().to_string(),
+            status,
+            times,
+            closure_size: self.closure_size().get(),
+            expected_secs,
+            attempts: Some(attempts),
+        });
+    }
+
+    fn set_pushes(&self, pushes: Vec<PushStatus>) {
+        self.pushes().set(pushes.clone());
+        events::publish(StateEvent::PackagePushes {
+            repo: self.commit().repo_url(),
+            commit: self.commit().hash.clone(),
+            path: self.path().to_string(),
+            pushes,
+        });
+    }
+
+    fn status(&self) -> PackageBuildStatus {
+        BuildAttempt::current_status(self.status_lock().get(), self.attempts())
+    }
+
+    /// Swap a finished status for `WaitingForBuild`, `false` while the build is
+    /// still queued or running
+    fn queue_again(&self) -> bool {
+        queue_again(self.status_lock())
+    }
+
+    /// `None` once the repo was removed
+    fn log_path(&self) -> Option<PathBuf> {
+        logs::latest_log_path(self.commit(), self.path(), &self.attempts().get())
+    }
+
+    /// How the build shows up in `/queue` while it waits for a slot
+    fn queue_entry(&self) -> QueueEntry {
+        QueueEntry {
+            repo: self.commit().repo_url(),
+            commit: self.commit().hash.clone(),
+            path: Some(self.path().to_string()),
+        }
+    }
+
+    /// `None` once the repo was removed
+    fn repo(&self) -> Option<Arc<RepoInfo>> {
+        self.commit().repo()
+    }
+
+    /// Older commits leave the build window first
+    fn commit_secs(&self) -> i64 {
+        self.commit().unix_secs
+    }
+
+    /// Drop a queued build or kill a running one
+    fn cancel(&self) -> CancelOutcome {
+        match self.status() {
+            PackageBuildStatus::Idle
+            | PackageBuildStatus::WaitingForWindow
+            | PackageBuildStatus::WaitingForBuild => {
+                self.control().cancel();
+                self.set_status(PackageBuildStatus::Cancelled);
+                CancelOutcome::Cancelled
+            }
+            // the build thread kills nix and sets the status
+            PackageBuildStatus::Building => {
+                self.control().cancel();
+                CancelOutcome::Killed
+            }
+            _ => CancelOutcome::AlreadyFinished,
+        }
+    }
+
+    /// Queue a failed, cancelled or successful build again, `false` while it is
+    /// still queued or running. Unsupported architectures would only be skipped again.
+    /// A `forced` build does not wait for the build window.
+    fn rebuild(self: Arc<Self>, forced: bool) -> bool {
+        // two requests at once must not both start a build
+        if !self.queue_again() {
+            return false;
+        }
+        self.control().reset(forced);
+        self.set_pushes(Vec::new());
+        self.set_status(PackageBuildStatus::WaitingForBuild);
+        self.build();
+        true
+    }
+
+    /// Sign and push the out paths of a successful build to the `post_build`
+    /// targets, before the build counts as finished
+    fn push(&self, out_paths: &str) {
+        post_build::run(out_paths, self.control(), |pushes| self.set_pushes(pushes));
+    }
+
+    /// Set the final status of a build that returned `result`, unless a cancel in the
+    /// queue already did
+    fn finish(&self, result: Result<PackageBuildStatus, Box<dyn std::error::Error>>) {
+        let status = self.finished_status(result);
+        if status == PackageBuildStatus::Cancelled && self.status() == status {
+            return;
+        }
+        self.set_status(status);
+    }
+
+    /// Final status of a build that returned `result`
+    fn finished_status(
+        &self,
+        result: Result<PackageBuildStatus, Box<dyn std::error::Error>>,
+    ) -> PackageBuildStatus {
+        match result {
+            Ok(status) => status,
+            Err(_) if self.control().is_cancelled() => PackageBuildStatus::Cancelled,
+            Err(e) => PackageBuildStatus::Failed(e.to_string()),
+        }
+    }
 
+    /// `Success` or `DryRun`
     fn build_static(
+        &self,
         flake_pkg_url: &str,
-        status: &RwLockWrapper<PackageBuildStatus>,
-    ) -> Result<String, Box<dyn std::error::Error>> {
-        status
-            .0
-            .write()
-            .unwrap()
-            .clone_from(&PackageBuildStatus::WaitingForBuild);
-        Semaphore::get_sem().execute(|| {
-            status
-                .0
-                .write()
-                .unwrap()
-                .clone_from(&PackageBuildStatus::Building);
-            println!("BUILD\t{}", flake_pkg_url);
-            let output = std::process::Command::new("nix")
-                .arg("build")
+    ) -> Result<PackageBuildStatus, Box<dyn std::error::Error>> {
+        // packages found while shutting down were missed by the cancel
+        if shutdown::requested() {
+            self.control().cancel();
+        }
+        // held until the build ends, even if the repo is removed meanwhile
+        let Some(repo) = self.repo() else {
+            return Err("the repo was removed".into());
+        };
+        loop {
+            if !self.control().is_forced() {
+                build_window::wait(self.commit_secs(), self.control(), || {
+                    self.set_status(PackageBuildStatus::WaitingForWindow)
+                });
+            }
+            if self.control().is_cancelled() {
+                return Err("Build cancelled".into());
+            }
+            self.set_status(PackageBuildStatus::WaitingForBuild);
+            // the repo slot first and the global one second, always in this order
+            let Some(_repo_slot) = repo.running_builds.acquire(self.control()) else {
+                return Err("Build cancelled".into());
+            };
+            let result = Semaphore::get_sem().execute(self.queue_entry(), || {
+                if self.control().is_cancelled() {
+                    return Some(Err("Build cancelled".into()));
+                }
+                // the window closed while the build waited for a slot
+                if !self.control().is_forced() && !build_window::is_open() {
+                    return None;
+                }
+                Some(self.run_nix_build(flake_pkg_url))
+            });
+            if let Some(result) = result {
+                return result;
+            }
+        }
+    }
+
+    fn run_nix_build(
+        &self,
+        flake_pkg_url: &str,
+    ) -> Result<PackageBuildStatus, Box<dyn std::error::Error>> {
+        let dry_run = dry_run::enabled();
+        self.set_status(PackageBuildStatus::Building);
+        match dry_run {
+            true => tracing::info!("Dry running {}", flake_pkg_url),
+            false => tracing::info!("Building {}", flake_pkg_url),
+        }
+        let log = self.log_path().and_then(|path| {
+            let compress = self.repo().is_some_and(|repo| repo.settings.compress_logs);
+            match logs::LogWriter::create(&path, compress) {
+                Ok(log) => Some(log),
+                Err(e) => {
+                    tracing::error!("creating log {}: {}", path.display(), e);
+                    None
+                }
+            }
+        });
+        let mut command = std::process::Command::new("nix");
+        command.arg("build");
+        match dry_run {
+            true => command.arg("--dry-run"),
+            false => command
                 .arg("--no-link")
                 .arg("--print-out-paths")
-                .arg(&flake_pkg_url)
-                .output()?;
+                .arg("--print-build-logs"),
+        };
+        let child = command
+            .arg(flake_pkg_url)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let Some(output) = cancel::wait_cancellable(child, self.control(), log, None)? else {
+            tracing::info!("Cancelled {}", flake_pkg_url);
+            return Err("Build cancelled".into());
+        };
 
-            if output.status.code().unwrap_or(-1) != 0 {
-                let build_error = String::from_utf8_lossy(&output.stderr);
-                println!("ERROR\t{} -> {}", flake_pkg_url, build_error);
-                return Err(build_error.into());
-            }
+        if output.status.code().unwrap_or(-1) != 0 {
+            let build_error = String::from_utf8_lossy(&output.stderr);
+            tracing::error!("build of {} failed: {}", flake_pkg_url, build_error);
+            // the full log is kept in the log file
+            return Err(last_lines(&build_error, FAILURE_LOG_LINES).into());
+        }
 
-            let build_output = String::from_utf8_lossy(&output.stdout);
-            let build_output = build_output.trim();
-            println!("RESULT\t{} -> {}", flake_pkg_url, build_output);
-            Ok(build_output.to_string())
-        })
+        if dry_run {
+            let (to_build, to_substitute) =
+                dry_run::count(&String::from_utf8_lossy(&output.stderr));
+            tracing::info!(
+                "Dry run of {}: {} to build, {} to fetch",
+                flake_pkg_url,
+                to_build,
+                to_substitute
+            );
+            return Ok(PackageBuildStatus::DryRun {
+                to_build,
+                to_substitute,
+            });
+        }
+        let build_output = String::from_utf8_lossy(&output.stdout);
+        let build_output = build_output.trim();
+        tracing::info!("Built {} -> {}", flake_pkg_url, build_output);
+        Ok(PackageBuildStatus::Success(build_output.to_string()))
     }
 }
 
@@ -605,170 +1302,733 @@ impl PackageBase for NixosConfigPackage {
             flake_url: format!("{}#{}", commit.flake_url, path),
             path,
             status: RwLockWrapper::new(PackageBuildStatus::Idle),
+            times: RwLockWrapper::new(BuildTimes::default()),
+            closure_size: RwLockWrapper::new(None),
+            expected_secs: RwLockWrapper::new(None),
+            result_missing: RwLockWrapper::new(false),
+            pushes: RwLockWrapper::new(Vec::new()),
+            attempts: RwLockWrapper::new(Vec::new()),
+            commit: commit.clone(),
+            control: BuildControl::default(),
         }))
     }
 
-    fn build(self: Arc<Self>) {
-        thread::spawn(move || {
-            *self.status.0.write().unwrap() = PackageBuildStatus::Building;
+    fn commit(&self) -> &Arc<CommitInfo> {
+        &self.commit
+    }
 
-            match Self::build_static(self.flake_url.as_str(), &self.status) {
-                Ok(path) => {
-                    *self.status.0.write().unwrap() = PackageBuildStatus::Success(path);
-                }
-                Err(e) => {
-                    *self.status.0.write().unwrap() = PackageBuildStatus::Failed(e.to_string());
-                }
-            };
-        });
+    fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn flake_url(&self) -> &str {
+        &self.flake_url
+    }
+
+    fn status_lock(&self) -> &RwLockWrapper<PackageBuildStatus> {
+        &self.status
+    }
+
+    fn times(&self) -> &RwLockWrapper<BuildTimes> {
+        &self.times
+    }
+
+    fn closure_size(&self) -> &RwLockWrapper<Option<u64>> {
+        &self.closure_size
+    }
+
+    fn expected_secs(&self) -> &RwLockWrapper<Option<u64>> {
+        &self.expected_secs
+    }
+
+    fn result_missing(&self) -> &RwLockWrapper<bool> {
+        &self.result_missing
+    }
+
+    fn pushes(&self) -> &RwLockWrapper<Vec<PushStatus>> {
+        &self.pushes
+    }
+
+    fn attempts(&self) -> &RwLockWrapper<Vec<BuildAttempt>> {
+        &self.attempts
+    }
+
+    fn control(&self) -> &BuildControl {
+        &self.control
+    }
+
+    fn duration_key(&self) -> DurationKey {
+        DurationKey {
+            repo: self.commit.repo_url(),
+            name: self.path.clone(),
+            arch: String::new(),
+        }
     }
 }
 
-static mut BUILD_REPOS: RepoList = RepoList(VecArcWrapper(Vec::new()));
+/// How much of a failed build's output is kept in its status
+const FAILURE_LOG_LINES: usize = 40;
 
-pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let config_path = args().nth(1).ok_or("No config Path Specified")?;
-    let settings = {
-        let config_data = std::fs::read_to_string(&config_path)?;
-        Arc::new(serde_json::from_str::<AutoBuildOptions>(&config_data)?)
-    };
+fn last_lines(text: &str, count: usize) -> String {
+    let lines = text.trim_end().lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
 
-    let build_pool_size = if settings.n_build_threads == 0 {
-        num_cpus::get() as usize
-    } else {
-        settings.n_build_threads as usize
+/// The tracked repos, app data of the server and handed to the threads that need
+/// them. A reload replaces the list, readers keep the one they already took.
+#[derive(Clone)]
+pub struct BuildRepos(Arc<RwLock<RepoList>>);
+
+impl Default for BuildRepos {
+    fn default() -> Self {
+        BuildRepos(Arc::new(RwLock::new(RepoList(VecArcWrapper(Vec::new())))))
+    }
+}
+
+impl BuildRepos {
+    /// Replace the tracked repos
+    pub fn set(&self, repos: RepoList) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = repos;
+    }
+
+    /// Cheap copy of the tracked repos, only the `Arc`s are cloned
+    pub fn snapshot(&self) -> RepoList {
+        self.0
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Look up a tracked repo by its name, its url, its url with `/` replaced by `_`
+    /// (as used for the checkout directory by older versions) or the last segment of
+    /// its url
+    pub fn find(&self, name: &str) -> Option<Arc<RepoInfo>> {
+        let build_repos = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        let repos = build_repos.0.inner();
+        // a name wins over the url of another repo ending in it
+        repos
+            .iter()
+            .find(|repo| repo.repo.name() == name)
+            .or_else(|| repos.iter().find(|repo| repo_matches(&repo.repo, name)))
+            .cloned()
+    }
+}
+
+/// Directory of the checkout of `repo` below `checkouts_dir`, named by
+/// `checkout_name_template`. A checkout of an earlier name, named after the repo or
+/// after the url as by older versions, and its logs are moved there first.
+pub(crate) fn checkout_path(settings: &AutoBuildOptions, repo: &Repo) -> PathBuf {
+    let repo_dir = settings.checkouts_dir();
+    // validated before, the fallback only keeps the repo working
+    let name = paths::checkout_name(settings.checkout_name_template.as_deref(), repo)
+        .unwrap_or_else(|_| repo.name());
+    let path = repo_dir.join(&name);
+    let legacy = repo.url.replace("/", "_").replace(":", "_");
+    let earlier = [repo.name(), legacy]
+        .into_iter()
+        .filter(|earlier| *earlier != name)
+        .find(|earlier| repo_dir.join(earlier).exists());
+    if let Some(earlier) = earlier
+        && !path.exists()
+    {
+        tracing::info!(
+            "Moving the checkout {} of {} to {}",
+            earlier,
+            repo.url,
+            name
+        );
+        let logs = settings.logs_dir();
+        for (from, to) in [
+            (repo_dir.join(&earlier), path.clone()),
+            (logs.join(&earlier), logs.join(&name)),
+        ] {
+            if from.exists()
+                && let Err(e) = std::fs::rename(&from, &to)
+            {
+                tracing::error!("moving {} to {}: {}", from.display(), to.display(), e);
+            }
+        }
+    }
+    path
+}
+
+pub async fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    match <cli::Cli as clap::Parser>::parse().command {
+        cli::Command::Serve { config } => serve(config).await.map(|()| ExitCode::SUCCESS),
+        cli::Command::Check { config } => cli::check(&config),
+        cli::Command::BuildOnce {
+            config,
+            repo,
+            rev,
+            dry_run,
+        } => build_once::run(&config, repo.as_deref(), rev.as_deref(), dry_run),
+        cli::Command::ShowConfig { config, format } => cli::show_config(&config, format),
+        cli::Command::GenerateModule { out_dir, check } => nixos_module::generate(&out_dir, check),
+        cli::Command::ShowSchema => schema::show(),
+        cli::Command::Import {
+            config,
+            file,
+            force,
+        } => export::import(&config, &file, force),
+    }
+}
+
+/// Threads building at the same time, one per CPU unless configured
+fn build_pool_size(settings: &AutoBuildOptions) -> usize {
+    match settings.n_build_threads {
+        0 => num_cpus::get(),
+        n => n,
+    }
+}
+
+async fn serve(config_path: String) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Arc::new(logging::init_from_config(&config_path)?);
+    for warning in validate::validate(&settings, settings.server.enabled)? {
+        tracing::warn!("config {}", warning);
+    }
+
+    // load the certificate before anything else so a broken TLS setup fails fast
+    let tls_cert = match &settings.tls {
+        Some(tls) if settings.server.enabled => {
+            let cert = tls::ReloadableCert::new(tls.clone())?;
+            if tls.reload_on_sighup {
+                cert.reload_on_sighup()?;
+            }
+            Some(cert)
+        }
+        _ => None,
     };
 
-    let build_sem = Arc::new(Semaphore::init(build_pool_size as usize));
+    Semaphore::init(build_pool_size(&settings));
+    build_window::set(settings.build_window.as_ref())?;
+    dry_run::set(settings.dry_run);
+    post_build::configure(&settings.post_build);
+
+    // before the checkouts are looked for in them
+    paths::create_dirs(&settings)?;
+    let saved_state = match settings.state.backend.as_str() {
+        "sqlite" => {
+            database::open(&settings);
+            durations::load(database::stored_durations().unwrap_or_default());
+            builder_info::load(database::stored_builders().unwrap_or_default());
+            None
+        }
+        "json" => state_file::load(&settings),
+        _ => None,
+    };
+    if let Some(saved_state) = &saved_state {
+        state_file::restore_durations(saved_state);
+        state_file::restore_builders(saved_state);
+    }
+    builder_info::capture();
 
-    let repo_dir = settings.dir.join("repos");
+    let build_repos = BuildRepos::default();
+    notify::start(&settings, build_repos.clone());
 
-    let build_repos = RepoList(VecArcWrapper::from(
+    let repos = RepoList(VecArcWrapper::from(
         settings
             .repos
             .par_iter()
             .map(|repo| {
                 let repo_info = RepoInfo::new(
                     repo.clone(),
-                    repo_dir.join(&repo.url.replace("/", "_").replace(":", "_")),
+                    checkout_path(&settings, repo),
                     settings.clone(),
                 );
-                thread::spawn({
-                    let repo_info = repo_info.clone();
-                    move || {
-                        repo_info.thread_poll();
-                    }
-                });
+                if let Some(saved_state) = &saved_state {
+                    state_file::restore(&repo_info, saved_state);
+                }
+                reload::start_poller(repo_info.clone());
                 repo_info
             })
             .collect::<Vec<_>>(),
     ));
 
-    // create data directory
-    std::fs::create_dir_all(&settings.dir)?;
-    std::fs::create_dir_all(&repo_dir)?;
+    build_repos.set(repos);
+    if settings.state.backend == "json" {
+        state_file::start(&settings, build_repos.clone());
+    }
+    log_retention::start(settings.clone(), build_repos.clone());
+    result_check::start(settings.clone(), build_repos.clone());
+    let reloader = reload::Reloader {
+        config_path,
+        settings: settings.clone(),
+        repos: build_repos.clone(),
+    };
+    reloader.clone().reload_on_sighup()?;
+    if settings.watch_config {
+        reloader.watch()?;
+    }
 
-    unsafe {
-        BUILD_REPOS = build_repos;
+    if settings.server.enabled {
+        run_server(settings.clone(), tls_cert, build_repos.clone()).await?;
+    } else {
+        // the repo threads keep polling and building until a signal stops them
+        tracing::info!("The HTTP server is disabled, only polling and building");
+        shutdown::signal_received().await?;
     }
 
-    println!(
-        "Starting server on http://{}:{}",
-        settings.host, settings.port
-    );
-    println!("Serving static files from: {}", FRONTEND_PATH);
-    HttpServer::new(|| {
-        App::new()
-            .service(repos)
-            .service(nix_store_files)
-            .service(store_files)
-            .service(static_files)
-    })
-    .bind((settings.host.as_str(), settings.port))?
-    .run()
-    .await?;
+    let grace = std::time::Duration::from_secs(settings.shutdown_grace_secs);
+    shutdown::request(&build_repos);
+    if !actix_web::rt::task::spawn_blocking(move || shutdown::wait_for_builds(grace)).await? {
+        return Err("builds did not stop within the grace period".into());
+    }
+    tracing::info!("Shutdown complete");
+    Ok(())
+}
 
+/// Serve the dashboard, the API and the binary cache until a shutdown signal
+async fn run_server(
+    settings: Arc<AutoBuildOptions>,
+    tls_cert: Option<Arc<tls::ReloadableCert>>,
+    build_repos: BuildRepos,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tcp_address = settings.tcp_address();
+    let unix_socket = settings
+        .listen
+        .as_ref()
+        .and_then(|listen| listen.unix.as_ref());
+    if let Some((host, port)) = &tcp_address {
+        tracing::info!(
+            "Starting server on {}://{}:{}",
+            if tls_cert.is_some() { "https" } else { "http" },
+            host,
+            port
+        );
+    }
+    if let Some(socket) = unix_socket {
+        // always plain HTTP, TLS is up to the proxy in front of it
+        tracing::info!("Starting server on unix:{}", socket.path.display());
+    }
+    let frontend_dir = static_files::frontend_dir(&settings);
+    tracing::info!("Serving static files from {}", frontend_dir.display());
+    let frontend_dir = actix_web::web::Data::new(static_files::FrontendDir(frontend_dir));
+    let prefix = settings.route_prefix();
+    if !prefix.is_empty() {
+        tracing::info!("Serving below {}", prefix);
+    }
+    // shared by all workers, so the limits apply per server and not per worker
+    let rate_limiter = Arc::new(rate_limit::RateLimiter::new(
+        &settings.rate_limits,
+        prefix.clone(),
+    ));
+    let access_log = Arc::new(access_log::AccessLog::new(&settings.access_log));
+    let cache_key = settings.binary_cache.as_ref().and_then(cache::signing_key);
+    let hook_secrets = push_hooks::HookSecrets::new(&settings.webhooks);
+    let server = HttpServer::new({
+        let settings = settings.clone();
+        let build_repos = build_repos.clone();
+        move || {
+            let handler_settings = settings.clone();
+            let rate_limiter = rate_limiter.clone();
+            let access_log = access_log.clone();
+            App::new()
+                .app_data(actix_web::web::Data::from(settings.clone()))
+                .app_data(actix_web::web::Data::new(build_repos.clone()))
+                .app_data(frontend_dir.clone())
+                .configure(|cfg| {
+                    // relative asset paths of the frontend need the trailing slash
+                    if !prefix.is_empty() {
+                        cfg.service(
+                            actix_web::web::resource(prefix.as_str())
+                                .to(static_files::redirect_to_slash),
+                        );
+                    }
+                })
+                .service(
+                    actix_web::web::scope(&prefix)
+                        .configure(|cfg| api::configure(cfg, &prefix, hook_secrets.clone()))
+                        .service(badge::repo_badge)
+                        .service(badge::branch_badge)
+                        .service(feed::feed)
+                        .service(feed::repo_feed)
+                        .service(store::nix_store_files)
+                        .service(store::store_files)
+                        .configure(|cfg| {
+                            if let Some(options) = &settings.binary_cache {
+                                cache::configure(cfg, options.clone(), cache_key.clone());
+                            }
+                        })
+                        .service(static_files::static_files),
+                )
+                .wrap_fn(move |req, srv| {
+                    let res = match rate_limiter.retry_after(&req) {
+                        None => Ok(srv.call(req)),
+                        Some(retry_after) => Err((req, retry_after)),
+                    };
+                    async move {
+                        match res {
+                            Ok(res) => Ok(res.await?.map_into_left_body()),
+                            Err((req, retry_after)) => Ok(req
+                                .into_response(rate_limit::too_many_requests(retry_after))
+                                .map_into_right_body()),
+                        }
+                    }
+                })
+                .wrap_fn(move |req, srv| {
+                    let settings = handler_settings.clone();
+                    let res = srv.call(req);
+                    async move {
+                        let mut res = res.await?;
+                        compress::skip_excluded(&mut res, &settings.compress_exclude_content_types);
+                        Ok(res)
+                    }
+                })
+                .wrap(Condition::new(
+                    settings.compress_responses,
+                    Compress::default(),
+                ))
+                .wrap(Condition::new(
+                    !settings.cors_allowed_origins.is_empty(),
+                    cors::cors(&settings.cors_allowed_origins),
+                ))
+                // outermost, so it sees the final status and size
+                .wrap_fn(move |req, srv| {
+                    let entry = access_log.start(&req);
+                    let res = srv.call(req);
+                    async move {
+                        let res = res.await;
+                        if let Some(entry) = entry {
+                            match &res {
+                                Ok(res) => entry.finish(res),
+                                Err(e) => entry.failed(e),
+                            }
+                        }
+                        res
+                    }
+                })
+        }
+    });
+    let mut server = match (&tcp_address, &tls_cert) {
+        (Some(address), Some(cert)) => {
+            server.bind_rustls_0_23((address.0.as_str(), address.1), cert.server_config()?)?
+        }
+        (Some(address), None) => server.bind((address.0.as_str(), address.1))?,
+        (None, _) => server,
+    };
+    if let Some(socket) = unix_socket {
+        unix_socket::prepare(socket)?;
+        server = server.bind_uds(&socket.path)?;
+        unix_socket::secure(socket)?;
+    }
+    let server = server
+        // the signals also have to stop the builds
+        .disable_signals()
+        .shutdown_timeout(settings.shutdown_grace_secs)
+        .run();
+    let handle = server.handle();
+    actix_web::rt::spawn(async move {
+        if let Err(e) = shutdown::signal_received().await {
+            tracing::error!("listening for shutdown signals: {}", e);
+            return;
+        }
+        shutdown::request(&build_repos);
+        // open requests may finish, live streams end after the shutdown event
+        handle.stop(true).await;
+    });
+    server.await?;
     Ok(())
 }
 
-#[allow(static_mut_refs)]
-#[get("/repos")]
-async fn repos() -> impl Responder {
-    println!("INFO\tRequested repo info");
-    let json = serde_json::to_string_pretty(unsafe { &BUILD_REPOS }).unwrap();
-    // Append Access-Control-Allow-Origin for browsers during development.
-    HttpResponse::Ok().body(json)
+async fn repos(repos: actix_web::web::Data<BuildRepos>) -> impl Responder {
+    tracing::debug!("Requested repo info");
+    HttpResponse::Ok().json(repos.snapshot())
 }
 
-async fn server_nix_file(path: String) -> actix_web::Result<HttpResponse> {
-    println!("INFO\tRequested nix file: {}", path);
+#[derive(serde::Deserialize, Debug)]
+struct PollQuery {
+    /// evaluate without the eval cache, see [`RepoInfoTrait::get_or_create_commit`]
+    #[serde(default)]
+    force_eval: bool,
+}
 
-    let metadata = match std::fs::metadata(&path) {
-        Ok(meta) => meta,
-        Err(_) => return Err(actix_web::error::ErrorNotFound("404 Not Found")),
+/// Wake the polling thread of a repo instead of waiting out its poll interval
+#[post("/repos/{name}/poll")]
+async fn poll_repo(
+    path: actix_web::web::Path<String>,
+    query: actix_web::web::Query<PollQuery>,
+    repos: actix_web::web::Data<BuildRepos>,
+) -> HttpResponse {
+    let name = path.into_inner();
+    tracing::debug!("Requested poll of {} {:?}", name, query);
+    let Some(repo) = repos.find(&name) else {
+        return HttpResponse::NotFound().body("404 Not Found");
     };
-
-    if metadata.is_file() {
-        match std::fs::read(&path) {
-            Ok(contents) => Ok(HttpResponse::Ok().body(contents)),
-            Err(_) => Err(actix_web::error::ErrorNotFound("404 Not Found")),
-        }
-    } else if metadata.is_dir() {
-        match std::fs::read_dir(&path) {
-            Ok(entries) => {
-                let mut listing = String::from("<html><body><h1>Directory listing</h1><ul>");
-                for entry in entries.flatten() {
-                    if let Ok(name) = entry.file_name().into_string() {
-                        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
-                        let suffix = if is_dir { "/" } else { "" };
-                        listing.push_str(&format!(
-                            "<li><a href=\"{}/{}{}\">{}{}</a></li>",
-                            path, name, suffix, name, suffix
-                        ));
-                    }
-                }
-                listing.push_str("</ul></body></html>");
-                Ok(HttpResponse::Ok().content_type("text/html").body(listing))
-            }
-            Err(_) => Err(actix_web::error::ErrorInternalServerError(
-                "Failed to read directory",
-            )),
+    match repo.status.get() {
+        RepoStatus::Idle if query.force_eval => {
+            repo.poll_trigger.trigger_eval();
+            HttpResponse::Accepted().json(RepoStatus::Idle)
         }
-    } else {
-        Err(actix_web::error::ErrorNotFound("404 Not Found"))
+        RepoStatus::Idle => {
+            repo.poll_trigger.trigger();
+            HttpResponse::Accepted().json(RepoStatus::Idle)
+        }
+        // cloning, opening, pulling or polling already
+        _ => HttpResponse::Ok().json("already_polling"),
     }
 }
 
-#[get("/nix/store{path:.*}")]
-// serve file if available or list the nix store directory
-async fn nix_store_files(path: actix_web::web::Path<String>) -> actix_web::Result<HttpResponse> {
-    let full_path = format!("/nix/store{}", path.into_inner());
-    server_nix_file(full_path).await
-}
-
-#[get("/store{path:.*}")]
-// serve file if available or list the nix store directory
-async fn store_files(path: actix_web::web::Path<String>) -> actix_web::Result<HttpResponse> {
-    let full_path = format!("/nix/store{}", path.into_inner());
-    server_nix_file(full_path).await
+pub(crate) fn repo_matches(repo: &Repo, name: &str) -> bool {
+    let url = &repo.url;
+    repo.name() == name
+        || url == name
+        || url.replace("/", "_") == name
+        || url.rsplit('/').next() == Some(name)
 }
 
-#[get("/{path:.*}")]
-async fn static_files(
-    path: actix_web::web::Path<String>,
-) -> actix_web::Result<actix_files::NamedFile> {
-    let file_path = if path.is_empty() {
-        "index.html".to_string()
-    } else {
-        path.into_inner()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::MAX_ATTEMPTS;
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service, read_body},
+        web,
     };
-    println!("INFO\tRequested static file: {}", file_path);
-    // TODO: Sanitize file_path to prevent directory traversal attacks
-
-    let full_path = format!("{}/{}", FRONTEND_PATH, file_path);
-    println!("INFO\tFull static file path: {}", full_path);
-    match actix_files::NamedFile::open_async(full_path).await {
-        Ok(named_file) => Ok(named_file.use_last_modified(true)),
-        Err(_) => Err(actix_web::error::ErrorNotFound("404 Not Found")),
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    fn entry(commit: &str) -> QueueEntry {
+        QueueEntry {
+            repo: "github.com/org/repo".to_string(),
+            commit: commit.to_string(),
+            path: None,
+        }
+    }
+
+    /// A tracked package that was never built, of a flake nix fails on right away
+    fn package(dir: &Path) -> Result<(Arc<RepoInfo>, Arc<Package>), Box<dyn std::error::Error>> {
+        let settings = serde_json::from_value::<AutoBuildOptions>(serde_json::json!({
+            "repos": [],
+            "dir": dir,
+            "supported_architectures": ["x86_64-linux"]
+        }))?;
+        let repo = RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"],
+                "max_concurrent_builds": 1
+            }))?,
+            dir.join("checkout"),
+            Arc::new(settings),
+        );
+        let commit = Arc::new(CommitInfo {
+            flake_url: format!("path:{}", dir.join("missing").display()),
+            hash: "abc123".to_string(),
+            message: "Flaky".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(&repo)),
+            unix_secs: 0,
+            diff_stats: None,
+        });
+        let stored = database::StoredPackage {
+            path: "packages.x86_64-linux.hello".to_string(),
+            nixos_config: false,
+            name: "hello".to_string(),
+            description: String::new(),
+            pkg_type: "derivation".to_string(),
+            arch: "x86_64-linux".to_string(),
+            status: PackageBuildStatus::Idle,
+            times: BuildTimes::default(),
+            closure_size: None,
+            attempts: Vec::new(),
+        };
+        match stored.into_package(&commit) {
+            PackageEnum::Derivation(pkg) => Ok((repo, pkg.inner().clone())),
+            PackageEnum::NixosConfig(_) => Err("not a derivation".into()),
+        }
+    }
+
+    /// Wait for the build thread of `pkg` to finish
+    fn finished(pkg: &Package) -> bool {
+        for _ in 0..1000 {
+            if pkg.status().is_finished() {
+                return true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn every_build_is_one_attempt() -> Result<(), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("nix_autobuild_attempts_{}", std::process::id()));
+        let (_repo, pkg) = package(&dir)?;
+        pkg.clone().build();
+        assert!(finished(&pkg));
+        let attempts = pkg.attempts.get();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].number, 1);
+        assert!(attempts[0].finished.is_some());
+        assert!(matches!(pkg.status(), PackageBuildStatus::Failed(_)));
+
+        for _ in 0..11 {
+            assert!(pkg.clone().rebuild(false));
+            assert!(finished(&pkg));
+        }
+        let attempts = pkg.attempts.get();
+        assert_eq!(attempts.len(), MAX_ATTEMPTS);
+        assert_eq!(
+            attempts
+                .iter()
+                .map(|attempt| attempt.number)
+                .collect::<Vec<_>>(),
+            (3..=12).collect::<Vec<_>>()
+        );
+        assert!(attempts.iter().all(|attempt| attempt.finished.is_some()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn the_status_of_an_attempt_is_taken_from_it() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_status_{}", std::process::id()));
+        let (_repo, pkg) = package(&dir)?;
+        pkg.set_status(PackageBuildStatus::Building);
+        pkg.set_status(PackageBuildStatus::Failed("flaky".to_string()));
+        // e.g. a snapshot that did not keep up with the attempts
+        pkg.status.set(PackageBuildStatus::Success(
+            "/nix/store/abc-hello".to_string(),
+        ));
+        assert_eq!(
+            pkg.status(),
+            PackageBuildStatus::Failed("flaky".to_string())
+        );
+        pkg.set_status(PackageBuildStatus::WaitingForBuild);
+        assert_eq!(pkg.status(), PackageBuildStatus::WaitingForBuild);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn a_queued_build_is_cancelled_without_a_kill() -> Result<(), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("nix_autobuild_dequeue_{}", std::process::id()));
+        let (repo, pkg) = package(&dir)?;
+        let slot = repo.running_builds.acquire(&BuildControl::default());
+        pkg.clone().build();
+        while pkg.status() != PackageBuildStatus::WaitingForBuild {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(pkg.cancel(), CancelOutcome::Cancelled);
+        assert_eq!(pkg.status(), PackageBuildStatus::Cancelled);
+        assert_eq!(pkg.cancel(), CancelOutcome::AlreadyFinished);
+        // the build thread stops waiting for the slot
+        std::thread::sleep(std::time::Duration::from_millis(400));
+        assert_eq!(repo.running_builds.running(), 1);
+        drop(slot);
+        // nor does a build of the commit started after the cancel
+        pkg.clone().build();
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert_eq!(pkg.status(), PackageBuildStatus::Cancelled);
+        let attempts = pkg.attempts.get();
+        assert_eq!(attempts.len(), 1);
+        assert_eq!(attempts[0].log_ref, None);
+        let _ = std::fs::remove_dir_all(&dir);
+        Ok(())
+    }
+
+    #[test]
+    fn the_pool_runs_no_more_than_its_slots() {
+        let pool = Semaphore::new(2);
+        let (running, most) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        std::thread::scope(|scope| {
+            for index in 0..8 {
+                let (pool, running, most) = (&pool, &running, &most);
+                scope.spawn(move || {
+                    pool.execute(entry(&index.to_string()), || {
+                        let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                        most.fetch_max(now, Ordering::SeqCst);
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                });
+            }
+        });
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+        let queue = pool.queue();
+        assert_eq!((queue.running, queue.waiting.len()), (0, 0));
+    }
+
+    #[test]
+    fn a_panicking_build_gives_its_slot_back() {
+        let pool = Semaphore::new(1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            pool.execute(entry("a"), || {
+                std::panic::resume_unwind(Box::new("the build panicked"))
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(pool.queue().running, 0);
+        // would wait forever for the slot otherwise
+        assert_eq!(pool.execute(entry("b"), || 42), 42);
+    }
+
+    #[test]
+    fn repo_slots_are_left_on_a_cancel_and_kept_over_a_reload() {
+        let old = BuildSlots::new(Some(1));
+        let held = old.acquire(&BuildControl::default());
+        let control = Arc::new(BuildControl::default());
+        let waiting = {
+            let (new, control) = (BuildSlots::new(Some(1)), control.clone());
+            new.take_over(&old);
+            std::thread::spawn(move || new.acquire(&control).is_some())
+        };
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        control.cancel();
+        assert!(matches!(waiting.join(), Ok(false)));
+        drop(held);
+        assert_eq!(old.running(), 0);
+    }
+
+    #[actix_web::test]
+    async fn repos_stay_readable_while_they_are_replaced() -> Result<(), Box<dyn std::error::Error>>
+    {
+        let settings = Arc::new(serde_json::from_value::<AutoBuildOptions>(
+            serde_json::json!({ "repos": [] }),
+        )?);
+        let mut available = Vec::new();
+        for name in ["a", "b", "c"] {
+            let repo = serde_json::from_value::<Repo>(serde_json::json!({
+                "url": format!("github.com/org/{}", name),
+                "branches": ["main"]
+            }))?;
+            available.push(RepoInfo::new(
+                repo,
+                PathBuf::from("/nonexistent/repo"),
+                settings.clone(),
+            ));
+        }
+        let build_repos = BuildRepos::default();
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (build_repos, done) = (build_repos.clone(), done.clone());
+            std::thread::spawn(move || {
+                let mut count = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let repos = available[..count % (available.len() + 1)].to_vec();
+                    build_repos.set(RepoList(VecArcWrapper::from(repos)));
+                    count += 1;
+                }
+            })
+        };
+
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(build_repos.clone()))
+                .route("/repos", web::get().to(repos)),
+        )
+        .await;
+        for _ in 0..500 {
+            let res = call_service(&app, TestRequest::get().uri("/repos").to_request()).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            let body = serde_json::from_slice::<Value>(&read_body(res).await)?;
+            assert!(body.as_array().is_some_and(|repos| repos.len() <= 3));
+        }
+        done.store(true, Ordering::Relaxed);
+        assert!(writer.join().is_ok());
+        Ok(())
     }
 }
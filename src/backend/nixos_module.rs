@@ -0,0 +1,303 @@
+//! `generate-module`: the NixOS module of the options, written to
+//! `bindings/autoBuildOptionsType.nix` and imported by `autobuildModule.nix`.
+//!
+//! serde-nixos only defines the submodules of the fields of `AutoBuildOptions`
+//! itself, deeper ones are left as `types.submodule { /* Name options */ }`
+//! placeholders, which do not evaluate. The fix-ups below define those as well,
+//! refer to every submodule by name and restrict the notification `events` to the
+//! known names. The sections come out sorted, so the file only changes with the
+//! options.
+
+use std::{path::Path, process::ExitCode};
+
+use crate::{
+    AtticOptions, AutoBuildOptions, CachixOptions, ChatWebhookOptions, EmailOptions, MatrixOptions,
+    NOTIFICATION_EVENTS, SecretSource, TcpListenOptions, UnixSocketOptions, WebhookOptions,
+};
+
+/// The module file of `AutoBuildOptions`
+pub fn render() -> String {
+    let nested = [
+        (
+            SecretSource::nixos_type_name(),
+            SecretSource::nixos_options(),
+        ),
+        (
+            TcpListenOptions::nixos_type_name(),
+            TcpListenOptions::nixos_options(),
+        ),
+        (
+            UnixSocketOptions::nixos_type_name(),
+            UnixSocketOptions::nixos_options(),
+        ),
+        (
+            CachixOptions::nixos_type_name(),
+            CachixOptions::nixos_options(),
+        ),
+        (
+            AtticOptions::nixos_type_name(),
+            AtticOptions::nixos_options(),
+        ),
+        (
+            WebhookOptions::nixos_type_name(),
+            WebhookOptions::nixos_options(),
+        ),
+        (
+            MatrixOptions::nixos_type_name(),
+            MatrixOptions::nixos_options(),
+        ),
+        (
+            ChatWebhookOptions::nixos_type_name(),
+            ChatWebhookOptions::nixos_options(),
+        ),
+        (
+            EmailOptions::nixos_type_name(),
+            EmailOptions::nixos_options(),
+        ),
+    ];
+    let nix = define_nested(&AutoBuildOptions::nixos_type_full_definition(), &nested);
+    let nix = restrict_to_enum(&refer_to_submodules(&nix), "events", &NOTIFICATION_EVENTS);
+    format!("{{lib, ...}}: let\n  types = lib.types;\nin {}", nix)
+}
+
+/// Where `render` goes below `out_dir`
+pub fn file_name() -> String {
+    format!("{}.nix", AutoBuildOptions::nixos_type_name())
+}
+
+/// Write the module to `out_dir`, or with `check` only compare it with the file
+/// there and fail when they differ
+pub fn generate(out_dir: &Path, check: bool) -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let path = out_dir.join(file_name());
+    let module = render();
+    if check {
+        return match std::fs::read_to_string(&path) {
+            Ok(existing) if existing == module => Ok(ExitCode::SUCCESS),
+            Ok(_) => {
+                eprintln!(
+                    "{} is outdated, run `nix_autobuild generate-module`",
+                    path.display()
+                );
+                Ok(ExitCode::FAILURE)
+            }
+            Err(e) => Err(format!("cannot read {}: {}", path.display(), e).into()),
+        };
+    }
+    std::fs::create_dir_all(out_dir)
+        .map_err(|e| format!("cannot create {}: {}", out_dir.display(), e))?;
+    std::fs::write(&path, module).map_err(|e| format!("cannot write {}: {}", path.display(), e))?;
+    eprintln!("Wrote {}", path.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Add a `let` binding for each `(type name, options)` that `nix` does not define
+/// yet and sort all bindings but the last, the type of the module
+fn define_nested(nix: &str, nested: &[(&str, String)]) -> String {
+    let Some(body) = nix.strip_prefix("let\n") else {
+        return nix.to_string();
+    };
+    let mut sections = Vec::new();
+    let mut section = String::new();
+    let mut tail = String::new();
+    for line in body.split_inclusive('\n') {
+        if !tail.is_empty() || line.starts_with("in ") {
+            tail.push_str(line);
+            continue;
+        }
+        section.push_str(line);
+        if line == "  };\n" {
+            sections.push(std::mem::take(&mut section));
+        }
+    }
+    // the type of the module itself
+    let root = sections.pop();
+
+    for (name, options) in nested {
+        if sections
+            .iter()
+            .any(|section| section.starts_with(&format!("  {} = ", name)))
+        {
+            continue;
+        }
+        let mut section = format!("  {} = types.submodule {{\n    options = {{\n", name);
+        for line in options.lines() {
+            if !line.is_empty() {
+                section.push_str("    ");
+                section.push_str(line);
+            }
+            section.push('\n');
+        }
+        section.push_str("    };\n  };\n");
+        sections.push(section);
+    }
+    sections.sort();
+    sections.extend(root);
+    format!("let\n{}{}", sections.concat(), tail)
+}
+
+/// Replace the placeholders of the submodules defined in `nix` by references and
+/// turn their definitions into plain modules
+fn refer_to_submodules(nix: &str) -> String {
+    let type_names = nix
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("  ")?
+                .strip_suffix("Type = types.submodule {")
+        })
+        .map(String::from)
+        .collect::<Vec<_>>();
+    let mut nix = nix.to_string();
+    for name in type_names {
+        let mut chars = name.chars();
+        let Some(first) = chars.next() else {
+            continue;
+        };
+        let struct_name = format!("{}{}", first.to_uppercase(), chars.as_str());
+        let type_name = format!("{}Type", name);
+        nix = nix
+            .replace(
+                &format!("types.submodule {{ /* {} options */ }}", struct_name),
+                &format!("(types.submodule {})", type_name),
+            )
+            .replace(
+                &format!("  {} = types.submodule {{", type_name),
+                &format!("  {} = {{", type_name),
+            )
+            // direct references, e.g. `types.nullOr tlsOptionsType;`
+            .replace(
+                &format!(" {};\n", type_name),
+                &format!(" (types.submodule {});\n", type_name),
+            );
+    }
+    nix
+}
+
+/// `types.str` of every option named `option` as `types.enum` of `values`
+fn restrict_to_enum(nix: &str, option: &str, values: &[&str]) -> String {
+    let declaration = format!("{} = lib.mkOption {{", option);
+    let values = values
+        .iter()
+        .map(|value| format!("\"{}\"", value))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut in_option = false;
+    let mut lines = Vec::new();
+    for line in nix.lines() {
+        match line.trim_start().strip_prefix("type = ") {
+            Some(_) if in_option => {
+                lines.push(line.replacen("types.str", &format!("(types.enum [ {} ])", values), 1))
+            }
+            _ => lines.push(line.to_string()),
+        }
+        in_option = line.trim() == declaration;
+    }
+    lines.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What serde-nixos generates for a root with a direct `tls` and a `Vec<Repo>`
+    /// whose repos have a nested `SecretSource`
+    const FULL_DEFINITION: &str = "let
+  tlsOptionsType = types.submodule {
+    options = {
+      cert_file = lib.mkOption {
+        type = types.path;
+      };
+    };
+  };
+  repoType = types.submodule {
+    options = {
+      credentials = lib.mkOption {
+        type = types.nullOr types.submodule { /* SecretSource options */ };
+        default = null;
+      };
+      events = lib.mkOption {
+        type = types.listOf types.str;
+      };
+    };
+  };
+  rootType = types.submodule {
+    options = {
+    repos = lib.mkOption {
+      type = types.listOf repoType;
+    };
+    tls = lib.mkOption {
+      type = types.nullOr tlsOptionsType;
+    };
+    };
+  };
+in rootType
+";
+
+    #[test]
+    fn placeholders_become_references() {
+        let secret_options = "env = lib.mkOption {\n  type = types.nullOr types.str;\n};\n";
+        let nix = define_nested(
+            FULL_DEFINITION,
+            &[
+                ("secretSourceType", secret_options.to_string()),
+                // already defined
+                ("tlsOptionsType", String::new()),
+            ],
+        );
+        let nix = restrict_to_enum(&refer_to_submodules(&nix), "events", &["a", "b"]);
+        assert_eq!(
+            nix,
+            "let
+  repoType = {
+    options = {
+      credentials = lib.mkOption {
+        type = types.nullOr (types.submodule secretSourceType);
+        default = null;
+      };
+      events = lib.mkOption {
+        type = types.listOf (types.enum [ \"a\" \"b\" ]);
+      };
+    };
+  };
+  secretSourceType = {
+    options = {
+    env = lib.mkOption {
+      type = types.nullOr types.str;
+    };
+    };
+  };
+  tlsOptionsType = {
+    options = {
+      cert_file = lib.mkOption {
+        type = types.path;
+      };
+    };
+  };
+  rootType = {
+    options = {
+    repos = lib.mkOption {
+      type = types.listOf (types.submodule repoType);
+    };
+    tls = lib.mkOption {
+      type = types.nullOr (types.submodule tlsOptionsType);
+    };
+    };
+  };
+in rootType
+"
+        );
+    }
+
+    #[test]
+    fn committed_module_is_current() -> Result<(), Box<dyn std::error::Error>> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("bindings")
+            .join(file_name());
+        let module = render();
+        assert_eq!(module, render(), "the sections are not sorted");
+        assert!(
+            std::fs::read_to_string(path)? == module,
+            "bindings are outdated, run `cargo run -- generate-module`"
+        );
+        Ok(())
+    }
+}
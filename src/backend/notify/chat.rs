@@ -0,0 +1,198 @@
+use std::{sync::Arc, time::Duration};
+
+use serde_json::{Value, json};
+use ureq::Agent;
+
+use crate::{ChatWebhookOptions, backend::secrets};
+
+use super::{Notification, NotificationEvent, Notifier};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const FAILED_COLOR: u32 = 0xd73a49;
+const SUCCESS_COLOR: u32 = 0x28a745;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Kind {
+    Slack,
+    Discord,
+}
+
+/// Chat message for every selected notification, posted to a Slack or Discord webhook
+pub struct Chat {
+    kind: Kind,
+    url: String,
+    events: Vec<String>,
+    dashboard: Option<String>,
+    agent: Agent,
+}
+
+impl Chat {
+    pub fn new(
+        kind: Kind,
+        options: &ChatWebhookOptions,
+        dashboard: Option<&str>,
+    ) -> Result<Self, String> {
+        let url = match (&options.url, &options.url_secret) {
+            (_, Some(source)) => secrets::resolve(source)?.expose().to_string(),
+            (Some(url), None) => url.clone(),
+            (None, None) => return Err("needs `url` or `url_secret`".to_string()),
+        };
+        let chat = Chat {
+            kind,
+            url,
+            events: options.events.clone(),
+            dashboard: dashboard.map(String::from),
+            agent: Agent::config_builder()
+                .timeout_global(Some(TIMEOUT))
+                .build()
+                .into(),
+        };
+        super::check_events(&chat.describe(), &options.events);
+        Ok(chat)
+    }
+
+    fn payload(&self, notification: &Notification) -> Value {
+        let message = Message::new(notification, self.dashboard.as_deref());
+        match self.kind {
+            Kind::Slack => slack_payload(&message),
+            Kind::Discord => discord_payload(&message),
+        }
+    }
+}
+
+impl Notifier for Chat {
+    fn describe(&self) -> String {
+        let kind = match self.kind {
+            Kind::Slack => "Slack",
+            Kind::Discord => "Discord",
+        };
+        // only the host, the rest of the URL is the secret token
+        let host = self
+            .url
+            .split("://")
+            .nth(1)
+            .and_then(|rest| rest.split('/').next())
+            .unwrap_or_default();
+        format!("{} webhook on {}", kind, host)
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        super::selected_by(&self.events, notification)
+    }
+
+    fn send(&mut self, notifications: &[Arc<Notification>]) -> Result<(), String> {
+        for notification in notifications {
+            self.agent
+                .post(&self.url)
+                .header("Content-Type", "application/json")
+                .send(self.payload(notification).to_string().as_bytes())
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+}
+
+/// The parts both formats are made of
+struct Message<'a> {
+    notification: &'a Notification,
+    title: String,
+    failed: bool,
+    commit_url: Option<String>,
+    dashboard_url: Option<String>,
+}
+
+impl<'a> Message<'a> {
+    fn new(notification: &'a Notification, dashboard: Option<&str>) -> Self {
+        let failed = notification.status == "failed";
+        let title = match (notification.event, &notification.package) {
+            (NotificationEvent::CommitFinished, _) | (_, None) => {
+                format!("Commit {}", if failed { "failed" } else { "built" })
+            }
+            (_, Some(package)) => {
+                let state = if failed {
+                    "Failed"
+                } else if notification.recovered {
+                    "Fixed"
+                } else {
+                    "Built"
+                };
+                match &notification.arch {
+                    Some(arch) => format!("{}: {} ({})", state, package, arch),
+                    None => format!("{}: {}", state, package),
+                }
+            }
+        };
+        Message {
+            notification,
+            title,
+            failed,
+            commit_url: notification.commit_url(),
+            dashboard_url: dashboard.map(|dashboard| notification.dashboard_url(dashboard)),
+        }
+    }
+
+    fn branch(&self) -> &str {
+        self.notification
+            .branches
+            .first()
+            .map(String::as_str)
+            .unwrap_or_default()
+    }
+}
+
+fn slack_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn slack_payload(message: &Message) -> Value {
+    let notification = message.notification;
+    let link = |url: &Option<String>, text: &str| match url {
+        Some(url) => format!("<{}|{}>", url, slack_escape(text)),
+        None => slack_escape(text),
+    };
+    let emoji = if message.failed {
+        ":x:"
+    } else {
+        ":white_check_mark:"
+    };
+    let text = format!(
+        "{} *{}*\n{} · {} · {} {}",
+        emoji,
+        link(&message.dashboard_url, &message.title),
+        slack_escape(&notification.repo_name),
+        slack_escape(message.branch()),
+        link(&message.commit_url, notification.short_commit()),
+        slack_escape(notification.summary())
+    );
+    json!({ "text": text })
+}
+
+fn discord_payload(message: &Message) -> Value {
+    let notification = message.notification;
+    let commit = match &message.commit_url {
+        Some(url) => format!("[`{}`]({})", notification.short_commit(), url),
+        None => format!("`{}`", notification.short_commit()),
+    };
+    let mut embed = json!({
+        "title": message.title,
+        "description": format!(
+            "{} · {} · {} {}",
+            notification.repo_name,
+            message.branch(),
+            commit,
+            notification.summary()
+        ),
+        "color": if message.failed { FAILED_COLOR } else { SUCCESS_COLOR },
+    });
+    if let Some(url) = &message.dashboard_url {
+        embed["url"] = json!(url);
+    }
+    json!({
+        "username": "nix_autobuild",
+        "embeds": [embed],
+        // commit messages must not ping anyone
+        "allowed_mentions": { "parse": [] },
+    })
+}
@@ -0,0 +1,306 @@
+use std::{collections::HashMap, fmt::Write, sync::Arc, time::Duration};
+
+use lettre::{
+    Message, SmtpTransport, Transport,
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+};
+
+use crate::{AutoBuildOptions, EmailOptions, backend::secrets};
+
+use super::{FailedPackage, Notification, NotificationEvent, Notifier, escape};
+
+const TIMEOUT: Duration = Duration::from_secs(30);
+/// Only the end of the build output goes into the email, the rest is linked
+const ERROR_LINES: usize = 30;
+
+/// Summary emails of failed commits, one per commit and quiet window
+pub struct Email {
+    host: String,
+    transport: SmtpTransport,
+    from: Mailbox,
+    to: Vec<Mailbox>,
+    /// repo URL -> recipients replacing `to`
+    repo_recipients: HashMap<String, Vec<Mailbox>>,
+    events: Vec<String>,
+    quiet: Duration,
+    dashboard: Option<String>,
+}
+
+impl Email {
+    pub fn new(options: &EmailOptions, settings: &AutoBuildOptions) -> Result<Self, String> {
+        let builder = match options.tls.as_str() {
+            "starttls" => SmtpTransport::starttls_relay(&options.smtp_host),
+            "tls" => SmtpTransport::relay(&options.smtp_host),
+            "none" => Ok(SmtpTransport::builder_dangerous(&options.smtp_host)),
+            tls => return Err(format!("unknown TLS mode `{}`", tls)),
+        }
+        .map_err(|e| e.to_string())?;
+        let mut builder = builder.port(options.smtp_port).timeout(Some(TIMEOUT));
+        let credentials = secrets::resolve_either(
+            options.credentials.as_ref(),
+            options.credentials_file.as_deref(),
+        )?;
+        if let Some(credentials) = credentials {
+            let Some((username, password)) = credentials.expose().split_once(':') else {
+                return Err("the credentials are not in the format username:password".to_string());
+            };
+            builder =
+                builder.credentials(Credentials::new(username.to_string(), password.to_string()));
+        }
+
+        let mut repo_recipients = HashMap::new();
+        for repo in &settings.repos {
+            if !repo.email_recipients.is_empty() {
+                repo_recipients.insert(repo.url.clone(), mailboxes(&repo.email_recipients)?);
+            }
+        }
+        super::check_events(&format!("email via {}", options.smtp_host), &options.events);
+        Ok(Email {
+            host: options.smtp_host.clone(),
+            transport: builder.build(),
+            from: options
+                .from
+                .parse()
+                .map_err(|e| format!("invalid from address {}: {}", options.from, e))?,
+            to: mailboxes(&options.to)?,
+            repo_recipients,
+            events: options.events.clone(),
+            quiet: Duration::from_secs(options.quiet_secs),
+            dashboard: settings.notifications.dashboard_url.clone(),
+        })
+    }
+}
+
+fn mailboxes(addresses: &[String]) -> Result<Vec<Mailbox>, String> {
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| format!("invalid address {}: {}", address, e))
+        })
+        .collect()
+}
+
+impl Notifier for Email {
+    fn describe(&self) -> String {
+        format!("email via {}", self.host)
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        super::selected_by(&self.events, notification)
+    }
+
+    fn batch_window(&self) -> Option<Duration> {
+        Some(self.quiet)
+    }
+
+    fn send(&mut self, notifications: &[Arc<Notification>]) -> Result<(), String> {
+        let Some(first) = notifications.first() else {
+            return Ok(());
+        };
+        let recipients = self.repo_recipients.get(&first.repo).unwrap_or(&self.to);
+        if recipients.is_empty() {
+            return Ok(());
+        }
+        let summary = Summary::new(notifications);
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(summary.subject());
+        for recipient in recipients {
+            message = message.to(recipient.clone());
+        }
+        let message = message
+            .multipart(MultiPart::alternative_plain_html(
+                summary.plain(self.dashboard.as_deref()),
+                summary.html(self.dashboard.as_deref()),
+            ))
+            .map_err(|e| e.to_string())?;
+        self.transport.send(&message).map_err(|e| e.to_string())?;
+        tracing::info!(
+            "Emailed {} recipients about {} {}",
+            recipients.len(),
+            first.repo,
+            first.commit
+        );
+        Ok(())
+    }
+}
+
+/// Everything reported about one commit within the quiet window
+struct Summary<'a> {
+    commit: &'a Notification,
+    failed: Vec<FailedPackage>,
+    succeeded: Vec<String>,
+}
+
+impl<'a> Summary<'a> {
+    fn new(notifications: &'a [Arc<Notification>]) -> Self {
+        let mut failed: Vec<FailedPackage> = Vec::new();
+        let mut succeeded = Vec::new();
+        for notification in notifications {
+            let packages = match notification.event {
+                NotificationEvent::CommitFinished => notification.failed_packages.clone(),
+                NotificationEvent::PackageFailed => vec![FailedPackage {
+                    package: notification.package.clone().unwrap_or_default(),
+                    arch: notification.arch.clone(),
+                    error: notification.error.clone().unwrap_or_default(),
+                }],
+                NotificationEvent::PackageSuccess => {
+                    succeeded.extend(notification.package.clone());
+                    Vec::new()
+                }
+            };
+            for package in packages {
+                if !failed.iter().any(|known| known.package == package.package) {
+                    failed.push(package);
+                }
+            }
+        }
+        Summary {
+            commit: &notifications[0],
+            failed,
+            succeeded,
+        }
+    }
+
+    fn subject(&self) -> String {
+        let state = if self.failed.is_empty() {
+            "built".to_string()
+        } else {
+            format!("{} failed", plural(self.failed.len(), "package"))
+        };
+        format!(
+            "[nix_autobuild] {} {}: {} - {}",
+            self.commit.repo_name,
+            self.commit.short_commit(),
+            state,
+            self.commit.summary()
+        )
+    }
+
+    fn plain(&self, dashboard: Option<&str>) -> String {
+        let commit = self.commit;
+        let mut text = format!(
+            "Repository: {}\nBranches: {}\nCommit: {} {}\n",
+            commit.repo,
+            commit.branches.join(", "),
+            commit.commit,
+            commit.summary()
+        );
+        if let Some(url) = commit.commit_url() {
+            let _ = writeln!(text, "{}", url);
+        }
+        if let Some(dashboard) = dashboard {
+            let _ = writeln!(text, "Dashboard: {}", commit.dashboard_url(dashboard));
+        }
+        for package in &self.failed {
+            let _ = write!(text, "\nFailed: {}", label(package));
+            if let Some(dashboard) = dashboard {
+                let _ = write!(
+                    text,
+                    "\nFull log: {}",
+                    commit.log_url(dashboard, &package.package)
+                );
+            }
+            let _ = writeln!(text, "\n\n{}", tail(&package.error));
+        }
+        for package in &self.succeeded {
+            let _ = writeln!(text, "\nBuilt: {}", package);
+        }
+        text
+    }
+
+    fn html(&self, dashboard: Option<&str>) -> String {
+        let commit = self.commit;
+        let hash = match commit.commit_url() {
+            Some(url) => format!(
+                "<a href=\"{}\"><code>{}</code></a>",
+                escape(&url),
+                commit.short_commit()
+            ),
+            None => format!("<code>{}</code>", commit.short_commit()),
+        };
+        let mut html = format!(
+            "<p><b>{}</b> ({})<br>{} {}</p>",
+            escape(&commit.repo),
+            escape(&commit.branches.join(", ")),
+            hash,
+            escape(commit.summary())
+        );
+        if let Some(dashboard) = dashboard {
+            let _ = write!(
+                html,
+                "<p><a href=\"{}\">Open in dashboard</a></p>",
+                escape(&commit.dashboard_url(dashboard))
+            );
+        }
+        for package in &self.failed {
+            let _ = write!(
+                html,
+                "<h3 style=\"color:#d73a49\">Failed: {}</h3>",
+                escape(&label(package))
+            );
+            if let Some(dashboard) = dashboard {
+                let _ = write!(
+                    html,
+                    "<p><a href=\"{}\">Full log</a></p>",
+                    escape(&commit.log_url(dashboard, &package.package))
+                );
+            }
+            let _ = write!(html, "<pre>{}</pre>", escape(&tail(&package.error)));
+        }
+        if !self.succeeded.is_empty() {
+            html.push_str("<h3 style=\"color:#28a745\">Built</h3><ul>");
+            for package in &self.succeeded {
+                let _ = write!(html, "<li><code>{}</code></li>", escape(package));
+            }
+            html.push_str("</ul>");
+        }
+        html
+    }
+}
+
+fn label(package: &FailedPackage) -> String {
+    match &package.arch {
+        Some(arch) => format!("{} ({})", package.package, arch),
+        None => package.package.clone(),
+    }
+}
+
+fn plural(count: usize, word: &str) -> String {
+    if count == 1 {
+        format!("1 {}", word)
+    } else {
+        format!("{} {}s", count, word)
+    }
+}
+
+/// Last [`ERROR_LINES`] lines of the build output, where nix reports the error
+fn tail(output: &str) -> String {
+    let lines = output.lines().collect::<Vec<_>>();
+    let start = lines.len().saturating_sub(ERROR_LINES);
+    let mut tail = lines[start..].join("\n");
+    if start > 0 {
+        tail.insert_str(0, "...\n");
+    }
+    tail
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_end_of_output() {
+        let output = (0..100)
+            .map(|i| i.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let tail = tail(&output);
+        assert!(tail.starts_with("...\n70\n"));
+        assert!(tail.ends_with("\n99"));
+        assert_eq!(super::tail("error: boom"), "error: boom");
+    }
+}
@@ -0,0 +1,205 @@
+use std::{collections::HashSet, fmt::Write, sync::Arc, time::Duration};
+
+use ring::digest;
+use ureq::Agent;
+
+use crate::{
+    MatrixOptions,
+    backend::secrets::{self, Secret},
+};
+
+use super::{Notification, NotificationEvent, Notifier, escape, hex, percent_encode};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Failures of one commit usually arrive close together, they end up in one message
+const BATCH_WINDOW: Duration = Duration::from_secs(60);
+/// Upper bound of remembered transitions
+const MAX_SENT: usize = 10_000;
+const FAILED_COLOR: &str = "#d73a49";
+const SUCCESS_COLOR: &str = "#28a745";
+
+/// Messages to a Matrix room for failed and recovered packages
+pub struct Matrix {
+    room_id: String,
+    /// `.../send/m.room.message/`, the transaction ID is appended
+    send_url: String,
+    access_token: Secret,
+    recoveries: bool,
+    /// (repo, commit, package, status) already reported, so every transition is
+    /// sent only once
+    sent: HashSet<(String, String, String, &'static str)>,
+    agent: Agent,
+}
+
+impl Matrix {
+    pub fn new(options: &MatrixOptions) -> Result<Self, String> {
+        let Some(access_token) = secrets::resolve_either(
+            options.access_token.as_ref(),
+            options.access_token_file.as_deref(),
+        )?
+        else {
+            return Err("needs `access_token` or `access_token_file`".to_string());
+        };
+        let send_url = format!(
+            "{}/_matrix/client/v3/rooms/{}/send/m.room.message/",
+            options.homeserver.trim_end_matches('/'),
+            percent_encode(&options.room_id)
+        );
+        let config = Agent::config_builder()
+            .timeout_global(Some(TIMEOUT))
+            .build();
+        Ok(Matrix {
+            room_id: options.room_id.clone(),
+            send_url,
+            access_token,
+            recoveries: options.recoveries,
+            sent: HashSet::new(),
+            agent: config.into(),
+        })
+    }
+}
+
+impl Notifier for Matrix {
+    fn describe(&self) -> String {
+        format!("Matrix room {}", self.room_id)
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        match notification.event {
+            NotificationEvent::PackageFailed => true,
+            NotificationEvent::PackageSuccess => self.recoveries && notification.recovered,
+            NotificationEvent::CommitFinished => false,
+        }
+    }
+
+    fn batch_window(&self) -> Option<Duration> {
+        Some(BATCH_WINDOW)
+    }
+
+    fn send(&mut self, notifications: &[Arc<Notification>]) -> Result<(), String> {
+        let notifications = notifications
+            .iter()
+            .filter(|notification| !self.sent.contains(&transition(notification)))
+            .collect::<Vec<_>>();
+        let Some(first) = notifications.first() else {
+            return Ok(());
+        };
+        let body = serde_json::json!({
+            "msgtype": "m.text",
+            "body": plain_message(&notifications),
+            "format": "org.matrix.custom.html",
+            "formatted_body": html_message(&notifications),
+        })
+        .to_string();
+        // the same message gets the same ID on retries, the homeserver drops duplicates
+        let txn_id = hex(digest::digest(&digest::SHA256, body.as_bytes()).as_ref());
+        self.agent
+            .put(format!("{}{}", self.send_url, txn_id))
+            .header(
+                "Authorization",
+                format!("Bearer {}", self.access_token.expose()),
+            )
+            .header("Content-Type", "application/json")
+            .send(body.as_bytes())
+            .map_err(|e| e.to_string())?;
+        tracing::info!(
+            "Sent {} notifications for {} {} to {}",
+            notifications.len(),
+            first.repo,
+            first.commit,
+            self.room_id
+        );
+
+        if self.sent.len() + notifications.len() > MAX_SENT {
+            self.sent.clear();
+        }
+        self.sent.extend(
+            notifications
+                .iter()
+                .map(|notification| transition(notification)),
+        );
+        Ok(())
+    }
+}
+
+fn transition(notification: &Notification) -> (String, String, String, &'static str) {
+    (
+        notification.repo.clone(),
+        notification.commit.clone(),
+        notification.package.clone().unwrap_or_default(),
+        notification.status,
+    )
+}
+
+fn headline(notification: &Notification) -> String {
+    format!(
+        "{} {} {}",
+        notification.repo,
+        notification.short_commit(),
+        notification.summary()
+    )
+}
+
+fn package_label(notification: &Notification) -> String {
+    let package = notification.package.as_deref().unwrap_or_default();
+    match &notification.arch {
+        Some(arch) => format!("{} ({})", package, arch),
+        None => package.to_string(),
+    }
+}
+
+fn status_label(notification: &Notification) -> &'static str {
+    if notification.status == "failed" {
+        "failed"
+    } else {
+        "fixed"
+    }
+}
+
+fn plain_message(notifications: &[&Arc<Notification>]) -> String {
+    let mut message = headline(notifications[0]);
+    for notification in notifications {
+        let _ = write!(
+            message,
+            "\n- {}: {}",
+            package_label(notification),
+            status_label(notification)
+        );
+    }
+    message
+}
+
+fn html_message(notifications: &[&Arc<Notification>]) -> String {
+    let first = notifications[0];
+    let short = first.short_commit();
+    let commit = match first.commit_url() {
+        Some(url) => format!(
+            "<a href=\"{}\"><code>{}</code></a>",
+            escape(&url),
+            escape(short)
+        ),
+        None => format!("<code>{}</code>", escape(short)),
+    };
+    let mut message = format!(
+        "<b>{}</b> {} {}<ul>",
+        escape(&first.repo),
+        commit,
+        escape(first.summary())
+    );
+    for notification in notifications {
+        let color = if notification.status == "failed" {
+            FAILED_COLOR
+        } else {
+            SUCCESS_COLOR
+        };
+        let _ = write!(
+            message,
+            "<li><code>{}</code> <font color=\"{}\">{}</font></li>",
+            escape(&package_label(notification)),
+            color,
+            status_label(notification)
+        );
+    }
+    message.push_str("</ul>");
+    message
+}
@@ -0,0 +1,752 @@
+//! Reports finished builds to external services. A listener thread turns the state
+//! events into [`Notification`]s and a dispatcher thread delivers them, so slow or
+//! unreachable services never hold up a build.
+
+mod chat;
+mod email;
+mod matrix;
+mod webhook;
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        mpsc::{self, Receiver, RecvTimeoutError, SyncSender},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::{
+    AutoBuildOptions,
+    commit::CommitInfo,
+    event::StateEvent,
+    package::{AggregateStatus, PackageBuildStatus, PackageEnum},
+};
+
+use super::{
+    BuildRepos,
+    events::{self, Sequenced},
+};
+
+/// Give up on a delivery after this many failed attempts
+const MAX_ATTEMPTS: u32 = 8;
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(10);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60 * 60);
+/// Deliveries waiting for a retry, the ones retried most are dropped beyond that
+const MAX_QUEUED: usize = 1000;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationEvent {
+    PackageSuccess,
+    PackageFailed,
+    /// every package of a commit finished
+    CommitFinished,
+}
+
+impl NotificationEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NotificationEvent::PackageSuccess => "package_success",
+            NotificationEvent::PackageFailed => "package_failed",
+            NotificationEvent::CommitFinished => "commit_finished",
+        }
+    }
+}
+
+/// What services are told about, also the JSON payload of webhooks
+#[derive(Serialize, Debug, Clone)]
+pub struct Notification {
+    pub event: NotificationEvent,
+    pub unix_secs: i64,
+    pub repo: String,
+    /// `name` of the repo, as in the API paths and badges
+    pub repo_name: String,
+    /// branches the commit is tracked on
+    pub branches: Vec<String>,
+    pub commit: String,
+    pub commit_message: String,
+    /// attribute path, not set for commit events
+    pub package: Option<String>,
+    pub arch: Option<String>,
+    /// `success` or `failed`
+    pub status: &'static str,
+    pub duration_secs: Option<i64>,
+    pub result_paths: Vec<String>,
+    /// end of the build output of failed packages
+    pub error: Option<String>,
+    /// the package failed on the previous commit of a branch and builds now
+    pub recovered: bool,
+    /// set for commit events
+    pub failed_packages: Vec<FailedPackage>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FailedPackage {
+    pub package: String,
+    pub arch: Option<String>,
+    pub error: String,
+}
+
+/// A service notifications are delivered to
+pub trait Notifier: Send {
+    /// Used in log messages
+    fn describe(&self) -> String;
+    fn wants(&self, notification: &Notification) -> bool;
+    /// Notifications of the same commit arriving within this time are sent together
+    fn batch_window(&self) -> Option<Duration> {
+        None
+    }
+    fn send(&mut self, notifications: &[Arc<Notification>]) -> Result<(), String>;
+}
+
+/// Start the notifier threads if any service is configured
+pub fn start(settings: &AutoBuildOptions, repos: BuildRepos) {
+    let notifiers = notifiers(settings);
+    if notifiers.is_empty() {
+        return;
+    }
+    tracing::info!("Sending notifications to {} services", notifiers.len());
+    // subscribe right away so no event published from now on is missed
+    let events = events::subscribe();
+    let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED);
+    thread::spawn(move || listen(events, sender, repos));
+    thread::spawn(move || dispatch(notifiers, receiver));
+}
+
+fn notifiers(settings: &AutoBuildOptions) -> Vec<Box<dyn Notifier>> {
+    let options = &settings.notifications;
+    let mut notifiers: Vec<Box<dyn Notifier>> = Vec::new();
+    for options in &options.webhooks {
+        match webhook::Webhook::new(options) {
+            Ok(webhook) => notifiers.push(Box::new(webhook)),
+            Err(e) => tracing::error!("setting up webhook {}: {}", options.url, e),
+        }
+    }
+    for options in &options.matrix {
+        match matrix::Matrix::new(options) {
+            Ok(matrix) => notifiers.push(Box::new(matrix)),
+            Err(e) => tracing::error!("setting up Matrix room {}: {}", options.room_id, e),
+        }
+    }
+    let dashboard = options.dashboard_url.as_deref();
+    let chats = [
+        (chat::Kind::Slack, &options.slack),
+        (chat::Kind::Discord, &options.discord),
+    ];
+    for (kind, webhooks) in chats {
+        for webhook in webhooks {
+            match chat::Chat::new(kind, webhook, dashboard) {
+                Ok(chat) => notifiers.push(Box::new(chat)),
+                Err(e) => tracing::error!("setting up {:?} webhook: {}", kind, e),
+            }
+        }
+    }
+    if let Some(email) = &options.email {
+        match email::Email::new(email, settings) {
+            Ok(email) => notifiers.push(Box::new(email)),
+            Err(e) => tracing::error!("setting up email via {}: {}", email.smtp_host, e),
+        }
+    }
+    notifiers
+}
+
+/// Selects successful builds of packages that failed on the previous commit
+const RECOVERED_EVENT: &str = "package_recovered";
+/// Selects commits that finished with at least one failed package
+const COMMIT_FAILED_EVENT: &str = "commit_failed";
+
+/// Warn about configured event names that do not exist
+fn check_events(service: &str, names: &[String]) {
+    for name in names {
+        if !crate::NOTIFICATION_EVENTS.contains(&name.as_str()) {
+            tracing::error!("unknown notification event `{}` for {}", name, service);
+        }
+    }
+}
+
+/// Whether a notification is selected by the configured event names
+fn selected_by(names: &[String], notification: &Notification) -> bool {
+    names.iter().any(|name| {
+        name == notification.event.name()
+            || (name == RECOVERED_EVENT && notification.recovered)
+            || (name == COMMIT_FAILED_EVENT
+                && notification.event == NotificationEvent::CommitFinished
+                && notification.status == "failed")
+    })
+}
+
+fn listen(
+    mut events: broadcast::Receiver<Sequenced>,
+    sender: SyncSender<Notification>,
+    repos: BuildRepos,
+) {
+    let mut tracker = Tracker {
+        repos,
+        ..Tracker::default()
+    };
+    loop {
+        match events.blocking_recv() {
+            Ok(event) => {
+                for notification in tracker.handle(&event) {
+                    if sender.send(notification).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(RecvError::Lagged(missed)) => {
+                tracing::error!("notifications missed {} state events", missed)
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Remembers when builds started, to report how long they took
+#[derive(Default)]
+struct Tracker {
+    /// the commits of finished builds are looked up in
+    repos: BuildRepos,
+    /// (repo, commit, package) -> unix secs
+    packages: HashMap<(String, String, String), i64>,
+    /// (repo, commit) -> unix secs of the first package build
+    commits: HashMap<(String, String), i64>,
+    /// (repo, commit) whose end was reported, until a package is queued again
+    finished: HashSet<(String, String)>,
+}
+
+impl Tracker {
+    fn handle(&mut self, event: &Sequenced) -> Vec<Notification> {
+        let StateEvent::PackageStatus {
+            repo,
+            commit,
+            path,
+            status,
+            ..
+        } = &event.event
+        else {
+            return Vec::new();
+        };
+        let package_key = (repo.clone(), commit.clone(), path.clone());
+        let commit_key = (repo.clone(), commit.clone());
+        if !status.is_finished() {
+            // the commit finishes again once this build ends
+            self.finished.remove(&commit_key);
+            if let PackageBuildStatus::Building = status {
+                self.packages.insert(package_key, event.unix_secs);
+                self.commits.entry(commit_key).or_insert(event.unix_secs);
+            }
+            return Vec::new();
+        }
+        let started = self.packages.remove(&package_key);
+        let Some(commit_info) = find_commit(&self.repos, repo, commit) else {
+            self.finished.remove(&commit_key);
+            return Vec::new();
+        };
+
+        let mut notifications = Vec::new();
+        let package_event = match status {
+            PackageBuildStatus::Success(_) => Some(NotificationEvent::PackageSuccess),
+            PackageBuildStatus::Failed(_) => Some(NotificationEvent::PackageFailed),
+            _ => None,
+        };
+        if let Some(package_event) = package_event {
+            let mut notification =
+                Notification::new(package_event, event.unix_secs, &commit_info, status);
+            notification.package = Some(path.clone());
+            notification.arch = package_arch(&commit_info, path);
+            notification.duration_secs = started.map(|started| event.unix_secs - started);
+            notification.recovered = package_event == NotificationEvent::PackageSuccess
+                && previously_failed(&commit_info, path);
+            notifications.push(notification);
+        }
+
+        let packages = commit_info.packages.get();
+        if packages.iter().all(|pkg| pkg.status().is_finished())
+            && self.finished.insert(commit_key.clone())
+        {
+            let started = self.commits.remove(&commit_key);
+            let statuses = packages.iter().map(|pkg| pkg.status()).collect::<Vec<_>>();
+            let status = match AggregateStatus::from_statuses(&statuses) {
+                Some(AggregateStatus::Success) => PackageBuildStatus::Success(String::new()),
+                Some(AggregateStatus::Failed) => PackageBuildStatus::Failed(String::new()),
+                // nothing was built, e.g. everything cancelled
+                _ => return notifications,
+            };
+            let mut notification = Notification::new(
+                NotificationEvent::CommitFinished,
+                event.unix_secs,
+                &commit_info,
+                &status,
+            );
+            notification.duration_secs = started.map(|started| event.unix_secs - started);
+            notification.result_paths = statuses
+                .iter()
+                .filter_map(|status| match status {
+                    PackageBuildStatus::Success(paths) => Some(paths.split_whitespace()),
+                    _ => None,
+                })
+                .flatten()
+                .map(String::from)
+                .collect();
+            notification.failed_packages = packages
+                .iter()
+                .filter_map(|pkg| match pkg.status() {
+                    PackageBuildStatus::Failed(error) => Some(FailedPackage {
+                        package: pkg.path().to_string(),
+                        arch: arch(pkg),
+                        error,
+                    }),
+                    _ => None,
+                })
+                .collect();
+            notifications.push(notification);
+        }
+        notifications
+    }
+}
+
+impl Notification {
+    pub fn short_commit(&self) -> &str {
+        self.commit.get(..8).unwrap_or(&self.commit)
+    }
+
+    /// First line of the commit message
+    pub fn summary(&self) -> &str {
+        self.commit_message.lines().next().unwrap_or_default()
+    }
+
+    /// Web page of the commit for repos cloned over HTTP from GitHub, GitLab, Gitea and
+    /// the like
+    pub fn commit_url(&self) -> Option<String> {
+        if !self.repo.starts_with("https://") && !self.repo.starts_with("http://") {
+            return None;
+        }
+        let repo = self.repo.trim_end_matches('/');
+        let repo = repo.strip_suffix(".git").unwrap_or(repo);
+        Some(format!("{}/commit/{}", repo, self.commit))
+    }
+
+    /// Full build output of a package, served by the API
+    pub fn log_url(&self, dashboard: &str, package: &str) -> String {
+        format!(
+            "{}{}/repos/{}/commits/{}/packages/{}/log",
+            dashboard.trim_end_matches('/'),
+            super::api::PREFIX,
+            percent_encode(&self.repo_name),
+            self.commit,
+            percent_encode(package)
+        )
+    }
+
+    /// The frontend opened at this package or commit
+    pub fn dashboard_url(&self, dashboard: &str) -> String {
+        dashboard_link(
+            dashboard,
+            &self.repo,
+            self.package.as_deref(),
+            self.arch.as_deref(),
+            self.branches.first().map(String::as_str),
+            &self.commit,
+        )
+    }
+
+    fn new(
+        event: NotificationEvent,
+        unix_secs: i64,
+        commit: &CommitInfo,
+        status: &PackageBuildStatus,
+    ) -> Self {
+        let repo = commit.repo();
+        let mut branches = repo
+            .iter()
+            .flat_map(|repo| &repo.branch_commit_hashes)
+            .filter(|(_, hashes)| hashes.get().contains(&commit.hash))
+            .map(|(branch, _)| branch.clone())
+            .collect::<Vec<_>>();
+        branches.sort();
+        let (status, result_paths, error) = match status {
+            PackageBuildStatus::Success(paths) => (
+                "success",
+                paths.split_whitespace().map(String::from).collect(),
+                None,
+            ),
+            PackageBuildStatus::Failed(error) => (
+                "failed",
+                Vec::new(),
+                Some(error.clone()).filter(|e| !e.is_empty()),
+            ),
+            _ => ("unknown", Vec::new(), None),
+        };
+        Notification {
+            event,
+            unix_secs,
+            repo: commit.repo_url(),
+            repo_name: repo.map(|repo| repo.repo.name()).unwrap_or_default(),
+            branches,
+            commit: commit.hash.clone(),
+            commit_message: commit.message.clone(),
+            package: None,
+            arch: None,
+            status,
+            duration_secs: None,
+            result_paths,
+            error,
+            recovered: false,
+            failed_packages: Vec::new(),
+        }
+    }
+}
+
+fn find_commit(repos: &BuildRepos, repo: &str, hash: &str) -> Option<Arc<CommitInfo>> {
+    let repo = repos.find(repo)?;
+    let commits = repo.commits.inner().read().ok()?;
+    commits.get(hash).cloned()
+}
+
+fn package_arch(commit: &CommitInfo, path: &str) -> Option<String> {
+    commit
+        .packages
+        .get()
+        .iter()
+        .find(|pkg| pkg.path() == path)
+        .and_then(arch)
+}
+
+fn arch(pkg: &PackageEnum) -> Option<String> {
+    match pkg {
+        PackageEnum::Derivation(pkg) => Some(pkg.0.arch.to_string()),
+        PackageEnum::NixosConfig(_) => None,
+    }
+}
+
+/// Whether the package failed on the parent of `commit` on any branch
+fn previously_failed(commit: &CommitInfo, path: &str) -> bool {
+    let Some(repo) = commit.repo() else {
+        return false;
+    };
+    repo.branch_commit_hashes.values().any(|hashes| {
+        let hashes = hashes.get();
+        let Some(parent) = hashes
+            .iter()
+            .position(|hash| *hash == commit.hash)
+            .and_then(|index| hashes.get(index + 1))
+        else {
+            return false;
+        };
+        let Some(parent) = repo
+            .commits
+            .inner()
+            .read()
+            .ok()
+            .and_then(|commits| commits.get(parent).cloned())
+        else {
+            return false;
+        };
+        parent
+            .packages
+            .get()
+            .iter()
+            .any(|pkg| pkg.path() == path && matches!(pkg.status(), PackageBuildStatus::Failed(_)))
+    })
+}
+
+struct Delivery {
+    notifier: usize,
+    notifications: Vec<Arc<Notification>>,
+    /// failed attempts so far
+    failures: u32,
+    due: Instant,
+}
+
+fn dispatch(mut notifiers: Vec<Box<dyn Notifier>>, receiver: Receiver<Notification>) {
+    let mut queue: Vec<Delivery> = Vec::new();
+    loop {
+        let next_due = queue.iter().map(|delivery| delivery.due).min();
+        let received = match next_due {
+            Some(due) => receiver.recv_timeout(due.saturating_duration_since(Instant::now())),
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok(notification) => {
+                let notification = Arc::new(notification);
+                for (index, notifier) in notifiers.iter().enumerate() {
+                    if !notifier.wants(&notification) {
+                        continue;
+                    }
+                    let Some(window) = notifier.batch_window() else {
+                        queue.push(Delivery {
+                            notifier: index,
+                            notifications: vec![notification.clone()],
+                            failures: 0,
+                            due: Instant::now(),
+                        });
+                        continue;
+                    };
+                    let batch = queue.iter_mut().find(|delivery| {
+                        delivery.notifier == index
+                            && delivery.failures == 0
+                            && delivery.notifications[0].repo == notification.repo
+                            && delivery.notifications[0].commit == notification.commit
+                    });
+                    match batch {
+                        Some(batch) => batch.notifications.push(notification.clone()),
+                        None => queue.push(Delivery {
+                            notifier: index,
+                            notifications: vec![notification.clone()],
+                            failures: 0,
+                            due: Instant::now() + window,
+                        }),
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+
+        let now = Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) =
+            queue.drain(..).partition(|delivery| delivery.due <= now);
+        queue = waiting;
+        for mut delivery in due {
+            let notifier = &mut notifiers[delivery.notifier];
+            let Err(e) = notifier.send(&delivery.notifications) else {
+                continue;
+            };
+            delivery.failures += 1;
+            if delivery.failures >= MAX_ATTEMPTS {
+                tracing::error!(
+                    "giving up notifying {} after {} attempts: {}",
+                    notifier.describe(),
+                    delivery.failures,
+                    e
+                );
+                continue;
+            }
+            let delay = retry_delay(delivery.failures);
+            tracing::error!(
+                "notifying {}, retrying in {}s: {}",
+                notifier.describe(),
+                delay.as_secs(),
+                e
+            );
+            delivery.due = Instant::now() + delay;
+            queue.push(delivery);
+        }
+
+        if queue.len() > MAX_QUEUED {
+            queue.sort_by_key(|delivery| delivery.failures);
+            let dropped = queue.len() - MAX_QUEUED;
+            queue.truncate(MAX_QUEUED);
+            tracing::error!("dropped {} queued notifications", dropped);
+        }
+    }
+}
+
+/// The frontend opened at a package of a commit, it reads the same query parameters it
+/// writes when navigating
+pub fn dashboard_link(
+    dashboard: &str,
+    repo: &str,
+    package: Option<&str>,
+    arch: Option<&str>,
+    branch: Option<&str>,
+    commit: &str,
+) -> String {
+    let mut params = vec![format!("repo={}", percent_encode(repo))];
+    if let Some(package) = package {
+        // packages are grouped by their path without the architecture
+        let name = match arch {
+            Some(arch) => package.replace(arch, "*"),
+            None => package.to_string(),
+        };
+        params.push(format!("package={}", percent_encode(&name)));
+        if let Some(branch) = branch {
+            params.push(format!("branch={}", percent_encode(branch)));
+            params.push(format!("commit={}", commit));
+            params.push(format!("arch={}", percent_encode(arch.unwrap_or("NONE"))));
+        }
+    }
+    format!("{}?{}", dashboard, params.join("&"))
+}
+
+/// Everything but unreserved characters, for path segments and query values
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// For the HTML of Matrix messages and emails, and the XML of the feeds
+pub(super) fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Exponential backoff, doubling from [`RETRY_BASE_DELAY`] up to [`RETRY_MAX_DELAY`]
+fn retry_delay(failures: u32) -> Duration {
+    let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+    RETRY_BASE_DELAY.saturating_mul(factor).min(RETRY_MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(retry_delay(1), Duration::from_secs(10));
+        assert_eq!(retry_delay(2), Duration::from_secs(20));
+        assert_eq!(retry_delay(4), Duration::from_secs(80));
+        assert_eq!(retry_delay(40), RETRY_MAX_DELAY);
+    }
+
+    #[test]
+    fn links_commits_and_dashboard() {
+        let notification = Notification {
+            event: NotificationEvent::PackageFailed,
+            unix_secs: 0,
+            repo: "https://github.com/AkosPapp/nix_autobuild.git".to_string(),
+            repo_name: "nix_autobuild".to_string(),
+            branches: vec!["main".to_string()],
+            commit: "0123456789abcdef".to_string(),
+            commit_message: "Fix the build\n\nlonger text".to_string(),
+            package: Some("packages.x86_64-linux.default".to_string()),
+            arch: Some("x86_64-linux".to_string()),
+            status: "failed",
+            duration_secs: None,
+            result_paths: Vec::new(),
+            error: None,
+            recovered: false,
+            failed_packages: Vec::new(),
+        };
+        assert_eq!(notification.short_commit(), "01234567");
+        assert_eq!(notification.summary(), "Fix the build");
+        assert_eq!(
+            notification.commit_url().as_deref(),
+            Some("https://github.com/AkosPapp/nix_autobuild/commit/0123456789abcdef")
+        );
+        assert_eq!(
+            notification.dashboard_url("https://ci.example.com/"),
+            "https://ci.example.com/?repo=https%3A%2F%2Fgithub.com%2FAkosPapp%2Fnix_autobuild.git\
+             &package=packages.%2A.default&branch=main&commit=0123456789abcdef&arch=x86_64-linux"
+        );
+        let ssh = Notification {
+            repo: "git@github.com:AkosPapp/nix_autobuild".to_string(),
+            ..notification
+        };
+        assert_eq!(ssh.commit_url(), None);
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            escape("<a href=\"x\">&</a>"),
+            "&lt;a href=&quot;x&quot;&gt;&amp;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn a_commit_finishes_once_per_round() -> Result<(), Box<dyn std::error::Error>> {
+        use super::super::{RepoInfoTrait, database::StoredPackage};
+        use crate::{
+            Repo, RepoList,
+            commit::CommitBuildStatus,
+            package::BuildTimes,
+            repo::RepoInfo,
+            serialize::{RwLockWrapper, VecArcWrapper},
+        };
+
+        let settings = serde_json::from_value::<AutoBuildOptions>(serde_json::json!({
+            "repos": []
+        }))?;
+        let repo = RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"]
+            }))?,
+            std::path::PathBuf::from("/nonexistent/repo"),
+            Arc::new(settings),
+        );
+        let commit = Arc::new(CommitInfo {
+            flake_url: "git+https://github.com/org/repo?rev=abc123".to_string(),
+            hash: "abc123".to_string(),
+            message: "Change".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(&repo)),
+            unix_secs: 0,
+            diff_stats: None,
+        });
+        let packages = ["a", "b"].map(|name| {
+            StoredPackage {
+                path: format!("packages.x86_64-linux.{}", name),
+                nixos_config: false,
+                name: name.to_string(),
+                description: String::new(),
+                pkg_type: "derivation".to_string(),
+                arch: "x86_64-linux".to_string(),
+                status: PackageBuildStatus::Success(format!("/nix/store/abc-{}", name)),
+                times: BuildTimes::default(),
+                closure_size: None,
+                attempts: Vec::new(),
+            }
+            .into_package(&commit)
+        });
+        commit.packages.set(packages.to_vec());
+        repo.commits
+            .inner()
+            .write()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .insert(commit.hash.clone(), commit.clone());
+        let repos = BuildRepos::default();
+        repos.set(RepoList(VecArcWrapper::from(vec![repo.clone()])));
+        let mut tracker = Tracker {
+            repos,
+            ..Tracker::default()
+        };
+        let mut commit_events = |name: &str, status: PackageBuildStatus| {
+            let event = Sequenced {
+                id: 0,
+                unix_secs: 0,
+                event: StateEvent::PackageStatus {
+                    repo: repo.repo.url.clone(),
+                    commit: commit.hash.clone(),
+                    path: format!("packages.x86_64-linux.{}", name),
+                    status,
+                    times: BuildTimes::default(),
+                    closure_size: None,
+                    expected_secs: None,
+                    attempts: None,
+                },
+            };
+            tracker
+                .handle(&event)
+                .iter()
+                .filter(|notification| notification.event == NotificationEvent::CommitFinished)
+                .count()
+        };
+        let success = |name: &str| PackageBuildStatus::Success(format!("/nix/store/abc-{}", name));
+        assert_eq!(commit_events("a", success("a")), 1);
+        // e.g. the result of a rebuild that was not queued through the tracker
+        assert_eq!(commit_events("b", success("b")), 0);
+        assert_eq!(commit_events("a", PackageBuildStatus::WaitingForBuild), 0);
+        assert_eq!(commit_events("a", success("a")), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,94 @@
+use std::{sync::Arc, time::Duration};
+
+use ring::hmac;
+use ureq::Agent;
+
+use crate::{WebhookOptions, backend::secrets};
+
+use super::{Notification, Notifier, hex};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const SIGNATURE_HEADER: &str = "X-Autobuild-Signature-256";
+
+/// JSON `POST` of the [`Notification`] to a URL
+pub struct Webhook {
+    url: String,
+    events: Vec<String>,
+    secret: Option<hmac::Key>,
+    agent: Agent,
+}
+
+impl Webhook {
+    pub fn new(options: &WebhookOptions) -> Result<Self, String> {
+        let secret =
+            secrets::resolve_either(options.secret.as_ref(), options.secret_file.as_deref())?
+                .map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.expose().as_bytes()));
+        super::check_events(&options.url, &options.events);
+        let config = Agent::config_builder()
+            .timeout_global(Some(TIMEOUT))
+            .build();
+        Ok(Webhook {
+            url: options.url.clone(),
+            events: options.events.clone(),
+            secret,
+            agent: config.into(),
+        })
+    }
+}
+
+impl Notifier for Webhook {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn wants(&self, notification: &Notification) -> bool {
+        super::selected_by(&self.events, notification)
+    }
+
+    fn send(&mut self, notifications: &[Arc<Notification>]) -> Result<(), String> {
+        for notification in notifications {
+            self.post(notification)?;
+        }
+        Ok(())
+    }
+}
+
+impl Webhook {
+    fn post(&self, notification: &Notification) -> Result<(), String> {
+        let body = serde_json::to_vec(notification).map_err(|e| e.to_string())?;
+        let mut request = self
+            .agent
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header(
+                "User-Agent",
+                concat!("nix_autobuild/", env!("CARGO_PKG_VERSION")),
+            )
+            .header("X-Autobuild-Event", notification.event.name());
+        if let Some(secret) = &self.secret {
+            request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+        }
+        request.send(&body[..]).map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+/// `sha256=<hex>` like GitHub does it
+fn signature(secret: &hmac::Key, body: &[u8]) -> String {
+    format!("sha256={}", hex(hmac::sign(secret, body).as_ref()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signs_like_hmac_sha256() {
+        // RFC 4231 test case 2
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"Jefe");
+        assert_eq!(
+            signature(&key, b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,145 @@
+//! The directories the server writes to, created at startup so a missing
+//! permission shows up right away instead of with the first clone or build
+
+use std::{io::ErrorKind, path::PathBuf};
+
+use ring::digest;
+
+use crate::{AutoBuildOptions, Repo};
+
+/// Longest file name most file systems allow, in bytes
+const MAX_NAME_LEN: usize = 255;
+
+const PLACEHOLDERS: [&str; 4] = ["host", "owner", "repo", "hash8"];
+
+/// Option names and directories, `dir` first since the others default to below it
+pub fn dirs(settings: &AutoBuildOptions) -> [(&'static str, PathBuf); 5] {
+    [
+        ("dir", settings.dir.clone()),
+        ("paths.checkouts_dir", settings.checkouts_dir()),
+        ("paths.logs_dir", settings.logs_dir()),
+        ("paths.state_dir", settings.state_dir()),
+        ("paths.gc_roots_dir", settings.gc_roots_dir()),
+    ]
+}
+
+pub fn create_dirs(settings: &AutoBuildOptions) -> Result<(), String> {
+    for (option, dir) in dirs(settings) {
+        std::fs::create_dir_all(&dir).map_err(|e| {
+            let hint = match e.kind() {
+                ErrorKind::PermissionDenied => {
+                    ", the user the server runs as needs write access to it or its parent"
+                }
+                _ => "",
+            };
+            format!("creating {} {}: {}{}", option, dir.display(), e, hint)
+        })?;
+    }
+    Ok(())
+}
+
+/// `{value}` of the placeholder `name` for `repo`. The url is split into the host,
+/// the path up to the last segment and `name()`, `hash8` is the start of its
+/// SHA-256.
+fn placeholder(name: &str, repo: &Repo) -> Option<String> {
+    let url = repo.url.trim_end_matches('/');
+    let (host, path) = url.split_once('/').unwrap_or((url, ""));
+    Some(match name {
+        "host" => host.to_string(),
+        "owner" => path
+            .rsplit_once('/')
+            .map_or("", |(owner, _)| owner)
+            .replace('/', "-"),
+        "repo" => repo.name(),
+        "hash8" => digest::digest(&digest::SHA256, repo.url.as_bytes())
+            .as_ref()
+            .iter()
+            .take(4)
+            .map(|byte| format!("{:02x}", byte))
+            .collect(),
+        _ => return None,
+    })
+}
+
+/// Name of the checkout of `repo` by `checkout_name_template`, `name()` without
+/// one. Rendered names have to be usable as a single directory name.
+pub fn checkout_name(template: Option<&str>, repo: &Repo) -> Result<String, String> {
+    let Some(template) = template else {
+        return Ok(repo.name());
+    };
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            return Err(format!("unclosed `{{` in `{}`", template));
+        };
+        let key = &rest[start + 1..start + end];
+        let Some(value) = placeholder(key, repo) else {
+            return Err(format!(
+                "unknown placeholder `{{{}}}`, known are {}",
+                key,
+                PLACEHOLDERS
+                    .map(|known| format!("{{{}}}", known))
+                    .join(", ")
+            ));
+        };
+        name.push_str(&value);
+        rest = &rest[start + end + 1..];
+    }
+    name.push_str(rest);
+
+    if name.is_empty() || name.starts_with('.') {
+        return Err(format!("`{}` is empty or starts with a dot", name));
+    }
+    if name.contains(['/', '\0']) {
+        return Err(format!("`{}` contains a `/`", name));
+    }
+    if name.len() > MAX_NAME_LEN {
+        return Err(format!(
+            "`{}` is longer than {} bytes, `{{hash8}}` keeps names short",
+            name, MAX_NAME_LEN
+        ));
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_default_to_below_dir() -> Result<(), Box<dyn std::error::Error>> {
+        let settings: AutoBuildOptions = serde_json::from_str(
+            r#"{"dir": "/srv/autobuild", "paths": {"logs_dir": "/var/log/autobuild"}}"#,
+        )?;
+        let dirs = dirs(&settings).map(|(_, dir)| dir);
+        assert_eq!(
+            dirs,
+            [
+                "/srv/autobuild",
+                "/srv/autobuild/repos",
+                "/var/log/autobuild",
+                "/srv/autobuild/state",
+                "/srv/autobuild/gcroots"
+            ]
+            .map(PathBuf::from)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn checkout_names_fill_in_the_template() -> Result<(), Box<dyn std::error::Error>> {
+        let repo: Repo = serde_json::from_str(r#"{"url": "gitlab.com/org/team/app.git"}"#)?;
+        let name = |template| checkout_name(Some(template), &repo);
+        assert_eq!(checkout_name(None, &repo)?, "app");
+        assert_eq!(name("{host}-{owner}-{repo}")?, "gitlab.com-org-team-app");
+        assert_eq!(name("{repo}-{hash8}")?.len(), "app-".len() + 8);
+        assert!(name("{repo}-{branch}").is_err());
+        assert!(name(".{repo}").is_err());
+        assert!(name("{owner}").is_ok());
+        assert!(name("{host}/{repo}").is_err());
+        assert!(name("a".repeat(300).as_str()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use std::{io::Read, process::Child, thread};
+
+use actix_web::web::Bytes;
+use futures_util::{Stream, stream};
+use tokio::sync::mpsc;
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Response body from the stdout of the last of `children`, e.g. the end of a
+/// pipeline. The processes are killed once the client is gone.
+pub fn stdout_stream(children: Vec<Child>) -> impl Stream<Item = actix_web::Result<Bytes>> {
+    // forward the pipe from a thread, reading it blocks
+    let (sender, receiver) = mpsc::channel::<Bytes>(16);
+    thread::spawn(move || forward_output(children, sender));
+    stream::unfold(receiver, |mut receiver| async move {
+        let chunk = receiver.recv().await?;
+        Some((Ok(chunk), receiver))
+    })
+}
+
+fn forward_output(mut children: Vec<Child>, sender: mpsc::Sender<Bytes>) {
+    let stdout = children.last_mut().and_then(|child| child.stdout.take());
+    if let Some(mut stdout) = stdout {
+        let mut buffer = vec![0; READ_CHUNK_SIZE];
+        while let Ok(read @ 1..) = stdout.read(&mut buffer) {
+            if sender
+                .blocking_send(Bytes::copy_from_slice(&buffer[..read]))
+                .is_err()
+            {
+                for child in children.iter_mut() {
+                    let _ = child.kill();
+                }
+                break;
+            }
+        }
+    }
+    for child in children.iter_mut() {
+        let _ = child.wait();
+    }
+}
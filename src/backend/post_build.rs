@@ -0,0 +1,283 @@
+//! `post_build`: the out paths of successful builds are signed and pushed to other
+//! stores and caches, one `PushStatus` per target is kept with the package
+
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::OnceLock,
+    time::Duration,
+};
+
+use crate::{
+    PostBuildOptions,
+    package::{BuildControl, PushState, PushStatus},
+};
+
+use super::{
+    FAILURE_LOG_LINES, cancel, last_lines,
+    secrets::{self, Secret},
+};
+
+/// Store URI schemes `nix copy --to` understands
+const STORE_SCHEMES: [&str; 10] = [
+    "daemon", "file", "gs", "http", "https", "local", "s3", "ssh", "ssh-ng", "unix",
+];
+
+/// Stores that are named without a `://`
+const BARE_STORES: [&str; 3] = ["auto", "daemon", "local"];
+
+enum Target {
+    Copy(String),
+    Cachix {
+        cache: String,
+        token: Option<Secret>,
+    },
+    Attic {
+        cache: String,
+    },
+}
+
+impl Target {
+    fn label(&self) -> String {
+        match self {
+            Target::Copy(uri) => uri.clone(),
+            Target::Cachix { cache, .. } => format!("cachix:{}", cache),
+            Target::Attic { cache } => format!("attic:{}", cache),
+        }
+    }
+
+    fn command(&self, paths: &[&str]) -> Command {
+        let mut command = match self {
+            Target::Copy(uri) => {
+                let mut command = Command::new("nix");
+                command.arg("copy").arg("--to").arg(uri);
+                command
+            }
+            Target::Cachix { cache, token } => {
+                let mut command = Command::new("cachix");
+                if let Some(token) = token {
+                    command.env("CACHIX_AUTH_TOKEN", token.expose());
+                }
+                command.arg("push").arg(cache);
+                command
+            }
+            Target::Attic { cache } => {
+                let mut command = Command::new("attic");
+                command.arg("push").arg(cache);
+                command
+            }
+        };
+        command.args(paths);
+        command
+    }
+}
+
+struct PostBuild {
+    sign_key_file: Option<PathBuf>,
+    targets: Vec<Target>,
+    /// of each command, `None` for no limit
+    timeout: Option<Duration>,
+}
+
+/// Set once at startup, `post_build` is not reloaded
+static POST_BUILD: OnceLock<PostBuild> = OnceLock::new();
+
+pub fn configure(options: &PostBuildOptions) {
+    let mut targets = options
+        .copy_to
+        .iter()
+        .cloned()
+        .map(Target::Copy)
+        .collect::<Vec<_>>();
+    if let Some(cachix) = &options.cachix {
+        let token = cachix.auth_token.as_ref().and_then(|source| {
+            secrets::resolve(source)
+                .inspect_err(|e| {
+                    tracing::error!("reading the cachix auth token, pushing without: {}", e)
+                })
+                .ok()
+        });
+        targets.push(Target::Cachix {
+            cache: cachix.cache.clone(),
+            token,
+        });
+    }
+    if let Some(attic) = &options.attic {
+        targets.push(Target::Attic {
+            cache: attic.cache.clone(),
+        });
+    }
+    let _ = POST_BUILD.set(PostBuild {
+        sign_key_file: options.sign_key_file.clone(),
+        targets,
+        timeout: (options.timeout_secs > 0).then(|| Duration::from_secs(options.timeout_secs)),
+    });
+}
+
+/// Sign `out_paths`, one per line as printed by `nix build --print-out-paths`, and
+/// push them to every target in turn. `update` gets the statuses of all targets
+/// whenever one changes. A cancel of the build kills the running command, the
+/// targets left fail.
+pub fn run(out_paths: &str, control: &BuildControl, update: impl Fn(Vec<PushStatus>)) {
+    let Some(post_build) = POST_BUILD.get() else {
+        return;
+    };
+    let paths = out_paths.split_whitespace().collect::<Vec<_>>();
+    let mut pushes = post_build
+        .targets
+        .iter()
+        .map(|target| PushStatus {
+            target: target.label(),
+            state: PushState::Pushing,
+        })
+        .collect::<Vec<_>>();
+    if !pushes.is_empty() {
+        update(pushes.clone());
+    }
+
+    let execute = |command| execute(command, control, post_build.timeout);
+    if let Some(key_file) = &post_build.sign_key_file
+        && let Err(e) = execute(sign_command(key_file, &paths))
+    {
+        tracing::error!("signing {}: {}", out_paths, e);
+        // unsigned paths would be rejected by the targets anyway
+        for push in pushes.iter_mut() {
+            push.state = PushState::Failed(format!("signing failed: {}", e));
+        }
+        if !pushes.is_empty() {
+            update(pushes);
+        }
+        return;
+    }
+
+    for index in 0..pushes.len() {
+        let target = &post_build.targets[index];
+        tracing::info!("Pushing {} to {}", out_paths, pushes[index].target);
+        pushes[index].state = match execute(target.command(&paths)) {
+            Ok(()) => PushState::Pushed,
+            Err(e) => {
+                tracing::error!("pushing {} to {}: {}", out_paths, pushes[index].target, e);
+                PushState::Failed(e)
+            }
+        };
+        update(pushes.clone());
+    }
+}
+
+fn sign_command(key_file: &Path, paths: &[&str]) -> Command {
+    let mut command = Command::new("nix");
+    command
+        .args(["store", "sign", "--recursive", "--key-file"])
+        .arg(key_file)
+        .args(paths);
+    command
+}
+
+/// The last lines of stderr when `command` fails, like a build it is killed on a
+/// cancel or after `timeout`
+fn execute(
+    mut command: Command,
+    control: &BuildControl,
+    timeout: Option<Duration>,
+) -> Result<(), String> {
+    if control.is_cancelled() {
+        return Err("cancelled".to_string());
+    }
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let output = cancel::wait_cancellable(child, control, None, timeout)
+        .map_err(|e| e.to_string())?
+        .ok_or("cancelled")?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Err(match stderr.trim() {
+        "" => format!("exited with {}", output.status),
+        _ => last_lines(&stderr, FAILURE_LOG_LINES),
+    })
+}
+
+/// A store `nix copy --to` can copy to, a known `scheme://...`, a bare store name
+/// or an absolute path
+pub fn check_store_uri(uri: &str) -> Result<(), String> {
+    if uri.chars().any(char::is_whitespace) {
+        return Err(format!("{:?} contains whitespace", uri));
+    }
+    let without_query = uri.split('?').next().unwrap_or_default();
+    if uri.starts_with('/') || BARE_STORES.contains(&without_query) {
+        return Ok(());
+    }
+    let Some((scheme, rest)) = uri.split_once("://") else {
+        return Err(format!(
+            "{:?} is neither a store URI nor an absolute path",
+            uri
+        ));
+    };
+    if !STORE_SCHEMES.contains(&scheme) {
+        return Err(format!(
+            "unknown store type {:?}, known are {}",
+            scheme,
+            STORE_SCHEMES.join(", ")
+        ));
+    }
+    let location = rest.split('?').next().unwrap_or_default();
+    if location.is_empty() && !BARE_STORES.contains(&scheme) && scheme != "unix" {
+        return Err(format!("{:?} names no store", uri));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_uris_are_checked() {
+        for uri in [
+            "s3://nix-cache?region=eu-central-1",
+            "ssh-ng://builder@cache.example.com",
+            "file:///srv/cache",
+            "/srv/store",
+            "daemon",
+            "local?root=/mnt",
+            "unix://?root=/mnt",
+        ] {
+            assert_eq!(check_store_uri(uri), Ok(()), "{}", uri);
+        }
+        assert!(check_store_uri("cache.example.com").is_err());
+        assert!(check_store_uri("ftp://cache.example.com").is_err());
+        assert!(check_store_uri("ssh://").is_err());
+        assert!(check_store_uri("s3://bucket name").is_err());
+    }
+
+    #[test]
+    fn pushes_are_killed_like_builds() {
+        let sleep = || {
+            let mut command = Command::new("sleep");
+            command.arg("10");
+            command
+        };
+        let control = std::sync::Arc::new(BuildControl::default());
+        assert_eq!(
+            execute(sleep(), &control, Some(Duration::from_millis(300))),
+            Err("killed after 300ms".to_string())
+        );
+        let cancel = {
+            let control = control.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(300));
+                control.cancel();
+            })
+        };
+        assert_eq!(
+            execute(sleep(), &control, None),
+            Err("cancelled".to_string())
+        );
+        assert!(cancel.join().is_ok());
+    }
+}
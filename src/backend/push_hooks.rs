@@ -0,0 +1,330 @@
+//! Webhooks forges send on pushes. A verified delivery wakes the poller of the
+//! pushed repo, so it needs no short poll interval to pick up new commits quickly.
+
+use actix_web::{
+    HttpRequest, HttpResponse, post,
+    web::{self, Bytes, ServiceConfig},
+};
+use ring::hmac;
+use serde::Deserialize;
+
+use super::{
+    BuildRepos,
+    secrets::{self, Secret},
+};
+use crate::IncomingWebhookOptions;
+
+/// GitHub caps its deliveries at 25 MB
+const MAX_PAYLOAD: usize = 25 * 1024 * 1024;
+const GITHUB_SIGNATURE_HEADER: &str = "X-Hub-Signature-256";
+const GITLAB_TOKEN_HEADER: &str = "X-Gitlab-Token";
+/// the header our own notification webhooks are signed with
+const GENERIC_SIGNATURE_HEADER: &str = "X-Autobuild-Signature-256";
+
+/// The secrets of `webhooks`, read once at startup. A hook without one is not served.
+#[derive(Clone, Default)]
+pub struct HookSecrets {
+    github: Option<hmac::Key>,
+    gitlab: Option<Token>,
+    generic: Option<hmac::Key>,
+}
+
+/// GitLab sends the token itself, it is compared through its HMAC in constant time
+#[derive(Clone)]
+struct Token {
+    key: hmac::Key,
+    /// of the token itself
+    tag: hmac::Tag,
+}
+
+impl Token {
+    fn new(key: hmac::Key, token: &str) -> Self {
+        let tag = hmac::sign(&key, token.as_bytes());
+        Token { key, tag }
+    }
+
+    fn matches(&self, token: &str) -> bool {
+        hmac::verify(&self.key, token.as_bytes(), self.tag.as_ref()).is_ok()
+    }
+}
+
+impl HookSecrets {
+    pub fn new(options: &IncomingWebhookOptions) -> Self {
+        let secret = |hook: &str, source, file| {
+            secrets::resolve_either(source, file).unwrap_or_else(|e| {
+                tracing::error!(
+                    "reading the secret of the {} webhook, not serving it: {}",
+                    hook,
+                    e
+                );
+                None
+            })
+        };
+        let key = |secret: &Secret| hmac::Key::new(hmac::HMAC_SHA256, secret.expose().as_bytes());
+        HookSecrets {
+            github: secret(
+                "GitHub",
+                options.github_secret.as_ref(),
+                options.github_secret_file.as_deref(),
+            )
+            .map(|secret| key(&secret)),
+            gitlab: secret(
+                "GitLab",
+                options.gitlab_token.as_ref(),
+                options.gitlab_token_file.as_deref(),
+            )
+            .map(|secret| Token::new(key(&secret), secret.expose())),
+            generic: secret(
+                "generic",
+                options.generic_secret.as_ref(),
+                options.generic_secret_file.as_deref(),
+            )
+            .map(|secret| key(&secret)),
+        }
+    }
+}
+
+pub fn configure(cfg: &mut ServiceConfig, secrets: HookSecrets) {
+    cfg.app_data(web::Data::new(secrets))
+        .app_data(web::PayloadConfig::new(MAX_PAYLOAD))
+        .service(github)
+        .service(gitlab)
+        .service(generic);
+}
+
+#[derive(Deserialize)]
+struct WebProject {
+    web_url: String,
+}
+
+#[derive(Deserialize)]
+struct GithubPush {
+    repository: WebRepository,
+}
+
+#[derive(Deserialize)]
+struct WebRepository {
+    html_url: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabPush {
+    project: WebProject,
+}
+
+#[derive(Deserialize)]
+struct GenericPush {
+    /// name or url of the repo, like the `{name}` of the other routes
+    repo: String,
+}
+
+#[post("/hooks/github")]
+async fn github(
+    req: HttpRequest,
+    body: Bytes,
+    secrets: web::Data<HookSecrets>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let Some(key) = &secrets.github else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    if !signed(key, &req, GITHUB_SIGNATURE_HEADER, &body) {
+        return unauthorized();
+    }
+    // sent when the hook is created, without a repository to poll
+    if header(&req, "X-GitHub-Event") == Some("ping") {
+        return HttpResponse::Ok().json("pong");
+    }
+    match serde_json::from_slice::<GithubPush>(&body) {
+        Ok(push) => poll(&repos, &push.repository.html_url),
+        Err(e) => HttpResponse::BadRequest().body(format!("not a push event: {}", e)),
+    }
+}
+
+#[post("/hooks/gitlab")]
+async fn gitlab(
+    req: HttpRequest,
+    body: Bytes,
+    secrets: web::Data<HookSecrets>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let Some(token) = &secrets.gitlab else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    if !header(&req, GITLAB_TOKEN_HEADER).is_some_and(|sent| token.matches(sent)) {
+        return unauthorized();
+    }
+    match serde_json::from_slice::<GitlabPush>(&body) {
+        Ok(push) => poll(&repos, &push.project.web_url),
+        Err(e) => HttpResponse::BadRequest().body(format!("not a push event: {}", e)),
+    }
+}
+
+#[post("/hooks/generic")]
+async fn generic(
+    req: HttpRequest,
+    body: Bytes,
+    secrets: web::Data<HookSecrets>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let Some(key) = &secrets.generic else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    if !signed(key, &req, GENERIC_SIGNATURE_HEADER, &body) {
+        return unauthorized();
+    }
+    match serde_json::from_slice::<GenericPush>(&body) {
+        Ok(push) => poll(&repos, &push.repo),
+        Err(e) => HttpResponse::BadRequest().body(format!("expected {{\"repo\": ...}}: {}", e)),
+    }
+}
+
+/// Wake the poller of the repo at `url`, which may start with a scheme unlike the
+/// configured urls
+fn poll(repos: &BuildRepos, url: &str) -> HttpResponse {
+    let name = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .trim_end_matches('/')
+        .trim_end_matches(".git");
+    let Some(repo) = repos.find(name) else {
+        tracing::debug!("Webhook for untracked repo {}", url);
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    tracing::info!("Webhook requested a poll of {}", repo.repo.url);
+    // a poll that is running already polls again right after
+    repo.poll_trigger.trigger();
+    HttpResponse::Accepted().json("poll_requested")
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Whether the `name` header holds the `sha256=<hex>` HMAC of `body`
+fn signed(key: &hmac::Key, req: &HttpRequest, name: &str, body: &[u8]) -> bool {
+    header(req, name)
+        .and_then(|signature| signature.strip_prefix("sha256="))
+        .and_then(unhex)
+        .is_some_and(|tag| hmac::verify(key, body, &tag).is_ok())
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().body("401 Unauthorized")
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::RepoInfoTrait;
+    use super::*;
+    use crate::{AutoBuildOptions, Repo, RepoList, repo::RepoInfo, serialize::VecArcWrapper};
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service},
+    };
+    use std::{
+        path::PathBuf,
+        sync::Arc,
+        time::{Duration, Instant},
+    };
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes());
+        let tag = hmac::sign(&key, body);
+        let hex = tag.as_ref().iter().map(|byte| format!("{:02x}", byte));
+        format!("sha256={}", hex.collect::<String>())
+    }
+
+    #[actix_web::test]
+    async fn verified_pushes_wake_the_poller() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_hooks_{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("github"), "hunter2\n")?;
+        std::fs::write(dir.join("gitlab"), "token")?;
+        let settings = serde_json::from_value::<AutoBuildOptions>(serde_json::json!({
+            "repos": [],
+            "webhooks": {
+                "github_secret": { "file": dir.join("github") },
+                "gitlab_token_file": dir.join("gitlab")
+            }
+        }))?;
+        let secrets = HookSecrets::new(&settings.webhooks);
+        std::fs::remove_dir_all(&dir)?;
+        let repo = RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"]
+            }))?,
+            PathBuf::from("/nonexistent/repo"),
+            Arc::new(settings.clone()),
+        );
+        let repos = BuildRepos::default();
+        repos.set(RepoList(VecArcWrapper::from(vec![repo.clone()])));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(repos))
+                .configure(|cfg| configure(cfg, secrets.clone())),
+        )
+        .await;
+
+        let push = br#"{"repository": {"html_url": "https://github.com/org/repo"}}"#;
+        let forged = TestRequest::post()
+            .uri("/hooks/github")
+            .insert_header((GITHUB_SIGNATURE_HEADER, sign("guess", push)))
+            .set_payload(&push[..])
+            .to_request();
+        assert_eq!(
+            call_service(&app, forged).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        let signed = TestRequest::post()
+            .uri("/hooks/github")
+            .insert_header((GITHUB_SIGNATURE_HEADER, sign("hunter2", push)))
+            .set_payload(&push[..])
+            .to_request();
+        assert_eq!(
+            call_service(&app, signed).await.status(),
+            StatusCode::ACCEPTED
+        );
+        let start = Instant::now();
+        repo.poll_trigger.wait(Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let push = br#"{"project": {"web_url": "https://github.com/org/repo.git"}}"#;
+        for (token, status) in [
+            ("tok", StatusCode::UNAUTHORIZED),
+            ("token", StatusCode::ACCEPTED),
+        ] {
+            let req = TestRequest::post()
+                .uri("/hooks/gitlab")
+                .insert_header((GITLAB_TOKEN_HEADER, token))
+                .set_payload(&push[..])
+                .to_request();
+            assert_eq!(call_service(&app, req).await.status(), status);
+        }
+
+        // no secret, no hook
+        let req = TestRequest::post()
+            .uri("/hooks/generic")
+            .set_payload(r#"{"repo": "repo"}"#)
+            .to_request();
+        assert_eq!(
+            call_service(&app, req).await.status(),
+            StatusCode::NOT_FOUND
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    HttpResponse,
+    dev::ServiceRequest,
+    http::{
+        Method,
+        header::{self, HeaderMap},
+    },
+};
+
+use crate::RateLimitOptions;
+
+use super::api;
+
+/// How often buckets that refilled completely are dropped, they behave exactly like
+/// a client that was never seen
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+/// Upper bound of tracked buckets, reached early the buckets are pruned right away
+const MAX_BUCKETS: usize = 100_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RouteClass {
+    /// `/store` and `/nix/store`, listings and archives
+    Store,
+    /// API requests that trigger work in the background
+    Actions,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Limit {
+    per_second: f64,
+    burst: f64,
+}
+
+impl Limit {
+    fn new(per_second: u32, burst: u32) -> Option<Self> {
+        (per_second != 0).then(|| Limit {
+            per_second: f64::from(per_second),
+            burst: f64::from(burst.max(1)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(limit: &Limit, now: Instant) -> Self {
+        Bucket {
+            tokens: limit.burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: &Limit, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst);
+        self.updated = now;
+    }
+
+    /// Take a token, or how long until the next one is available
+    fn take(&mut self, limit: &Limit, now: Instant) -> Result<(), Duration> {
+        self.refill(limit, now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - self.tokens) / limit.per_second,
+            ))
+        }
+    }
+}
+
+struct Buckets {
+    buckets: HashMap<(RouteClass, IpAddr), Bucket>,
+    pruned: Instant,
+}
+
+/// Token buckets per client IP and route class
+pub struct RateLimiter {
+    /// `base_path` of the routes
+    prefix: String,
+    store: Option<Limit>,
+    actions: Option<Limit>,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(options: &RateLimitOptions, prefix: String) -> Self {
+        RateLimiter {
+            prefix,
+            store: Limit::new(options.store_per_second, options.store_burst),
+            actions: Limit::new(options.actions_per_second, options.actions_burst),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                pruned: Instant::now(),
+            }),
+        }
+    }
+
+    /// `None` if `req` may pass, otherwise the seconds until the client may retry
+    pub fn retry_after(&self, req: &ServiceRequest) -> Option<u64> {
+        let class = classify(req.method(), req.path().strip_prefix(self.prefix.as_str())?)?;
+        let ip = client_ip(req.peer_addr().map(|addr| addr.ip()), req.headers());
+        let wait = self.check(class, ip, Instant::now()).err()?;
+        tracing::info!("Rate limited {} {} {}", ip, req.method(), req.path());
+        Some(wait.as_secs_f64().ceil().max(1.0) as u64)
+    }
+
+    fn limit(&self, class: RouteClass) -> Option<Limit> {
+        match class {
+            RouteClass::Store => self.store,
+            RouteClass::Actions => self.actions,
+        }
+    }
+
+    fn check(&self, class: RouteClass, ip: IpAddr, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit(class) else {
+            return Ok(());
+        };
+        let mut buckets = self.buckets.lock().unwrap_or_else(|p| p.into_inner());
+        if now.saturating_duration_since(buckets.pruned) >= PRUNE_INTERVAL
+            || buckets.buckets.len() >= MAX_BUCKETS
+        {
+            buckets.buckets.retain(|(class, _), bucket| {
+                let Some(limit) = self.limit(*class) else {
+                    return false;
+                };
+                bucket.refill(&limit, now);
+                bucket.tokens < limit.burst
+            });
+            // all of them busy, forgetting is better than growing without bounds
+            if buckets.buckets.len() >= MAX_BUCKETS {
+                buckets.buckets.clear();
+            }
+            buckets.pruned = now;
+        }
+        buckets
+            .buckets
+            .entry((class, ip))
+            .or_insert_with(|| Bucket::full(&limit, now))
+            .take(&limit, now)
+    }
+
+    #[cfg(test)]
+    fn tracked_clients(&self) -> usize {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .buckets
+            .len()
+    }
+}
+
+pub fn too_many_requests(retry_after: u64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after))
+        .body("429 Too Many Requests")
+}
+
+/// The address of the client of a request from `peer`. On the Unix socket there is
+/// no peer address, the only peer is the local proxy that was allowed to connect,
+/// so the client it names in `X-Real-IP` or last in `X-Forwarded-For` is trusted.
+/// Without them all requests through the socket share one bucket. Over TCP the
+/// headers are ignored, any client could set them.
+fn client_ip(peer: Option<IpAddr>, headers: &HeaderMap) -> IpAddr {
+    if let Some(peer) = peer {
+        return peer;
+    }
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    header("x-real-ip")
+        .or_else(|| header("x-forwarded-for")?.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(IpAddr::V6(Ipv6Addr::UNSPECIFIED))
+}
+
+/// `path` is `prefix` or below it, `/stores` is not below `/store`
+fn below(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+fn classify(method: &Method, path: &str) -> Option<RouteClass> {
+    if below(path, "/store") || below(path, "/nix/store") {
+        Some(RouteClass::Store)
+    } else if method == Method::POST && below(path, api::PREFIX) {
+        Some(RouteClass::Actions)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(
+            &RateLimitOptions {
+                store_per_second: 2,
+                store_burst: 3,
+                actions_per_second: 0,
+                actions_burst: 0,
+            },
+            String::new(),
+        )
+    }
+
+    #[test]
+    fn limits_after_burst() {
+        let limiter = limiter();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert_eq!(limiter.check(RouteClass::Store, ip, start), Ok(()));
+        }
+        assert_eq!(
+            limiter.check(RouteClass::Store, ip, start),
+            Err(Duration::from_millis(500))
+        );
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.check(RouteClass::Store, ip, later), Ok(()));
+        // other clients and unlimited classes are unaffected
+        let other = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(limiter.check(RouteClass::Store, other, start), Ok(()));
+        assert_eq!(limiter.check(RouteClass::Actions, ip, start), Ok(()));
+    }
+
+    #[test]
+    fn forgets_idle_clients() {
+        let limiter = limiter();
+        let start = Instant::now();
+        for i in 0..100 {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, i));
+            assert_eq!(limiter.check(RouteClass::Store, ip, start), Ok(()));
+        }
+        assert_eq!(limiter.tracked_clients(), 100);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let later = start + PRUNE_INTERVAL;
+        assert_eq!(limiter.check(RouteClass::Store, ip, later), Ok(()));
+        assert_eq!(limiter.tracked_clients(), 1);
+    }
+
+    #[test]
+    fn only_the_unix_socket_trusts_the_proxy() -> Result<(), Box<dyn std::error::Error>> {
+        let mut headers = HeaderMap::new();
+        let peer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        assert_eq!(client_ip(None, &headers), IpAddr::V6(Ipv6Addr::UNSPECIFIED));
+        headers.insert(
+            header::HeaderName::from_static("x-forwarded-for"),
+            header::HeaderValue::from_static("192.0.2.1, 198.51.100.7"),
+        );
+        assert_eq!(client_ip(None, &headers), "198.51.100.7".parse::<IpAddr>()?);
+        assert_eq!(client_ip(Some(peer), &headers), peer);
+        headers.insert(
+            header::HeaderName::from_static("x-real-ip"),
+            header::HeaderValue::from_static("203.0.113.5"),
+        );
+        assert_eq!(client_ip(None, &headers), "203.0.113.5".parse::<IpAddr>()?);
+        Ok(())
+    }
+
+    #[test]
+    fn classifies_routes() {
+        assert_eq!(
+            classify(&Method::GET, "/nix/store/abc-pkg"),
+            Some(RouteClass::Store)
+        );
+        assert_eq!(
+            classify(&Method::GET, "/store/abc-pkg"),
+            Some(RouteClass::Store)
+        );
+        assert_eq!(
+            classify(&Method::POST, "/api/v1/repos/x/poll"),
+            Some(RouteClass::Actions)
+        );
+        assert_eq!(classify(&Method::GET, "/api/v1/repos"), None);
+        assert_eq!(classify(&Method::GET, "/repos"), None);
+        assert_eq!(classify(&Method::GET, "/store"), Some(RouteClass::Store));
+        assert_eq!(classify(&Method::GET, "/stores"), None);
+        assert_eq!(classify(&Method::GET, "/storefoo/abc-pkg"), None);
+    }
+}
@@ -0,0 +1,156 @@
+use actix_web::{HttpResponse, post, web};
+use serde::{Deserialize, Serialize};
+
+use super::{BuildRepos, PackageEnumTrait, logs, shutdown};
+use crate::package::PackageBuildStatus;
+
+#[derive(Deserialize, Debug)]
+struct RebuildQuery {
+    /// build right away, also outside of the build window
+    #[serde(default)]
+    force: bool,
+}
+
+/// Why a package that is neither queued nor running cannot be built again
+#[derive(Serialize)]
+struct NotRebuildable {
+    error: &'static str,
+    status: PackageBuildStatus,
+}
+
+/// Build a finished package of a commit again, e.g. after a flaky failure
+#[post("/repos/{name}/commits/{hash}/packages/{path}/rebuild")]
+async fn rebuild_package(
+    path: web::Path<(String, String, String)>,
+    query: web::Query<RebuildQuery>,
+    repos: web::Data<BuildRepos>,
+) -> HttpResponse {
+    let (name, hash, pkg_path) = path.into_inner();
+    tracing::debug!(
+        "Requested rebuild of {} {} {} {:?}",
+        name,
+        hash,
+        pkg_path,
+        query
+    );
+    let Some(pkg) = logs::find_package(&repos, &name, &hash, &pkg_path) else {
+        return HttpResponse::NotFound().body("404 Not Found");
+    };
+    // the build would be cancelled right away
+    if shutdown::requested() {
+        return HttpResponse::ServiceUnavailable().body("503 Shutting Down");
+    }
+    // never built, or only skipped again
+    let status = pkg.status();
+    if matches!(
+        status,
+        PackageBuildStatus::Idle | PackageBuildStatus::UnsupportedArchitecture(_)
+    ) {
+        return HttpResponse::UnprocessableEntity().json(NotRebuildable {
+            error: "not_rebuildable",
+            status,
+        });
+    }
+    match pkg.rebuild(query.force) {
+        true => HttpResponse::Accepted().json("queued"),
+        false => HttpResponse::Conflict().json("already_queued"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RepoInfoTrait, database::StoredPackage};
+    use super::*;
+    use crate::{
+        AutoBuildOptions, Repo, RepoList,
+        commit::{CommitBuildStatus, CommitInfo},
+        package::BuildTimes,
+        repo::RepoInfo,
+        serialize::{RwLockWrapper, VecArcWrapper},
+    };
+    use actix_web::{
+        App,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service, read_body},
+    };
+    use std::{
+        path::PathBuf,
+        sync::{Arc, PoisonError},
+    };
+
+    #[actix_web::test]
+    async fn skipped_packages_are_not_rebuilt() -> Result<(), Box<dyn std::error::Error>> {
+        let settings = serde_json::from_value::<AutoBuildOptions>(serde_json::json!({
+            "repos": []
+        }))?;
+        let repo = RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"]
+            }))?,
+            PathBuf::from("/nonexistent/repo"),
+            Arc::new(settings),
+        );
+        let commit = Arc::new(CommitInfo {
+            flake_url: "git+https://github.com/org/repo?rev=abc123".to_string(),
+            hash: "abc123".to_string(),
+            message: "Initial commit".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(&repo)),
+            unix_secs: 0,
+            diff_stats: None,
+        });
+        let package = |path: &str, status| StoredPackage {
+            path: path.to_string(),
+            nixos_config: false,
+            name: "hello".to_string(),
+            description: String::new(),
+            pkg_type: "derivation".to_string(),
+            arch: "aarch64-linux".to_string(),
+            status,
+            times: BuildTimes::default(),
+            closure_size: None,
+            attempts: Vec::new(),
+        };
+        commit.packages.set(vec![
+            package(
+                "packages.aarch64-linux.hello",
+                PackageBuildStatus::UnsupportedArchitecture("aarch64-linux"),
+            )
+            .into_package(&commit),
+            package("packages.aarch64-linux.other", PackageBuildStatus::Idle).into_package(&commit),
+        ]);
+        repo.commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(commit.hash.clone(), commit.clone());
+        let repos = BuildRepos::default();
+        repos.set(RepoList(VecArcWrapper::from(vec![repo])));
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(repos))
+                .service(rebuild_package),
+        )
+        .await;
+
+        for (path, status) in [
+            ("hello", r#""UnsupportedArchitecture":"aarch64-linux""#),
+            ("other", r#""status":"Idle""#),
+        ] {
+            let req = TestRequest::post()
+                .uri(&format!(
+                    "/repos/repo/commits/abc123/packages/packages.aarch64-linux.{}/rebuild",
+                    path
+                ))
+                .to_request();
+            let response = call_service(&app, req).await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+            let body = String::from_utf8(read_body(response).await.to_vec())?;
+            assert!(body.contains("not_rebuildable"), "{}", body);
+            assert!(body.contains(status), "{}", body);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,405 @@
+//! Applying an edited config file on SIGHUP, or when `watch_config` sees it change,
+//! without a restart. Added repos start polling and removed ones stop. A repo whose
+//! settings changed gets a new poller that takes over its commits, so running builds
+//! go on. Outside of `repos` only `dry_run` and `build_window` are applied, the rest
+//! is only read at startup.
+
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, LazyLock, Mutex, PoisonError, mpsc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use actix_web::{
+    HttpResponse, get,
+    rt::signal::unix::{SignalKind, signal},
+};
+use notify::{RecursiveMode, Watcher};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+    AutoBuildOptions, Repo, RepoList, event::StateEvent, repo::RepoInfo, serialize::VecArcWrapper,
+};
+
+use super::{
+    BuildRepos, PackageEnumTrait, RepoInfoTrait, builder_info, config_file, events, retention,
+    validate,
+};
+
+/// Settings applied without a restart, `repos` by a reload and the rest while it runs
+const RELOADED_OPTIONS: [&str; 4] = [
+    "repos",
+    "delete_removed_checkouts",
+    "dry_run",
+    "build_window",
+];
+/// Repo settings that are only read at startup
+const STARTUP_REPO_OPTIONS: [&str; 1] = ["email_recipients"];
+
+/// Quiet time after a change of the config file before it is reloaded
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// How often the removal of a repo checks whether its killed builds ended
+const BUILD_END_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Polling thread of every repo by url
+static POLLERS: LazyLock<Mutex<HashMap<String, JoinHandle<()>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Poll `repo` on its own thread
+pub fn start_poller(repo: Arc<RepoInfo>) {
+    let url = repo.repo.url.clone();
+    let poller = thread::spawn(move || repo.thread_poll());
+    POLLERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(url, poller);
+}
+
+/// Wake the poller of `repo` and wait until it exited after its current poll
+fn stop_poller(repo: &RepoInfo) {
+    repo.poll_trigger.stop();
+    let poller = POLLERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&repo.repo.url);
+    if let Some(poller) = poller {
+        let _ = poller.join();
+    }
+}
+
+/// Cancel the queued and running builds of a removed `repo` and wait until they all
+/// ended, nix must not run in a checkout that gets deleted
+fn cancel_builds(repo: &RepoInfo) {
+    let packages = repo
+        .commits
+        .inner()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .flat_map(|commit| commit.packages.get())
+        .collect::<Vec<_>>();
+    for pkg in &packages {
+        pkg.cancel();
+    }
+    for pkg in &packages {
+        while !pkg.status().is_finished() {
+            thread::sleep(BUILD_END_CHECK_INTERVAL);
+        }
+    }
+}
+
+/// Everything a reload needs besides the new config
+#[derive(Clone)]
+pub struct Reloader {
+    pub config_path: String,
+    /// as read at startup
+    pub settings: Arc<AutoBuildOptions>,
+    /// replaced by the repos of the reloaded config
+    pub repos: BuildRepos,
+}
+
+impl Reloader {
+    /// Reload every time the process receives SIGHUP
+    pub fn reload_on_sighup(self) -> std::io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let reloader = self.clone();
+                // loading may evaluate nix and the old pollers are waited for
+                let reloaded =
+                    actix_web::rt::task::spawn_blocking(move || reloader.apply("SIGHUP")).await;
+                if let Err(e) = reloaded {
+                    tracing::error!("reloading config: {}", e);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Reload whenever the content of the config file or of the files of a config
+    /// directory changes. The directory is watched, editors and config maps replace
+    /// the file instead of writing to it.
+    pub fn watch(self) -> notify::Result<()> {
+        if !config_file::is_file(&self.config_path) {
+            tracing::info!("`watch_config` does not apply to flake outputs, send SIGHUP instead");
+            return Ok(());
+        }
+        let config_path = Path::new(&self.config_path);
+        let dir = match config_path.is_dir() {
+            true => config_path,
+            false => config_path
+                .parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new(".")),
+        };
+        let (sender, changes) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        tracing::info!("Watching {} for changes", self.config_path);
+        STATUS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .watching = true;
+
+        let mut loaded = config_file::contents(&self.config_path);
+        thread::spawn(move || {
+            let _watcher = watcher;
+            while changes.recv().is_ok() {
+                // editors write several times in a row, wait until it settles
+                while changes.recv_timeout(DEBOUNCE).is_ok() {}
+                let current = config_file::contents(&self.config_path);
+                if current.is_none() || current == loaded {
+                    continue;
+                }
+                loaded = current;
+                self.apply("a change of the config file");
+            }
+        });
+        Ok(())
+    }
+
+    /// Reload and record the outcome for `/config/status`, a config that fails to
+    /// load leaves the running one as it is
+    fn apply(&self, cause: &str) {
+        // SIGHUP and the watcher must not apply two configs at once
+        static APPLYING: Mutex<()> = Mutex::new(());
+        let _applying = APPLYING.lock().unwrap_or_else(PoisonError::into_inner);
+        tracing::info!("Reloading {} after {}", self.config_path, cause);
+        let result = reload(&self.config_path, &self.settings, &self.repos);
+        if result.is_ok() {
+            // e.g. nix was upgraded along with the config
+            builder_info::capture();
+        }
+        let mut status = STATUS.lock().unwrap_or_else(PoisonError::into_inner);
+        status.last_attempt = Some(events::unix_now());
+        match result {
+            Ok(()) => {
+                status.last_success = status.last_attempt;
+                status.error = None;
+            }
+            Err(e) => {
+                tracing::error!(
+                    "==== config {} REJECTED, the previous config keeps running ====",
+                    self.config_path
+                );
+                tracing::error!("{}", e);
+                status.error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ReloadStatus {
+    /// unix seconds of the last reload, applied or not
+    last_attempt: Option<i64>,
+    last_success: Option<i64>,
+    /// why the config of the last reload was rejected
+    error: Option<String>,
+    /// whether `watch_config` is active
+    watching: bool,
+}
+
+static STATUS: Mutex<ReloadStatus> = Mutex::new(ReloadStatus {
+    last_attempt: None,
+    last_success: None,
+    error: None,
+    watching: false,
+});
+
+/// Outcome of the last config reload
+#[get("/config/status")]
+pub async fn config_status() -> HttpResponse {
+    let status = STATUS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    HttpResponse::Ok().json(status)
+}
+
+fn to_object<T: Serialize>(value: &T) -> serde_json::Map<String, Value> {
+    match serde_json::to_value(value) {
+        Ok(Value::Object(object)) => object,
+        _ => serde_json::Map::new(),
+    }
+}
+
+/// Top level options that differ, apart from `skip`
+fn changed_options<T: Serialize>(old: &T, new: &T, skip: &[&str]) -> Vec<String> {
+    let (old, new) = (to_object(old), to_object(new));
+    let mut changed = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| !skip.contains(&key.as_str()) && old.get(*key) != new.get(*key))
+        .cloned()
+        .collect::<Vec<_>>();
+    changed.sort();
+    changed.dedup();
+    changed
+}
+
+/// `new` continues where `old` left off until its first poll, the commits belong
+/// to it from now on
+fn take_over(new: &Arc<RepoInfo>, old: &RepoInfo) {
+    let commits = old
+        .commits
+        .inner()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    for commit in commits.values() {
+        commit.repo.set(Arc::downgrade(new));
+    }
+    *new.commits
+        .inner()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = commits;
+    for (branch, hashes) in &new.branch_commit_hashes {
+        if let Some(old_hashes) = old.branch_commit_hashes.get(branch) {
+            hashes.set(old_hashes.get());
+        }
+    }
+    new.poll_history.set(old.poll_history.get());
+    new.running_builds.take_over(&old.running_builds);
+}
+
+fn reload(
+    config_path: &str,
+    settings: &Arc<AutoBuildOptions>,
+    build_repos: &BuildRepos,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reloaded = config_file::load(config_path)?;
+    // the address is ours already, and changes to it wait for a restart anyway
+    for warning in validate::validate(&reloaded, false)? {
+        tracing::warn!("config {}", warning);
+    }
+    for option in changed_options(settings.as_ref(), &reloaded, &RELOADED_OPTIONS) {
+        tracing::info!("Config option `{}` changed, it requires a restart", option);
+    }
+    // builds that already ended stay as they are
+    super::dry_run::set(reloaded.dry_run);
+    // waiting builds go as soon as the new window is open
+    super::build_window::set(reloaded.build_window.as_ref())?;
+
+    let running = build_repos.snapshot();
+    let running = running.0.inner();
+    let mut changed = false;
+    let mut repos = Vec::new();
+    for repo in &reloaded.repos {
+        if repos
+            .iter()
+            .any(|other: &Arc<RepoInfo>| other.repo.url == repo.url)
+        {
+            tracing::error!("repo {} is listed twice, skipping the second one", repo.url);
+            continue;
+        }
+        let old = running.iter().find(|old| old.repo.url == repo.url);
+        let repo_changes = old
+            .map(|old| changed_options(&old.repo, repo, &[]))
+            .unwrap_or_default();
+        match old {
+            Some(old) if repo_changes.is_empty() => repos.push(old.clone()),
+            Some(old) => {
+                tracing::info!(
+                    "Reconfiguring repo {}: {}",
+                    repo.url,
+                    repo_changes.join(", ")
+                );
+                for option in repo_changes
+                    .iter()
+                    .filter(|option| STARTUP_REPO_OPTIONS.contains(&option.as_str()))
+                {
+                    tracing::info!(
+                        "`{}` of repo {} changed, it requires a restart",
+                        option,
+                        repo.url
+                    );
+                }
+                let repo_info =
+                    RepoInfo::new(repo.clone(), old.checkout_path.clone(), settings.clone());
+                // commits the old poller adds after the take over would be lost
+                stop_poller(old);
+                take_over(&repo_info, old);
+                start_poller(repo_info.clone());
+                repos.push(repo_info);
+                changed = true;
+            }
+            None => {
+                tracing::info!("Adding repo {}", repo.url);
+                let repo_info = RepoInfo::new(
+                    repo.clone(),
+                    super::checkout_path(settings, repo),
+                    settings.clone(),
+                );
+                start_poller(repo_info.clone());
+                repos.push(repo_info);
+                changed = true;
+            }
+        }
+    }
+    for old in running {
+        if reloaded
+            .repos
+            .iter()
+            .any(|repo: &Repo| repo.url == old.repo.url)
+        {
+            continue;
+        }
+        tracing::info!("Removing repo {}", old.repo.url);
+        let delete = reloaded.delete_removed_checkouts;
+        let old = old.clone();
+        thread::spawn(move || {
+            // the last poll may still add commits
+            stop_poller(&old);
+            cancel_builds(&old);
+            retention::forget_all(&old);
+            if delete && let Err(e) = old.delete_repo() {
+                tracing::error!("deleting {}: {}", old.checkout_path.display(), e);
+            }
+        });
+        changed = true;
+    }
+
+    if !changed {
+        tracing::info!("Repos unchanged");
+        return Ok(());
+    }
+    build_repos.set(RepoList(VecArcWrapper::from(repos)));
+    events::publish(StateEvent::ReposChanged);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_options_skip_the_reloaded_ones() -> Result<(), Box<dyn std::error::Error>> {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/config.json");
+        let old = config_file::load(path)?;
+        let mut new = config_file::load(path)?;
+        assert!(changed_options(&old, &new, &RELOADED_OPTIONS).is_empty());
+
+        new.repos.pop();
+        new.delete_removed_checkouts = true;
+        new.port += 1;
+        new.n_build_threads = 4;
+        assert_eq!(
+            changed_options(&old, &new, &RELOADED_OPTIONS),
+            ["n_build_threads", "port"]
+        );
+
+        let mut repo = old.repos[0].clone();
+        repo.poll_interval_sec = Some(60);
+        repo.branches.push("dev".to_string());
+        assert_eq!(
+            changed_options(&old.repos[0], &repo, &[]),
+            ["branches", "poll_interval_sec"]
+        );
+        Ok(())
+    }
+}
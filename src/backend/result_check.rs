@@ -0,0 +1,115 @@
+//! Checking that the output paths of successful builds are still in the store every
+//! `result_check_interval_secs`. `nix-collect-garbage` deletes them unless something
+//! roots them, their links would then only answer 404. The status keeps the paths,
+//! the package is marked as missing its result until it is built again or the paths
+//! are back, e.g. substituted by another build.
+
+use std::{
+    path::Path,
+    sync::{Arc, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use super::{BuildRepos, events, shutdown};
+use crate::{
+    AutoBuildOptions,
+    event::StateEvent,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+/// Any of the whitespace separated `out_paths` is gone
+fn is_missing(out_paths: &str) -> bool {
+    out_paths
+        .split_whitespace()
+        .any(|path| !Path::new(path).exists())
+}
+
+/// Mark the successful builds of `repos` whose result is gone or back, returns how
+/// many are missing it
+pub fn check(repos: &[Arc<RepoInfo>]) -> usize {
+    let mut missing_count = 0;
+    for repo in repos {
+        let commits = repo
+            .commits
+            .inner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        for commit in commits {
+            for pkg in commit.packages.get() {
+                let PackageBuildStatus::Success(out_paths) = pkg.status() else {
+                    continue;
+                };
+                let missing = is_missing(&out_paths);
+                if missing {
+                    missing_count += 1;
+                }
+                if missing == pkg.result_missing() {
+                    continue;
+                }
+                let stored = match &pkg {
+                    PackageEnum::Derivation(pkg) => &pkg.0.result_missing,
+                    PackageEnum::NixosConfig(pkg) => &pkg.0.result_missing,
+                };
+                stored.set(missing);
+                match missing {
+                    true => tracing::warn!(
+                        "The result of {} was garbage collected: {}",
+                        pkg.flake_url(),
+                        out_paths
+                    ),
+                    false => tracing::info!("The result of {} is back", pkg.flake_url()),
+                }
+                events::publish(StateEvent::PackageResult {
+                    repo: repo.repo.url.clone(),
+                    commit: commit.hash.clone(),
+                    path: pkg.path().to_string(),
+                    missing,
+                });
+            }
+        }
+    }
+    missing_count
+}
+
+/// Check the results at startup and every `result_check_interval_secs` until the
+/// shutdown, unless it is 0
+pub fn start(settings: Arc<AutoBuildOptions>, repos: BuildRepos) {
+    if settings.result_check_interval_secs == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(settings.result_check_interval_secs);
+    thread::spawn(move || {
+        while !shutdown::requested() {
+            let missing = check(repos.snapshot().0.inner());
+            tracing::debug!("{} successful builds miss their result", missing);
+            thread::sleep(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_result_is_missing_with_any_of_its_paths() -> Result<(), Box<dyn std::error::Error>> {
+        let dir =
+            std::env::temp_dir().join(format!("nix_autobuild_result_check_{}", std::process::id()));
+        let (out, dev) = (dir.join("hello"), dir.join("hello-dev"));
+        std::fs::create_dir_all(&out)?;
+        std::fs::write(&dev, "")?;
+        let paths = format!("{} {}", out.display(), dev.display());
+        assert!(!is_missing(&paths));
+
+        std::fs::remove_file(&dev)?;
+        assert!(is_missing(&paths));
+        assert!(!is_missing(&out.display().to_string()));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
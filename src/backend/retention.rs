@@ -0,0 +1,288 @@
+//! Forgetting commits that are on no branch anymore, so a long running server
+//! neither grows without bound nor sends all of them with every `/repos`. A
+//! forgotten commit drops its packages, which point back at it, so the memory is
+//! freed once no build or request holds them anymore. With `archive_after_days`
+//! they are written to the [`super::archive`] first.
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, PoisonError},
+};
+
+use super::{archive, database, events};
+use crate::{
+    commit::{CommitBuildStatus, CommitInfo},
+    event::StateEvent,
+    repo::RepoInfo,
+};
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Commits on no branch kept without a `max_commits_per_repo`, e.g. those just
+/// force pushed over, the others would pile up until a restart
+const GRACE_COMMITS: usize = 20;
+
+/// The packages are still evaluated, or builds wait or run
+fn is_busy(commit: &CommitInfo) -> bool {
+    matches!(commit.status.get(), CommitBuildStatus::GettingPackages)
+        || commit
+            .packages
+            .get()
+            .iter()
+            .any(|pkg| !pkg.status().is_finished())
+}
+
+/// Hashes of the `candidates` (hash and commit time) to forget. `kept` commits
+/// stay anyway but count towards `max_commits`, the candidates fill the remaining
+/// room newest first unless they are older than `oldest`. Without `max_commits`
+/// (0) the room is [`GRACE_COMMITS`], and without `oldest` there is no age limit.
+fn to_prune(
+    kept: usize,
+    mut candidates: Vec<(String, i64)>,
+    max_commits: usize,
+    oldest: Option<i64>,
+) -> Vec<String> {
+    candidates.sort_by_key(|(_, unix_secs)| std::cmp::Reverse(*unix_secs));
+    let room = match max_commits {
+        0 => GRACE_COMMITS,
+        max => max.saturating_sub(kept),
+    };
+    candidates
+        .into_iter()
+        .enumerate()
+        .filter(|(index, (_, unix_secs))| {
+            *index >= room || oldest.is_some_and(|oldest| *unix_secs < oldest)
+        })
+        .map(|(_, (hash, _))| hash)
+        .collect()
+}
+
+/// Forget the commits of `repo` beyond `max_commits_per_repo` and
+/// `max_commit_age_days` that are on no branch and have no build in flight,
+/// returns how many
+pub fn prune(repo: &Arc<RepoInfo>, now: i64) -> usize {
+    let on_branch = repo
+        .branch_commit_hashes
+        .values()
+        .flat_map(|hashes| hashes.get())
+        .collect::<HashSet<_>>();
+    let (kept, candidates) = {
+        let commits = repo
+            .commits
+            .inner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        let (kept, candidates): (Vec<_>, Vec<_>) = commits
+            .values()
+            .partition(|commit| on_branch.contains(&commit.hash) || is_busy(commit));
+        (
+            kept.len(),
+            candidates
+                .iter()
+                .map(|commit| (commit.hash.clone(), commit.unix_secs))
+                .collect::<Vec<_>>(),
+        )
+    };
+    let oldest = match repo.settings.max_commit_age_days {
+        0 => None,
+        days => Some(now - days as i64 * SECS_PER_DAY),
+    };
+    // the archive takes them earlier
+    let oldest = match repo.settings.archive_after_days {
+        0 => oldest,
+        days => {
+            let archived = now - days as i64 * SECS_PER_DAY;
+            Some(oldest.map_or(archived, |oldest| oldest.max(archived)))
+        }
+    };
+    let pruned = to_prune(kept, candidates, repo.settings.max_commits_per_repo, oldest);
+    // the history of each package is kept like the commits
+    database::prune_history(&repo.repo.url, repo.settings.max_commits_per_repo, oldest);
+    if pruned.is_empty() {
+        return 0;
+    }
+
+    if repo.settings.archive_after_days > 0 {
+        let archived = {
+            let commits = repo
+                .commits
+                .inner()
+                .read()
+                .unwrap_or_else(PoisonError::into_inner);
+            pruned
+                .iter()
+                .filter_map(|hash| commits.get(hash).cloned())
+                .collect::<Vec<_>>()
+        };
+        // kept until they are safe in the archive, which must not block the
+        // requests and the builds reading the commits
+        if let Err(e) = archive::archive(&repo.settings, &archived, now) {
+            tracing::error!("archiving the commits of {}: {}", repo.repo.name(), e);
+            return 0;
+        }
+    }
+    let mut commits = repo
+        .commits
+        .inner()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner);
+    // a rebuild may have started while they were archived, those stay until it ended
+    let pruned = pruned
+        .into_iter()
+        .filter(|hash| commits.get(hash).is_some_and(|commit| !is_busy(commit)))
+        .collect::<Vec<_>>();
+    for hash in &pruned {
+        if let Some(commit) = commits.remove(hash) {
+            commit.forget();
+        }
+    }
+    drop(commits);
+    if pruned.is_empty() {
+        return 0;
+    }
+    database::forget_commits(&repo.repo.url, &pruned);
+    tracing::info!(
+        "Forgot {} commits of {} that are on no branch anymore",
+        pruned.len(),
+        repo.repo.name()
+    );
+    events::publish(StateEvent::CommitsChanged {
+        repo: repo.repo.url.clone(),
+    });
+    pruned.len()
+}
+
+/// Forget all commits of a removed `repo`, builds still running finish first
+pub fn forget_all(repo: &RepoInfo) {
+    let commits = std::mem::take(
+        &mut *repo
+            .commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner),
+    );
+    for commit in commits.values() {
+        commit.forget();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::super::{RepoInfoTrait, database::StoredPackage};
+    use super::*;
+    use crate::{
+        AutoBuildOptions, Repo,
+        package::{BuildTimes, PackageBuildStatus},
+        serialize::RwLockWrapper,
+    };
+
+    fn candidates() -> Vec<(String, i64)> {
+        [("a", 100), ("b", 400), ("c", 300), ("d", 200)]
+            .map(|(hash, unix_secs)| (hash.to_string(), unix_secs))
+            .to_vec()
+    }
+
+    #[test]
+    fn keeps_the_newest_within_the_limits() {
+        // two on a branch leave room for two more
+        assert_eq!(to_prune(2, candidates(), 4, None), ["d", "a"]);
+        assert_eq!(to_prune(2, candidates(), 0, Some(250)), ["d", "a"]);
+        assert_eq!(to_prune(0, candidates(), 3, Some(350)), ["c", "d", "a"]);
+        assert_eq!(to_prune(5, candidates(), 4, None).len(), 4);
+        assert!(to_prune(5, candidates(), 0, None).is_empty());
+    }
+
+    #[test]
+    fn keeps_a_grace_set_without_limits() {
+        let candidates = (0..GRACE_COMMITS as i64 + 3)
+            .map(|unix_secs| (unix_secs.to_string(), unix_secs))
+            .collect::<Vec<_>>();
+        assert_eq!(to_prune(100, candidates, 0, None), ["2", "1", "0"]);
+    }
+
+    /// A commit as a poll finds it, with one package that built
+    fn poll(repo: &Arc<RepoInfo>, hash: String, unix_secs: i64) -> Arc<CommitInfo> {
+        let commit = Arc::new(CommitInfo {
+            flake_url: format!("git+https://{}?rev={}", repo.repo.url, hash),
+            hash: hash.clone(),
+            message: "Change".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(repo)),
+            unix_secs,
+            diff_stats: None,
+        });
+        let package = StoredPackage {
+            path: "packages.x86_64-linux.hello".to_string(),
+            nixos_config: false,
+            name: "hello".to_string(),
+            description: String::new(),
+            pkg_type: "derivation".to_string(),
+            arch: "x86_64-linux".to_string(),
+            status: PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+            times: BuildTimes::default(),
+            closure_size: None,
+            attempts: Vec::new(),
+        };
+        commit.packages.set(vec![package.into_package(&commit)]);
+        repo.commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(hash.clone(), commit.clone());
+        if let Some(hashes) = repo.branch_commit_hashes.get("main") {
+            hashes.set(vec![hash]);
+        }
+        commit
+    }
+
+    #[test]
+    fn polls_keep_the_commits_bounded() -> Result<(), Box<dyn std::error::Error>> {
+        let settings: AutoBuildOptions = serde_json::from_value(serde_json::json!({
+            "repos": [],
+            "max_commits_per_repo": 5,
+            "max_commit_age_days": 0
+        }))?;
+        let repo = RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"]
+            }))?,
+            PathBuf::from("/nonexistent/repo"),
+            Arc::new(settings),
+        );
+        let mut forgotten = Vec::new();
+        for poll_number in 0..200 {
+            let commit = poll(&repo, format!("{:040x}", poll_number), poll_number);
+            forgotten.push(Arc::downgrade(&commit));
+            prune(&repo, poll_number);
+            assert!(
+                repo.commits
+                    .inner()
+                    .read()
+                    .map_or(0, |commits| commits.len())
+                    <= 5
+            );
+        }
+        // freed, not only gone from the map
+        assert!(
+            forgotten[..195]
+                .iter()
+                .all(|commit| commit.upgrade().is_none())
+        );
+        assert!(
+            forgotten[195..]
+                .iter()
+                .all(|commit| commit.upgrade().is_some())
+        );
+
+        let removed = Arc::downgrade(&repo);
+        forget_all(&repo);
+        drop(repo);
+        assert!(removed.upgrade().is_none());
+        assert!(forgotten.iter().all(|commit| commit.upgrade().is_none()));
+        Ok(())
+    }
+}
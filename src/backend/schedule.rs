@@ -0,0 +1,83 @@
+//! When a repo is polled next, `poll_interval_sec` after the last poll or at the
+//! next time its `poll_schedule` fires
+
+use std::str::FromStr;
+
+use chrono::{DateTime, TimeZone};
+use cron::Schedule;
+
+use crate::Repo;
+
+/// `expression` with five fields gets a seconds field of 0 in front, the `cron`
+/// crate only knows six and seven
+pub fn parse(expression: &str) -> Result<Schedule, String> {
+    let expression = expression.trim();
+    let expression = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string(),
+    };
+    Schedule::from_str(&expression)
+        .map_err(|e| format!("invalid cron expression `{}`: {}", expression, e))
+}
+
+/// Unix seconds of the next poll of `repo` after the one at `now`. A schedule that
+/// does not fire anymore falls back to the interval.
+pub fn next_poll<Tz: TimeZone>(repo: &Repo, now: &DateTime<Tz>) -> i64 {
+    if let Some(expression) = &repo.poll_schedule {
+        match parse(expression) {
+            Ok(schedule) => {
+                if let Some(next) = schedule.after(now).next() {
+                    return next.timestamp();
+                }
+            }
+            Err(e) => tracing::error!("polling {}: {}", repo.name(), e),
+        }
+    }
+    now.timestamp() + repo.poll_interval_sec() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn repo(
+        poll_interval_sec: Option<u64>,
+        poll_schedule: Option<&str>,
+    ) -> Result<Repo, serde_json::Error> {
+        serde_json::from_value(serde_json::json!({
+            "url": "github.com/a/b",
+            "poll_interval_sec": poll_interval_sec,
+            "poll_schedule": poll_schedule,
+        }))
+    }
+
+    #[test]
+    fn polls_at_the_next_fire_time() -> Result<(), Box<dyn std::error::Error>> {
+        // Wednesday 2026-01-07 19:57 UTC
+        let now = Utc
+            .with_ymd_and_hms(2026, 1, 7, 19, 57, 0)
+            .single()
+            .ok_or("no such time")?;
+        let at = |hour, minute| {
+            Utc.with_ymd_and_hms(2026, 1, 7, hour, minute, 0)
+                .single()
+                .map(|time| time.timestamp())
+        };
+
+        let weekdays = repo(None, Some("*/10 8-19 * * Mon-Fri"))?;
+        assert_eq!(
+            Some(next_poll(&weekdays, &now)),
+            at(8, 0).map(|t| t + 86400)
+        );
+        let seconds = repo(None, Some("30 */5 * * * *"))?;
+        assert_eq!(Some(next_poll(&seconds, &now)), at(20, 0).map(|t| t + 30));
+        assert_eq!(
+            next_poll(&repo(Some(60), None)?, &now),
+            now.timestamp() + 60
+        );
+        assert_eq!(next_poll(&repo(None, None)?, &now), now.timestamp() + 300);
+        assert!(parse("every ten minutes").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+//! `show-schema` and `GET /api/v1/config-schema`: a JSON Schema of the config for
+//! editors. The descriptions are taken from the `#[nixos(description)]` attributes
+//! through the NixOS module, so the schema and the module document the options in
+//! the same words.
+
+use std::{collections::HashMap, process::ExitCode};
+
+use actix_web::{HttpResponse, get};
+use serde_json::Value;
+
+use crate::{AutoBuildOptions, NOTIFICATION_EVENTS};
+
+use super::nixos_module;
+
+/// Descriptions of the options by name, per type
+type Descriptions = HashMap<String, HashMap<String, String>>;
+
+/// The schema of `AutoBuildOptions` and its nested types in `$defs`
+pub fn schema() -> Value {
+    let mut schema = schemars::schema_for!(AutoBuildOptions).to_value();
+    let descriptions = descriptions(&nixos_module::render());
+    if let Some(options) = descriptions.get("AutoBuildOptions") {
+        describe(&mut schema, options);
+    }
+    if let Some(Value::Object(definitions)) = schema.get_mut("$defs") {
+        for (name, definition) in definitions.iter_mut() {
+            if let Some(options) = descriptions.get(name) {
+                describe(definition, options);
+            }
+        }
+    }
+    schema
+}
+
+pub fn show() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    println!("{}", serde_json::to_string_pretty(&schema())?);
+    Ok(ExitCode::SUCCESS)
+}
+
+#[get("/config-schema")]
+pub async fn config_schema() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/schema+json")
+        .json(schema())
+}
+
+/// The `description`s of the options in the sections of `module`, the options of
+/// `repoType` under `Repo`
+fn descriptions(module: &str) -> Descriptions {
+    let mut descriptions = Descriptions::new();
+    let mut section = None;
+    let mut option = None;
+    for line in module.lines() {
+        if let Some(name) = line
+            .strip_prefix("  ")
+            .and_then(|line| line.strip_suffix("Type = {"))
+        {
+            let mut chars = name.chars();
+            section = chars
+                .next()
+                .map(|first| format!("{}{}", first.to_uppercase(), chars.as_str()));
+            continue;
+        }
+        let line = line.trim();
+        if let Some(name) = line.strip_suffix(" = lib.mkOption {") {
+            option = Some(name.to_string());
+        } else if let (Some(section), Some(option)) = (&section, &option)
+            && let Some(description) = line
+                .strip_prefix("description = \"")
+                .and_then(|line| line.strip_suffix("\";"))
+        {
+            descriptions
+                .entry(section.clone())
+                .or_default()
+                .insert(option.clone(), unescape(description));
+        }
+    }
+    descriptions
+}
+
+/// A Nix string literal without the quotes as the string
+fn unescape(literal: &str) -> String {
+    let mut string = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => string.push('\n'),
+            Some('t') => string.push('\t'),
+            Some(escaped) => string.push(escaped),
+            None => {}
+        }
+    }
+    string
+}
+
+/// Set the descriptions of the properties of `schema` and restrict the notification
+/// `events` to the known names, like the NixOS module does
+fn describe(schema: &mut Value, descriptions: &HashMap<String, String>) {
+    let Some(Value::Object(properties)) = schema.get_mut("properties") else {
+        return;
+    };
+    for (name, property) in properties.iter_mut() {
+        let Value::Object(property) = property else {
+            continue;
+        };
+        if let Some(description) = descriptions.get(name) {
+            property.insert("description".into(), description.as_str().into());
+        }
+        if name == "events"
+            && let Some(Value::Object(items)) = property.get_mut("items")
+        {
+            items.insert("enum".into(), NOTIFICATION_EVENTS.to_vec().into());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// Properties of the schema without a description, as `Type.option`
+    fn undescribed(schema: &Value) -> Vec<String> {
+        let mut types = vec![("AutoBuildOptions", schema)];
+        if let Some(Value::Object(definitions)) = schema.get("$defs") {
+            types.extend(definitions.iter().map(|(name, def)| (name.as_str(), def)));
+        }
+        let mut missing = Vec::new();
+        for (name, schema) in types {
+            if let Some(Value::Object(properties)) = schema.get("properties") {
+                for (option, property) in properties {
+                    if property.get("description").is_none() {
+                        missing.push(format!("{}.{}", name, option));
+                    }
+                }
+            }
+        }
+        missing
+    }
+
+    #[test]
+    fn every_option_is_described() {
+        let schema = schema();
+        assert_eq!(undescribed(&schema), Vec::<String>::new());
+        assert_eq!(
+            schema["properties"]["repos"]["description"],
+            descriptions(&nixos_module::render())["AutoBuildOptions"]["repos"]
+        );
+        assert_eq!(unescape(r#"a \"b\"\nc\\d"#), "a \"b\"\nc\\d");
+    }
+
+    #[test]
+    fn example_configs_match_the_schema() -> Result<(), Box<dyn std::error::Error>> {
+        let validator = jsonschema::validator_for(&schema())?;
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let examples: [(&str, Value); 3] = [
+            (
+                "config.json",
+                serde_json::from_str(&std::fs::read_to_string(root.join("config.json"))?)?,
+            ),
+            (
+                "config.toml",
+                serde_json::to_value(toml::from_str::<toml::Value>(&std::fs::read_to_string(
+                    root.join("config.toml"),
+                )?)?)?,
+            ),
+            (
+                "config.yaml",
+                serde_norway::from_str(&std::fs::read_to_string(root.join("config.yaml"))?)?,
+            ),
+        ];
+        for (name, example) in examples {
+            let errors = validator
+                .iter_errors(&example)
+                .map(|error| format!("{} at {}", error, error.instance_path))
+                .collect::<Vec<_>>();
+            assert!(errors.is_empty(), "{}: {:#?}", name, errors);
+        }
+        let invalid = serde_json::json!({
+            "repos": [],
+            "notifications": { "webhooks": [{ "url": "https://example.com", "events": ["nope"] }] }
+        });
+        let errors = validator
+            .iter_errors(&invalid)
+            .map(|error| error.instance_path.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(errors, ["/notifications/webhooks/0/events/0"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Secrets of the config, read from a file, an environment variable or a systemd
+//! credential. Only where they come from is part of the settings, the values stay
+//! in `Secret`s that neither print nor serialize.
+
+use std::{fmt, path::Path};
+
+use crate::SecretSource;
+
+/// Set by systemd for units with `LoadCredential=`
+const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([redacted])")
+    }
+}
+
+fn read_file(path: &Path) -> Result<Secret, String> {
+    std::fs::read_to_string(path)
+        .map(|secret| Secret(secret.trim().to_string()))
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e))
+}
+
+pub fn resolve(source: &SecretSource) -> Result<Secret, String> {
+    match (&source.file, &source.env, &source.systemd_credential) {
+        (Some(path), None, None) => read_file(path),
+        (None, Some(var), None) => match std::env::var(var) {
+            Ok(secret) => Ok(Secret(secret.trim().to_string())),
+            Err(_) => Err(format!("environment variable {} is not set", var)),
+        },
+        (None, None, Some(name)) => {
+            let Some(dir) = std::env::var_os(CREDENTIALS_DIRECTORY) else {
+                return Err(format!(
+                    "no systemd credential {}, {} is not set. Is `LoadCredential={}:...` missing from the unit?",
+                    name, CREDENTIALS_DIRECTORY, name
+                ));
+            };
+            read_file(&Path::new(&dir).join(name))
+        }
+        _ => Err("set exactly one of `file`, `env` and `systemd_credential`".to_string()),
+    }
+}
+
+/// The secret of `source`, or of `file` from the older `*_file` options, `None`
+/// without either
+pub fn resolve_either(
+    source: Option<&SecretSource>,
+    file: Option<&Path>,
+) -> Result<Option<Secret>, String> {
+    match (source, file) {
+        (Some(source), _) => resolve(source).map(Some),
+        (None, Some(file)) => read_file(file).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_come_from_files_and_the_environment() -> Result<(), Box<dyn std::error::Error>> {
+        let path =
+            std::env::temp_dir().join(format!("nix_autobuild_secret_{}", std::process::id()));
+        std::fs::write(&path, "user:hunter2\n")?;
+        let from_file = resolve(&SecretSource {
+            file: Some(path.clone()),
+            ..SecretSource::default()
+        })?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(from_file.expose(), "user:hunter2");
+        assert!(!format!("{:?}", Some(&from_file)).contains("hunter2"));
+
+        let from_env = resolve(&SecretSource {
+            env: Some("PATH".to_string()),
+            ..SecretSource::default()
+        })?;
+        assert_eq!(from_env.expose(), std::env::var("PATH")?.trim());
+
+        let missing = SecretSource {
+            env: Some("NIX_AUTOBUILD_TEST_UNSET".to_string()),
+            ..SecretSource::default()
+        };
+        assert_eq!(
+            resolve(&missing).err().as_deref(),
+            Some("environment variable NIX_AUTOBUILD_TEST_UNSET is not set")
+        );
+        assert!(resolve(&SecretSource::default()).is_err());
+        assert!(resolve_either(None, None)?.is_none());
+        Ok(())
+    }
+}
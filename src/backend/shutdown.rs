@@ -0,0 +1,97 @@
+use std::{
+    sync::{
+        Condvar, Mutex, OnceLock,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use actix_web::rt::signal::unix::{SignalKind, signal};
+
+use crate::event::StateEvent;
+
+use super::{BuildRepos, PackageEnumTrait, events, state_file};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static REQUESTED_AT: OnceLock<Instant> = OnceLock::new();
+
+/// Number of package build threads that did not finish yet
+static RUNNING_BUILDS: Mutex<usize> = Mutex::new(0);
+static BUILD_FINISHED: Condvar = Condvar::new();
+
+/// Whether the server is shutting down, polling threads stop and no new builds start
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
+
+/// Counts a build thread as running until dropped
+pub struct BuildGuard(());
+
+pub fn track_build() -> BuildGuard {
+    *RUNNING_BUILDS.lock().unwrap_or_else(|p| p.into_inner()) += 1;
+    BuildGuard(())
+}
+
+impl Drop for BuildGuard {
+    fn drop(&mut self) {
+        let mut running = RUNNING_BUILDS.lock().unwrap_or_else(|p| p.into_inner());
+        *running = running.saturating_sub(1);
+        BUILD_FINISHED.notify_all();
+    }
+}
+
+/// Resolves once the process receives SIGTERM or SIGINT
+pub async fn signal_received() -> std::io::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let name = tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+    tracing::info!("Received {}, shutting down", name);
+    Ok(())
+}
+
+/// Tell the live clients, wake the polling threads so they exit and cancel all
+/// queued and running builds
+pub fn request(repos: &BuildRepos) {
+    if REQUESTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let _ = REQUESTED_AT.set(Instant::now());
+    events::publish(StateEvent::ServerShutdown);
+    state_file::save_final();
+
+    let repos = repos.snapshot();
+    for repo in repos.0.inner() {
+        repo.poll_trigger.trigger();
+        let commits = match repo.commits.inner().read() {
+            Ok(commits) => commits.values().cloned().collect::<Vec<_>>(),
+            Err(poisoned) => poisoned.into_inner().values().cloned().collect(),
+        };
+        for commit in commits {
+            for pkg in commit.packages.get() {
+                pkg.cancel();
+            }
+        }
+    }
+}
+
+/// Block until all build threads finished or `grace` passed since the shutdown was
+/// requested, `false` if some are still running
+pub fn wait_for_builds(grace: Duration) -> bool {
+    let deadline = REQUESTED_AT.get().copied().unwrap_or_else(Instant::now) + grace;
+    let mut running = RUNNING_BUILDS.lock().unwrap_or_else(|p| p.into_inner());
+    while *running > 0 {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            tracing::error!("{} builds still running after the grace period", *running);
+            return false;
+        }
+        running = BUILD_FINISHED
+            .wait_timeout(running, remaining)
+            .unwrap_or_else(|p| p.into_inner())
+            .0;
+    }
+    true
+}
@@ -0,0 +1,112 @@
+use std::{collections::VecDeque, time::Duration};
+
+use actix_web::{
+    HttpRequest, HttpResponse, get,
+    http::header,
+    rt::time::{Instant, Interval, interval_at},
+    web::Bytes,
+};
+use futures_util::stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::event::StateEvent;
+
+use super::events::{self, Sequenced};
+
+/// Idle proxies tend to drop connections after 30-60 seconds of silence
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+struct EventStream {
+    backlog: VecDeque<Sequenced>,
+    receiver: broadcast::Receiver<Sequenced>,
+    keep_alive: Interval,
+    /// the shutdown event was sent, the stream ends
+    finished: bool,
+}
+
+/// Status changes as Server-Sent Events. Clients reconnecting with `Last-Event-ID`
+/// first receive the buffered events they missed.
+#[get("/events")]
+async fn event_stream(req: HttpRequest) -> HttpResponse {
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|id| id.to_str().ok())
+        .and_then(|id| id.trim().parse::<u64>().ok());
+    tracing::debug!("SSE client connected, last event id {:?}", last_id);
+
+    let (backlog, receiver) = match last_id {
+        Some(last_id) => events::subscribe_since(last_id),
+        None => (Vec::new(), events::subscribe()),
+    };
+    let state = EventStream {
+        backlog: backlog.into(),
+        receiver,
+        keep_alive: interval_at(Instant::now() + KEEP_ALIVE, KEEP_ALIVE),
+        finished: false,
+    };
+
+    let body = stream::unfold(state, |mut state| async move {
+        let chunk = next_chunk(&mut state).await?;
+        Some((Ok::<_, actix_web::Error>(Bytes::from(chunk)), state))
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        // stop nginx from buffering the stream
+        .insert_header(("X-Accel-Buffering", "no"))
+        .streaming(body)
+}
+
+/// The next piece of the stream, `None` once the server shuts down
+async fn next_chunk(state: &mut EventStream) -> Option<String> {
+    if state.finished {
+        return None;
+    }
+    loop {
+        if let Some(event) = state.backlog.pop_front() {
+            state.finished = matches!(event.event, StateEvent::ServerShutdown);
+            match format_event(&event) {
+                Some(chunk) => return Some(chunk),
+                None => continue,
+            }
+        }
+        tokio::select! {
+            event = state.receiver.recv() => match event {
+                Ok(event) => state.backlog.push_back(event),
+                // the gap in the ids tells the client it missed events
+                Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return None,
+            },
+            _ = state.keep_alive.tick() => return Some(": keep-alive\n\n".to_string()),
+        }
+    }
+}
+
+fn format_event(event: &Sequenced) -> Option<String> {
+    let name = match event.event {
+        StateEvent::RepoStatus { .. }
+        | StateEvent::PollHistory { .. }
+        | StateEvent::NextPoll { .. }
+        | StateEvent::CommitsChanged { .. }
+        | StateEvent::ReposChanged => "repo",
+        StateEvent::CommitStatus { .. } => "commit",
+        StateEvent::PackageStatus { .. }
+        | StateEvent::PackagePushes { .. }
+        | StateEvent::PackageResult { .. } => "package",
+        StateEvent::ServerShutdown => "shutdown",
+        // snapshots are only sent over the WebSocket
+        StateEvent::Snapshot { .. } => return None,
+    };
+    match serde_json::to_string(&event.event) {
+        Ok(data) => Some(format!(
+            "id: {}\nevent: {}\ndata: {}\n\n",
+            event.id, name, data
+        )),
+        Err(e) => {
+            tracing::error!("serializing event: {}", e);
+            None
+        }
+    }
+}
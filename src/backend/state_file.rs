@@ -0,0 +1,709 @@
+//! The `json` state backend: all tracked repos are written to `state.json` in
+//! `state_dir` every `snapshot_interval_secs` and once more when the server shuts
+//! down, through a temporary file so a crash never leaves half a snapshot. On
+//! startup the commits and package statuses of the snapshot are taken over, so the
+//! dashboard is not empty while the repos are cloned again.
+
+use std::{
+    collections::HashMap,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+    thread,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AutoBuildOptions, RepoList,
+    builder::BuilderInfo,
+    commit::{CommitBuildStatus, CommitInfo, DiffStats},
+    package::{BuildAttempt, BuildTimes, PackageBuildStatus},
+    repo::RepoInfo,
+    serialize::{RwLockHashMapArc, RwLockWrapper, VecArcWrapper},
+};
+
+use super::{
+    BuildRepos, CommitInfoTrait, builder_info,
+    database::StoredPackage,
+    durations::{self, SavedSamples},
+    events,
+    export::{Dump, DumpRepo},
+};
+
+const FILE_NAME: &str = "state.json";
+
+/// Of the layout below, a snapshot of another version is ignored
+const VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    unix_secs: i64,
+    repos: &'a RepoList,
+    durations: Vec<SavedSamples>,
+    builders: Vec<BuilderInfo>,
+}
+
+/// A snapshot as written from [`RepoList`], only the parts that are restored
+#[derive(Deserialize, Debug)]
+pub struct Snapshot {
+    repos: VecArcWrapper<SavedRepo>,
+    // not in snapshots from before the build durations
+    #[serde(default)]
+    durations: Vec<SavedSamples>,
+    // and the builders
+    #[serde(default)]
+    builders: Vec<BuilderInfo>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedRepo {
+    repo: SavedRepoKey,
+    branch_commit_hashes: HashMap<String, RwLockWrapper<Vec<String>>>,
+    commits: RwLockHashMapArc<SavedCommit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedRepoKey {
+    url: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedCommit {
+    hash: String,
+    message: String,
+    packages: RwLockWrapper<Vec<SavedPackageEnum>>,
+    unix_secs: i64,
+    diff_stats: Option<DiffStats>,
+}
+
+#[derive(Deserialize, Debug)]
+enum SavedPackageEnum {
+    Derivation(SavedPackage),
+    NixosConfig(SavedPackage),
+}
+
+#[derive(Deserialize, Debug)]
+struct SavedPackage {
+    path: String,
+    pkg_type: String,
+    // only of derivations
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    arch: String,
+    status: RwLockWrapper<PackageBuildStatus>,
+    times: RwLockWrapper<BuildTimes>,
+    closure_size: RwLockWrapper<Option<u64>>,
+    // not in snapshots from before the attempts
+    #[serde(default)]
+    attempts: Option<RwLockWrapper<Vec<BuildAttempt>>>,
+}
+
+impl SavedPackageEnum {
+    fn stored(&self) -> StoredPackage {
+        let (nixos_config, package) = match self {
+            SavedPackageEnum::Derivation(package) => (false, package),
+            SavedPackageEnum::NixosConfig(package) => (true, package),
+        };
+        StoredPackage {
+            path: package.path.clone(),
+            nixos_config,
+            name: package.name.clone(),
+            description: package.description.clone(),
+            pkg_type: package.pkg_type.clone(),
+            arch: package.arch.clone(),
+            status: package.status.get(),
+            times: package.times.get(),
+            closure_size: package.closure_size.get(),
+            attempts: package
+                .attempts
+                .as_ref()
+                .map(RwLockWrapper::get)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+struct Writer {
+    path: PathBuf,
+    repos: BuildRepos,
+    /// the last snapshot before the shutdown was written
+    closed: bool,
+}
+
+static WRITER: OnceLock<Mutex<Writer>> = OnceLock::new();
+
+fn path(settings: &AutoBuildOptions) -> PathBuf {
+    settings.state_dir().join(FILE_NAME)
+}
+
+/// The snapshot of an earlier run, `None` if there is none or it cannot be used
+pub fn load(settings: &AutoBuildOptions) -> Option<Snapshot> {
+    let path = path(settings);
+    let content = match std::fs::read(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return None,
+        Err(e) => {
+            tracing::error!("reading the state snapshot {}: {}", path.display(), e);
+            return None;
+        }
+    };
+    #[derive(Deserialize)]
+    struct Version {
+        version: u32,
+    }
+    // before the layout, which may be another one
+    match serde_json::from_slice::<Version>(&content) {
+        Ok(Version { version }) if version == VERSION => {}
+        Ok(Version { version }) => {
+            tracing::warn!(
+                "Ignoring the state snapshot {} of version {}, this server writes version {}",
+                path.display(),
+                version,
+                VERSION
+            );
+            return None;
+        }
+        Err(e) => {
+            tracing::error!("reading the state snapshot {}: {}", path.display(), e);
+            return None;
+        }
+    }
+    match serde_json::from_slice::<Snapshot>(&content) {
+        Ok(snapshot) => {
+            tracing::info!("Restoring the build state from {}", path.display());
+            Some(snapshot)
+        }
+        Err(e) => {
+            tracing::error!("reading the state snapshot {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Take over the commits of `repo` from `snapshot` before its poller starts. Builds
+/// that had not finished run again.
+pub fn restore(repo: &Arc<RepoInfo>, snapshot: &Snapshot) {
+    let Some(saved) = snapshot
+        .repos
+        .inner()
+        .iter()
+        .find(|saved| saved.repo.url == repo.repo.url)
+    else {
+        return;
+    };
+    let saved_commits = saved
+        .commits
+        .inner()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .values()
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut restored = Vec::new();
+    for saved_commit in saved_commits {
+        let commit = Arc::new(CommitInfo {
+            flake_url: format!("git+https://{}?rev={}", repo.repo.url, saved_commit.hash),
+            hash: saved_commit.hash.clone(),
+            message: saved_commit.message.clone(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(repo)),
+            unix_secs: saved_commit.unix_secs,
+            diff_stats: saved_commit.diff_stats.clone(),
+        });
+        let packages = saved_commit
+            .packages
+            .inner()
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|package| package.stored().into_package(&commit))
+            .collect::<Vec<_>>();
+        restored.push((commit, packages));
+    }
+    {
+        let mut commits = repo
+            .commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (commit, _) in &restored {
+            commits.insert(commit.hash.clone(), commit.clone());
+        }
+    }
+    for (branch, hashes) in &saved.branch_commit_hashes {
+        if let Some(current) = repo.branch_commit_hashes.get(branch) {
+            current.set(hashes.get());
+        }
+    }
+    for (commit, packages) in restored {
+        commit.restore(packages);
+    }
+}
+
+/// Take over the build durations of `snapshot`
+pub fn restore_durations(snapshot: &Snapshot) {
+    durations::load(snapshot.durations.iter().cloned());
+}
+
+/// Take over the builder snapshots of `snapshot`
+pub fn restore_builders(snapshot: &Snapshot) {
+    builder_info::load(snapshot.builders.iter().cloned());
+}
+
+/// Write a snapshot every `snapshot_interval_secs` until the shutdown
+pub fn start(settings: &AutoBuildOptions, repos: BuildRepos) {
+    let path = path(settings);
+    let interval = Duration::from_secs(settings.state.snapshot_interval_secs.max(1));
+    if WRITER
+        .set(Mutex::new(Writer {
+            path,
+            repos,
+            closed: false,
+        }))
+        .is_err()
+    {
+        return;
+    }
+    thread::spawn(move || {
+        loop {
+            thread::sleep(interval);
+            if !save(false) {
+                return;
+            }
+        }
+    });
+}
+
+/// The last snapshot, taken before the shutdown cancels the builds so those run
+/// again after the restart
+pub fn save_final() {
+    save(true);
+}
+
+/// `false` once the last snapshot was written
+fn save(last: bool) -> bool {
+    let Some(writer) = WRITER.get() else {
+        return false;
+    };
+    let mut writer = writer.lock().unwrap_or_else(PoisonError::into_inner);
+    if writer.closed {
+        return false;
+    }
+    if let Err(e) = write(&writer.path, &writer.repos.snapshot()) {
+        tracing::error!(
+            "writing the state snapshot {}: {}",
+            writer.path.display(),
+            e
+        );
+    }
+    writer.closed = last;
+    !last
+}
+
+fn write(path: &Path, repos: &RepoList) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_vec(&SnapshotRef {
+        version: VERSION,
+        unix_secs: events::unix_now(),
+        repos,
+        durations: durations::saved(),
+        builders: builder_info::saved(),
+    })?;
+    write_atomically(path, &json)
+}
+
+fn write_atomically(path: &Path, json: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    let temporary = path.with_extension("json.tmp");
+    let mut file = std::fs::File::create(&temporary)?;
+    file.write_all(json)?;
+    file.sync_all()?;
+    std::fs::rename(&temporary, path)?;
+    tracing::debug!("Wrote the state snapshot {}", path.display());
+    Ok(())
+}
+
+/// A snapshot of the evaluated commits of `repos`, in the layout of [`RepoList`]
+/// with the fields that are restored
+fn imported_snapshot(
+    repos: &[DumpRepo],
+    durations: &[SavedSamples],
+    builders: &[BuilderInfo],
+) -> serde_json::Value {
+    let repos = repos
+        .iter()
+        .map(|repo| {
+            let commits = repo
+                .commits
+                .iter()
+                .filter_map(|commit| {
+                    let packages = commit
+                        .packages
+                        .as_ref()?
+                        .iter()
+                        .map(|package| {
+                            let kind = match package.nixos_config {
+                                true => "NixosConfig",
+                                false => "Derivation",
+                            };
+                            serde_json::json!({ kind: package })
+                        })
+                        .collect::<Vec<_>>();
+                    Some((
+                        commit.hash.clone(),
+                        serde_json::json!({
+                            "hash": commit.hash,
+                            "message": commit.message,
+                            "packages": packages,
+                            "unix_secs": commit.unix_secs,
+                            "diff_stats": null,
+                        }),
+                    ))
+                })
+                .collect::<serde_json::Map<_, _>>();
+            serde_json::json!({
+                "repo": { "url": repo.url },
+                "branch_commit_hashes": {},
+                "commits": commits,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::json!({
+        "version": VERSION,
+        "unix_secs": events::unix_now(),
+        "repos": repos,
+        "durations": durations,
+        "builders": builders,
+    })
+}
+
+/// Write an export as the snapshot in `state_dir` of `settings`,
+/// which must not exist unless `force` replaces it. Returns the path of the snapshot.
+pub(super) fn import(
+    settings: &AutoBuildOptions,
+    dump: &Dump,
+    force: bool,
+) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let path = path(settings);
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already holds a state snapshot, --force replaces it",
+            path.display()
+        )
+        .into());
+    }
+    write_atomically(
+        &path,
+        &serde_json::to_vec(&imported_snapshot(
+            &dump.repos,
+            &dump.durations,
+            &dump.builders,
+        ))?,
+    )?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{RepoInfoTrait, export::DumpCommit};
+    use super::*;
+    use crate::Repo;
+
+    fn settings(dir: &Path) -> Result<AutoBuildOptions, serde_json::Error> {
+        serde_json::from_value(serde_json::json!({
+            "repos": [],
+            "dir": dir,
+        }))
+    }
+
+    fn repo(settings: &AutoBuildOptions) -> Result<Arc<RepoInfo>, serde_json::Error> {
+        Ok(RepoInfo::new(
+            serde_json::from_value::<Repo>(serde_json::json!({
+                "url": "github.com/org/repo",
+                "branches": ["main"]
+            }))?,
+            PathBuf::from("/nonexistent/repo"),
+            Arc::new(settings.clone()),
+        ))
+    }
+
+    #[test]
+    fn written_snapshots_restore() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!("nix_autobuild_state_{}", std::process::id()));
+        let settings = settings(&dir)?;
+        std::fs::create_dir_all(settings.state_dir())?;
+        let running = repo(&settings)?;
+        let commit = Arc::new(CommitInfo {
+            flake_url: "git+https://github.com/org/repo?rev=abc123".to_string(),
+            hash: "abc123".to_string(),
+            message: "Initial commit".to_string(),
+            status: RwLockWrapper::new(CommitBuildStatus::Idle),
+            packages: RwLockWrapper::new(Vec::new()),
+            repo: RwLockWrapper::new(Arc::downgrade(&running)),
+            unix_secs: 100,
+            diff_stats: None,
+        });
+        let stored = StoredPackage {
+            path: "packages.x86_64-linux.hello".to_string(),
+            nixos_config: false,
+            name: "hello-2.12".to_string(),
+            description: String::new(),
+            pkg_type: "derivation".to_string(),
+            arch: "x86_64-linux".to_string(),
+            status: PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+            times: BuildTimes {
+                started: Some(10),
+                finished: Some(20),
+            },
+            closure_size: Some(42),
+            attempts: Vec::new(),
+        };
+        commit.packages.set(vec![stored.into_package(&commit)]);
+        running
+            .commits
+            .inner()
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(commit.hash.clone(), commit.clone());
+        if let Some(hashes) = running.branch_commit_hashes.get("main") {
+            hashes.set(vec!["abc123".to_string()]);
+        }
+        write(
+            &path(&settings),
+            &RepoList(VecArcWrapper::from(vec![running])),
+        )?;
+
+        let snapshot = load(&settings).ok_or("the snapshot did not load")?;
+        let restarted = repo(&settings)?;
+        restore(&restarted, &snapshot);
+        std::fs::remove_dir_all(&dir)?;
+        let statuses = restarted
+            .commits
+            .with("abc123", |commit| {
+                commit
+                    .packages
+                    .get()
+                    .iter()
+                    .map(|package| package.status())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        assert_eq!(
+            statuses,
+            [PackageBuildStatus::Success(
+                "/nix/store/abc-hello".to_string()
+            )]
+        );
+        assert_eq!(
+            restarted
+                .branch_commit_hashes
+                .get("main")
+                .map(|hashes| hashes.get()),
+            Some(vec!["abc123".to_string()])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn snapshots_of_another_version_are_ignored() -> Result<(), Box<dyn std::error::Error>> {
+        let dir = std::env::temp_dir().join(format!(
+            "nix_autobuild_state_version_{}",
+            std::process::id()
+        ));
+        let settings = settings(&dir)?;
+        std::fs::create_dir_all(settings.state_dir())?;
+        assert!(load(&settings).is_none());
+        let json = serde_json::json!({
+            "version": VERSION + 1,
+            "unix_secs": 1700000000,
+            "repos": [],
+        });
+        write_atomically(&path(&settings), &serde_json::to_vec(&json)?)?;
+        let loaded = load(&settings);
+        std::fs::remove_dir_all(&dir)?;
+        assert!(loaded.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn snapshots_of_the_repo_list_load() -> Result<(), Box<dyn std::error::Error>> {
+        // as `RepoInfo` serializes, with fields that are not restored
+        let json = r#"{
+            "version": 1,
+            "unix_secs": 1700000000,
+            "repos": [{
+                "flake_url": "git+https://github.com/org/repo",
+                "repo": { "url": "github.com/org/repo", "branches": ["main"] },
+                "branch_commit_hashes": { "main": ["abc123"] },
+                "commits": { "abc123": {
+                    "hash": "abc123",
+                    "message": "Initial commit",
+                    "flake_url": "git+https://github.com/org/repo?rev=abc123",
+                    "status": "Idle",
+                    "packages": [
+                        { "Derivation": {
+                            "description": "", "name": "hello-2.12", "pkg_type": "derivation",
+                            "path": "packages.x86_64-linux.hello", "arch": "x86_64-linux",
+                            "flake_url": "", "status": { "Success": "/nix/store/abc-hello" },
+                            "times": { "started": 10, "finished": 20 },
+                            "closure_size": 42, "pushes": []
+                        } },
+                        { "NixosConfig": {
+                            "path": "nixosConfigurations.host.config.system.build.toplevel",
+                            "pkg_type": "nixos-configuration", "flake_url": "",
+                            "status": { "UnsupportedArchitecture": "aarch64-linux" },
+                            "times": { "started": null, "finished": null },
+                            "closure_size": null, "pushes": []
+                        } }
+                    ],
+                    "unix_secs": 1700000000,
+                    "diff_stats": { "Changed": { "files_changed": 1, "insertions": 2, "deletions": 0 } }
+                } },
+                "status": "Idle",
+                "poll_history": [],
+                "next_poll": null,
+                "supported_architectures": ["x86_64-linux"],
+                "running_builds": 0
+            }]
+        }"#;
+        let snapshot = serde_json::from_str::<Snapshot>(json)?;
+        let repo = &snapshot.repos.inner()[0];
+        assert_eq!(repo.branch_commit_hashes["main"].get(), ["abc123"]);
+        let packages = repo
+            .commits
+            .with("abc123", |commit| {
+                commit
+                    .packages
+                    .inner()
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .iter()
+                    .map(SavedPackageEnum::stored)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        assert_eq!(
+            packages
+                .iter()
+                .map(|p| p.status.clone())
+                .collect::<Vec<_>>(),
+            [
+                PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+                PackageBuildStatus::UnsupportedArchitecture("aarch64-linux"),
+            ]
+        );
+        assert!(packages[1].nixos_config);
+        assert_eq!(packages[0].closure_size, Some(42));
+        Ok(())
+    }
+
+    #[test]
+    fn imported_snapshots_load() -> Result<(), Box<dyn std::error::Error>> {
+        let package = StoredPackage {
+            path: "packages.x86_64-linux.hello".to_string(),
+            nixos_config: false,
+            name: "hello-2.12".to_string(),
+            description: String::new(),
+            pkg_type: "derivation".to_string(),
+            arch: "x86_64-linux".to_string(),
+            status: PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+            times: BuildTimes {
+                started: Some(10),
+                finished: Some(20),
+            },
+            closure_size: Some(42),
+            attempts: vec![BuildAttempt {
+                number: 1,
+                started: Some(10),
+                finished: Some(20),
+                status: PackageBuildStatus::Success("/nix/store/abc-hello".to_string()),
+                log_ref: Some("packages.x86_64-linux.hello.log".to_string()),
+                builder: Some("0123456789ab".to_string()),
+            }],
+        };
+        let configuration = StoredPackage {
+            path: "nixosConfigurations.host.config.system.build.toplevel".to_string(),
+            nixos_config: true,
+            name: String::new(),
+            arch: String::new(),
+            pkg_type: "nixos-configuration".to_string(),
+            status: PackageBuildStatus::Failed("error: builder failed".to_string()),
+            attempts: Vec::new(),
+            ..package.clone()
+        };
+        let repos = [DumpRepo {
+            url: "github.com/org/repo".to_string(),
+            name: "repo".to_string(),
+            commits: vec![
+                DumpCommit {
+                    hash: "abc123".to_string(),
+                    message: "Initial commit".to_string(),
+                    unix_secs: 100,
+                    packages: Some(vec![package.clone(), configuration.clone()]),
+                },
+                DumpCommit {
+                    hash: "def456".to_string(),
+                    message: "Not evaluated yet".to_string(),
+                    unix_secs: 200,
+                    packages: None,
+                },
+            ],
+            builds: Vec::new(),
+        }];
+        let durations = [SavedSamples {
+            key: durations::DurationKey {
+                repo: "github.com/org/repo".to_string(),
+                name: "packages.*.hello".to_string(),
+                arch: "x86_64-linux".to_string(),
+            },
+            samples: durations::Samples {
+                count: 3,
+                recent: [10, 12, 11].into(),
+            },
+        }];
+        let builders = [BuilderInfo {
+            id: "0123456789ab".to_string(),
+            captured_secs: 5,
+            nix_version: "nix (Nix) 2.24.9".to_string(),
+            nix_config: Default::default(),
+            kernel: "Linux 6.6.52 x86_64".to_string(),
+            system: "x86_64-linux".to_string(),
+            server_version: "0.1.0".to_string(),
+            git_rev: "unknown".to_string(),
+        }];
+        let snapshot =
+            serde_json::from_value::<Snapshot>(imported_snapshot(&repos, &durations, &builders))?;
+        assert_eq!(snapshot.durations, durations);
+        assert_eq!(
+            snapshot
+                .builders
+                .iter()
+                .map(|info| &info.id)
+                .collect::<Vec<_>>(),
+            [&builders[0].id]
+        );
+        let saved = &snapshot.repos.inner()[0];
+        assert_eq!(saved.repo.url, "github.com/org/repo");
+        assert!(saved.commits.with("def456", |_| ()).is_none());
+        let packages = saved
+            .commits
+            .with("abc123", |commit| {
+                commit
+                    .packages
+                    .inner()
+                    .read()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .iter()
+                    .map(SavedPackageEnum::stored)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        assert_eq!(packages, [package, configuration]);
+        Ok(())
+    }
+}
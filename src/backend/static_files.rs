@@ -0,0 +1,188 @@
+use std::path::{Component, Path, PathBuf};
+
+use actix_files::NamedFile;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse, get, http::header};
+
+use super::access_log::ServedFile;
+use crate::AutoBuildOptions;
+
+/// Frontend of the build, set by the flake
+const BUILT_FRONTEND_PATH: &str = match option_env!("FRONTEND_PATH") {
+    Some(path) => path,
+    None => "/workspaces/nix_autobuild/result/dist",
+};
+
+/// Where the frontend is served from, app data of the server
+pub struct FrontendDir(pub PathBuf);
+
+/// `frontend_dir` of the settings, else `FRONTEND_PATH` of the environment, else
+/// the frontend the binary was built with
+pub fn frontend_dir(settings: &AutoBuildOptions) -> PathBuf {
+    settings
+        .frontend_dir
+        .clone()
+        .or_else(|| {
+            std::env::var_os("FRONTEND_PATH")
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+        })
+        .unwrap_or_else(|| PathBuf::from(BUILT_FRONTEND_PATH))
+}
+
+#[get("/{path:.*}")]
+async fn static_files(
+    req: HttpRequest,
+    path: actix_web::web::Path<String>,
+    frontend: actix_web::web::Data<FrontendDir>,
+) -> actix_web::Result<NamedFile> {
+    tracing::debug!("Requested static file: {}", path);
+    serve(&req, &frontend.0, &path).await
+}
+
+/// `<base_path>` to `<base_path>/`, keeping the query
+pub async fn redirect_to_slash(req: HttpRequest) -> HttpResponse {
+    let location = match req.query_string() {
+        "" => format!("{}/", req.path()),
+        query => format!("{}/?{}", req.path(), query),
+    };
+    HttpResponse::PermanentRedirect()
+        .insert_header((header::LOCATION, location))
+        .finish()
+}
+
+async fn serve(req: &HttpRequest, root: &Path, request_path: &str) -> actix_web::Result<NamedFile> {
+    let Some(full_path) = resolve(root, request_path) else {
+        return Err(actix_web::error::ErrorNotFound("404 Not Found"));
+    };
+    tracing::debug!("Full static file path: {}", full_path.display());
+    req.extensions_mut().insert(ServedFile(full_path.clone()));
+    match NamedFile::open_async(full_path).await {
+        Ok(named_file) => Ok(named_file.use_last_modified(true)),
+        Err(_) => Err(actix_web::error::ErrorNotFound("404 Not Found")),
+    }
+}
+
+/// File below `root` to serve for `request_path`, `None` for traversal attempts and
+/// missing assets. Unknown paths without a file extension are routes of the
+/// frontend and get `index.html`.
+fn resolve(root: &Path, request_path: &str) -> Option<PathBuf> {
+    if request_path.contains('\0') {
+        return None;
+    }
+    let mut relative = PathBuf::new();
+    for component in Path::new(request_path).components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir | Component::RootDir => {}
+            Component::ParentDir | Component::Prefix(_) => return None,
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push("index.html");
+    }
+
+    let root = root.canonicalize().ok()?;
+    match root.join(&relative).canonicalize() {
+        // symlinks may still point outside
+        Ok(full_path) if full_path.starts_with(&root) && full_path.is_file() => Some(full_path),
+        Ok(_) => None,
+        Err(_) if relative.extension().is_none() => Some(root.join("index.html")),
+        Err(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{
+        App, HttpResponse,
+        http::StatusCode,
+        test::{TestRequest, call_service, init_service, read_body},
+        web,
+    };
+
+    fn frontend_root(name: &str) -> std::io::Result<PathBuf> {
+        let base =
+            std::env::temp_dir().join(format!("nix_autobuild_{}_{}", name, std::process::id()));
+        let root = base.join("dist");
+        std::fs::create_dir_all(root.join("assets"))?;
+        std::fs::write(root.join("index.html"), "index")?;
+        std::fs::write(root.join("assets/app.js"), "app")?;
+        std::fs::write(base.join("secret"), "secret")?;
+        Ok(root)
+    }
+
+    #[test]
+    fn resolves_files_inside_root() -> std::io::Result<()> {
+        let root = frontend_root("inside")?;
+        let canonical = root.canonicalize()?;
+        assert_eq!(resolve(&root, ""), Some(canonical.join("index.html")));
+        assert_eq!(
+            resolve(&root, "assets/app.js"),
+            Some(canonical.join("assets/app.js"))
+        );
+        assert_eq!(
+            resolve(&root, "./assets//app.js"),
+            Some(canonical.join("assets/app.js"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_traversal() -> std::io::Result<()> {
+        let root = frontend_root("traversal")?;
+        assert_eq!(resolve(&root, "../secret"), None);
+        assert_eq!(resolve(&root, "assets/../../secret"), None);
+        assert_eq!(resolve(&root, "assets/../index.html"), None);
+        assert_eq!(resolve(&root, "/../secret"), None);
+        assert_eq!(resolve(&root, "index.html\0"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn falls_back_to_index_for_routes() -> std::io::Result<()> {
+        let root = frontend_root("fallback")?;
+        let index = root.canonicalize()?.join("index.html");
+        assert_eq!(resolve(&root, "repos/some/deep/link"), Some(index));
+        assert_eq!(resolve(&root, "assets/missing.js"), None);
+        Ok(())
+    }
+
+    #[actix_web::test]
+    async fn rejects_encoded_traversal() -> std::io::Result<()> {
+        let root = frontend_root("encoded")?;
+        let app = init_service(App::new().route(
+            "/{path:.*}",
+            web::get().to(
+                move |req: actix_web::HttpRequest, path: web::Path<String>| {
+                    let root = root.clone();
+                    async move {
+                        match serve(&req, &root, &path).await {
+                            Ok(file) => file.into_response(&req),
+                            Err(_) => HttpResponse::NotFound().finish(),
+                        }
+                    }
+                },
+            ),
+        ))
+        .await;
+
+        for uri in [
+            "/%2e%2e%2fsecret",
+            "/%2e%2e/secret",
+            "/assets/%2e%2e%2f%2e%2e%2fsecret",
+            "/..%2fsecret",
+            "/%2E%2E%2Fsecret",
+            "/assets%2f..%2f..%2fsecret",
+        ] {
+            let res = call_service(&app, TestRequest::get().uri(uri).to_request()).await;
+            if res.status() == StatusCode::OK {
+                assert_ne!(read_body(res).await, "secret", "{} escaped the root", uri);
+            }
+        }
+
+        let res = call_service(&app, TestRequest::get().uri("/assets/app.js").to_request()).await;
+        assert_eq!(read_body(res).await, "app");
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use actix_files::NamedFile;
+use actix_web::{
+    HttpMessage, HttpRequest, HttpResponse, get,
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    mime::{self, Mime},
+    web,
+};
+use serde::Deserialize;
+use zip::{CompressionMethod, ZipWriter, write::SimpleFileOptions};
+
+use crate::AutoBuildOptions;
+
+use super::{BuildRepos, STORE_DIR, access_log::ServedFile, cache, compress, pipe};
+
+/// How much of an extensionless file is looked at to guess its type
+const SNIFF_LENGTH: usize = 1024;
+
+/// Keeps names of temporary zip archives apart
+static NEXT_ARCHIVE: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize, Debug)]
+struct StoreQuery {
+    download: Option<String>,
+    /// `tar.gz` or `zip`
+    archive: Option<String>,
+    /// allow archives above the size limit
+    force: Option<String>,
+}
+
+impl StoreQuery {
+    fn download(&self) -> bool {
+        is_set(&self.download)
+    }
+
+    fn force(&self) -> bool {
+        is_set(&self.force)
+    }
+}
+
+fn is_set(flag: &Option<String>) -> bool {
+    matches!(flag.as_deref(), Some("1" | "true"))
+}
+
+#[get("/nix/store{path:.*}")]
+// serve file if available or list the nix store directory
+async fn nix_store_files(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<StoreQuery>,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let full_path = format!("{}{}", STORE_DIR, path.into_inner());
+    server_nix_file(&req, full_path, &query, settings, repos).await
+}
+
+#[get("/store{path:.*}")]
+// serve file if available or list the nix store directory
+async fn store_files(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<StoreQuery>,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let full_path = format!("{}{}", STORE_DIR, path.into_inner());
+    server_nix_file(&req, full_path, &query, settings, repos).await
+}
+
+async fn server_nix_file(
+    req: &HttpRequest,
+    path: String,
+    query: &StoreQuery,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    tracing::debug!("Requested nix file: {}", path);
+
+    let Some(full_path) = resolve_store_path(Path::new(STORE_DIR), Path::new(&path)) else {
+        return Err(actix_web::error::ErrorNotFound("404 Not Found"));
+    };
+    req.extensions_mut().insert(ServedFile(full_path.clone()));
+    let metadata = match std::fs::metadata(&full_path) {
+        Ok(meta) => meta,
+        Err(_) => return Err(actix_web::error::ErrorNotFound("404 Not Found")),
+    };
+
+    if let Some(format) = &query.archive {
+        return archive(
+            req,
+            &path,
+            full_path,
+            format,
+            query.force(),
+            settings,
+            repos,
+        )
+        .await;
+    }
+
+    if metadata.is_file() {
+        // streamed from disk, also answers range requests
+        match NamedFile::open_async(&full_path).await {
+            Ok(file) => {
+                // the requested name, symlinks like `result` often have the better one
+                let content_type = content_type(Path::new(&path), &full_path);
+                let disposition = disposition(
+                    &content_type,
+                    download_name(Path::new(&path)),
+                    query.download(),
+                );
+                Ok(file
+                    .use_last_modified(true)
+                    .set_content_type(content_type)
+                    .set_content_disposition(disposition)
+                    .into_response(req))
+            }
+            Err(_) => Err(actix_web::error::ErrorNotFound("404 Not Found")),
+        }
+    } else if metadata.is_dir() {
+        match std::fs::read_dir(&full_path) {
+            Ok(entries) => {
+                let prefix = settings.route_prefix();
+                let mut listing = String::from("<html><body><h1>Directory listing</h1><ul>");
+                for entry in entries.flatten() {
+                    if let Ok(name) = entry.file_name().into_string() {
+                        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+                        let suffix = if is_dir { "/" } else { "" };
+                        listing.push_str(&format!(
+                            "<li><a href=\"{}{}/{}{}\">{}{}</a></li>",
+                            prefix, path, name, suffix, name, suffix
+                        ));
+                    }
+                }
+                listing.push_str("</ul></body></html>");
+                Ok(HttpResponse::Ok().content_type("text/html").body(listing))
+            }
+            Err(_) => Err(actix_web::error::ErrorInternalServerError(
+                "Failed to read directory",
+            )),
+        }
+    } else {
+        Err(actix_web::error::ErrorNotFound("404 Not Found"))
+    }
+}
+
+/// `path` with all symlinks resolved, `None` if it does not exist or ends up outside
+/// of `store`
+fn resolve_store_path(store: &Path, path: &Path) -> Option<PathBuf> {
+    let store = store.canonicalize().ok()?;
+    let full_path = path.canonicalize().ok()?;
+    full_path.starts_with(&store).then_some(full_path)
+}
+
+/// Stream `full_path` as an archive built on the fly. Only outputs of successful builds
+/// are available unless the binary cache exposes the whole store.
+async fn archive(
+    req: &HttpRequest,
+    path: &str,
+    full_path: PathBuf,
+    format: &str,
+    force: bool,
+    settings: web::Data<AutoBuildOptions>,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let (extension, content_type) = match format {
+        "tar.gz" => ("tar.gz", "application/gzip"),
+        "zip" => ("zip", "application/zip"),
+        _ => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "Unsupported archive format, use `tar.gz` or `zip`",
+            ));
+        }
+    };
+    tracing::debug!("Requested {} archive of {}", extension, full_path.display());
+
+    let expose_whole_store = settings
+        .binary_cache
+        .as_ref()
+        .is_some_and(|cache| cache.expose_whole_store);
+    let size_limit = settings.archive_size_limit;
+    let checked_path = full_path.clone();
+    let size = web::block(move || {
+        let store_path = top_level_store_path(&checked_path)?;
+        let tracked = expose_whole_store
+            || cache::tracked_outputs(&repos)
+                .iter()
+                .any(|output| Path::new(output) == store_path);
+        tracked.then(|| tree_size(&checked_path))
+    })
+    .await?
+    .ok_or_else(|| actix_web::error::ErrorNotFound("404 Not Found"))?;
+    if size_limit != 0 && size > size_limit && !force {
+        return Err(actix_web::error::ErrorPayloadTooLarge(format!(
+            "The archive would contain {} bytes, more than the limit of {} bytes. Add `force=1` to download it anyway.",
+            size, size_limit
+        )));
+    }
+
+    let filename = format!("{}.{}", download_name(Path::new(path)), extension);
+    if extension == "zip" {
+        let archive_path = full_path.clone();
+        let tmp_dir = settings.dir.join("tmp");
+        let file = web::block(move || zip_archive(&archive_path, &tmp_dir))
+            .await?
+            .map_err(|e| {
+                tracing::error!("creating archive of {}: {}", full_path.display(), e);
+                actix_web::error::ErrorInternalServerError("Failed to create archive")
+            })?;
+        return Ok(NamedFile::from_file(file, &filename)?
+            .set_content_type(
+                content_type
+                    .parse()
+                    .unwrap_or(mime::APPLICATION_OCTET_STREAM),
+            )
+            .set_content_disposition(ContentDisposition::attachment(filename))
+            .into_response(req));
+    }
+
+    let (Some(parent), Some(name)) = (full_path.parent(), full_path.file_name()) else {
+        return Err(actix_web::error::ErrorNotFound("404 Not Found"));
+    };
+    // symlinks and permissions are kept
+    let child = Command::new("tar")
+        .arg("--create")
+        .arg("--gzip")
+        .arg("--file=-")
+        .arg("--directory")
+        .arg(parent)
+        // relative, so names starting with `-` are not taken as options
+        .arg(Path::new(".").join(name))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| {
+            tracing::error!("creating archive of {}: {}", full_path.display(), e);
+            actix_web::error::ErrorInternalServerError("Failed to create archive")
+        })?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .insert_header(ContentDisposition::attachment(filename))
+        .streaming(pipe::stdout_stream(vec![child])))
+}
+
+/// Zip files need to be seekable while written, so the archive is built in an
+/// unlinked temporary file. Symlinks are stored as links, which most unzip tools
+/// restore, instead of following them out of the store path.
+fn zip_archive(full_path: &Path, tmp_dir: &Path) -> std::io::Result<File> {
+    std::fs::create_dir_all(tmp_dir)?;
+    let tmp_path = tmp_dir.join(format!(
+        "archive-{}-{}.zip",
+        std::process::id(),
+        NEXT_ARCHIVE.fetch_add(1, Ordering::Relaxed)
+    ));
+    let file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)?;
+    // only the open handle keeps it alive, nothing is left behind
+    std::fs::remove_file(&tmp_path)?;
+
+    let parent = full_path.parent().unwrap_or(full_path);
+    let mut zip = ZipWriter::new(file);
+    let mut pending = vec![full_path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let metadata = std::fs::symlink_metadata(&path)?;
+        let name = path
+            .strip_prefix(parent)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .unix_permissions(metadata.permissions().mode())
+            .large_file(metadata.len() >= u64::from(u32::MAX));
+        if metadata.is_symlink() {
+            let target = std::fs::read_link(&path)?;
+            zip.add_symlink(name, target.to_string_lossy(), options)?;
+        } else if metadata.is_dir() {
+            zip.add_directory(name, options)?;
+            pending.extend(
+                std::fs::read_dir(&path)?
+                    .flatten()
+                    .map(|entry| entry.path()),
+            );
+        } else {
+            zip.start_file(name, options)?;
+            std::io::copy(&mut File::open(&path)?, &mut zip)?;
+        }
+    }
+    let mut file = zip.finish()?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(file)
+}
+
+/// `/nix/store/<hash>-<name>` that contains `full_path`
+fn top_level_store_path(full_path: &Path) -> Option<PathBuf> {
+    let relative = full_path.strip_prefix(STORE_DIR).ok()?;
+    let name = relative.components().next()?;
+    Some(Path::new(STORE_DIR).join(name))
+}
+
+/// Total size of the files below `path` without following symlinks
+fn tree_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut pending = vec![path.to_path_buf()];
+    while let Some(path) = pending.pop() {
+        let Ok(metadata) = std::fs::symlink_metadata(&path) else {
+            continue;
+        };
+        size += metadata.len();
+        if metadata.is_dir()
+            && let Ok(entries) = std::fs::read_dir(&path)
+        {
+            pending.extend(entries.flatten().map(|entry| entry.path()));
+        }
+    }
+    size
+}
+
+/// From the extension of `path`, or the first bytes of `full_path` if it has none
+fn content_type(path: &Path, full_path: &Path) -> Mime {
+    let name = path.to_string_lossy();
+    if let Some(content_type) =
+        compress::store_content_type(&name).and_then(|content_type| content_type.parse().ok())
+    {
+        return content_type;
+    }
+    if path.extension().is_some()
+        && let Some(content_type) = mime_guess::from_path(path).first()
+    {
+        return content_type;
+    }
+    let mut start = Vec::with_capacity(SNIFF_LENGTH);
+    let read = File::open(full_path)
+        .and_then(|file| file.take(SNIFF_LENGTH as u64).read_to_end(&mut start));
+    match read {
+        Ok(_) => sniff(&start),
+        Err(_) => mime::APPLICATION_OCTET_STREAM,
+    }
+}
+
+fn sniff(start: &[u8]) -> Mime {
+    if start.starts_with(b"\x7fELF") || start.contains(&0) {
+        return mime::APPLICATION_OCTET_STREAM;
+    }
+    match std::str::from_utf8(start) {
+        Ok(_) => mime::TEXT_PLAIN_UTF_8,
+        // only cut off in the middle of a character
+        Err(e) if e.error_len().is_none() => mime::TEXT_PLAIN_UTF_8,
+        Err(_) => mime::APPLICATION_OCTET_STREAM,
+    }
+}
+
+/// Whether browsers can show files of this type themselves
+fn is_displayable(content_type: &Mime) -> bool {
+    match content_type.type_() {
+        mime::TEXT | mime::IMAGE | mime::AUDIO | mime::VIDEO => true,
+        mime::APPLICATION => matches!(
+            content_type.subtype().as_str(),
+            "json" | "javascript" | "pdf" | "xhtml" | "xml"
+        ),
+        _ => false,
+    }
+}
+
+fn disposition(content_type: &Mime, filename: String, download: bool) -> ContentDisposition {
+    let disposition = if !download && is_displayable(content_type) {
+        DispositionType::Inline
+    } else {
+        DispositionType::Attachment
+    };
+    ContentDisposition {
+        disposition,
+        parameters: vec![DispositionParam::Filename(filename)],
+    }
+}
+
+/// File name for downloads, the hash is dropped from top level store paths:
+/// `/nix/store/<hash>-disk.qcow2` becomes `disk.qcow2`
+fn download_name(path: &Path) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if path.parent() == Some(Path::new(STORE_DIR))
+        && let Some((_, name)) = name.split_once('-')
+    {
+        return name.to_string();
+    }
+    name
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_symlinks_stay_inside_store() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("nix_autobuild_store_{}", std::process::id()));
+        let store = base.join("store");
+        std::fs::create_dir_all(store.join("abc-pkg"))?;
+        std::fs::write(store.join("abc-pkg/file"), "file")?;
+        std::fs::write(base.join("secret"), "secret")?;
+        let _ = std::fs::remove_file(store.join("inside"));
+        let _ = std::fs::remove_file(store.join("outside"));
+        std::os::unix::fs::symlink(store.join("abc-pkg/file"), store.join("inside"))?;
+        std::os::unix::fs::symlink(base.join("secret"), store.join("outside"))?;
+
+        let canonical = store.canonicalize()?;
+        assert_eq!(
+            resolve_store_path(&store, &store.join("inside")),
+            Some(canonical.join("abc-pkg/file"))
+        );
+        assert_eq!(resolve_store_path(&store, &store.join("outside")), None);
+        assert_eq!(
+            resolve_store_path(&store, &store.join("abc-pkg/../../secret")),
+            None
+        );
+        assert_eq!(resolve_store_path(&store, &store.join("missing")), None);
+        Ok(())
+    }
+
+    #[test]
+    fn sniffs_extensionless_files() {
+        assert_eq!(
+            sniff(b"\x7fELF\x02\x01\x01"),
+            mime::APPLICATION_OCTET_STREAM
+        );
+        assert_eq!(sniff(b"#!/bin/sh\necho hi\n"), mime::TEXT_PLAIN_UTF_8);
+        // a character cut off by the sniff length
+        assert_eq!(
+            sniff("grüße".as_bytes()[..3].as_ref()),
+            mime::TEXT_PLAIN_UTF_8
+        );
+        assert_eq!(sniff(b"\xff\xfe\x00a"), mime::APPLICATION_OCTET_STREAM);
+    }
+
+    #[test]
+    fn sums_tree_without_following_symlinks() -> std::io::Result<()> {
+        let base = std::env::temp_dir().join(format!("nix_autobuild_tree_{}", std::process::id()));
+        std::fs::create_dir_all(base.join("out/bin"))?;
+        std::fs::write(base.join("out/bin/tool"), [0; 100])?;
+        std::fs::write(base.join("big"), [0; 10_000])?;
+        let _ = std::fs::remove_file(base.join("out/link"));
+        std::os::unix::fs::symlink(base.join("big"), base.join("out/link"))?;
+
+        let size = tree_size(&base.join("out"));
+        assert!(size >= 100, "{}", size);
+        assert!(size < 10_000, "{}", size);
+        Ok(())
+    }
+
+    #[test]
+    fn names_downloads_without_hash() {
+        assert_eq!(
+            download_name(Path::new("/nix/store/abc-disk.qcow2")),
+            "disk.qcow2"
+        );
+        assert_eq!(
+            download_name(Path::new("/nix/store/abc-doc/share/index-1.html")),
+            "index-1.html"
+        );
+    }
+}
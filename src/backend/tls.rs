@@ -0,0 +1,117 @@
+use std::sync::{Arc, RwLock};
+
+use actix_web::rt::signal::unix::{SignalKind, signal};
+use rustls::{
+    ServerConfig,
+    crypto::{CryptoProvider, ring},
+    pki_types::{CertificateDer, PrivateKeyDer, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::TlsOptions;
+
+/// Certificate resolver whose certificate can be swapped at runtime, so renewed
+/// certificates can be picked up without restarting the server
+#[derive(Debug)]
+pub struct ReloadableCert {
+    options: TlsOptions,
+    provider: Arc<CryptoProvider>,
+    key: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadableCert {
+    pub fn new(options: TlsOptions) -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        let provider = Arc::new(ring::default_provider());
+        let key = load_certified_key(&options, &provider)?;
+        Ok(Arc::new(ReloadableCert {
+            options,
+            provider,
+            key: RwLock::new(Arc::new(key)),
+        }))
+    }
+
+    /// Re-read the certificate chain and key from disk. On error the previously
+    /// loaded certificate stays in use.
+    pub fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let key = load_certified_key(&self.options, &self.provider)?;
+        *self
+            .key
+            .write()
+            .map_err(|_| "TLS certificate lock poisoned")? = Arc::new(key);
+        Ok(())
+    }
+
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig, rustls::Error> {
+        let mut config = ServerConfig::builder_with_provider(self.provider.clone())
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+
+    /// Reload the certificate every time the process receives SIGHUP
+    pub fn reload_on_sighup(self: &Arc<Self>) -> std::io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        let cert = self.clone();
+        actix_web::rt::spawn(async move {
+            while hangup.recv().await.is_some() {
+                match cert.reload() {
+                    Ok(()) => tracing::info!(
+                        "Reloaded TLS certificate {}",
+                        cert.options.cert_file.display()
+                    ),
+                    Err(e) => tracing::error!("reloading TLS certificate: {}", e),
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl ResolvesServerCert for ReloadableCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.key.read().ok().map(|key| key.clone())
+    }
+}
+
+fn load_certified_key(
+    options: &TlsOptions,
+    provider: &CryptoProvider,
+) -> Result<CertifiedKey, Box<dyn std::error::Error>> {
+    let cert_chain = CertificateDer::pem_file_iter(&options.cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| {
+            format!(
+                "failed to read TLS certificate {}: {}",
+                options.cert_file.display(),
+                e
+            )
+        })?;
+    if cert_chain.is_empty() {
+        return Err(format!(
+            "no PEM certificates found in {}",
+            options.cert_file.display()
+        )
+        .into());
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&options.key_file).map_err(|e| {
+        format!(
+            "failed to read TLS private key {}: {}",
+            options.key_file.display(),
+            e
+        )
+    })?;
+
+    CertifiedKey::from_der(cert_chain, key, provider).map_err(|e| {
+        format!(
+            "TLS private key {} can not be used with certificate {}: {}",
+            options.key_file.display(),
+            options.cert_file.display(),
+            e
+        )
+        .into()
+    })
+}
@@ -0,0 +1,105 @@
+use std::{
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::UnixStream,
+    },
+    path::Path,
+};
+
+use crate::UnixSocketOptions;
+
+/// Create the parent directory and remove a socket left behind by a previous run, so
+/// binding does not fail with "address in use"
+pub fn prepare(options: &UnixSocketOptions) -> Result<(), Box<dyn std::error::Error>> {
+    // fail before binding on a typo
+    parse_mode(&options.mode)?;
+    if let Some(parent) = options.path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let Ok(metadata) = std::fs::symlink_metadata(&options.path) else {
+        return Ok(());
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(format!("{} exists and is not a socket", options.path.display()).into());
+    }
+    if UnixStream::connect(&options.path).is_ok() {
+        return Err(format!("{} is in use by another server", options.path.display()).into());
+    }
+    tracing::info!("Removing stale socket {}", options.path.display());
+    std::fs::remove_file(&options.path)?;
+    Ok(())
+}
+
+/// Apply the configured permissions and owner to the bound socket
+pub fn secure(options: &UnixSocketOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let mode = parse_mode(&options.mode)?;
+    std::fs::set_permissions(&options.path, std::fs::Permissions::from_mode(mode))?;
+    let uid = match &options.user {
+        Some(user) => Some(lookup_id(user, Path::new("/etc/passwd"))?),
+        None => None,
+    };
+    let gid = match &options.group {
+        Some(group) => Some(lookup_id(group, Path::new("/etc/group"))?),
+        None => None,
+    };
+    if uid.is_some() || gid.is_some() {
+        std::os::unix::fs::chown(&options.path, uid, gid).map_err(|e| {
+            format!(
+                "failed to change the owner of {}: {}",
+                options.path.display(),
+                e
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn parse_mode(mode: &str) -> Result<u32, String> {
+    let digits = mode.trim_start_matches("0o");
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(format!(
+            "invalid socket mode `{}`, expected octal like 660",
+            mode
+        )),
+    }
+}
+
+/// Numeric ID of a user or group, names are looked up in `/etc/passwd` or `/etc/group`
+fn lookup_id(name: &str, database: &Path) -> Result<u32, String> {
+    if let Ok(id) = name.parse() {
+        return Ok(id);
+    }
+    let entries = std::fs::read_to_string(database)
+        .map_err(|e| format!("failed to read {}: {}", database.display(), e))?;
+    find_id(&entries, name).ok_or_else(|| format!("`{}` not found in {}", name, database.display()))
+}
+
+/// Both files are `name:password:id:...` lines
+fn find_id(entries: &str, name: &str) -> Option<u32> {
+    entries.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next()? != name {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_modes_and_ids() {
+        assert_eq!(parse_mode("660"), Ok(0o660));
+        assert_eq!(parse_mode("0o600"), Ok(0o600));
+        assert!(parse_mode("rw-rw----").is_err());
+        assert!(parse_mode("99999").is_err());
+
+        let passwd = "root:x:0:0:root:/root:/bin/sh\nnginx:x:60:60::/var/empty:/bin/false\n";
+        assert_eq!(find_id(passwd, "nginx"), Some(60));
+        assert_eq!(find_id(passwd, "nobody"), None);
+        assert_eq!(lookup_id("1000", Path::new("/nonexistent")), Ok(1000));
+    }
+}
@@ -0,0 +1,787 @@
+//! Checks of the settings that the parsers let through, all problems are collected
+//! before giving up so a broken config takes a single round of fixes
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::TcpListener,
+    path::Path,
+};
+
+use super::{build_window, cache, database, logging, paths, post_build, schedule, secrets};
+use crate::{ARCHITECTURES, AutoBuildOptions, SecretSource};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// the server refuses the config
+    Error,
+    /// worth a look, the server still starts
+    Warning,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// JSON path of the offending field, like `repos[2].poll_interval_sec`
+    pub path: String,
+    pub message: String,
+    pub severity: Severity,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Names end up in paths and URLs, so only characters that need no escaping
+fn is_valid_name(name: &str) -> bool {
+    !matches!(name, "" | "." | "..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+}
+
+fn error(path: impl Into<String>, message: impl Into<String>) -> Problem {
+    Problem {
+        path: path.into(),
+        message: message.into(),
+        severity: Severity::Error,
+    }
+}
+
+fn warning(path: impl Into<String>, message: impl Into<String>) -> Problem {
+    Problem {
+        path: path.into(),
+        message: message.into(),
+        severity: Severity::Warning,
+    }
+}
+
+/// Everything wrong with `settings`. `serving` also checks what only the server
+/// needs: the frontend files, and that the TCP address is free, which only makes
+/// sense while nothing of ours listens on it yet.
+pub fn problems(settings: &AutoBuildOptions, serving: bool) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let paths = &settings.paths;
+    for (option, dir) in [
+        ("dir", Some(&settings.dir)),
+        ("paths.checkouts_dir", paths.checkouts_dir.as_ref()),
+        ("paths.logs_dir", paths.logs_dir.as_ref()),
+        ("paths.state_dir", paths.state_dir.as_ref()),
+        ("paths.gc_roots_dir", paths.gc_roots_dir.as_ref()),
+    ] {
+        if let Some(dir) = dir
+            && !dir.is_absolute()
+        {
+            problems.push(error(
+                option,
+                format!(
+                    "{} is relative, use an absolute path so it does not depend on the working directory",
+                    dir.display()
+                ),
+            ));
+        }
+    }
+
+    check_architectures(
+        "supported_architectures",
+        &settings.supported_architectures,
+        &mut problems,
+    );
+
+    if let Err(e) = logging::parse_level(&settings.log.level) {
+        problems.push(error("log.level", e));
+    }
+    if !logging::FORMATS.contains(&settings.log.format.as_str()) {
+        problems.push(error(
+            "log.format",
+            format!(
+                "unknown format `{}`, use {}",
+                settings.log.format,
+                logging::FORMATS.join(" or ")
+            ),
+        ));
+    }
+
+    if !database::STATE_BACKENDS.contains(&settings.state.backend.as_str()) {
+        problems.push(error(
+            "state.backend",
+            format!(
+                "unknown backend `{}`, use {}",
+                settings.state.backend,
+                database::STATE_BACKENDS.join(", ")
+            ),
+        ));
+    }
+
+    if let Some(window) = &settings.build_window
+        && let Err(e) = build_window::Window::parse(window)
+    {
+        problems.push(error("build_window", e));
+    }
+
+    if settings.repos.is_empty() {
+        problems.push(warning(
+            "repos",
+            "no repos configured, nothing will be built",
+        ));
+    }
+    let mut first_index = HashMap::new();
+    let mut first_name_index = HashMap::new();
+    for (index, repo) in settings.repos.iter().enumerate() {
+        let path = format!("repos[{}]", index);
+        let name = repo.name();
+        if let Some(first) = first_index.insert(repo.url.as_str(), index) {
+            first_index.insert(repo.url.as_str(), first);
+            problems.push(error(
+                format!("{}.url", path),
+                format!(
+                    "{} is already listed as repos[{}]{}",
+                    repo.url,
+                    first,
+                    files(settings, first, index)
+                ),
+            ));
+        } else if !is_valid_name(&name) {
+            problems.push(error(
+                format!("{}.name", path),
+                format!(
+                    "`{}` is no valid name, use letters, digits, `.`, `_` and `-`",
+                    name
+                ),
+            ));
+        } else if let Some(first) = first_name_index.insert(name.clone(), index) {
+            first_name_index.insert(name.clone(), first);
+            // a derived name clashes when two urls end in the same segment
+            let hint = match repo.name {
+                Some(_) => "",
+                None => ", set `name` to tell them apart",
+            };
+            problems.push(error(
+                format!("{}.name", path),
+                format!(
+                    "{} is already the name of repos[{}]{}{}",
+                    name,
+                    first,
+                    files(settings, first, index),
+                    hint
+                ),
+            ));
+        }
+        if repo.poll_interval_sec == Some(0) {
+            problems.push(error(
+                format!("{}.poll_interval_sec", path),
+                "0 would poll without pause, use at least 1",
+            ));
+        }
+        if let Some(expression) = &repo.poll_schedule {
+            if repo.poll_interval_sec.is_some() {
+                problems.push(error(
+                    format!("{}.poll_schedule", path),
+                    "set either `poll_schedule` or `poll_interval_sec`, not both",
+                ));
+            } else if let Err(e) = schedule::parse(expression) {
+                problems.push(error(format!("{}.poll_schedule", path), e));
+            }
+        }
+        if let Some(archs) = &repo.supported_architectures {
+            check_architectures(
+                &format!("{}.supported_architectures", path),
+                archs,
+                &mut problems,
+            );
+        }
+        // empty was the default of the NixOS module
+        let credentials_file = repo
+            .credentials_file
+            .as_deref()
+            .filter(|file| !file.is_empty())
+            .map(Path::new);
+        check_secret(
+            &path,
+            "credentials",
+            repo.credentials.as_ref(),
+            credentials_file,
+            &mut problems,
+        );
+    }
+
+    if let Some(template) = &settings.checkout_name_template {
+        let mut first_checkout_index = HashMap::new();
+        // errors of the template itself are the same for every repo
+        let mut reported = HashSet::new();
+        for (index, repo) in settings.repos.iter().enumerate() {
+            match paths::checkout_name(Some(template), repo) {
+                Err(e) => {
+                    if reported.insert(e.clone()) {
+                        problems.push(error(
+                            "checkout_name_template",
+                            format!("repos[{}]: {}", index, e),
+                        ));
+                    }
+                }
+                Ok(name) => {
+                    if let Some(first) = first_checkout_index.get(&name) {
+                        problems.push(error(
+                            "checkout_name_template",
+                            format!(
+                                "repos[{}] and repos[{}] both check out to `{}`, add `{{hash8}}` to tell them apart",
+                                first, index, name
+                            ),
+                        ));
+                    } else {
+                        first_checkout_index.insert(name, index);
+                    }
+                }
+            }
+        }
+    }
+
+    let webhooks = &settings.webhooks;
+    for (name, source, file) in [
+        (
+            "github_secret",
+            &webhooks.github_secret,
+            &webhooks.github_secret_file,
+        ),
+        (
+            "gitlab_token",
+            &webhooks.gitlab_token,
+            &webhooks.gitlab_token_file,
+        ),
+        (
+            "generic_secret",
+            &webhooks.generic_secret,
+            &webhooks.generic_secret_file,
+        ),
+    ] {
+        check_secret(
+            "webhooks",
+            name,
+            source.as_ref(),
+            file.as_deref(),
+            &mut problems,
+        );
+    }
+
+    let notifications = &settings.notifications;
+    for (index, webhook) in notifications.webhooks.iter().enumerate() {
+        check_secret(
+            &format!("notifications.webhooks[{}]", index),
+            "secret",
+            webhook.secret.as_ref(),
+            webhook.secret_file.as_deref(),
+            &mut problems,
+        );
+    }
+    for (index, matrix) in notifications.matrix.iter().enumerate() {
+        let path = format!("notifications.matrix[{}]", index);
+        if matrix.access_token.is_none() && matrix.access_token_file.is_none() {
+            problems.push(error(
+                format!("{}.access_token", path),
+                "needs `access_token` or `access_token_file`",
+            ));
+        }
+        check_secret(
+            &path,
+            "access_token",
+            matrix.access_token.as_ref(),
+            matrix.access_token_file.as_deref(),
+            &mut problems,
+        );
+    }
+    for (kind, webhooks) in [
+        ("slack", &notifications.slack),
+        ("discord", &notifications.discord),
+    ] {
+        for (index, webhook) in webhooks.iter().enumerate() {
+            let path = format!("notifications.{}[{}]", kind, index);
+            match (&webhook.url, &webhook.url_secret) {
+                (None, None) => {
+                    problems.push(error(
+                        format!("{}.url", path),
+                        "needs `url` or `url_secret`",
+                    ));
+                }
+                (Some(_), Some(_)) => {
+                    problems.push(error(
+                        format!("{}.url", path),
+                        "set either `url` or `url_secret`, not both",
+                    ));
+                }
+                (None, Some(source)) => {
+                    if let Err(e) = secrets::resolve(source) {
+                        problems.push(error(format!("{}.url_secret", path), e));
+                    }
+                }
+                (Some(_), None) => {}
+            }
+        }
+    }
+    if let Some(email) = &notifications.email {
+        check_secret(
+            "notifications.email",
+            "credentials",
+            email.credentials.as_ref(),
+            email.credentials_file.as_deref(),
+            &mut problems,
+        );
+    }
+    if let Some(cache) = &settings.binary_cache {
+        check_secret(
+            "binary_cache",
+            "secret_key",
+            cache.secret_key.as_ref(),
+            cache.secret_key_file.as_deref(),
+            &mut problems,
+        );
+    }
+
+    let post_build = &settings.post_build;
+    for (index, uri) in post_build.copy_to.iter().enumerate() {
+        if let Err(e) = post_build::check_store_uri(uri) {
+            problems.push(error(format!("post_build.copy_to[{}]", index), e));
+        }
+    }
+    if let Some(cachix) = &post_build.cachix {
+        if cachix.cache.trim().is_empty() {
+            problems.push(error("post_build.cachix.cache", "must not be empty"));
+        }
+        check_secret(
+            "post_build.cachix",
+            "auth_token",
+            cachix.auth_token.as_ref(),
+            None,
+            &mut problems,
+        );
+    }
+    if let Some(attic) = &post_build.attic
+        && attic.cache.trim().is_empty()
+    {
+        problems.push(error("post_build.attic.cache", "must not be empty"));
+    }
+    if let Some(key_file) = &post_build.sign_key_file {
+        let parsed = std::fs::read_to_string(key_file)
+            .map_err(|e| format!("cannot read {}: {}", key_file.display(), e))
+            .and_then(|key| {
+                cache::parse_secret_key(key.trim())
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = parsed {
+            problems.push(error("post_build.sign_key_file", e));
+        }
+    }
+
+    if serving {
+        let frontend_dir = super::static_files::frontend_dir(settings);
+        if !frontend_dir.join("index.html").is_file() {
+            problems.push(error(
+                "frontend_dir",
+                format!("{} has no index.html", frontend_dir.display()),
+            ));
+        }
+    }
+
+    let tcp_address = settings.tcp_address();
+    let unix_socket = settings
+        .listen
+        .as_ref()
+        .and_then(|listen| listen.unix.as_ref());
+    match &tcp_address {
+        _ if !settings.server.enabled && settings.binary_cache.is_some() => {
+            problems.push(warning(
+                "binary_cache",
+                "is served over HTTP, which `server.enabled` turns off",
+            ));
+        }
+        _ if !settings.server.enabled => {}
+        None if unix_socket.is_none() => {
+            problems.push(error("listen", "needs `tcp`, `unix` or both"));
+        }
+        Some((host, port)) if serving => {
+            if let Err(e) = TcpListener::bind((host.as_str(), *port)) {
+                let path = match settings.listen {
+                    Some(_) => "listen.tcp.port",
+                    None => "port",
+                };
+                problems.push(error(
+                    path,
+                    format!("cannot listen on {}:{}: {}", host, port, e),
+                ));
+            }
+        }
+        _ => {}
+    }
+
+    problems
+}
+
+/// Which files of a config directory the repos `first` and `index` are from
+fn files(settings: &AutoBuildOptions, first: usize, index: usize) -> String {
+    let source = |index: usize| settings.sources.get(&format!("repos[{}]", index));
+    match (source(first), source(index)) {
+        (Some(first), Some(this)) => format!(" from {}, this one is from {}", first, this),
+        _ => String::new(),
+    }
+}
+
+/// The secret option `name` of `path` and its older `{name}_file` resolve, and at
+/// most one of them is set
+fn check_secret(
+    path: &str,
+    name: &str,
+    source: Option<&SecretSource>,
+    file: Option<&Path>,
+    problems: &mut Vec<Problem>,
+) {
+    let (option, result) = match (source, file) {
+        (Some(_), Some(_)) => {
+            problems.push(error(
+                format!("{}.{}", path, name),
+                format!("set either `{}` or `{}_file`, not both", name, name),
+            ));
+            return;
+        }
+        (Some(_), None) => (name.to_string(), secrets::resolve_either(source, None)),
+        (None, Some(_)) => (
+            format!("{}_file", name),
+            secrets::resolve_either(None, file),
+        ),
+        (None, None) => return,
+    };
+    if let Err(e) = result {
+        problems.push(error(format!("{}.{}", path, option), e));
+    }
+}
+
+fn check_architectures(path: &str, archs: &[String], problems: &mut Vec<Problem>) {
+    for (index, arch) in archs.iter().enumerate() {
+        if !ARCHITECTURES.contains(&arch.as_str()) {
+            problems.push(error(
+                format!("{}[{}]", path, index),
+                format!(
+                    "unknown architecture {:?}, known are {}",
+                    arch,
+                    ARCHITECTURES.join(", ")
+                ),
+            ));
+        }
+    }
+}
+
+/// The warnings if there are no errors, otherwise all problems as a numbered list
+pub fn validate(settings: &AutoBuildOptions, serving: bool) -> Result<Vec<Problem>, String> {
+    let problems = problems(settings, serving);
+    if problems
+        .iter()
+        .all(|problem| problem.severity == Severity::Warning)
+    {
+        return Ok(problems);
+    }
+    let mut report = match problems.len() {
+        1 => "The config has 1 problem:".to_string(),
+        count => format!("The config has {} problems:", count),
+    };
+    for (index, problem) in problems.iter().enumerate() {
+        let prefix = match problem.severity {
+            Severity::Error => "",
+            Severity::Warning => "warning, ",
+        };
+        report.push_str(&format!("\n  {}. {}{}", index + 1, prefix, problem));
+    }
+    Err(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Result<AutoBuildOptions, Box<dyn std::error::Error>> {
+        super::super::config_file::read(concat!(env!("CARGO_MANIFEST_DIR"), "/config.json"))
+    }
+
+    #[test]
+    fn example_config_is_valid() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(problems(&example()?, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn all_problems_are_listed() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.dir = "data".into();
+        settings.paths.logs_dir = Some("logs".into());
+        settings
+            .supported_architectures
+            .push("x86-linux".to_string());
+        settings.log.format = "logfmt".to_string();
+        settings.build_window = Some(crate::BuildWindowOptions {
+            start: "22:00".to_string(),
+            end: "6am".to_string(),
+            timezone: None,
+        });
+        settings.repos[1].poll_interval_sec = Some(0);
+        settings.repos[2].url = settings.repos[0].url.clone();
+        settings.repos[3].credentials_file = Some("/nonexistent/credentials".to_string());
+        settings.repos[3].supported_architectures =
+            Some(vec!["aarch64-linux".to_string(), "arm64-linux".to_string()]);
+
+        let paths = problems(&settings, false)
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                "dir",
+                "paths.logs_dir",
+                "supported_architectures[1]",
+                "log.format",
+                "build_window",
+                "repos[1].poll_interval_sec",
+                "repos[2].url",
+                "repos[3].supported_architectures[1]",
+                "repos[3].credentials_file"
+            ]
+        );
+        let report = validate(&settings, false).err().unwrap_or_default();
+        assert!(report.starts_with("The config has 9 problems:\n  1. dir: data is relative"));
+        assert!(report.contains(
+            "\n  7. repos[2].url: github.com/PPAPSONKA/nix is already listed as repos[0]"
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn checkout_names_are_unique() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.checkout_name_template = Some("{owner}".to_string());
+        assert_eq!(
+            problems(&settings, false)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "checkout_name_template: repos[0] and repos[1] both check out to `PPAPSONKA`, add `{hash8}` to tell them apart",
+                "checkout_name_template: repos[2] and repos[3] both check out to `AkosPapp`, add `{hash8}` to tell them apart"
+            ]
+        );
+        settings.checkout_name_template = Some("{owner}-{repo}-{branch}".to_string());
+        assert_eq!(problems(&settings, false).len(), 1);
+        settings.checkout_name_template = Some("{host}_{owner}_{repo}".to_string());
+        assert_eq!(problems(&settings, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn headless_needs_no_listener() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.listen = Some(serde_json::from_str("{}")?);
+        assert_eq!(problems(&settings, false)[0].path, "listen");
+
+        settings.server.enabled = false;
+        settings.binary_cache = Some(serde_json::from_str("{}")?);
+        let problems = problems(&settings, false);
+        assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            ["binary_cache: is served over HTTP, which `server.enabled` turns off"]
+        );
+        assert_eq!(problems[0].severity, Severity::Warning);
+        Ok(())
+    }
+
+    #[test]
+    fn repo_names_are_unique() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.repos[1].url = "github.com/AkosPapp/nix.git".to_string();
+        settings.repos[2].name = Some("../nix".to_string());
+        settings.repos[3].name = Some("nix".to_string());
+
+        let problems = problems(&settings, false);
+        assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "repos[1].name: nix is already the name of repos[0], set `name` to tell them apart",
+                "repos[2].name: `../nix` is no valid name, use letters, digits, `.`, `_` and `-`",
+                "repos[3].name: nix is already the name of repos[0]"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn name_clashes_name_the_files() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.repos[3].name = Some("nix".to_string());
+        for (index, file) in ["base.json", "a.json", "b.json", "team.toml", "c.json"]
+            .iter()
+            .enumerate()
+        {
+            settings
+                .sources
+                .insert(format!("repos[{}]", index), file.to_string());
+        }
+        assert_eq!(
+            problems(&settings, false)
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>(),
+            [
+                "repos[3].name: nix is already the name of repos[0] from base.json, this one is from team.toml"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn secrets_must_resolve() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        let unset = SecretSource {
+            env: Some("NIX_AUTOBUILD_TEST_UNSET".to_string()),
+            ..SecretSource::default()
+        };
+        settings.repos[0].credentials = Some(unset.clone());
+        settings.repos[1].credentials = Some(SecretSource {
+            env: Some("PATH".to_string()),
+            ..SecretSource::default()
+        });
+        settings.repos[1].credentials_file = Some("/run/secrets/github".to_string());
+        settings.notifications.matrix = vec![serde_json::from_value(serde_json::json!({
+            "homeserver": "https://matrix.org",
+            "room_id": "!ci:matrix.org",
+        }))?];
+        settings.notifications.webhooks = vec![serde_json::from_value(serde_json::json!({
+            "url": "https://example.com/hook",
+            "secret": {"env": "PATH"},
+        }))?];
+        settings.notifications.slack = vec![
+            serde_json::from_value(serde_json::json!({}))?,
+            serde_json::from_value(serde_json::json!({"url_secret": {"env": "PATH"}}))?,
+        ];
+        settings.notifications.discord = vec![serde_json::from_value(serde_json::json!({
+            "url_secret": unset,
+        }))?];
+
+        let problems = problems(&settings, false);
+        assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "repos[0].credentials: environment variable NIX_AUTOBUILD_TEST_UNSET is not set",
+                "repos[1].credentials: set either `credentials` or `credentials_file`, not both",
+                "notifications.matrix[0].access_token: needs `access_token` or `access_token_file`",
+                "notifications.slack[0].url: needs `url` or `url_secret`",
+                "notifications.discord[0].url_secret: environment variable NIX_AUTOBUILD_TEST_UNSET is not set"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn post_build_targets_are_checked() -> Result<(), Box<dyn std::error::Error>> {
+        let key_file =
+            std::env::temp_dir().join(format!("nix_autobuild_sign_key_{}", std::process::id()));
+        std::fs::write(&key_file, "cache-1:AAAA\n")?;
+        let mut settings = example()?;
+        settings.post_build = serde_json::from_value(serde_json::json!({
+            "copy_to": ["s3://nix-cache?region=eu-central-1", "cache.example.com"],
+            "cachix": {"cache": " "},
+            "attic": {"cache": "central:main"},
+            "sign_key_file": key_file,
+        }))?;
+
+        let problems = problems(&settings, false);
+        std::fs::remove_file(&key_file)?;
+        assert_eq!(
+            problems.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [
+                "post_build.copy_to[1]: \"cache.example.com\" is neither a store URI nor an absolute path",
+                "post_build.cachix.cache: must not be empty",
+                "post_build.sign_key_file: secret key must be 64 bytes"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn poll_schedule_replaces_the_interval() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.repos[0].poll_schedule = Some("0 3 * * *".to_string());
+        settings.repos[1].poll_interval_sec = None;
+        settings.repos[1].poll_schedule = Some("0 3 * *".to_string());
+        settings.repos[2].poll_interval_sec = None;
+        settings.repos[2].poll_schedule = Some("*/10 8-19 * * Mon-Fri".to_string());
+
+        let paths = problems(&settings, false)
+            .into_iter()
+            .map(|problem| problem.path)
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["repos[0].poll_schedule", "repos[1].poll_schedule"]);
+        Ok(())
+    }
+
+    #[test]
+    fn empty_repo_list_is_only_a_warning() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.repos.clear();
+        let warnings = validate(&settings, false)?;
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].path, "repos");
+
+        settings.listen = Some(crate::ListenOptions {
+            tcp: None,
+            unix: None,
+        });
+        assert!(validate(&settings, false).is_err());
+        Ok(())
+    }
+
+    /// Directory with an `index.html`, like the output of trunk
+    fn frontend(name: &str) -> std::io::Result<std::path::PathBuf> {
+        let dir = std::env::temp_dir().join(format!(
+            "nix_autobuild_validate_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("index.html"), "index")?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn frontend_needs_an_index() -> Result<(), Box<dyn std::error::Error>> {
+        let mut settings = example()?;
+        settings.host = "127.0.0.1".to_string();
+        settings.port = 0;
+        settings.frontend_dir = Some(frontend("index")?);
+        assert_eq!(problems(&settings, true), []);
+
+        let missing = frontend("missing")?;
+        std::fs::remove_file(missing.join("index.html"))?;
+        settings.frontend_dir = Some(missing.clone());
+        assert_eq!(
+            problems(&settings, true),
+            [error(
+                "frontend_dir",
+                format!("{} has no index.html", missing.display())
+            )]
+        );
+        assert_eq!(problems(&settings, false), []);
+        Ok(())
+    }
+
+    #[test]
+    fn taken_ports_are_reported() -> Result<(), Box<dyn std::error::Error>> {
+        let taken = TcpListener::bind(("127.0.0.1", 0))?;
+        let mut settings = example()?;
+        settings.frontend_dir = Some(frontend("ports")?);
+        settings.host = "127.0.0.1".to_string();
+        settings.port = taken.local_addr()?.port();
+        let problems = problems(&settings, true);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "port");
+        assert_eq!(super::problems(&settings, false), []);
+        Ok(())
+    }
+}
@@ -0,0 +1,89 @@
+use actix_web::{HttpRequest, HttpResponse, get, web};
+use actix_ws::{Message, Session};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::event::StateEvent;
+
+use super::{
+    BuildRepos,
+    events::{self, Sequenced},
+};
+
+/// Live state updates: a full snapshot on connect followed by incremental events
+#[get("/ws")]
+async fn ws(
+    req: HttpRequest,
+    body: web::Payload,
+    repos: web::Data<BuildRepos>,
+) -> actix_web::Result<HttpResponse> {
+    let (response, mut session, mut msg_stream) = actix_ws::handle(&req, body)?;
+    let repos = repos.get_ref().clone();
+    let mut events = events::subscribe();
+    tracing::debug!("WebSocket client connected");
+
+    actix_web::rt::spawn(async move {
+        if send_snapshot(&mut session, &repos).await.is_err() {
+            return;
+        }
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let sent = match event {
+                        // structural changes are rare, resend everything instead of diffing
+                        Ok(Sequenced {
+                            event: StateEvent::CommitsChanged { .. },
+                            ..
+                        })
+                        | Err(RecvError::Lagged(_)) => {
+                            send_snapshot(&mut session, &repos).await
+                        }
+                        Ok(Sequenced {
+                            event: StateEvent::ServerShutdown,
+                            ..
+                        }) => {
+                            let _ = send_event(&mut session, &StateEvent::ServerShutdown).await;
+                            break;
+                        }
+                        Ok(Sequenced { event, .. }) => send_event(&mut session, &event).await,
+                        Err(RecvError::Closed) => break,
+                    };
+                    if sent.is_err() {
+                        break;
+                    }
+                }
+                msg = msg_stream.recv() => match msg {
+                    Some(Ok(Message::Ping(bytes))) => {
+                        if session.pong(&bytes).await.is_err() {
+                            break;
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => {}
+                },
+            }
+        }
+        let _ = session.close(None).await;
+        tracing::debug!("WebSocket client disconnected");
+    });
+
+    Ok(response)
+}
+
+async fn send_snapshot(session: &mut Session, repos: &BuildRepos) -> Result<(), actix_ws::Closed> {
+    // take the version first, changes racing with the snapshot are sent again
+    let snapshot = StateEvent::Snapshot {
+        version: events::version(),
+        repos: repos.snapshot(),
+    };
+    send_event(session, &snapshot).await
+}
+
+async fn send_event(session: &mut Session, event: &StateEvent) -> Result<(), actix_ws::Closed> {
+    match serde_json::to_string(event) {
+        Ok(json) => session.text(json).await,
+        Err(e) => {
+            tracing::error!("serializing event: {}", e);
+            Ok(())
+        }
+    }
+}
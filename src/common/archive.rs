@@ -0,0 +1,44 @@
+#[cfg(target_arch = "wasm32")]
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{Deserialize, Serialize};
+
+use crate::package::{BuildTimes, PackageBuildStatus};
+
+/// A month of the archive in `/archive`, the commits are sorted in by their
+/// commit time
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct ArchiveMonth {
+    /// `YYYY-MM`, in UTC
+    pub month: String,
+    pub commits: usize,
+    /// of the gzipped file
+    pub bytes: u64,
+}
+
+/// A commit moved out of the build state, as `/archive/{month}` returns it
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct ArchivedCommit {
+    pub repo: String,
+    pub hash: String,
+    pub message: String,
+    pub unix_secs: i64,
+    /// when it was archived
+    pub archived_secs: i64,
+    /// empty if the flake was never evaluated
+    pub packages: Vec<ArchivedPackage>,
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct ArchivedPackage {
+    pub path: String,
+    pub status: PackageBuildStatus,
+    pub times: BuildTimes,
+    pub closure_size: Option<u64>,
+}
@@ -0,0 +1,29 @@
+#[cfg(target_arch = "wasm32")]
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// What the builds of the server ran with, taken at startup and on every config
+/// reload. Attempts name the snapshot they were built on by its `id`.
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub struct BuilderInfo {
+    /// of everything but `captured_secs`, an unchanged builder keeps it across
+    /// restarts
+    pub id: String,
+    /// when the builder was first seen like this
+    pub captured_secs: i64,
+    /// output of `nix --version`
+    pub nix_version: String,
+    /// the settings of `nix config show` that decide how and where paths are built
+    /// and where they are fetched from
+    pub nix_config: BTreeMap<String, String>,
+    /// output of `uname -srm`
+    pub kernel: String,
+    /// architecture and operating system the server was compiled for
+    pub system: String,
+    pub server_version: String,
+    pub git_rev: String,
+}
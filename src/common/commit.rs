@@ -1,10 +1,12 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Weak;
 use std::sync::{Arc, RwLock};
 
 use serde::{Deserialize, Serialize};
 
 use crate::{commit, package::PackageEnum, repo::RepoInfo, serialize::RwLockWrapper};
 
-#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
 #[derive(Debug)]
 
@@ -16,20 +18,58 @@ pub struct CommitInfo {
 
     pub packages: RwLockWrapper<Vec<PackageEnum>>,
 
+    /// weak, the repo keeps its commits alive and not the other way around
     #[cfg(not(target_arch = "wasm32"))]
     #[serde(skip)]
-    pub repo: Arc<RepoInfo>,
+    pub repo: RwLockWrapper<Weak<RepoInfo>>,
 
     pub unix_secs: i64,
+
+    /// `None` if git could not compute it
+    pub diff_stats: Option<DiffStats>,
 }
 
 unsafe impl Send for CommitInfo {}
 unsafe impl Sync for CommitInfo {}
 
+#[cfg(not(target_arch = "wasm32"))]
+impl CommitInfo {
+    /// The repo it belongs to, `None` once the repo was removed and only a request
+    /// still holds the commit
+    pub fn repo(&self) -> Option<Arc<RepoInfo>> {
+        self.repo.get().upgrade()
+    }
+
+    /// The url of its repo, empty once the repo was removed
+    pub fn repo_url(&self) -> String {
+        self.repo()
+            .map(|repo| repo.repo.url.clone())
+            .unwrap_or_default()
+    }
+
+    /// Drop the packages, which hold the commit, once the repo forgot it
+    pub fn forget(&self) {
+        self.packages.set(Vec::new());
+    }
+}
+
+/// Changes of a commit against its first parent
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Deserialize))]
+#[derive(Debug, Clone)]
+pub enum DiffStats {
+    /// a root commit, without a parent to compare with
+    Initial,
+    Changed {
+        files_changed: usize,
+        insertions: usize,
+        deletions: usize,
+    },
+}
 
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum RepoStatus {
     Cloning,
     Opening,
@@ -38,10 +78,9 @@ pub enum RepoStatus {
     Polling,
 }
 
-
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CommitBuildStatus {
     Idle,
     GettingPackages,
@@ -0,0 +1,269 @@
+#[cfg(target_arch = "wasm32")]
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use serde::Serialize;
+use std::collections::VecDeque;
+
+#[cfg(target_arch = "wasm32")]
+use crate::package::PackageEnum;
+
+use crate::{
+    RepoList,
+    commit::{CommitBuildStatus, RepoStatus},
+    package::{BuildAttempt, BuildTimes, PackageBuildStatus, PushStatus},
+    repo::{PollEvent, RepoInfo},
+    serialize::VecArcWrapper,
+};
+
+/// Change notification pushed to live clients, repos are identified by url and
+/// packages by their attribute path
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Clone))]
+#[derive(Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StateEvent {
+    /// Full state, sent when a client connects or can not be updated incrementally
+    Snapshot {
+        version: u64,
+        repos: RepoList,
+    },
+    RepoStatus {
+        repo: String,
+        status: RepoStatus,
+    },
+    /// After every poll, with all polls still kept
+    PollHistory {
+        repo: String,
+        polls: VecDeque<PollEvent>,
+    },
+    CommitStatus {
+        repo: String,
+        commit: String,
+        status: CommitBuildStatus,
+    },
+    PackageStatus {
+        repo: String,
+        commit: String,
+        path: String,
+        status: PackageBuildStatus,
+        #[serde(default)]
+        times: BuildTimes,
+        /// of a successful build
+        #[serde(default)]
+        closure_size: Option<u64>,
+        /// seconds a successful build usually takes while it waits or runs
+        #[serde(default)]
+        expected_secs: Option<u64>,
+        /// `None` leaves the attempts as they are
+        #[serde(default)]
+        attempts: Option<Vec<BuildAttempt>>,
+    },
+    /// A successful build is being pushed to the `post_build` targets
+    PackagePushes {
+        repo: String,
+        commit: String,
+        path: String,
+        pushes: Vec<PushStatus>,
+    },
+    /// The output paths of a successful build were garbage collected, or are back
+    PackageResult {
+        repo: String,
+        commit: String,
+        path: String,
+        missing: bool,
+    },
+    /// The poller waits until `unix_secs` unless a poll is requested earlier
+    NextPoll {
+        repo: String,
+        unix_secs: Option<i64>,
+    },
+    /// Commits, branch tips or package lists of a repo changed
+    CommitsChanged {
+        repo: String,
+    },
+    /// Repos were added, removed or reconfigured, only a snapshot shows that
+    ReposChanged,
+    /// Last event before the server exits, clients should reconnect later
+    ServerShutdown,
+}
+
+/// Answer of `/repos/changes`, everything that changed after the requested version
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug)]
+pub struct RepoChanges {
+    /// Pass as `since` to get the next changes
+    pub version: u64,
+    /// Full state, set instead of `repos` and `patches` when the requested version
+    /// is too old to diff
+    pub snapshot: Option<RepoList>,
+    /// Repos whose commits, branches or package lists changed, replaced as a whole
+    pub repos: VecArcWrapper<RepoInfo>,
+    /// Status changes of all other repos, oldest first
+    pub patches: Vec<StateEvent>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl RepoList {
+    pub fn apply_changes(&mut self, changes: RepoChanges) {
+        if let Some(snapshot) = changes.snapshot {
+            *self = snapshot;
+            return;
+        }
+        for repo in changes.repos.0 {
+            match self
+                .0
+                .0
+                .iter_mut()
+                .find(|old| old.repo.url == repo.repo.url)
+            {
+                Some(old) => *old = repo,
+                None => self.0.0.push(repo),
+            }
+        }
+        for patch in changes.patches {
+            self.apply_event(patch);
+        }
+    }
+
+    fn repo_mut(&mut self, url: &str) -> Option<&mut RepoInfo> {
+        self.0.0.iter_mut().find(|repo| repo.repo.url == url)
+    }
+
+    /// Apply a status change, events that need a refetch are ignored
+    pub fn apply_event(&mut self, event: StateEvent) {
+        match event {
+            StateEvent::Snapshot { repos, .. } => *self = repos,
+            StateEvent::RepoStatus { repo, status } => {
+                if let Some(repo) = self.repo_mut(&repo) {
+                    repo.status.0 = status;
+                }
+            }
+            StateEvent::PollHistory { repo, polls } => {
+                if let Some(repo) = self.repo_mut(&repo) {
+                    repo.poll_history.0 = polls;
+                }
+            }
+            StateEvent::NextPoll { repo, unix_secs } => {
+                if let Some(repo) = self.repo_mut(&repo) {
+                    repo.next_poll.0 = unix_secs;
+                }
+            }
+            StateEvent::CommitStatus {
+                repo,
+                commit,
+                status,
+            } => {
+                if let Some(commit) = self
+                    .repo_mut(&repo)
+                    .and_then(|repo| repo.commits.0.get_mut(&commit))
+                {
+                    commit.status.0 = status;
+                }
+            }
+            StateEvent::PackageStatus {
+                repo,
+                commit,
+                path,
+                status,
+                times,
+                closure_size,
+                expected_secs,
+                attempts,
+            } => {
+                let Some(commit) = self
+                    .repo_mut(&repo)
+                    .and_then(|repo| repo.commits.0.get_mut(&commit))
+                else {
+                    return;
+                };
+                for package in commit.packages.0.iter_mut() {
+                    match package {
+                        // only a new build brings the result back, not e.g. a queued
+                        // rebuild that is put back to the old status
+                        PackageEnum::Derivation(pkg) if pkg.0.path == path => {
+                            if matches!(status, PackageBuildStatus::Success(_))
+                                && pkg.0.status.0 != status
+                            {
+                                pkg.0.result_missing.0 = false;
+                            }
+                            pkg.0.status.0 = status.clone();
+                            pkg.0.times.0 = times;
+                            pkg.0.closure_size.0 = closure_size;
+                            pkg.0.expected_secs.0 = expected_secs;
+                            if let Some(attempts) = &attempts {
+                                pkg.0.attempts.0 = attempts.clone();
+                            }
+                        }
+                        PackageEnum::NixosConfig(pkg) if pkg.0.path == path => {
+                            if matches!(status, PackageBuildStatus::Success(_))
+                                && pkg.0.status.0 != status
+                            {
+                                pkg.0.result_missing.0 = false;
+                            }
+                            pkg.0.status.0 = status.clone();
+                            pkg.0.times.0 = times;
+                            pkg.0.closure_size.0 = closure_size;
+                            pkg.0.expected_secs.0 = expected_secs;
+                            if let Some(attempts) = &attempts {
+                                pkg.0.attempts.0 = attempts.clone();
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            StateEvent::PackagePushes {
+                repo,
+                commit,
+                path,
+                pushes,
+            } => {
+                let Some(commit) = self
+                    .repo_mut(&repo)
+                    .and_then(|repo| repo.commits.0.get_mut(&commit))
+                else {
+                    return;
+                };
+                for package in commit.packages.0.iter_mut() {
+                    match package {
+                        PackageEnum::Derivation(pkg) if pkg.0.path == path => {
+                            pkg.0.pushes.0 = pushes.clone();
+                        }
+                        PackageEnum::NixosConfig(pkg) if pkg.0.path == path => {
+                            pkg.0.pushes.0 = pushes.clone();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            StateEvent::PackageResult {
+                repo,
+                commit,
+                path,
+                missing,
+            } => {
+                let Some(commit) = self
+                    .repo_mut(&repo)
+                    .and_then(|repo| repo.commits.0.get_mut(&commit))
+                else {
+                    return;
+                };
+                for package in commit.packages.0.iter_mut() {
+                    match package {
+                        PackageEnum::Derivation(pkg) if pkg.0.path == path => {
+                            pkg.0.result_missing.0 = missing;
+                        }
+                        PackageEnum::NixosConfig(pkg) if pkg.0.path == path => {
+                            pkg.0.result_missing.0 = missing;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            StateEvent::CommitsChanged { .. }
+            | StateEvent::ReposChanged
+            | StateEvent::ServerShutdown => {}
+        }
+    }
+}
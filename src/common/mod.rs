@@ -1,92 +1,1364 @@
+pub mod archive;
+pub mod builder;
 pub mod commit;
-pub mod macros;
+pub mod event;
 pub mod package;
+pub mod queue;
 pub mod repo;
 pub mod serialize;
 
-// Re-export dependencies needed by macros
 #[cfg(not(target_arch = "wasm32"))]
-#[doc(hidden)]
-pub use paste;
-#[cfg(not(target_arch = "wasm32"))]
-#[doc(hidden)]
-pub use regex;
-
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use serde_nixos::NixosType;
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::BTreeMap, path::PathBuf};
 
-// Import macro exported at crate root
-use crate::{generate_nixos_module, repo::RepoInfo, serialize::VecArcWrapper};
+use crate::{repo::RepoInfo, serialize::VecArcWrapper};
 
 #[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
 pub struct Repo {
     #[nixos(description = "Repository URL", example = "\"github.com/org/repo\"")]
     pub url: String,
 
     #[nixos(
-        description = "Polling interval in seconds to check for updates",
-        default = "300"
+        description = "Name of the repository in the API paths, badges, checkout directory and dashboard. Letters, digits, `.`, `_` and `-`, unique among the repositories. Derived from the last segment of `url` when not set.",
+        default = "null",
+        example = "\"nix_autobuild\""
+    )]
+    #[serde(default)]
+    pub name: Option<String>,
+
+    #[nixos(
+        description = "Polling interval in seconds to check for updates, 300 when neither it nor `poll_schedule` is set",
+        default = "null",
+        example = "30"
+    )]
+    #[serde(default)]
+    pub poll_interval_sec: Option<u64>,
+
+    #[nixos(
+        description = "Cron expression of the poll times in the local time of the server, instead of `poll_interval_sec`. Five fields (minute, hour, day of month, month, day of week), six with the seconds first, or `@hourly`, `@daily` and the like. Day names are clearer than numbers, the days of the week count from 1 for Sunday.",
+        default = "null",
+        example = "\"*/10 8-19 * * Mon-Fri\""
     )]
-    pub poll_interval_sec: u64,
+    #[serde(default)]
+    pub poll_schedule: Option<String>,
 
     #[nixos(
         description = "Branches to monitor. If empty or not set, all branches are monitored.",
         default = "[]",
         example = "[\"main\" \"dev\"]"
     )]
+    #[serde(default)]
     pub branches: Vec<String>,
 
     #[nixos(
         description = "How many commints to build from the tip of each branch",
         default = "1"
     )]
+    #[serde(default = "default_build_depth")]
     pub build_depth: u8,
 
     #[nixos(
         description = "Optional path to a credentials file. When set, the file must contain a single line with credentials in the format `username:password` (no quotes). If omitted or empty, no credentials are used.",
-        default = "\"\"",
+        default = "null",
         example = "\"/path/to/credentials\""
     )]
+    #[serde(default)]
     pub credentials_file: Option<String>,
+
+    #[nixos(
+        description = "Credentials in the format `username:password` from a file, an environment variable or a systemd credential, instead of `credentials_file`",
+        default = "null",
+        example = "{ systemd_credential = \"github\"; }"
+    )]
+    #[serde(default)]
+    pub credentials: Option<SecretSource>,
+
+    #[nixos(
+        description = "Email addresses notified about this repository instead of `notifications.email.to`",
+        default = "[]",
+        example = "[\"team@example.com\"]"
+    )]
+    #[serde(default)]
+    pub email_recipients: Vec<String>,
+
+    #[nixos(
+        description = "Web page of a commit, `{hash}` is replaced by the commit hash. Derived from `url` for GitHub, GitLab and Codeberg when not set.",
+        default = "null",
+        example = "\"https://git.example.com/org/repo/commit/{hash}\""
+    )]
+    #[serde(default)]
+    pub commit_url_template: Option<String>,
+
+    #[nixos(
+        description = "Architectures to build for this repository instead of the global `supported_architectures`",
+        default = "null",
+        example = "[\"x86_64-linux\" \"aarch64-linux\"]"
+    )]
+    #[serde(default)]
+    pub supported_architectures: Option<Vec<String>>,
+
+    #[nixos(
+        description = "Builds of this repository that may run at the same time, within `n_build_threads`. 0 or null for no limit of its own.",
+        default = "null",
+        example = "2"
+    )]
+    #[serde(default)]
+    pub max_concurrent_builds: Option<usize>,
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-generate_nixos_module!(AutoBuildOptions);
+impl Repo {
+    /// `name`, or the last segment of `url` without `.git` and with everything but
+    /// letters, digits, `.`, `_` and `-` replaced by `-`
+    pub fn name(&self) -> String {
+        if let Some(name) = &self.name {
+            return name.clone();
+        }
+        let url = self.url.trim_end_matches('/');
+        let last = url.rsplit(['/', ':']).next().unwrap_or(url);
+        let last = last.strip_suffix(".git").unwrap_or(last);
+        last.chars()
+            .map(|c| match c.is_ascii_alphanumeric() || "._-".contains(c) {
+                true => c,
+                false => '-',
+            })
+            .collect()
+    }
 
-#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+    /// `poll_interval_sec`, or 300 when it is not set
+    pub fn poll_interval_sec(&self) -> u64 {
+        self.poll_interval_sec.unwrap_or(DEFAULT_POLL_INTERVAL_SEC)
+    }
+}
 
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
 pub struct AutoBuildOptions {
     #[nixos(description = "List of repositories to monitor", default = "[]")]
+    #[serde(default)]
     pub repos: Vec<Repo>,
 
     #[nixos(
         description = "Directory used to checkout repositories",
         default = "\"/var/lib/nix_autobuild\""
     )]
+    #[serde(default = "default_dir")]
     pub dir: PathBuf,
 
+    #[nixos(
+        description = "Where checkouts, logs, state and GC roots are kept, each below `dir` unless set",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub paths: PathOptions,
+
+    #[nixos(
+        description = "How the build state is kept in `paths.state_dir` across restarts",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub state: StateOptions,
+
+    #[nixos(
+        description = "Directory name of each checkout below `paths.checkouts_dir`, with the placeholders `{host}`, `{owner}`, `{repo}` and `{hash8}` (the start of the SHA-256 of the url). When not set, the name of the repo. Checkouts of an earlier name are moved on startup.",
+        default = "null",
+        example = "\"{owner}-{repo}\""
+    )]
+    #[serde(default)]
+    pub checkout_name_template: Option<String>,
+
+    #[nixos(
+        description = "Directory of the built frontend, served at the root. It has to contain `index.html`. When not set, the `FRONTEND_PATH` environment variable is used, and without it the path the binary was built with.",
+        default = "null",
+        example = "\"/opt/nix_autobuild/dist\""
+    )]
+    #[serde(default)]
+    pub frontend_dir: Option<PathBuf>,
+
     #[nixos(
         description = "List of supported Nix build architectures (e.g. x86_64-linux)",
         default = "[]",
         example = "[\"x86_64-linux\" \"aarch64-linux\"]"
     )]
+    #[serde(default)]
     pub supported_architectures: Vec<String>,
 
+    #[nixos(
+        description = "The HTTP server with the dashboard, the API and the binary cache",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub server: ServerOptions,
+
     #[nixos(
         description = "Host address for the server to bind to",
         default = "\"127.0.0.1\""
     )]
+    #[serde(default = "default_tcp_host")]
     pub host: String,
 
     #[nixos(description = "Port for the server to bind to", default = "8080")]
+    #[serde(default = "default_tcp_port")]
     pub port: u16,
 
+    #[nixos(
+        description = "Where to accept connections. When not set, `host` and `port` are used.",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub listen: Option<ListenOptions>,
+
+    #[nixos(
+        description = "Path prefix of all routes, for serving below a path of a reverse proxy that passes the prefix on. Empty to serve at the root.",
+        default = "\"\"",
+        example = "\"/autobuild\""
+    )]
+    #[serde(default)]
+    pub base_path: String,
+
     #[nixos(
         description = "Number of threads to use for building. If 0, uses the number of CPU cores.",
         default = "0"
     )]
+    #[serde(default)]
     pub n_build_threads: usize,
+
+    #[nixos(
+        description = "Evaluate the packages but only run `nix build --dry-run`, recording how many derivations would be built and how many paths fetched. Turning it off on a reload does not rebuild the packages that were only dry run.",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub dry_run: bool,
+
+    #[nixos(
+        description = "Commits kept per repository, counting the ones on a branch, which are always kept. Beyond it the oldest commits that are on no branch anymore, e.g. after a force push, are forgotten once their builds finished. 0 for no limit on the commits on a branch, of the others the newest 20 are kept.",
+        default = "100"
+    )]
+    #[serde(default = "default_max_commits_per_repo")]
+    pub max_commits_per_repo: usize,
+
+    #[nixos(
+        description = "Days after which commits that are on no branch anymore are forgotten once their builds finished, by commit time. 0 for no limit.",
+        default = "30"
+    )]
+    #[serde(default = "default_max_commit_age_days")]
+    pub max_commit_age_days: u64,
+
+    #[nixos(
+        description = "Days after which commits on no branch are moved to gzipped monthly files in `archive` below `paths.state_dir`, also before `max_commit_age_days`; commits forgotten because of `max_commits_per_repo` are archived too. 0 to forget them without an archive.",
+        default = "0"
+    )]
+    #[serde(default)]
+    pub archive_after_days: u64,
+
+    #[nixos(
+        description = "Days after which build logs are deleted, by the time they were last written. The attempts of the packages then show the log as expired. 0 for no limit.",
+        default = "90"
+    )]
+    #[serde(default = "default_max_log_age_days")]
+    pub max_log_age_days: u64,
+
+    #[nixos(
+        description = "Bytes all build logs in `paths.logs_dir` may take together, beyond them the oldest logs are deleted. 0 for no limit.",
+        default = "0"
+    )]
+    #[serde(default)]
+    pub max_total_log_bytes: u64,
+
+    #[nixos(
+        description = "Gzip the log of a build once it finished, the logs are still served as plain text",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub compress_logs: bool,
+
+    #[nixos(
+        description = "Keep the output of `nix flake show` of each commit in `paths.state_dir`, so a commit is not evaluated again, e.g. after a restart",
+        default = "true"
+    )]
+    #[serde(default = "default_eval_cache")]
+    pub eval_cache: bool,
+
+    #[nixos(
+        description = "Days after which a cached evaluation is deleted, by the time it was last used. 0 for no limit.",
+        default = "30"
+    )]
+    #[serde(default = "default_max_eval_cache_age_days")]
+    pub max_eval_cache_age_days: u64,
+
+    #[nixos(
+        description = "Bytes all cached evaluations may take together, beyond them the least recently used are deleted. 0 for no limit.",
+        default = "268435456"
+    )]
+    #[serde(default = "default_max_eval_cache_bytes")]
+    pub max_eval_cache_bytes: u64,
+
+    #[nixos(
+        description = "Seconds between checks that the output paths of successful builds are still in the store. Collected ones, e.g. by `nix-collect-garbage`, are shown as missing until they are built again. 0 to never check.",
+        default = "3600"
+    )]
+    #[serde(default = "default_result_check_interval_secs")]
+    pub result_check_interval_secs: u64,
+
+    #[nixos(
+        description = "Hours in which packages are built. Outside of them repos are still polled and evaluated, but the builds wait until the window opens. When not set, builds run at any time.",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub build_window: Option<BuildWindowOptions>,
+
+    #[nixos(
+        description = "Delete the checkouts of repos that were removed from the config when it is reloaded",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub delete_removed_checkouts: bool,
+
+    #[nixos(
+        description = "Reload the config file like on SIGHUP whenever it changes on disk",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub watch_config: bool,
+
+    #[nixos(
+        description = "Seconds to wait on SIGTERM or SIGINT for open requests and cancelled builds to finish before exiting anyway",
+        default = "30"
+    )]
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
+
+    #[nixos(
+        description = "Serve HTTPS using the given certificate and key. When not set, plain HTTP is used.",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub tls: Option<TlsOptions>,
+
+    #[nixos(
+        description = "Compress responses with gzip, brotli or zstd when the client supports it",
+        default = "true"
+    )]
+    #[serde(default = "default_compress_responses")]
+    pub compress_responses: bool,
+
+    #[nixos(
+        description = "Content type prefixes that are never compressed, e.g. already compressed archives or streams that must not be buffered",
+        default = "[\"application/x-nix-nar\" \"application/x-xz\" \"application/zstd\" \"application/gzip\" \"application/x-bzip2\" \"application/zip\" \"image/\" \"text/event-stream\"]"
+    )]
+    #[serde(default = "default_compress_exclude_content_types")]
+    pub compress_exclude_content_types: Vec<String>,
+
+    #[nixos(
+        description = "Origins allowed to access the API from a browser. Entries are `*`, exact origins or contain a single `*` wildcard. When empty, no CORS headers are sent.",
+        default = "[]",
+        example = "[\"http://localhost:8081\" \"https://*.example.com\"]"
+    )]
+    #[serde(default)]
+    pub cors_allowed_origins: Vec<String>,
+
+    #[nixos(
+        description = "Serve the build results as a nix binary cache, so other machines can use this server as a substituter. Disabled when not set.",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub binary_cache: Option<BinaryCacheOptions>,
+
+    #[nixos(
+        description = "Where successful builds are signed and pushed to",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub post_build: PostBuildOptions,
+
+    #[nixos(
+        description = "Largest store path in bytes that is served as a tar or zip archive. Bigger ones need `force=1` in the request. 0 disables the limit.",
+        default = "4294967296"
+    )]
+    #[serde(default = "default_archive_size_limit")]
+    pub archive_size_limit: u64,
+
+    #[nixos(
+        description = "Limits per client IP for expensive routes, requests above them get `429 Too Many Requests`",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub rate_limits: RateLimitOptions,
+
+    #[nixos(
+        description = "Logs of the server, the `RUST_LOG` environment variable overrides `level`",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub log: LogOptions,
+
+    #[nixos(description = "Log of the handled HTTP requests", default = "{}")]
+    #[serde(default)]
+    pub access_log: AccessLogOptions,
+
+    #[nixos(
+        description = "Secrets the webhooks forges send on pushes are checked with",
+        default = "{}"
+    )]
+    #[serde(default)]
+    pub webhooks: IncomingWebhookOptions,
+
+    #[nixos(description = "Where to report finished builds", default = "{}")]
+    #[serde(default)]
+    pub notifications: NotificationOptions,
+
+    /// Where the values of a config directory came from, a file or an environment
+    /// variable by option path like `listen.tcp.port` or `repos[2]`. Empty for a
+    /// single config file.
+    #[nixos(skip)]
+    #[serde(skip)]
+    pub sources: BTreeMap<String, String>,
+}
+
+impl AutoBuildOptions {
+    /// Address of the TCP listener, `host` and `port` unless `listen` is set
+    pub fn tcp_address(&self) -> Option<(String, u16)> {
+        match &self.listen {
+            None => Some((self.host.clone(), self.port)),
+            Some(listen) => listen.tcp.as_ref().map(|tcp| (tcp.host.clone(), tcp.port)),
+        }
+    }
+
+    /// `base_path` with a leading and without a trailing slash, empty at the root
+    pub fn route_prefix(&self) -> String {
+        let path = self.base_path.trim_matches('/');
+        if path.is_empty() {
+            String::new()
+        } else {
+            format!("/{}", path)
+        }
+    }
+
+    /// `paths.checkouts_dir`, or `repos` below `dir`
+    pub fn checkouts_dir(&self) -> PathBuf {
+        self.paths
+            .checkouts_dir
+            .clone()
+            .unwrap_or_else(|| self.dir.join("repos"))
+    }
+
+    /// `paths.logs_dir`, or `logs` below `dir`
+    pub fn logs_dir(&self) -> PathBuf {
+        self.paths
+            .logs_dir
+            .clone()
+            .unwrap_or_else(|| self.dir.join("logs"))
+    }
+
+    /// `paths.state_dir`, or `state` below `dir`
+    pub fn state_dir(&self) -> PathBuf {
+        self.paths
+            .state_dir
+            .clone()
+            .unwrap_or_else(|| self.dir.join("state"))
+    }
+
+    /// `paths.gc_roots_dir`, or `gcroots` below `dir`
+    pub fn gc_roots_dir(&self) -> PathBuf {
+        self.paths
+            .gc_roots_dir
+            .clone()
+            .unwrap_or_else(|| self.dir.join("gcroots"))
+    }
+}
+
+const DEFAULT_POLL_INTERVAL_SEC: u64 = 300;
+
+fn default_build_depth() -> u8 {
+    1
+}
+
+fn default_dir() -> PathBuf {
+    PathBuf::from("/var/lib/nix_autobuild")
+}
+
+fn default_max_commits_per_repo() -> usize {
+    100
+}
+
+fn default_max_commit_age_days() -> u64 {
+    30
+}
+
+fn default_max_log_age_days() -> u64 {
+    90
+}
+
+fn default_eval_cache() -> bool {
+    true
+}
+
+fn default_max_eval_cache_age_days() -> u64 {
+    30
+}
+
+fn default_max_eval_cache_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_result_check_interval_secs() -> u64 {
+    3600
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    30
+}
+
+fn default_compress_responses() -> bool {
+    true
+}
+
+fn default_compress_exclude_content_types() -> Vec<String> {
+    [
+        "application/x-nix-nar",
+        "application/x-xz",
+        "application/zstd",
+        "application/gzip",
+        "application/x-bzip2",
+        "application/zip",
+        "image/",
+        "text/event-stream",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+fn default_archive_size_limit() -> u64 {
+    4 * 1024 * 1024 * 1024
+}
+
+/// Where a secret is read from, only the place is part of the config
+#[derive(Deserialize, Serialize, Clone, Debug, Default, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct SecretSource {
+    #[nixos(
+        description = "File containing the secret, surrounding whitespace is ignored",
+        default = "null",
+        example = "\"/run/secrets/github\""
+    )]
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+
+    #[nixos(
+        description = "Environment variable containing the secret",
+        default = "null",
+        example = "\"GITHUB_CREDENTIALS\""
+    )]
+    #[serde(default)]
+    pub env: Option<String>,
+
+    #[nixos(
+        description = "Name of a credential passed by systemd with `LoadCredential=`, read from `$CREDENTIALS_DIRECTORY`",
+        default = "null",
+        example = "\"github\""
+    )]
+    #[serde(default)]
+    pub systemd_credential: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct PathOptions {
+    #[nixos(
+        description = "Checkouts of the repositories, `repos` below `dir` when not set",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/repos\""
+    )]
+    #[serde(default)]
+    pub checkouts_dir: Option<PathBuf>,
+
+    #[nixos(
+        description = "Build logs, one directory per repository and commit, `logs` below `dir` when not set",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/logs\""
+    )]
+    #[serde(default)]
+    pub logs_dir: Option<PathBuf>,
+
+    #[nixos(
+        description = "State kept across restarts, `state` below `dir` when not set",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/state\""
+    )]
+    #[serde(default)]
+    pub state_dir: Option<PathBuf>,
+
+    #[nixos(
+        description = "GC roots of build results, `gcroots` below `dir` when not set",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/gcroots\""
+    )]
+    #[serde(default)]
+    pub gc_roots_dir: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct StateOptions {
+    #[nixos(
+        description = "`sqlite` for a database with every status change, `json` for a snapshot of all repos written every `snapshot_interval_secs` and on shutdown, or `none` to keep the state in memory only",
+        default = "\"sqlite\""
+    )]
+    #[serde(default = "default_state_backend")]
+    pub backend: String,
+
+    #[nixos(
+        description = "Seconds between two snapshots with the `json` backend",
+        default = "60"
+    )]
+    #[serde(default = "default_snapshot_interval_secs")]
+    pub snapshot_interval_secs: u64,
+}
+
+impl Default for StateOptions {
+    fn default() -> Self {
+        StateOptions {
+            backend: default_state_backend(),
+            snapshot_interval_secs: default_snapshot_interval_secs(),
+        }
+    }
+}
+
+fn default_state_backend() -> String {
+    "sqlite".to_string()
+}
+
+fn default_snapshot_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct BuildWindowOptions {
+    #[nixos(
+        description = "When builds may start, as `HH:MM`",
+        example = "\"22:00\""
+    )]
+    pub start: String,
+
+    #[nixos(
+        description = "When the window closes, as `HH:MM`. Before `start` for a window across midnight. Builds running at that time finish.",
+        example = "\"06:00\""
+    )]
+    pub end: String,
+
+    #[nixos(
+        description = "Time zone of `start` and `end`, like `Europe/Berlin`. When not set, the local time of the server.",
+        default = "null",
+        example = "\"Europe/Berlin\""
+    )]
+    #[serde(default)]
+    pub timezone: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct ServerOptions {
+    #[nixos(
+        description = "Serve HTTP at all. When disabled the repos are still polled and built, and logs and notifications keep working, but nothing listens.",
+        default = "true"
+    )]
+    #[serde(default = "default_server_enabled")]
+    pub enabled: bool,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        ServerOptions {
+            enabled: default_server_enabled(),
+        }
+    }
+}
+
+fn default_server_enabled() -> bool {
+    true
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct ListenOptions {
+    #[nixos(description = "Listen on a TCP port", default = "null")]
+    #[serde(default)]
+    pub tcp: Option<TcpListenOptions>,
+
+    #[nixos(description = "Listen on a Unix domain socket", default = "null")]
+    #[serde(default)]
+    pub unix: Option<UnixSocketOptions>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct TcpListenOptions {
+    #[nixos(description = "Address to bind to", default = "\"127.0.0.1\"")]
+    #[serde(default = "default_tcp_host")]
+    pub host: String,
+
+    #[nixos(description = "Port to bind to", default = "8080")]
+    #[serde(default = "default_tcp_port")]
+    pub port: u16,
+}
+
+fn default_tcp_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_tcp_port() -> u16 {
+    8080
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct UnixSocketOptions {
+    #[nixos(
+        description = "Path of the socket, the parent directory is created and a stale socket removed",
+        example = "\"/run/nix_autobuild/http.sock\""
+    )]
+    pub path: PathBuf,
+
+    #[nixos(description = "Octal permissions of the socket", default = "\"660\"")]
+    #[serde(default = "default_socket_mode")]
+    pub mode: String,
+
+    #[nixos(
+        description = "User owning the socket, a name or numeric ID. Unchanged when not set.",
+        default = "null",
+        example = "\"nginx\""
+    )]
+    #[serde(default)]
+    pub user: Option<String>,
+
+    #[nixos(
+        description = "Group owning the socket, a name or numeric ID. Unchanged when not set.",
+        default = "null",
+        example = "\"nginx\""
+    )]
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+fn default_socket_mode() -> String {
+    "660".to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct TlsOptions {
+    #[nixos(
+        description = "Path to the PEM encoded certificate chain, leaf certificate first",
+        example = "\"/var/lib/acme/example.com/fullchain.pem\""
+    )]
+    pub cert_file: PathBuf,
+
+    #[nixos(
+        description = "Path to the PEM encoded private key matching the certificate",
+        example = "\"/var/lib/acme/example.com/key.pem\""
+    )]
+    pub key_file: PathBuf,
+
+    #[nixos(
+        description = "Reload the certificate and key from disk when the process receives SIGHUP",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub reload_on_sighup: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct BinaryCacheOptions {
+    #[nixos(
+        description = "Secret key created with `nix-store --generate-binary-cache-key` used to sign served paths. Paths are unsigned when not set.",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/cache-priv-key.pem\""
+    )]
+    #[serde(default)]
+    pub secret_key_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "The secret key from a file, an environment variable or a systemd credential, instead of `secret_key_file`",
+        default = "null",
+        example = "{ systemd_credential = \"cache-priv-key\"; }"
+    )]
+    #[serde(default)]
+    pub secret_key: Option<SecretSource>,
+
+    #[nixos(
+        description = "Compression of served NARs, either `none` or `xz`. `xz` requires the xz binary.",
+        default = "\"none\""
+    )]
+    #[serde(default = "default_nar_compression")]
+    pub compression: String,
+
+    #[nixos(
+        description = "Substituter priority announced to clients, lower values are preferred",
+        default = "40"
+    )]
+    #[serde(default = "default_cache_priority")]
+    pub priority: u32,
+
+    #[nixos(
+        description = "Serve every path of the local store instead of only the outputs of tracked builds and their dependencies",
+        default = "false"
+    )]
+    #[serde(default)]
+    pub expose_whole_store: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct PostBuildOptions {
+    #[nixos(
+        description = "Stores the out paths of every successful build are copied to with `nix copy --to`, store URIs like `s3://`, `ssh-ng://` or `file://` or absolute paths. The closure is copied, not only the out paths.",
+        default = "[]",
+        example = "[\"s3://nix-cache?region=eu-central-1\" \"ssh-ng://builder@cache.example.com\"]"
+    )]
+    #[serde(default)]
+    pub copy_to: Vec<String>,
+
+    #[nixos(
+        description = "Push successful builds to a Cachix cache with `cachix push`",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub cachix: Option<CachixOptions>,
+
+    #[nixos(
+        description = "Push successful builds to an Attic cache with `attic push`",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub attic: Option<AtticOptions>,
+
+    #[nixos(
+        description = "Secret key created with `nix-store --generate-binary-cache-key`. The closures of the out paths are signed with `nix store sign` before they are pushed, nothing is pushed when signing fails.",
+        default = "null",
+        example = "\"/var/lib/nix_autobuild/cache-priv-key.pem\""
+    )]
+    #[serde(default)]
+    pub sign_key_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "Seconds after which signing or one push is killed and counts as failed, 0 for no limit. Cancelling the build kills them too.",
+        default = "3600"
+    )]
+    #[serde(default = "default_push_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl Default for PostBuildOptions {
+    fn default() -> Self {
+        PostBuildOptions {
+            copy_to: Vec::new(),
+            cachix: None,
+            attic: None,
+            sign_key_file: None,
+            timeout_secs: default_push_timeout_secs(),
+        }
+    }
+}
+
+fn default_push_timeout_secs() -> u64 {
+    3600
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct CachixOptions {
+    #[nixos(description = "Name of the Cachix cache", example = "\"my-cache\"")]
+    pub cache: String,
+
+    #[nixos(
+        description = "Auth token of the cache from a file, an environment variable or a systemd credential. Without it `cachix` uses `CACHIX_AUTH_TOKEN` or its own config.",
+        default = "null",
+        example = "{ systemd_credential = \"cachix-token\"; }"
+    )]
+    #[serde(default)]
+    pub auth_token: Option<SecretSource>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct AtticOptions {
+    #[nixos(
+        description = "Cache to push to, `server:cache` or a cache on the default server. The server has to be set up with `attic login` for the user running nix_autobuild.",
+        example = "\"central:main\""
+    )]
+    pub cache: String,
+}
+
+fn default_nar_compression() -> String {
+    "none".to_string()
+}
+
+fn default_cache_priority() -> u32 {
+    40
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct RateLimitOptions {
+    #[nixos(
+        description = "Requests per second to `/store` and `/nix/store`, 0 disables the limit",
+        default = "5"
+    )]
+    #[serde(default = "default_store_rate")]
+    pub store_per_second: u32,
+
+    #[nixos(
+        description = "Requests to `/store` and `/nix/store` allowed at once before the rate applies",
+        default = "20"
+    )]
+    #[serde(default = "default_store_burst")]
+    pub store_burst: u32,
+
+    #[nixos(
+        description = "Requests per second that trigger work, e.g. polling or cancelling, 0 disables the limit",
+        default = "1"
+    )]
+    #[serde(default = "default_actions_rate")]
+    pub actions_per_second: u32,
+
+    #[nixos(
+        description = "Requests that trigger work allowed at once before the rate applies",
+        default = "5"
+    )]
+    #[serde(default = "default_actions_burst")]
+    pub actions_burst: u32,
+}
+
+impl Default for RateLimitOptions {
+    fn default() -> Self {
+        RateLimitOptions {
+            store_per_second: default_store_rate(),
+            store_burst: default_store_burst(),
+            actions_per_second: default_actions_rate(),
+            actions_burst: default_actions_burst(),
+        }
+    }
+}
+
+fn default_store_rate() -> u32 {
+    5
+}
+
+fn default_store_burst() -> u32 {
+    20
+}
+
+fn default_actions_rate() -> u32 {
+    1
+}
+
+fn default_actions_burst() -> u32 {
+    5
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct LogOptions {
+    #[nixos(
+        description = "One of `off`, `error`, `warn`, `info` (also builds, clones and new commits), `debug` (also requests, polls and pulls) or `trace`",
+        default = "\"info\""
+    )]
+    #[serde(default = "default_log_level")]
+    pub level: String,
+
+    #[nixos(
+        description = "`text` for one readable line per event, `json` for one JSON object per line, e.g. for Loki or Elasticsearch. The access log uses it as well.",
+        default = "\"text\""
+    )]
+    #[serde(default = "default_log_format")]
+    pub format: String,
+
+    #[nixos(
+        description = "File the logs are appended to. When not set, they are written to stdout.",
+        default = "null",
+        example = "\"/var/log/nix_autobuild/server.log\""
+    )]
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        LogOptions {
+            level: default_log_level(),
+            format: default_log_format(),
+            file: None,
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_format() -> String {
+    "text".to_string()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct AccessLogOptions {
+    #[nixos(
+        description = "One of `off`, `info` (method, path, status, latency, response size, client IP and served file) or `debug` (also the request headers)",
+        default = "\"info\""
+    )]
+    #[serde(default = "default_access_log_level")]
+    pub level: String,
+
+    #[nixos(
+        description = "File the access log is appended to. When not set, it is written to stdout, or to `log.file` if that is set.",
+        default = "null",
+        example = "\"/var/log/nix_autobuild/access.log\""
+    )]
+    #[serde(default)]
+    pub file: Option<PathBuf>,
+
+    #[nixos(
+        description = "Query parameters whose values are replaced by `[redacted]`, matched case-insensitively as parts of the name",
+        default = "[\"token\" \"secret\" \"password\" \"key\" \"auth\" \"signature\" \"session\"]"
+    )]
+    #[serde(default = "default_redacted_query_parameters")]
+    pub redact_query_parameters: Vec<String>,
+}
+
+impl Default for AccessLogOptions {
+    fn default() -> Self {
+        AccessLogOptions {
+            level: default_access_log_level(),
+            file: None,
+            redact_query_parameters: default_redacted_query_parameters(),
+        }
+    }
+}
+
+fn default_access_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_redacted_query_parameters() -> Vec<String> {
+    [
+        "token",
+        "secret",
+        "password",
+        "key",
+        "auth",
+        "signature",
+        "session",
+    ]
+    .map(String::from)
+    .to_vec()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct IncomingWebhookOptions {
+    #[nixos(
+        description = "Secret GitHub signs its webhook deliveries with, enables `POST /api/v1/hooks/github`",
+        default = "null",
+        example = "{ file = \"/run/secrets/github-webhook\"; }"
+    )]
+    #[serde(default)]
+    pub github_secret: Option<SecretSource>,
+
+    #[nixos(
+        description = "File containing the secret GitHub signs its webhook deliveries with",
+        default = "null",
+        example = "\"/run/secrets/github-webhook\""
+    )]
+    #[serde(default)]
+    pub github_secret_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "Secret token GitLab sends with its webhooks, enables `POST /api/v1/hooks/gitlab`",
+        default = "null",
+        example = "{ file = \"/run/secrets/gitlab-webhook\"; }"
+    )]
+    #[serde(default)]
+    pub gitlab_token: Option<SecretSource>,
+
+    #[nixos(
+        description = "File containing the secret token GitLab sends with its webhooks",
+        default = "null",
+        example = "\"/run/secrets/gitlab-webhook\""
+    )]
+    #[serde(default)]
+    pub gitlab_token_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "Secret other forges sign their webhooks with like GitHub does, enables `POST /api/v1/hooks/generic`",
+        default = "null",
+        example = "{ env = \"WEBHOOK_SECRET\"; }"
+    )]
+    #[serde(default)]
+    pub generic_secret: Option<SecretSource>,
+
+    #[nixos(
+        description = "File containing the secret of webhooks from other forges",
+        default = "null",
+        example = "\"/run/secrets/webhook\""
+    )]
+    #[serde(default)]
+    pub generic_secret_file: Option<PathBuf>,
+}
+
+/// Event names the `events` of notifications select from
+pub const NOTIFICATION_EVENTS: [&str; 5] = [
+    "package_success",
+    "package_failed",
+    "package_recovered",
+    "commit_finished",
+    "commit_failed",
+];
+
+#[derive(Deserialize, Serialize, Clone, Debug, Default, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct NotificationOptions {
+    #[nixos(
+        description = "URLs that get a JSON `POST` for every selected event",
+        default = "[]"
+    )]
+    #[serde(default)]
+    pub webhooks: Vec<WebhookOptions>,
+
+    #[nixos(
+        description = "Matrix rooms that get a message when builds fail",
+        default = "[]"
+    )]
+    #[serde(default)]
+    pub matrix: Vec<MatrixOptions>,
+
+    #[nixos(
+        description = "Slack incoming webhooks that get a message for every selected event",
+        default = "[]"
+    )]
+    #[serde(default)]
+    pub slack: Vec<ChatWebhookOptions>,
+
+    #[nixos(
+        description = "Discord webhooks that get a message for every selected event",
+        default = "[]"
+    )]
+    #[serde(default)]
+    pub discord: Vec<ChatWebhookOptions>,
+
+    #[nixos(
+        description = "Public URL of the frontend, chat messages link to the package on it when set",
+        default = "null",
+        example = "\"https://ci.example.com/\""
+    )]
+    #[serde(default)]
+    pub dashboard_url: Option<String>,
+
+    #[nixos(
+        description = "Send summaries of failed commits by email",
+        default = "null"
+    )]
+    #[serde(default)]
+    pub email: Option<EmailOptions>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct WebhookOptions {
+    #[nixos(
+        description = "URL the notifications are posted to",
+        example = "\"https://example.com/hooks/nix_autobuild\""
+    )]
+    pub url: String,
+
+    #[nixos(
+        description = "Events to send, any of `package_success`, `package_failed`, `package_recovered` (a package builds again after failing on the previous commit) and `commit_finished`",
+        default = "[\"package_success\" \"package_failed\"]"
+    )]
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<String>,
+
+    #[nixos(
+        description = "File containing a secret used to sign the payload with HMAC-SHA256. The signature is sent as `sha256=<hex>` in the `X-Autobuild-Signature-256` header. Payloads are unsigned when not set.",
+        default = "null",
+        example = "\"/run/secrets/webhook\""
+    )]
+    #[serde(default)]
+    pub secret_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "The signing secret from a file, an environment variable or a systemd credential, instead of `secret_file`",
+        default = "null",
+        example = "{ env = \"WEBHOOK_SECRET\"; }"
+    )]
+    #[serde(default)]
+    pub secret: Option<SecretSource>,
+}
+
+fn default_webhook_events() -> Vec<String> {
+    ["package_success", "package_failed"]
+        .map(String::from)
+        .to_vec()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct ChatWebhookOptions {
+    #[nixos(
+        description = "Webhook URL from the channel settings. It contains the token and ends up in the nix store, prefer `url_secret`. Either it or `url_secret` is required.",
+        default = "null",
+        example = "\"https://hooks.slack.com/services/T000/B000/XXXX\""
+    )]
+    #[serde(default)]
+    pub url: Option<String>,
+
+    #[nixos(
+        description = "The webhook URL from a file, an environment variable or a systemd credential, instead of `url`",
+        default = "null",
+        example = "{ file = \"/run/secrets/slack-webhook\"; }"
+    )]
+    #[serde(default)]
+    pub url_secret: Option<SecretSource>,
+
+    #[nixos(
+        description = "Events to send, any of `package_failed`, `package_recovered` (a package builds again after failing on the previous commit), `package_success` and `commit_finished`",
+        default = "[\"package_failed\" \"package_recovered\"]"
+    )]
+    #[serde(default = "default_chat_events")]
+    pub events: Vec<String>,
+}
+
+fn default_chat_events() -> Vec<String> {
+    ["package_failed", "package_recovered"]
+        .map(String::from)
+        .to_vec()
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct EmailOptions {
+    #[nixos(description = "SMTP server", example = "\"smtp.example.com\"")]
+    pub smtp_host: String,
+
+    #[nixos(
+        description = "SMTP port, usually 587 for `starttls` and 465 for `tls`",
+        default = "587"
+    )]
+    #[serde(default = "default_smtp_port")]
+    pub smtp_port: u16,
+
+    #[nixos(
+        description = "`starttls`, `tls` or `none`. `none` sends the credentials unencrypted and is only meant for a relay on localhost.",
+        default = "\"starttls\""
+    )]
+    #[serde(default = "default_smtp_tls")]
+    pub tls: String,
+
+    #[nixos(
+        description = "File with a single line of SMTP credentials in the format `username:password`. No authentication when not set.",
+        default = "null",
+        example = "\"/run/secrets/smtp\""
+    )]
+    #[serde(default)]
+    pub credentials_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "SMTP credentials in the format `username:password` from a file, an environment variable or a systemd credential, instead of `credentials_file`",
+        default = "null",
+        example = "{ env = \"SMTP_CREDENTIALS\"; }"
+    )]
+    #[serde(default)]
+    pub credentials: Option<SecretSource>,
+
+    #[nixos(
+        description = "Sender address",
+        example = "\"nix_autobuild <ci@example.com>\""
+    )]
+    pub from: String,
+
+    #[nixos(
+        description = "Recipients of repositories without `email_recipients`",
+        default = "[]"
+    )]
+    #[serde(default)]
+    pub to: Vec<String>,
+
+    #[nixos(
+        description = "Events to send, any of `commit_failed` (every package of a commit finished and at least one failed), `commit_finished`, `package_failed`, `package_recovered` and `package_success`",
+        default = "[\"commit_failed\"]"
+    )]
+    #[serde(default = "default_email_events")]
+    pub events: Vec<String>,
+
+    #[nixos(
+        description = "Seconds to collect notifications of the same commit before sending a single email",
+        default = "300"
+    )]
+    #[serde(default = "default_email_quiet_secs")]
+    pub quiet_secs: u64,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_smtp_tls() -> String {
+    "starttls".to_string()
+}
+
+fn default_email_events() -> Vec<String> {
+    vec!["commit_failed".to_string()]
+}
+
+fn default_email_quiet_secs() -> u64 {
+    300
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, NixosType)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(JsonSchema))]
+pub struct MatrixOptions {
+    #[nixos(
+        description = "Base URL of the homeserver",
+        example = "\"https://matrix.org\""
+    )]
+    pub homeserver: String,
+
+    #[nixos(
+        description = "File containing the access token of the account sending the messages. Either it or `access_token` is required.",
+        default = "null",
+        example = "\"/run/secrets/matrix-token\""
+    )]
+    #[serde(default)]
+    pub access_token_file: Option<PathBuf>,
+
+    #[nixos(
+        description = "The access token from a file, an environment variable or a systemd credential, instead of `access_token_file`",
+        default = "null",
+        example = "{ systemd_credential = \"matrix-token\"; }"
+    )]
+    #[serde(default)]
+    pub access_token: Option<SecretSource>,
+
+    #[nixos(
+        description = "ID of the room the messages are sent to, the account has to be joined already",
+        example = "\"!abcdefghijklmnop:matrix.org\""
+    )]
+    pub room_id: String,
+
+    #[nixos(
+        description = "Also send a message when a package builds again after failing on the previous commit",
+        default = "true"
+    )]
+    #[serde(default = "default_matrix_recoveries")]
+    pub recoveries: bool,
+}
+
+fn default_matrix_recoveries() -> bool {
+    true
 }
 
 pub const ARCHITECTURES: [&str; 24] = [
@@ -118,5 +1390,5 @@ pub const ARCHITECTURES: [&str; 24] = [
 
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RepoList(pub VecArcWrapper<RepoInfo>);
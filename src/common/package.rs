@@ -1,12 +1,14 @@
 use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::commit::CommitInfo;
 use crate::serialize::{ArcWrapper, RwLockWrapper};
 use serde::{Deserialize, Serialize};
 
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
-#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Clone))]
-#[derive(Debug)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
 
 pub enum PackageEnum {
     Derivation(ArcWrapper<Package>),
@@ -17,25 +19,375 @@ pub enum PackageEnum {
 unsafe impl Send for PackageEnum {}
 unsafe impl Sync for PackageEnum {}
 
+impl PackageEnum {
+    pub fn status(&self) -> PackageBuildStatus {
+        match self {
+            PackageEnum::Derivation(pkg) => {
+                BuildAttempt::current_status(pkg.0.status.get(), &pkg.0.attempts)
+            }
+            PackageEnum::NixosConfig(pkg) => {
+                BuildAttempt::current_status(pkg.0.status.get(), &pkg.0.attempts)
+            }
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            PackageEnum::Derivation(pkg) => &pkg.0.path,
+            PackageEnum::NixosConfig(pkg) => &pkg.0.path,
+        }
+    }
+
+    pub fn flake_url(&self) -> &str {
+        match self {
+            PackageEnum::Derivation(pkg) => &pkg.0.flake_url,
+            PackageEnum::NixosConfig(pkg) => &pkg.0.flake_url,
+        }
+    }
+
+    pub fn times(&self) -> BuildTimes {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.times.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.times.get(),
+        }
+    }
+
+    pub fn closure_size(&self) -> Option<u64> {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.closure_size.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.closure_size.get(),
+        }
+    }
+
+    pub fn expected_secs(&self) -> Option<u64> {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.expected_secs.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.expected_secs.get(),
+        }
+    }
+
+    pub fn result_missing(&self) -> bool {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.result_missing.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.result_missing.get(),
+        }
+    }
+
+    pub fn pushes(&self) -> Vec<PushStatus> {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.pushes.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.pushes.get(),
+        }
+    }
+
+    pub fn attempts(&self) -> Vec<BuildAttempt> {
+        match self {
+            PackageEnum::Derivation(pkg) => pkg.0.attempts.get(),
+            PackageEnum::NixosConfig(pkg) => pkg.0.attempts.get(),
+        }
+    }
+}
+
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Clone))]
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub enum PackageBuildStatus {
     Idle,
     #[cfg(target_arch = "wasm32")]
     UnsupportedArchitecture(String),
     #[cfg(not(target_arch = "wasm32"))]
     UnsupportedArchitecture(&'static str),
+    /// outside of the build window
+    WaitingForWindow,
     WaitingForBuild,
     Building,
     Success(String),
     Failed(String),
+    Cancelled,
+    /// evaluated with `dry_run`, nothing was built
+    DryRun {
+        to_build: usize,
+        to_substitute: usize,
+    },
+}
+
+impl PackageBuildStatus {
+    /// The build ended and the status will not change anymore
+    pub fn is_finished(&self) -> bool {
+        !matches!(
+            self,
+            PackageBuildStatus::Idle
+                | PackageBuildStatus::WaitingForWindow
+                | PackageBuildStatus::WaitingForBuild
+                | PackageBuildStatus::Building
+        )
+    }
+
+    /// Built or being built, the status of an attempt rather than one between them
+    fn is_attempt(&self) -> bool {
+        !matches!(
+            self,
+            PackageBuildStatus::Idle
+                | PackageBuildStatus::UnsupportedArchitecture(_)
+                | PackageBuildStatus::WaitingForWindow
+                | PackageBuildStatus::WaitingForBuild
+        )
+    }
+}
+
+/// One of [`crate::ARCHITECTURES`], `unknown` for any other
+#[cfg(not(target_arch = "wasm32"))]
+pub fn static_arch(arch: &str) -> &'static str {
+    crate::ARCHITECTURES
+        .into_iter()
+        .find(|known| *known == arch)
+        .unwrap_or("unknown")
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de> Deserialize<'de> for PackageBuildStatus {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        /// As serialized, the architecture is looked up in [`crate::ARCHITECTURES`]
+        #[derive(Deserialize)]
+        enum Serialized {
+            Idle,
+            UnsupportedArchitecture(String),
+            WaitingForWindow,
+            WaitingForBuild,
+            Building,
+            Success(String),
+            Failed(String),
+            Cancelled,
+            DryRun {
+                to_build: usize,
+                to_substitute: usize,
+            },
+        }
+        Ok(match Serialized::deserialize(deserializer)? {
+            Serialized::Idle => PackageBuildStatus::Idle,
+            Serialized::UnsupportedArchitecture(arch) => {
+                PackageBuildStatus::UnsupportedArchitecture(static_arch(&arch))
+            }
+            Serialized::WaitingForWindow => PackageBuildStatus::WaitingForWindow,
+            Serialized::WaitingForBuild => PackageBuildStatus::WaitingForBuild,
+            Serialized::Building => PackageBuildStatus::Building,
+            Serialized::Success(out_paths) => PackageBuildStatus::Success(out_paths),
+            Serialized::Failed(error) => PackageBuildStatus::Failed(error),
+            Serialized::Cancelled => PackageBuildStatus::Cancelled,
+            Serialized::DryRun {
+                to_build,
+                to_substitute,
+            } => PackageBuildStatus::DryRun {
+                to_build,
+                to_substitute,
+            },
+        })
+    }
 }
 
 unsafe impl Send for PackageBuildStatus {}
 unsafe impl Sync for PackageBuildStatus {}
 
+/// When the last build of a package started and finished, unix timestamps in
+/// seconds
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BuildTimes {
+    pub started: Option<i64>,
+    pub finished: Option<i64>,
+}
+
+impl BuildTimes {
+    /// Times after the package changed to `status` at `now`
+    pub fn update(self, status: &PackageBuildStatus, now: i64) -> Self {
+        match status {
+            // a build waiting for a slot starts again once it gets one
+            PackageBuildStatus::Building => BuildTimes {
+                started: Some(now),
+                finished: None,
+            },
+            PackageBuildStatus::Idle
+            | PackageBuildStatus::WaitingForWindow
+            | PackageBuildStatus::WaitingForBuild => BuildTimes::default(),
+            _ => BuildTimes {
+                finished: Some(now),
+                ..self
+            },
+        }
+    }
+}
+
+/// Attempts kept per package, older ones are dropped
+pub const MAX_ATTEMPTS: usize = 10;
+
+/// `log_ref` of an attempt whose log was deleted by the log retention
+pub const EXPIRED_LOG: &str = "expired";
+
+/// One build of a package, e.g. the first one and each rebuild after a failure
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildAttempt {
+    /// counting from 1, the dropped attempts included
+    pub number: usize,
+    pub started: Option<i64>,
+    pub finished: Option<i64>,
+    pub status: PackageBuildStatus,
+    /// file name of the build log, next to the logs of the other packages of the
+    /// commit. `None` if the build ended before nix ran, e.g. cancelled in the queue.
+    pub log_ref: Option<String>,
+    /// id of the [`crate::builder::BuilderInfo`] it was built on, `None` if it never
+    /// ran or ran before the builders were recorded
+    #[serde(default)]
+    pub builder: Option<String>,
+}
+
+impl BuildAttempt {
+    /// The current status of a package last set to `status`: that of the latest of
+    /// its `attempts` while a build runs or after it ended, `status` while the
+    /// package waits for a build or never had one
+    pub fn current_status(
+        status: PackageBuildStatus,
+        attempts: &RwLockWrapper<Vec<BuildAttempt>>,
+    ) -> PackageBuildStatus {
+        if !status.is_attempt() {
+            return status;
+        }
+        attempts
+            .get()
+            .pop()
+            .map_or(status, |attempt| attempt.status)
+    }
+
+    /// The log was written but is deleted by now
+    pub fn log_expired(&self) -> bool {
+        self.log_ref.as_deref() == Some(EXPIRED_LOG)
+    }
+
+    /// Add the change of a package to `status` with `times` to its `attempts`, the
+    /// latest last. A build that starts is a new attempt with its log in
+    /// `log_ref(number)`, the status it ends with finishes the attempt. The current
+    /// status of a package that built at least once is that of its latest attempt.
+    pub fn record(
+        attempts: &mut Vec<BuildAttempt>,
+        status: &PackageBuildStatus,
+        times: BuildTimes,
+        log_ref: impl FnOnce(usize) -> String,
+    ) {
+        let number = attempts.last().map_or(1, |attempt| attempt.number + 1);
+        match status {
+            PackageBuildStatus::Building => attempts.push(BuildAttempt {
+                number,
+                started: times.started,
+                finished: None,
+                status: status.clone(),
+                log_ref: Some(log_ref(number)),
+                builder: None,
+            }),
+            PackageBuildStatus::Success(_)
+            | PackageBuildStatus::Failed(_)
+            | PackageBuildStatus::Cancelled
+            | PackageBuildStatus::DryRun { .. } => match attempts.last_mut() {
+                Some(attempt) if attempt.finished.is_none() => {
+                    attempt.status = status.clone();
+                    attempt.finished = times.finished;
+                }
+                _ => attempts.push(BuildAttempt {
+                    number,
+                    started: None,
+                    finished: times.finished,
+                    status: status.clone(),
+                    log_ref: None,
+                    builder: None,
+                }),
+            },
+            _ => {}
+        }
+        let dropped = attempts.len().saturating_sub(MAX_ATTEMPTS);
+        attempts.drain(..dropped);
+    }
+}
+
+/// Combined status of a group of packages, e.g. all packages of one commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AggregateStatus {
+    Success,
+    Failed,
+    Building,
+}
+
+impl AggregateStatus {
+    /// Failed if any package failed, success if all packages succeeded and building
+    /// otherwise. Packages skipped because of an unsupported architecture, cancelled
+    /// or only dry run are ignored, `None` is returned if no package is left to judge.
+    pub fn from_statuses<'a>(
+        statuses: impl IntoIterator<Item = &'a PackageBuildStatus>,
+    ) -> Option<Self> {
+        let mut result = None;
+        for status in statuses {
+            match status {
+                PackageBuildStatus::UnsupportedArchitecture(_)
+                | PackageBuildStatus::Cancelled
+                | PackageBuildStatus::DryRun { .. } => {}
+                PackageBuildStatus::Failed(_) => return Some(AggregateStatus::Failed),
+                PackageBuildStatus::Success(_) => {
+                    result.get_or_insert(AggregateStatus::Success);
+                }
+                _ => result = Some(AggregateStatus::Building),
+            }
+        }
+        result
+    }
+}
+
+/// One finished build of a package, newest first in its history
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildRecord {
+    pub commit: String,
+    pub started: Option<i64>,
+    pub finished: i64,
+    /// `None` if the start is unknown
+    pub duration_secs: Option<i64>,
+    pub outcome: BuildOutcome,
+    /// of a successful build
+    pub out_paths: Option<String>,
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildOutcome {
+    Success,
+    Failed,
+    Cancelled,
+}
+
+/// A successful build copied to one `post_build` target
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct PushStatus {
+    /// the store URI, `cachix:<cache>` or `attic:<cache>`
+    pub target: String,
+    pub state: PushState,
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub enum PushState {
+    Pushing,
+    Pushed,
+    Failed(String),
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
 #[derive(Debug)]
 pub struct Package {
@@ -51,10 +403,27 @@ pub struct Package {
 
     pub flake_url: String,
     pub status: RwLockWrapper<PackageBuildStatus>,
+    pub times: RwLockWrapper<BuildTimes>,
+    /// bytes of the closure of a successful build, `None` if unknown
+    pub closure_size: RwLockWrapper<Option<u64>>,
+    /// seconds a successful build usually takes while one waits or runs, `None`
+    /// otherwise or before the first one
+    pub expected_secs: RwLockWrapper<Option<u64>>,
+    /// the output paths of a successful build were garbage collected, the status
+    /// still names them
+    pub result_missing: RwLockWrapper<bool>,
+    /// of the `post_build` targets, empty without any or before a successful build
+    pub pushes: RwLockWrapper<Vec<PushStatus>>,
+    /// the latest [`MAX_ATTEMPTS`] builds, oldest first
+    pub attempts: RwLockWrapper<Vec<BuildAttempt>>,
 
     #[cfg(not(target_arch = "wasm32"))]
     #[serde(skip)]
     pub commit: Arc<CommitInfo>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    pub control: BuildControl,
 }
 impl Package {
     pub fn get_no_arch_name(&self) -> String {
@@ -65,7 +434,7 @@ impl Package {
 unsafe impl Send for Package {}
 unsafe impl Sync for Package {}
 
-#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
 #[derive(Debug)]
 pub struct NixosConfigPackage {
@@ -73,7 +442,59 @@ pub struct NixosConfigPackage {
     pub pkg_type: String,
     pub flake_url: String,
     pub status: RwLockWrapper<PackageBuildStatus>,
+    pub times: RwLockWrapper<BuildTimes>,
+    /// bytes of the closure of a successful build, `None` if unknown
+    pub closure_size: RwLockWrapper<Option<u64>>,
+    /// seconds a successful build usually takes while one waits or runs, `None`
+    /// otherwise or before the first one
+    pub expected_secs: RwLockWrapper<Option<u64>>,
+    /// the output paths of a successful build were garbage collected, the status
+    /// still names them
+    pub result_missing: RwLockWrapper<bool>,
+    /// of the `post_build` targets, empty without any or before a successful build
+    pub pushes: RwLockWrapper<Vec<PushStatus>>,
+    /// the latest [`MAX_ATTEMPTS`] builds, oldest first
+    pub attempts: RwLockWrapper<Vec<BuildAttempt>>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    pub commit: Arc<CommitInfo>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    pub control: BuildControl,
 }
 
 unsafe impl Send for NixosConfigPackage {}
 unsafe impl Sync for NixosConfigPackage {}
+
+/// Cancellation flag of a single build, the build thread checks it while waiting for
+/// a build slot and while nix is running
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+pub struct BuildControl {
+    cancelled: AtomicBool,
+    /// the build does not wait for the build window
+    forced: AtomicBool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BuildControl {
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Allow a finished build to run again, inside the build window unless `forced`
+    pub fn reset(&self, forced: bool) {
+        self.cancelled.store(false, Ordering::SeqCst);
+        self.forced.store(forced, Ordering::SeqCst);
+    }
+
+    pub fn is_forced(&self) -> bool {
+        self.forced.load(Ordering::SeqCst)
+    }
+}
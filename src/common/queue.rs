@@ -0,0 +1,26 @@
+#[cfg(target_arch = "wasm32")]
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use serde::Serialize;
+
+/// Answer of `/queue`, the jobs waiting for one of the build slots in the order
+/// they get one
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone, Default)]
+pub struct BuildQueue {
+    pub slots: usize,
+    /// slots in use
+    pub running: usize,
+    pub waiting: Vec<QueueEntry>,
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub repo: String,
+    pub commit: String,
+    /// `None` while listing the packages of the commit
+    pub path: Option<String>,
+}
@@ -2,22 +2,33 @@
 use serde::Deserialize;
 use serde::Serialize;
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     path::PathBuf,
     sync::{Arc, RwLock},
 };
+#[cfg(not(target_arch = "wasm32"))]
+use std::{
+    sync::{
+        Condvar, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::package::BuildControl;
 use crate::serialize::RwLockHashMapArc;
 use crate::{AutoBuildOptions, Repo};
 use crate::{
-    common::commit::{CommitInfo, RepoStatus},
+    common::commit::{CommitBuildStatus, CommitInfo, RepoStatus},
+    package::AggregateStatus,
     serialize::RwLockWrapper,
 };
 
 unsafe impl Send for RepoStatus {}
 unsafe impl Sync for RepoStatus {}
 
-#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
 #[derive(Debug)]
 
@@ -31,11 +42,230 @@ pub struct RepoInfo {
 
     pub status: RwLockWrapper<RepoStatus>,
 
+    /// the latest polls of the remote, oldest first
+    pub poll_history: RwLockWrapper<VecDeque<PollEvent>>,
+
+    /// unix seconds of the next scheduled poll, while the poller waits for it
+    pub next_poll: RwLockWrapper<Option<i64>>,
+
+    /// the architectures of the repo, or the global ones when it has none
+    pub supported_architectures: Vec<String>,
+
+    /// serialized as the number of builds of the repo in flight
+    pub running_builds: BuildSlots,
+
     #[cfg(not(target_arch = "wasm32"))]
     #[serde(skip)]
     pub settings: Arc<AutoBuildOptions>,
 
     #[cfg(not(target_arch = "wasm32"))]
     #[serde(skip)]
-    pub credentials: Option<String>,
+    pub credentials: Option<crate::backend::secrets::Secret>,
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[serde(skip)]
+    pub poll_trigger: PollTrigger,
+}
+
+impl RepoInfo {
+    /// Aggregate status of the newest commit on a branch, shared by the badges and
+    /// the dashboard so both agree
+    pub fn branch_status(&self, branch: &str) -> Option<AggregateStatus> {
+        let tip = self
+            .branch_commit_hashes
+            .get(branch)?
+            .get()
+            .first()?
+            .clone();
+        self.commits.with(&tip, |commit| {
+            let statuses = commit
+                .packages
+                .get()
+                .iter()
+                .map(|pkg| pkg.status())
+                .collect::<Vec<_>>();
+            AggregateStatus::from_statuses(&statuses).or_else(|| {
+                // no packages yet while the flake is still being evaluated
+                match commit.status.get() {
+                    CommitBuildStatus::GettingPackages => Some(AggregateStatus::Building),
+                    CommitBuildStatus::Idle => None,
+                }
+            })
+        })?
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub struct PollEvent {
+    pub unix_secs: i64,
+    pub outcome: PollOutcome,
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, PartialEq))]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Serialize))]
+#[derive(Debug, Clone)]
+pub enum PollOutcome {
+    Unchanged,
+    /// a branch moved
+    Changed,
+    Failed(String),
+}
+
+/// Lets other threads cut the wait between two polls short
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+pub struct PollTrigger {
+    requested: Mutex<bool>,
+    condvar: Condvar,
+    /// the repo is no longer tracked, or tracked by a new poller
+    stopped: AtomicBool,
+    /// the next poll evaluates without the cached evaluations
+    force_eval: AtomicBool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl PollTrigger {
+    /// Wake the waiting thread, or make its next wait return immediately
+    pub fn trigger(&self) {
+        *self.requested.lock().unwrap_or_else(|p| p.into_inner()) = true;
+        self.condvar.notify_all();
+    }
+
+    /// Block until `timeout` passed or a poll was requested
+    pub fn wait(&self, timeout: Duration) {
+        let requested = self.requested.lock().unwrap_or_else(|p| p.into_inner());
+        let (mut requested, _) = self
+            .condvar
+            .wait_timeout_while(requested, timeout, |requested| !*requested)
+            .unwrap_or_else(|p| p.into_inner());
+        *requested = false;
+    }
+
+    /// Like `trigger`, the poll evaluates the commits again without the cache
+    pub fn trigger_eval(&self) {
+        self.force_eval.store(true, Ordering::SeqCst);
+        self.trigger();
+    }
+
+    /// A poll from `trigger_eval` waits, without taking it
+    pub fn eval_requested(&self) -> bool {
+        self.force_eval.load(Ordering::SeqCst)
+    }
+
+    /// Whether the poll about to start was requested by `trigger_eval`
+    pub fn take_eval_request(&self) -> bool {
+        self.force_eval.swap(false, Ordering::SeqCst)
+    }
+
+    /// Make the polling thread exit after its current poll
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.trigger();
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// Caps the builds of one repo running at the same time, taken before a slot of the
+/// global build semaphore so a repo with many packages leaves slots for the others
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+pub struct BuildSlots {
+    /// `None` for no limit
+    limit: Option<usize>,
+    /// shared with the repo before a reload, see `take_over`
+    counter: RwLock<Arc<SlotCounter>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Default)]
+struct SlotCounter {
+    running: Mutex<usize>,
+    condvar: Condvar,
+}
+
+/// How often a build waiting for a slot of its repo checks whether it was cancelled
+#[cfg(not(target_arch = "wasm32"))]
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+#[cfg(target_arch = "wasm32")]
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct BuildSlots(pub usize);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl BuildSlots {
+    /// `0` and `None` are unlimited
+    pub fn new(limit: Option<usize>) -> Self {
+        BuildSlots {
+            limit: limit.filter(|limit| *limit > 0),
+            ..Default::default()
+        }
+    }
+
+    fn counter(&self) -> Arc<SlotCounter> {
+        self.counter
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .clone()
+    }
+
+    /// Block until the repo has a slot left, it is given back when the guard drops.
+    /// `None` once the build is cancelled while it waits.
+    pub fn acquire(&self, control: &BuildControl) -> Option<BuildSlot> {
+        let counter = self.counter();
+        let mut running = counter.running.lock().unwrap_or_else(|p| p.into_inner());
+        while self.limit.is_some_and(|limit| *running >= limit) {
+            if control.is_cancelled() {
+                return None;
+            }
+            running = counter
+                .condvar
+                .wait_timeout(running, CANCEL_CHECK_INTERVAL)
+                .unwrap_or_else(|p| p.into_inner())
+                .0;
+        }
+        *running += 1;
+        drop(running);
+        Some(BuildSlot(counter))
+    }
+
+    pub fn running(&self) -> usize {
+        *self
+            .counter()
+            .running
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+    }
+
+    /// Count the builds of `old`, the slots of the same repo before a reload, along
+    /// with the new ones, so the limit also holds while they finish
+    pub fn take_over(&self, old: &BuildSlots) {
+        *self.counter.write().unwrap_or_else(|p| p.into_inner()) = old.counter();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub struct BuildSlot(Arc<SlotCounter>);
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for BuildSlot {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap_or_else(|p| p.into_inner()) -= 1;
+        self.0.condvar.notify_one();
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Serialize for BuildSlots {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u64(self.running() as u64)
+    }
 }
@@ -1,14 +1,14 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::hash::Hash;
 use std::{
     collections::{HashMap, HashSet},
     sync::{Arc, RwLock},
 };
 
-#[cfg(target_arch = "wasm32")]
-use serde::Deserialize;
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 
 // Newtype for Arc<T>
-#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
 #[derive(Debug)]
 pub struct ArcWrapper<T>(
     #[cfg(target_arch = "wasm32")] pub T,
@@ -50,14 +50,31 @@ impl<T: Serialize> Serialize for ArcWrapper<T> {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for ArcWrapper<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(|value| Self(Arc::new(value)))
+    }
+}
+
 // Newtype for Vec<Arc<T>>
-#[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
-#[derive(Debug, Clone)]
+#[cfg_attr(target_arch = "wasm32", derive(Deserialize, Clone))]
+#[derive(Debug)]
 pub struct VecArcWrapper<T>(
     #[cfg(target_arch = "wasm32")] pub Vec<T>,
     #[cfg(not(target_arch = "wasm32"))] pub Vec<Arc<T>>,
 );
 
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Clone for VecArcWrapper<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
 impl<T> Default for VecArcWrapper<T> {
     fn default() -> Self {
         Self(Vec::new())
@@ -93,6 +110,17 @@ impl<T: Serialize> Serialize for VecArcWrapper<T> {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for VecArcWrapper<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let vec = Vec::<T>::deserialize(deserializer)?;
+        Ok(Self(vec.into_iter().map(Arc::new).collect()))
+    }
+}
+
 // Newtype for RwLock<HashSet<Arc<T>>>
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
 #[derive(Debug)]
@@ -124,6 +152,17 @@ impl<T: Serialize> Serialize for RwLockHashSetArcWrapper<T> {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de> + Eq + Hash> Deserialize<'de> for RwLockHashSetArcWrapper<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let vec = Vec::<T>::deserialize(deserializer)?;
+        Ok(Self(RwLock::new(vec.into_iter().map(Arc::new).collect())))
+    }
+}
+
 // Newtype for RwLock<Vec<Arc<T>>>
 #[cfg_attr(target_arch = "wasm32", derive(Deserialize))]
 #[derive(Debug)]
@@ -155,8 +194,19 @@ impl<T: Serialize> Serialize for RwLockVecArcWrapper<T> {
     }
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for RwLockVecArcWrapper<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let vec = Vec::<T>::deserialize(deserializer)?;
+        Ok(Self(RwLock::new(vec.into_iter().map(Arc::new).collect())))
+    }
+}
+
 // Newtype for RwLock<T>
-#[cfg_attr(target_arch = "wasm32", derive(serde::Deserialize))]
+#[cfg_attr(target_arch = "wasm32", derive(serde::Deserialize, Clone))]
 #[derive(Debug)]
 pub struct RwLockWrapper<T>(
     #[cfg(target_arch = "wasm32")] pub T,
@@ -172,6 +222,26 @@ impl<T> RwLockWrapper<T> {
     pub fn inner(&self) -> &RwLock<T> {
         &self.0
     }
+
+    pub fn set(&self, value: T) {
+        *self
+            .0
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = value;
+    }
+}
+
+impl<T: Clone> RwLockWrapper<T> {
+    /// Clone of the current value, usable on both the backend and the frontend
+    pub fn get(&self) -> T {
+        #[cfg(target_arch = "wasm32")]
+        return self.0.clone();
+        #[cfg(not(target_arch = "wasm32"))]
+        return match self.0.read() {
+            Ok(guard) => guard.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+    }
 }
 
 #[cfg(not(target_arch = "wasm32"))]
@@ -192,7 +262,17 @@ impl<T: Serialize> Serialize for RwLockWrapper<T> {
     }
 }
 
-#[cfg_attr(target_arch = "wasm32", derive(serde::Deserialize))]
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for RwLockWrapper<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
+#[cfg_attr(target_arch = "wasm32", derive(serde::Deserialize, Clone))]
 #[derive(Debug)]
 pub struct RwLockHashMapArc<T>(
     #[cfg(target_arch = "wasm32")] pub HashMap<String, T>,
@@ -210,6 +290,19 @@ impl<T> RwLockHashMapArc<T> {
     }
 }
 
+impl<T> RwLockHashMapArc<T> {
+    /// `f` of the value of `key`, usable on both the backend and the frontend
+    pub fn with<R>(&self, key: &str, f: impl FnOnce(&T) -> R) -> Option<R> {
+        #[cfg(target_arch = "wasm32")]
+        return self.0.get(key).map(f);
+        #[cfg(not(target_arch = "wasm32"))]
+        return match self.0.read() {
+            Ok(guard) => guard.get(key).map(|value| f(value)),
+            Err(poisoned) => poisoned.into_inner().get(key).map(|value| f(value)),
+        };
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 impl<T> From<RwLock<HashMap<String, Arc<T>>>> for RwLockHashMapArc<T> {
     fn from(value: RwLock<HashMap<String, Arc<T>>>) -> Self {
@@ -228,3 +321,15 @@ impl<T: Serialize> Serialize for RwLockHashMapArc<T> {
         map.serialize(serializer)
     }
 }
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for RwLockHashMapArc<T> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let map = HashMap::<String, T>::deserialize(deserializer)?;
+        let map = map.into_iter().map(|(k, v)| (k, Arc::new(v))).collect();
+        Ok(Self(RwLock::new(map)))
+    }
+}
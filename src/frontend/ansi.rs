@@ -0,0 +1,167 @@
+//! Colors and text attributes of ANSI escape sequences (SGR) in build output. Other
+//! escape sequences, e.g. cursor movement, are dropped.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Color {
+    /// one of the 16 colors, styled by the `ansi-fg-N` and `ansi-bg-N` classes
+    Palette(u8),
+    Rgb(u8, u8, u8),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+}
+
+impl Style {
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+
+    pub fn classes(&self) -> Vec<String> {
+        let mut classes = Vec::new();
+        if let Some(Color::Palette(n)) = self.fg {
+            classes.push(format!("ansi-fg-{}", n));
+        }
+        if let Some(Color::Palette(n)) = self.bg {
+            classes.push(format!("ansi-bg-{}", n));
+        }
+        for (set, class) in [
+            (self.bold, "ansi-bold"),
+            (self.dim, "ansi-dim"),
+            (self.italic, "ansi-italic"),
+            (self.underline, "ansi-underline"),
+        ] {
+            if set {
+                classes.push(class.to_string());
+            }
+        }
+        classes
+    }
+
+    /// Inline style for colors outside the palette
+    pub fn css(&self) -> Option<String> {
+        let mut css = String::new();
+        if let Some(Color::Rgb(r, g, b)) = self.fg {
+            css.push_str(&format!("color: rgb({}, {}, {});", r, g, b));
+        }
+        if let Some(Color::Rgb(r, g, b)) = self.bg {
+            css.push_str(&format!("background-color: rgb({}, {}, {});", r, g, b));
+        }
+        (!css.is_empty()).then_some(css)
+    }
+
+    /// Apply the parameters of a `ESC [ ... m` sequence
+    fn apply(&mut self, params: &str) {
+        let mut codes = params
+            .split(';')
+            .map(|code| code.parse::<u16>().unwrap_or(0));
+        // `ESC [ m` is a reset
+        if params.is_empty() {
+            *self = Style::default();
+            return;
+        }
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                2 => self.dim = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => {
+                    self.bold = false;
+                    self.dim = false;
+                }
+                23 => self.italic = false,
+                24 => self.underline = false,
+                30..=37 => self.fg = Some(Color::Palette((code - 30) as u8)),
+                38 => self.fg = extended_color(&mut codes),
+                39 => self.fg = None,
+                40..=47 => self.bg = Some(Color::Palette((code - 40) as u8)),
+                48 => self.bg = extended_color(&mut codes),
+                49 => self.bg = None,
+                90..=97 => self.fg = Some(Color::Palette((code - 90 + 8) as u8)),
+                100..=107 => self.bg = Some(Color::Palette((code - 100 + 8) as u8)),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// `5;n` for the 256 color palette or `2;r;g;b`
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    let byte = |code: Option<u16>| code.map(|code| code.min(255) as u8);
+    match codes.next()? {
+        5 => {
+            let n = byte(codes.next())?;
+            Some(match n {
+                0..=15 => Color::Palette(n),
+                16..=231 => {
+                    let level = |value: u8| if value == 0 { 0 } else { 55 + value * 40 };
+                    let n = n - 16;
+                    Color::Rgb(level(n / 36), level(n / 6 % 6), level(n % 6))
+                }
+                _ => {
+                    let gray = 8 + (n - 232) * 10;
+                    Color::Rgb(gray, gray, gray)
+                }
+            })
+        }
+        2 => Some(Color::Rgb(
+            byte(codes.next())?,
+            byte(codes.next())?,
+            byte(codes.next())?,
+        )),
+        _ => None,
+    }
+}
+
+/// Split `text` into runs of equally styled text, starting with `style`. Also
+/// returns the style in effect at the end, for the text following it.
+pub fn parse(text: &str, mut style: Style) -> (Vec<(Style, &str)>, Style) {
+    let mut runs = Vec::new();
+    let mut rest = text;
+    while let Some(escape) = rest.find('\x1b') {
+        if escape > 0 {
+            runs.push((style, &rest[..escape]));
+        }
+        let sequence = &rest[escape + 1..];
+        rest = match sequence.as_bytes().first() {
+            // CSI: parameters and intermediates up to a final byte in @..~
+            Some(b'[') => {
+                let body = &sequence[1..];
+                match body.find(|c: char| ('@'..='~').contains(&c)) {
+                    Some(end) => {
+                        if body.as_bytes()[end] == b'm' {
+                            style.apply(&body[..end]);
+                        }
+                        &body[end + 1..]
+                    }
+                    None => "",
+                }
+            }
+            // OSC, e.g. window titles or hyperlinks, ends with BEL or ESC \
+            Some(b']') => match sequence.find(['\x07', '\x1b']) {
+                Some(end) if sequence.as_bytes()[end] == b'\x1b' => {
+                    sequence.get(end + 2..).unwrap_or("")
+                }
+                Some(end) => &sequence[end + 1..],
+                None => "",
+            },
+            Some(_) => {
+                let len = sequence.chars().next().map_or(0, char::len_utf8);
+                &sequence[len..]
+            }
+            None => "",
+        };
+    }
+    if !rest.is_empty() {
+        runs.push((style, rest));
+    }
+    (runs, style)
+}
@@ -0,0 +1,146 @@
+//! The commits moved to the archive, below the live state. Nothing is fetched until
+//! the archive is opened, then the list of months and one month at a time.
+
+use std::rc::Rc;
+
+use yew::prelude::*;
+
+use crate::{
+    archive::{ArchiveMonth, ArchivedCommit},
+    package::AggregateStatus,
+};
+
+use super::{
+    API_PREFIX, Fetched,
+    closure_size::format_size,
+    fetch_json,
+    i18n::{t, t_args},
+};
+
+fn repo_name(url: &str) -> &str {
+    let url = url.trim_end_matches('/');
+    let name = url.rsplit('/').next().unwrap_or(url);
+    name.strip_suffix(".git").unwrap_or(name)
+}
+
+fn commit_row(commit: &ArchivedCommit) -> Html {
+    let (symbol, class) =
+        match AggregateStatus::from_statuses(commit.packages.iter().map(|pkg| &pkg.status)) {
+            Some(AggregateStatus::Success) => ("✓", "status-success"),
+            Some(AggregateStatus::Failed) => ("✗", "status-failed"),
+            Some(AggregateStatus::Building) => ("●", "status-building"),
+            None => ("–", "status-unknown"),
+        };
+    let date = web_sys::js_sys::Date::new(&((commit.unix_secs * 1000) as f64).into());
+    html! {
+        <tr>
+            <td class={classes!("center", class)}>{ symbol }</td>
+            <td>{ String::from(date.to_locale_date_string("default", &Default::default())) }</td>
+            <td>{ repo_name(&commit.repo) }</td>
+            <td class="mono">{ commit.hash.chars().take(8).collect::<String>() }</td>
+            <td>{ commit.message.lines().next().unwrap_or_default() }</td>
+            <td class="center">{ commit.packages.len() }</td>
+        </tr>
+    }
+}
+
+#[function_component]
+pub fn ArchiveBrowser() -> Html {
+    let months = use_state(|| None::<Result<Vec<ArchiveMonth>, String>>);
+    let selected = use_state(|| None::<String>);
+    let commits = use_state(|| None::<Result<Rc<Vec<ArchivedCommit>>, String>>);
+
+    let load_months = {
+        let months = months.clone();
+        Callback::from(move |_: MouseEvent| {
+            let months = months.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let path = format!("{}/archive", API_PREFIX);
+                months.set(Some(
+                    match fetch_json::<Vec<ArchiveMonth>>(&path, None).await {
+                        Ok(Fetched::Changed(fetched, _)) => Ok(fetched),
+                        Ok(Fetched::NotModified) => Ok(Vec::new()),
+                        Err(e) => Err(e.to_string()),
+                    },
+                ));
+            });
+        })
+    };
+    let open_month = |month: String| {
+        let selected = selected.clone();
+        let commits = commits.clone();
+        Callback::from(move |_: MouseEvent| {
+            selected.set(Some(month.clone()));
+            commits.set(None);
+            let commits = commits.clone();
+            let path = format!("{}/archive/{}", API_PREFIX, month);
+            wasm_bindgen_futures::spawn_local(async move {
+                commits.set(Some(
+                    match fetch_json::<Vec<ArchivedCommit>>(&path, None).await {
+                        Ok(Fetched::Changed(fetched, _)) => Ok(Rc::new(fetched)),
+                        Ok(Fetched::NotModified) => Ok(Rc::default()),
+                        Err(e) => Err(e.to_string()),
+                    },
+                ));
+            });
+        })
+    };
+
+    let body = match &*months {
+        None => html! {
+            <button class="poll-button" onclick={load_months}>{ t("archive.show") }</button>
+        },
+        Some(Err(e)) => html! { <p class="meta error">{ t_args("app.error", &[e]) }</p> },
+        Some(Ok(months)) if months.is_empty() => {
+            html! { <p class="meta">{ t("archive.empty") }</p> }
+        }
+        Some(Ok(months)) => html! {
+            <div class="archive-months">
+                { for months.iter().rev().map(|month| {
+                    let active = selected.as_deref() == Some(month.month.as_str());
+                    let title = t_args("archive.month_title", &[&format_size(month.bytes)]);
+                    html! {
+                        <button
+                            class={classes!("poll-button", active.then_some("active"))}
+                            {title}
+                            onclick={open_month(month.month.clone())}
+                        >
+                            { format!("{} ({})", month.month, month.commits) }
+                        </button>
+                    }
+                }) }
+            </div>
+        },
+    };
+    let table = match (&*selected, &*commits) {
+        (None, _) => html! {},
+        (Some(_), None) => html! { <p class="meta">{ t("app.loading") }</p> },
+        (Some(_), Some(Err(e))) => {
+            html! { <p class="meta error">{ t_args("app.error", &[e]) }</p> }
+        }
+        (Some(_), Some(Ok(commits))) => html! {
+            <table class="repo-table archive-table">
+                <thead>
+                    <tr>
+                        <th></th>
+                        <th>{ t("archive.date") }</th>
+                        <th>{ t("archive.repo") }</th>
+                        <th>{ t("archive.commit") }</th>
+                        <th>{ t("archive.message") }</th>
+                        <th>{ t("archive.packages") }</th>
+                    </tr>
+                </thead>
+                <tbody>
+                    { for commits.iter().rev().map(commit_row) }
+                </tbody>
+            </table>
+        },
+    };
+    html! {
+        <section class="archive">
+            <h2>{ t("archive.title") }</h2>
+            { body }
+            { table }
+        </section>
+    }
+}
@@ -0,0 +1,76 @@
+//! Earlier builds of a package on the same commit, to tell a build that only passed
+//! on a retry from one that always passed
+
+use yew::prelude::*;
+
+use crate::package::{BuildAttempt, PackageBuildStatus};
+
+use super::{
+    i18n::{t, t_args},
+    log_viewer::log_url,
+};
+
+/// "attempt 3 of 3, 2 earlier failed", nothing before the second attempt
+pub fn attempts_summary_html(attempts: &[BuildAttempt]) -> Html {
+    let Some(latest) = attempts.last() else {
+        return html! {};
+    };
+    if latest.number < 2 {
+        return html! {};
+    }
+    let failed = attempts[..attempts.len() - 1]
+        .iter()
+        .filter(|attempt| matches!(attempt.status, PackageBuildStatus::Failed(_)))
+        .count();
+    let text = match failed {
+        0 => t_args("attempts.summary", &[&latest.number, &latest.number]),
+        _ => t_args(
+            "attempts.summary_failed",
+            &[&latest.number, &latest.number, &failed],
+        ),
+    };
+    html! { <span class="attempts-summary">{ text }</span> }
+}
+
+/// The logs of the earlier attempts, one link each
+pub fn attempt_logs_html(
+    repo_name: &str,
+    commit: &str,
+    path: &str,
+    attempts: &[BuildAttempt],
+) -> Html {
+    let Some((_, earlier)) = attempts.split_last() else {
+        return html! {};
+    };
+    let url = log_url(repo_name, commit, path);
+    html! {
+        for earlier.iter().map(|attempt| {
+            let class = match attempt.status {
+                PackageBuildStatus::Success(_) => "success",
+                PackageBuildStatus::Failed(_) => "failed",
+                _ => "other",
+            };
+            let label = format!("#{}", attempt.number);
+            match attempt.log_ref {
+                Some(_) if attempt.log_expired() => html! {
+                    <span
+                        class={classes!("attempt-log", "expired", class)}
+                        title={t("attempts.log_expired")}
+                    >
+                        { label }
+                    </span>
+                },
+                Some(_) => html! {
+                    <a
+                        class={classes!("attempt-log", class)}
+                        href={format!("{}?attempt={}", url, attempt.number)}
+                        target="_blank"
+                    >
+                        { label }
+                    </a>
+                },
+                None => html! { <span class={classes!("attempt-log", class)}>{ label }</span> },
+            }
+        })
+    }
+}
@@ -0,0 +1,59 @@
+//! The builder snapshot an attempt was built on, in the debug info of a package
+
+use yew::prelude::*;
+
+use crate::builder::BuilderInfo;
+
+use super::{
+    API_PREFIX, Fetched, fetch_json,
+    i18n::{t, t_args},
+};
+
+#[derive(Properties, PartialEq)]
+pub struct BuilderDetailsProps {
+    /// of the snapshot, as the attempt names it
+    pub id: AttrValue,
+}
+
+fn info_text(info: &BuilderInfo) -> String {
+    let mut lines = vec![
+        format!("id: {}", info.id),
+        format!("nix: {}", info.nix_version),
+        format!("kernel: {}", info.kernel),
+        format!("system: {}", info.system),
+        format!("server: {} ({})", info.server_version, info.git_rev),
+    ];
+    lines.extend(
+        info.nix_config
+            .iter()
+            .map(|(key, value)| format!("{}: {}", key, value)),
+    );
+    lines.join("\n")
+}
+
+/// Fetched once per snapshot, the id alone when the server does not know it, e.g.
+/// after an import
+#[function_component]
+pub fn BuilderDetails(props: &BuilderDetailsProps) -> Html {
+    // `Some(None)` once the server answered without it
+    let info = use_state(|| None::<Option<BuilderInfo>>);
+    {
+        let info = info.clone();
+        let path = format!("{}/builder-info/{}", API_PREFIX, props.id);
+        use_effect_with(path.clone(), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                info.set(Some(match fetch_json::<BuilderInfo>(&path, None).await {
+                    Ok(Fetched::Changed(fetched, _)) => Some(fetched),
+                    _ => None,
+                }));
+            });
+        });
+    }
+    match &*info {
+        None => html! { <p class="meta">{ t("app.loading") }</p> },
+        Some(Some(info)) => html! { <pre class="debug-info">{ info_text(info) }</pre> },
+        Some(None) => {
+            html! { <p class="meta">{ t_args("debug.builder_unknown", &[&props.id]) }</p> }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Cancelling queued and running builds from the dashboard. The new status arrives
+//! with the next update.
+
+use gloo_net::http::Request;
+use gloo_timers::callback::Timeout;
+use yew::prelude::*;
+
+use super::{API_PREFIX, base_path, toast::Toaster};
+
+/// Milliseconds a cancel of running builds waits for the confirming click
+const CONFIRM_MS: u32 = 4000;
+
+async fn request_cancel(repo_name: &str, commit: &str, path: &str) -> Result<(), String> {
+    let url = format!(
+        "{}{}/repos/{}/commits/{}/packages/{}/cancel",
+        base_path(),
+        API_PREFIX,
+        repo_name,
+        commit,
+        path
+    );
+    let response = Request::post(&url)
+        .send()
+        .await
+        .map_err(|e| format!("Cancel request failed: {}", e))?;
+    match response.status() {
+        200..300 => Ok(()),
+        401 | 403 => Err("Not authorized to cancel builds".to_string()),
+        404 => Err(format!("{} is no longer tracked", path)),
+        status => Err(format!("Cancel of {} failed with HTTP {}", path, status)),
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CancelButtonProps {
+    pub repo_name: String,
+    pub commit: String,
+    /// packages to cancel, more than one for "Cancel all"
+    pub paths: Vec<String>,
+    /// one of them is building, killing it needs a second click
+    pub running: bool,
+    #[prop_or(AttrValue::Static("Cancel"))]
+    pub label: AttrValue,
+}
+
+#[function_component]
+pub fn CancelButton(props: &CancelButtonProps) -> Html {
+    let in_flight = use_state(|| false);
+    let confirming = use_state(|| false);
+    let toaster = use_context::<Toaster>();
+    {
+        let confirming = confirming.clone();
+        use_effect_with(*confirming, move |active| {
+            let timeout = active.then(|| Timeout::new(CONFIRM_MS, move || confirming.set(false)));
+            move || drop(timeout)
+        });
+    }
+    let onclick = {
+        let in_flight = in_flight.clone();
+        let confirming = confirming.clone();
+        let repo_name = props.repo_name.clone();
+        let commit = props.commit.clone();
+        let paths = props.paths.clone();
+        let running = props.running;
+        Callback::from(move |event: MouseEvent| {
+            // the button sits inside links and expandable rows
+            event.prevent_default();
+            event.stop_propagation();
+            if *in_flight {
+                return;
+            }
+            if running && !*confirming {
+                confirming.set(true);
+                return;
+            }
+            confirming.set(false);
+            in_flight.set(true);
+            let in_flight = in_flight.clone();
+            let toaster = toaster.clone();
+            let (repo_name, commit, paths) = (repo_name.clone(), commit.clone(), paths.clone());
+            wasm_bindgen_futures::spawn_local(async move {
+                for path in paths {
+                    if let Err(message) = request_cancel(&repo_name, &commit, &path).await
+                        && let Some(toaster) = &toaster
+                    {
+                        toaster.show(message);
+                    }
+                }
+                in_flight.set(false);
+            });
+        })
+    };
+    let text = match (*in_flight, *confirming) {
+        (true, _) => "Cancelling…".to_string(),
+        (false, true) if props.paths.len() > 1 => "Kill running builds?".to_string(),
+        (false, true) => "Kill running build?".to_string(),
+        (false, false) => props.label.to_string(),
+    };
+    html! {
+        <button
+            class={classes!("poll-button", "cancel-button", confirming.then_some("confirming"))}
+            disabled={*in_flight}
+            {onclick}
+        >
+            { text }
+        </button>
+    }
+}
@@ -0,0 +1,120 @@
+//! Copying store paths, flake URLs and hashes, e.g. for `nix copy` or `nix shell`
+
+use gloo_timers::callback::Timeout;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{HtmlDocument, HtmlTextAreaElement};
+use yew::prelude::*;
+
+/// Milliseconds the confirmation replaces the label
+const COPIED_MS: u32 = 1500;
+/// Characters kept on each side of a shortened text
+const TRUNCATE_KEEP: usize = 24;
+
+/// The async Clipboard API only exists in secure contexts, elsewhere the text is
+/// copied from a hidden text area
+pub async fn write(text: &str) -> Result<(), String> {
+    let window = web_sys::window().ok_or("no window available")?;
+    if window.is_secure_context() {
+        let copied = JsFuture::from(window.navigator().clipboard().write_text(text)).await;
+        if copied.is_ok() {
+            return Ok(());
+        }
+    }
+    write_fallback(text)
+}
+
+fn write_fallback(text: &str) -> Result<(), String> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("no document available")?;
+    let root = document.document_element().ok_or("no document element")?;
+    let area = document
+        .create_element("textarea")
+        .map_err(|e| format!("{e:?}"))?
+        .dyn_into::<HtmlTextAreaElement>()
+        .map_err(|_| "not a text area")?;
+    area.set_value(text);
+    let _ = area.set_attribute("readonly", "");
+    let _ = area.set_attribute("style", "position: fixed; top: -1000px; opacity: 0");
+    root.append_child(&area).map_err(|e| format!("{e:?}"))?;
+    area.select();
+    let copied = document
+        .dyn_into::<HtmlDocument>()
+        .map_err(|_| "not an HTML document".to_string())
+        .and_then(|document| document.exec_command("copy").map_err(|e| format!("{e:?}")));
+    area.remove();
+    match copied? {
+        true => Ok(()),
+        false => Err("copying is not supported".to_string()),
+    }
+}
+
+/// Start and end of `text` around an ellipsis, store paths differ at both ends
+pub fn middle_truncate(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    if chars.len() <= 2 * TRUNCATE_KEEP + 1 {
+        return text.to_string();
+    }
+    let start = chars[..TRUNCATE_KEEP].iter().collect::<String>();
+    let end = chars[chars.len() - TRUNCATE_KEEP..]
+        .iter()
+        .collect::<String>();
+    format!("{}…{}", start, end)
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CopyButtonProps {
+    pub text: AttrValue,
+}
+
+#[function_component]
+pub fn CopyButton(props: &CopyButtonProps) -> Html {
+    let label = use_state(|| "copy");
+    {
+        let label = label.clone();
+        use_effect_with(*label, move |current| {
+            let timeout =
+                (*current != "copy").then(|| Timeout::new(COPIED_MS, move || label.set("copy")));
+            move || drop(timeout)
+        });
+    }
+    let onclick = {
+        let label = label.clone();
+        let text = props.text.clone();
+        Callback::from(move |event: MouseEvent| {
+            // the button sits inside links and expandable rows
+            event.prevent_default();
+            event.stop_propagation();
+            let label = label.clone();
+            let text = text.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                label.set(match write(&text).await {
+                    Ok(()) => "copied!",
+                    Err(_) => "copy failed",
+                });
+            });
+        })
+    };
+    html! {
+        <button class="copy-button" title={format!("Copy {}", props.text)} {onclick}>
+            { *label }
+        </button>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct CopyTextProps {
+    pub text: AttrValue,
+}
+
+/// Shortened text with the full one as tooltip and a copy button
+#[function_component]
+pub fn CopyText(props: &CopyTextProps) -> Html {
+    html! {
+        <span class="copy-text">
+            <code title={props.text.clone()}>{ middle_truncate(&props.text) }</code>
+            <CopyButton text={props.text.clone()} />
+        </span>
+    }
+}
@@ -0,0 +1,85 @@
+//! Closure sizes of successful builds, compared with the same package on the
+//! parent commit to spot a dependency that pulled in half the world
+
+use yew::prelude::*;
+
+use crate::{
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// "412 MiB", with one decimal below 10 units
+pub fn format_size(bytes: u64) -> String {
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ if size < 10.0 => format!("{:.1} {}", size, UNITS[unit]),
+        _ => format!("{:.0} {}", size, UNITS[unit]),
+    }
+}
+
+fn same_package(a: &PackageEnum, b: &PackageEnum) -> bool {
+    match (a, b) {
+        (PackageEnum::Derivation(a), PackageEnum::Derivation(b)) => {
+            a.0.arch == b.0.arch && a.0.get_no_arch_name() == b.0.get_no_arch_name()
+        }
+        (PackageEnum::NixosConfig(a), PackageEnum::NixosConfig(b)) => a.0.path == b.0.path,
+        _ => false,
+    }
+}
+
+/// The package on the commit before `commit` on one of its branches
+fn parent_package<'a>(
+    repo: &'a RepoInfo,
+    commit: &CommitInfo,
+    pkg: &PackageEnum,
+) -> Option<&'a PackageEnum> {
+    repo.branch_commit_hashes.values().find_map(|hashes| {
+        let index = hashes.0.iter().position(|hash| *hash == commit.hash)?;
+        let parent = repo.commits.0.get(hashes.0.get(index + 1)?)?;
+        parent
+            .packages
+            .0
+            .iter()
+            .find(|other| same_package(pkg, other))
+    })
+}
+
+/// "412 MiB" and the change since the parent commit, nothing unless both builds
+/// succeeded with a known size
+pub fn closure_size_html(repo: &RepoInfo, commit: &CommitInfo, pkg: &PackageEnum) -> Html {
+    if !matches!(pkg.status(), PackageBuildStatus::Success(_)) {
+        return html! {};
+    }
+    let Some(size) = pkg.closure_size() else {
+        return html! {};
+    };
+    let parent_size = parent_package(repo, commit, pkg)
+        .filter(|parent| matches!(parent.status(), PackageBuildStatus::Success(_)))
+        .and_then(PackageEnum::closure_size);
+    let delta = match parent_size {
+        Some(parent) if size > parent => {
+            Some(("grown", format!("+{}", format_size(size - parent))))
+        }
+        Some(parent) if size < parent => {
+            Some(("shrunk", format!("−{}", format_size(parent - size))))
+        }
+        _ => None,
+    };
+    html! {
+        <span class="closure-size" title="closure size">
+            { format_size(size) }
+            if let Some((class, text)) = delta {
+                <span class={classes!("size-delta", class)}>{ text }</span>
+            }
+        </span>
+    }
+}
@@ -0,0 +1,39 @@
+//! Developer mode with the raw state dumps. `?debug=1` turns it on and `?debug=0`
+//! off, the choice is remembered in localStorage for later visits.
+
+use std::cell::OnceCell;
+
+const STORAGE_KEY: &str = "nix_autobuild.debug";
+
+fn from_url() -> Option<bool> {
+    let search = web_sys::window()?.location().search().ok()?;
+    let params = web_sys::UrlSearchParams::new_with_str(&search).ok()?;
+    match params.get("debug")?.as_str() {
+        "1" | "true" => Some(true),
+        "0" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+fn load() -> bool {
+    let storage = super::local_storage();
+    if let Some(enabled) = from_url() {
+        if let Some(storage) = &storage {
+            let _ = match enabled {
+                true => storage.set_item(STORAGE_KEY, "1"),
+                false => storage.remove_item(STORAGE_KEY),
+            };
+        }
+        return enabled;
+    }
+    storage.is_some_and(|storage| storage.get_item(STORAGE_KEY).ok().flatten().is_some())
+}
+
+thread_local! {
+    /// Read once, switching takes a page load with the query parameter
+    static ENABLED: OnceCell<bool> = const { OnceCell::new() };
+}
+
+pub fn enabled() -> bool {
+    ENABLED.with(|enabled| *enabled.get_or_init(load))
+}
@@ -0,0 +1,68 @@
+//! Build durations, timed by the backend and ticking in the browser while a build
+//! runs. The clocks of the server and the browser may differ, so durations are
+//! clamped to zero.
+
+use gloo_timers::callback::Interval;
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+
+use crate::package::BuildTimes;
+
+pub fn humanize(secs: i64) -> String {
+    let secs = secs.max(0);
+    match secs {
+        0..60 => format!("{}s", secs),
+        60..3600 => format!("{}m {}s", secs / 60, secs % 60),
+        _ => format!("{}h {}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+pub fn now_secs() -> i64 {
+    (Date::now() / 1000.0) as i64
+}
+
+/// Seconds the build took or, while it runs, has taken so far
+pub fn elapsed(times: BuildTimes, now: i64) -> Option<i64> {
+    let started = times.started?;
+    Some((times.finished.unwrap_or(now) - started).max(0))
+}
+
+/// From the first package start to the last package finish, `None` while some
+/// package is still building or none started
+pub fn wall_clock(times: impl IntoIterator<Item = BuildTimes>) -> Option<i64> {
+    let mut first_start = None::<i64>;
+    let mut last_finish = None::<i64>;
+    for times in times {
+        let Some(started) = times.started else {
+            continue;
+        };
+        let finished = times.finished?;
+        first_start = Some(first_start.map_or(started, |first| first.min(started)));
+        last_finish = Some(last_finish.map_or(finished, |last| last.max(finished)));
+    }
+    Some((last_finish? - first_start?).max(0))
+}
+
+#[derive(Properties, PartialEq)]
+pub struct BuildDurationProps {
+    pub times: BuildTimes,
+}
+
+/// "took 3m 12s" once finished, "building for 45s" while running
+#[function_component]
+pub fn BuildDuration(props: &BuildDurationProps) -> Html {
+    let update = use_force_update();
+    let running = props.times.started.is_some() && props.times.finished.is_none();
+    use_effect_with(running, move |running| {
+        let interval = running.then(|| Interval::new(1000, move || update.force_update()));
+        move || drop(interval)
+    });
+    let Some(secs) = elapsed(props.times, now_secs()) else {
+        return html! {};
+    };
+    let text = match running {
+        true => format!("building for {}", humanize(secs)),
+        false => format!("took {}", humanize(secs)),
+    };
+    html! { <span class="build-duration">{ text }</span> }
+}
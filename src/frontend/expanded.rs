@@ -0,0 +1,35 @@
+//! Expanded rows of the package table, remembered in localStorage so they stay open
+//! across refreshes, navigation and reloads
+
+/// localStorage key of the ids of the expanded rows, one per line
+const STORAGE_KEY: &str = "nix_autobuild.expanded_rows";
+/// Rows remembered at most, the oldest are forgotten first
+const MAX_REMEMBERED: usize = 100;
+
+/// Stable identifier of a table row
+pub fn row_id(repo_url: &str, commit: &str, path: &str) -> String {
+    format!("{}/{}/{}", repo_url, commit, path)
+}
+
+fn load() -> Vec<String> {
+    super::local_storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok()?)
+        .map(|ids| ids.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+pub fn is_expanded(id: &str) -> bool {
+    load().iter().any(|expanded| expanded == id)
+}
+
+pub fn set_expanded(id: &str, expanded: bool) {
+    let mut ids = load();
+    ids.retain(|other| other != id);
+    if expanded {
+        ids.push(id.to_string());
+    }
+    let skip = ids.len().saturating_sub(MAX_REMEMBERED);
+    if let Some(storage) = super::local_storage() {
+        let _ = storage.set_item(STORAGE_KEY, &ids[skip..].join("\n"));
+    }
+}
@@ -0,0 +1,194 @@
+//! Download of the table rows matching the filters, in table order and across all
+//! pages, as CSV or JSON built in the browser
+
+use serde_json::{Value, json};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url, js_sys::Array, js_sys::Date};
+use yew::prelude::*;
+
+use crate::{
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+use super::{
+    duration::{elapsed, now_secs},
+    i18n::t,
+    table_sort::branch_of,
+};
+
+pub struct ExportRow {
+    repo: String,
+    package_path: String,
+    branch: String,
+    commit_hash: String,
+    commit_message: String,
+    arch: String,
+    status: String,
+    duration_secs: Option<i64>,
+    result: Option<String>,
+}
+
+impl ExportRow {
+    pub fn new(repo: &RepoInfo, commit: &CommitInfo, pkg: &PackageEnum) -> Self {
+        ExportRow {
+            repo: repo.repo.url.clone(),
+            package_path: pkg.path().to_string(),
+            branch: branch_of(repo, commit),
+            commit_hash: commit.hash.clone(),
+            commit_message: commit.message.clone(),
+            arch: match pkg {
+                PackageEnum::Derivation(derivation) => derivation.0.arch.clone(),
+                PackageEnum::NixosConfig(_) => String::new(),
+            },
+            status: status_key(&pkg.status()).to_string(),
+            duration_secs: elapsed(pkg.times(), now_secs()),
+            result: match pkg.status() {
+                PackageBuildStatus::Success(path) => Some(path),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Like the variant names of the API, unlike the labels the same in every language
+fn status_key(status: &PackageBuildStatus) -> &'static str {
+    match status {
+        PackageBuildStatus::Idle => "idle",
+        PackageBuildStatus::UnsupportedArchitecture(_) => "unsupported_architecture",
+        PackageBuildStatus::WaitingForWindow => "waiting_for_window",
+        PackageBuildStatus::WaitingForBuild => "waiting_for_build",
+        PackageBuildStatus::Building => "building",
+        PackageBuildStatus::Success(_) => "success",
+        PackageBuildStatus::Failed(_) => "failed",
+        PackageBuildStatus::Cancelled => "cancelled",
+        PackageBuildStatus::DryRun { .. } => "dry_run",
+    }
+}
+
+const CSV_HEADER: [&str; 9] = [
+    "repo",
+    "package_path",
+    "branch",
+    "commit_hash",
+    "commit_message",
+    "arch",
+    "status",
+    "duration_secs",
+    "result",
+];
+
+/// Quoted if it contains a separator, quote or line break, quotes doubled (RFC 4180)
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+fn to_csv(rows: &[ExportRow]) -> String {
+    let mut csv = CSV_HEADER.join(",");
+    csv.push_str("\r\n");
+    for row in rows {
+        let fields = [
+            row.repo.clone(),
+            row.package_path.clone(),
+            row.branch.clone(),
+            row.commit_hash.clone(),
+            row.commit_message.clone(),
+            row.arch.clone(),
+            row.status.clone(),
+            row.duration_secs
+                .map(|secs| secs.to_string())
+                .unwrap_or_default(),
+            row.result.clone().unwrap_or_default(),
+        ];
+        let line = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<_>>();
+        csv.push_str(&line.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn to_json(rows: &[ExportRow]) -> String {
+    let rows = rows
+        .iter()
+        .map(|row| {
+            json!({
+                "repo": row.repo,
+                "package_path": row.package_path,
+                "branch": row.branch,
+                "commit_hash": row.commit_hash,
+                "commit_message": row.commit_message,
+                "arch": row.arch,
+                "status": row.status,
+                "duration_secs": row.duration_secs,
+                "result": row.result,
+            })
+        })
+        .collect::<Vec<_>>();
+    serde_json::to_string_pretty(&Value::Array(rows)).unwrap_or_default()
+}
+
+/// "nix_autobuild-20260314-140312.csv" in local time
+fn filename(extension: &str) -> String {
+    let now = Date::new_0();
+    format!(
+        "nix_autobuild-{:04}{:02}{:02}-{:02}{:02}{:02}.{}",
+        now.get_full_year(),
+        now.get_month() + 1,
+        now.get_date(),
+        now.get_hours(),
+        now.get_minutes(),
+        now.get_seconds(),
+        extension
+    )
+}
+
+/// Save `content` through a temporary link to a Blob URL
+fn download(name: &str, mime: &str, content: &str) -> Result<(), JsValue> {
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or("no document")?;
+    let options = BlobPropertyBag::new();
+    options.set_type(mime);
+    let parts = Array::of1(&JsValue::from_str(content));
+    let blob = Blob::new_with_str_sequence_and_options(&parts, &options)?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let link = document
+        .create_element("a")?
+        .unchecked_into::<HtmlAnchorElement>();
+    link.set_href(&url);
+    link.set_download(name);
+    link.click();
+    Url::revoke_object_url(&url)
+}
+
+/// `rows` makes the rows when a download is clicked
+pub fn export_html(rows: Callback<(), Vec<ExportRow>>) -> Html {
+    let button = |label: &'static str, extension: &'static str, mime: &'static str| {
+        let rows = rows.clone();
+        let onclick = Callback::from(move |_: MouseEvent| {
+            let rows = rows.emit(());
+            let content = match extension {
+                "csv" => to_csv(&rows),
+                _ => to_json(&rows),
+            };
+            let _ = download(&filename(extension), mime, &content);
+        });
+        html! { <button class="poll-button" {onclick}>{ label }</button> }
+    };
+    html! {
+        <details class="export-menu">
+            <summary class="poll-button">{ t("export.menu") }</summary>
+            <div class="export-options">
+                { button("CSV", "csv", "text/csv;charset=utf-8") }
+                { button("JSON", "json", "application/json") }
+            </div>
+        </details>
+    }
+}
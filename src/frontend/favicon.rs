@@ -0,0 +1,104 @@
+//! Overall status in the tab title and favicon, for a pinned tab. The icons are drawn
+//! on a canvas, so no extra assets are needed.
+
+use std::{cell::Cell, f64::consts::TAU};
+
+use wasm_bindgen::JsCast;
+use web_sys::{CanvasRenderingContext2d, Document, Element, HtmlCanvasElement};
+
+use crate::RepoList;
+
+use super::summary::totals;
+
+const TITLE: &str = "Nix Autobuild";
+const ICON_SIZE: u32 = 32;
+/// Attribute of the icon link keeping the icon of `index.html` while another is shown
+const NEUTRAL_HREF: &str = "data-neutral-href";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Overall {
+    Green,
+    Building,
+    Failed(usize),
+}
+
+impl Overall {
+    fn color(self) -> Option<&'static str> {
+        match self {
+            Overall::Green => None,
+            Overall::Building => Some("#f59e0b"),
+            Overall::Failed(_) => Some("#ef4444"),
+        }
+    }
+}
+
+/// What the tab shows, so unchanged states do not touch the document
+#[derive(Default)]
+pub struct TabStatus {
+    shown: Cell<Option<Overall>>,
+}
+
+impl TabStatus {
+    pub fn update(&self, repos: &RepoList) {
+        let totals = totals(repos);
+        let overall = match (totals.failed, totals.building + totals.queued) {
+            (0, 0) => Overall::Green,
+            (0, _) => Overall::Building,
+            (failed, _) => Overall::Failed(failed),
+        };
+        if self.shown.replace(Some(overall)) == Some(overall) {
+            return;
+        }
+        let Some(document) = web_sys::window().and_then(|window| window.document()) else {
+            return;
+        };
+        document.set_title(&match overall {
+            Overall::Failed(failed) => format!("({}✗) {}", failed, TITLE),
+            _ => TITLE.to_string(),
+        });
+        set_icon(&document, overall);
+    }
+}
+
+fn set_icon(document: &Document, overall: Overall) {
+    let Ok(Some(link)) = document.query_selector("link[rel='icon']") else {
+        return;
+    };
+    let neutral = match link.get_attribute(NEUTRAL_HREF) {
+        Some(neutral) => neutral,
+        None => {
+            let neutral = link.get_attribute("href").unwrap_or_default();
+            let _ = link.set_attribute(NEUTRAL_HREF, &neutral);
+            neutral
+        }
+    };
+    let href = overall
+        .color()
+        .and_then(|color| draw_icon(document, color))
+        .unwrap_or(neutral);
+    let _ = link.set_attribute("href", &href);
+}
+
+/// Data URL of a dot in `color`
+fn draw_icon(document: &Document, color: &str) -> Option<String> {
+    let canvas = document
+        .create_element("canvas")
+        .ok()
+        .and_then(|element: Element| element.dyn_into::<HtmlCanvasElement>().ok())?;
+    canvas.set_width(ICON_SIZE);
+    canvas.set_height(ICON_SIZE);
+    let context = canvas
+        .get_context("2d")
+        .ok()??
+        .dyn_into::<CanvasRenderingContext2d>()
+        .ok()?;
+    let center = f64::from(ICON_SIZE) / 2.0;
+    context.begin_path();
+    context.arc(center, center, center - 2.0, 0.0, TAU).ok()?;
+    context.set_fill_style_str(color);
+    context.fill();
+    context.set_line_width(2.0);
+    context.set_stroke_style_str("#0f172a");
+    context.stroke();
+    canvas.to_data_url().ok()
+}
@@ -0,0 +1,368 @@
+//! Narrowing the dashboard down to the packages matching a search text, a status,
+//! status categories and architectures
+
+use web_sys::{HtmlInputElement, HtmlSelectElement};
+use yew::prelude::*;
+
+use crate::{
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+/// Values of the status dropdown
+pub const STATUSES: [&str; 9] = [
+    "success",
+    "failed",
+    "building",
+    "waiting",
+    "waiting_window",
+    "idle",
+    "cancelled",
+    "unsupported",
+    "dry_run",
+];
+
+pub fn status_name(status: &PackageBuildStatus) -> &'static str {
+    match status {
+        PackageBuildStatus::Success(_) => "success",
+        PackageBuildStatus::Failed(_) => "failed",
+        PackageBuildStatus::Building => "building",
+        PackageBuildStatus::WaitingForBuild => "waiting",
+        PackageBuildStatus::WaitingForWindow => "waiting_window",
+        PackageBuildStatus::Idle => "idle",
+        PackageBuildStatus::Cancelled => "cancelled",
+        PackageBuildStatus::UnsupportedArchitecture(_) => "unsupported",
+        PackageBuildStatus::DryRun { .. } => "dry_run",
+    }
+}
+
+/// Status categories of the chips, in display order
+pub const CATEGORIES: [&str; 5] = ["failed", "building", "queued", "success", "skipped"];
+
+/// localStorage key of the selected categories, used when the URL has none
+const STORAGE_KEY: &str = "nix_autobuild.status_filter";
+
+pub fn category(status: &PackageBuildStatus) -> &'static str {
+    match status {
+        PackageBuildStatus::Failed(_) => "failed",
+        PackageBuildStatus::Building => "building",
+        PackageBuildStatus::Idle
+        | PackageBuildStatus::WaitingForWindow
+        | PackageBuildStatus::WaitingForBuild => "queued",
+        PackageBuildStatus::Success(_) => "success",
+        PackageBuildStatus::Cancelled
+        | PackageBuildStatus::UnsupportedArchitecture(_)
+        | PackageBuildStatus::DryRun { .. } => "skipped",
+    }
+}
+
+fn category_label(category: &str) -> &'static str {
+    match category {
+        "failed" => "Failed",
+        "building" => "Building",
+        "queued" => "Queued",
+        "success" => "Success",
+        _ => "Skipped",
+    }
+}
+
+/// Known categories of a comma separated list, in display order
+pub fn parse_categories(list: &str) -> Vec<String> {
+    let listed = list.split(',').map(str::trim).collect::<Vec<_>>();
+    CATEGORIES
+        .iter()
+        .filter(|category| listed.contains(category))
+        .map(|category| category.to_string())
+        .collect()
+}
+
+pub fn stored_categories() -> Vec<String> {
+    super::local_storage()
+        .and_then(|storage| storage.get_item(STORAGE_KEY).ok()?)
+        .map(|list| parse_categories(&list))
+        .unwrap_or_default()
+}
+
+pub fn store_categories(categories: &[String]) {
+    if let Some(storage) = super::local_storage() {
+        let _ = storage.set_item(STORAGE_KEY, &categories.join(","));
+    }
+}
+
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct Filter {
+    /// whitespace separated terms, each has to appear in the repo name or url,
+    /// package path, branch, commit message or hash
+    pub search: Option<String>,
+    /// one of `STATUSES`
+    pub status: Option<String>,
+    /// `CATEGORIES` to show, all when empty
+    pub categories: Vec<String>,
+    /// architectures of the derivations to show, all packages when empty
+    pub archs: Vec<String>,
+}
+
+impl Filter {
+    pub fn is_active(&self) -> bool {
+        self.search.is_some()
+            || self.status.is_some()
+            || !self.categories.is_empty()
+            || !self.archs.is_empty()
+    }
+
+    /// Lowercase search terms
+    pub fn terms(&self) -> Vec<String> {
+        self.search
+            .as_deref()
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(|term| term.to_ascii_lowercase())
+            .collect()
+    }
+
+    pub fn matches(
+        &self,
+        repo: &RepoInfo,
+        commit: &CommitInfo,
+        pkg: &PackageEnum,
+        branch: &str,
+    ) -> bool {
+        if let Some(status) = &self.status
+            && status_name(&pkg.status()) != status
+        {
+            return false;
+        }
+        if !self.categories.is_empty()
+            && !self
+                .categories
+                .iter()
+                .any(|selected| selected == category(&pkg.status()))
+        {
+            return false;
+        }
+        if !self.archs.is_empty() {
+            match pkg {
+                PackageEnum::Derivation(derivation) if self.archs.contains(&derivation.0.arch) => {}
+                _ => return false,
+            }
+        }
+        let name = repo.repo.name();
+        let fields = [
+            name.as_str(),
+            repo.repo.url.as_str(),
+            pkg.path(),
+            branch,
+            commit.message.as_str(),
+            commit.hash.as_str(),
+        ]
+        .map(str::to_ascii_lowercase);
+        self.terms()
+            .iter()
+            .all(|term| fields.iter().any(|field| field.contains(term.as_str())))
+    }
+
+    /// Whether the package matches on one of the branches of its commit, or on
+    /// the other fields if it is on none anymore
+    pub fn matches_any_branch(
+        &self,
+        repo: &RepoInfo,
+        commit: &CommitInfo,
+        pkg: &PackageEnum,
+    ) -> bool {
+        repo.branch_commit_hashes
+            .iter()
+            .filter(|(_, hashes)| hashes.0.contains(&commit.hash))
+            .map(|(branch, _)| branch.as_str())
+            .chain(["-"])
+            .any(|branch| self.matches(repo, commit, pkg, branch))
+    }
+}
+
+/// Shown instead of an empty view when the filter hides everything
+pub fn nothing_matches() -> Html {
+    html! {
+        <section class="card">
+            <h2>{ "Nothing matches" }</h2>
+            <p class="meta">{ "No packages match the current filters." }</p>
+        </section>
+    }
+}
+
+/// `text` with the parts matching one of `terms` in `<mark>`
+pub fn highlight(text: &str, terms: &[String]) -> Html {
+    if terms.is_empty() {
+        return html! { text };
+    }
+    // ASCII lowercasing keeps the byte offsets and matches never split a character
+    let lower = text.to_ascii_lowercase();
+    let mut marked = vec![false; text.len()];
+    for term in terms {
+        for (start, _) in lower.match_indices(term.as_str()) {
+            marked[start..start + term.len()].fill(true);
+        }
+    }
+    let mut parts = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let mark = marked[start];
+        let end = (start..text.len())
+            .find(|&i| marked[i] != mark)
+            .unwrap_or(text.len());
+        parts.push((mark, &text[start..end]));
+        start = end;
+    }
+    html! {
+        { for parts.into_iter().map(|(mark, part)| if mark {
+            html! { <mark>{ part }</mark> }
+        } else {
+            html! { part }
+        }) }
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct FilterControlsProps {
+    pub filter: Filter,
+    /// architectures of the known packages
+    pub archs: Vec<String>,
+    pub onchange: Callback<Filter>,
+    /// the grouped view starts with the architecture instead of the package
+    pub group_by_arch: bool,
+    pub on_group_by_arch: Callback<bool>,
+}
+
+fn non_empty(value: String) -> Option<String> {
+    (!value.is_empty()).then_some(value)
+}
+
+#[function_component]
+pub fn FilterControls(props: &FilterControlsProps) -> Html {
+    let oninput = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |event: InputEvent| {
+            let search = event.target_unchecked_into::<HtmlInputElement>().value();
+            onchange.emit(Filter {
+                search: non_empty(search),
+                ..filter.clone()
+            });
+        })
+    };
+    let onstatus = {
+        let filter = props.filter.clone();
+        let onchange = props.onchange.clone();
+        Callback::from(move |event: Event| {
+            let status = event.target_unchecked_into::<HtmlSelectElement>().value();
+            onchange.emit(Filter {
+                status: non_empty(status),
+                ..filter.clone()
+            });
+        })
+    };
+    let status = props.filter.status.as_deref();
+    let toggle_group = {
+        let group_by_arch = props.group_by_arch;
+        props
+            .on_group_by_arch
+            .reform(move |_: MouseEvent| !group_by_arch)
+    };
+
+    html! {
+        <div class="filter-bar">
+            <input
+                type="search"
+                placeholder="Filter by repo, package, branch or commit"
+                value={props.filter.search.clone().unwrap_or_default()}
+                {oninput}
+            />
+            <select onchange={onstatus}>
+                <option value="" selected={status.is_none()}>{ "Any status" }</option>
+                { for STATUSES.iter().map(|name| html! {
+                    <option value={*name} selected={status == Some(*name)}>{ *name }</option>
+                }) }
+            </select>
+            <div class="status-chips">
+                { for props.archs.iter().map(|arch| {
+                    let selected = props.filter.archs.contains(arch);
+                    let onclick = {
+                        let filter = props.filter.clone();
+                        let onchange = props.onchange.clone();
+                        let arch = arch.clone();
+                        Callback::from(move |_: MouseEvent| {
+                            let mut archs = filter.archs.clone();
+                            match selected {
+                                true => archs.retain(|a| *a != arch),
+                                false => archs.push(arch.clone()),
+                            }
+                            archs.sort();
+                            onchange.emit(Filter {
+                                archs,
+                                ..filter.clone()
+                            });
+                        })
+                    };
+                    html! {
+                        <button
+                            class={classes!("status-chip", selected.then_some("selected"))}
+                            {onclick}
+                        >
+                            { arch }
+                        </button>
+                    }
+                }) }
+            </div>
+            <button
+                class={classes!("status-chip", props.group_by_arch.then_some("selected"))}
+                onclick={toggle_group}
+            >
+                { "Group by architecture" }
+            </button>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct StatusChipsProps {
+    pub filter: Filter,
+    /// packages per entry of `CATEGORIES` matching the rest of the filter
+    pub counts: Vec<usize>,
+    pub onchange: Callback<Filter>,
+}
+
+/// Toggles for the status categories
+#[function_component]
+pub fn StatusChips(props: &StatusChipsProps) -> Html {
+    html! {
+        <div class="status-chips">
+            { for CATEGORIES.iter().zip(&props.counts).map(|(category, count)| {
+                let selected = props.filter.categories.iter().any(|c| c == category);
+                let onclick = {
+                    let filter = props.filter.clone();
+                    let onchange = props.onchange.clone();
+                    let category = category.to_string();
+                    Callback::from(move |_: MouseEvent| {
+                        let mut categories = filter.categories.clone();
+                        match selected {
+                            true => categories.retain(|c| *c != category),
+                            false => categories.push(category.clone()),
+                        }
+                        onchange.emit(Filter {
+                            categories: parse_categories(&categories.join(",")),
+                            ..filter.clone()
+                        });
+                    })
+                };
+                html! {
+                    <button
+                        class={classes!("status-chip", selected.then_some("selected"))}
+                        {onclick}
+                    >
+                        { category_label(category) }
+                        <span class="chip-count">{ count }</span>
+                    </button>
+                }
+            }) }
+        </div>
+    }
+}
@@ -0,0 +1,23 @@
+//! Links to the web pages of commits on the forge hosting a repo
+
+use crate::Repo;
+
+/// A configured `commit_url_template`, else a guess from the host of the repo url.
+/// `None` for hosts whose URL scheme is unknown.
+pub fn commit_url(repo: &Repo, hash: &str) -> Option<String> {
+    if let Some(template) = &repo.commit_url_template {
+        return Some(template.replace("{hash}", hash));
+    }
+    let url = repo.url.trim_end_matches('/').trim_end_matches(".git");
+    let (host, project) = url.split_once('/')?;
+    let commit_path = match host {
+        "github.com" | "codeberg.org" => "commit",
+        _ if host == "gitlab.com" || host.starts_with("gitlab.") => "-/commit",
+        _ if host.starts_with("gitea.") || host.starts_with("forgejo.") => "commit",
+        _ => return None,
+    };
+    Some(format!(
+        "https://{}/{}/{}/{}",
+        host, project, commit_path, hash
+    ))
+}
@@ -0,0 +1,71 @@
+//! The last builds of a package across commits, one square per build oldest to
+//! newest, to tell a flaky package from one that broke at some commit
+
+use yew::prelude::*;
+
+use crate::package::{BuildOutcome, BuildRecord, PackageBuildStatus};
+
+use super::{
+    API_PREFIX, Fetched,
+    duration::{humanize, now_secs},
+    fetch_json,
+    i18n::t_args,
+    time_ago::relative,
+};
+
+/// Builds shown per package
+const LIMIT: usize = 20;
+
+#[derive(Properties, PartialEq)]
+pub struct BuildHistoryProps {
+    pub repo_name: AttrValue,
+    pub path: AttrValue,
+    /// of the package on the shown commit, a finished build refetches the history
+    pub status: PackageBuildStatus,
+}
+
+fn square_html(build: &BuildRecord, now: i64) -> Html {
+    let (class, key) = match build.outcome {
+        BuildOutcome::Success => ("success", "history.success"),
+        BuildOutcome::Failed => ("failed", "history.failed"),
+        BuildOutcome::Cancelled => ("cancelled", "history.cancelled"),
+    };
+    let commit = build.commit.chars().take(8).collect::<String>();
+    let when = relative(now - build.finished).unwrap_or_default();
+    let duration = build.duration_secs.map(humanize).unwrap_or_default();
+    let title = t_args(key, &[&commit, &when, &duration]);
+    html! { <span class={classes!("history-build", class)} {title}></span> }
+}
+
+/// Nothing until the history arrived or when there is none, e.g. without the
+/// `sqlite` state backend
+#[function_component]
+pub fn BuildHistory(props: &BuildHistoryProps) -> Html {
+    let history = use_state(Vec::<BuildRecord>::new);
+    {
+        let history = history.clone();
+        let path = format!(
+            "{}/repos/{}/packages/{}/history?limit={}",
+            API_PREFIX, props.repo_name, props.path, LIMIT
+        );
+        let finished = props.status.is_finished();
+        use_effect_with((path.clone(), finished), move |_| {
+            wasm_bindgen_futures::spawn_local(async move {
+                if let Ok(Fetched::Changed(fetched, _)) =
+                    fetch_json::<Vec<BuildRecord>>(&path, None).await
+                {
+                    history.set(fetched);
+                }
+            });
+        });
+    }
+    if history.is_empty() {
+        return html! {};
+    }
+    let now = now_secs();
+    html! {
+        <span class="build-history">
+            { for history.iter().rev().map(|build| square_html(build, now)) }
+        </span>
+    }
+}
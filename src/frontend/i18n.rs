@@ -0,0 +1,374 @@
+//! Translations of the user-facing strings. The locale follows `navigator.language`
+//! unless one was picked in the header, strings missing in a locale fall back to
+//! English and unknown keys show the key itself.
+
+use std::{cell::OnceCell, collections::HashMap};
+
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
+
+use crate::{commit::RepoStatus, package::PackageBuildStatus};
+
+const STORAGE_KEY: &str = "nix_autobuild.locale";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    En,
+    De,
+}
+
+/// In the order of the language picker
+const LOCALES: [Locale; 2] = [Locale::En, Locale::De];
+
+impl Locale {
+    fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+        }
+    }
+
+    /// In the language itself
+    fn name(self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::De => "Deutsch",
+        }
+    }
+
+    /// `de-AT` matches `de`
+    fn parse(tag: &str) -> Option<Self> {
+        let language = tag.split(['-', '_']).next()?.to_ascii_lowercase();
+        LOCALES.into_iter().find(|locale| locale.code() == language)
+    }
+
+    fn strings(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Locale::En => EN,
+            Locale::De => DE,
+        }
+    }
+}
+
+/// `{}` are filled in order by `t_args`
+const EN: &[(&str, &str)] = &[
+    ("app.kicker", "Nix Autobuild"),
+    ("app.title", "Repository Overview"),
+    ("app.loading", "Loading data..."),
+    ("app.error", "Error: {}"),
+    ("app.no_table", "No table to display"),
+    ("app.language", "Language"),
+    ("connection.connecting", "Connecting"),
+    ("connection.live", "Live"),
+    ("connection.reconnecting", "Reconnecting"),
+    ("connection.polling", "Polling every {}s"),
+    ("connection.paused", "Paused"),
+    ("refresh.label", "Refresh interval"),
+    ("refresh.every", "Every {}s"),
+    ("refresh.paused", "Paused"),
+    ("updated.just_now", "updated just now"),
+    ("updated.secs_ago", "updated {}s ago"),
+    ("updated.mins_ago", "updated {}m ago"),
+    ("repo.poll_now", "Poll now"),
+    ("commit.no_message", "no commit message"),
+    ("commit.total", "{} in total"),
+    ("commit.cancel_all", "Cancel all"),
+    ("commit.initial", "initial commit"),
+    ("commit.file_changed", "1 file changed"),
+    ("commit.files_changed", "{} files changed"),
+    ("package.nixos_config", "NixOS Config"),
+    ("package.build_result", "→ Build Result"),
+    (
+        "package.result_missing",
+        "The result was garbage collected from the store, build it again to restore it",
+    ),
+    ("package.usually_takes", "usually takes ~{}"),
+    ("details.flake_url", "Flake URL"),
+    ("details.result", "Result"),
+    ("details.log", "Log"),
+    ("details.open_log", "Open raw log"),
+    ("debug.repo", "Repository Debug Info"),
+    ("debug.commit", "Commit Debug Info"),
+    ("debug.package", "Package Debug Info"),
+    ("debug.builder", "Builder of the Latest Attempt"),
+    ("debug.builder_result", "Builder of the Result"),
+    (
+        "debug.builder_unknown",
+        "Builder {}, not known to the server",
+    ),
+    ("table.repository", "Repository"),
+    ("table.package_path", "Package Path"),
+    ("table.branch", "Branch"),
+    ("table.commit", "Commit"),
+    ("table.duration", "Duration"),
+    ("table.status", "Status"),
+    ("status.idle", "Idle"),
+    ("status.unsupported", "Unsupported architecture ({})"),
+    ("status.waiting_window", "Waiting for build window"),
+    ("status.waiting", "Waiting for build"),
+    ("status.building", "Building"),
+    ("status.success", "Success"),
+    ("status.failed", "Failed"),
+    ("status.cancelled", "Cancelled"),
+    ("status.dry_run", "Dry run: {} to build, {} to fetch"),
+    ("push.pushing", "Pushing to {}"),
+    ("push.pushed", "Pushed to {}"),
+    ("push.failed", "Pushing to {} failed: {}"),
+    ("history.success", "{} succeeded {} in {}"),
+    ("history.failed", "{} failed {} after {}"),
+    ("history.cancelled", "{} cancelled {} after {}"),
+    ("attempts.summary", "attempt {} of {}"),
+    (
+        "attempts.summary_failed",
+        "attempt {} of {}, {} earlier failed",
+    ),
+    ("attempts.log_expired", "The log of this attempt expired"),
+    ("repo_status.cloning", "Cloning"),
+    ("repo_status.opening", "Opening"),
+    ("repo_status.idle", "Idle"),
+    ("repo_status.pulling", "Pulling"),
+    ("repo_status.polling", "Polling"),
+    ("poll.unchanged", "no changes"),
+    ("poll.changed", "new commits"),
+    ("poll.failed", "failed: {}"),
+    ("poll.next", "next poll {}"),
+    ("export.menu", "Export"),
+    ("archive.title", "Archive"),
+    ("archive.show", "Show archived commits"),
+    ("archive.empty", "No commits were archived yet"),
+    ("archive.month_title", "{} compressed"),
+    ("archive.date", "Date"),
+    ("archive.repo", "Repo"),
+    ("archive.commit", "Commit"),
+    ("archive.message", "Message"),
+    ("archive.packages", "Packages"),
+    ("pending.building", "{} building"),
+    ("pending.queued", "{} queued"),
+    ("pending.failed_today", "{} failed today"),
+    ("pending.separator", " · "),
+    ("shortcuts.title", "Keyboard shortcuts"),
+    ("shortcuts.next", "Select the next row"),
+    ("shortcuts.previous", "Select the previous row"),
+    ("shortcuts.toggle", "Expand or collapse the selected row"),
+    ("shortcuts.search", "Focus the search box"),
+    (
+        "shortcuts.failures",
+        "Show only failures, or everything again",
+    ),
+    ("shortcuts.retry", "Retry the selected failed package"),
+    ("shortcuts.help", "Show this overview"),
+    ("shortcuts.close", "Close this overview"),
+    ("shortcuts.dismiss", "Close"),
+];
+
+const DE: &[(&str, &str)] = &[
+    ("app.kicker", "Nix Autobuild"),
+    ("app.title", "Repository-Übersicht"),
+    ("app.loading", "Daten werden geladen..."),
+    ("app.error", "Fehler: {}"),
+    ("app.no_table", "Keine Tabelle vorhanden"),
+    ("app.language", "Sprache"),
+    ("connection.connecting", "Verbinde"),
+    ("connection.live", "Live"),
+    ("connection.reconnecting", "Verbinde neu"),
+    ("connection.polling", "Abfrage alle {}s"),
+    ("connection.paused", "Pausiert"),
+    ("refresh.label", "Aktualisierungsintervall"),
+    ("refresh.every", "Alle {}s"),
+    ("refresh.paused", "Pausiert"),
+    ("updated.just_now", "gerade aktualisiert"),
+    ("updated.secs_ago", "vor {}s aktualisiert"),
+    ("updated.mins_ago", "vor {}m aktualisiert"),
+    ("repo.poll_now", "Jetzt abfragen"),
+    ("commit.no_message", "keine Commit-Nachricht"),
+    ("commit.total", "{} insgesamt"),
+    ("commit.cancel_all", "Alle abbrechen"),
+    ("commit.initial", "erster Commit"),
+    ("commit.file_changed", "1 Datei geändert"),
+    ("commit.files_changed", "{} Dateien geändert"),
+    ("package.nixos_config", "NixOS-Konfiguration"),
+    ("package.build_result", "→ Build-Ergebnis"),
+    (
+        "package.result_missing",
+        "Das Ergebnis wurde bei der Garbage Collection aus dem Store gelöscht, ein neuer Build stellt es wieder her",
+    ),
+    ("package.usually_takes", "dauert meist ~{}"),
+    ("details.flake_url", "Flake-URL"),
+    ("details.result", "Ergebnis"),
+    ("details.log", "Log"),
+    ("details.open_log", "Rohes Log öffnen"),
+    ("debug.repo", "Debug-Infos zum Repository"),
+    ("debug.commit", "Debug-Infos zum Commit"),
+    ("debug.package", "Debug-Infos zum Paket"),
+    ("debug.builder", "Builder des letzten Versuchs"),
+    ("debug.builder_result", "Builder des Ergebnisses"),
+    (
+        "debug.builder_unknown",
+        "Builder {}, dem Server nicht bekannt",
+    ),
+    ("table.repository", "Repository"),
+    ("table.package_path", "Paketpfad"),
+    ("table.branch", "Branch"),
+    ("table.commit", "Commit"),
+    ("table.duration", "Dauer"),
+    ("table.status", "Status"),
+    ("status.idle", "Inaktiv"),
+    ("status.unsupported", "Nicht unterstützte Architektur ({})"),
+    ("status.waiting_window", "Wartet auf Build-Zeitfenster"),
+    ("status.waiting", "Wartet auf Build"),
+    ("status.building", "Baut"),
+    ("status.success", "Erfolgreich"),
+    ("status.failed", "Fehlgeschlagen"),
+    ("status.cancelled", "Abgebrochen"),
+    ("status.dry_run", "Probelauf: {} zu bauen, {} zu laden"),
+    ("push.pushing", "Wird nach {} übertragen"),
+    ("push.pushed", "Nach {} übertragen"),
+    ("push.failed", "Übertragen nach {} fehlgeschlagen: {}"),
+    ("history.success", "{} erfolgreich {} in {}"),
+    ("history.failed", "{} fehlgeschlagen {} nach {}"),
+    ("history.cancelled", "{} abgebrochen {} nach {}"),
+    ("attempts.summary", "Versuch {} von {}"),
+    (
+        "attempts.summary_failed",
+        "Versuch {} von {}, {} zuvor fehlgeschlagen",
+    ),
+    (
+        "attempts.log_expired",
+        "Das Log dieses Versuchs ist abgelaufen",
+    ),
+    ("repo_status.cloning", "Klont"),
+    ("repo_status.opening", "Öffnet"),
+    ("repo_status.idle", "Inaktiv"),
+    ("repo_status.pulling", "Holt Änderungen"),
+    ("repo_status.polling", "Fragt ab"),
+    ("poll.unchanged", "keine Änderungen"),
+    ("poll.changed", "neue Commits"),
+    ("poll.failed", "fehlgeschlagen: {}"),
+    ("poll.next", "nächste Abfrage {}"),
+    ("export.menu", "Exportieren"),
+    ("archive.title", "Archiv"),
+    ("archive.show", "Archivierte Commits anzeigen"),
+    ("archive.empty", "Noch keine Commits archiviert"),
+    ("archive.month_title", "{} komprimiert"),
+    ("archive.date", "Datum"),
+    ("archive.repo", "Repo"),
+    ("archive.commit", "Commit"),
+    ("archive.message", "Nachricht"),
+    ("archive.packages", "Pakete"),
+    ("pending.building", "{} im Bau"),
+    ("pending.queued", "{} in der Warteschlange"),
+    ("pending.failed_today", "{} heute fehlgeschlagen"),
+    ("pending.separator", " · "),
+    ("shortcuts.title", "Tastenkürzel"),
+    ("shortcuts.next", "Nächste Zeile auswählen"),
+    ("shortcuts.previous", "Vorherige Zeile auswählen"),
+    ("shortcuts.toggle", "Ausgewählte Zeile auf- oder zuklappen"),
+    ("shortcuts.search", "Suchfeld fokussieren"),
+    (
+        "shortcuts.failures",
+        "Nur Fehlschläge zeigen, oder wieder alles",
+    ),
+    (
+        "shortcuts.retry",
+        "Ausgewähltes fehlgeschlagenes Paket erneut bauen",
+    ),
+    ("shortcuts.help", "Diese Übersicht zeigen"),
+    ("shortcuts.close", "Diese Übersicht schließen"),
+    ("shortcuts.dismiss", "Schließen"),
+];
+
+fn stored() -> Option<Locale> {
+    let storage = super::local_storage()?;
+    Locale::parse(&storage.get_item(STORAGE_KEY).ok()??)
+}
+
+fn browser() -> Option<Locale> {
+    Locale::parse(&web_sys::window()?.navigator().language()?)
+}
+
+thread_local! {
+    /// Picking another locale reloads the page, so it is chosen once
+    static LOCALE: OnceCell<Locale> = const { OnceCell::new() };
+    static STRINGS: OnceCell<HashMap<&'static str, &'static str>> = const { OnceCell::new() };
+}
+
+pub fn locale() -> Locale {
+    LOCALE.with(|locale| *locale.get_or_init(|| stored().or_else(browser).unwrap_or(Locale::En)))
+}
+
+/// The string of `key` in the current locale
+pub fn t(key: &'static str) -> &'static str {
+    STRINGS.with(|strings| {
+        let strings = strings.get_or_init(|| {
+            EN.iter()
+                .chain(locale().strings())
+                .copied()
+                .collect::<HashMap<_, _>>()
+        });
+        strings.get(key).copied().unwrap_or(key)
+    })
+}
+
+/// `t(key)` with its `{}` replaced by `args` in order
+pub fn t_args(key: &'static str, args: &[&dyn std::fmt::Display]) -> String {
+    let mut parts = t(key).split("{}");
+    let mut text = parts.next().unwrap_or_default().to_string();
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            text.push_str(&arg.to_string());
+        }
+        text.push_str(part);
+    }
+    text
+}
+
+pub fn status_label(status: &PackageBuildStatus) -> String {
+    match status {
+        PackageBuildStatus::Idle => t("status.idle").to_string(),
+        PackageBuildStatus::UnsupportedArchitecture(arch) => t_args("status.unsupported", &[arch]),
+        PackageBuildStatus::WaitingForWindow => t("status.waiting_window").to_string(),
+        PackageBuildStatus::WaitingForBuild => t("status.waiting").to_string(),
+        PackageBuildStatus::Building => t("status.building").to_string(),
+        PackageBuildStatus::Success(_) => t("status.success").to_string(),
+        PackageBuildStatus::Failed(_) => t("status.failed").to_string(),
+        PackageBuildStatus::Cancelled => t("status.cancelled").to_string(),
+        PackageBuildStatus::DryRun {
+            to_build,
+            to_substitute,
+        } => t_args("status.dry_run", &[to_build, to_substitute]),
+    }
+}
+
+pub fn repo_status_label(status: &RepoStatus) -> &'static str {
+    match status {
+        RepoStatus::Cloning => t("repo_status.cloning"),
+        RepoStatus::Opening => t("repo_status.opening"),
+        RepoStatus::Idle => t("repo_status.idle"),
+        RepoStatus::Pulling => t("repo_status.pulling"),
+        RepoStatus::Polling => t("repo_status.polling"),
+    }
+}
+
+/// Remembers the choice and reloads, the strings are only looked up once
+#[function_component]
+pub fn LanguageSelect() -> Html {
+    let onchange = Callback::from(|event: Event| {
+        let code = event.target_unchecked_into::<HtmlSelectElement>().value();
+        if let Some(storage) = super::local_storage() {
+            let _ = storage.set_item(STORAGE_KEY, &code);
+        }
+        if let Some(window) = web_sys::window() {
+            let _ = window.location().reload();
+        }
+    });
+    let current = locale();
+    html! {
+        <select class="poll-button language-select" aria-label={t("app.language")} {onchange}>
+            { for LOCALES.into_iter().map(|locale| html! {
+                <option value={locale.code()} selected={locale == current}>
+                    { locale.name() }
+                </option>
+            }) }
+        </select>
+    }
+}
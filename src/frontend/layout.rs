@@ -0,0 +1,48 @@
+//! Narrow screens get the table as one card per row, the breakpoint lives here
+//! instead of a media query because the rows render differently too
+
+use wasm_bindgen::{JsCast, prelude::Closure};
+use web_sys::MediaQueryList;
+use yew::prelude::*;
+
+/// Widest viewport in CSS pixels that shows the table as cards
+pub const CARD_ROWS_MAX_WIDTH: u32 = 720;
+
+fn media_query() -> Option<MediaQueryList> {
+    let query = format!("(max-width: {}px)", CARD_ROWS_MAX_WIDTH);
+    web_sys::window()?.match_media(&query).ok()?
+}
+
+/// Whether the viewport is at most `CARD_ROWS_MAX_WIDTH` wide, following resizes
+/// and rotations
+#[hook]
+pub fn use_narrow() -> bool {
+    let narrow = use_state(|| media_query().is_some_and(|query| query.matches()));
+    {
+        let narrow = narrow.clone();
+        use_effect_with((), move |_| {
+            let query = media_query();
+            let onchange = Closure::<dyn FnMut()>::new({
+                let query = query.clone();
+                move || {
+                    if let Some(query) = &query {
+                        narrow.set(query.matches());
+                    }
+                }
+            });
+            if let Some(query) = &query {
+                let _ = query
+                    .add_event_listener_with_callback("change", onchange.as_ref().unchecked_ref());
+            }
+            move || {
+                if let Some(query) = query {
+                    let _ = query.remove_event_listener_with_callback(
+                        "change",
+                        onchange.as_ref().unchecked_ref(),
+                    );
+                }
+            }
+        });
+    }
+    *narrow
+}
@@ -0,0 +1,466 @@
+//! Build log panel of a package. Only the end of the log is fetched at first, earlier
+//! parts are loaded on request with range requests, so big logs do not block the tab.
+
+use std::{cell::RefCell, rc::Rc};
+
+use gloo_net::http::Request;
+use gloo_timers::callback::Interval;
+use wasm_bindgen_futures::spawn_local;
+use web_sys::Element;
+use yew::prelude::*;
+
+use super::{
+    API_PREFIX,
+    ansi::{self, Style},
+    base_path, clipboard,
+};
+
+/// Bytes fetched at once, the end of the log first
+const CHUNK_SIZE: u64 = 256 * 1024;
+/// How often the log of a running build is checked for new output
+const FOLLOW_MS: u32 = 2000;
+/// Pixels from the bottom that still count as following the output
+const BOTTOM_SLACK: i32 = 16;
+
+pub fn log_url(repo_name: &str, commit: &str, path: &str) -> String {
+    format!(
+        "{}{}/repos/{}/commits/{}/packages/{}/log",
+        base_path(),
+        API_PREFIX,
+        repo_name,
+        commit,
+        path
+    )
+}
+
+#[derive(Properties, PartialEq)]
+pub struct LogViewerProps {
+    pub repo_name: String,
+    pub commit: String,
+    pub path: String,
+    /// the build did not finish yet, new output is fetched until it does
+    pub building: bool,
+}
+
+/// Decoded part of the log, rendered only once
+struct SegmentData {
+    /// byte offset in the log, also the key of the rendered part
+    offset: u64,
+    text: String,
+    /// style in effect at the start, colors may continue from the previous part
+    style: Style,
+    end_style: Style,
+}
+
+#[derive(Clone)]
+struct Segment(Rc<SegmentData>);
+
+impl Segment {
+    fn new(offset: u64, text: String, style: Style) -> Self {
+        let (_, end_style) = ansi::parse(&text, style);
+        Segment(Rc::new(SegmentData {
+            offset,
+            text,
+            style,
+            end_style,
+        }))
+    }
+}
+
+impl PartialEq for Segment {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+#[derive(Default)]
+struct LogState {
+    /// loaded text, oldest first
+    segments: Vec<Segment>,
+    /// byte offset of the first loaded byte
+    start: u64,
+    /// byte offset after the last loaded byte
+    end: u64,
+    /// end of a character cut off by `start`, completed by the earlier chunk
+    head: Vec<u8>,
+    /// start of a character cut off by `end`, completed by the next chunk
+    tail: Vec<u8>,
+    /// the end of the log was fetched, following may start
+    loaded: bool,
+    following: bool,
+    loading_earlier: bool,
+    error: Option<String>,
+    /// the pane is scrolled to the bottom and stays there as output arrives
+    stick_to_bottom: bool,
+    /// scroll height before earlier output was prepended, to keep the position
+    prepended_from: Option<i32>,
+}
+
+#[function_component]
+pub fn LogViewer(props: &LogViewerProps) -> Html {
+    let url = log_url(&props.repo_name, &props.commit, &props.path);
+    let state = use_mut_ref(|| LogState {
+        stick_to_bottom: true,
+        ..LogState::default()
+    });
+    let update = use_force_update();
+    let pane = use_node_ref();
+    let copy_label = use_state(|| "Copy");
+
+    {
+        let url = url.clone();
+        let state = state.clone();
+        let update = update.clone();
+        let building = props.building;
+        use_effect_with((), move |_| {
+            spawn_local(load_tail(url, state, update, building));
+        });
+    }
+
+    {
+        let url = url.clone();
+        let state = state.clone();
+        let update = update.clone();
+        use_effect_with(props.building, move |building| {
+            let interval = if *building {
+                Some(Interval::new(FOLLOW_MS, move || {
+                    spawn_local(follow(url.clone(), state.clone(), update.clone()));
+                }))
+            } else {
+                // output written just before the build finished
+                spawn_local(follow(url, state, update));
+                None
+            };
+            move || drop(interval)
+        });
+    }
+
+    {
+        let state = state.clone();
+        let pane = pane.clone();
+        use_effect(move || {
+            let Some(pane) = pane.cast::<Element>() else {
+                return;
+            };
+            let mut state = state.borrow_mut();
+            if let Some(height) = state.prepended_from.take() {
+                pane.set_scroll_top(pane.scroll_top() + pane.scroll_height() - height);
+            } else if state.stick_to_bottom {
+                pane.set_scroll_top(pane.scroll_height());
+            }
+        });
+    }
+
+    let onscroll = {
+        let state = state.clone();
+        let pane = pane.clone();
+        Callback::from(move |_: Event| {
+            if let Some(pane) = pane.cast::<Element>() {
+                state.borrow_mut().stick_to_bottom =
+                    pane.scroll_top() + pane.client_height() >= pane.scroll_height() - BOTTOM_SLACK;
+            }
+        })
+    };
+
+    let load_earlier = {
+        let url = url.clone();
+        let state = state.clone();
+        let update = update.clone();
+        let pane = pane.clone();
+        Callback::from(move |_: MouseEvent| {
+            if let Some(pane) = pane.cast::<Element>() {
+                state.borrow_mut().prepended_from = Some(pane.scroll_height());
+            }
+            spawn_local(load_earlier(url.clone(), state.clone(), update.clone()));
+        })
+    };
+
+    let copy = {
+        let url = url.clone();
+        let copy_label = copy_label.clone();
+        Callback::from(move |_: MouseEvent| {
+            let url = url.clone();
+            let copy_label = copy_label.clone();
+            spawn_local(async move {
+                copy_label.set(match copy_log(&url).await {
+                    Ok(()) => "Copied",
+                    Err(_) => "Copy failed",
+                });
+            });
+        })
+    };
+
+    let state = state.borrow();
+    let file_name = format!(
+        "{}-{}.log",
+        props.path,
+        &props.commit[..7.min(props.commit.len())]
+    );
+
+    html! {
+        <div class="log-viewer">
+            <div class="log-toolbar">
+                if state.start > 0 {
+                    <button class="poll-button" onclick={load_earlier} disabled={state.loading_earlier}>
+                        { format!("Load earlier output ({} KiB)", state.start.div_ceil(1024)) }
+                    </button>
+                }
+                <a class="result-link" href={url} download={file_name}>{ "Download raw" }</a>
+                <button class="poll-button" onclick={copy}>{ *copy_label }</button>
+                if props.building {
+                    <span class="meta">{ "following" }</span>
+                }
+            </div>
+            if let Some(error) = &state.error {
+                <p class="meta error">{ error }</p>
+            } else if !state.loaded {
+                <p class="meta">{ "Loading log..." }</p>
+            }
+            <pre class="log-output" ref={pane} {onscroll}>
+                { for state.segments.iter().map(|segment| html! {
+                    <LogSegment key={segment.0.offset} segment={segment.clone()} />
+                }) }
+            </pre>
+        </div>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct LogSegmentProps {
+    segment: Segment,
+}
+
+#[function_component]
+fn LogSegment(props: &LogSegmentProps) -> Html {
+    let segment = &props.segment.0;
+    let (runs, _) = ansi::parse(&segment.text, segment.style);
+    html! {
+        { for runs.into_iter().map(|(style, text)| {
+            if style.is_plain() {
+                html! { { text } }
+            } else {
+                html! { <span class={style.classes()} style={style.css()}>{ text }</span> }
+            }
+        }) }
+    }
+}
+
+/// A requested byte range of the log, empty if nothing is there yet
+struct Chunk {
+    start: u64,
+    total: u64,
+    bytes: Vec<u8>,
+}
+
+/// `None` if there is no log yet
+async fn fetch_range(url: &str, range: &str) -> Result<Option<Chunk>, String> {
+    let res = Request::get(url)
+        .header("Range", range)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    match res.status() {
+        206 => {
+            let (start, total) = res
+                .headers()
+                .get("content-range")
+                .as_deref()
+                .and_then(parse_content_range)
+                .ok_or("invalid Content-Range")?;
+            let bytes = res.binary().await.map_err(|e| e.to_string())?;
+            Ok(Some(Chunk {
+                start,
+                total,
+                bytes,
+            }))
+        }
+        // the whole log, e.g. when it is shorter than the requested range
+        200 => {
+            let bytes = res.binary().await.map_err(|e| e.to_string())?;
+            Ok(Some(Chunk {
+                start: 0,
+                total: bytes.len() as u64,
+                bytes,
+            }))
+        }
+        // nothing after the requested offset, tells the current size
+        416 => Ok(res
+            .headers()
+            .get("content-range")
+            .and_then(|range| range.strip_prefix("bytes */")?.trim().parse().ok())
+            .map(|total| Chunk {
+                start: total,
+                total,
+                bytes: Vec::new(),
+            })),
+        // the build did not start yet
+        404 => Ok(None),
+        410 => Err("The log of this build expired".to_string()),
+        status => Err(format!("log request failed with HTTP {}", status)),
+    }
+}
+
+/// `bytes 100-199/1000` -> `(100, 1000)`
+fn parse_content_range(value: &str) -> Option<(u64, u64)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, _) = range.split_once('-')?;
+    Some((start.trim().parse().ok()?, total.trim().parse().ok()?))
+}
+
+/// Number of continuation bytes at the start, belonging to a character before it
+fn continuation_prefix(bytes: &[u8]) -> usize {
+    bytes
+        .iter()
+        .take(3)
+        .take_while(|byte| *byte & 0xC0 == 0x80)
+        .count()
+}
+
+/// Length of a character at the end whose last bytes are still missing
+fn incomplete_suffix(bytes: &[u8]) -> usize {
+    for back in 1..=bytes.len().min(3) {
+        let byte = bytes[bytes.len() - back];
+        if byte & 0xC0 != 0x80 {
+            let len = match byte {
+                0xF0.. => 4,
+                0xE0.. => 3,
+                0xC0.. => 2,
+                _ => 1,
+            };
+            return if len > back { back } else { 0 };
+        }
+    }
+    0
+}
+
+async fn load_tail(
+    url: String,
+    state: Rc<RefCell<LogState>>,
+    update: UseForceUpdateHandle,
+    building: bool,
+) {
+    let chunk = fetch_range(&url, &format!("bytes=-{}", CHUNK_SIZE)).await;
+    let mut log = state.borrow_mut();
+    match chunk {
+        Ok(Some(chunk)) => {
+            let mut bytes = chunk.bytes;
+            let end = chunk.start + bytes.len() as u64;
+            let head = if chunk.start > 0 {
+                bytes.drain(..continuation_prefix(&bytes)).collect()
+            } else {
+                Vec::new()
+            };
+            let tail = bytes.split_off(bytes.len() - incomplete_suffix(&bytes));
+            log.segments = vec![Segment::new(
+                chunk.start,
+                String::from_utf8_lossy(&bytes).into_owned(),
+                Style::default(),
+            )];
+            log.start = chunk.start;
+            log.end = end;
+            log.head = head;
+            log.tail = tail;
+        }
+        Ok(None) if !building => log.error = Some("No log available for this build".to_string()),
+        Ok(None) => {}
+        Err(e) => log.error = Some(e),
+    }
+    log.loaded = true;
+    drop(log);
+    update.force_update();
+}
+
+async fn load_earlier(url: String, state: Rc<RefCell<LogState>>, update: UseForceUpdateHandle) {
+    let (start, end) = {
+        let mut log = state.borrow_mut();
+        if log.loading_earlier || log.start == 0 {
+            return;
+        }
+        log.loading_earlier = true;
+        (log.start.saturating_sub(CHUNK_SIZE), log.start - 1)
+    };
+    update.force_update();
+    let chunk = fetch_range(&url, &format!("bytes={}-{}", start, end)).await;
+    let mut log = state.borrow_mut();
+    log.loading_earlier = false;
+    match chunk {
+        Ok(Some(chunk)) if chunk.start == start && chunk.bytes.len() as u64 == end - start + 1 => {
+            let mut bytes = chunk.bytes;
+            bytes.append(&mut log.head);
+            if start > 0 {
+                log.head = bytes.drain(..continuation_prefix(&bytes)).collect();
+            }
+            log.segments.insert(
+                0,
+                Segment::new(
+                    start,
+                    String::from_utf8_lossy(&bytes).into_owned(),
+                    Style::default(),
+                ),
+            );
+            log.start = start;
+        }
+        Ok(_) => log.error = Some("The log changed, reopen it to see the new one".to_string()),
+        Err(e) => log.error = Some(e),
+    }
+    drop(log);
+    update.force_update();
+}
+
+/// Append the output written since the last fetch
+async fn follow(url: String, state: Rc<RefCell<LogState>>, update: UseForceUpdateHandle) {
+    let end = {
+        let mut log = state.borrow_mut();
+        if !log.loaded || log.following {
+            return;
+        }
+        log.following = true;
+        log.end
+    };
+    let chunk = fetch_range(&url, &format!("bytes={}-", end)).await;
+    let mut log = state.borrow_mut();
+    log.following = false;
+    let Ok(Some(chunk)) = chunk else {
+        return;
+    };
+    if chunk.total < end || (chunk.start != end && !chunk.bytes.is_empty()) {
+        // the log was rewritten, e.g. by a new build of the package
+        *log = LogState {
+            stick_to_bottom: log.stick_to_bottom,
+            ..LogState::default()
+        };
+        drop(log);
+        spawn_local(load_tail(url, state, update, true));
+        return;
+    }
+    if chunk.bytes.is_empty() {
+        return;
+    }
+    log.end = end + chunk.bytes.len() as u64;
+    let mut bytes = std::mem::take(&mut log.tail);
+    bytes.extend_from_slice(&chunk.bytes);
+    log.tail = bytes.split_off(bytes.len() - incomplete_suffix(&bytes));
+    let style = log
+        .segments
+        .last()
+        .map_or(Style::default(), |segment| segment.0.end_style);
+    log.segments.push(Segment::new(
+        end,
+        String::from_utf8_lossy(&bytes).into_owned(),
+        style,
+    ));
+    drop(log);
+    update.force_update();
+}
+
+/// Copy the complete log, not only the loaded part
+async fn copy_log(url: &str) -> Result<(), String> {
+    let text = Request::get(url)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?
+        .text()
+        .await
+        .map_err(|e| e.to_string())?;
+    clipboard::write(&text).await
+}
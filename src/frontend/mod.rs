@@ -1,32 +1,186 @@
-use std::collections::BTreeMap;
+use std::{
+    cell::{Cell, RefCell},
+    collections::BTreeMap,
+    rc::Rc,
+};
 
 use crate::{
     RepoList,
-    commit::CommitInfo,
-    package::{self, PackageBuildStatus, PackageEnum},
+    commit::{CommitInfo, DiffStats},
+    event::{RepoChanges, StateEvent},
+    package::{self, AggregateStatus, BuildAttempt, BuildTimes, PackageBuildStatus, PackageEnum},
+    queue::BuildQueue,
     repo::{self, RepoInfo},
+    serialize::VecArcWrapper,
 };
-use gloo_timers::callback::Interval;
+use gloo_timers::callback::{Interval, Timeout};
 use serde::de;
 use serde_json;
-use wasm_bindgen::JsCast;
+use wasm_bindgen::{JsCast, JsValue, prelude::Closure};
 use wasm_bindgen_futures::JsFuture;
-use web_sys::Response;
+use web_sys::{
+    Element, Headers, MessageEvent, Request, RequestInit, Response, ScrollRestoration, Storage,
+    WebSocket,
+    js_sys::{Date, encode_uri_component},
+};
 use yew::prelude::*;
 
-// Fetch the repo list via Fetch API and return deserialized RepoList
-async fn fetch_repos() -> Result<RepoList, String> {
+mod ansi;
+mod archive;
+mod attempts;
+mod builder_info;
+mod cancel;
+mod clipboard;
+mod closure_size;
+mod dev_mode;
+mod duration;
+mod expanded;
+mod export;
+mod favicon;
+mod filter;
+mod forge;
+mod history;
+mod i18n;
+mod layout;
+mod log_viewer;
+mod notifications;
+mod pager;
+mod poll_history;
+mod pushes;
+mod queue;
+mod refresh;
+mod retry;
+mod shortcuts;
+mod stale;
+mod summary;
+mod table_sort;
+mod theme;
+mod time_ago;
+mod toast;
+
+use archive::ArchiveBrowser;
+use attempts::{attempt_logs_html, attempts_summary_html};
+use builder_info::BuilderDetails;
+use cancel::CancelButton;
+use clipboard::{CopyButton, CopyText};
+use closure_size::closure_size_html;
+use duration::{BuildDuration, humanize, wall_clock};
+use export::{ExportRow, export_html};
+use favicon::TabStatus;
+use filter::{
+    CATEGORIES, Filter, FilterControls, StatusChips, category, highlight, nothing_matches,
+    parse_categories, store_categories, stored_categories,
+};
+use history::BuildHistory;
+use i18n::{LanguageSelect, repo_status_label, status_label, t, t_args};
+use layout::use_narrow;
+use log_viewer::LogViewer;
+use notifications::{FailureWatch, NotificationBell};
+use pager::{TablePage, pager_html};
+use poll_history::poll_history_html;
+use pushes::pushes_html;
+use queue::{QueueInfo, QueuePosition};
+use refresh::{RefreshRate, RefreshSelect, use_page_visible};
+use retry::{RebuildAccessProvider, RetryButton};
+use shortcuts::{SelectedRow, Shortcut, ShortcutHelp, use_shortcuts};
+use stale::{Outage, StaleBanner};
+use summary::{pending_html, summary_html};
+use table_sort::{SortColumn, TableSort, branch_of, header_html, sort_rows};
+use theme::ThemeToggle;
+use time_ago::TimeAgo;
+use toast::ToastProvider;
+
+/// Prefix of the versioned backend API
+const API_PREFIX: &str = "/api/v1";
+
+/// Path the frontend is served below, e.g. `/autobuild` behind a reverse proxy and
+/// empty at the root. The frontend only uses query parameters, so it is the
+/// directory of the current page.
+fn base_path() -> String {
+    web_sys::window()
+        .and_then(|window| window.location().pathname().ok())
+        .and_then(|path| path.rsplit_once('/').map(|(base, _)| base.to_string()))
+        .unwrap_or_default()
+}
+
+/// Where the theme and other choices of the user are kept, `None` if the browser
+/// forbids it
+fn local_storage() -> Option<Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+/// Result of a conditional fetch
+enum Fetched<T> {
+    /// with the `ETag` to send next time, `None` if the server has none
+    Changed(T, Option<String>),
+    NotModified,
+}
+
+/// Why a fetch failed, shown differently to the user
+#[derive(Debug, Clone, PartialEq)]
+enum FetchError {
+    /// the server is unreachable or answered with an error
+    Network(String),
+    /// the answer does not match this frontend, e.g. after an update of the server
+    Parse(String),
+}
+
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FetchError::Network(e) | FetchError::Parse(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Fetch `path` via the Fetch API and return the deserialized JSON, unless it still
+/// matches `etag`
+async fn fetch_json<T: de::DeserializeOwned>(
+    path: &str,
+    etag: Option<&str>,
+) -> Result<Fetched<T>, FetchError> {
+    let text = fetch_text(path, etag).await.map_err(FetchError::Network)?;
+    let Some(text) = text else {
+        return Ok(Fetched::NotModified);
+    };
+    serde_json::from_str(&text.0)
+        .map(|value| Fetched::Changed(value, text.1))
+        .map_err(|e| FetchError::Parse(format!("failed to parse json: {e}")))
+}
+
+/// Body and `ETag` of `path`, `None` if it still matches `etag`
+async fn fetch_text(
+    path: &str,
+    etag: Option<&str>,
+) -> Result<Option<(String, Option<String>)>, String> {
     let window = web_sys::window().ok_or_else(|| "no window available".to_string())?;
     let location = window.location();
     let protocol = location.protocol().map_err(|_| "no protocol".to_string())?;
     let host = location.host().map_err(|_| "no host".to_string())?;
-    let url = format!("{}//{}/repos", protocol, host);
-    let resp_value = JsFuture::from(window.fetch_with_str(&url))
+    let url = format!("{}//{}{}{}", protocol, host, base_path(), path);
+    let init = RequestInit::new();
+    if let Some(etag) = etag {
+        let headers = Headers::new().map_err(|e| format!("creating headers failed: {e:?}"))?;
+        headers
+            .set("If-None-Match", etag)
+            .map_err(|e| format!("setting If-None-Match failed: {e:?}"))?;
+        init.set_headers(&headers);
+    }
+    let request = Request::new_with_str_and_init(&url, &init)
+        .map_err(|e| format!("creating request failed: {e:?}"))?;
+    let resp_value = JsFuture::from(window.fetch_with_request(&request))
         .await
         .map_err(|e| format!("fetch failed: {e:?}"))?;
     let resp: Response = resp_value
         .dyn_into()
         .map_err(|_| "failed to cast response".to_string())?;
+    if resp.status() == 304 {
+        return Ok(None);
+    }
+    if !resp.ok() {
+        return Err(format!("server answered {}", resp.status()));
+    }
+    let etag = resp.headers().get("ETag").ok().flatten();
 
     let text_promise = resp
         .text()
@@ -38,8 +192,251 @@ async fn fetch_repos() -> Result<RepoList, String> {
     let text = text_js
         .as_string()
         .ok_or_else(|| "response not text".to_string())?;
+    Ok(Some((text, etag)))
+}
 
-    serde_json::from_str(&text).map_err(|e| format!("failed to parse json: {e}"))
+/// Ask the backend to poll a repo now, the new status arrives with the next update
+async fn request_poll(repo_name: &str) -> Result<(), String> {
+    let window = web_sys::window().ok_or_else(|| "no window available".to_string())?;
+    let init = RequestInit::new();
+    init.set_method("POST");
+    let url = format!("{}{}/repos/{}/poll", base_path(), API_PREFIX, repo_name);
+    JsFuture::from(window.fetch_with_str_and_init(&url, &init))
+        .await
+        .map_err(|e| format!("fetch failed: {e:?}"))?;
+    Ok(())
+}
+
+/// Milliseconds to wait before the first reconnect of a dropped WebSocket, doubled
+/// after every failed attempt up to `SOCKET_RECONNECT_MAX_MS`
+const SOCKET_RECONNECT_MS: u32 = 1000;
+const SOCKET_RECONNECT_MAX_MS: u32 = 30_000;
+/// Failed connection attempts after which the header shows polling instead of
+/// reconnecting
+const POLLING_AFTER_ATTEMPTS: u32 = 3;
+/// Status events arriving in this many milliseconds are rendered together
+const RENDER_DELAY_MS: u32 = 200;
+
+/// How updates currently arrive, shown in the header
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Connection {
+    Connecting,
+    /// over the WebSocket
+    Live,
+    Reconnecting,
+    /// the WebSocket can not be established, the interval refetches the changes
+    Polling,
+}
+
+type ConnectionHandle = UseStateHandle<Connection>;
+
+/// Shared between the WebSocket handlers and the polling interval
+#[derive(Default)]
+struct LiveState {
+    socket_open: Cell<bool>,
+    /// connection attempts that failed since the socket was last open
+    failed_attempts: Cell<u32>,
+    /// status events were applied to `repos` but not rendered yet
+    render_scheduled: Cell<bool>,
+    /// an incremental event arrived since the last fetch
+    dirty: Cell<bool>,
+    /// a fetch is running, overlapping ones would apply the same changes twice
+    fetching: Cell<bool>,
+    /// version of `repos`, `None` until the first snapshot arrived
+    version: Cell<Option<u64>>,
+    /// of the last fetched changes, the server answers 304 while nothing changed
+    etag: RefCell<Option<String>>,
+    repos: RefCell<Option<RepoList>>,
+    /// when the server last confirmed the state, in milliseconds since the epoch
+    last_updated: Cell<Option<f64>>,
+    /// fetches fail while the last state stays on the page
+    outage: RefCell<Option<Outage>>,
+    /// the page keeps its state, events only mark it dirty for the catch-up
+    paused: Cell<bool>,
+    /// bumped with every state handed to the page, values derived from it are
+    /// memoized on it
+    generation: Cell<u64>,
+}
+
+impl LiveState {
+    fn show(&self, data: &DataHandle, repos: RepoList) {
+        self.generation.set(self.generation.get() + 1);
+        data.set(Some(Ok(repos)));
+    }
+
+    fn mark_updated(&self) {
+        self.last_updated.set(Some(Date::now()));
+        self.outage.take();
+    }
+
+    /// A dismissed banner stays hidden until the next outage
+    fn fail(&self, error: FetchError) {
+        let mut outage = self.outage.borrow_mut();
+        match outage.as_mut() {
+            Some(outage) => outage.error = error,
+            None => *outage = Some(Outage::new(error)),
+        }
+    }
+}
+
+/// Fetch what changed since the last known version and merge it into the state. A
+/// 304 leaves the state alone and only moves the time of the last update.
+fn refresh(data: DataHandle, live: Rc<LiveState>) {
+    if live.fetching.replace(true) {
+        live.dirty.set(true);
+        return;
+    }
+    let path = match live.version.get() {
+        Some(version) => format!("{}/repos/changes?since={}", API_PREFIX, version),
+        None => format!("{}/repos/changes", API_PREFIX),
+    };
+    wasm_bindgen_futures::spawn_local(async move {
+        let etag = live.etag.borrow().clone();
+        let changes = fetch_json::<RepoChanges>(&path, etag.as_deref()).await;
+        live.fetching.set(false);
+        match changes {
+            // keep the state as it is, setting it would render everything again
+            Ok(Fetched::NotModified) => live.mark_updated(),
+            Ok(Fetched::Changed(changes, etag)) => {
+                live.mark_updated();
+                *live.etag.borrow_mut() = etag;
+                live.version.set(Some(changes.version));
+                let mut repos = live.repos.borrow_mut();
+                let repos = repos.get_or_insert_with(|| RepoList(VecArcWrapper::default()));
+                repos.apply_changes(changes);
+                live.show(&data, repos.clone());
+            }
+            // keep showing the last state, the banner tells it is stale
+            Err(e) if live.repos.borrow().is_some() => live.fail(e),
+            Err(e) => data.set(Some(Err(e.to_string()))),
+        }
+    });
+}
+
+type DataHandle = UseStateHandle<Option<Result<RepoList, String>>>;
+
+fn socket_url() -> Option<String> {
+    let location = web_sys::window()?.location();
+    let scheme = match location.protocol().ok()?.as_str() {
+        "https:" => "wss:",
+        _ => "ws:",
+    };
+    Some(format!(
+        "{}//{}{}{}/ws",
+        scheme,
+        location.host().ok()?,
+        base_path(),
+        API_PREFIX
+    ))
+}
+
+/// Receive state over a WebSocket. Snapshots replace the data and status events are
+/// applied to it directly, new commits or packages mark the state dirty so the
+/// polling interval fetches them. While the socket is down the interval polls as
+/// usual.
+fn connect_socket(data: DataHandle, live: Rc<LiveState>, connection: ConnectionHandle) {
+    let Some(url) = socket_url() else {
+        connection.set(Connection::Polling);
+        return;
+    };
+    let Ok(socket) = WebSocket::new(&url) else {
+        reconnect_later(data, live, connection);
+        return;
+    };
+
+    let onopen = Closure::<dyn FnMut()>::new({
+        let live = live.clone();
+        let connection = connection.clone();
+        move || {
+            live.socket_open.set(true);
+            live.failed_attempts.set(0);
+            connection.set(Connection::Live);
+        }
+    });
+    socket.set_onopen(Some(onopen.as_ref().unchecked_ref()));
+    onopen.forget();
+
+    let onmessage = Closure::<dyn FnMut(MessageEvent)>::new({
+        let data = data.clone();
+        let live = live.clone();
+        move |event: MessageEvent| {
+            let Some(text) = event.data().as_string() else {
+                return;
+            };
+            if live.paused.get() {
+                live.dirty.set(true);
+                return;
+            }
+            live.mark_updated();
+            match serde_json::from_str::<StateEvent>(&text) {
+                Ok(StateEvent::Snapshot { version, repos }) => {
+                    live.dirty.set(false);
+                    live.version.set(Some(version));
+                    live.show(&data, repos.clone());
+                    *live.repos.borrow_mut() = Some(repos);
+                }
+                // poll until the socket is back, the close reconnects it
+                Ok(StateEvent::ServerShutdown) => live.socket_open.set(false),
+                // carries no data, the new commits or packages have to be fetched
+                Ok(StateEvent::CommitsChanged { .. } | StateEvent::ReposChanged) | Err(_) => {
+                    live.dirty.set(true)
+                }
+                Ok(event) => {
+                    match live.repos.borrow_mut().as_mut() {
+                        Some(repos) => repos.apply_event(event),
+                        None => {
+                            live.dirty.set(true);
+                            return;
+                        }
+                    }
+                    schedule_render(data.clone(), live.clone());
+                }
+            }
+        }
+    });
+    socket.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+    onmessage.forget();
+
+    // also fires when the connection could not be established at all
+    let onclose = Closure::<dyn FnMut()>::new(move || {
+        live.socket_open.set(false);
+        reconnect_later(data.clone(), live.clone(), connection.clone());
+    });
+    socket.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+    onclose.forget();
+}
+
+/// Try again with exponential backoff
+fn reconnect_later(data: DataHandle, live: Rc<LiveState>, connection: ConnectionHandle) {
+    let attempts = live.failed_attempts.get() + 1;
+    live.failed_attempts.set(attempts);
+    connection.set(if attempts >= POLLING_AFTER_ATTEMPTS {
+        Connection::Polling
+    } else {
+        Connection::Reconnecting
+    });
+    let delay = SOCKET_RECONNECT_MS
+        .saturating_mul(1 << (attempts - 1).min(15))
+        .min(SOCKET_RECONNECT_MAX_MS);
+    Timeout::new(delay, move || connect_socket(data, live, connection)).forget();
+}
+
+/// Applies a status event locally before the backend confirms it, provided by `App`
+#[derive(Clone, PartialEq)]
+struct ApplyEvent(Callback<StateEvent>);
+
+/// Render the applied status events once a burst of them is over
+fn schedule_render(data: DataHandle, live: Rc<LiveState>) {
+    if live.render_scheduled.replace(true) {
+        return;
+    }
+    Timeout::new(RENDER_DELAY_MS, move || {
+        live.render_scheduled.set(false);
+        if let Some(repos) = live.repos.borrow().as_ref() {
+            live.show(&data, repos.clone());
+        }
+    })
+    .forget();
 }
 
 fn repos(repos: &RepoList, props: &Props) -> Html {
@@ -84,6 +481,7 @@ fn repos(repos: &RepoList, props: &Props) -> Html {
     for repo in repos.0.0.iter() {
         grouped.insert(repo.repo.url.clone(), (repo, BTreeMap::new()));
     }
+    let filter = &props.filter;
 
     for package in &all_packages {
         let repo_url = package.repo.repo.url.clone();
@@ -96,9 +494,29 @@ fn repos(repos: &RepoList, props: &Props) -> Html {
             PackageEnum::NixosConfig(arc_wrapper) => arc_wrapper.0.path.clone(),
         };
 
+        // arch -> package_name -> commit -> arch, commits are on any branch
+        if props.group_by_arch {
+            if filter.matches_any_branch(package.repo, package.commit, package.pkg)
+                && let Some(entry) = grouped.get_mut(&repo_url)
+            {
+                entry
+                    .1
+                    .entry(arch.clone())
+                    .or_default()
+                    .entry(package_name)
+                    .or_default()
+                    .entry(package.commit.hash.clone())
+                    .or_default()
+                    .insert(arch, package);
+            }
+            continue;
+        }
+
         // Find which branches contain this commit
         for (branch_name, commit_hashes) in &package.repo.branch_commit_hashes {
-            if commit_hashes.0.contains(&package.commit.hash) {
+            if commit_hashes.0.contains(&package.commit.hash)
+                && filter.matches(package.repo, package.commit, package.pkg, branch_name)
+            {
                 // Entry already exists from initialization above
                 if let Some(entry) = grouped.get_mut(&repo_url) {
                     entry
@@ -115,6 +533,14 @@ fn repos(repos: &RepoList, props: &Props) -> Html {
         }
     }
 
+    // only the repos with matching packages
+    if filter.is_active() {
+        grouped.retain(|_, (_, packages)| !packages.is_empty());
+        if grouped.is_empty() {
+            return nothing_matches();
+        }
+    }
+
     html! {
         <div class="stack">
             { for grouped.iter().map(|(repo_name, repo)| {
@@ -124,6 +550,52 @@ fn repos(repos: &RepoList, props: &Props) -> Html {
     }
 }
 
+/// Changes the route without reloading the page, provided by `App`
+#[derive(Clone, PartialEq)]
+struct Navigator(Callback<Props>);
+
+#[derive(Properties, PartialEq)]
+struct RouteLinkProps {
+    to: Props,
+    #[prop_or_default]
+    children: Html,
+}
+
+/// Link to another route. Plain clicks are handled in place, modified ones (e.g.
+/// opening a new tab) and clicks on links nested inside are left to the browser.
+#[function_component]
+fn RouteLink(props: &RouteLinkProps) -> Html {
+    let navigator = use_context::<Navigator>();
+    let onclick = {
+        let to = props.to.clone();
+        Callback::from(move |event: MouseEvent| {
+            let Some(navigator) = &navigator else {
+                return;
+            };
+            if event.button() != 0
+                || event.ctrl_key()
+                || event.meta_key()
+                || event.shift_key()
+                || event.alt_key()
+            {
+                return;
+            }
+            let clicked_link = event
+                .target()
+                .and_then(|target| target.dyn_into::<Element>().ok())
+                .and_then(|element| element.closest("a").ok().flatten());
+            if clicked_link.map(JsValue::from) != event.current_target().map(JsValue::from) {
+                return;
+            }
+            event.prevent_default();
+            navigator.0.emit(to.clone());
+        })
+    };
+    html! {
+        <a href={props.to.get_url().unwrap_or_default()} {onclick}>{ props.children.clone() }</a>
+    }
+}
+
 // group repo -> package_name -> branch -> commit -> arch -> package info
 fn repo_html(
     repo_name: &str,
@@ -133,36 +605,40 @@ fn repo_html(
     ),
     props: &Props,
 ) -> Html {
-    let status_text = format!("{:?}", repo_data.0.status.0);
-    let status_class = match status_text.as_str() {
-        s if s.contains("Success") => "status-success",
-        s if s.contains("Failed") || s.contains("Failure") => "status-failed",
-        s if s.contains("Building") || s.contains("Running") => "status-building",
-        s if s.contains("Pending") || s.contains("Queued") || s.contains("WaitingForBuild") => {
-            "status-pending"
-        }
-        _ => "status-unknown",
+    let status_text = repo_status_label(&repo_data.0.status.0);
+    let poll_now = {
+        let name = repo_data.0.repo.name();
+        Callback::from(move |_: MouseEvent| {
+            let name = name.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                let _ = request_poll(&name).await;
+            });
+        })
     };
     let is_open = props.repo_name.as_deref() == Some(repo_name);
-    let link_url = if is_open {
-        Props::default().get_url().unwrap_or_default()
+    let link = if is_open {
+        props.clear_from_repo()
     } else {
-        props
-            .with_repo_name(repo_name.to_string())
-            .get_url()
-            .unwrap_or_default()
+        props.with_repo_name(repo_name.to_string())
     };
 
     html! {
         <section class="card">
-            <a href={link_url}>
+            <RouteLink to={link}>
                 <div class="repo-header">
-                    <h2>{ repo_name }</h2>
-                    <span class={classes!("status-indicator", status_class)}>{ status_text }</span>
+                    <h2>{ highlight(&repo_data.0.repo.name(), &props.filter.terms()) }</h2>
+                    <span class="status-indicator status-unknown">{ status_text }</span>
                 </div>
                 <p class="meta">{ &repo_data.0.flake_url }</p>
-            </a>
-            if is_open {
+            </RouteLink>
+            { poll_history_html(repo_data.0) }
+            { branch_chips_html(repo_name, repo_data.0, props) }
+            <button class="poll-button" onclick={poll_now}>{ t("repo.poll_now") }</button>
+            if is_open && props.group_by_arch {
+                { for repo_data.1.iter().map(|(arch, packages)| {
+                    arch_group_html(arch, packages, props)
+                }) }
+            } else if is_open {
                 { for repo_data.1.iter().map(|(package_name, branches)| {
                     package_name_html(package_name, branches, props)
                 }) }
@@ -171,48 +647,132 @@ fn repo_html(
     }
 }
 
+/// Status of the newest commit per configured branch, visible while the repo is
+/// collapsed. A chip opens the branch in every package.
+fn branch_chips_html(repo_name: &str, repo: &RepoInfo, props: &Props) -> Html {
+    html! {
+        <div class="branch-chips">
+            { for repo.repo.branches.iter().map(|branch| {
+                let (symbol, class) = match repo.branch_status(branch) {
+                    Some(AggregateStatus::Success) => ("✓", "status-success"),
+                    Some(AggregateStatus::Failed) => ("✗", "status-failed"),
+                    Some(AggregateStatus::Building) => ("●", "status-building"),
+                    None => ("–", "status-unknown"),
+                };
+                let to = props
+                    .with_repo_name(repo_name.to_string())
+                    .with_branch(branch.clone());
+                html! {
+                    <RouteLink {to}>
+                        <span class={classes!("branch-chip", class)}>
+                            { format!("{} {}", branch, symbol) }
+                        </span>
+                    </RouteLink>
+                }
+            }) }
+        </div>
+    }
+}
+
 fn package_name_html(
-    package_name: &String,
+    package_name: &str,
     branches: &BTreeMap<String, BTreeMap<String, BTreeMap<String, &Package<'_>>>>,
     props: &Props,
 ) -> Html {
-    let is_open = props.package_name.as_deref() == Some(package_name);
-    let link_url = if is_open {
-        props.clear_from_package().get_url().unwrap_or_default()
+    // a branch chip opens its branch in all packages
+    let only_branch = props.package_name.is_none() && props.branch.is_some();
+    let is_open = props.package_name.as_deref() == Some(package_name) || only_branch;
+    let link = if props.package_name.as_deref() == Some(package_name) {
+        props.clear_from_package()
     } else {
-        props
-            .with_package(package_name.clone())
-            .get_url()
-            .unwrap_or_default()
+        props.with_package(package_name.to_string())
     };
 
     html! {
         <div class="card">
-            <a href={link_url}>
-                <h3>{ package_name }</h3>
-            </a>
+            <RouteLink to={link}>
+                <h3>{ highlight(package_name, &props.filter.terms()) }</h3>
+            </RouteLink>
             if is_open {
-                { for branches.iter().map(|(branch_name, commits)| {
-                    branch_html(branch_name, commits, props)
+                { for branches
+                    .iter()
+                    .filter(|(branch_name, _)| !only_branch || props.branch.as_ref() == Some(*branch_name))
+                    .map(|(branch_name, commits)| branch_html(branch_name, commits, props)) }
+            }
+        </div>
+    }
+}
+
+/// Architecture of the view grouped by architecture, with its packages
+fn arch_group_html(
+    arch: &str,
+    packages: &BTreeMap<String, BTreeMap<String, BTreeMap<String, &Package<'_>>>>,
+    props: &Props,
+) -> Html {
+    let is_open = props.arch.as_deref() == Some(arch);
+    let link = if is_open {
+        props.clear_from_package().clear_arch()
+    } else {
+        props.clear_from_package().with_arch(arch.to_string())
+    };
+
+    html! {
+        <div class="card">
+            <RouteLink to={link}>
+                <h3>{ arch }</h3>
+            </RouteLink>
+            if is_open {
+                { for packages.iter().map(|(package_name, commits)| {
+                    arch_package_html(package_name, commits, props)
                 }) }
             }
         </div>
     }
 }
 
+/// Package below an architecture, with its commits of all branches
+fn arch_package_html(
+    package_name: &str,
+    commits: &BTreeMap<String, BTreeMap<String, &Package<'_>>>,
+    props: &Props,
+) -> Html {
+    let is_open = props.package_name.as_deref() == Some(package_name);
+    let link = if is_open {
+        props.clear_from_package()
+    } else {
+        props.with_package(package_name.to_string())
+    };
+    let mut sorted_commits = commits.iter().collect::<Vec<_>>();
+    sorted_commits.sort_by_key(|(_, archs)| {
+        std::cmp::Reverse(archs.values().next().map(|p| p.commit.unix_secs))
+    });
+
+    html! {
+        <div class="card">
+            <RouteLink to={link}>
+                <h4>{ highlight(package_name, &props.filter.terms()) }</h4>
+            </RouteLink>
+            if is_open {
+                <ul>
+                    { for sorted_commits.iter().map(|(commit_hash, archs)| {
+                        commit_html(commit_hash, archs, props)
+                    }) }
+                </ul>
+            }
+        </div>
+    }
+}
+
 fn branch_html(
-    branch_name: &String,
+    branch_name: &str,
     commits: &BTreeMap<String, BTreeMap<String, &Package<'_>>>,
     props: &Props,
 ) -> Html {
     let is_open = props.branch.as_deref() == Some(branch_name);
-    let link_url = if is_open {
-        props.clear_from_branch().get_url().unwrap_or_default()
+    let link = if is_open {
+        props.clear_from_branch()
     } else {
-        props
-            .with_branch(branch_name.clone())
-            .get_url()
-            .unwrap_or_default()
+        props.with_branch(branch_name.to_string())
     };
 
     // Sort commits by timestamp (newest first)
@@ -233,9 +793,9 @@ fn branch_html(
 
     html! {
         <div class="card">
-            <a href={link_url}>
-                <h4>{ branch_name }</h4>
-            </a>
+            <RouteLink to={link}>
+                <h4>{ highlight(branch_name, &props.filter.terms()) }</h4>
+            </RouteLink>
             if is_open {
                 <ul>
                     { for sorted_commits.iter().map(|(commit_hash, archs)| {
@@ -257,22 +817,60 @@ fn commit_html(
         .values()
         .next()
         .map(|p| p.commit.message.as_str())
-        .unwrap_or("no commit message");
+        .unwrap_or(t("commit.no_message"));
     let is_open = props.commit_hash.as_deref() == Some(commit_hash);
-    let link_url = if is_open {
-        props.clear_from_commit().get_url().unwrap_or_default()
+    let link = if is_open {
+        props.clear_from_commit()
     } else {
-        props
-            .with_commit(commit_hash.clone())
-            .get_url()
-            .unwrap_or_default()
+        props.with_commit(commit_hash.clone())
     };
+    let total = wall_clock(archs.values().map(|package| package.pkg.times()));
+    let cancellable = archs
+        .values()
+        .filter(|package| !package.pkg.status().is_finished())
+        .collect::<Vec<_>>();
+    let unix_secs = archs.values().next().map(|p| p.commit.unix_secs);
+    let diff_stats = archs
+        .values()
+        .next()
+        .and_then(|p| p.commit.diff_stats.as_ref());
+    let commit_url = archs
+        .values()
+        .next()
+        .and_then(|p| forge::commit_url(&p.repo.repo, commit_hash));
+    let terms = props.filter.terms();
 
     html! {
         <li class="card">
-            <a href={link_url}>
-                { format!("{} - {}", short_hash, commit_message) }
-            </a>
+            if let Some(commit_url) = commit_url {
+                <a class="commit-link" href={commit_url} target="_blank" rel="noopener">
+                    { highlight(short_hash, &terms) }
+                </a>
+                <RouteLink to={link}>
+                    { highlight(&format!(" - {}", commit_message), &terms) }
+                </RouteLink>
+            } else {
+                <RouteLink to={link}>
+                    { highlight(&format!("{} - {}", short_hash, commit_message), &terms) }
+                </RouteLink>
+            }
+            <CopyButton text={commit_hash.clone()} />
+            if let Some(unix_secs) = unix_secs {
+                <TimeAgo {unix_secs} />
+            }
+            { diff_stats_html(diff_stats) }
+            if let Some(total) = total {
+                <span class="build-duration">{ t_args("commit.total", &[&humanize(total)]) }</span>
+            }
+            if cancellable.len() > 1 {
+                <CancelButton
+                    repo_name={cancellable[0].repo.repo.name()}
+                    commit={commit_hash.clone()}
+                    paths={cancellable.iter().map(|package| package.pkg.path().to_string()).collect::<Vec<_>>()}
+                    running={cancellable.iter().any(|package| matches!(package.pkg.status(), PackageBuildStatus::Building))}
+                    label={t("commit.cancel_all")}
+                />
+            }
             if is_open {
                 <div>
                     { for archs.iter().map(|(arch, package)| {
@@ -284,12 +882,53 @@ fn commit_html(
     }
 }
 
+/// "+120 −40" with the number of files as tooltip
+fn diff_stats_html(diff_stats: Option<&DiffStats>) -> Html {
+    match diff_stats {
+        Some(DiffStats::Initial) => {
+            html! { <span class="diff-stats">{ t("commit.initial") }</span> }
+        }
+        Some(DiffStats::Changed {
+            files_changed,
+            insertions,
+            deletions,
+        }) => {
+            let files = match files_changed {
+                1 => t("commit.file_changed").to_string(),
+                _ => t_args("commit.files_changed", &[files_changed]),
+            };
+            html! {
+                <span class="diff-stats" title={files}>
+                    <span class="insertions">{ format!("+{}", insertions) }</span>
+                    { " " }
+                    <span class="deletions">{ format!("−{}", deletions) }</span>
+                </span>
+            }
+        }
+        None => html! {},
+    }
+}
+
+fn status_class(status: &PackageBuildStatus) -> &'static str {
+    match status {
+        PackageBuildStatus::Success(_) => "status-success",
+        PackageBuildStatus::Failed(_) => "status-failed",
+        PackageBuildStatus::Building => "status-building",
+        PackageBuildStatus::WaitingForWindow | PackageBuildStatus::WaitingForBuild => {
+            "status-pending"
+        }
+        PackageBuildStatus::DryRun { .. } => "status-dry-run",
+        PackageBuildStatus::Idle
+        | PackageBuildStatus::UnsupportedArchitecture(_)
+        | PackageBuildStatus::Cancelled => "status-unknown",
+    }
+}
+
 fn arch_html(arch: &String, package: &Package<'_>, props: &Props) -> Html {
-    let (_name, pkg_type, status_text, result) = match package.pkg {
+    let (_name, pkg_type, result) = match package.pkg {
         PackageEnum::Derivation(arc_wrapper) => (
             arc_wrapper.0.name.clone(),
             arc_wrapper.0.pkg_type.clone(),
-            format!("{:?}", arc_wrapper.0.status.0),
             match &arc_wrapper.0.status.0 {
                 PackageBuildStatus::Success(path) => Some(path.clone()),
                 _ => None,
@@ -297,8 +936,7 @@ fn arch_html(arch: &String, package: &Package<'_>, props: &Props) -> Html {
         ),
         PackageEnum::NixosConfig(arc_wrapper) => (
             arc_wrapper.0.pkg_type.clone(),
-            "NixOS Config".to_string(),
-            format!("{:?}", arc_wrapper.0.status.0),
+            t("package.nixos_config").to_string(),
             match (&arc_wrapper.0.status.0) {
                 PackageBuildStatus::Success(path) => Some(path.clone()),
                 _ => None,
@@ -306,50 +944,147 @@ fn arch_html(arch: &String, package: &Package<'_>, props: &Props) -> Html {
         ),
     };
 
-    let status_class = match status_text.as_str() {
-        s if s.contains("Success") => "status-success",
-        s if s.contains("Failed") || s.contains("Failure") => "status-failed",
-        s if s.contains("Building") || s.contains("Running") => "status-building",
-        s if s.contains("Pending") || s.contains("Queued") || s.contains("WaitingForBuild") => {
-            "status-pending"
-        }
-        _ => "status-unknown",
-    };
+    let store_path = result.clone();
+    // store paths are served below the base path too
+    let result = result.map(|path| format!("{}{}", base_path(), path));
+    let status = package.pkg.status();
 
     let is_selected = props.arch.as_deref() == Some(arch);
-    let link_url = if is_selected {
-        props.clear_arch().get_url().unwrap_or_default()
+    // the architecture is already open above the commit when grouped by it
+    let link = if props.group_by_arch {
+        props.clear_from_commit()
+    } else if is_selected {
+        props.clear_arch()
     } else {
-        props.with_arch(arch.clone()).get_url().unwrap_or_default()
+        props.with_arch(arch.clone())
     };
 
     html! {
         <div class="card">
-            <a href={link_url}>
+            <RouteLink to={link}>
                 <div class="pkg-header">
                     <p>{ format!("{} ({})", arch, pkg_type) }</p>
-                    <span class={classes!("status-indicator", status_class)}>{ status_text }</span>
+                    <BuildDuration times={package.pkg.times()} />
+                    if let Some(secs) = package.pkg.expected_secs() {
+                        <span class="expected-duration">
+                            { t_args("package.usually_takes", &[&humanize(secs as i64)]) }
+                        </span>
+                    }
+                    { closure_size_html(package.repo, package.commit, package.pkg) }
+                    { pushes_html(package.pkg) }
+                    // one request per opened card instead of one per card shown
+                    if is_selected {
+                        <BuildHistory
+                            repo_name={package.repo.repo.name()}
+                            path={package.pkg.path().to_string()}
+                            status={status.clone()}
+                        />
+                    }
+                    if matches!(package.pkg.status(), PackageBuildStatus::WaitingForBuild) {
+                        <QueuePosition
+                            repo_url={package.repo.repo.url.clone()}
+                            commit={package.commit.hash.clone()}
+                            path={package.pkg.path().to_string()}
+                        />
+                    }
+                    <span class={classes!("status-indicator", status_class(&status))}>
+                        { status_label(&status) }
+                    </span>
+                    { attempts_summary_html(&package.pkg.attempts()) }
+                    if let PackageBuildStatus::Failed(error) = package.pkg.status() {
+                        <RetryButton
+                            repo_url={package.repo.repo.url.clone()}
+                            repo_name={package.repo.repo.name()}
+                            commit={package.commit.hash.clone()}
+                            path={package.pkg.path().to_string()}
+                            status={PackageBuildStatus::Failed(error)}
+                            times={package.pkg.times()}
+                        />
+                    }
+                    if !package.pkg.status().is_finished() {
+                        <CancelButton
+                            repo_name={package.repo.repo.name()}
+                            commit={package.commit.hash.clone()}
+                            paths={vec![package.pkg.path().to_string()]}
+                            running={matches!(package.pkg.status(), PackageBuildStatus::Building)}
+                        />
+                    }
                 </div>
                 if let Some(result_path) = result {
-                    <p class="meta">
-                        <a href={result_path.clone()} class="result-link">{ "→ Build Result" }</a>
-                    </p>
+                    if package.pkg.result_missing() {
+                        <p class="meta result-missing" title={t("package.result_missing")}>
+                            <span class="result-link">{ t("package.build_result") }</span>
+                            <RetryButton
+                                repo_url={package.repo.repo.url.clone()}
+                                repo_name={package.repo.repo.name()}
+                                commit={package.commit.hash.clone()}
+                                path={package.pkg.path().to_string()}
+                                status={package.pkg.status()}
+                                times={package.pkg.times()}
+                            />
+                        </p>
+                    } else {
+                        <p class="meta">
+                            <a href={result_path.clone()} class="result-link">{ t("package.build_result") }</a>
+                            <a href={format!("{}?archive=tar.gz", result_path)} class="result-link archive-link">{ "tar.gz" }</a>
+                            <a href={format!("{}?archive=zip", result_path)} class="result-link archive-link">{ "zip" }</a>
+                        </p>
+                    }
+                }
+            </RouteLink>
+            <div class="copy-list">
+                if let Some(store_path) = store_path {
+                    <CopyText text={store_path} />
                 }
-            </a>
+                <CopyText text={package.pkg.flake_url().to_string()} />
+            </div>
+            if is_selected {
+                <LogViewer
+                    key={format!("{}/{}", package.commit.hash, package.pkg.path())}
+                    repo_name={package.repo.repo.name()}
+                    commit={package.commit.hash.clone()}
+                    path={package.pkg.path().to_string()}
+                    building={!package.pkg.status().is_finished()}
+                />
+            }
         </div>
     }
 }
 
+/// The builder of the attempt that made `result` and otherwise of the latest attempt
+/// that ran, with the key of its label
+fn builder_of(
+    attempts: &[BuildAttempt],
+    result: Option<&String>,
+) -> Option<(String, &'static str)> {
+    let made_result = attempts.iter().rev().find(|attempt| {
+        matches!(&attempt.status, PackageBuildStatus::Success(path) if Some(path) == result)
+    });
+    match made_result.and_then(|attempt| attempt.builder.clone()) {
+        Some(builder) => Some((builder, "debug.builder_result")),
+        None => attempts
+            .iter()
+            .rev()
+            .find_map(|attempt| attempt.builder.clone())
+            .map(|builder| (builder, "debug.builder")),
+    }
+}
+
 #[derive(PartialEq, Clone, Debug)]
 pub struct Props {
     pub repo_name: Option<String>,
     pub package_name: Option<String>,
     pub branch: Option<String>,
     pub commit_hash: Option<String>,
+    /// the selected arch card, or the open architecture with `group_by_arch`
     pub arch: Option<String>,
+    pub filter: Filter,
+    /// group by architecture, package and commit instead of package, branch and commit
+    pub group_by_arch: bool,
 }
 
 impl Props {
+    /// Route of the current page
     pub fn from_url() -> Self {
         let window = match web_sys::window() {
             Some(w) => w,
@@ -357,13 +1092,16 @@ impl Props {
         };
 
         let location = window.location();
-        let search = match location.search() {
-            Ok(s) => s,
-            Err(_) => return Self::default(),
-        };
+        match location.search() {
+            Ok(search) => Self::parse(&search),
+            Err(_) => Self::default(),
+        }
+    }
 
-        // Parse URLSearchParams
-        let url_params = match web_sys::UrlSearchParams::new_with_str(&search) {
+    /// Route of a query string, the path is always the frontend itself so deep links
+    /// resolve on a hard refresh too
+    pub fn parse(search: &str) -> Self {
+        let url_params = match web_sys::UrlSearchParams::new_with_str(search) {
             Ok(params) => params,
             Err(_) => return Self::default(),
         };
@@ -374,6 +1112,25 @@ impl Props {
             branch: url_params.get("branch"),
             commit_hash: url_params.get("commit"),
             arch: url_params.get("arch"),
+            filter: Filter {
+                search: url_params.get("q"),
+                status: url_params.get("status"),
+                // a bookmarked view wins over the last selection
+                categories: url_params
+                    .get("categories")
+                    .map(|list| parse_categories(&list))
+                    .unwrap_or_else(stored_categories),
+                archs: url_params
+                    .get("only_arch")
+                    .map(|list| {
+                        list.split(',')
+                            .filter(|arch| !arch.is_empty())
+                            .map(str::to_string)
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+            },
+            group_by_arch: url_params.get("group").as_deref() == Some("arch"),
         }
     }
 
@@ -402,6 +1159,22 @@ impl Props {
         if let Some(arch) = &self.arch {
             params.push(format!("arch={}", arch));
         }
+        if let Some(search) = &self.filter.search {
+            params.push(format!("q={}", encode_uri_component(search)));
+        }
+        if !self.filter.archs.is_empty() {
+            let archs = self.filter.archs.join(",");
+            params.push(format!("only_arch={}", encode_uri_component(&archs)));
+        }
+        if let Some(status) = &self.filter.status {
+            params.push(format!("status={}", encode_uri_component(status)));
+        }
+        if !self.filter.categories.is_empty() {
+            params.push(format!("categories={}", self.filter.categories.join(",")));
+        }
+        if self.group_by_arch {
+            params.push("group=arch".to_string());
+        }
 
         Some(format!(
             "{}//{}{}?{}",
@@ -412,6 +1185,19 @@ impl Props {
         ))
     }
 
+    /// With `group_by_arch` the architecture is opened before packages and commits
+    fn outer_arch(&self) -> Option<String> {
+        self.arch.clone().filter(|_| self.group_by_arch)
+    }
+
+    /// Nothing open, with the filter and grouping kept
+    pub fn clear_from_repo(&self) -> Self {
+        Self {
+            repo_name: None,
+            ..self.with_repo_name(String::new())
+        }
+    }
+
     pub fn with_repo_name(&self, repo_name: String) -> Self {
         Self {
             repo_name: Some(repo_name),
@@ -419,6 +1205,8 @@ impl Props {
             branch: None,
             commit_hash: None,
             arch: None,
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -428,7 +1216,9 @@ impl Props {
             package_name: Some(package_name),
             branch: None,
             commit_hash: None,
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -438,7 +1228,9 @@ impl Props {
             package_name: self.package_name.clone(),
             branch: Some(branch),
             commit_hash: None,
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -448,7 +1240,9 @@ impl Props {
             package_name: self.package_name.clone(),
             branch: self.branch.clone(),
             commit_hash: Some(commit_hash),
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -459,6 +1253,8 @@ impl Props {
             branch: self.branch.clone(),
             commit_hash: self.commit_hash.clone(),
             arch: Some(arch),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -468,7 +1264,9 @@ impl Props {
             package_name: None,
             branch: None,
             commit_hash: None,
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -478,7 +1276,9 @@ impl Props {
             package_name: self.package_name.clone(),
             branch: None,
             commit_hash: None,
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -488,7 +1288,9 @@ impl Props {
             package_name: self.package_name.clone(),
             branch: self.branch.clone(),
             commit_hash: None,
-            arch: None,
+            arch: self.outer_arch(),
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 
@@ -499,6 +1301,8 @@ impl Props {
             branch: self.branch.clone(),
             commit_hash: self.commit_hash.clone(),
             arch: None,
+            filter: self.filter.clone(),
+            group_by_arch: self.group_by_arch,
         }
     }
 }
@@ -511,6 +1315,8 @@ impl Default for Props {
             branch: None,
             commit_hash: None,
             arch: None,
+            filter: Filter::default(),
+            group_by_arch: false,
         }
     }
 }
@@ -525,59 +1331,183 @@ pub struct Package<'a> {
 #[derive(Properties, PartialEq)]
 struct TableRowProps {
     repo_url: String,
+    repo_name: String,
     package_path: String,
+    commit_hash: String,
+    building: bool,
+    /// error of a failed build, which can be retried
+    failed: Option<String>,
+    flake_url: String,
+    /// store path of a successful build
+    result: Option<String>,
+    /// the result was garbage collected
+    result_missing: bool,
     branch: String,
     commit_message: String,
+    commit_secs: i64,
+    diff_stats: Option<DiffStats>,
     status_class: String,
-    repo_debug: String,
-    commit_debug: String,
-    pkg_debug: String,
+    status_label: String,
+    /// raw state dumps, only in developer mode
+    repo_debug: Option<String>,
+    commit_debug: Option<String>,
+    pkg_debug: Option<String>,
+    /// search terms to highlight
+    terms: Vec<String>,
+    times: BuildTimes,
+    attempts: Vec<BuildAttempt>,
+    /// shown as a card, the debug info stays closed until tapped
+    narrow: bool,
 }
 
 #[function_component]
 fn TableRow(props: &TableRowProps) -> Html {
-    let expanded = use_state(|| false);
+    let id = expanded::row_id(&props.repo_url, &props.commit_hash, &props.package_path);
+    let expanded = use_state(|| expanded::is_expanded(&id));
+    let selected = use_context::<SelectedRow>().is_some_and(|row| row.0.as_ref() == Some(&id));
+    let row = use_node_ref();
+    {
+        let row = row.clone();
+        use_effect_with(selected, move |selected| {
+            if let Some(element) = row.cast::<web_sys::Element>()
+                && *selected
+            {
+                shortcuts::scroll_into_view(&element);
+            }
+        });
+    }
     let toggle = {
         let expanded = expanded.clone();
+        let id = id.clone();
         Callback::from(move |_| {
+            expanded::set_expanded(&id, !*expanded);
             expanded.set(!*expanded);
         })
     };
 
     html! {
         <>
-            <tr onclick={toggle} class="table-row-hover" style="cursor: pointer; border-bottom: 1px solid rgba(255, 255, 255, 0.08);">
-                <td style="padding: 12px; color: var(--text);">{ &props.repo_url }</td>
-                <td style="padding: 12px; font-family: monospace; font-size: 0.9em; color: var(--text);">{ &props.package_path }</td>
-                <td style="padding: 12px; color: var(--text);">{ &props.branch }</td>
-                <td style="padding: 12px; color: var(--muted);">{ &props.commit_message }</td>
-                <td style="padding: 12px; text-align: center;">
-                    <span style={format!("display: inline-block; width: 12px; height: 12px; border-radius: 50%; {}",
-                        match props.status_class.as_str() {
-                            "status-success" => "background-color: #4caf50;",
-                            "status-failed" => "background-color: #f44336;",
-                            "status-building" => "background-color: #ff9800;",
-                            "status-pending" => "background-color: #2196f3;",
-                            _ => "background-color: #9e9e9e;",
-                        }
-                    )} title={props.status_class.clone()}></span>
+            <tr
+                ref={row}
+                onclick={toggle}
+                class={classes!("table-row-hover", selected.then_some("selected-row"))}
+                data-row={id}
+            >
+                <td data-label={t("table.repository")}>{ highlight(&props.repo_url, &props.terms) }</td>
+                <td class="mono" data-label={t("table.package_path")}>{ highlight(&props.package_path, &props.terms) }</td>
+                <td data-label={t("table.branch")}>{ highlight(&props.branch, &props.terms) }</td>
+                <td class="muted" data-label={t("table.commit")}>
+                    { highlight(&props.commit_message, &props.terms) }
+                    <TimeAgo unix_secs={props.commit_secs} />
+                    { diff_stats_html(props.diff_stats.as_ref()) }
+                </td>
+                <td data-label={t("table.duration")}><BuildDuration times={props.times} /></td>
+                <td class="center" data-label={t("table.status")}>
+                    <span class={classes!("status-dot", props.status_class.clone())} title={props.status_label.clone()}></span>
+                    if let Some(error) = &props.failed {
+                        <RetryButton
+                            repo_url={props.repo_url.clone()}
+                            repo_name={props.repo_name.clone()}
+                            commit={props.commit_hash.clone()}
+                            path={props.package_path.clone()}
+                            status={PackageBuildStatus::Failed(error.clone())}
+                            times={props.times}
+                        />
+                    }
+                    if props.building {
+                        <CancelButton
+                            repo_name={props.repo_name.clone()}
+                            commit={props.commit_hash.clone()}
+                            paths={vec![props.package_path.clone()]}
+                            running={props.status_class == "status-building"}
+                        />
+                    }
                 </td>
             </tr>
             if *expanded {
                 <tr>
-                    <td colspan="5" style="background: var(--card-strong); padding: 10px; border-bottom: 1px solid rgba(255, 255, 255, 0.08);">
-                        <details open={true}>
-                            <summary><strong style="color: var(--text);">{ "Repository Debug Info" }</strong></summary>
-                            <pre style="overflow-x: auto; white-space: pre-wrap; color: var(--muted); background: var(--card); padding: 8px; border-radius: 4px; margin-top: 8px;">{ &props.repo_debug }</pre>
-                        </details>
-                        <details open={true}>
-                            <summary><strong style="color: var(--text);">{ "Commit Debug Info" }</strong></summary>
-                            <pre style="overflow-x: auto; white-space: pre-wrap; color: var(--muted); background: var(--card); padding: 8px; border-radius: 4px; margin-top: 8px;">{ &props.commit_debug }</pre>
-                        </details>
-                        <details open={true}>
-                            <summary><strong style="color: var(--text);">{ "Package Debug Info" }</strong></summary>
-                            <pre style="overflow-x: auto; white-space: pre-wrap; color: var(--muted); background: var(--card); padding: 8px; border-radius: 4px; margin-top: 8px;">{ &props.pkg_debug }</pre>
-                        </details>
+                    <td colspan="6" class="row-details">
+                        <dl class="row-facts">
+                            <dt>{ t("details.flake_url") }</dt>
+                            <dd><CopyText text={props.flake_url.clone()} /></dd>
+                            <dt>{ t("table.commit") }</dt>
+                            <dd><CopyText text={props.commit_hash.clone()} /></dd>
+                            <dt>{ t("table.status") }</dt>
+                            <dd>
+                                { &props.status_label }
+                                if let Some(since) = props.times.finished.or(props.times.started) {
+                                    { " · " }<TimeAgo unix_secs={since} />
+                                }
+                                { " " }{ attempts_summary_html(&props.attempts) }
+                            </dd>
+                            <dt>{ t("table.duration") }</dt>
+                            <dd><BuildDuration times={props.times} /></dd>
+                            if let Some(result) = &props.result {
+                                <dt>{ t("details.result") }</dt>
+                                if props.result_missing {
+                                    <dd class="result-missing" title={t("package.result_missing")}>
+                                        <CopyText text={result.clone()} />
+                                        <RetryButton
+                                            repo_url={props.repo_url.clone()}
+                                            repo_name={props.repo_name.clone()}
+                                            commit={props.commit_hash.clone()}
+                                            path={props.package_path.clone()}
+                                            status={PackageBuildStatus::Success(result.clone())}
+                                            times={props.times}
+                                        />
+                                    </dd>
+                                } else {
+                                    <dd>
+                                        <CopyText text={result.clone()} />
+                                        // store paths are served below the base path too
+                                        <a href={format!("{}{}", base_path(), result)} class="result-link">{ t("package.build_result") }</a>
+                                        <a href={format!("{}{}?archive=tar.gz", base_path(), result)} class="result-link archive-link">{ "tar.gz" }</a>
+                                        <a href={format!("{}{}?archive=zip", base_path(), result)} class="result-link archive-link">{ "zip" }</a>
+                                    </dd>
+                                }
+                            }
+                            <dt>{ t("details.log") }</dt>
+                            <dd>
+                                <a
+                                    href={log_viewer::log_url(&props.repo_name, &props.commit_hash, &props.package_path)}
+                                    class="result-link"
+                                    target="_blank"
+                                >
+                                    { t("details.open_log") }
+                                </a>
+                                { attempt_logs_html(&props.repo_name, &props.commit_hash, &props.package_path, &props.attempts) }
+                            </dd>
+                        </dl>
+                        <LogViewer
+                            repo_name={props.repo_name.clone()}
+                            commit={props.commit_hash.clone()}
+                            path={props.package_path.clone()}
+                            building={props.building}
+                        />
+                        if let Some(repo_debug) = &props.repo_debug {
+                            <details open={!props.narrow}>
+                                <summary><strong>{ t("debug.repo") }</strong></summary>
+                                <pre class="debug-info">{ repo_debug }</pre>
+                            </details>
+                        }
+                        if let Some(commit_debug) = &props.commit_debug {
+                            <details open={!props.narrow}>
+                                <summary><strong>{ t("debug.commit") }</strong></summary>
+                                <pre class="debug-info">{ commit_debug }</pre>
+                            </details>
+                        }
+                        if let Some(pkg_debug) = &props.pkg_debug {
+                            <details open={!props.narrow}>
+                                <summary><strong>{ t("debug.package") }</strong></summary>
+                                <pre class="debug-info">{ pkg_debug }</pre>
+                            </details>
+                            if let Some((builder, label)) = builder_of(&props.attempts, props.result.as_ref()) {
+                                <details open={!props.narrow}>
+                                    <summary><strong>{ t(label) }</strong></summary>
+                                    <BuilderDetails id={builder} />
+                                </details>
+                            }
+                        }
                     </td>
                 </tr>
             }
@@ -587,10 +1517,12 @@ fn TableRow(props: &TableRowProps) -> Html {
 
 fn format_repo_debug(repo: &RepoInfo) -> String {
     format!(
-        "RepoInfo {{\n  flake_url: {:?},\n  repo: {:#?},\n  checkout_path: {:?},\n  branch_commit_hashes: {:#?},\n  commits: <{} commits (excluded from display)>,\n  status: {:?},\n}}",
+        "RepoInfo {{\n  flake_url: {:?},\n  repo: {:#?},\n  checkout_path: {:?},\n  supported_architectures: {:?},\n  running_builds: {},\n  branch_commit_hashes: {:#?},\n  commits: <{} commits (excluded from display)>,\n  status: {:?},\n}}",
         repo.flake_url,
         repo.repo,
         repo.checkout_path,
+        repo.supported_architectures,
+        repo.running_builds.0,
         repo.branch_commit_hashes,
         repo.commits.0.len(),
         repo.status.0
@@ -609,8 +1541,14 @@ fn format_commit_debug(commit: &CommitInfo) -> String {
     )
 }
 
-fn repos_table(repos: &RepoList) -> Html {
-    let mut package_list: Vec<(&RepoInfo, &CommitInfo, &PackageEnum)> = repos
+/// The rows matching `filter`, ordered by repo, package, branch, commit and arch
+/// unless sorted by a column
+fn table_rows<'a>(
+    repos: &'a RepoList,
+    filter: &Filter,
+    sort: Option<TableSort>,
+) -> Vec<(&'a RepoInfo, &'a CommitInfo, &'a PackageEnum)> {
+    let mut package_list: Vec<(&'a RepoInfo, &'a CommitInfo, &'a PackageEnum)> = repos
         .0
         .0
         .iter()
@@ -619,8 +1557,8 @@ fn repos_table(repos: &RepoList) -> Html {
                 commit.packages.0.iter().map(move |pkg| (repo, commit, pkg))
             })
         })
+        .filter(|(repo, commit, pkg)| filter.matches_any_branch(repo, commit, pkg))
         .collect();
-
     // Sort by: repo name, package name, branch, commit time (desc), arch
     package_list.sort_by(|(repo_a, commit_a, pkg_a), (repo_b, commit_b, pkg_b)| {
         let repo_name_a = &repo_a.repo.url;
@@ -635,28 +1573,8 @@ fn repos_table(repos: &RepoList) -> Html {
             PackageEnum::NixosConfig(arc_wrapper) => arc_wrapper.0.path.clone(),
         };
 
-        let branch_a = repo_a
-            .branch_commit_hashes
-            .iter()
-            .find_map(|(branch, hashes)| {
-                if hashes.0.contains(&commit_a.hash) {
-                    Some(branch.clone())
-                } else {
-                    None
-                }
-            })
-            .unwrap_or_else(|| "-".to_string());
-        let branch_b = repo_b
-            .branch_commit_hashes
-            .iter()
-            .find_map(|(branch, hashes)| {
-                if hashes.0.contains(&commit_b.hash) {
-                    Some(branch.clone())
-                } else {
-                    None
-                }
-            })
-            .unwrap_or_else(|| "-".to_string());
+        let branch_a = branch_of(repo_a, commit_a);
+        let branch_b = branch_of(repo_b, commit_b);
 
         let arch_a = match pkg_a {
             PackageEnum::Derivation(arc_wrapper) => arc_wrapper.0.arch.clone(),
@@ -673,126 +1591,563 @@ fn repos_table(repos: &RepoList) -> Html {
             .then_with(|| branch_a.cmp(&branch_b))
             .then_with(|| commit_b.unix_secs.cmp(&commit_a.unix_secs)) // Descending (newest first)
             .then_with(|| arch_a.cmp(&arch_b))
+            // keeps the order, and with it the pages, stable across refreshes
+            .then_with(|| pkg_a.path().cmp(pkg_b.path()))
     });
+    if let Some(sort) = sort {
+        sort_rows(&mut package_list, sort);
+    }
+    package_list
+}
+
+/// Only the rows of `page` are rendered, `export_rows` makes the rows of all pages
+/// when an export is downloaded
+#[allow(clippy::too_many_arguments)]
+fn repos_table(
+    repos: &RepoList,
+    filter: &Filter,
+    sort: Option<TableSort>,
+    on_sort: Callback<SortColumn>,
+    page: TablePage,
+    on_page: Callback<TablePage>,
+    narrow: bool,
+    export_rows: Callback<(), Vec<ExportRow>>,
+) -> Html {
+    let package_list = table_rows(repos, filter, sort);
+    if package_list.is_empty() && filter.is_active() {
+        return nothing_matches();
+    }
+    let terms = filter.terms();
+    let dev_mode = dev_mode::enabled();
+    let rows = package_list.len();
+    let visible = package_list.get(page.range(rows)).unwrap_or_default();
 
     html! {
-        <table style="width: 100%; border-collapse: collapse; background: var(--card); box-shadow: var(--shadow); border-radius: var(--radius); overflow: hidden;">
-            <thead>
-                <tr style="background: var(--card-strong); border-bottom: 2px solid rgba(255, 255, 255, 0.08);">
-                    <th style="padding: 12px; text-align: left; font-weight: 600; color: var(--text);">{ "Repository" }</th>
-                    <th style="padding: 12px; text-align: left; font-weight: 600; color: var(--text);">{ "Package Path" }</th>
-                    <th style="padding: 12px; text-align: left; font-weight: 600; color: var(--text);">{ "Branch" }</th>
-                    <th style="padding: 12px; text-align: left; font-weight: 600; color: var(--text);">{ "Commit" }</th>
-                    <th style="padding: 12px; text-align: center; font-weight: 600; color: var(--text);">{ "Status" }</th>
-                </tr>
-            </thead>
-            <tbody>
-                { for package_list.iter().map(|(repo, commit, pkg)| {
-                    let package_path = match pkg {
-                        PackageEnum::Derivation(arc_wrapper) => arc_wrapper.0.path.clone(),
-                        PackageEnum::NixosConfig(arc_wrapper) => arc_wrapper.0.path.clone(),
-                    };
-                    let branch = repo.branch_commit_hashes.iter()
-                        .find_map(|(branch, hashes)| {
-                            if hashes.0.contains(&commit.hash) {
-                                Some(branch.clone())
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap_or_else(|| "-".to_string());
+        <>
+            { export_html(export_rows) }
+            <table class={classes!("repo-table", narrow.then_some("card-rows"))}>
+                <thead>
+                    <tr>
+                        { header_html(t("table.repository"), SortColumn::Repository, sort, &on_sort, None) }
+                        { header_html(t("table.package_path"), SortColumn::Package, sort, &on_sort, None) }
+                        { header_html(t("table.branch"), SortColumn::Branch, sort, &on_sort, None) }
+                        { header_html(t("table.commit"), SortColumn::Commit, sort, &on_sort, None) }
+                        { header_html(t("table.duration"), SortColumn::Duration, sort, &on_sort, None) }
+                        { header_html(t("table.status"), SortColumn::Status, sort, &on_sort, Some("center")) }
+                    </tr>
+                </thead>
+                <tbody>
+                    { for visible.iter().map(|(repo, commit, pkg)| {
+                        let package_path = match pkg {
+                            PackageEnum::Derivation(arc_wrapper) => arc_wrapper.0.path.clone(),
+                            PackageEnum::NixosConfig(arc_wrapper) => arc_wrapper.0.path.clone(),
+                        };
+                        let branch = branch_of(repo, commit);
+
+                        let commit_first_line = commit.message.lines().next().unwrap_or("");
+                        let commit_display = if commit_first_line.len() > 10 {
+                            format!("{}...", &commit_first_line[..10])
+                        } else {
+                            commit_first_line.to_string()
+                        };
+
+                        let status = pkg.status();
+
+                        // keeps an expanded row open while it stays in the table
+                        let key = expanded::row_id(&repo.repo.url, &commit.hash, &package_path);
+                        html! {
+                            <TableRow
+                                {key}
+                                repo_url={repo.repo.url.clone()}
+                                repo_name={repo.repo.name()}
+                                package_path={package_path}
+                                commit_hash={commit.hash.clone()}
+                                building={!pkg.status().is_finished()}
+                                failed={match pkg.status() {
+                                    PackageBuildStatus::Failed(error) => Some(error),
+                                    _ => None,
+                                }}
+                                flake_url={pkg.flake_url().to_string()}
+                                result={match pkg.status() {
+                                    PackageBuildStatus::Success(path) => Some(path),
+                                    _ => None,
+                                }}
+                                result_missing={pkg.result_missing()}
+                                branch={branch}
+                                commit_message={commit_display}
+                                commit_secs={commit.unix_secs}
+                                diff_stats={commit.diff_stats.clone()}
+                                status_class={status_class(&status).to_string()}
+                                status_label={status_label(&status)}
+                                repo_debug={dev_mode.then(|| format_repo_debug(repo))}
+                                commit_debug={dev_mode.then(|| format_commit_debug(commit))}
+                                pkg_debug={dev_mode.then(|| format!("{:#?}", pkg))}
+                                terms={terms.clone()}
+                                times={pkg.times()}
+                                attempts={pkg.attempts()}
+                                {narrow}
+                            />
+                        }
+                    }) }
+                </tbody>
+            </table>
+            { pager_html(rows, page, on_page) }
+        </>
+    }
+}
 
-                    let commit_first_line = commit.message.lines().next().unwrap_or("");
-                    let commit_display = if commit_first_line.len() > 10 {
-                        format!("{}...", &commit_first_line[..10])
-                    } else {
-                        commit_first_line.to_string()
-                    };
-
-                    let status_text = match pkg {
-                        PackageEnum::Derivation(arc_wrapper) => format!("{:?}", arc_wrapper.0.status.0),
-                        PackageEnum::NixosConfig(arc_wrapper) => format!("{:?}", arc_wrapper.0.status.0),
-                    };
-
-                    let status_class = match status_text.as_str() {
-                        s if s.contains("Success") => "status-success",
-                        s if s.contains("Failed") || s.contains("Failure") => "status-failed",
-                        s if s.contains("Building") || s.contains("Running") => "status-building",
-                        s if s.contains("Pending") || s.contains("Queued") || s.contains("WaitingForBuild") => "status-pending",
-                        _ => "status-unknown",
-                    };
-
-                    html! {
-                        <TableRow
-                            repo_url={repo.repo.url.clone()}
-                            package_path={package_path}
-                            branch={branch}
-                            commit_message={commit_display}
-                            status_class={status_class.to_string()}
-                            repo_debug={format_repo_debug(repo)}
-                            commit_debug={format_commit_debug(commit)}
-                            pkg_debug={format!("{:#?}", pkg)}
-                        />
-                    }
-                }) }
-            </tbody>
-        </table>
+fn connection_html(connection: Connection, rate: RefreshRate, live: Rc<LiveState>) -> Html {
+    let (text, class) = match (connection, rate.secs()) {
+        (_, None) => (t("connection.paused").to_string(), "status-unknown"),
+        (Connection::Connecting, _) => (t("connection.connecting").to_string(), "status-pending"),
+        (Connection::Live, _) => (t("connection.live").to_string(), "status-success"),
+        (Connection::Reconnecting, _) => {
+            (t("connection.reconnecting").to_string(), "status-pending")
+        }
+        (Connection::Polling, Some(secs)) => {
+            (t_args("connection.polling", &[&secs]), "status-unknown")
+        }
+    };
+    html! {
+        <p class="meta">
+            <span class={classes!("status-indicator", class)}>{ text }</span>
+            <LastUpdated {live} />
+        </p>
+    }
+}
+
+#[derive(Properties)]
+struct LastUpdatedProps {
+    live: Rc<LiveState>,
+}
+
+impl PartialEq for LastUpdatedProps {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.live, &other.live)
+    }
+}
+
+/// Time since the server last confirmed the state, ticking on its own so unchanged
+/// data does not render the rest of the page
+#[function_component]
+fn LastUpdated(props: &LastUpdatedProps) -> Html {
+    let update = use_force_update();
+    use_effect_with((), move |_| {
+        let interval = Interval::new(1000, move || update.force_update());
+        move || drop(interval)
+    });
+    let Some(at) = props.live.last_updated.get() else {
+        return html! {};
+    };
+    let secs = ((Date::now() - at) / 1000.0).max(0.0) as u64;
+    let text = match secs {
+        0 => t("updated.just_now").to_string(),
+        1..60 => t_args("updated.secs_ago", &[&secs]),
+        _ => t_args("updated.mins_ago", &[&(secs / 60)]),
+    };
+    html! { <span class="last-updated">{ text }</span> }
+}
+
+/// Show the URL of `to` in the address bar, as a new history entry unless `replace`.
+/// The state of an entry is the scroll position it was left at.
+fn update_history(to: &Props, replace: bool) {
+    let Some(url) = to.get_url() else {
+        return;
+    };
+    if let Some(window) = web_sys::window()
+        && let Ok(history) = window.history()
+    {
+        let _ = match replace {
+            true => {
+                let state = history.state().unwrap_or(JsValue::NULL);
+                history.replace_state_with_url(&state, "", Some(&url))
+            }
+            false => {
+                let scroll_y = JsValue::from_f64(window.scroll_y().unwrap_or(0.0));
+                let _ = history.replace_state(&scroll_y, "");
+                history.push_state_with_url(&JsValue::NULL, "", Some(&url))
+            }
+        };
     }
 }
 
+/// Scroll back to where the current history entry was left, once the restored route
+/// rendered
+fn restore_scroll() {
+    let Some(window) = web_sys::window() else {
+        return;
+    };
+    let Some(scroll_y) = window
+        .history()
+        .ok()
+        .and_then(|history| history.state().ok()?.as_f64())
+    else {
+        return;
+    };
+    Timeout::new(0, move || window.scroll_to_with_x_and_y(0.0, scroll_y)).forget();
+}
+
+/// Packages per status category that match the filter apart from the categories
+fn category_counts(repos: &RepoList, filter: &Filter) -> Vec<usize> {
+    let filter = Filter {
+        categories: Vec::new(),
+        ..filter.clone()
+    };
+    let mut counts = vec![0; CATEGORIES.len()];
+    for repo in repos.0.0.iter() {
+        for commit in repo.commits.0.values() {
+            for pkg in commit.packages.0.iter() {
+                if filter.matches_any_branch(repo, commit, pkg)
+                    && let Some(index) = CATEGORIES
+                        .iter()
+                        .position(|name| *name == category(&pkg.status()))
+                {
+                    counts[index] += 1;
+                }
+            }
+        }
+    }
+    counts
+}
+
+/// Architectures of all derivations, for the filter dropdown
+fn known_archs(repos: &RepoList) -> Vec<String> {
+    let mut archs = repos
+        .0
+        .0
+        .iter()
+        .flat_map(|repo| repo.commits.0.values())
+        .flat_map(|commit| commit.packages.0.iter())
+        .filter_map(|pkg| match pkg {
+            PackageEnum::Derivation(derivation) => Some(derivation.0.arch.clone()),
+            PackageEnum::NixosConfig(_) => None,
+        })
+        .collect::<Vec<_>>();
+    archs.sort();
+    archs.dedup();
+    archs
+}
+
 #[function_component]
 fn App() -> Html {
     let data = use_state(|| None::<Result<RepoList, String>>);
-    let props = Props::from_url();
+    let connection = use_state(|| Connection::Connecting);
+    let table_sort = use_state(TableSort::stored);
+    let table_page = use_state(TablePage::stored);
+    let refresh_rate = use_state(RefreshRate::stored);
+    let visible = use_page_visible();
+    let selected_row = use_state(|| None::<String>);
+    let show_shortcuts = use_state(|| false);
+    let narrow = use_narrow();
+    let live = use_memo((), |_| LiveState::default());
+    let failures = use_memo((), |_| FailureWatch::default());
+    let tab_status = use_memo((), |_| TabStatus::default());
+    let build_queue = use_state(Rc::<BuildQueue>::default);
+    let route = use_state(Props::from_url);
+    let props = (*route).clone();
 
     {
-        let data = data.clone();
-        // Fetch immediately, then refresh every second
+        let route = route.clone();
+        // back and forward restore the route of the history entry
         use_effect_with((), move |_| {
-            wasm_bindgen_futures::spawn_local({
-                let data = data.clone();
-                async move {
-                    let res = fetch_repos().await;
-                    data.set(Some(res));
-                }
+            // show categories restored from localStorage in the URL
+            if !route.filter.categories.is_empty() {
+                update_history(&route, true);
+            }
+            let onpopstate = Closure::<dyn FnMut()>::new(move || {
+                route.set(Props::from_url());
+                restore_scroll();
             });
+            let window = web_sys::window();
+            if let Some(window) = &window {
+                // the page renders after popstate, the browser would scroll too early
+                if let Ok(history) = window.history() {
+                    let _ = history.set_scroll_restoration(ScrollRestoration::Manual);
+                }
+                let _ = window.add_event_listener_with_callback(
+                    "popstate",
+                    onpopstate.as_ref().unchecked_ref(),
+                );
+            }
+            move || {
+                if let Some(window) = window {
+                    let _ = window.remove_event_listener_with_callback(
+                        "popstate",
+                        onpopstate.as_ref().unchecked_ref(),
+                    );
+                }
+            }
+        });
+    }
 
-            let interval = Interval::new(1000, move || {
-                let data = data.clone();
-                wasm_bindgen_futures::spawn_local(async move {
-                    let res = fetch_repos().await;
-                    data.set(Some(res));
+    let navigator = {
+        let route = route.clone();
+        Navigator(Callback::from(move |to: Props| {
+            update_history(&to, false);
+            route.set(to);
+        }))
+    };
+    // the levels below the repo mean something else in the other grouping
+    let on_group_by_arch = {
+        let route = route.clone();
+        Callback::from(move |group_by_arch: bool| {
+            let open = match &route.repo_name {
+                Some(repo_name) => route.with_repo_name(repo_name.clone()),
+                None => route.clear_from_repo(),
+            };
+            let to = Props {
+                group_by_arch,
+                ..open
+            };
+            update_history(&to, false);
+            route.set(to);
+        })
+    };
+    {
+        let data = data.clone();
+        let navigator = navigator.clone();
+        // after every render, an unchanged state finds no new failures
+        use_effect(move || {
+            if let Some(Ok(repos)) = &*data {
+                failures.check(repos, &navigator);
+                tab_status.update(repos);
+            }
+        });
+    }
+    let apply_event = {
+        let data = data.clone();
+        let live = live.clone();
+        ApplyEvent(Callback::from(move |event: StateEvent| {
+            if let Some(repos) = live.repos.borrow_mut().as_mut() {
+                repos.apply_event(event);
+            }
+            schedule_render(data.clone(), live.clone());
+        }))
+    };
+    // typing in the filter would add a history entry per key otherwise
+    let set_filter = {
+        let route = route.clone();
+        let table_page = table_page.clone();
+        Callback::from(move |filter: Filter| {
+            table_page.set(table_page.first());
+            if filter.categories != route.filter.categories {
+                store_categories(&filter.categories);
+            }
+            let to = Props {
+                filter,
+                ..(*route).clone()
+            };
+            update_history(&to, true);
+            route.set(to);
+        })
+    };
+    {
+        let route = route.clone();
+        let set_filter = set_filter.clone();
+        let selected_row = selected_row.clone();
+        let show_shortcuts = show_shortcuts.clone();
+        use_shortcuts(Callback::from(move |shortcut| match shortcut {
+            Shortcut::Next => selected_row.set(shortcuts::step(selected_row.as_deref(), 1)),
+            Shortcut::Previous => selected_row.set(shortcuts::step(selected_row.as_deref(), -1)),
+            Shortcut::Toggle => {
+                if let Some(id) = &*selected_row {
+                    shortcuts::click(id, None);
+                }
+            }
+            Shortcut::Retry => {
+                if let Some(id) = &*selected_row {
+                    shortcuts::click(id, Some(".retry-button"));
+                }
+            }
+            Shortcut::FocusSearch => shortcuts::focus_search(),
+            Shortcut::FailuresOnly => {
+                let failures_only = route.filter.categories == ["failed"];
+                set_filter.emit(Filter {
+                    categories: match failures_only {
+                        true => Vec::new(),
+                        false => vec!["failed".to_string()],
+                    },
+                    ..route.filter.clone()
                 });
-            });
+            }
+            Shortcut::Help => show_shortcuts.set(!*show_shortcuts),
+            Shortcut::Close => show_shortcuts.set(false),
+        }));
+    }
 
+    {
+        let data = data.clone();
+        let connection = connection.clone();
+        let live = live.clone();
+        use_effect_with((), move |_| connect_socket(data, live, connection));
+    }
+    {
+        let data = data.clone();
+        let live = live.clone();
+        // Catch up whenever polling starts again, then refresh at the chosen rate while
+        // the WebSocket is down or missed changes. Paused only loads the first state.
+        use_effect_with((*refresh_rate, visible), move |(rate, visible)| {
+            live.paused.set(rate.is_paused());
+            if !rate.is_paused() || live.repos.borrow().is_none() {
+                refresh(data.clone(), live.clone());
+            }
+            let interval = rate.period_ms(*visible).map(|period| {
+                Interval::new(period, move || {
+                    if live.socket_open.get() && !live.dirty.replace(false) {
+                        return;
+                    }
+                    refresh(data.clone(), live.clone());
+                })
+            });
+            move || drop(interval)
+        });
+    }
+    {
+        let build_queue = build_queue.clone();
+        let live = live.clone();
+        // only while builds wait, the queue is empty otherwise
+        use_effect_with((), move |_| {
+            let interval = Interval::new(queue::REFRESH_MS, move || {
+                let waiting = live
+                    .repos
+                    .borrow()
+                    .as_ref()
+                    .is_some_and(|repos| summary::totals(repos).queued > 0);
+                if waiting {
+                    queue::fetch(build_queue.clone());
+                } else if !build_queue.waiting.is_empty() {
+                    build_queue.set(Rc::default());
+                }
+            });
             move || drop(interval)
         });
     }
+    let queue_info = QueueInfo {
+        queue: (*build_queue).clone(),
+        average_secs: match &*data {
+            Some(Ok(list)) => queue::average_secs(list),
+            _ => None,
+        },
+    };
 
     let body = match &*data {
         Some(Ok(list)) => repos(&list, &props),
-        Some(Err(err)) => html! { <p class="meta error">{ format!("Error: {}", err) }</p> },
-        None => html! { <p class="meta">{ "Loading data..." }</p> },
+        Some(Err(err)) => html! { <p class="meta error">{ t_args("app.error", &[err]) }</p> },
+        None => html! { <p class="meta">{ t("app.loading") }</p> },
+    };
+
+    let summary = match &*data {
+        Some(Ok(list)) => summary_html(list, &props.filter, set_filter.clone()),
+        _ => html! {},
+    };
+
+    let (archs, counts) = match &*data {
+        Some(Ok(list)) => (known_archs(list), category_counts(list, &props.filter)),
+        _ => (Vec::new(), vec![0; CATEGORIES.len()]),
+    };
+
+    let on_sort = {
+        let table_sort = table_sort.clone();
+        let table_page = table_page.clone();
+        Callback::from(move |column| {
+            table_sort.set(TableSort::next(*table_sort, column));
+            table_page.set(table_page.first());
+        })
+    };
+
+    let pending = {
+        let data = data.clone();
+        use_memo(
+            (live.generation.get(), summary::local_midnight_secs()),
+            move |(_, midnight)| match &*data {
+                Some(Ok(list)) => Some(summary::pending(list, *midnight)),
+                _ => None,
+            },
+        )
+    };
+
+    let set_refresh_rate = {
+        let refresh_rate = refresh_rate.clone();
+        Callback::from(move |rate| refresh_rate.set(rate))
     };
 
+    // the rows of all pages are only made when they are downloaded
+    let export_rows = {
+        let data = data.clone();
+        let filter = props.filter.clone();
+        let sort = *table_sort;
+        Callback::from(move |()| match &*data {
+            Some(Ok(list)) => table_rows(list, &filter, sort)
+                .into_iter()
+                .map(|(repo, commit, pkg)| ExportRow::new(repo, commit, pkg))
+                .collect(),
+            _ => Vec::new(),
+        })
+    };
     let table = match &*data {
-        Some(Ok(list)) => repos_table(&list),
-        _ => html! { <p class="meta">{ "No table to display" }</p> },
+        Some(Ok(list)) => repos_table(
+            &list,
+            &props.filter,
+            *table_sort,
+            on_sort,
+            *table_page,
+            Callback::from(move |page| table_page.set(page)),
+            narrow,
+            export_rows,
+        ),
+        _ => html! { <p class="meta">{ t("app.no_table") }</p> },
     };
 
     html! {
-        <div class="app-bg">
-            <main class="page">
-                <header class="page-header">
-                    <p class="kicker">{ "Nix Autobuild" }</p>
-                    <h1>{ "Repository Overview" }</h1>
-                    <p class="meta">{ "Auto-refreshing every second" }</p>
-                </header>
-                { body }
-                { table }
-                { format!("{:?}", props) }
-            </main>
-        </div>
+        <ContextProvider<Navigator> context={navigator}>
+            <ContextProvider<ApplyEvent> context={apply_event}>
+                <ContextProvider<QueueInfo> context={queue_info}>
+                    <ToastProvider>
+                        <RebuildAccessProvider>
+                            <div class="app-bg">
+                                <main class="page">
+                                    <StaleBanner live={live.clone()} />
+                                    <header class="page-header">
+                                        <div class="header-actions">
+                                            <RefreshSelect rate={*refresh_rate} onchange={set_refresh_rate} />
+                                            <LanguageSelect />
+                                            <NotificationBell />
+                                            <ThemeToggle />
+                                        </div>
+                                        <p class="kicker">{ t("app.kicker") }</p>
+                                        <h1>{ t("app.title") }</h1>
+                                        { connection_html(*connection, *refresh_rate, live) }
+                                        if let Some(pending) = &*pending {
+                                            { pending_html(pending, &props, set_filter.clone()) }
+                                        }
+                                        <FilterControls
+                                            filter={props.filter.clone()}
+                                            archs={archs}
+                                            onchange={set_filter.clone()}
+                                            group_by_arch={props.group_by_arch}
+                                            {on_group_by_arch}
+                                        />
+                                    </header>
+                                    { summary }
+                                    <StatusChips filter={props.filter.clone()} {counts} onchange={set_filter} />
+                                    { body }
+                                    <ContextProvider<SelectedRow> context={SelectedRow((*selected_row).clone())}>
+                                        { table }
+                                    </ContextProvider<SelectedRow>>
+                                    <ArchiveBrowser />
+                                    if dev_mode::enabled() {
+                                        <pre class="debug-info">{ format!("{:?}", props) }</pre>
+                                    }
+                                    if *show_shortcuts {
+                                        <ShortcutHelp onclose={Callback::from(move |_| show_shortcuts.set(false))} />
+                                    }
+                                </main>
+                            </div>
+                        </RebuildAccessProvider>
+                    </ToastProvider>
+                </ContextProvider<QueueInfo>>
+            </ContextProvider<ApplyEvent>>
+        </ContextProvider<Navigator>>
     }
 }
 
@@ -0,0 +1,186 @@
+//! Desktop notifications about packages that newly failed, for a dashboard left in a
+//! background tab. Opt-in with the bell in the header.
+
+use std::{cell::RefCell, collections::HashSet};
+
+use wasm_bindgen::{JsCast, JsValue, prelude::Closure};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{Notification, NotificationOptions, NotificationPermission, js_sys::Reflect};
+use yew::prelude::*;
+
+use crate::{
+    RepoList,
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+use super::{Navigator, Props, table_sort::branch_of};
+
+/// localStorage key, set while notifications are turned on
+const STORAGE_KEY: &str = "nix_autobuild.notifications";
+
+/// Some browsers have no Notification API at all, calling it would throw
+fn supported() -> bool {
+    web_sys::window().is_some_and(|window| {
+        Reflect::has(&window, &JsValue::from_str("Notification")).unwrap_or(false)
+    })
+}
+
+fn permission() -> NotificationPermission {
+    match supported() {
+        true => Notification::permission(),
+        false => NotificationPermission::Denied,
+    }
+}
+
+/// Turned on and allowed by the browser
+fn enabled() -> bool {
+    permission() == NotificationPermission::Granted
+        && super::local_storage()
+            .and_then(|storage| storage.get_item(STORAGE_KEY).ok()?)
+            .is_some()
+}
+
+fn set_enabled(enabled: bool) {
+    if let Some(storage) = super::local_storage() {
+        let _ = match enabled {
+            true => storage.set_item(STORAGE_KEY, "on"),
+            false => storage.remove_item(STORAGE_KEY),
+        };
+    }
+}
+
+/// The failed packages of the previous update, to find the ones that newly failed
+#[derive(Default)]
+pub struct FailureWatch {
+    /// `None` until the first data arrived, failures already there are not reported
+    failed: RefCell<Option<HashSet<String>>>,
+    /// of packages notified about, each package of a commit is reported once
+    notified: RefCell<HashSet<String>>,
+}
+
+impl FailureWatch {
+    /// Notify about the packages that failed since the last call
+    pub fn check(&self, repos: &RepoList, navigator: &Navigator) {
+        let mut failed = HashSet::new();
+        let mut newly_failed = Vec::new();
+        let previous = self.failed.borrow();
+        for repo in repos.0.0.iter() {
+            for commit in repo.commits.0.values() {
+                for pkg in commit.packages.0.iter() {
+                    if !matches!(pkg.status(), PackageBuildStatus::Failed(_)) {
+                        continue;
+                    }
+                    let id = format!("{}/{}/{}", repo.repo.url, commit.hash, pkg.path());
+                    if previous
+                        .as_ref()
+                        .is_some_and(|previous| !previous.contains(&id))
+                    {
+                        newly_failed.push((id.clone(), repo, commit, pkg));
+                    }
+                    failed.insert(id);
+                }
+            }
+        }
+        drop(previous);
+        *self.failed.borrow_mut() = Some(failed);
+        if newly_failed.is_empty() || !enabled() {
+            return;
+        }
+        let mut notified = self.notified.borrow_mut();
+        for (id, repo, commit, pkg) in newly_failed {
+            if notified.insert(id.clone()) {
+                show(&id, repo, commit, pkg, navigator);
+            }
+        }
+    }
+}
+
+/// Route of the grouped view showing the package with its log
+fn deep_link(repo: &RepoInfo, commit: &CommitInfo, pkg: &PackageEnum) -> Props {
+    let (package_name, arch) = match pkg {
+        PackageEnum::Derivation(pkg) => (pkg.0.get_no_arch_name(), pkg.0.arch.clone()),
+        PackageEnum::NixosConfig(pkg) => (pkg.0.path.clone(), "NONE".to_string()),
+    };
+    Props {
+        repo_name: Some(repo.repo.url.clone()),
+        package_name: Some(package_name),
+        branch: Some(branch_of(repo, commit)),
+        commit_hash: Some(commit.hash.clone()),
+        arch: Some(arch),
+        ..Props::default()
+    }
+}
+
+fn show(id: &str, repo: &RepoInfo, commit: &CommitInfo, pkg: &PackageEnum, navigator: &Navigator) {
+    let options = NotificationOptions::new();
+    let message = commit.message.lines().next().unwrap_or_default();
+    options.set_body(&format!("{}\n{}", pkg.path(), message));
+    // the browser replaces a notification with the same tag instead of stacking them
+    options.set_tag(id);
+    let title = format!("Build failed in {}", repo.repo.url);
+    let Ok(notification) = Notification::new_with_options(&title, &options) else {
+        return;
+    };
+    let onclick = Closure::<dyn FnMut()>::new({
+        let notification = notification.clone();
+        let navigator = navigator.clone();
+        let to = deep_link(repo, commit, pkg);
+        move || {
+            if let Some(window) = web_sys::window() {
+                let _ = window.focus();
+            }
+            navigator.0.emit(to.clone());
+            notification.close();
+        }
+    });
+    notification.set_onclick(Some(onclick.as_ref().unchecked_ref()));
+    onclick.forget();
+}
+
+/// Turns the notifications on and off, asking for the permission first
+#[function_component]
+pub fn NotificationBell() -> Html {
+    let on = use_state(enabled);
+    let denied = permission() == NotificationPermission::Denied;
+    if !supported() {
+        return html! {};
+    }
+    let onclick = {
+        let on = on.clone();
+        Callback::from(move |_: MouseEvent| {
+            if *on {
+                set_enabled(false);
+                on.set(false);
+                return;
+            }
+            let on = on.clone();
+            wasm_bindgen_futures::spawn_local(async move {
+                if permission() == NotificationPermission::Default
+                    && let Ok(request) = Notification::request_permission()
+                {
+                    let _ = JsFuture::from(request).await;
+                }
+                let granted = permission() == NotificationPermission::Granted;
+                set_enabled(granted);
+                on.set(granted);
+            });
+        })
+    };
+    let title = match (denied, *on) {
+        (true, _) => "Notifications are blocked in the browser settings",
+        (false, true) => "Notifying about new failures, click to stop",
+        (false, false) => "Notify about new failures",
+    };
+    html! {
+        <button
+            class={classes!("poll-button", "notification-bell", on.then_some("selected"))}
+            disabled={denied}
+            {title}
+            {onclick}
+        >
+            { if *on { "🔔" } else { "🔕" } }
+        </button>
+    }
+}
@@ -0,0 +1,90 @@
+//! Pagination of the package table, rendering thousands of rows every second makes
+//! the tab unusable
+
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
+
+/// Rows per page to choose from, the first is the default
+pub const PAGE_SIZES: [usize; 4] = [50, 25, 100, 250];
+
+/// localStorage key of the chosen page size
+const STORAGE_KEY: &str = "nix_autobuild.page_size";
+
+/// Visible part of the table
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TablePage {
+    pub index: usize,
+    pub size: usize,
+}
+
+impl TablePage {
+    pub fn stored() -> Self {
+        let size = super::local_storage()
+            .and_then(|storage| storage.get_item(STORAGE_KEY).ok()?)
+            .and_then(|size| size.parse().ok())
+            .filter(|size| PAGE_SIZES.contains(size))
+            .unwrap_or(PAGE_SIZES[0]);
+        TablePage { index: 0, size }
+    }
+
+    pub fn first(self) -> Self {
+        TablePage { index: 0, ..self }
+    }
+
+    pub fn pages(self, rows: usize) -> usize {
+        rows.div_ceil(self.size).max(1)
+    }
+
+    /// Range of `rows` shown, the last page if the table shrunk below this one
+    pub fn range(self, rows: usize) -> std::ops::Range<usize> {
+        let start = self.index.min(self.pages(rows) - 1) * self.size;
+        start..(start + self.size).min(rows)
+    }
+}
+
+pub fn store_page_size(size: usize) {
+    if let Some(storage) = super::local_storage() {
+        let _ = storage.set_item(STORAGE_KEY, &size.to_string());
+    }
+}
+
+/// Previous and next buttons with a page size selector below the table
+pub fn pager_html(rows: usize, page: TablePage, on_page: Callback<TablePage>) -> Html {
+    let pages = page.pages(rows);
+    let index = page.index.min(pages - 1);
+    let go = |index: usize| {
+        let on_page = on_page.clone();
+        Callback::from(move |_: MouseEvent| on_page.emit(TablePage { index, ..page }))
+    };
+    let on_size = {
+        let on_page = on_page.clone();
+        Callback::from(move |event: Event| {
+            let value = event.target_unchecked_into::<HtmlSelectElement>().value();
+            if let Ok(size) = value.parse() {
+                store_page_size(size);
+                on_page.emit(TablePage { index: 0, size });
+            }
+        })
+    };
+    let mut sizes = PAGE_SIZES;
+    sizes.sort();
+
+    html! {
+        <div class="pager">
+            <button class="poll-button" disabled={index == 0} onclick={go(index.saturating_sub(1))}>
+                { "Previous" }
+            </button>
+            <span class="meta">{ format!("Page {} of {}, {} packages", index + 1, pages, rows) }</span>
+            <button class="poll-button" disabled={index + 1 >= pages} onclick={go(index + 1)}>
+                { "Next" }
+            </button>
+            <select onchange={on_size}>
+                { for sizes.iter().map(|size| html! {
+                    <option value={size.to_string()} selected={*size == page.size}>
+                        { format!("{} per page", size) }
+                    </option>
+                }) }
+            </select>
+        </div>
+    }
+}
@@ -0,0 +1,38 @@
+//! Timeline of the latest polls below the repo header, answering whether polling
+//! works without the server logs
+
+use wasm_bindgen::JsValue;
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+
+use crate::repo::{PollOutcome, RepoInfo};
+
+use super::i18n::{t, t_args};
+
+fn local_time(unix_secs: i64) -> String {
+    let date = Date::new(&JsValue::from_f64(unix_secs as f64 * 1000.0));
+    String::from(date.to_locale_string("default", &JsValue::UNDEFINED))
+}
+
+/// One tick per poll, oldest on the left, followed by the time of the next one
+pub fn poll_history_html(repo: &RepoInfo) -> Html {
+    if repo.poll_history.0.is_empty() && repo.next_poll.0.is_none() {
+        return html! {};
+    }
+    html! {
+        <div class="poll-history">
+            { for repo.poll_history.0.iter().map(|poll| {
+                let (class, outcome) = match &poll.outcome {
+                    PollOutcome::Unchanged => ("unchanged", t("poll.unchanged").to_string()),
+                    PollOutcome::Changed => ("changed", t("poll.changed").to_string()),
+                    PollOutcome::Failed(error) => ("failed", t_args("poll.failed", &[error])),
+                };
+                let title = format!("{}: {}", local_time(poll.unix_secs), outcome);
+                html! { <span class={classes!("poll-tick", class)} {title}></span> }
+            }) }
+            if let Some(unix_secs) = repo.next_poll.0 {
+                <span class="poll-next">{ t_args("poll.next", &[&local_time(unix_secs)]) }</span>
+            }
+        </div>
+    }
+}
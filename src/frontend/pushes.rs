@@ -0,0 +1,24 @@
+//! Where a successful build was pushed to, one badge per `post_build` target
+
+use yew::prelude::*;
+
+use crate::package::{PackageEnum, PushState};
+
+use super::i18n::t_args;
+
+pub fn pushes_html(pkg: &PackageEnum) -> Html {
+    html! {
+        for pkg.pushes().into_iter().map(|push| {
+            let (class, title) = match &push.state {
+                PushState::Pushing => ("pushing", t_args("push.pushing", &[&push.target])),
+                PushState::Pushed => ("pushed", t_args("push.pushed", &[&push.target])),
+                PushState::Failed(error) => {
+                    ("failed", t_args("push.failed", &[&push.target, error]))
+                }
+            };
+            html! {
+                <span class={classes!("push", class)} {title}>{ push.target.clone() }</span>
+            }
+        })
+    }
+}
@@ -0,0 +1,101 @@
+//! Place of the queued builds in the line for the build slots, with a guess when
+//! they start from how long the successful builds took
+
+use std::rc::Rc;
+
+use yew::prelude::*;
+
+use crate::{RepoList, package::PackageBuildStatus, queue::BuildQueue};
+
+use super::{
+    API_PREFIX, Fetched,
+    duration::{elapsed, now_secs},
+    fetch_json,
+};
+
+/// Milliseconds between fetches of the queue while builds wait
+pub const REFRESH_MS: u32 = 2000;
+
+#[derive(Clone, PartialEq, Default)]
+pub struct QueueInfo {
+    pub queue: Rc<BuildQueue>,
+    /// of the successful builds, `None` before the first one
+    pub average_secs: Option<i64>,
+}
+
+impl QueueInfo {
+    /// Starting at 1, `None` when the package does not wait for a slot
+    pub fn position(&self, repo_url: &str, commit: &str, path: &str) -> Option<usize> {
+        self.queue
+            .waiting
+            .iter()
+            .position(|entry| {
+                entry.repo == repo_url
+                    && entry.commit == commit
+                    && entry.path.as_deref() == Some(path)
+            })
+            .map(|index| index + 1)
+    }
+
+    /// Seconds until the build at `position` gets a slot, as if the running builds
+    /// had just started
+    pub fn estimate(&self, position: usize) -> Option<i64> {
+        let rounds = position.div_ceil(self.queue.slots.max(1));
+        Some(rounds as i64 * self.average_secs?)
+    }
+}
+
+pub fn average_secs(repos: &RepoList) -> Option<i64> {
+    let now = now_secs();
+    let durations = repos
+        .0
+        .0
+        .iter()
+        .flat_map(|repo| repo.commits.0.values())
+        .flat_map(|commit| commit.packages.0.iter())
+        .filter(|pkg| matches!(pkg.status(), PackageBuildStatus::Success(_)))
+        .filter_map(|pkg| elapsed(pkg.times(), now))
+        .collect::<Vec<_>>();
+    let count = durations.len() as i64;
+    (count > 0).then(|| durations.iter().sum::<i64>() / count)
+}
+
+/// Keep the last known queue if the fetch fails, the next one catches up
+pub fn fetch(queue: UseStateHandle<Rc<BuildQueue>>) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let path = format!("{}/queue", API_PREFIX);
+        if let Ok(Fetched::Changed(fetched, _)) = fetch_json::<BuildQueue>(&path, None).await {
+            queue.set(Rc::new(fetched));
+        }
+    });
+}
+
+/// "~12m", rounded up to whole minutes
+fn approx(secs: i64) -> String {
+    let minutes = (secs.max(0) + 59) / 60;
+    match minutes {
+        0..60 => format!("~{}m", minutes.max(1)),
+        _ => format!("~{}h {}m", minutes / 60, minutes % 60),
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct QueuePositionProps {
+    pub repo_url: AttrValue,
+    pub commit: AttrValue,
+    pub path: AttrValue,
+}
+
+/// "#4 in line, est. start in ~12m", only the position without finished builds
+#[function_component]
+pub fn QueuePosition(props: &QueuePositionProps) -> Html {
+    let info = use_context::<QueueInfo>().unwrap_or_default();
+    let Some(position) = info.position(&props.repo_url, &props.commit, &props.path) else {
+        return html! {};
+    };
+    let text = match info.estimate(position) {
+        Some(secs) => format!("#{} in line, est. start in {}", position, approx(secs)),
+        None => format!("position #{}", position),
+    };
+    html! { <span class="queue-position">{ text }</span> }
+}
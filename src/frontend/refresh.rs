@@ -0,0 +1,154 @@
+//! How often the dashboard polls, picked in the header and remembered in
+//! localStorage. `?refresh=5` overrides it for one visit, hidden tabs poll less and
+//! catch up as soon as they are shown again.
+
+use wasm_bindgen::{JsCast, prelude::Closure};
+use web_sys::HtmlSelectElement;
+use yew::prelude::*;
+
+use super::i18n::{t, t_args};
+
+const STORAGE_KEY: &str = "nix_autobuild.refresh";
+/// Seconds between polls offered in the header, `None` pauses
+const RATES: [Option<u32>; 4] = [Some(1), Some(5), Some(30), None];
+/// Hidden tabs poll at most this often
+const HIDDEN_SECS: u32 = 30;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct RefreshRate(Option<u32>);
+
+impl Default for RefreshRate {
+    fn default() -> Self {
+        RefreshRate(Some(1))
+    }
+}
+
+impl RefreshRate {
+    /// Seconds, or `paused`
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "paused" | "0" => Some(RefreshRate(None)),
+            secs => secs
+                .parse()
+                .ok()
+                .filter(|secs| *secs > 0)
+                .map(|secs| RefreshRate(Some(secs))),
+        }
+    }
+
+    fn value(self) -> String {
+        match self.0 {
+            Some(secs) => secs.to_string(),
+            None => "paused".to_string(),
+        }
+    }
+
+    fn label(self) -> String {
+        match self.0 {
+            Some(secs) => t_args("refresh.every", &[&secs]),
+            None => t("refresh.paused").to_string(),
+        }
+    }
+
+    fn from_url() -> Option<Self> {
+        let search = web_sys::window()?.location().search().ok()?;
+        let params = web_sys::UrlSearchParams::new_with_str(&search).ok()?;
+        Self::parse(&params.get("refresh")?)
+    }
+
+    /// The URL wins over the stored choice
+    pub fn stored() -> Self {
+        Self::from_url()
+            .or_else(|| Self::parse(&super::local_storage()?.get_item(STORAGE_KEY).ok()??))
+            .unwrap_or_default()
+    }
+
+    fn store(self) {
+        if let Some(storage) = super::local_storage() {
+            let _ = storage.set_item(STORAGE_KEY, &self.value());
+        }
+    }
+
+    pub fn secs(self) -> Option<u32> {
+        self.0
+    }
+
+    pub fn is_paused(self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Milliseconds between polls, `None` while paused
+    pub fn period_ms(self, visible: bool) -> Option<u32> {
+        let secs = match visible {
+            true => self.0?,
+            false => self.0?.max(HIDDEN_SECS),
+        };
+        Some(secs.saturating_mul(1000))
+    }
+}
+
+fn page_visible() -> bool {
+    web_sys::window()
+        .and_then(|window| window.document())
+        .is_none_or(|document| !document.hidden())
+}
+
+/// Whether the tab is shown, following `visibilitychange`
+#[hook]
+pub fn use_page_visible() -> bool {
+    let visible = use_state(page_visible);
+    {
+        let visible = visible.clone();
+        use_effect_with((), move |_| {
+            let onchange = Closure::<dyn FnMut()>::new(move || visible.set(page_visible()));
+            let document = web_sys::window().and_then(|window| window.document());
+            if let Some(document) = &document {
+                let _ = document.add_event_listener_with_callback(
+                    "visibilitychange",
+                    onchange.as_ref().unchecked_ref(),
+                );
+            }
+            move || {
+                if let Some(document) = document {
+                    let _ = document.remove_event_listener_with_callback(
+                        "visibilitychange",
+                        onchange.as_ref().unchecked_ref(),
+                    );
+                }
+            }
+        });
+    }
+    *visible
+}
+
+#[derive(Properties, PartialEq)]
+pub struct RefreshSelectProps {
+    pub rate: RefreshRate,
+    pub onchange: Callback<RefreshRate>,
+}
+
+/// Picking a rate remembers it, a rate from the URL is listed too
+#[function_component]
+pub fn RefreshSelect(props: &RefreshSelectProps) -> Html {
+    let onchange = props.onchange.clone();
+    let onchange = Callback::from(move |event: Event| {
+        let value = event.target_unchecked_into::<HtmlSelectElement>().value();
+        if let Some(rate) = RefreshRate::parse(&value) {
+            rate.store();
+            onchange.emit(rate);
+        }
+    });
+    let mut rates = RATES.map(RefreshRate).to_vec();
+    if !rates.contains(&props.rate) {
+        rates.insert(0, props.rate);
+    }
+    html! {
+        <select class="poll-button refresh-select" aria-label={t("refresh.label")} {onchange}>
+            { for rates.into_iter().map(|rate| html! {
+                <option value={rate.value()} selected={rate == props.rate}>
+                    { rate.label() }
+                </option>
+            }) }
+        </select>
+    }
+}
@@ -0,0 +1,200 @@
+//! Queueing failed builds again from the dashboard, and successful ones whose
+//! result was garbage collected
+
+use gloo_net::http::Request;
+use yew::prelude::*;
+
+use crate::{
+    event::StateEvent,
+    package::{BuildTimes, PackageBuildStatus},
+};
+
+use super::{API_PREFIX, ApplyEvent, base_path, toast::Toaster};
+
+/// Why the backend did not queue the build
+enum RebuildError {
+    /// the build is queued or running already, its status arrives with the updates
+    Conflict,
+    /// e.g. a reverse proxy that requires a token the browser does not have
+    Unauthorized,
+    Other(String),
+}
+
+/// Whether this browser may queue rebuilds, provided by `RebuildAccessProvider`.
+/// The backend has no tokens of its own, a proxy in front of it may refuse the
+/// mutating endpoints though, and after the first refusal no Retry button is shown.
+#[derive(Clone, PartialEq)]
+pub struct RebuildAccess {
+    denied: bool,
+    deny: Callback<()>,
+}
+
+#[derive(Properties, PartialEq)]
+pub struct RebuildAccessProviderProps {
+    #[prop_or_default]
+    pub children: Html,
+}
+
+#[function_component]
+pub fn RebuildAccessProvider(props: &RebuildAccessProviderProps) -> Html {
+    let denied = use_state(|| false);
+    let access = RebuildAccess {
+        denied: *denied,
+        deny: Callback::from(move |()| denied.set(true)),
+    };
+    html! {
+        <ContextProvider<RebuildAccess> context={access}>
+            { props.children.clone() }
+        </ContextProvider<RebuildAccess>>
+    }
+}
+
+async fn request_rebuild(repo_name: &str, commit: &str, path: &str) -> Result<(), RebuildError> {
+    let url = format!(
+        "{}{}/repos/{}/commits/{}/packages/{}/rebuild",
+        base_path(),
+        API_PREFIX,
+        repo_name,
+        commit,
+        path
+    );
+    let response = Request::post(&url)
+        .send()
+        .await
+        .map_err(|e| RebuildError::Other(format!("Rebuild request failed: {}", e)))?;
+    match response.status() {
+        200..300 => Ok(()),
+        409 => Err(RebuildError::Conflict),
+        422 => Err(RebuildError::Other(format!(
+            "{} was skipped or never built, there is nothing to rebuild",
+            path
+        ))),
+        401 | 403 => Err(RebuildError::Unauthorized),
+        404 => Err(RebuildError::Other(format!(
+            "{} is no longer tracked",
+            path
+        ))),
+        503 => Err(RebuildError::Other(
+            "The server is shutting down".to_string(),
+        )),
+        status => Err(RebuildError::Other(format!(
+            "Rebuild of {} failed with HTTP {}",
+            path, status
+        ))),
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct RetryButtonProps {
+    pub repo_url: String,
+    pub repo_name: String,
+    pub commit: String,
+    pub path: String,
+    /// of the finished build, shown again if the rebuild is refused
+    pub status: PackageBuildStatus,
+    pub times: BuildTimes,
+}
+
+/// Shows the package as queued right away and puts the status back if the backend
+/// refuses
+#[function_component]
+pub fn RetryButton(props: &RetryButtonProps) -> Html {
+    let in_flight = use_state(|| false);
+    let apply_event = use_context::<ApplyEvent>();
+    let toaster = use_context::<Toaster>();
+    let access = use_context::<RebuildAccess>();
+    let onclick = {
+        let access = access.clone();
+        let in_flight = in_flight.clone();
+        let repo_url = props.repo_url.clone();
+        let repo_name = props.repo_name.clone();
+        let commit = props.commit.clone();
+        let path = props.path.clone();
+        let status = props.status.clone();
+        let times = props.times;
+        Callback::from(move |event: MouseEvent| {
+            // the button sits inside links and expandable rows
+            event.prevent_default();
+            event.stop_propagation();
+            if *in_flight {
+                return;
+            }
+            in_flight.set(true);
+            let status_event = |status| StateEvent::PackageStatus {
+                repo: repo_url.clone(),
+                commit: commit.clone(),
+                path: path.clone(),
+                status,
+                times,
+                closure_size: None,
+                expected_secs: None,
+                attempts: None,
+            };
+            let queued = status_event(PackageBuildStatus::WaitingForBuild);
+            let mut previous = vec![status_event(status.clone())];
+            // a successful build is only rebuilt to restore its collected result
+            if matches!(status, PackageBuildStatus::Success(_)) {
+                previous.push(StateEvent::PackageResult {
+                    repo: repo_url.clone(),
+                    commit: commit.clone(),
+                    path: path.clone(),
+                    missing: true,
+                });
+            }
+            if let Some(apply_event) = &apply_event {
+                apply_event.0.emit(queued);
+            }
+            let in_flight = in_flight.clone();
+            let apply_event = apply_event.clone();
+            let toaster = toaster.clone();
+            let access = access.clone();
+            let (repo_name, commit, path) = (repo_name.clone(), commit.clone(), path.clone());
+            wasm_bindgen_futures::spawn_local(async move {
+                let result = request_rebuild(&repo_name, &commit, &path).await;
+                in_flight.set(false);
+                let message = match result {
+                    Ok(()) => return,
+                    Err(RebuildError::Conflict) => {
+                        format!("{} is already queued or building", path)
+                    }
+                    Err(RebuildError::Unauthorized) => {
+                        if let Some(apply_event) = &apply_event {
+                            previous
+                                .into_iter()
+                                .for_each(|event| apply_event.0.emit(event));
+                        }
+                        if let Some(access) = &access {
+                            access.deny.emit(());
+                        }
+                        "Not authorized to rebuild packages".to_string()
+                    }
+                    Err(RebuildError::Other(message)) => {
+                        if let Some(apply_event) = &apply_event {
+                            previous
+                                .into_iter()
+                                .for_each(|event| apply_event.0.emit(event));
+                        }
+                        message
+                    }
+                };
+                if let Some(toaster) = toaster {
+                    toaster.show(message);
+                }
+            });
+        })
+    };
+    if access.as_ref().is_some_and(|access| access.denied) {
+        return html! {};
+    }
+    let label = match (&props.status, *in_flight) {
+        (PackageBuildStatus::Success(_), true) => "Restoring…",
+        (PackageBuildStatus::Success(_), false) => "Rebuild to restore",
+        (_, true) => "Retrying…",
+        (_, false) => "Retry",
+    };
+    html! {
+        <button class="poll-button retry-button" disabled={*in_flight} {onclick}>
+            { label }
+        </button>
+    }
+}
@@ -0,0 +1,192 @@
+//! Driving the table from the keyboard. The shortcuts act on the rows as rendered,
+//! so they follow the filters, sorting and page without knowing about them.
+
+use wasm_bindgen::{JsCast, prelude::Closure};
+use web_sys::{
+    Document, Element, HtmlElement, KeyboardEvent, ScrollIntoViewOptions, ScrollLogicalPosition,
+};
+use yew::prelude::*;
+
+use super::i18n::t;
+
+/// Rows of the package table, `data-row` holds their `expanded::row_id`
+const ROW_SELECTOR: &str = ".repo-table tbody tr[data-row]";
+const SEARCH_SELECTOR: &str = ".filter-bar input[type='search']";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Shortcut {
+    Next,
+    Previous,
+    Toggle,
+    FocusSearch,
+    FailuresOnly,
+    Retry,
+    Help,
+    Close,
+}
+
+/// Keys and what they do, in the order of the overlay
+const KEYS: [(&str, &str); 8] = [
+    ("j", "shortcuts.next"),
+    ("k", "shortcuts.previous"),
+    ("Enter", "shortcuts.toggle"),
+    ("/", "shortcuts.search"),
+    ("f", "shortcuts.failures"),
+    ("r", "shortcuts.retry"),
+    ("?", "shortcuts.help"),
+    ("Esc", "shortcuts.close"),
+];
+
+/// Id of the selected row, given to the rows so they can highlight themselves
+#[derive(Clone, PartialEq, Default)]
+pub struct SelectedRow(pub Option<String>);
+
+fn document() -> Option<Document> {
+    web_sys::window()?.document()
+}
+
+/// Keys typed into a field belong to the field, and Enter on a button or link
+/// activates it
+fn owned_by_target(event: &KeyboardEvent) -> bool {
+    let Some(target) = event
+        .target()
+        .and_then(|target| target.dyn_into::<HtmlElement>().ok())
+    else {
+        return false;
+    };
+    let tag = target.tag_name();
+    matches!(tag.as_str(), "INPUT" | "TEXTAREA" | "SELECT")
+        || target.is_content_editable()
+        || (event.key() == "Enter" && matches!(tag.as_str(), "BUTTON" | "A" | "SUMMARY"))
+}
+
+fn shortcut(event: &KeyboardEvent) -> Option<Shortcut> {
+    if event.ctrl_key() || event.meta_key() || event.alt_key() || owned_by_target(event) {
+        return None;
+    }
+    match event.key().as_str() {
+        "j" => Some(Shortcut::Next),
+        "k" => Some(Shortcut::Previous),
+        "Enter" => Some(Shortcut::Toggle),
+        "/" => Some(Shortcut::FocusSearch),
+        "f" => Some(Shortcut::FailuresOnly),
+        "r" => Some(Shortcut::Retry),
+        "?" => Some(Shortcut::Help),
+        "Escape" => Some(Shortcut::Close),
+        _ => None,
+    }
+}
+
+/// Calls `onshortcut` for the shortcuts pressed anywhere on the page
+#[hook]
+pub fn use_shortcuts(onshortcut: Callback<Shortcut>) {
+    use_effect_with(onshortcut, |onshortcut| {
+        let onshortcut = onshortcut.clone();
+        let onkeydown = Closure::<dyn FnMut(KeyboardEvent)>::new(move |event: KeyboardEvent| {
+            if let Some(shortcut) = shortcut(&event) {
+                event.prevent_default();
+                onshortcut.emit(shortcut);
+            }
+        });
+        let document = document();
+        if let Some(document) = &document {
+            let _ = document
+                .add_event_listener_with_callback("keydown", onkeydown.as_ref().unchecked_ref());
+        }
+        move || {
+            if let Some(document) = document {
+                let _ = document.remove_event_listener_with_callback(
+                    "keydown",
+                    onkeydown.as_ref().unchecked_ref(),
+                );
+            }
+        }
+    });
+}
+
+fn rows() -> Vec<(String, HtmlElement)> {
+    let Some(list) = document().and_then(|document| document.query_selector_all(ROW_SELECTOR).ok())
+    else {
+        return Vec::new();
+    };
+    (0..list.length())
+        .filter_map(|index| list.item(index)?.dyn_into::<HtmlElement>().ok())
+        .filter_map(|row| Some((row.get_attribute("data-row")?, row)))
+        .collect()
+}
+
+/// The row `offset` rows away from `selected`, stopping at the first and last row.
+/// Without a selection, or one that is no longer shown, the first row.
+pub fn step(selected: Option<&str>, offset: isize) -> Option<String> {
+    let rows = rows();
+    let index = match rows
+        .iter()
+        .position(|(id, _)| Some(id.as_str()) == selected)
+    {
+        Some(index) => index.saturating_add_signed(offset).min(rows.len() - 1),
+        None => 0,
+    };
+    rows.into_iter().nth(index).map(|(id, _)| id)
+}
+
+/// Clicks the row `id`, or the element matching `within` inside of it
+pub fn click(id: &str, within: Option<&str>) {
+    let Some((_, row)) = rows().into_iter().find(|(row_id, _)| row_id == id) else {
+        return;
+    };
+    let target = match within {
+        Some(selector) => row
+            .query_selector(selector)
+            .ok()
+            .flatten()
+            .and_then(|element| element.dyn_into::<HtmlElement>().ok()),
+        None => Some(row),
+    };
+    if let Some(target) = target {
+        target.click();
+    }
+}
+
+pub fn focus_search() {
+    let search = document()
+        .and_then(|document| document.query_selector(SEARCH_SELECTOR).ok()?)
+        .and_then(|element| element.dyn_into::<HtmlElement>().ok());
+    if let Some(search) = search {
+        let _ = search.focus();
+    }
+}
+
+/// Scrolls only as far as needed to show all of `element`
+pub fn scroll_into_view(element: &Element) {
+    let options = ScrollIntoViewOptions::new();
+    options.set_block(ScrollLogicalPosition::Nearest);
+    element.scroll_into_view_with_scroll_into_view_options(&options);
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ShortcutHelpProps {
+    pub onclose: Callback<()>,
+}
+
+#[function_component]
+pub fn ShortcutHelp(props: &ShortcutHelpProps) -> Html {
+    let onclose = props.onclose.reform(|_: MouseEvent| ());
+    // clicks on the dialog itself keep it open
+    let keep_open = Callback::from(|event: MouseEvent| event.stop_propagation());
+    html! {
+        <div class="shortcut-overlay" onclick={onclose.clone()}>
+            <section class="card shortcut-dialog" role="dialog" aria-modal="true" onclick={keep_open}>
+                <h2>{ t("shortcuts.title") }</h2>
+                <dl class="shortcut-list">
+                    { for KEYS.iter().map(|(key, description)| html! {
+                        <>
+                            <dt><kbd>{ *key }</kbd></dt>
+                            <dd>{ t(description) }</dd>
+                        </>
+                    }) }
+                </dl>
+                <button class="poll-button" onclick={onclose}>{ t("shortcuts.dismiss") }</button>
+            </section>
+        </div>
+    }
+}
@@ -0,0 +1,91 @@
+//! Banner over the last known state while the server can not be reached, the page
+//! keeps showing what it had and the polling retries on its own
+
+use std::rc::Rc;
+
+use gloo_timers::callback::Interval;
+use wasm_bindgen::JsValue;
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+
+use super::{FetchError, LiveState};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outage {
+    /// first failed fetch, in milliseconds since the epoch
+    pub since: f64,
+    /// of the latest fetch
+    pub error: FetchError,
+    pub dismissed: bool,
+}
+
+impl Outage {
+    pub fn new(error: FetchError) -> Self {
+        Outage {
+            since: Date::now(),
+            error,
+            dismissed: false,
+        }
+    }
+}
+
+/// "14:03" in local time
+fn clock(millis: f64) -> String {
+    let date = Date::new(&JsValue::from_f64(millis));
+    format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
+
+#[derive(Properties)]
+pub struct StaleBannerProps {
+    pub live: Rc<LiveState>,
+}
+
+impl PartialEq for StaleBannerProps {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.live, &other.live)
+    }
+}
+
+/// Checks the state every second like `LastUpdated`, a failing fetch does not
+/// render the page
+#[function_component]
+pub fn StaleBanner(props: &StaleBannerProps) -> Html {
+    let update = use_force_update();
+    {
+        let update = update.clone();
+        use_effect_with((), move |_| {
+            let interval = Interval::new(1000, move || update.force_update());
+            move || drop(interval)
+        });
+    }
+    let Some(outage) = props.live.outage.borrow().clone() else {
+        return html! {};
+    };
+    if outage.dismissed {
+        return html! {};
+    }
+    let since = clock(outage.since);
+    let text = match &outage.error {
+        FetchError::Network(_) => format!("Live data unavailable since {}, retrying…", since),
+        FetchError::Parse(_) => format!(
+            "The server sends data this page does not understand since {}, probably \
+             after an update. A hard refresh (Ctrl+Shift+R) loads the new version.",
+            since
+        ),
+    };
+    let ondismiss = {
+        let live = props.live.clone();
+        Callback::from(move |_: MouseEvent| {
+            if let Some(outage) = live.outage.borrow_mut().as_mut() {
+                outage.dismissed = true;
+            }
+            update.force_update();
+        })
+    };
+    html! {
+        <div class="stale-banner" role="alert" title={outage.error.to_string()}>
+            <span>{ text }</span>
+            <button class="poll-button" onclick={ondismiss}>{ "Dismiss" }</button>
+        </div>
+    }
+}
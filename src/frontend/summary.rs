@@ -0,0 +1,168 @@
+//! Totals over all repos at the top of the page, readable from across the room
+
+use std::collections::HashSet;
+
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+
+use crate::{RepoList, package::PackageBuildStatus};
+
+use super::{
+    Props,
+    filter::Filter,
+    i18n::{t, t_args},
+};
+
+#[derive(Default)]
+pub struct Totals {
+    pub repos: usize,
+    pub building: usize,
+    pub queued: usize,
+    /// of the commits at the branch tips
+    pub failed: usize,
+    pub succeeded: usize,
+}
+
+pub fn totals(repos: &RepoList) -> Totals {
+    let mut totals = Totals {
+        repos: repos.0.0.len(),
+        ..Totals::default()
+    };
+    for repo in repos.0.0.iter() {
+        let tips = repo
+            .branch_commit_hashes
+            .values()
+            .filter_map(|hashes| hashes.0.first())
+            .collect::<HashSet<_>>();
+        for (hash, commit) in repo.commits.0.iter() {
+            let latest = tips.contains(hash);
+            for pkg in commit.packages.0.iter() {
+                match pkg.status() {
+                    PackageBuildStatus::Building => totals.building += 1,
+                    PackageBuildStatus::Idle
+                    | PackageBuildStatus::WaitingForWindow
+                    | PackageBuildStatus::WaitingForBuild => totals.queued += 1,
+                    PackageBuildStatus::Failed(_) if latest => totals.failed += 1,
+                    PackageBuildStatus::Success(_) if latest => totals.succeeded += 1,
+                    _ => {}
+                }
+            }
+        }
+    }
+    totals
+}
+
+/// Counts of the header line
+#[derive(PartialEq)]
+pub struct Pending {
+    pub building: usize,
+    pub queued: usize,
+    pub failed_today: usize,
+}
+
+/// Start of the current day in local time, in seconds since the epoch
+pub fn local_midnight_secs() -> i64 {
+    let today = Date::new_0();
+    today.set_hours(0);
+    today.set_minutes(0);
+    today.set_seconds(0);
+    today.set_milliseconds(0);
+    (today.get_time() / 1000.0) as i64
+}
+
+/// Failures count from when the build finished, or from the commit time for
+/// states from before the build times were kept
+pub fn pending(repos: &RepoList, midnight: i64) -> Pending {
+    let totals = totals(repos);
+    let failed_today = repos
+        .0
+        .0
+        .iter()
+        .flat_map(|repo| repo.commits.0.values())
+        .flat_map(|commit| {
+            commit
+                .packages
+                .0
+                .iter()
+                .map(move |pkg| (pkg, commit.unix_secs))
+        })
+        .filter(|(pkg, _)| matches!(pkg.status(), PackageBuildStatus::Failed(_)))
+        .filter(|(pkg, commit_secs)| pkg.times().finished.unwrap_or(*commit_secs) >= midnight)
+        .count();
+    Pending {
+        building: totals.building,
+        queued: totals.queued,
+        failed_today,
+    }
+}
+
+/// "3 building · 7 queued · 2 failed today", each part a link to its status filter
+pub fn pending_html(pending: &Pending, props: &Props, onchange: Callback<Filter>) -> Html {
+    let segment = |text: String, category: &str, alert: bool| {
+        let filter = Filter {
+            categories: vec![category.to_string()],
+            ..props.filter.clone()
+        };
+        let href = Props {
+            filter: filter.clone(),
+            ..props.clone()
+        }
+        .get_url();
+        let onchange = onchange.clone();
+        let onclick = Callback::from(move |event: MouseEvent| {
+            event.prevent_default();
+            onchange.emit(filter.clone());
+        });
+        html! {
+            <a class={classes!("pending-link", alert.then_some("alert"))} {href} {onclick}>
+                { text }
+            </a>
+        }
+    };
+    html! {
+        <p class="meta pending-counter" aria-live="polite">
+            { segment(t_args("pending.building", &[&pending.building]), "building", false) }
+            { t("pending.separator") }
+            { segment(t_args("pending.queued", &[&pending.queued]), "queued", false) }
+            { t("pending.separator") }
+            { segment(
+                t_args("pending.failed_today", &[&pending.failed_today]),
+                "failed",
+                pending.failed_today > 0,
+            ) }
+        </p>
+    }
+}
+
+/// Each count applies the matching status filter, the repo count clears it
+pub fn summary_html(repos: &RepoList, filter: &Filter, onchange: Callback<Filter>) -> Html {
+    let totals = totals(repos);
+    let item = |label: &str, count: usize, category: Option<&str>, alert: bool| {
+        let onclick = {
+            let filter = filter.clone();
+            let onchange = onchange.clone();
+            let categories = category.map(|c| vec![c.to_string()]).unwrap_or_default();
+            Callback::from(move |_: MouseEvent| {
+                onchange.emit(Filter {
+                    categories: categories.clone(),
+                    ..filter.clone()
+                })
+            })
+        };
+        html! {
+            <button class={classes!("summary-item", alert.then_some("alert"))} {onclick}>
+                <span class="summary-count">{ count }</span>
+                <span class="summary-label">{ label.to_string() }</span>
+            </button>
+        }
+    };
+    html! {
+        <div class="summary-strip">
+            { item("repos", totals.repos, None, false) }
+            { item("building", totals.building, Some("building"), false) }
+            { item("queued", totals.queued, Some("queued"), false) }
+            { item("failed in latest commits", totals.failed, Some("failed"), totals.failed > 0) }
+            { item("succeeded in latest commits", totals.succeeded, Some("success"), false) }
+        </div>
+    }
+}
@@ -0,0 +1,165 @@
+//! Sorting the package table by a clicked column header. Sorting keeps the default
+//! order between equal rows, so pages stay stable across refreshes.
+
+use yew::prelude::*;
+
+use crate::{
+    commit::CommitInfo,
+    package::{PackageBuildStatus, PackageEnum},
+    repo::RepoInfo,
+};
+
+use super::{
+    duration::{elapsed, now_secs},
+    filter::{CATEGORIES, category},
+};
+
+/// localStorage key of the sort, the column name with a `-` prefix when reversed
+const STORAGE_KEY: &str = "nix_autobuild.table_sort";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SortColumn {
+    Repository,
+    Package,
+    Branch,
+    Commit,
+    Duration,
+    Status,
+}
+
+const COLUMNS: [SortColumn; 6] = [
+    SortColumn::Repository,
+    SortColumn::Package,
+    SortColumn::Branch,
+    SortColumn::Commit,
+    SortColumn::Duration,
+    SortColumn::Status,
+];
+
+impl SortColumn {
+    fn name(self) -> &'static str {
+        match self {
+            SortColumn::Repository => "repository",
+            SortColumn::Package => "package",
+            SortColumn::Branch => "branch",
+            SortColumn::Commit => "commit",
+            SortColumn::Duration => "duration",
+            SortColumn::Status => "status",
+        }
+    }
+}
+
+/// The first click sorts the way that is most useful for a column, e.g. newest
+/// commits, longest builds and failures first. `reversed` flips that.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TableSort {
+    pub column: SortColumn,
+    pub reversed: bool,
+}
+
+impl TableSort {
+    pub fn stored() -> Option<Self> {
+        let stored = super::local_storage()?.get_item(STORAGE_KEY).ok()??;
+        let (reversed, name) = match stored.strip_prefix('-') {
+            Some(name) => (true, name.to_string()),
+            None => (false, stored),
+        };
+        let column = COLUMNS.into_iter().find(|column| column.name() == name)?;
+        Some(TableSort { column, reversed })
+    }
+
+    /// Sort after a click on `column`: its natural order, reversed, then the default
+    pub fn next(current: Option<Self>, column: SortColumn) -> Option<Self> {
+        let next = match current {
+            Some(sort) if sort.column == column && sort.reversed => None,
+            Some(sort) if sort.column == column => Some(TableSort {
+                column,
+                reversed: true,
+            }),
+            _ => Some(TableSort {
+                column,
+                reversed: false,
+            }),
+        };
+        if let Some(storage) = super::local_storage() {
+            let _ = match next {
+                Some(sort) if sort.reversed => {
+                    storage.set_item(STORAGE_KEY, &format!("-{}", sort.column.name()))
+                }
+                Some(sort) => storage.set_item(STORAGE_KEY, sort.column.name()),
+                None => storage.remove_item(STORAGE_KEY),
+            };
+        }
+        next
+    }
+}
+
+/// Arrow of the header of `column`, pointing at the end the first rows come from
+pub fn marker(sort: Option<TableSort>, column: SortColumn) -> &'static str {
+    match sort {
+        Some(sort) if sort.column == column && sort.reversed => " ↑",
+        Some(sort) if sort.column == column => " ↓",
+        _ => "",
+    }
+}
+
+/// Failures first, then building, queued, successful and skipped builds, the order
+/// of the status chips
+fn severity(status: &PackageBuildStatus) -> Option<usize> {
+    CATEGORIES.iter().position(|name| *name == category(status))
+}
+
+pub fn branch_of(repo: &RepoInfo, commit: &CommitInfo) -> String {
+    repo.branch_commit_hashes
+        .iter()
+        .find_map(|(branch, hashes)| hashes.0.contains(&commit.hash).then(|| branch.clone()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub type Row<'a> = (&'a RepoInfo, &'a CommitInfo, &'a PackageEnum);
+
+pub fn sort_rows(rows: &mut [Row<'_>], sort: TableSort) {
+    let now = now_secs();
+    let compare = |(repo_a, commit_a, pkg_a): &Row<'_>, (repo_b, commit_b, pkg_b): &Row<'_>| {
+        match sort.column {
+            SortColumn::Repository => repo_a.repo.url.cmp(&repo_b.repo.url),
+            SortColumn::Package => pkg_a.path().cmp(pkg_b.path()),
+            SortColumn::Branch => branch_of(repo_a, commit_a).cmp(&branch_of(repo_b, commit_b)),
+            SortColumn::Commit => commit_b.unix_secs.cmp(&commit_a.unix_secs),
+            SortColumn::Duration => {
+                let duration_a = elapsed(pkg_a.times(), now);
+                let duration_b = elapsed(pkg_b.times(), now);
+                duration_b.cmp(&duration_a)
+            }
+            SortColumn::Status => severity(&pkg_a.status()).cmp(&severity(&pkg_b.status())),
+        }
+    };
+    // packages that never started go last either way
+    let unstarted = |row: &Row<'_>| {
+        sort.column == SortColumn::Duration && elapsed(row.2.times(), now).is_none()
+    };
+    rows.sort_by(|a, b| {
+        unstarted(a)
+            .cmp(&unstarted(b))
+            .then_with(|| match sort.reversed {
+                true => compare(b, a),
+                false => compare(a, b),
+            })
+    });
+}
+
+/// Header cell sorting by `column` when clicked
+pub fn header_html(
+    label: &str,
+    column: SortColumn,
+    sort: Option<TableSort>,
+    on_sort: &Callback<SortColumn>,
+    class: Option<&'static str>,
+) -> Html {
+    let onclick = on_sort.reform(move |_: MouseEvent| column);
+    html! {
+        <th class={classes!("sortable", class)} {onclick}>
+            { format!("{}{}", label, marker(sort, column)) }
+        </th>
+    }
+}
@@ -0,0 +1,50 @@
+//! Dark and light palette. `index.html` puts the `theme-dark` or `theme-light`
+//! class on the root element before the first paint, from the stored choice or
+//! `prefers-color-scheme`, the toggle only flips it.
+
+use web_sys::Element;
+use yew::prelude::*;
+
+/// Read by `index.html` too
+const STORAGE_KEY: &str = "nix_autobuild.theme";
+
+fn root() -> Option<Element> {
+    web_sys::window()?.document()?.document_element()
+}
+
+fn is_light() -> bool {
+    root().is_some_and(|root| root.class_list().contains("theme-light"))
+}
+
+/// Switch the palette and remember the explicit choice
+fn apply(light: bool) {
+    let (theme, other) = match light {
+        true => ("light", "dark"),
+        false => ("dark", "light"),
+    };
+    if let Some(root) = root() {
+        let classes = root.class_list();
+        let _ = classes.remove_1(&format!("theme-{}", other));
+        let _ = classes.add_1(&format!("theme-{}", theme));
+    }
+    if let Some(storage) = super::local_storage() {
+        let _ = storage.set_item(STORAGE_KEY, theme);
+    }
+}
+
+#[function_component]
+pub fn ThemeToggle() -> Html {
+    let light = use_state(is_light);
+    let onclick = {
+        let light = light.clone();
+        Callback::from(move |_: MouseEvent| {
+            apply(!*light);
+            light.set(!*light);
+        })
+    };
+    html! {
+        <button class="poll-button theme-toggle" {onclick}>
+            { if *light { "Dark theme" } else { "Light theme" } }
+        </button>
+    }
+}
@@ -0,0 +1,60 @@
+//! Relative commit times, "2 hours ago", with the absolute local time as tooltip
+
+use gloo_timers::callback::Interval;
+use wasm_bindgen::JsValue;
+use web_sys::js_sys::Date;
+use yew::prelude::*;
+
+use super::duration::now_secs;
+
+/// Older commits show their timestamp instead
+const MAX_RELATIVE_DAYS: i64 = 30;
+
+fn plural(count: i64, unit: &str) -> String {
+    match count {
+        1 => format!("1 {} ago", unit),
+        _ => format!("{} {}s ago", count, unit),
+    }
+}
+
+/// `None` when more than `MAX_RELATIVE_DAYS` ago, a commit dated in the future is
+/// treated as just now
+pub fn relative(age_secs: i64) -> Option<String> {
+    let age = age_secs.max(0);
+    Some(match age {
+        0..60 => "just now".to_string(),
+        60..3600 => plural(age / 60, "minute"),
+        3600..86_400 => plural(age / 3600, "hour"),
+        _ if age / 86_400 <= MAX_RELATIVE_DAYS => plural(age / 86_400, "day"),
+        _ => return None,
+    })
+}
+
+fn date(unix_secs: i64) -> Date {
+    Date::new(&JsValue::from_f64(unix_secs as f64 * 1000.0))
+}
+
+#[derive(Properties, PartialEq)]
+pub struct TimeAgoProps {
+    pub unix_secs: i64,
+}
+
+#[function_component]
+pub fn TimeAgo(props: &TimeAgoProps) -> Html {
+    let update = use_force_update();
+    let age = now_secs() - props.unix_secs;
+    // seconds only matter during the first minute
+    let period = if age < 60 { 1000 } else { 30_000 };
+    use_effect_with(period, move |period| {
+        let interval = Interval::new(*period, move || update.force_update());
+        move || drop(interval)
+    });
+    let date = date(props.unix_secs);
+    let iso = String::from(date.to_iso_string());
+    let local = String::from(date.to_locale_string("default", &JsValue::UNDEFINED));
+    html! {
+        <time class="time-ago" datetime={iso.clone()} title={local}>
+            { relative(age).unwrap_or(iso) }
+        </time>
+    }
+}
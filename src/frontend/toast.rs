@@ -0,0 +1,100 @@
+//! Short lived messages in the corner of the page, e.g. for failed requests
+
+use std::rc::Rc;
+
+use gloo_timers::callback::Timeout;
+use yew::prelude::*;
+
+/// Milliseconds a message stays unless clicked away earlier
+const TOAST_MS: u32 = 6000;
+
+/// Shows a message, provided by `ToastProvider`
+#[derive(Clone, PartialEq)]
+pub struct Toaster(Callback<String>);
+
+impl Toaster {
+    pub fn show(&self, message: impl Into<String>) {
+        self.0.emit(message.into());
+    }
+}
+
+enum ToastAction {
+    Push(String),
+    Dismiss(u32),
+}
+
+#[derive(Default)]
+struct Toasts {
+    next_id: u32,
+    shown: Vec<(u32, String)>,
+}
+
+impl Reducible for Toasts {
+    type Action = ToastAction;
+
+    fn reduce(self: Rc<Self>, action: Self::Action) -> Rc<Self> {
+        let mut shown = self.shown.clone();
+        let mut next_id = self.next_id;
+        match action {
+            ToastAction::Push(message) => {
+                shown.push((next_id, message));
+                next_id += 1;
+            }
+            ToastAction::Dismiss(id) => shown.retain(|(shown_id, _)| *shown_id != id),
+        }
+        Rc::new(Toasts { next_id, shown })
+    }
+}
+
+#[derive(Properties, PartialEq)]
+pub struct ToastProviderProps {
+    #[prop_or_default]
+    pub children: Html,
+}
+
+#[function_component]
+pub fn ToastProvider(props: &ToastProviderProps) -> Html {
+    let toasts = use_reducer(Toasts::default);
+    let toaster = {
+        let toasts = toasts.dispatcher();
+        Toaster(Callback::from(move |message| {
+            toasts.dispatch(ToastAction::Push(message))
+        }))
+    };
+    html! {
+        <ContextProvider<Toaster> context={toaster}>
+            { props.children.clone() }
+            <div class="toasts">
+                { for toasts.shown.iter().map(|(id, message)| {
+                    let ondismiss = {
+                        let toasts = toasts.dispatcher();
+                        let id = *id;
+                        Callback::from(move |_| toasts.dispatch(ToastAction::Dismiss(id)))
+                    };
+                    html! { <Toast key={*id} message={message.clone()} {ondismiss} /> }
+                }) }
+            </div>
+        </ContextProvider<Toaster>>
+    }
+}
+
+#[derive(Properties, PartialEq)]
+struct ToastProps {
+    message: String,
+    ondismiss: Callback<()>,
+}
+
+#[function_component]
+fn Toast(props: &ToastProps) -> Html {
+    {
+        let ondismiss = props.ondismiss.clone();
+        use_effect_with((), move |_| {
+            let timeout = Timeout::new(TOAST_MS, move || ondismiss.emit(()));
+            move || drop(timeout)
+        });
+    }
+    let onclick = props.ondismiss.reform(|_: MouseEvent| ());
+    html! {
+        <div class="toast" role="alert" {onclick}>{ &props.message }</div>
+    }
+}
@@ -1,14 +1,18 @@
 #[cfg(not(target_arch = "wasm32"))]
 
 #[actix_web::main]
-async fn main() {
+async fn main() -> std::process::ExitCode {
     use nix_autobuild::backend::main;
 
-    main().await.unwrap();
+    // `Display` keeps the lines of multi-line errors apart
+    main().await.unwrap_or_else(|e| {
+        eprintln!("ERROR {}", e);
+        std::process::ExitCode::FAILURE
+    })
 }
 
 #[cfg(target_arch = "wasm32")]
 fn main() {
     use nix_autobuild::frontend::main;
     main();
-}
\ No newline at end of file
+}
@@ -0,0 +1,120 @@
+#![cfg(not(target_arch = "wasm32"))]
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const GRACE_SECS: u64 = 5;
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|address| address.port())
+        .unwrap_or(18_181)
+}
+
+fn connect(port: u16, timeout: Duration) -> Option<TcpStream> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(stream) = TcpStream::connect(("127.0.0.1", port)) {
+            return Some(stream);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+fn wait_exit(child: &mut Child, timeout: Duration) -> Option<std::process::ExitStatus> {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(Some(status)) = child.try_wait() {
+            return Some(status);
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+#[test]
+fn exits_cleanly_on_sigterm() {
+    let dir = std::env::temp_dir().join(format!("nix_autobuild_shutdown_{}", std::process::id()));
+    let _ = std::fs::create_dir_all(&dir);
+    let port = free_port();
+    let frontend = dir.join("dist");
+    let _ = std::fs::create_dir_all(&frontend);
+    let _ = std::fs::write(frontend.join("index.html"), "index");
+    let config = dir.join("config.json");
+    let written = std::fs::write(
+        &config,
+        format!(
+            r#"{{"repos": [], "dir": "{}", "supported_architectures": [], "n_build_threads": 1,
+                "host": "127.0.0.1", "port": {}, "shutdown_grace_secs": {},
+                "frontend_dir": "{}"}}"#,
+            dir.join("data").display(),
+            port,
+            GRACE_SECS,
+            frontend.display()
+        ),
+    );
+    assert!(written.is_ok(), "writing {}", config.display());
+
+    let Ok(mut child) = Command::new(env!("CARGO_BIN_EXE_nix_autobuild"))
+        .arg("serve")
+        .arg(&config)
+        .stdout(Stdio::null())
+        .spawn()
+    else {
+        panic!("starting the server failed");
+    };
+
+    let Some(mut events) = connect(port, Duration::from_secs(10)) else {
+        let _ = child.kill();
+        panic!("server did not start listening on port {}", port);
+    };
+    let request =
+        "GET /api/v1/events HTTP/1.1\r\nHost: localhost\r\nAccept: text/event-stream\r\n\r\n";
+    assert!(events.write_all(request.as_bytes()).is_ok());
+    let _ = events.set_read_timeout(Some(Duration::from_secs(GRACE_SECS)));
+    let mut events = BufReader::new(events);
+    // wait for the headers, the stream is subscribed after that
+    let mut line = String::new();
+    while events.read_line(&mut line).is_ok_and(|read| read > 0) && line != "\r\n" {
+        line.clear();
+    }
+
+    let killed = Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().to_string())
+        .status();
+    assert!(killed.is_ok_and(|status| status.success()));
+
+    let mut body = String::new();
+    while !body.contains("event: shutdown") {
+        line.clear();
+        match events.read_line(&mut line) {
+            Ok(1..) => body.push_str(&line),
+            _ => break,
+        }
+    }
+    assert!(
+        body.contains("event: shutdown"),
+        "no shutdown event in {:?}",
+        body
+    );
+    drop(events);
+
+    let status = wait_exit(&mut child, Duration::from_secs(GRACE_SECS * 2));
+    if status.is_none() {
+        let _ = child.kill();
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(
+        status.is_some_and(|status| status.success()),
+        "server exited with {:?}",
+        status
+    );
+}